@@ -70,6 +70,7 @@ pub struct ReadOnlyStorageArgs {
     // `--with-readonly-storage=true`.
     #[clap(
         long,
+        visible_alias = "readonly-storage",
         value_name = "BOOL",
         parse(try_from_str),
         default_value = "false",
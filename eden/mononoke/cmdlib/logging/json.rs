@@ -0,0 +1,215 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use serde_json::Map;
+use serde_json::Value;
+use slog::Drain;
+use slog::Key;
+use slog::OwnedKVList;
+use slog::Record;
+use slog::KV;
+
+/// Drain that writes each record as a JSON object on its own line.
+///
+/// Key-value pairs of the record take precedence over those of the logger.
+pub struct JsonDrain<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> JsonDrain<W> {
+    pub fn new(writer: W) -> Self {
+        JsonDrain {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write> Drain for JsonDrain<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64());
+        let mut object = Map::new();
+        object.insert("timestamp".to_string(), timestamp.into());
+        object.insert("level".to_string(), record.level().as_str().into());
+        object.insert("msg".to_string(), record.msg().to_string().into());
+        object.insert("module".to_string(), record.module().into());
+        object.insert("file".to_string(), record.file().into());
+        object.insert("line".to_string(), record.line().into());
+        if !record.tag().is_empty() {
+            object.insert("tag".to_string(), record.tag().into());
+        }
+
+        let mut serializer = JsonSerializer(&mut object);
+        record
+            .kv()
+            .serialize(record, &mut serializer)
+            .and_then(|_| values.serialize(record, &mut serializer))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "JSON log writer is poisoned"))?;
+        serde_json::to_writer(&mut *writer, &Value::Object(object))?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// Collects key-value pairs. The first value of a key wins.
+struct JsonSerializer<'a>(&'a mut Map<String, Value>);
+
+impl JsonSerializer<'_> {
+    fn insert(&mut self, key: Key, value: Value) -> slog::Result {
+        self.0.entry(key.to_string()).or_insert(value);
+        Ok(())
+    }
+}
+
+impl slog::Serializer for JsonSerializer<'_> {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.insert(key, val.to_string().into())
+    }
+
+    fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
+        self.insert(key, val.into())
+    }
+
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        self.insert(key, Value::Null)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use slog::info;
+    use slog::o;
+    use slog::Logger;
+
+    use super::*;
+
+    /// Writer whose output can be read while the drain owns it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<Value> {
+            let buffer = self.0.lock().unwrap();
+            std::str::from_utf8(&buffer)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_json_drain() {
+        let buffer = SharedBuffer::default();
+        let logger = Logger::root(
+            JsonDrain::new(buffer.clone()).fuse(),
+            o!("component" => "test", "n" => 1),
+        );
+        info!(logger, "hello {}", "world"; "n" => 2u8, "small" => -3i16, "name" => "x");
+        info!(logger, "again"; "ratio" => 0.5f32, "missing" => None::<u32>);
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        let first = &lines[0];
+        assert_eq!(first["msg"], "hello world");
+        assert_eq!(first["level"], "INFO");
+        assert_eq!(first["module"], module_path!());
+        assert!(first["timestamp"].is_f64());
+        // Values of the record win over those of the logger.
+        assert_eq!(first["n"], 2);
+        assert_eq!(first["small"], -3);
+        assert_eq!(first["name"], "x");
+        assert_eq!(first["component"], "test");
+        assert!(first.get("tag").is_none());
+
+        let second = &lines[1];
+        assert_eq!(second["msg"], "again");
+        assert_eq!(second["n"], 1);
+        assert_eq!(second["ratio"], 0.5);
+        assert_eq!(second["missing"], Value::Null);
+    }
+}
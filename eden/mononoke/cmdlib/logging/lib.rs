@@ -7,11 +7,13 @@
 
 #[cfg(fbcode_build)]
 pub mod glog;
+pub mod json;
 pub mod log;
 mod logging_args;
 mod scribe;
 mod scuba;
 
+pub use logging_args::LogFormat;
 pub use logging_args::LoggingArgs;
 pub use scribe::ScribeLoggingArgs;
 pub use scuba::ScubaLoggingArgs;
@@ -31,6 +31,8 @@ use slog_glog_fmt::kv_defaults::FacebookKV;
 use slog_glog_fmt::GlogFormat;
 use slog_term::TermDecorator;
 
+use crate::json::JsonDrain;
+
 /// Command line arguments for spawning slog Logger
 #[derive(Args, Debug)]
 pub struct LoggingArgs {
@@ -85,6 +87,14 @@ pub struct LoggingArgs {
     pub with_dynamic_observability: bool,
 }
 
+/// Format of log messages written to stderr
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[clap(rename_all = "lower")]
+pub enum LogFormat {
+    Glog,
+    Json,
+}
+
 #[derive(ArgEnum, Clone, Copy, Debug)]
 #[clap(rename_all = "lower")]
 pub enum PanicFate {
@@ -107,12 +117,21 @@ impl LoggingArgs {
         }
     }
 
-    // Logic copied from: https://fburl.com/code/ygj4muxz
     pub fn create_root_log_drain(
         &self,
         fb: FacebookInit,
         log_level: Level,
     ) -> Result<impl Drain<Ok = (), Err = Never> + Clone> {
+        self.create_root_log_drain_with_format(fb, log_level, LogFormat::Glog)
+    }
+
+    // Logic copied from: https://fburl.com/code/ygj4muxz
+    pub fn create_root_log_drain_with_format(
+        &self,
+        fb: FacebookInit,
+        log_level: Level,
+        log_format: LogFormat,
+    ) -> Result<Arc<dyn SendSyncRefUnwindSafeDrain<Ok = (), Err = Never>>> {
         // Set the panic handler up here. Not really relevent to logger other than it emits output
         // when things go wrong. This writes directly to stderr as coredumper expects.
         // TODO: separate the panic handler out from logging
@@ -128,8 +147,13 @@ impl LoggingArgs {
 
         let stdlog_env = "RUST_LOG";
 
-        let glog_drain = make_tag_filter_drain(
-            glog_drain(),
+        let format_drain: Arc<dyn SendSyncRefUnwindSafeDrain<Ok = (), Err = Never>> =
+            match log_format {
+                LogFormat::Glog => Arc::new(glog_drain()),
+                LogFormat::Json => Arc::new(JsonDrain::new(std::io::stderr()).ignore_res()),
+            };
+        let stderr_drain = make_tag_filter_drain(
+            format_drain,
             self.log_include_tag.iter().cloned().collect(),
             self.log_exclude_tag.iter().cloned().collect(),
             true, // Log messages which have no tags
@@ -151,13 +175,13 @@ impl LoggingArgs {
                                 .map_err(|_| format_err!("Unknown log level: {}", log_level_str))?;
 
                             let drain = slog::Duplicate::new(
-                                glog_drain,
+                                stderr_drain,
                                 logview_drain.filter_level(logview_level).ignore_res(),
                             );
                             Arc::new(drain.ignore_res())
                         }
                         None => {
-                            let drain = slog::Duplicate::new(glog_drain, logview_drain);
+                            let drain = slog::Duplicate::new(stderr_drain, logview_drain);
                             Arc::new(drain.ignore_res())
                         }
                     }
@@ -170,7 +194,7 @@ impl LoggingArgs {
                     )
                 }
             }
-            None => Arc::new(glog_drain),
+            None => Arc::new(stderr_drain),
         };
 
         // NOTE: We pass an unfiltered Logger to init_stdlog_once. That's because we do the filtering
@@ -8,6 +8,7 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::num::NonZeroU64;
 
 use anyhow::Error;
 use anyhow::Result;
@@ -23,12 +24,19 @@ pub struct ScubaLoggingArgs {
     /// The name of the scuba dataset to log to
     #[clap(long)]
     pub scuba_dataset: Option<String>,
-    /// A log file to write JSON Scuba logs to (primarily useful in testing)
+    /// A log file to write JSON Scuba logs to (primarily useful in testing).
+    /// Samples are still written to it with --no-scuba.
     #[clap(long)]
     pub scuba_log_file: Option<String>,
     /// Do not use the default scuba dataset for this app
     #[clap(long)]
     pub no_default_scuba_dataset: bool,
+    /// Do not log to any scuba dataset
+    #[clap(long, conflicts_with = "scuba-dataset")]
+    pub no_scuba: bool,
+    /// Log one in this many scuba samples
+    #[clap(long)]
+    pub scuba_sample_rate: Option<NonZeroU64>,
     /// Special dataset to be used by warm bookmark cache.  If a binary doesn't
     /// use warm bookmark cache then this parameter is ignored
     #[clap(long)]
@@ -42,7 +50,9 @@ impl ScubaLoggingArgs {
         observability_context: &ObservabilityContext,
         default_scuba_set: &Option<String>,
     ) -> Result<MononokeScubaSampleBuilder> {
-        let mut scuba_logger = if let Some(scuba_dataset) = &self.scuba_dataset {
+        let mut scuba_logger = if self.no_scuba {
+            MononokeScubaSampleBuilder::with_discard()
+        } else if let Some(scuba_dataset) = &self.scuba_dataset {
             MononokeScubaSampleBuilder::new(fb, scuba_dataset.as_str())
         } else if let Some(default_scuba_dataset) = default_scuba_set {
             if self.no_default_scuba_dataset {
@@ -61,6 +71,9 @@ impl ScubaLoggingArgs {
             .with_seq("seq");
 
         scuba_logger.add_common_server_data();
+        if let Some(sample_rate) = self.scuba_sample_rate {
+            scuba_logger.sampled(sample_rate);
+        }
 
         Ok(scuba_logger)
     }
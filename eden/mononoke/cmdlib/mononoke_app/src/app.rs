@@ -42,6 +42,7 @@ use metaconfig_types::Redaction;
 use metaconfig_types::RepoConfig;
 use mononoke_repos::MononokeRepos;
 use mononoke_types::RepositoryId;
+use permission_checker::BoxPermissionChecker;
 use prefixblob::PrefixBlobstore;
 use redactedblobstore::RedactedBlobstore;
 use redactedblobstore::RedactedBlobstoreConfig;
@@ -53,6 +54,7 @@ use services::Fb303Service;
 use slog::error;
 use slog::info;
 use slog::o;
+use slog::warn;
 use slog::Logger;
 use sql_ext::facebook::MysqlOptions;
 use stats::prelude::*;
@@ -60,18 +62,26 @@ use stats::prelude::*;
 use stats::schedule_stats_aggregation_preview;
 use tokio::runtime::Handle;
 
+use crate::args::AclArgs;
 use crate::args::ConfigArgs;
 use crate::args::ConfigMode;
+use crate::args::MaintenanceWindowAppExtension;
 use crate::args::MultiRepoArgs;
+use crate::args::RateLimitAppExtension;
+use crate::args::RateLimitConfig;
 use crate::args::RepoArg;
 use crate::args::RepoArgs;
 use crate::args::RepoBlobstoreArgs;
+use crate::args::ShutdownAppExtension;
 use crate::args::SourceAndTargetRepoArg;
 use crate::args::SourceAndTargetRepoArgs;
+use crate::config_source::ConfigSource;
 use crate::extension::AppExtension;
 use crate::extension::AppExtensionArgsBox;
 use crate::extension::BoxedAppExtensionArgs;
 use crate::fb303::Fb303AppExtension;
+use crate::maintenance_window::MaintenanceWindow;
+use crate::shutdown::ShutdownController;
 
 define_stats! {
     prefix = "mononoke.app";
@@ -108,11 +118,14 @@ impl MononokeApp {
         extension_args: HashMap<TypeId, Box<dyn BoxedAppExtensionArgs>>,
     ) -> Result<Self> {
         let env = Arc::new(env);
-        let config_path = ConfigArgs::from_arg_matches(&args)?.config_path();
+        let config_args = ConfigArgs::from_arg_matches(&args)?;
+        let config_source = ConfigSource::resolve(&config_args, &env.runtime)?;
 
         let config_store = &env.as_ref().config_store;
-        let storage_configs = metaconfig_parser::load_storage_configs(&config_path, config_store)?;
-        let repo_configs = metaconfig_parser::load_repo_configs(&config_path, config_store)?;
+        let storage_configs =
+            metaconfig_parser::load_storage_configs(config_source.path(), config_store)?;
+        let repo_configs =
+            metaconfig_parser::load_repo_configs(config_source.path(), config_store)?;
 
         let repo_factory = RepoFactory::new(env.clone(), &repo_configs.common);
 
@@ -267,11 +280,59 @@ impl MononokeApp {
         self.repo_factory.clone()
     }
 
+    /// Scuba sample builder for the scuba dataset given on the command line,
+    /// or the default dataset of this app, with server metadata.
+    pub fn scuba(&self) -> MononokeScubaSampleBuilder {
+        self.env.scuba_sample_builder.clone()
+    }
+
     /// Mononoke environment for this app.
     pub fn environment(&self) -> &Arc<MononokeEnvironment> {
         &self.env
     }
 
+    /// Build a permission checker for the named tier ACL, honouring the
+    /// ACL allowlist and bypass arguments.
+    pub async fn permission_checker(&self, tier_name: &str) -> Result<BoxPermissionChecker> {
+        let acl_args: AclArgs = self.args()?;
+        if acl_args.acl_bypass {
+            warn!(
+                self.logger(),
+                "ACL checks are bypassed for tier {}", tier_name
+            );
+        }
+        acl_args
+            .permission_checker(self.env.acl_provider.as_ref(), tier_name)
+            .await
+    }
+
+    /// Wait for the maintenance window given on the command line to open.
+    /// Batch jobs should run within `MaintenanceWindow::run`, and checkpoint
+    /// and exit once the returned window closes.
+    ///
+    /// Requires `MaintenanceWindowAppExtension` to be registered.
+    pub async fn maintenance_window(&self) -> Result<MaintenanceWindow> {
+        let args = self.extension_args::<MaintenanceWindowAppExtension>()?;
+        MaintenanceWindow::open(self.logger(), args).await
+    }
+
+    /// The validated rate limits given on the command line, for services to
+    /// enforce.
+    ///
+    /// Requires `RateLimitAppExtension` to be registered.
+    pub fn rate_limit_config(&self) -> Result<RateLimitConfig> {
+        self.extension_args::<RateLimitAppExtension>()?.config()
+    }
+
+    /// A controller for the graceful shutdown of services, with the phase
+    /// durations given on the command line.
+    ///
+    /// Requires `ShutdownAppExtension` to be registered.
+    pub fn shutdown_controller(&self) -> Result<ShutdownController> {
+        let args = self.extension_args::<ShutdownAppExtension>()?;
+        Ok(ShutdownController::new(self.logger().clone(), args))
+    }
+
     /// Returns true if this is a production configuration of Mononoke
     pub fn is_production(&self) -> bool {
         self.config_mode == ConfigMode::Production
@@ -331,13 +392,7 @@ impl MononokeApp {
     where
         Repo: for<'builder> AsyncBuildable<'builder, RepoFactoryBuilder<'builder>>,
     {
-        let args = repos_args.ids_or_names()?;
-        let mut repos = vec![];
-        for arg in args {
-            repos.push(self.repo_config(arg)?);
-        }
-
-        let repos: HashMap<_, _> = repos.into_iter().collect();
+        let repos = repos_args.resolve_repos(&self.repo_configs)?;
         let repos: Vec<_> = stream::iter(repos)
             .map(|(repo_name, repo_config)| {
                 let repo_factory = self.repo_factory.clone();
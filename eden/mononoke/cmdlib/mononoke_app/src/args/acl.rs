@@ -6,13 +6,226 @@
  */
 
 use std::path::PathBuf;
+use std::sync::Arc;
 
+use anyhow::Context;
+use anyhow::Result;
+use clap::ArgEnum;
 use clap::Args;
+use fbinit::FacebookInit;
+use permission_checker::AclProvider;
+use permission_checker::BoxPermissionChecker;
+use permission_checker::DefaultAclProvider;
+use permission_checker::InternalAclProvider;
+use permission_checker::MononokeIdentity;
+use permission_checker::PermissionCheckerBuilder;
 
 /// Command line arguments for controlling Acls
 #[derive(Args, Debug)]
 pub struct AclArgs {
+    /// Which ACL provider to use. Defaults to `file` if `--acl-file` is
+    /// given, and to `default` otherwise.
+    #[clap(long, arg_enum)]
+    pub acl_provider: Option<AclProviderKind>,
+
     /// Load ACLs from a JSON-formatted file.
     #[clap(long, value_parser)]
     pub acl_file: Option<PathBuf>,
+
+    /// Identity (as TYPE:data) that is always granted access, in addition
+    /// to the ACL. May be repeated.
+    #[clap(long, value_parser)]
+    pub acl_allowlist_identity: Vec<MononokeIdentity>,
+
+    /// Skip all ACL checks. Only intended for emergency tooling.
+    #[clap(long)]
+    pub acl_bypass: bool,
+}
+
+#[derive(Copy, Clone, Debug, ArgEnum, Eq, PartialEq)]
+pub enum AclProviderKind {
+    /// The default provider for this build.
+    Default,
+    /// ACLs loaded from the file given by `--acl-file`.
+    File,
+}
+
+impl AclArgs {
+    /// The ACL provider selected by these arguments.
+    pub fn provider_kind(&self) -> AclProviderKind {
+        self.acl_provider.unwrap_or(match self.acl_file {
+            Some(_) => AclProviderKind::File,
+            None => AclProviderKind::Default,
+        })
+    }
+
+    /// Create the ACL provider selected by these arguments.
+    pub fn create_acl_provider(&self, fb: FacebookInit) -> Result<Arc<dyn AclProvider>> {
+        let acl_provider = match self.provider_kind() {
+            AclProviderKind::Default => DefaultAclProvider::new(fb),
+            AclProviderKind::File => {
+                let acl_file = self
+                    .acl_file
+                    .as_ref()
+                    .context("--acl-provider=file requires --acl-file")?;
+                InternalAclProvider::from_file(acl_file).with_context(|| {
+                    format!("Failed to load ACLs from '{}'", acl_file.to_string_lossy())
+                })?
+            }
+        };
+        Ok(acl_provider)
+    }
+
+    /// Build a permission checker for the named tier ACL, extended with
+    /// the allowlisted identities. If `--acl-bypass` is set, the checker
+    /// allows everything.
+    pub async fn permission_checker(
+        &self,
+        acl_provider: &dyn AclProvider,
+        tier_name: &str,
+    ) -> Result<BoxPermissionChecker> {
+        if self.acl_bypass {
+            return Ok(PermissionCheckerBuilder::new().allow_all().build());
+        }
+        let mut builder = PermissionCheckerBuilder::new().allow(
+            acl_provider
+                .tier_acl(tier_name)
+                .await
+                .with_context(|| format!("Failed to load ACL for tier '{}'", tier_name))?,
+        );
+        if !self.acl_allowlist_identity.is_empty() {
+            builder =
+                builder.allow_allowlist(self.acl_allowlist_identity.iter().cloned().collect());
+        }
+        Ok(builder.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use clap::Parser;
+    use permission_checker::MononokeIdentitySet;
+    use permission_checker::PermissionChecker;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[clap(flatten)]
+        acl: AclArgs,
+    }
+
+    fn parse(args: &[&str]) -> Result<AclArgs> {
+        let args = TestArgs::try_parse_from(std::iter::once("test").chain(args.iter().copied()))?;
+        Ok(args.acl)
+    }
+
+    fn identities(ids: &[&str]) -> MononokeIdentitySet {
+        ids.iter().map(|id| id.parse().unwrap()).collect()
+    }
+
+    fn acl_file() -> Result<tempfile::NamedTempFile> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        write!(
+            file,
+            r#"{{"tiers": {{"mononoke": {{"actions": {{"tupperware": ["USER:alice"]}}}}}}}}"#
+        )?;
+        Ok(file)
+    }
+
+    #[test]
+    fn test_provider_kind() -> Result<()> {
+        assert_eq!(parse(&[])?.provider_kind(), AclProviderKind::Default);
+        assert_eq!(
+            parse(&["--acl-file", "acls.json"])?.provider_kind(),
+            AclProviderKind::File
+        );
+        assert_eq!(
+            parse(&["--acl-provider", "default", "--acl-file", "acls.json"])?.provider_kind(),
+            AclProviderKind::Default
+        );
+        assert_eq!(
+            parse(&["--acl-provider", "file"])?.provider_kind(),
+            AclProviderKind::File
+        );
+        assert!(parse(&["--acl-provider", "other"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_allowlist_identity() -> Result<()> {
+        let args = parse(&[
+            "--acl-allowlist-identity",
+            "USER:bob",
+            "--acl-allowlist-identity",
+            "SERVICE_IDENTITY:tool",
+        ])?;
+        assert_eq!(
+            args.acl_allowlist_identity,
+            vec![
+                MononokeIdentity::new("USER", "bob"),
+                MononokeIdentity::new("SERVICE_IDENTITY", "tool"),
+            ]
+        );
+        assert!(parse(&["--acl-allowlist-identity", "bob"]).is_err());
+        Ok(())
+    }
+
+    #[fbinit::test]
+    fn test_file_provider_requires_file(fb: FacebookInit) -> Result<()> {
+        let args = parse(&["--acl-provider", "file"])?;
+        let err = args.create_acl_provider(fb).err().unwrap();
+        assert_eq!(err.to_string(), "--acl-provider=file requires --acl-file");
+        Ok(())
+    }
+
+    #[fbinit::test]
+    async fn test_permission_checker(fb: FacebookInit) -> Result<()> {
+        let file = acl_file()?;
+        let path = file.path().to_str().unwrap();
+
+        let args = parse(&["--acl-file", path])?;
+        let provider = args.create_acl_provider(fb)?;
+        let checker = args
+            .permission_checker(provider.as_ref(), "mononoke")
+            .await?;
+        assert!(
+            checker
+                .check_set(&identities(&["USER:alice"]), &["tupperware"])
+                .await
+        );
+        assert!(
+            !checker
+                .check_set(&identities(&["USER:bob"]), &["tupperware"])
+                .await
+        );
+
+        let args = parse(&["--acl-file", path, "--acl-allowlist-identity", "USER:bob"])?;
+        let checker = args
+            .permission_checker(provider.as_ref(), "mononoke")
+            .await?;
+        assert!(
+            checker
+                .check_set(&identities(&["USER:bob"]), &["tupperware"])
+                .await
+        );
+        assert!(
+            !checker
+                .check_set(&identities(&["USER:carol"]), &["tupperware"])
+                .await
+        );
+
+        let args = parse(&["--acl-file", path, "--acl-bypass"])?;
+        let checker = args
+            .permission_checker(provider.as_ref(), "mononoke")
+            .await?;
+        assert!(
+            checker
+                .check_set(&identities(&["USER:carol"]), &["tupperware"])
+                .await
+        );
+        Ok(())
+    }
 }
@@ -10,13 +10,26 @@ use std::path::PathBuf;
 use clap::ArgGroup;
 use clap::Args;
 
+use crate::fixtures;
+
 #[derive(Args, Debug)]
-#[clap(group(ArgGroup::new("config").args(&["config-path", "config-tier", "prod"]).required(true)))]
+#[clap(group(ArgGroup::new("config").args(&["config-path", "config-tier", "prod", "local-fixtures"]).required(true)))]
 pub struct ConfigArgs {
-    /// Path to Mononoke config
+    /// Path to Mononoke config. Also accepts `tar:<path>` for a tarball of
+    /// the config, or an `https://` URL of such a tarball.
     #[clap(long, alias = "mononoke-config-path")]
     pub config_path: Option<String>,
 
+    /// Expected SHA-256 of the config tarball fetched from an `https://`
+    /// config path
+    #[clap(long)]
+    pub config_sha256: Option<String>,
+
+    /// Directory to cache config tarballs fetched over HTTPS in. Defaults
+    /// to a directory under the system temporary directory.
+    #[clap(long)]
+    pub config_cache_dir: Option<PathBuf>,
+
     /// Use configerator-based configuration for a specific tier
     #[clap(long)]
     pub config_tier: Option<String>,
@@ -25,6 +38,11 @@ pub struct ConfigArgs {
     #[clap(long)]
     pub prod: bool,
 
+    /// Use local fixture repos stored under this directory, with their
+    /// configs generated and file-backed storage
+    #[clap(long)]
+    pub local_fixtures: Option<PathBuf>,
+
     /// Local path to fetch configerator configs from
     #[clap(long)]
     pub local_configerator_path: Option<PathBuf>,
@@ -49,6 +67,8 @@ impl ConfigArgs {
             configerator_config_path("prod")
         } else if let Some(tier) = &self.config_tier {
             configerator_config_path(tier)
+        } else if let Some(dir) = &self.local_fixtures {
+            fixtures::config_path(dir).to_string_lossy().into_owned()
         } else {
             String::new()
         }
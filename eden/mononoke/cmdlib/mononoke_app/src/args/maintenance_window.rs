@@ -0,0 +1,39 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::time::Duration;
+
+use clap::Args;
+use mononoke_types::DateTime;
+
+use crate::args::duration_secs_from_str;
+use crate::AppExtension;
+
+/// Command line arguments that bound when and for how long a batch job,
+/// such as a backfill, may run
+#[derive(Args, Debug)]
+pub struct MaintenanceWindowArgs {
+    /// Number of seconds the job may run for. Once exceeded, the job
+    /// checkpoints and exits.
+    #[clap(long, parse(try_from_str=duration_secs_from_str))]
+    pub max_runtime: Option<Duration>,
+
+    /// Wait until this time (in RFC3339 format) before starting the job
+    #[clap(long, value_parser = DateTime::from_rfc3339)]
+    pub not_before: Option<DateTime>,
+
+    /// Checkpoint and exit when receiving a shutdown signal, aborting if
+    /// that takes longer than this number of seconds
+    #[clap(long, parse(try_from_str=duration_secs_from_str))]
+    pub abort_on_shutdown_signal_grace: Option<Duration>,
+}
+
+pub struct MaintenanceWindowAppExtension;
+
+impl AppExtension for MaintenanceWindowAppExtension {
+    type Args = MaintenanceWindowArgs;
+}
@@ -5,6 +5,8 @@
  * GNU General Public License version 2.
  */
 
+use std::time::Duration;
+
 use anyhow::format_err;
 use anyhow::Result;
 
@@ -12,24 +14,40 @@ mod acl;
 mod changeset;
 mod config;
 mod hooks;
+mod maintenance_window;
 mod mcrouter;
 mod mysql;
+mod observability;
+mod progress;
+mod rate_limit;
+pub(crate) mod renamed;
 mod repo;
 mod repo_blobstore;
 mod repo_filter;
 mod runtime;
+mod shutdown;
 mod shutdown_timeout;
 mod tls;
 mod tunables;
 
 pub use acl::AclArgs;
+pub use acl::AclProviderKind;
 pub use changeset::ChangesetArgs;
 pub use config::ConfigArgs;
 pub use config::ConfigMode;
 pub use hooks::HooksAppExtension;
+pub use maintenance_window::MaintenanceWindowAppExtension;
+pub use maintenance_window::MaintenanceWindowArgs;
 pub use mcrouter::McrouterAppExtension;
 pub use mcrouter::McrouterArgs;
 pub use mysql::MysqlArgs;
+pub use mysql::MysqlReadPreference;
+pub use observability::ObservabilityArgs;
+pub use progress::ProgressArgs;
+pub use rate_limit::RateLimitAppExtension;
+pub use rate_limit::RateLimitArgs;
+pub use rate_limit::RateLimitConfig;
+pub use renamed::RenamedArg;
 pub use repo::MultiRepoArgs;
 pub use repo::RepoArg;
 pub use repo::RepoArgs;
@@ -38,12 +56,19 @@ pub use repo::SourceAndTargetRepoArgs;
 pub use repo_blobstore::RepoBlobstoreArgs;
 pub use repo_filter::RepoFilterAppExtension;
 pub use runtime::RuntimeArgs;
+pub use shutdown::ShutdownAppExtension;
+pub use shutdown::ShutdownArgs;
 pub use shutdown_timeout::ShutdownTimeoutArgs;
 pub use tls::TLSArgs;
 
 pub use self::tunables::TunablesArgs;
 pub use crate::fb303::Fb303Args;
 
+/// Parse a number of seconds, for duration arguments.
+pub(crate) fn duration_secs_from_str(s: &str) -> Result<Duration> {
+    Ok(Duration::from_secs(s.parse::<u64>()?))
+}
+
 /// NOTE: Don't use this. "configerator:" prefix don't need to exist and is going to be removed.
 /// Pass raw path instead.
 pub fn parse_config_spec_to_path(source_spec: &str) -> Result<String> {
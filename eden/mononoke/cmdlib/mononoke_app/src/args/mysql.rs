@@ -5,7 +5,13 @@
  * GNU General Public License version 2.
  */
 
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use clap::ArgEnum;
 use clap::Args;
+use sql_ext::facebook::ReadConnectionType;
 
 /// Command line arguments for controlling MySql
 // Defaults are derived from `sql_ext::facebook::mysql`
@@ -16,12 +22,21 @@ pub struct MysqlArgs {
     #[clap(long)]
     pub mysql_master_only: bool,
 
+    /// Which MySql instances to read from. Defaults to replica-only.
+    #[clap(long, arg_enum, conflicts_with = "mysql-master-only")]
+    pub mysql_read_preference: Option<MysqlReadPreference>,
+
+    /// Maximum replication lag in millisecs that jobs waiting for
+    /// replication accept
+    #[clap(long, conflicts_with = "mysql-master-only")]
+    pub mysql_max_replica_lag: Option<u64>,
+
     /// Size of the MySql connection pool
     #[clap(long, default_value = "10000")]
     pub mysql_pool_limit: usize,
 
-    /// MySql connection pool per key limit
-    #[clap(long, default_value = "100")]
+    /// MySql connection pool per key (shard) limit
+    #[clap(long, default_value = "100", alias = "mysql-pool-per-shard-limit")]
     pub mysql_pool_per_key_limit: u64,
 
     /// Number of threads in MySql connection pool (number of real pools)
@@ -40,8 +55,13 @@ pub struct MysqlArgs {
     #[clap(long, default_value = "10000", alias = "mysql-sqblob-pool-limit")]
     pub mysql_sqlblob_pool_limit: usize,
 
-    /// MySql connection pool per key limit for SqlBlob
-    #[clap(long, default_value = "100", alias = "mysql-sqblob-pool-per-key-limit")]
+    /// MySql connection pool per key (shard) limit for SqlBlob
+    #[clap(
+        long,
+        default_value = "100",
+        alias = "mysql-sqblob-pool-per-key-limit",
+        alias = "mysql-sqlblob-pool-per-shard-limit"
+    )]
     pub mysql_sqlblob_pool_per_key_limit: u64,
 
     /// Number of threads in MySql connection pool (number of real pools) for
@@ -65,3 +85,111 @@ pub struct MysqlArgs {
     #[clap(long, default_value = "10000", alias = "mysql-max-query-time")]
     pub mysql_query_time_limit: u64,
 }
+
+/// Which MySql instances to read from
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MysqlReadPreference {
+    /// Master or replica, whichever is closest
+    Closest,
+    /// Replicas only, even if they are in a remote region
+    ReplicaOnly,
+    /// Master only
+    Master,
+    /// Closest region first, and replicas first within a region
+    ReplicaFirst,
+    /// Replicas that are consistent with the client's writes
+    ReadAfterWrite,
+}
+
+impl From<MysqlReadPreference> for ReadConnectionType {
+    fn from(preference: MysqlReadPreference) -> Self {
+        match preference {
+            MysqlReadPreference::Closest => ReadConnectionType::Closest,
+            MysqlReadPreference::ReplicaOnly => ReadConnectionType::ReplicaOnly,
+            MysqlReadPreference::Master => ReadConnectionType::Master,
+            MysqlReadPreference::ReplicaFirst => ReadConnectionType::ReplicaFirst,
+            MysqlReadPreference::ReadAfterWrite => ReadConnectionType::ReadAfterWriteConsistency,
+        }
+    }
+}
+
+impl MysqlArgs {
+    /// The MySql instances to read from.
+    pub fn read_connection_type(&self) -> ReadConnectionType {
+        if self.mysql_master_only {
+            ReadConnectionType::Master
+        } else {
+            self.mysql_read_preference
+                .map_or(ReadConnectionType::ReplicaOnly, ReadConnectionType::from)
+        }
+    }
+
+    /// The maximum replication lag, if given.
+    pub fn max_replica_lag(&self) -> Result<Option<Duration>> {
+        match self.mysql_max_replica_lag {
+            Some(_) if self.mysql_read_preference == Some(MysqlReadPreference::Master) => Err(
+                anyhow!("--mysql-max-replica-lag can't be used when reading from master"),
+            ),
+            Some(0) => Err(anyhow!("--mysql-max-replica-lag must be positive")),
+            lag => Ok(lag.map(Duration::from_millis)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[clap(flatten)]
+        mysql: MysqlArgs,
+    }
+
+    fn parse(args: &[&str]) -> Result<MysqlArgs> {
+        let args = TestArgs::try_parse_from(std::iter::once("test").chain(args.iter().copied()))?;
+        Ok(args.mysql)
+    }
+
+    #[test]
+    fn test_read_connection_type() {
+        let read_connection_type =
+            |args: &[&str]| format!("{:?}", parse(args).unwrap().read_connection_type());
+        assert_eq!(read_connection_type(&[]), "ReplicaOnly");
+        assert_eq!(read_connection_type(&["--mysql-master-only"]), "Master");
+        assert_eq!(
+            read_connection_type(&["--mysql-read-preference", "read-after-write"]),
+            "ReadAfterWriteConsistency"
+        );
+        assert!(parse(&["--mysql-master-only", "--mysql-read-preference", "closest"]).is_err());
+    }
+
+    #[test]
+    fn test_max_replica_lag() {
+        let max_replica_lag = |args: &[&str]| parse(args).unwrap().max_replica_lag();
+        assert_eq!(max_replica_lag(&[]).unwrap(), None);
+        assert_eq!(
+            max_replica_lag(&["--mysql-max-replica-lag", "500"]).unwrap(),
+            Some(Duration::from_millis(500))
+        );
+        assert!(max_replica_lag(&["--mysql-max-replica-lag", "0"]).is_err());
+        assert!(
+            max_replica_lag(&[
+                "--mysql-read-preference",
+                "master",
+                "--mysql-max-replica-lag",
+                "500"
+            ])
+            .is_err()
+        );
+        assert!(parse(&["--mysql-master-only", "--mysql-max-replica-lag", "500"]).is_err());
+    }
+
+    #[test]
+    fn test_per_shard_limit_alias() {
+        let args = parse(&["--mysql-pool-per-shard-limit", "7"]).unwrap();
+        assert_eq!(args.mysql_pool_per_key_limit, 7);
+    }
+}
@@ -0,0 +1,264 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use clap::Args;
+use cmdlib_logging::LogFormat;
+use slog::Drain;
+use slog::Level;
+use slog::Never;
+use slog::OwnedKVList;
+use slog::Record;
+use slog::SendSyncRefUnwindSafeDrain;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Layer;
+
+/// Command line arguments that control the format and filtering of logs and
+/// traces, shared by all binaries
+#[derive(Args, Debug)]
+pub struct ObservabilityArgs {
+    /// Format of log messages and traces written to stderr
+    #[clap(long, arg_enum, default_value_t = LogFormat::Glog)]
+    pub log_format: LogFormat,
+
+    /// Log level of a module and its submodules, as MODULE=LEVEL. Overrides
+    /// --log-level for that module. Can be repeated.
+    #[clap(long, value_parser = parse_module_level)]
+    pub log_module_level: Vec<(String, Level)>,
+
+    /// Fraction of tracing events to write to stderr, between 0 and 1.
+    /// Tracing events are not collected if this is 0.
+    #[clap(long, default_value_t = 0.0, value_parser = parse_sample_rate)]
+    pub tracing_sample_rate: f64,
+
+    /// Scuba dataset to log to. Takes precedence over --scuba-dataset and
+    /// the default dataset of this app.
+    #[clap(long)]
+    pub scuba_dataset_override: Option<String>,
+}
+
+impl ObservabilityArgs {
+    /// The most verbose level any module logs at, given the default level
+    /// from `--log-level`.
+    pub fn max_log_level(&self, default_level: Level) -> Level {
+        self.log_module_level
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(default_level, |max, level| {
+                if level.as_usize() > max.as_usize() {
+                    level
+                } else {
+                    max
+                }
+            })
+    }
+
+    /// Apply per-module log levels to `drain`. Modules without a level use
+    /// `default_level`.
+    pub fn module_level_drain(
+        &self,
+        drain: Arc<dyn SendSyncRefUnwindSafeDrain<Ok = (), Err = Never>>,
+        default_level: Level,
+    ) -> Arc<dyn SendSyncRefUnwindSafeDrain<Ok = (), Err = Never>> {
+        if self.log_module_level.is_empty() {
+            return drain;
+        }
+        let drain = ModuleLevelDrain {
+            drain,
+            default_level,
+            module_levels: self.log_module_level.clone(),
+        };
+        Arc::new(drain.ignore_res())
+    }
+
+    /// Install a global tracing subscriber that writes sampled events to
+    /// stderr in the same format and with the same levels as logs.
+    pub fn init_tracing(&self, default_level: Level) -> Result<()> {
+        if self.tracing_sample_rate == 0.0 {
+            return Ok(());
+        }
+        let mut filter = EnvFilter::try_new(tracing_level(default_level))?;
+        for (module, level) in &self.log_module_level {
+            let directive = format!("{}={}", module, tracing_level(*level));
+            filter = filter.add_directive(directive.parse()?);
+        }
+        let sample_rate = self.tracing_sample_rate;
+        let sampler = filter_fn(move |metadata| {
+            metadata.is_span() || sample_rate >= 1.0 || rand::random::<f64>() < sample_rate
+        });
+        let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+        let registry = tracing_subscriber::registry().with(filter);
+        match self.log_format {
+            LogFormat::Glog => registry.with(layer.with_filter(sampler)).try_init()?,
+            LogFormat::Json => registry
+                .with(layer.json().with_filter(sampler))
+                .try_init()?,
+        }
+        Ok(())
+    }
+}
+
+/// Filters records by the level of the module that logged them.
+struct ModuleLevelDrain<D> {
+    drain: D,
+    default_level: Level,
+    module_levels: Vec<(String, Level)>,
+}
+
+impl<D> ModuleLevelDrain<D> {
+    /// The level of the longest module prefix of `module`.
+    fn level_for(&self, module: &str) -> Level {
+        self.module_levels
+            .iter()
+            .filter(|(prefix, _)| {
+                module == prefix
+                    || (module.starts_with(prefix.as_str())
+                        && module[prefix.len()..].starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default_level, |(_, level)| *level)
+    }
+}
+
+impl<D: Drain> Drain for ModuleLevelDrain<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        if record.level().is_at_least(self.level_for(record.module())) {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn tracing_level(level: Level) -> &'static str {
+    match level {
+        Level::Critical | Level::Error => "error",
+        Level::Warning => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+fn parse_module_level(s: &str) -> Result<(String, Level)> {
+    let (module, level) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected MODULE=LEVEL, got {:?}", s))?;
+    let level = Level::from_str(level).map_err(|_| anyhow!("unknown log level: {}", level))?;
+    Ok((module.to_string(), level))
+}
+
+fn parse_sample_rate(s: &str) -> Result<f64> {
+    let rate = s.parse::<f64>()?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(anyhow!("sample rate must be between 0 and 1, got {}", rate));
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use slog::o;
+    use slog::Logger;
+
+    use super::*;
+
+    /// Drain that records the messages it receives.
+    #[derive(Clone, Default)]
+    struct CollectDrain(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for CollectDrain {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, record: &Record, _values: &OwnedKVList) -> Result<(), Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    fn args(module_levels: Vec<(&str, Level)>) -> ObservabilityArgs {
+        ObservabilityArgs {
+            log_format: LogFormat::Glog,
+            log_module_level: module_levels
+                .into_iter()
+                .map(|(module, level)| (module.to_string(), level))
+                .collect(),
+            tracing_sample_rate: 0.0,
+            scuba_dataset_override: None,
+        }
+    }
+
+    #[test]
+    fn test_level_for() {
+        let drain = ModuleLevelDrain {
+            drain: CollectDrain::default(),
+            default_level: Level::Info,
+            module_levels: vec![
+                ("a".to_string(), Level::Debug),
+                ("a::b".to_string(), Level::Error),
+            ],
+        };
+        assert_eq!(drain.level_for("a"), Level::Debug);
+        assert_eq!(drain.level_for("a::c"), Level::Debug);
+        assert_eq!(drain.level_for("a::b"), Level::Error);
+        assert_eq!(drain.level_for("a::b::c"), Level::Error);
+        assert_eq!(drain.level_for("ab"), Level::Info);
+        assert_eq!(drain.level_for("b::a"), Level::Info);
+    }
+
+    #[test]
+    fn test_module_level_drain() {
+        let collect = CollectDrain::default();
+        let drain = args(vec![(module_path!(), Level::Warning)])
+            .module_level_drain(Arc::new(collect.clone()), Level::Trace);
+        let logger = Logger::root(drain, o!());
+        slog::info!(logger, "dropped");
+        slog::warn!(logger, "kept");
+        slog::error!(logger, "also kept");
+        assert_eq!(*collect.0.lock().unwrap(), ["kept", "also kept"]);
+
+        // Other modules use the default level.
+        let drain = args(vec![("other", Level::Trace)])
+            .module_level_drain(Arc::new(collect.clone()), Level::Info);
+        let logger = Logger::root(drain, o!());
+        slog::debug!(logger, "dropped");
+        slog::info!(logger, "kept");
+        assert_eq!(*collect.0.lock().unwrap(), ["kept", "also kept", "kept"]);
+    }
+
+    #[test]
+    fn test_max_log_level() {
+        assert_eq!(args(vec![]).max_log_level(Level::Info), Level::Info);
+        assert_eq!(
+            args(vec![("a", Level::Debug), ("b", Level::Error)]).max_log_level(Level::Info),
+            Level::Debug
+        );
+    }
+
+    #[test]
+    fn test_parse_module_level() {
+        assert_eq!(
+            parse_module_level("a::b=debug").unwrap(),
+            ("a::b".to_string(), Level::Debug)
+        );
+        assert!(parse_module_level("a::b").is_err());
+        assert!(parse_module_level("a=loud").is_err());
+    }
+}
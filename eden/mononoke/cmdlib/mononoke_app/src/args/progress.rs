@@ -0,0 +1,54 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Args;
+use slog::Logger;
+
+use crate::args::duration_secs_from_str;
+use crate::progress::BulkOperation;
+
+/// Command line arguments for reporting progress of, checkpointing and
+/// retrying long-running bulk operations such as backfills
+#[derive(Args, Debug)]
+pub struct ProgressArgs {
+    /// Number of seconds between progress log lines
+    #[clap(long, default_value = "60", parse(try_from_str=duration_secs_from_str))]
+    pub progress_interval: Duration,
+
+    /// File to persist checkpoints to. If the file exists, the operation
+    /// resumes from the checkpoint it contains.
+    #[clap(long, value_parser)]
+    pub checkpoint_file: Option<PathBuf>,
+
+    /// Number of processed items between checkpoints
+    #[clap(long, default_value = "1000", value_parser)]
+    pub checkpoint_every: u64,
+
+    /// Number of times to retry a failed step before giving up
+    #[clap(long, default_value = "0", value_parser)]
+    pub max_retries: usize,
+
+    /// Number of seconds to wait between retries
+    #[clap(long, default_value = "5", parse(try_from_str=duration_secs_from_str))]
+    pub retry_delay: Duration,
+}
+
+impl ProgressArgs {
+    /// Start tracking a bulk operation called `name`, which is expected to
+    /// process `total` items, if known.
+    pub fn bulk_operation(
+        &self,
+        logger: &Logger,
+        name: impl Into<String>,
+        total: Option<u64>,
+    ) -> BulkOperation {
+        BulkOperation::new(logger.clone(), name.into(), total, self)
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::anyhow;
+use anyhow::Result;
+use clap::Args;
+
+use crate::AppExtension;
+
+/// Command line arguments that limit the rate of requests a service accepts
+#[derive(Args, Debug)]
+pub struct RateLimitArgs {
+    /// Maximum number of requests per second across all clients
+    #[clap(long)]
+    pub rate_limit_qps: Option<u64>,
+
+    /// Maximum number of requests per second from a single client
+    #[clap(long)]
+    pub rate_limit_per_client_qps: Option<u64>,
+
+    /// Number of requests that may be accepted at once before the QPS limits
+    /// apply. Must be at least the QPS limits. Defaults to the largest QPS
+    /// limit.
+    #[clap(long)]
+    pub rate_limit_burst: Option<u64>,
+
+    /// Number of in-flight requests above which new requests are rejected
+    #[clap(long)]
+    pub load_shed_threshold: Option<u64>,
+}
+
+/// Validated rate limits, to be enforced by services.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Maximum number of requests per second across all clients.
+    pub qps: Option<u64>,
+    /// Maximum number of requests per second from a single client.
+    pub per_client_qps: Option<u64>,
+    /// Number of requests accepted at once. Only set if a QPS limit is set,
+    /// in which case it is at least that limit.
+    pub burst: Option<u64>,
+    /// Number of in-flight requests above which new requests are rejected.
+    pub load_shed_threshold: Option<u64>,
+}
+
+impl RateLimitArgs {
+    /// Validate the arguments.
+    pub fn config(&self) -> Result<RateLimitConfig> {
+        for (name, value) in [
+            ("rate-limit-qps", self.rate_limit_qps),
+            ("rate-limit-per-client-qps", self.rate_limit_per_client_qps),
+            ("rate-limit-burst", self.rate_limit_burst),
+            ("load-shed-threshold", self.load_shed_threshold),
+        ] {
+            if value == Some(0) {
+                return Err(anyhow!("--{} must be positive", name));
+            }
+        }
+
+        if let (Some(qps), Some(per_client_qps)) =
+            (self.rate_limit_qps, self.rate_limit_per_client_qps)
+        {
+            if per_client_qps > qps {
+                return Err(anyhow!(
+                    "--rate-limit-per-client-qps ({}) must not exceed --rate-limit-qps ({})",
+                    per_client_qps,
+                    qps
+                ));
+            }
+        }
+
+        let max_qps = self.rate_limit_qps.max(self.rate_limit_per_client_qps);
+        let burst = match (self.rate_limit_burst, max_qps) {
+            (Some(burst), Some(qps)) if burst < qps => {
+                return Err(anyhow!(
+                    "--rate-limit-burst ({}) must be at least the QPS limit ({})",
+                    burst,
+                    qps
+                ));
+            }
+            (Some(_), None) => {
+                return Err(anyhow!("--rate-limit-burst requires a QPS limit"));
+            }
+            (burst, qps) => burst.or(qps),
+        };
+
+        Ok(RateLimitConfig {
+            qps: self.rate_limit_qps,
+            per_client_qps: self.rate_limit_per_client_qps,
+            burst,
+            load_shed_threshold: self.load_shed_threshold,
+        })
+    }
+}
+
+pub struct RateLimitAppExtension;
+
+impl AppExtension for RateLimitAppExtension {
+    type Args = RateLimitArgs;
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[clap(flatten)]
+        rate_limit: RateLimitArgs,
+    }
+
+    fn config(args: &[&str]) -> Result<RateLimitConfig> {
+        let args = TestArgs::try_parse_from(std::iter::once("test").chain(args.iter().copied()))?;
+        args.rate_limit.config()
+    }
+
+    #[test]
+    fn test_no_limits() {
+        assert_eq!(config(&[]).unwrap(), RateLimitConfig::default());
+    }
+
+    #[test]
+    fn test_limits() {
+        assert_eq!(
+            config(&[
+                "--rate-limit-qps",
+                "100",
+                "--rate-limit-per-client-qps",
+                "10",
+                "--load-shed-threshold",
+                "500",
+            ])
+            .unwrap(),
+            RateLimitConfig {
+                qps: Some(100),
+                per_client_qps: Some(10),
+                burst: Some(100),
+                load_shed_threshold: Some(500),
+            }
+        );
+        assert_eq!(
+            config(&[
+                "--rate-limit-per-client-qps",
+                "10",
+                "--rate-limit-burst",
+                "20"
+            ])
+            .unwrap(),
+            RateLimitConfig {
+                per_client_qps: Some(10),
+                burst: Some(20),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        let err = |args: &[&str]| config(args).unwrap_err().to_string();
+        assert_eq!(
+            err(&["--rate-limit-qps", "100", "--rate-limit-burst", "50"]),
+            "--rate-limit-burst (50) must be at least the QPS limit (100)"
+        );
+        assert_eq!(
+            err(&["--rate-limit-burst", "50"]),
+            "--rate-limit-burst requires a QPS limit"
+        );
+        assert_eq!(
+            err(&[
+                "--rate-limit-qps",
+                "10",
+                "--rate-limit-per-client-qps",
+                "20"
+            ]),
+            "--rate-limit-per-client-qps (20) must not exceed --rate-limit-qps (10)"
+        );
+        assert_eq!(
+            err(&["--rate-limit-qps", "0"]),
+            "--rate-limit-qps must be positive"
+        );
+        assert!(config(&["--rate-limit-qps", "fast"]).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Flags that were renamed, but whose old names still work.
+//!
+//! Old names are replaced with the new names before the arguments are
+//! parsed, and a deprecation warning is logged for each.  Once the sunset
+//! time of an old name, set in the `deprecated_args_sunset_timestamps`
+//! tunable, has passed, using it is an error.
+//!
+//! `deprecated_args_sunset_timestamps` is a by-repo tunable, but its keys are
+//! old flag names without the leading `--`, not repo names.  The sunset of
+//! `--old-flag` is set in `ints_by_repo` under the `old-flag` key.
+
+use std::ffi::OsString;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use mononoke_types::DateTime;
+use slog::warn;
+use slog::Logger;
+use tunables::tunables;
+
+/// A flag that was renamed, without the leading `--`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenamedArg {
+    pub old_name: &'static str,
+    pub new_name: &'static str,
+}
+
+impl RenamedArg {
+    pub const fn new(old_name: &'static str, new_name: &'static str) -> Self {
+        RenamedArg { old_name, new_name }
+    }
+}
+
+/// Replace the old names in `args` with the new names. Returns the new
+/// arguments, and the renames that were used.
+pub(crate) fn rename_args(
+    args: impl IntoIterator<Item = OsString>,
+    renamed_args: &[RenamedArg],
+) -> (Vec<OsString>, Vec<RenamedArg>) {
+    let mut used = Vec::new();
+    let mut positional = false;
+    let args = args
+        .into_iter()
+        .map(|arg| {
+            if positional {
+                return arg;
+            }
+            let flag = match arg.to_str().and_then(|arg| arg.strip_prefix("--")) {
+                Some("") => {
+                    // Everything after `--` is positional.
+                    positional = true;
+                    return arg;
+                }
+                Some(flag) => flag,
+                None => return arg,
+            };
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (flag, None),
+            };
+            match renamed_args.iter().find(|renamed| renamed.old_name == name) {
+                Some(renamed) => {
+                    if !used.contains(renamed) {
+                        used.push(renamed.clone());
+                    }
+                    match value {
+                        Some(value) => format!("--{}={}", renamed.new_name, value).into(),
+                        None => format!("--{}", renamed.new_name).into(),
+                    }
+                }
+                None => arg,
+            }
+        })
+        .collect();
+    (args, used)
+}
+
+/// Warn about the old names that were used, and fail if any of them are
+/// past their sunset time.
+pub(crate) fn check_renamed_args(logger: &Logger, used: &[RenamedArg]) -> Result<()> {
+    let now = DateTime::now().timestamp_secs();
+    for renamed in used {
+        // Keyed by the old flag name rather than a repo, see the module docs.
+        let sunset = tunables().get_by_repo_deprecated_args_sunset_timestamps(renamed.old_name);
+        if let Some(sunset) = sunset {
+            if now >= sunset {
+                return Err(anyhow!(
+                    "--{} has been removed, use --{} instead",
+                    renamed.old_name,
+                    renamed.new_name
+                ));
+            }
+        }
+        warn!(
+            logger,
+            "--{} is deprecated, use --{} instead", renamed.old_name, renamed.new_name;
+            "deprecated_arg" => renamed.old_name,
+            "replacement_arg" => renamed.new_name,
+            "sunset_timestamp" => sunset,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use slog::o;
+    use slog::Discard;
+    use tunables::with_tunables;
+    use tunables::MononokeTunables;
+
+    use super::*;
+
+    const RENAMED: &[RenamedArg] = &[
+        RenamedArg::new("old-flag", "new-flag"),
+        RenamedArg::new("old-option", "new-option"),
+    ];
+
+    fn rename(args: &[&str]) -> (Vec<String>, Vec<&'static str>) {
+        let (args, used) = rename_args(args.iter().map(OsString::from), RENAMED);
+        (
+            args.into_iter()
+                .map(|arg| arg.into_string().unwrap())
+                .collect(),
+            used.into_iter().map(|renamed| renamed.old_name).collect(),
+        )
+    }
+
+    #[test]
+    fn test_rename_args() {
+        assert_eq!(
+            rename(&["app", "--old-flag", "--old-option", "1", "--other"]),
+            (
+                vec!["app", "--new-flag", "--new-option", "1", "--other"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                vec!["old-flag", "old-option"]
+            )
+        );
+        assert_eq!(
+            rename(&["app", "--old-option=1", "--old-option=2"]),
+            (
+                vec!["app", "--new-option=1", "--new-option=2"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                vec!["old-option"]
+            )
+        );
+    }
+
+    #[test]
+    fn test_check_renamed_args() {
+        let logger = Logger::root(Discard, o!());
+        let tunables = MononokeTunables::default();
+        tunables.update_by_repo_ints(&HashMap::from([(
+            "old-flag".to_string(),
+            HashMap::from([("deprecated_args_sunset_timestamps".to_string(), 0)]),
+        )]));
+        with_tunables(tunables, || {
+            assert!(check_renamed_args(&logger, &RENAMED[1..]).is_ok());
+            let err = check_renamed_args(&logger, RENAMED).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "--old-flag has been removed, use --new-flag instead"
+            );
+        });
+        assert!(check_renamed_args(&logger, RENAMED).is_ok());
+    }
+
+    #[test]
+    fn test_rename_args_ignores_values() {
+        let args = vec!["app", "--old-flagging", "old-flag", "--", "--old-flag"];
+        assert_eq!(
+            rename(&args),
+            (args.into_iter().map(String::from).collect(), vec![])
+        );
+    }
+}
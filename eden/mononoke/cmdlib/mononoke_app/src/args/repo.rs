@@ -5,10 +5,19 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
 use clap::ArgGroup;
 use clap::Args;
+use metaconfig_parser::RepoConfigs;
+use metaconfig_types::RepoConfig;
 use mononoke_types::RepositoryId;
+use regex::Regex;
 
 /// Command line arguments for specifying a single repo.
 #[derive(Args, Debug)]
@@ -53,12 +62,12 @@ impl RepoArgs {
 }
 
 /// Command line arguments for specifying multiple repos.
-#[derive(Args, Debug)]
+#[derive(Args, Debug, Default)]
 #[clap(group(
     ArgGroup::new("multirepos")
         .multiple(true)
         .conflicts_with("repo")
-        .args(&["repo-id", "repo-name"]),
+        .args(&["repo-id", "repo-name", "repo-pattern", "repo-list-file"]),
 ))]
 pub struct MultiRepoArgs {
     /// Numeric repository ID
@@ -68,10 +77,27 @@ pub struct MultiRepoArgs {
     /// Repository name
     #[clap(short = 'R', long)]
     pub repo_name: Vec<String>,
+
+    /// Glob pattern of repository names, where `*` matches any sequence of
+    /// characters and `?` matches a single character
+    #[clap(long, value_parser = parse_repo_pattern)]
+    pub repo_pattern: Vec<Regex>,
+
+    /// File with one repository name or ID per line. Empty lines and lines
+    /// starting with `#` are skipped
+    #[clap(long)]
+    pub repo_list_file: Option<PathBuf>,
 }
 
 impl MultiRepoArgs {
+    /// The repositories selected by ID or name. Patterns and list files can
+    /// only be resolved against the repo configs, see `resolve_repos`.
     pub fn ids_or_names(&self) -> Result<Vec<RepoArg>> {
+        if !self.repo_pattern.is_empty() || self.repo_list_file.is_some() {
+            return Err(anyhow!(
+                "repo-pattern and repo-list-file are not supported here, use resolve_repos instead"
+            ));
+        }
         let mut l = vec![];
         for id in &self.repo_id {
             l.push(RepoArg::Id(RepositoryId::new(*id)));
@@ -82,6 +108,85 @@ impl MultiRepoArgs {
 
         Ok(l)
     }
+
+    /// Resolve all selected repositories against `configs`.
+    ///
+    /// Repositories are returned in the order they were selected: IDs, names,
+    /// the list file, then pattern matches sorted by name. Repositories
+    /// selected more than once are only returned once.
+    pub fn resolve_repos(&self, configs: &RepoConfigs) -> Result<Vec<(String, RepoConfig)>> {
+        let mut names = Vec::new();
+        for id in &self.repo_id {
+            names.push(resolve_repo_id(configs, RepositoryId::new(*id))?);
+        }
+        for name in &self.repo_name {
+            names.push(resolve_repo_name(configs, name)?);
+        }
+        if let Some(path) = &self.repo_list_file {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read repo list file {}", path.display()))?;
+            for line in content.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                // Names take precedence, in case a repository is named after a number.
+                let name = match (configs.repos.contains_key(line), line.parse::<i32>()) {
+                    (false, Ok(id)) => resolve_repo_id(configs, RepositoryId::new(id))?,
+                    _ => resolve_repo_name(configs, line)?,
+                };
+                names.push(name);
+            }
+        }
+        for pattern in &self.repo_pattern {
+            let mut matched = configs
+                .repos
+                .keys()
+                .filter(|name| pattern.is_match(name))
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            if matched.is_empty() {
+                return Err(anyhow!("no repos match pattern: {}", pattern));
+            }
+            matched.sort_unstable();
+            names.extend(matched);
+        }
+
+        let mut unique_names = HashSet::new();
+        Ok(names
+            .into_iter()
+            .filter(|name| unique_names.insert(*name))
+            .map(|name| (name.to_string(), configs.repos[name].clone()))
+            .collect())
+    }
+}
+
+fn resolve_repo_id(configs: &RepoConfigs, repo_id: RepositoryId) -> Result<&str> {
+    let (name, _) = configs
+        .get_repo_config(repo_id)
+        .ok_or_else(|| anyhow!("unknown repoid: {:?}", repo_id))?;
+    Ok(name)
+}
+
+fn resolve_repo_name<'a>(configs: &'a RepoConfigs, name: &str) -> Result<&'a str> {
+    let (name, _) = configs
+        .repos
+        .get_key_value(name)
+        .ok_or_else(|| anyhow!("unknown reponame: {:?}", name))?;
+    Ok(name)
+}
+
+/// Convert a glob pattern of repository names to an anchored regex.
+fn parse_repo_pattern(pattern: &str) -> Result<Regex> {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    Ok(Regex::new(&regex)?)
 }
 
 /// Command line arguments for specifying only a source  and a target repos,
@@ -164,3 +269,139 @@ pub enum RepoArg<'name> {
     Id(RepositoryId),
     Name(&'name str),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use clap::Parser;
+    use metaconfig_types::CommonConfig;
+    use metaconfig_types::Identity;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[clap(flatten)]
+        repos: MultiRepoArgs,
+    }
+
+    fn parse(args: &[&str]) -> Result<MultiRepoArgs> {
+        let args = TestArgs::try_parse_from(std::iter::once("test").chain(args.iter().copied()))?;
+        Ok(args.repos)
+    }
+
+    fn configs() -> RepoConfigs {
+        let repos = [("fbsource", 0), ("www", 1), ("www-test", 2), ("1", 3)]
+            .into_iter()
+            .map(|(name, id)| {
+                let config = RepoConfig {
+                    repoid: RepositoryId::new(id),
+                    ..Default::default()
+                };
+                (name.to_string(), config)
+            })
+            .collect();
+        RepoConfigs {
+            repos,
+            common: CommonConfig {
+                trusted_parties_hipster_tier: None,
+                trusted_parties_allowlist: vec![],
+                global_allowlist: vec![],
+                loadlimiter_category: None,
+                censored_scuba_params: Default::default(),
+                enable_http_control_api: false,
+                redaction_config: Default::default(),
+                internal_identity: Identity {
+                    id_type: "SERVICE_IDENTITY".to_string(),
+                    id_data: "mononoke".to_string(),
+                },
+            },
+        }
+    }
+
+    fn resolve(args: &[&str]) -> Result<Vec<String>> {
+        let repos = parse(args)?.resolve_repos(&configs())?;
+        Ok(repos.into_iter().map(|(name, _)| name).collect())
+    }
+
+    fn list_file(content: &str) -> Result<NamedTempFile> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(content.as_bytes())?;
+        Ok(file)
+    }
+
+    #[test]
+    fn test_repo_pattern() -> Result<()> {
+        assert_eq!(resolve(&["--repo-pattern", "www*"])?, ["www", "www-test"]);
+        assert_eq!(resolve(&["--repo-pattern", "ww?"])?, ["www"]);
+        assert_eq!(resolve(&["--repo-pattern", "*source"])?, ["fbsource"]);
+        // Patterns are anchored and other regex characters are literal.
+        assert!(resolve(&["--repo-pattern", "ww"]).is_err());
+        assert!(resolve(&["--repo-pattern", "www.*"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_regex() -> Result<()> {
+        // Patterns are globs, so regex syntax, even invalid, matches literally.
+        assert!(parse_repo_pattern("www(")?.is_match("www("));
+        assert!(parse_repo_pattern("a[b")?.is_match("a[b"));
+        assert!(!parse_repo_pattern("a.b")?.is_match("axb"));
+        let err = resolve(&["--repo-pattern", "www("]).unwrap_err();
+        assert!(err.to_string().contains("no repos match pattern"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_repo_list_file() -> Result<()> {
+        let file = list_file("www\n\n# comment\n  fbsource  \n2\n1\n")?;
+        let path = file.path().to_str().unwrap();
+        // "1" is a repository name, so it isn't resolved as an ID.
+        assert_eq!(
+            resolve(&["--repo-list-file", path])?,
+            ["www", "fbsource", "www-test", "1"]
+        );
+
+        let file = list_file("www\nunknown\n")?;
+        assert!(resolve(&["--repo-list-file", file.path().to_str().unwrap()]).is_err());
+
+        let missing = file.path().with_extension("missing");
+        assert!(resolve(&["--repo-list-file", missing.to_str().unwrap()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedup() -> Result<()> {
+        let file = list_file("www\nfbsource\n")?;
+        assert_eq!(
+            resolve(&[
+                "--repo-id",
+                "2",
+                "--repo-name",
+                "www",
+                "--repo-list-file",
+                file.path().to_str().unwrap(),
+                "--repo-pattern",
+                "www*",
+                "--repo-pattern",
+                "*",
+            ])?,
+            ["www-test", "www", "fbsource", "1"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ids_or_names() -> Result<()> {
+        assert_eq!(
+            parse(&["--repo-id", "1", "-R", "www"])?
+                .ids_or_names()?
+                .len(),
+            2
+        );
+        assert!(parse(&["--repo-pattern", "www*"])?.ids_or_names().is_err());
+        Ok(())
+    }
+}
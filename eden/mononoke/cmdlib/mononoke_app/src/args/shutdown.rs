@@ -0,0 +1,46 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::time::Duration;
+
+use clap::Args;
+
+use crate::args::duration_secs_from_str;
+use crate::args::RenamedArg;
+use crate::AppExtension;
+
+/// Command line arguments for the phases of a graceful shutdown
+#[derive(Args, Debug)]
+pub struct ShutdownArgs {
+    /// Number of seconds to keep serving after receiving a shutdown signal,
+    /// while the service stops accepting new requests
+    #[clap(long, default_value = "0", parse(try_from_str=duration_secs_from_str))]
+    pub shutdown_stop_accepting_period: Duration,
+
+    /// Number of seconds to wait for in-flight requests to drain
+    #[clap(long, default_value = "10", parse(try_from_str=duration_secs_from_str))]
+    pub shutdown_drain_timeout: Duration,
+
+    /// Number of seconds to wait for draining to be cancelled once the drain
+    /// timeout has passed, before aborting the process
+    #[clap(long, default_value = "5", parse(try_from_str=duration_secs_from_str))]
+    pub shutdown_abort_timeout: Duration,
+}
+
+pub struct ShutdownAppExtension;
+
+impl AppExtension for ShutdownAppExtension {
+    type Args = ShutdownArgs;
+
+    fn renamed_args(&self) -> Vec<RenamedArg> {
+        // The names used by `ShutdownTimeoutArgs`.
+        vec![
+            RenamedArg::new("shutdown-grace-period", "shutdown-stop-accepting-period"),
+            RenamedArg::new("shutdown-timeout", "shutdown-drain-timeout"),
+        ]
+    }
+}
@@ -7,9 +7,10 @@
 
 use std::time::Duration;
 
-use anyhow::Result;
 use clap::Args;
 
+use crate::args::duration_secs_from_str;
+
 /// Command line arguments for shutdown timeout
 #[derive(Args, Debug)]
 pub struct ShutdownTimeoutArgs {
@@ -21,7 +22,3 @@ pub struct ShutdownTimeoutArgs {
     #[clap(long, default_value = "10", parse(try_from_str=duration_secs_from_str))]
     pub shutdown_timeout: Duration,
 }
-
-fn duration_secs_from_str(s: &str) -> Result<Duration> {
-    Ok(Duration::from_secs(s.parse::<u64>()?))
-}
@@ -14,10 +14,16 @@ pub struct TunablesArgs {
     #[clap(long)]
     pub tunables_config: Option<String>,
 
-    /// Tunables static config local path
+    /// Tunables config local path. The tunables are reloaded when the file
+    /// changes.
     #[clap(long, conflicts_with = "tunables-config")]
     pub tunables_local_path: Option<String>,
 
+    /// Interval in seconds at which tunables from --tunables-local-path are
+    /// reloaded, even if the file does not appear to have changed
+    #[clap(long, default_value_t = 60)]
+    pub tunables_poll_interval_secs: u64,
+
     /// Use the default values for all tunables (useful for tests)
     #[clap(long, conflicts_with_all = &["tunables-config", "tunables-local-path"])]
     pub disable_tunables: bool,
@@ -7,6 +7,7 @@
 
 use std::any::TypeId;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -24,7 +25,6 @@ use blobstore_factory::PackOptions;
 use blobstore_factory::ReadOnlyStorage;
 use blobstore_factory::ReadOnlyStorageArgs;
 use blobstore_factory::ThrottleOptions;
-use cached_config::ConfigHandle;
 use cached_config::ConfigStore;
 use clap::Args;
 use clap::Command;
@@ -42,9 +42,6 @@ use fbinit::FacebookInit;
 use megarepo_config::MegarepoConfigsArgs;
 use megarepo_config::MononokeMegarepoConfigsOptions;
 use observability::DynamicLevelDrain;
-use permission_checker::AclProvider;
-use permission_checker::DefaultAclProvider;
-use permission_checker::InternalAclProvider;
 use rendezvous::RendezVousArgs;
 use slog::debug;
 use slog::o;
@@ -53,21 +50,24 @@ use slog::Never;
 use slog::SendSyncRefUnwindSafeDrain;
 use sql_ext::facebook::MysqlOptions;
 use sql_ext::facebook::PoolConfig;
-use sql_ext::facebook::ReadConnectionType;
 use sql_ext::facebook::SharedConnectionPool;
 use tokio::runtime::Runtime;
 
 use crate::app::MononokeApp;
 use crate::args::parse_config_spec_to_path;
+use crate::args::renamed;
 use crate::args::AclArgs;
 use crate::args::ConfigArgs;
 use crate::args::MysqlArgs;
+use crate::args::ObservabilityArgs;
+use crate::args::RenamedArg;
 use crate::args::RuntimeArgs;
 use crate::args::TunablesArgs;
 use crate::extension::AppExtension;
 use crate::extension::AppExtensionBox;
 use crate::extension::BoxedAppExtension;
 use crate::extension::BoxedAppExtensionArgs;
+use crate::fixtures;
 
 pub struct MononokeAppBuilder {
     fb: FacebookInit,
@@ -76,6 +76,7 @@ pub struct MononokeAppBuilder {
     cachelib_settings: CachelibSettings,
     default_scuba_dataset: Option<String>,
     defaults: HashMap<&'static str, String>,
+    renamed_args: Vec<RenamedArg>,
     warm_bookmarks_cache_derived_data: Option<WarmBookmarksCacheDerivedData>,
     skiplist_enabled: bool,
 }
@@ -94,6 +95,9 @@ pub struct EnvironmentArgs {
     #[clap(flatten, next_help_heading = "SCUBA LOGGING OPTIONS")]
     scuba_logging_args: ScubaLoggingArgs,
 
+    #[clap(flatten, next_help_heading = "OBSERVABILITY OPTIONS")]
+    observability_args: ObservabilityArgs,
+
     #[clap(flatten, next_help_heading = "CACHELIB OPTIONS")]
     cachelib_args: CachelibArgs,
 
@@ -135,6 +139,7 @@ impl MononokeAppBuilder {
             cachelib_settings: CachelibSettings::default(),
             default_scuba_dataset: None,
             defaults: HashMap::new(),
+            renamed_args: Vec::new(),
             skiplist_enabled: true,
             warm_bookmarks_cache_derived_data: None,
         }
@@ -145,6 +150,13 @@ impl MononokeAppBuilder {
         self
     }
 
+    /// Accept the old names of renamed flags of the app's arguments, with a
+    /// deprecation warning.
+    pub fn with_renamed_args(mut self, renamed_args: impl IntoIterator<Item = RenamedArg>) -> Self {
+        self.renamed_args.extend(renamed_args);
+        self
+    }
+
     pub fn with_default_scuba_dataset(mut self, default: impl Into<String>) -> Self {
         self.default_scuba_dataset = Some(default.into());
         self
@@ -205,6 +217,7 @@ impl MononokeAppBuilder {
             for (arg, default) in ext.arg_defaults() {
                 self.defaults.insert(arg, default);
             }
+            self.renamed_args.extend(ext.renamed_args());
         }
 
         let mut app = AppArgs::command();
@@ -233,7 +246,9 @@ impl MononokeAppBuilder {
             app = app.mut_arg(*name, |arg| arg.default_value(default.as_str()));
         }
 
-        let args = app.get_matches();
+        let (argv, used_renamed_args) =
+            renamed::rename_args(std::env::args_os(), &self.renamed_args);
+        let args = app.get_matches_from(argv);
 
         let extension_args = self
             .extensions
@@ -248,6 +263,8 @@ impl MononokeAppBuilder {
             extension_args.iter().map(|(_type_id, ext)| ext.as_ref()),
         )?;
 
+        renamed::check_renamed_args(&env.logger, &used_renamed_args)?;
+
         for (_type_id, ext) in extension_args.iter() {
             ext.environment_hook(&mut env)?;
         }
@@ -271,7 +288,8 @@ impl MononokeAppBuilder {
             config_args,
             runtime_args,
             logging_args,
-            scuba_logging_args,
+            mut scuba_logging_args,
+            observability_args,
             cachelib_args,
             #[cfg(fbcode_build)]
             manifold_args,
@@ -284,12 +302,24 @@ impl MononokeAppBuilder {
             tunables_args,
         } = env_args;
 
-        let log_level = logging_args.create_log_level();
+        let default_log_level = logging_args.create_log_level();
         #[cfg(fbcode_build)]
-        cmdlib_logging::glog::set_glog_log_level(self.fb, log_level)?;
+        cmdlib_logging::glog::set_glog_log_level(self.fb, default_log_level)?;
+        // Records are filtered per module by the root drain. Other filters
+        // must let through everything a module might log.
+        let log_level = observability_args.max_log_level(default_log_level);
         let root_log_drain = logging_args
-            .create_root_log_drain(self.fb, log_level)
+            .create_root_log_drain_with_format(self.fb, log_level, observability_args.log_format)
             .context("Failed to create root log drain")?;
+        let root_log_drain =
+            observability_args.module_level_drain(root_log_drain, default_log_level);
+        observability_args
+            .init_tracing(default_log_level)
+            .context("Failed to initialize tracing")?;
+
+        if let Some(dir) = &config_args.local_fixtures {
+            fixtures::setup_local_fixtures(dir).context("Failed to set up local fixtures")?;
+        }
 
         let config_store = create_config_store(
             self.fb,
@@ -313,6 +343,9 @@ impl MononokeAppBuilder {
 
         let logger = logging_args.create_logger(root_log_drain)?;
 
+        if let Some(dataset) = observability_args.scuba_dataset_override {
+            scuba_logging_args.scuba_dataset = Some(dataset);
+        }
         let scuba_sample_builder = scuba_logging_args
             .create_scuba_sample_builder(
                 self.fb,
@@ -329,7 +362,8 @@ impl MononokeAppBuilder {
         let runtime = create_runtime(&runtime_args)?;
 
         let mysql_options =
-            create_mysql_options(&mysql_args, create_mysql_pool_config(&mysql_args));
+            create_mysql_options(&mysql_args, create_mysql_pool_config(&mysql_args))
+                .context("Failed to parse MySQL options")?;
 
         let blobstore_options = create_blobstore_options(
             &blobstore_args,
@@ -350,8 +384,9 @@ impl MononokeAppBuilder {
 
         let remote_derivation_options = remote_derivation_args.into();
 
-        let acl_provider =
-            create_acl_provider(self.fb, &acl_args).context("Failed to create ACL provider")?;
+        let acl_provider = acl_args
+            .create_acl_provider(self.fb)
+            .context("Failed to create ACL provider")?;
 
         init_tunables_worker(&tunables_args, &config_store, logger.clone())?;
 
@@ -395,6 +430,13 @@ fn create_config_store(
             String::new(),
             CONFIGERATOR_POLL_INTERVAL,
         ))
+    } else if let Some(dir) = &config_args.local_fixtures {
+        Ok(ConfigStore::file(
+            logger,
+            fixtures::configerator_path(dir),
+            String::new(),
+            CONFIGERATOR_POLL_INTERVAL,
+        ))
     } else {
         let crypto_regex_paths = match &config_args.crypto_path_regex {
             Some(paths) => paths.clone(),
@@ -429,18 +471,14 @@ fn create_runtime(runtime_args: &RuntimeArgs) -> Result<Runtime> {
     Ok(runtime)
 }
 
-fn create_mysql_options(mysql_args: &MysqlArgs, pool_config: PoolConfig) -> MysqlOptions {
+fn create_mysql_options(mysql_args: &MysqlArgs, pool_config: PoolConfig) -> Result<MysqlOptions> {
     let pool = SharedConnectionPool::new();
-    let read_connection_type = if mysql_args.mysql_master_only {
-        ReadConnectionType::Master
-    } else {
-        ReadConnectionType::ReplicaOnly
-    };
-    MysqlOptions {
+    Ok(MysqlOptions {
         pool,
         pool_config,
-        read_connection_type,
-    }
+        read_connection_type: mysql_args.read_connection_type(),
+        max_replica_lag: mysql_args.max_replica_lag()?,
+    })
 }
 
 fn create_mysql_pool_config(mysql_args: &MysqlArgs) -> PoolConfig {
@@ -500,7 +538,7 @@ fn create_blobstore_options(
     let blobstore_put_behaviour = blobstore_args.blobstore_put_behaviour;
 
     let mysql_sqlblob_options =
-        create_mysql_options(mysql_args, create_mysql_sqlblob_pool_config(mysql_args));
+        create_mysql_options(mysql_args, create_mysql_sqlblob_pool_config(mysql_args))?;
 
     let blobstore_options = BlobstoreOptions::new(
         chaos_options,
@@ -528,11 +566,11 @@ fn init_tunables_worker(
     }
 
     if let Some(tunables_local_path) = &tunables_args.tunables_local_path {
-        let value = std::fs::read_to_string(tunables_local_path)
-            .with_context(|| format!("failed to open tunables path {}", tunables_local_path))?;
-        let config_handle = ConfigHandle::from_json(&value)
-            .with_context(|| format!("failed to parse tunables at path {}", tunables_local_path))?;
-        return tunables::init_tunables_worker(logger, config_handle);
+        return tunables::init_tunables_file_worker(
+            logger,
+            PathBuf::from(tunables_local_path),
+            Duration::from_secs(tunables_args.tunables_poll_interval_secs),
+        );
     }
 
     let tunables_config = tunables_args.tunables_config_or_default();
@@ -541,13 +579,3 @@ fn init_tunables_worker(
 
     tunables::init_tunables_worker(logger, config_handle)
 }
-
-fn create_acl_provider(fb: FacebookInit, acl_args: &AclArgs) -> Result<Arc<dyn AclProvider>> {
-    let acl_provider = match &acl_args.acl_file {
-        Some(acl_file) => InternalAclProvider::from_file(acl_file).with_context(|| {
-            format!("Failed to load ACLs from '{}'", acl_file.to_string_lossy())
-        })?,
-        None => DefaultAclProvider::new(fb),
-    };
-    Ok(acl_provider)
-}
@@ -0,0 +1,281 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Mononoke configs that are not in a local directory.
+//!
+//! `--config-path` accepts, besides a local directory or a configerator path:
+//!
+//! * `tar:<path>`: a tarball of the config directory, optionally gzipped.
+//! * `https://<url>`: a tarball of the config directory, fetched over HTTPS.
+//!   `--config-sha256` must be given and is checked against the fetched
+//!   tarball.  Tarballs are cached under `--config-cache-dir`, named after
+//!   their checksum, which is checked again whenever a cached tarball is
+//!   used.
+//!
+//! Tarballs are unpacked to a private temporary directory which is removed
+//! once the configs are loaded.
+
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use flate2::read::GzDecoder;
+use hyper::body;
+use hyper::Client;
+use hyper::StatusCode;
+use hyper::Uri;
+use hyper_openssl::HttpsConnector;
+use sha2::Digest;
+use sha2::Sha256;
+use tar::Archive;
+use tempfile::NamedTempFile;
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+
+use crate::args::ConfigArgs;
+
+const HTTPS_PREFIX: &str = "https://";
+const TAR_PREFIX: &str = "tar:";
+const DEFAULT_CACHE_DIR: &str = "mononoke_config_cache";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const FETCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Local path of the config, to be passed to the config loaders.
+pub(crate) struct ConfigSource {
+    path: String,
+    /// Directory a tarball was unpacked to, removed when dropped.
+    _temp_dir: Option<TempDir>,
+}
+
+impl ConfigSource {
+    /// Resolve the config path given by `config_args` to a local path,
+    /// fetching and unpacking it if needed.
+    pub(crate) fn resolve(config_args: &ConfigArgs, runtime: &Runtime) -> Result<Self> {
+        let config_path = config_args.config_path();
+        if config_path.starts_with(HTTPS_PREFIX) {
+            let sha256 = config_args
+                .config_sha256
+                .as_deref()
+                .ok_or_else(|| anyhow!("--config-sha256 is required for {}", config_path))?;
+            let cache_dir = config_args
+                .config_cache_dir
+                .clone()
+                .unwrap_or_else(|| env::temp_dir().join(DEFAULT_CACHE_DIR));
+            let bundle = read_cached(sha256, &cache_dir, || {
+                runtime.block_on(fetch_with_timeout(&config_path))
+            })
+            .with_context(|| format!("Failed to fetch config from {}", config_path))?;
+            let temp_dir = unpack_to_temp_dir(&bundle)
+                .with_context(|| format!("Failed to unpack config from {}", config_path))?;
+            Ok(ConfigSource::from_temp_dir(temp_dir))
+        } else if let Some(tarball) = config_path.strip_prefix(TAR_PREFIX) {
+            let bundle = fs::read(tarball)
+                .with_context(|| format!("Failed to read config tarball {}", tarball))?;
+            let temp_dir = unpack_to_temp_dir(&bundle)
+                .with_context(|| format!("Failed to unpack config tarball {}", tarball))?;
+            Ok(ConfigSource::from_temp_dir(temp_dir))
+        } else {
+            Ok(ConfigSource {
+                path: config_path,
+                _temp_dir: None,
+            })
+        }
+    }
+
+    fn from_temp_dir(temp_dir: TempDir) -> Self {
+        ConfigSource {
+            path: temp_dir.path().to_string_lossy().into_owned(),
+            _temp_dir: Some(temp_dir),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// The tarball whose checksum is `sha256`. It is read from `cache_dir` if
+/// it is there and its checksum matches, so a tampered cache is never
+/// trusted. Otherwise it is fetched with `fetch` and cached.
+fn read_cached(
+    sha256: &str,
+    cache_dir: &Path,
+    fetch: impl FnOnce() -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    let sha256 = sha256.to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid --config-sha256: {}", sha256));
+    }
+    let path = cache_dir.join(format!("{}.tar", sha256));
+    if let Ok(bundle) = fs::read(&path) {
+        if checksum(&bundle) == sha256 {
+            return Ok(bundle);
+        }
+    }
+
+    let bundle = fetch()?;
+    let actual = checksum(&bundle);
+    if actual != sha256 {
+        return Err(anyhow!(
+            "Checksum mismatch: expected {}, got {}",
+            sha256,
+            actual
+        ));
+    }
+    // The cache is only an optimization. The config can be loaded without
+    // it.
+    let _ = write_cache(cache_dir, &path, &bundle);
+    Ok(bundle)
+}
+
+/// Write `bundle` to `path` atomically, so concurrent processes never read
+/// a partially written tarball.
+fn write_cache(cache_dir: &Path, path: &Path, bundle: &[u8]) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(cache_dir)?;
+    let mut file = NamedTempFile::new_in(cache_dir)?;
+    file.write_all(bundle)?;
+    file.persist(path)?;
+    Ok(())
+}
+
+fn checksum(bundle: &[u8]) -> String {
+    hex::encode(Sha256::digest(bundle))
+}
+
+async fn fetch_with_timeout(url: &str) -> Result<Vec<u8>> {
+    tokio::time::timeout(FETCH_TIMEOUT, fetch(url))
+        .await
+        .map_err(|_| anyhow!("Timed out after {:?}", FETCH_TIMEOUT))?
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>> {
+    let uri: Uri = url.parse()?;
+    let connector = HttpsConnector::new()?;
+    let client = Client::builder().build::<_, hyper::Body>(connector);
+    let response = client.get(uri).await?;
+    if response.status() != StatusCode::OK {
+        return Err(anyhow!("Unexpected status: {}", response.status()));
+    }
+    let bytes = body::to_bytes(response.into_body()).await?;
+    Ok(bytes.to_vec())
+}
+
+/// Unpack a tarball, which may be gzipped, into a new temporary directory
+/// that only the current user can access.
+fn unpack_to_temp_dir(bundle: &[u8]) -> Result<TempDir> {
+    let temp_dir = tempfile::Builder::new()
+        .prefix("mononoke_config")
+        .tempdir()?;
+    unpack(bundle, temp_dir.path())?;
+    Ok(temp_dir)
+}
+
+/// Unpack a tarball, which may be gzipped, into `dir`.
+fn unpack(bundle: &[u8], dir: &Path) -> Result<()> {
+    let reader: Box<dyn Read + '_> = if bundle.starts_with(&GZIP_MAGIC) {
+        Box::new(GzDecoder::new(bundle))
+    } else {
+        Box::new(bundle)
+    };
+    Archive::new(reader).unpack(dir)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    /// A tarball with a single file.
+    fn tarball(name: &str, content: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, content).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_unpack() -> Result<()> {
+        let bundle = tarball("repos/a.toml", b"plain");
+        let dir = unpack_to_temp_dir(&bundle)?;
+        assert_eq!(fs::read(dir.path().join("repos/a.toml"))?, b"plain");
+
+        let bundle = gzip(&tarball("common.toml", b"gzipped"));
+        let dir = unpack_to_temp_dir(&bundle)?;
+        assert_eq!(fs::read(dir.path().join("common.toml"))?, b"gzipped");
+
+        assert!(unpack_to_temp_dir(b"not a tarball").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_mismatch() -> Result<()> {
+        let cache_dir = tempfile::tempdir()?;
+        let bundle = tarball("a.toml", b"a");
+        let other = checksum(&tarball("a.toml", b"b"));
+        let err = read_cached(&other, cache_dir.path(), || Ok(bundle.clone())).unwrap_err();
+        assert!(err.to_string().starts_with("Checksum mismatch"));
+        // Nothing is cached.
+        assert_eq!(fs::read_dir(cache_dir.path())?.count(), 0);
+
+        assert!(read_cached("abc", cache_dir.path(), || Ok(bundle)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_hit() -> Result<()> {
+        let cache_dir = tempfile::tempdir()?;
+        let bundle = tarball("a.toml", b"a");
+        let sha256 = checksum(&bundle);
+        let fetches = Cell::new(0);
+        let fetch = || -> Result<Vec<u8>> {
+            fetches.set(fetches.get() + 1);
+            Ok(bundle.clone())
+        };
+
+        assert_eq!(read_cached(&sha256, cache_dir.path(), fetch)?, bundle);
+        assert_eq!(fetches.get(), 1);
+        // Cached, and the checksum is not case sensitive.
+        let upper = sha256.to_ascii_uppercase();
+        assert_eq!(read_cached(&upper, cache_dir.path(), fetch)?, bundle);
+        assert_eq!(fetches.get(), 1);
+
+        // A tampered cache entry is fetched again.
+        let path = cache_dir.path().join(format!("{}.tar", sha256));
+        fs::write(&path, tarball("a.toml", b"evil"))?;
+        assert_eq!(read_cached(&sha256, cache_dir.path(), fetch)?, bundle);
+        assert_eq!(fetches.get(), 2);
+        assert_eq!(fs::read(&path)?, bundle);
+        Ok(())
+    }
+}
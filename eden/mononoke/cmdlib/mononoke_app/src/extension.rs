@@ -17,6 +17,8 @@ use environment::MononokeEnvironment;
 use slog::Never;
 use slog::SendSyncRefUnwindSafeDrain;
 
+use crate::args::RenamedArg;
+
 /// Trait implemented by things that need to extend the app building process,
 /// including adding additional arguments and modifying the environment before
 /// it is used to start Mononoke.
@@ -29,6 +31,11 @@ pub trait AppExtension: Send + Sync + 'static {
         Vec::new()
     }
 
+    /// Flags of these arguments that were renamed.
+    fn renamed_args(&self) -> Vec<RenamedArg> {
+        Vec::new()
+    }
+
     /// Hook executed after creating the environment before initializing Mononoke.
     fn environment_hook(&self, _args: &Self::Args, _env: &mut MononokeEnvironment) -> Result<()> {
         Ok(())
@@ -48,6 +55,7 @@ pub trait AppExtension: Send + Sync + 'static {
 pub(crate) trait BoxedAppExtension: Send + Sync + 'static {
     fn augment_args<'help>(&self, app: Command<'help>) -> Command<'help>;
     fn arg_defaults(&self) -> Vec<(&'static str, String)>;
+    fn renamed_args(&self) -> Vec<RenamedArg>;
     fn parse_args(&self, args: &ArgMatches) -> Result<Box<dyn BoxedAppExtensionArgs>>;
 }
 
@@ -72,6 +80,10 @@ impl<Ext: AppExtension> BoxedAppExtension for AppExtensionBox<Ext> {
         self.ext.arg_defaults()
     }
 
+    fn renamed_args(&self) -> Vec<RenamedArg> {
+        self.ext.renamed_args()
+    }
+
     fn parse_args(&self, args: &ArgMatches) -> Result<Box<dyn BoxedAppExtensionArgs>> {
         let args = Ext::Args::from_arg_matches(args)?;
         Ok(Box::new(AppExtensionArgsBox {
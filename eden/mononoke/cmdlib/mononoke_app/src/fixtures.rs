@@ -0,0 +1,352 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Local fixture repos, to run Mononoke binaries without production config sources.
+//!
+//! `--local-fixtures <dir>` uses the following layout, which is created as needed:
+//!
+//! * `<dir>/repos/<name>/`: a repo, with its blobs and its SQLite databases.  Create a
+//!   directory here to add a repo.  A repo named "repo" is created if there is none.
+//! * `<dir>/config/`: the Mononoke config of the repos, rewritten on every run.
+//! * `<dir>/configerator/`: the local configerator configs (tunables, observability,
+//!   redaction), written if they are missing so they can be edited.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+
+const DEFAULT_REPO_NAME: &str = "repo";
+const REPO_ID_FILE: &str = "repo_id";
+const REDACTION_STORAGE: &str = "fixture_redaction";
+
+/// Mononoke config of the fixture repos.
+pub(crate) fn config_path(dir: &Path) -> PathBuf {
+    dir.join("config")
+}
+
+/// Local configerator configs of the fixture repos.
+pub(crate) fn configerator_path(dir: &Path) -> PathBuf {
+    dir.join("configerator")
+}
+
+/// Write the configs of the fixture repos under `dir`.
+pub(crate) fn setup_local_fixtures(dir: &Path) -> Result<()> {
+    let repos_dir = dir.join("repos");
+    fs::create_dir_all(&repos_dir)
+        .with_context(|| format!("Failed to create {}", repos_dir.display()))?;
+    let mut names = repo_names(&repos_dir)?;
+    if names.is_empty() {
+        fs::create_dir(repos_dir.join(DEFAULT_REPO_NAME))?;
+        names.push(DEFAULT_REPO_NAME.to_string());
+    }
+
+    // Blobs are keyed by repo id, so ids are assigned once and kept in the repo directory.
+    let mut repos = Vec::with_capacity(names.len());
+    let mut next_id = 0;
+    for name in names {
+        let id = read_repo_id(&repos_dir.join(&name))?;
+        if let Some(id) = id {
+            next_id = next_id.max(id + 1);
+        }
+        repos.push((name, id));
+    }
+    let repos = repos
+        .into_iter()
+        .map(|(name, id)| {
+            let id = match id {
+                Some(id) => id,
+                None => {
+                    let id = next_id;
+                    next_id += 1;
+                    fs::write(repos_dir.join(&name).join(REPO_ID_FILE), id.to_string())?;
+                    id
+                }
+            };
+            Ok((name, id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    write_config(dir, &repos_dir, &repos)?;
+    write_configerator_configs(&configerator_path(dir))
+}
+
+/// Names of the repo directories, sorted.
+fn repo_names(repos_dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(repos_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn read_repo_id(repo_dir: &Path) -> Result<Option<i32>> {
+    let path = repo_dir.join(REPO_ID_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let id = fs::read_to_string(&path)?;
+    let id = id
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid repo id in {}", path.display()))?;
+    Ok(Some(id))
+}
+
+// The subset of the raw Mononoke config written for the fixture repos.  Serializing it, rather
+// than formatting TOML by hand, keeps names and paths with special characters well-formed.
+
+#[derive(Serialize)]
+struct StorageConfig {
+    metadata: MetadataConfig,
+    blobstore: BlobstoreConfig,
+}
+
+#[derive(Serialize)]
+struct MetadataConfig {
+    local: LocalDbConfig,
+}
+
+#[derive(Serialize)]
+struct LocalDbConfig {
+    local_db_path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct BlobstoreConfig {
+    blob_files: BlobFilesConfig,
+}
+
+#[derive(Serialize)]
+struct BlobFilesConfig {
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct RepoConfig<'a> {
+    storage_config: &'a str,
+}
+
+#[derive(Serialize)]
+struct RepoDefinition<'a> {
+    repo_id: i32,
+    repo_name: &'a str,
+    repo_config: &'a str,
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+struct CommonConfig<'a> {
+    internal_identity: IdentityConfig<'a>,
+    redaction_config: RedactionConfig<'a>,
+}
+
+#[derive(Serialize)]
+struct IdentityConfig<'a> {
+    identity_type: &'a str,
+    identity_data: &'a str,
+}
+
+#[derive(Serialize)]
+struct RedactionConfig<'a> {
+    blobstore: &'a str,
+    redaction_sets_location: &'a str,
+}
+
+fn storage_config(path: &Path) -> Result<StorageConfig> {
+    fs::create_dir_all(path.join("blobs"))?;
+    fs::create_dir_all(path.join("sqlite"))?;
+    Ok(StorageConfig {
+        metadata: MetadataConfig {
+            local: LocalDbConfig {
+                local_db_path: path.join("sqlite"),
+            },
+        },
+        blobstore: BlobstoreConfig {
+            blob_files: BlobFilesConfig {
+                path: path.to_path_buf(),
+            },
+        },
+    })
+}
+
+fn write_toml(path: &Path, value: &impl Serialize) -> Result<()> {
+    let content = toml::to_string(value)
+        .with_context(|| format!("Failed to serialize {}", path.display()))?;
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn write_config(dir: &Path, repos_dir: &Path, repos: &[(String, i32)]) -> Result<()> {
+    let config_path = config_path(dir);
+    let common_dir = config_path.join("common");
+    fs::create_dir_all(&common_dir)?;
+
+    let mut storage = BTreeMap::new();
+    storage.insert(
+        REDACTION_STORAGE.to_string(),
+        storage_config(&dir.join("redaction"))?,
+    );
+    for (name, id) in repos {
+        let storage_name = format!("fixture_{}", name);
+        storage.insert(storage_name.clone(), storage_config(&repos_dir.join(name))?);
+
+        let repo_config_dir = config_path.join("repos").join(name);
+        fs::create_dir_all(&repo_config_dir)?;
+        write_toml(
+            &repo_config_dir.join("server.toml"),
+            &RepoConfig {
+                storage_config: &storage_name,
+            },
+        )?;
+
+        let repo_definition_dir = config_path.join("repo_definitions").join(name);
+        fs::create_dir_all(&repo_definition_dir)?;
+        write_toml(
+            &repo_definition_dir.join("server.toml"),
+            &RepoDefinition {
+                repo_id: *id,
+                repo_name: name,
+                repo_config: name,
+                enabled: true,
+            },
+        )?;
+    }
+    write_toml(&common_dir.join("storage.toml"), &storage)?;
+
+    write_toml(
+        &common_dir.join("common.toml"),
+        &CommonConfig {
+            internal_identity: IdentityConfig {
+                identity_type: "SERVICE_IDENTITY",
+                identity_data: "mononoke",
+            },
+            redaction_config: RedactionConfig {
+                blobstore: REDACTION_STORAGE,
+                redaction_sets_location: "scm/mononoke/redaction/redaction_sets",
+            },
+        },
+    )
+}
+
+fn write_configerator_configs(configerator_path: &Path) -> Result<()> {
+    let configs = [
+        ("scm/mononoke/tunables/default", "{}"),
+        (
+            "scm/mononoke/observability/observability_config",
+            r#"{
+  "slog_config": {
+    "level": 4
+  },
+  "scuba_config": {
+    "level": 1,
+    "verbose_sessions": [],
+    "verbose_unixnames": [],
+    "verbose_source_hostnames": []
+  }
+}"#,
+        ),
+        (
+            "scm/mononoke/redaction/redaction_sets",
+            r#"{
+  "all_redactions": []
+}"#,
+        ),
+    ];
+    for (config, content) in configs {
+        let path = configerator_path.join(config);
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, content)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use cached_config::ConfigStore;
+    use cached_config::TestSource;
+    use metaconfig_types::BlobConfig;
+
+    use super::*;
+
+    fn load_repo_configs(dir: &Path) -> metaconfig_parser::RepoConfigs {
+        let config_store = ConfigStore::new(Arc::new(TestSource::new()), None, None);
+        metaconfig_parser::load_repo_configs(config_path(dir), &config_store).unwrap()
+    }
+
+    #[test]
+    fn test_default_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        setup_local_fixtures(dir.path()).unwrap();
+
+        let configs = load_repo_configs(dir.path());
+        assert_eq!(configs.repos.len(), 1);
+        let repo = &configs.repos[DEFAULT_REPO_NAME];
+        assert_eq!(repo.repoid.id(), 0);
+        match &repo.storage_config.blobstore {
+            BlobConfig::Files { path } => {
+                assert_eq!(path, &dir.path().join("repos").join(DEFAULT_REPO_NAME))
+            }
+            other => panic!("unexpected blobstore {:?}", other),
+        }
+        assert!(
+            configerator_path(dir.path())
+                .join("scm/mononoke/tunables/default")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_repo_ids_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let repos_dir = dir.path().join("repos");
+        fs::create_dir_all(repos_dir.join("b")).unwrap();
+        setup_local_fixtures(dir.path()).unwrap();
+
+        // A new repo gets a fresh id, even if it sorts before the existing one.
+        fs::create_dir_all(repos_dir.join("a")).unwrap();
+        setup_local_fixtures(dir.path()).unwrap();
+
+        let configs = load_repo_configs(dir.path());
+        assert_eq!(configs.repos["b"].repoid.id(), 0);
+        assert_eq!(configs.repos["a"].repoid.id(), 1);
+    }
+
+    #[test]
+    fn test_special_characters() {
+        // Quotes and backslashes in names and paths must not break the generated TOML.
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().join("fix\"tures\\");
+        let name = "my \"repo\"";
+        fs::create_dir_all(dir.join("repos").join(name)).unwrap();
+        setup_local_fixtures(&dir).unwrap();
+
+        let configs = load_repo_configs(&dir);
+        let repo = &configs.repos[name];
+        match &repo.storage_config.metadata {
+            metaconfig_types::MetadataDatabaseConfig::Local(local) => {
+                assert_eq!(local.path, dir.join("repos").join(name).join("sqlite"))
+            }
+            other => panic!("unexpected metadata db {:?}", other),
+        }
+    }
+}
@@ -8,8 +8,14 @@
 mod app;
 pub mod args;
 mod builder;
+mod config_source;
 mod extension;
 pub mod fb303;
+mod fixtures;
+pub mod maintenance_window;
+pub mod progress;
+pub mod shutdown;
+mod signal;
 
 pub use app::MononokeApp;
 pub use builder::MononokeAppBuilder;
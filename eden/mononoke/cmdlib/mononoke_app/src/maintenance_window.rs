@@ -0,0 +1,207 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Bounds on when and for how long batch jobs run.
+
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use mononoke_types::DateTime;
+use slog::error;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use crate::args::MaintenanceWindowArgs;
+use crate::signal::shutdown_signal;
+
+/// The period during which a batch job is allowed to run. Jobs should check
+/// `is_closed` between steps, and checkpoint and exit once it returns true.
+#[derive(Clone)]
+pub struct MaintenanceWindow {
+    logger: Logger,
+    deadline: Option<Instant>,
+    shutdown_requested: Arc<AtomicBool>,
+    /// Time to stop after a shutdown was requested, before `run` aborts the
+    /// job.
+    grace: Option<Duration>,
+    abort_sender: Arc<watch::Sender<bool>>,
+    aborted: watch::Receiver<bool>,
+}
+
+impl MaintenanceWindow {
+    /// Wait for the window to open, and start watching for shutdown signals
+    /// if requested.
+    pub(crate) async fn open(logger: &Logger, args: &MaintenanceWindowArgs) -> Result<Self> {
+        if let Some(not_before) = &args.not_before {
+            let wait = not_before.timestamp_secs() - DateTime::now().timestamp_secs();
+            if wait > 0 {
+                info!(
+                    logger,
+                    "Waiting {}s for the maintenance window to open", wait
+                );
+                tokio::time::sleep(Duration::from_secs(wait as u64)).await;
+            }
+        }
+
+        let window = Self::new(logger, args);
+        if window.grace.is_some() {
+            let signalled = shutdown_signal()?;
+            let window = window.clone();
+            tokio::spawn(async move {
+                signalled.await;
+                window.request_shutdown();
+            });
+        }
+        Ok(window)
+    }
+
+    fn new(logger: &Logger, args: &MaintenanceWindowArgs) -> Self {
+        let (abort_sender, aborted) = watch::channel(false);
+        MaintenanceWindow {
+            logger: logger.clone(),
+            deadline: args
+                .max_runtime
+                .map(|max_runtime| Instant::now() + max_runtime),
+            shutdown_requested: Arc::new(AtomicBool::new(false)),
+            grace: args.abort_on_shutdown_signal_grace,
+            abort_sender: Arc::new(abort_sender),
+            aborted,
+        }
+    }
+
+    /// Returns true once the maximum runtime is exceeded or a shutdown
+    /// signal has been received.
+    pub fn is_closed(&self) -> bool {
+        self.shutdown_requested.load(Ordering::Acquire)
+            || self
+                .deadline
+                .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    /// Run `job`, which should stop once the window closes. If it is still
+    /// running the grace period after a shutdown signal, it is cancelled and
+    /// an error is returned.
+    pub async fn run<T>(&self, job: impl Future<Output = Result<T>>) -> Result<T> {
+        let mut aborted = self.aborted.clone();
+        let aborted = async move {
+            while !*aborted.borrow() {
+                if aborted.changed().await.is_err() {
+                    // The window is gone, so the job can't be aborted.
+                    futures::future::pending::<()>().await;
+                }
+            }
+        };
+        tokio::select! {
+            result = job => result,
+            _ = aborted => Err(anyhow!(
+                "Did not stop within {}s of the shutdown signal",
+                self.grace.unwrap_or_default().as_secs()
+            )),
+        }
+    }
+
+    /// Close the window, and abort `run` if the job is still running once
+    /// the grace period is over.
+    fn request_shutdown(&self) {
+        warn!(
+            self.logger,
+            "Shutdown signal received, stopping at the next checkpoint"
+        );
+        self.shutdown_requested.store(true, Ordering::Release);
+
+        if let Some(grace) = self.grace {
+            let window = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(grace).await;
+                error!(
+                    window.logger,
+                    "Did not stop within {}s of the shutdown signal, aborting",
+                    grace.as_secs()
+                );
+                // This never fails, as the window holds a receiver.
+                let _ = window.abort_sender.send(true);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::o;
+
+    use super::*;
+
+    fn args(max_runtime: Option<u64>, grace: Option<u64>) -> MaintenanceWindowArgs {
+        MaintenanceWindowArgs {
+            max_runtime: max_runtime.map(Duration::from_secs),
+            not_before: None,
+            abort_on_shutdown_signal_grace: grace.map(Duration::from_secs),
+        }
+    }
+
+    fn test_window(max_runtime: Option<u64>, grace: Option<u64>) -> MaintenanceWindow {
+        // Not `open`, which would install signal handlers in the test process.
+        MaintenanceWindow::new(
+            &Logger::root(slog::Discard, o!()),
+            &args(max_runtime, grace),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_runtime() -> Result<()> {
+        let window = test_window(Some(10), None);
+        assert!(!window.is_closed());
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert!(window.is_closed());
+
+        // Without a maximum runtime, the window stays open.
+        let window = test_window(None, None);
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        assert!(!window.is_closed());
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_stops_job() -> Result<()> {
+        let window = test_window(None, Some(10));
+        window.request_shutdown();
+        assert!(window.is_closed());
+
+        // A job which stops within the grace period returns its result.
+        let job = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(42)
+        };
+        assert_eq!(window.run(job).await?, 42);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_aborts_job() -> Result<()> {
+        let window = test_window(None, Some(10));
+        let job = {
+            let window = window.clone();
+            async move {
+                window.request_shutdown();
+                futures::future::pending::<Result<()>>().await
+            }
+        };
+        assert_eq!(
+            window.run(job).await.unwrap_err().to_string(),
+            "Did not stop within 10s of the shutdown signal"
+        );
+        Ok(())
+    }
+}
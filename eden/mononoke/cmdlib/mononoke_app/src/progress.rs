@@ -0,0 +1,344 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Progress reporting, checkpointing and retries for bulk operations.
+
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use slog::info;
+use slog::o;
+use slog::warn;
+use slog::Logger;
+
+use crate::args::ProgressArgs;
+use crate::maintenance_window::MaintenanceWindow;
+
+/// Tracks a long-running bulk operation, such as a backfill or a
+/// verification. Logs progress periodically, persists checkpoints so the
+/// operation can be restarted, and retries failing steps.
+pub struct BulkOperation {
+    logger: Logger,
+    total: Option<u64>,
+    /// Items processed by previous runs, as recorded in the checkpoint.
+    resumed: u64,
+    /// Items processed by this run.
+    processed: u64,
+    started: Instant,
+    last_report: Instant,
+    progress_interval: Duration,
+    checkpoint_file: Option<PathBuf>,
+    checkpoint_every: u64,
+    since_checkpoint: u64,
+    max_retries: usize,
+    retry_delay: Duration,
+    window: Option<MaintenanceWindow>,
+}
+
+impl BulkOperation {
+    pub(crate) fn new(
+        logger: Logger,
+        name: String,
+        total: Option<u64>,
+        args: &ProgressArgs,
+    ) -> Self {
+        let now = Instant::now();
+        BulkOperation {
+            logger: logger.new(o!("operation" => name)),
+            total,
+            resumed: 0,
+            processed: 0,
+            started: now,
+            last_report: now,
+            progress_interval: args.progress_interval,
+            checkpoint_file: args.checkpoint_file.clone(),
+            checkpoint_every: args.checkpoint_every.max(1),
+            since_checkpoint: 0,
+            max_retries: args.max_retries,
+            retry_delay: args.retry_delay,
+            window: None,
+        }
+    }
+
+    /// Stop the operation when `window` closes. See `should_stop`.
+    pub fn with_maintenance_window(mut self, window: MaintenanceWindow) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// The checkpoint persisted by a previous run, if any. The operation
+    /// should resume after this point. Items processed by previous runs
+    /// count towards the total when estimating the remaining time.
+    pub fn resume_from(&mut self) -> Result<Option<String>> {
+        let path = match &self.checkpoint_file {
+            Some(path) if path.exists() => path,
+            _ => return Ok(None),
+        };
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read checkpoint from {}", path.display()))?;
+        // The checkpoint is on the first line, followed by the number of
+        // items processed so far. Older checkpoints only have the first line.
+        let mut lines = content.lines();
+        let checkpoint = lines.next().unwrap_or_default().trim();
+        if checkpoint.is_empty() {
+            return Ok(None);
+        }
+        self.resumed = match lines.next() {
+            Some(count) => count
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid item count in checkpoint {}", path.display()))?,
+            None => 0,
+        };
+        info!(
+            self.logger,
+            "Resuming from checkpoint {} after {} items", checkpoint, self.resumed
+        );
+        Ok(Some(checkpoint.to_string()))
+    }
+
+    /// Record that `count` more items have been processed. `checkpoint`
+    /// identifies the last of them, and is persisted every
+    /// `--checkpoint-every` items.
+    pub fn record(&mut self, count: u64, checkpoint: &str) -> Result<()> {
+        self.processed += count;
+        self.since_checkpoint += count;
+        if self.since_checkpoint >= self.checkpoint_every {
+            self.save_checkpoint(checkpoint)?;
+        }
+        if self.last_report.elapsed() >= self.progress_interval {
+            self.report();
+        }
+        Ok(())
+    }
+
+    /// Returns true if the operation should stop because its maintenance
+    /// window has closed. In that case `checkpoint`, which identifies the
+    /// last processed item, is persisted so the next run resumes after it.
+    pub fn should_stop(&mut self, checkpoint: &str) -> Result<bool> {
+        match &self.window {
+            Some(window) if window.is_closed() => {
+                self.save_checkpoint(checkpoint)?;
+                info!(
+                    self.logger,
+                    "Maintenance window closed: stopping after {} items at checkpoint {}",
+                    self.processed,
+                    checkpoint
+                );
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Run `step`, retrying up to `--max-retries` times if it fails.
+    pub async fn retry<T, F, Fut>(&self, mut step: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match step().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_retries => {
+                    attempt += 1;
+                    warn!(
+                        self.logger,
+                        "Attempt {} of {} failed, retrying: {:?}",
+                        attempt,
+                        self.max_retries + 1,
+                        e
+                    );
+                    tokio::time::sleep(self.retry_delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Finish the operation, persisting the final checkpoint and logging
+    /// the overall rate.
+    pub fn finish(mut self, checkpoint: &str) -> Result<()> {
+        self.save_checkpoint(checkpoint)?;
+        let elapsed = self.started.elapsed();
+        info!(
+            self.logger,
+            "Finished: processed {} items in {:.1}s ({:.1} items/sec)",
+            self.processed,
+            elapsed.as_secs_f64(),
+            rate(self.processed, elapsed),
+        );
+        Ok(())
+    }
+
+    fn save_checkpoint(&mut self, checkpoint: &str) -> Result<()> {
+        self.since_checkpoint = 0;
+        if let Some(path) = &self.checkpoint_file {
+            // Write to a temporary file and rename it so that a crash never
+            // leaves a partial checkpoint behind.
+            let tmp = path.with_extension("tmp");
+            let content = format!("{}\n{}\n", checkpoint, self.resumed + self.processed);
+            fs::write(&tmp, content)
+                .and_then(|_| fs::rename(&tmp, path))
+                .with_context(|| format!("Failed to save checkpoint to {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// The estimated time to process the remaining items, based on the
+    /// rate of this run. Items processed by previous runs are not counted
+    /// towards the rate, as `elapsed` doesn't include the time they took.
+    fn eta(&self, elapsed: Duration) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.resumed + self.processed);
+        let rate = rate(self.processed, elapsed);
+        if rate > 0.0 {
+            Some(Duration::from_secs_f64(remaining as f64 / rate))
+        } else {
+            None
+        }
+    }
+
+    fn report(&mut self) {
+        self.last_report = Instant::now();
+        let elapsed = self.started.elapsed();
+        let rate = rate(self.processed, elapsed);
+        let done = self.resumed + self.processed;
+        match self.total {
+            Some(total) => {
+                let eta = match self.eta(elapsed) {
+                    Some(eta) => format!("{:.0}s", eta.as_secs_f64()),
+                    None => "unknown".to_string(),
+                };
+                info!(
+                    self.logger,
+                    "Processed {}/{} items ({:.1} items/sec, ETA {})", done, total, rate, eta
+                );
+            }
+            None => {
+                info!(
+                    self.logger,
+                    "Processed {} items ({:.1} items/sec)", done, rate
+                );
+            }
+        }
+    }
+}
+
+fn rate(count: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 { count as f64 / secs } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use slog::Discard;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[clap(flatten)]
+        progress: ProgressArgs,
+    }
+
+    fn bulk_operation(args: &[&str], total: Option<u64>) -> BulkOperation {
+        let args = TestArgs::parse_from(std::iter::once("test").chain(args.iter().copied()));
+        let logger = Logger::root(Discard, o!());
+        args.progress.bulk_operation(&logger, "test", total)
+    }
+
+    #[test]
+    fn test_checkpoint_resume() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("checkpoint");
+        let args = [
+            "--checkpoint-file",
+            path.to_str().unwrap(),
+            "--checkpoint-every",
+            "10",
+        ];
+
+        let mut op = bulk_operation(&args, Some(100));
+        assert_eq!(op.resume_from()?, None);
+        op.record(5, "a")?;
+        assert!(!path.exists());
+        op.record(5, "b")?;
+        assert_eq!(fs::read_to_string(&path)?, "b\n10\n");
+
+        let mut op = bulk_operation(&args, Some(100));
+        assert_eq!(op.resume_from()?.as_deref(), Some("b"));
+        op.record(20, "c")?;
+        op.finish("d")?;
+        assert_eq!(fs::read_to_string(&path)?, "d\n30\n");
+
+        // Checkpoints written before the item count was recorded.
+        fs::write(&path, "e")?;
+        let mut op = bulk_operation(&args, Some(100));
+        assert_eq!(op.resume_from()?.as_deref(), Some("e"));
+        assert_eq!(op.resumed, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_eta_after_resume() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("checkpoint");
+        fs::write(&path, "b\n60\n")?;
+
+        let mut op = bulk_operation(&["--checkpoint-file", path.to_str().unwrap()], Some(100));
+        assert_eq!(op.eta(Duration::from_secs(10)), None);
+        op.resume_from()?;
+        op.record(20, "c")?;
+        // 20 items in 10s, with 20 of the 100 items left.
+        assert_eq!(
+            op.eta(Duration::from_secs(10)),
+            Some(Duration::from_secs(10))
+        );
+
+        let op = bulk_operation(&[], None);
+        assert_eq!(op.eta(Duration::from_secs(10)), None);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry() -> Result<()> {
+        let op = bulk_operation(&["--max-retries", "2"], None);
+        let mut attempts = 0;
+        let value = op
+            .retry(|| {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt < 3 {
+                        anyhow::bail!("attempt {} failed", attempt);
+                    }
+                    Ok(attempt)
+                }
+            })
+            .await?;
+        assert_eq!(value, 3);
+
+        let mut attempts = 0;
+        let err = op
+            .retry(|| {
+                attempts += 1;
+                let attempt = attempts;
+                async move { Err::<(), _>(anyhow::anyhow!("attempt {} failed", attempt)) }
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "attempt 3 failed");
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Graceful shutdown of services, in phases.
+//!
+//! When a shutdown signal is received, or the server exits, the
+//! [`ShutdownController`]:
+//!
+//! 1. Stops accepting: runs the stop-accepting callbacks, then keeps serving
+//!    for `--shutdown-stop-accepting-period` so that clients move elsewhere.
+//! 2. Drains: runs the drain callbacks concurrently, and waits for them until
+//!    `--shutdown-drain-timeout`.
+//! 3. Aborts: cancels the drain callbacks that did not finish, and gives up
+//!    waiting for them `--shutdown-abort-timeout` later.
+//!
+//! Services register their callbacks on a [`ShutdownHandle`].
+
+use std::future::Future;
+use std::mem;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use anyhow::Error;
+use anyhow::Result;
+use futures::future;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use slog::error;
+use slog::info;
+use slog::warn;
+use slog::Logger;
+use tokio::sync::watch;
+use tokio::time;
+use tokio::time::Instant;
+
+use crate::args::ShutdownArgs;
+use crate::signal::shutdown_signal;
+
+/// Phase of the shutdown. Phases are ordered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShutdownPhase {
+    Running,
+    StopAccepting,
+    Draining,
+    Aborting,
+}
+
+type StopAcceptingCallback = Box<dyn FnOnce() + Send>;
+type DrainCallback = Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>;
+
+#[derive(Default)]
+struct Callbacks {
+    stop_accepting: Vec<StopAcceptingCallback>,
+    drain: Vec<(String, DrainCallback)>,
+}
+
+/// Handle for services to take part in the shutdown.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    callbacks: Arc<Mutex<Callbacks>>,
+    phase: watch::Receiver<ShutdownPhase>,
+}
+
+impl ShutdownHandle {
+    /// Register a callback to run when the service should stop accepting
+    /// new requests. Callbacks registered after that are not run.
+    pub fn on_stop_accepting(&self, callback: impl FnOnce() + Send + 'static) {
+        self.callbacks
+            .lock()
+            .expect("Poisoned lock")
+            .stop_accepting
+            .push(Box::new(callback));
+    }
+
+    /// Register a callback that drains in-flight requests. Its future is
+    /// cancelled if it does not complete before the drain timeout. Callbacks
+    /// registered once draining started are not run.
+    pub fn on_drain<F, Fut>(&self, name: impl Into<String>, callback: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.callbacks
+            .lock()
+            .expect("Poisoned lock")
+            .drain
+            .push((name.into(), Box::new(move || callback().boxed())));
+    }
+
+    /// The current phase of the shutdown.
+    pub fn phase(&self) -> ShutdownPhase {
+        *self.phase.borrow()
+    }
+
+    /// Wait until the shutdown reaches `phase`.
+    pub async fn wait_for(&self, phase: ShutdownPhase) {
+        let mut receiver = self.phase.clone();
+        while *receiver.borrow() < phase {
+            if receiver.changed().await.is_err() {
+                // The controller was dropped, so the phase can't change.
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the phases of the shutdown. See the module documentation.
+pub struct ShutdownController {
+    logger: Logger,
+    stop_accepting_period: Duration,
+    drain_timeout: Duration,
+    abort_timeout: Duration,
+    callbacks: Arc<Mutex<Callbacks>>,
+    phase_sender: watch::Sender<ShutdownPhase>,
+    phase_receiver: watch::Receiver<ShutdownPhase>,
+}
+
+impl ShutdownController {
+    pub(crate) fn new(logger: Logger, args: &ShutdownArgs) -> Self {
+        let (phase_sender, phase_receiver) = watch::channel(ShutdownPhase::Running);
+        ShutdownController {
+            logger,
+            stop_accepting_period: args.shutdown_stop_accepting_period,
+            drain_timeout: args.shutdown_drain_timeout,
+            abort_timeout: args.shutdown_abort_timeout,
+            callbacks: Arc::new(Mutex::new(Callbacks::default())),
+            phase_sender,
+            phase_receiver,
+        }
+    }
+
+    /// A handle for services to register their callbacks on.
+    pub fn handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            callbacks: self.callbacks.clone(),
+            phase: self.phase_receiver.clone(),
+        }
+    }
+
+    /// Run `server` until it exits or a shutdown signal is received, then
+    /// shut down. The server is cancelled once draining is over.
+    pub async fn run<Server>(self, server: Server) -> Result<()>
+    where
+        Server: Future<Output = Result<()>> + Send + 'static,
+    {
+        let signalled = shutdown_signal()?;
+        let mut server = tokio::spawn(server);
+
+        let server_result = tokio::select! {
+            result = &mut server => {
+                let result = result.map_err(Error::from).and_then(|result| result);
+                match &result {
+                    Ok(()) => error!(self.logger, "Server has exited! Starting shutdown..."),
+                    Err(e) => error!(
+                        self.logger,
+                        "Server exited with an error! Starting shutdown... Error: {:?}", e
+                    ),
+                }
+                Some(result)
+            }
+            _ = signalled => {
+                info!(self.logger, "Signalled! Starting shutdown...");
+                None
+            }
+        };
+
+        let shutdown_result = self.shutdown().await;
+        let server_result = match server_result {
+            Some(result) => result,
+            None => {
+                server.abort();
+                match server.await {
+                    Ok(result) => result,
+                    Err(e) if e.is_cancelled() => Ok(()),
+                    Err(e) => Err(e.into()),
+                }
+            }
+        };
+        shutdown_result?;
+        server_result
+    }
+
+    /// Run the shutdown phases. Fails if draining timed out, or if the drain
+    /// callbacks did not stop once cancelled.
+    pub async fn shutdown(self) -> Result<()> {
+        self.enter(ShutdownPhase::StopAccepting);
+        let stop_accepting = mem::take(&mut self.lock_callbacks().stop_accepting);
+        for callback in stop_accepting {
+            callback();
+        }
+        info!(
+            self.logger,
+            "Waiting {}s before draining",
+            self.stop_accepting_period.as_secs()
+        );
+        time::sleep(self.stop_accepting_period).await;
+
+        self.enter(ShutdownPhase::Draining);
+        let drain = mem::take(&mut self.lock_callbacks().drain);
+        info!(self.logger, "Draining...");
+        let tasks = drain
+            .into_iter()
+            .map(|(name, callback)| (name, tokio::spawn(callback())))
+            .collect::<Vec<_>>();
+        let deadline = Instant::now() + self.drain_timeout;
+        let mut unfinished = Vec::new();
+        for (name, mut task) in tasks {
+            match time::timeout_at(deadline, &mut task).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!(self.logger, "Failed to drain {}: {}", name, e),
+                Err(_) => unfinished.push((name, task)),
+            }
+        }
+        if unfinished.is_empty() {
+            info!(self.logger, "Shutdown complete");
+            return Ok(());
+        }
+
+        self.enter(ShutdownPhase::Aborting);
+        let names = unfinished
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(
+            self.logger,
+            "Did not drain {} within {}s, cancelling",
+            names,
+            self.drain_timeout.as_secs()
+        );
+        let cancelled = future::join_all(unfinished.into_iter().map(|(_, task)| {
+            task.abort();
+            task
+        }));
+        if time::timeout(self.abort_timeout, cancelled).await.is_err() {
+            return Err(anyhow!(
+                "Timed out draining {}, which did not stop within {}s of cancelling",
+                names,
+                self.abort_timeout.as_secs()
+            ));
+        }
+        Err(anyhow!("Timed out draining {}", names))
+    }
+
+    fn enter(&self, phase: ShutdownPhase) {
+        // This never fails, as the controller holds a receiver.
+        let _ = self.phase_sender.send(phase);
+    }
+
+    fn lock_callbacks(&self) -> std::sync::MutexGuard<'_, Callbacks> {
+        self.callbacks.lock().expect("Poisoned lock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+
+    use slog::o;
+
+    use super::*;
+
+    fn controller(drain_timeout: u64) -> ShutdownController {
+        let args = ShutdownArgs {
+            shutdown_stop_accepting_period: Duration::from_secs(1),
+            shutdown_drain_timeout: Duration::from_secs(drain_timeout),
+            shutdown_abort_timeout: Duration::from_secs(1),
+        };
+        ShutdownController::new(Logger::root(slog::Discard, o!()), &args)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown() {
+        let controller = controller(10);
+        let handle = controller.handle();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let drained = Arc::new(AtomicBool::new(false));
+        handle.on_stop_accepting({
+            let stopped = stopped.clone();
+            move || stopped.store(true, Ordering::Release)
+        });
+        handle.on_drain("requests", {
+            let (handle, stopped, drained) = (handle.clone(), stopped.clone(), drained.clone());
+            move || async move {
+                // Draining starts once the service stopped accepting.
+                assert!(stopped.load(Ordering::Acquire));
+                assert_eq!(handle.phase(), ShutdownPhase::Draining);
+                time::sleep(Duration::from_secs(5)).await;
+                drained.store(true, Ordering::Release);
+            }
+        });
+        assert_eq!(handle.phase(), ShutdownPhase::Running);
+
+        controller.shutdown().await.unwrap();
+        assert!(drained.load(Ordering::Acquire));
+        assert_eq!(handle.phase(), ShutdownPhase::Draining);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_drain_timeout() {
+        let controller = controller(10);
+        let handle = controller.handle();
+        handle.on_drain("fast", || async {});
+        handle.on_drain("slow", future::pending);
+
+        let waiter = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.wait_for(ShutdownPhase::Aborting).await }
+        });
+        assert_eq!(
+            controller.shutdown().await.unwrap_err().to_string(),
+            "Timed out draining slow"
+        );
+        assert_eq!(handle.phase(), ShutdownPhase::Aborting);
+        waiter.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_server_exits() {
+        let controller = controller(10);
+        let handle = controller.handle();
+        let drained = Arc::new(AtomicBool::new(false));
+        handle.on_drain("requests", {
+            let drained = drained.clone();
+            move || async move { drained.store(true, Ordering::Release) }
+        });
+
+        let err = controller
+            .run(async { Err(anyhow!("Server failed")) })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Server failed");
+        // The shutdown still ran.
+        assert!(drained.load(Ordering::Acquire));
+    }
+}
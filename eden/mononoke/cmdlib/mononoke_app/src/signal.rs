@@ -0,0 +1,40 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::future::Future;
+
+use anyhow::Result;
+
+/// Returns a future which completes when the process receives a shutdown
+/// signal: SIGTERM or SIGINT on Unix, Ctrl-C elsewhere.
+///
+/// The signal handlers are installed before returning, so signals received
+/// before the future is polled are not missed.
+#[cfg(unix)]
+pub(crate) fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    use tokio::signal::unix::signal;
+    use tokio::signal::unix::SignalKind;
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    Ok(async move {
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = interrupt.recv() => {}
+        }
+    })
+}
+
+#[cfg(not(unix))]
+pub(crate) fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    Ok(async {
+        // If listening fails, shutdown can't be requested by signal.
+        if tokio::signal::ctrl_c().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    })
+}
@@ -48,6 +48,7 @@ pub const MYSQL_POOL_AGE_TIMEOUT: &str = "mysql-pool-age-timeout";
 pub const MYSQL_POOL_IDLE_TIMEOUT: &str = "mysql-pool-idle-timeout";
 pub const MYSQL_CONN_OPEN_TIMEOUT: &str = "mysql-conn-open-timeout";
 pub const MYSQL_MAX_QUERY_TIME: &str = "mysql-query-time-limit";
+pub const MYSQL_MAX_REPLICA_LAG: &str = "mysql-max-replica-lag";
 pub const MYSQL_SQLBLOB_POOL_LIMIT: &str = "mysql-sqblob-pool-limit";
 pub const MYSQL_SQLBLOB_POOL_PER_KEY_LIMIT: &str = "mysql-sqblob-pool-per-key-limit";
 pub const MYSQL_SQLBLOB_POOL_THREADS_NUM: &str = "mysql-sqblob-pool-threads-num";
@@ -1088,6 +1089,13 @@ fn add_mysql_options_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
             .takes_value(true)
             .default_value("10000"),
     )
+    .arg(
+        Arg::with_name(MYSQL_MAX_REPLICA_LAG)
+            .long(MYSQL_MAX_REPLICA_LAG)
+            .help("Maximum replica lag in millisecs to wait for before writing more")
+            .takes_value(true)
+            .conflicts_with(MYSQL_MASTER_ONLY),
+    )
 }
 
 pub fn bool_as_str(v: bool) -> &'static str {
@@ -103,6 +103,7 @@ use super::app::LOG_INCLUDE_TAG;
 use super::app::MYSQL_CONN_OPEN_TIMEOUT;
 use super::app::MYSQL_MASTER_ONLY;
 use super::app::MYSQL_MAX_QUERY_TIME;
+use super::app::MYSQL_MAX_REPLICA_LAG;
 use super::app::MYSQL_POOL_AGE_TIMEOUT;
 use super::app::MYSQL_POOL_IDLE_TIMEOUT;
 use super::app::MYSQL_POOL_LIMIT;
@@ -588,6 +589,18 @@ fn parse_mysql_pool_options(matches: &ArgMatches<'_>) -> Result<PoolConfig, Erro
     ))
 }
 
+fn parse_mysql_max_replica_lag(matches: &ArgMatches<'_>) -> Result<Option<Duration>, Error> {
+    let max_replica_lag: Option<u64> = matches
+        .value_of(MYSQL_MAX_REPLICA_LAG)
+        .map(|v| v.parse())
+        .transpose()
+        .context("Provided mysql-max-replica-lag is not u64")?;
+    if max_replica_lag == Some(0) {
+        bail!("Provided mysql-max-replica-lag must be positive");
+    }
+    Ok(max_replica_lag.map(Duration::from_millis))
+}
+
 fn parse_mysql_options(
     matches: &ArgMatches<'_>,
     app_data: &MononokeAppData,
@@ -600,11 +613,13 @@ fn parse_mysql_options(
     } else {
         ReadConnectionType::ReplicaOnly
     };
+    let max_replica_lag = parse_mysql_max_replica_lag(matches)?;
 
     Ok(MysqlOptions {
         pool,
         pool_config,
         read_connection_type,
+        max_replica_lag,
     })
 }
 
@@ -669,11 +684,13 @@ fn parse_sqlblob_mysql_options(
     } else {
         ReadConnectionType::ReplicaOnly
     };
+    let max_replica_lag = parse_mysql_max_replica_lag(matches)?;
 
     Ok(MysqlOptions {
         pool,
         pool_config,
         read_connection_type,
+        max_replica_lag,
     })
 }
 
@@ -205,7 +205,18 @@ async fn maybe_schedule_healer_for_storage(
         drain_only,
     );
 
-    schedule_healing(ctx, multiplex_healer, lag_monitor, iter_limit, heal_min_age).await
+    let wait_config = mysql_options
+        .wait_for_replication_config()
+        .with_logger(ctx.logger());
+    schedule_healing(
+        ctx,
+        multiplex_healer,
+        lag_monitor,
+        wait_config,
+        iter_limit,
+        heal_min_age,
+    )
+    .await
 }
 
 // Pass None as iter_limit for never ending run
@@ -213,11 +224,11 @@ async fn schedule_healing(
     ctx: &CoreContext,
     multiplex_healer: Healer,
     lag_monitor: Box<dyn ReplicaLagMonitor>,
+    wait_config: WaitForReplicationConfig<'_>,
     iter_limit: Option<u64>,
     heal_min_age: ChronoDuration,
 ) -> Result<(), Error> {
     let mut count = 0;
-    let wait_config = WaitForReplicationConfig::default().with_logger(ctx.logger());
     let healing_start_time = Instant::now();
     let mut total_deleted_rows = 0;
 
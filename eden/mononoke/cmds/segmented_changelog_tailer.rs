@@ -73,13 +73,13 @@ async fn async_main(app: MononokeApp) -> Result<(), Error> {
     let args: SegmentedChangelogTailerArgs = app.args()?;
 
     let repos = MultiRepoArgs {
-        repo_id: args.repos.repo_id,
         repo_name: args
             .repos
             .repo_name
             .into_iter()
             .chain(args.repo_names.into_iter())
             .collect(),
+        ..args.repos
     };
 
     // This is a bit weird from the dependency point of view but I think that it is best. The
@@ -61,7 +61,6 @@ use slog::warn;
 use sql_ext::facebook::MyAdmin;
 use sql_ext::replication::NoReplicaLagMonitor;
 use sql_ext::replication::ReplicaLagMonitor;
-use sql_ext::replication::WaitForReplicationConfig;
 use synced_commit_mapping::EquivalentWorkingCopyEntry;
 use synced_commit_mapping::SqlSyncedCommitMapping;
 use synced_commit_mapping::SyncedCommitMapping;
@@ -1102,7 +1101,10 @@ async fn process_stream_and_wait_for_replication<'a>(
         }
     };
 
-    let wait_config = WaitForReplicationConfig::default().with_logger(ctx.logger());
+    let wait_config = matches
+        .mysql_options()
+        .wait_for_replication_config()
+        .with_logger(ctx.logger());
     let replica_lag_monitor: Arc<dyn ReplicaLagMonitor> = match db_address {
         None => Arc::new(NoReplicaLagMonitor()),
         Some(address) => {
@@ -29,6 +29,7 @@ pub mod facebook {
 
     use std::fmt;
     use std::fmt::Debug;
+    use std::time::Duration;
 
     #[cfg(fbcode_build)]
     pub use r#impl::create_mysql_connections_sharded;
@@ -58,6 +59,8 @@ pub mod facebook {
     #[cfg(not(fbcode_build))]
     pub use crate::oss::SharedConnectionPool;
 
+    use crate::replication::WaitForReplicationConfig;
+
     /// MySQL global shared connection pool configuration.
     #[derive(Clone)]
     pub struct MysqlOptions {
@@ -65,6 +68,8 @@ pub mod facebook {
         // pool config is used only once when the shared connection pool is being created
         pub pool_config: PoolConfig,
         pub read_connection_type: ReadConnectionType,
+        // maximum replication lag that callers waiting for replication accept
+        pub max_replica_lag: Option<Duration>,
     }
 
     impl MysqlOptions {
@@ -78,14 +83,24 @@ pub mod facebook {
                 Some(self.pool_config.per_key_limit as usize)
             }
         }
+
+        /// Config for waiting for replication, using the maximum replica lag
+        /// of these options if set.
+        pub fn wait_for_replication_config<'a>(&self) -> WaitForReplicationConfig<'a> {
+            let config = WaitForReplicationConfig::default();
+            match self.max_replica_lag {
+                Some(max_replica_lag) => config.with_max_replication_lag(max_replica_lag),
+                None => config,
+            }
+        }
     }
 
     impl Debug for MysqlOptions {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
             write!(
                 f,
-                "MySQL pool with config {:?}, connection type: {:?}",
-                self.pool_config, self.read_connection_type
+                "MySQL pool with config {:?}, connection type: {:?}, max replica lag: {:?}",
+                self.pool_config, self.read_connection_type, self.max_replica_lag
             )
         }
     }
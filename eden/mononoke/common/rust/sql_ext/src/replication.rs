@@ -127,6 +127,11 @@ impl<'a> WaitForReplicationConfig<'a> {
         self.logger = Some(logger);
         self
     }
+
+    pub fn with_max_replication_lag(mut self, max_replication_lag_allowed: Duration) -> Self {
+        self.max_replication_lag_allowed = max_replication_lag_allowed;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -238,7 +238,7 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
 
     let tls_session_data_log = args.tls_session_data_log_file.clone();
 
-    let scuba_logger = app.environment().scuba_sample_builder.clone();
+    let scuba_logger = app.scuba();
 
     let will_exit = Arc::new(AtomicBool::new(false));
 
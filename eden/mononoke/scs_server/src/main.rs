@@ -210,7 +210,7 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
     let exec = runtime.clone();
     let env = app.environment();
 
-    let scuba_builder = env.scuba_sample_builder.clone();
+    let scuba_builder = app.scuba();
     let repo_factory = app.repo_factory();
 
     let mononoke = Arc::new(runtime.block_on(Mononoke::new(Arc::clone(&app)))?);
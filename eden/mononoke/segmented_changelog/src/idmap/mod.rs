@@ -12,6 +12,7 @@ mod sql;
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::bail;
 use anyhow::format_err;
@@ -300,6 +301,7 @@ pub struct IdMapFactory {
     replica_lag_monitor: Arc<dyn ReplicaLagMonitor>,
     repo_id: RepositoryId,
     cache_handlers: Option<CacheHandlers>,
+    max_replica_lag: Option<Duration>,
 }
 
 impl IdMapFactory {
@@ -313,6 +315,7 @@ impl IdMapFactory {
             replica_lag_monitor,
             repo_id,
             cache_handlers: None,
+            max_replica_lag: None,
         }
     }
 
@@ -323,7 +326,8 @@ impl IdMapFactory {
             self.replica_lag_monitor.clone(),
             self.repo_id,
             version,
-        );
+        )
+        .with_max_replica_lag(self.max_replica_lag);
         slog::debug!(
             ctx.logger(),
             "segmented changelog idmap instantiated - version: {}",
@@ -357,6 +361,11 @@ impl IdMapFactory {
         self.cache_handlers = Some(cache_handlers);
         self
     }
+
+    pub fn with_max_replica_lag(mut self, max_replica_lag: Option<Duration>) -> Self {
+        self.max_replica_lag = max_replica_lag;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -7,6 +7,7 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::format_err;
 use anyhow::Error;
@@ -43,6 +44,7 @@ pub struct SqlIdMap {
     replica_lag_monitor: Arc<dyn ReplicaLagMonitor>,
     repo_id: RepositoryId,
     version: IdMapVersion,
+    max_replica_lag: Option<Duration>,
 }
 
 queries! {
@@ -182,9 +184,17 @@ impl SqlIdMap {
             replica_lag_monitor,
             repo_id,
             version,
+            max_replica_lag: None,
         }
     }
 
+    /// Replication lag to wait for between insert batches, instead of the
+    /// default.
+    pub fn with_max_replica_lag(mut self, max_replica_lag: Option<Duration>) -> Self {
+        self.max_replica_lag = max_replica_lag;
+        self
+    }
+
     async fn select_many_changesetids(
         &self,
         conn: &Connection,
@@ -303,7 +313,10 @@ impl IdMap for SqlIdMap {
         // transactions.
         for (i, chunk) in mappings.chunks(INSERT_MAX).enumerate() {
             if i > 0 {
-                let wait_config = WaitForReplicationConfig::default().with_logger(ctx.logger());
+                let mut wait_config = WaitForReplicationConfig::default().with_logger(ctx.logger());
+                if let Some(max_replica_lag) = self.max_replica_lag {
+                    wait_config = wait_config.with_max_replication_lag(max_replica_lag);
+                }
                 self.replica_lag_monitor
                     .wait_for_replication(&wait_config)
                     .await?;
@@ -195,7 +195,7 @@ impl SegmentedChangelogTailer {
 
         let bonsai_hg_mapping = blobrepo.bonsai_hg_mapping_arc();
 
-        Ok(SegmentedChangelogTailer::new(
+        let mut tailer = SegmentedChangelogTailer::new(
             repo_id,
             segmented_changelog_sql_connections,
             replica_lag_monitor,
@@ -206,7 +206,11 @@ impl SegmentedChangelogTailer {
             Arc::clone(blobrepo.bookmarks()) as Arc<dyn Bookmarks>,
             seed_heads,
             caching,
-        ))
+        );
+        tailer.idmap_factory = tailer
+            .idmap_factory
+            .with_max_replica_lag(mysql_options.max_replica_lag);
+        Ok(tailer)
     }
 
     pub async fn run(&self, ctx: &CoreContext, mode: OperationMode) {
@@ -138,7 +138,7 @@ fn main(fb: FacebookInit) -> Result<()> {
 
     let env = app.environment();
 
-    let scuba = env.scuba_sample_builder.clone();
+    let scuba = app.scuba();
 
     let will_exit = Arc::new(AtomicBool::new(false));
 
@@ -0,0 +1,119 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Reload tunables from a local file when it changes.
+//!
+//! The file's modification time and size are checked every second, and the
+//! file is reloaded when they change.  As a fallback for changes that keep
+//! both, the file is also reloaded at a jittered poll interval.  Components
+//! can be notified of changes with `subscribe_tunables`.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use anyhow::Result;
+use cached_config::ConfigHandle;
+use rand::Rng;
+use slog::debug;
+use slog::warn;
+use slog::Logger;
+use tunables_structs::Tunables as TunablesStruct;
+
+use crate::init_tunables_worker;
+use crate::update_tunables;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fraction of the poll interval that polls are randomly spread over.
+const POLL_JITTER: f64 = 0.25;
+
+/// Initialize the tunables from the JSON file at `path`, and reload them
+/// when the file changes, or at least every `poll_interval`.
+pub fn init_tunables_file_worker(
+    logger: Logger,
+    path: PathBuf,
+    poll_interval: Duration,
+) -> Result<()> {
+    let config_handle = load_tunables(&path)?;
+    let current = config_handle.get();
+    init_tunables_worker(logger.clone(), config_handle)?;
+
+    thread::Builder::new()
+        .name("mononoke-tunables-file".into())
+        .spawn(move || file_worker(logger, path, current, poll_interval))
+        .expect("Can't spawn tunables file watcher");
+
+    Ok(())
+}
+
+/// Load tunables from the JSON file at `path`.
+fn load_tunables(path: &Path) -> Result<ConfigHandle<TunablesStruct>> {
+    let value = fs::read_to_string(path)
+        .with_context(|| format!("failed to open tunables path {}", path.display()))?;
+    ConfigHandle::from_json(&value)
+        .with_context(|| format!("failed to parse tunables at path {}", path.display()))
+}
+
+/// Update the tunables when the file at `path` changes.  `current` are the
+/// tunables that were loaded from it.
+fn file_worker(
+    logger: Logger,
+    path: PathBuf,
+    mut current: Arc<TunablesStruct>,
+    poll_interval: Duration,
+) {
+    let mut stamp = file_stamp(&path);
+    let mut next_poll = Instant::now() + jittered(poll_interval);
+    loop {
+        thread::sleep(CHECK_INTERVAL);
+
+        let new_stamp = file_stamp(&path);
+        let now = Instant::now();
+        if new_stamp == stamp && now < next_poll {
+            continue;
+        }
+        stamp = new_stamp;
+        if now >= next_poll {
+            next_poll = now + jittered(poll_interval);
+        }
+
+        let new_tunables = match load_tunables(&path) {
+            Ok(config_handle) => config_handle.get(),
+            Err(e) => {
+                // Keep the current tunables until the file is fixed.
+                warn!(logger, "Failed to reload tunables: {:#}", e);
+                continue;
+            }
+        };
+        if new_tunables == current {
+            continue;
+        }
+        debug!(logger, "Reloading tunables from {}", path.display());
+        match update_tunables(new_tunables.clone()) {
+            Ok(()) => current = new_tunables,
+            Err(e) => warn!(logger, "Failed to refresh tunables: {}", e),
+        }
+    }
+}
+
+/// Modification time and size of the file, or `None` if it can't be read.
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn jittered(interval: Duration) -> Duration {
+    let factor = rand::thread_rng().gen_range(1.0 - POLL_JITTER..=1.0 + POLL_JITTER);
+    interval.mul_f64(factor)
+}
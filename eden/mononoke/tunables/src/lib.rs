@@ -19,6 +19,7 @@ use std::time::Duration;
 use anyhow::Result;
 use arc_swap::ArcSwap;
 use cached_config::ConfigHandle;
+use futures::channel::mpsc;
 use futures::future::poll_fn;
 use futures::Future;
 use futures::FutureExt;
@@ -29,8 +30,14 @@ use slog::Logger;
 use tunables_derive::Tunables;
 use tunables_structs::Tunables as TunablesStruct;
 
+mod file_watcher;
+
+pub use file_watcher::init_tunables_file_worker;
+
 static TUNABLES: OnceCell<MononokeTunables> = OnceCell::new();
 static TUNABLES_WORKER_STATE: OnceCell<Mutex<TunablesWorkerState>> = OnceCell::new();
+static TUNABLES_SUBSCRIBERS: OnceCell<Mutex<Vec<mpsc::UnboundedSender<Arc<TunablesStruct>>>>> =
+    OnceCell::new();
 const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
 
 thread_local! {
@@ -310,6 +317,16 @@ pub struct MononokeTunables {
 
     // If set the `draft` ACL action will be used for `draft` access.
     enforce_draft_acl: AtomicBool,
+
+    // Unix timestamps after which renamed command line flags can no longer
+    // be used with their old names.
+    //
+    // This reuses the by-repo tunables, but is keyed by the old flag name,
+    // without the leading `--`, instead of a repo name.  In `ints_by_repo`,
+    // the sunset of `--old-flag` is set with:
+    //   "old-flag": { "deprecated_args_sunset_timestamps": 1700000000 }
+    // and read with `get_by_repo_deprecated_args_sunset_timestamps("old-flag")`.
+    deprecated_args_sunset_timestamps: TunableI64ByRepo,
 }
 
 fn log_tunables(tunables: &TunablesStruct) -> String {
@@ -402,7 +419,32 @@ fn worker_iteration() {
     }
 }
 
-fn update_tunables(new_tunables: Arc<TunablesStruct>) -> Result<()> {
+/// Subscribe to tunables changes. The new tunables are sent each time they
+/// are updated, until the receiver is dropped.
+pub fn subscribe_tunables() -> mpsc::UnboundedReceiver<Arc<TunablesStruct>> {
+    let (sender, receiver) = mpsc::unbounded();
+    TUNABLES_SUBSCRIBERS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("Poisoned lock")
+        .push(sender);
+    receiver
+}
+
+fn notify_subscribers(new_tunables: &Arc<TunablesStruct>) {
+    if let Some(subscribers) = TUNABLES_SUBSCRIBERS.get() {
+        subscribers
+            .lock()
+            .expect("Poisoned lock")
+            .retain(|sender| sender.unbounded_send(new_tunables.clone()).is_ok());
+    }
+}
+
+/// Replace the tunables with `new_tunables` and notify subscribers.
+///
+/// Tunables from the config handle of the worker are updated by the worker.
+/// This is for tunables from other sources, like a watched local file.
+pub(crate) fn update_tunables(new_tunables: Arc<TunablesStruct>) -> Result<()> {
     let tunables = tunables();
     tunables.update_bools(&new_tunables.killswitches);
     tunables.update_ints(&new_tunables.ints);
@@ -420,6 +462,8 @@ fn update_tunables(new_tunables: Arc<TunablesStruct>) -> Result<()> {
     if let Some(vec_of_strings_by_repo) = &new_tunables.vec_of_strings_by_repo {
         tunables.update_by_repo_vec_of_strings(vec_of_strings_by_repo);
     }
+
+    notify_subscribers(&new_tunables);
     Ok(())
 }
 
@@ -822,6 +866,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_subscribe_tunables() {
+        // Update overridden tunables, so that other tests aren't affected.
+        with_tunables(MononokeTunables::default(), || {
+            let mut receiver = subscribe_tunables();
+            let new_tunables = Arc::new(TunablesStruct {
+                ints: hashmap! { s("wishlist_write_qps") => 2 },
+                ..Default::default()
+            });
+            update_tunables(new_tunables.clone()).unwrap();
+            assert_eq!(receiver.try_next().unwrap(), Some(new_tunables));
+            assert_eq!(tunables().get_wishlist_write_qps(), 2);
+
+            // Dropped receivers are unsubscribed.
+            drop(receiver);
+            update_tunables(Arc::new(TunablesStruct::default())).unwrap();
+            let subscribers = TUNABLES_SUBSCRIBERS.get().unwrap().lock().unwrap();
+            assert!(subscribers.iter().all(|sender| !sender.is_closed()));
+        });
+        assert_eq!(tunables().get_wishlist_write_qps(), 0);
+    }
+
     #[fbinit::test]
     async fn test_with_tunables_async(_fb: fbinit::FacebookInit) {
         let res = with_tunables_async(
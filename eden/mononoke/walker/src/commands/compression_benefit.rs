@@ -71,7 +71,7 @@ impl RepoShardedProcess for WalkerSizingProcess {
         info!(&logger, "Setting up walker sizing for repo {}", repo_name);
         let repos = MultiRepoArgs {
             repo_name: vec![repo_name.to_string()],
-            repo_id: vec![],
+            ..Default::default()
         };
         let (job_params, command) = setup_sizing(&repos, &self.app, &self.args)
             .await
@@ -70,7 +70,7 @@ impl RepoShardedProcess for WalkerCorpusProcess {
         info!(&logger, "Setting up walker corpus for repo {}", repo_name);
         let repos = MultiRepoArgs {
             repo_name: vec![repo_name.to_string()],
-            repo_id: vec![],
+            ..Default::default()
         };
         let (job_params, command) = setup_corpus(&repos, &self.app, &self.args)
             .await
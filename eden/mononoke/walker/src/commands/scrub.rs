@@ -81,7 +81,7 @@ impl RepoShardedProcess for WalkerScrubProcess {
         info!(&logger, "Setting up walker scrub for repo {}", repo_name);
         let repos = MultiRepoArgs {
             repo_name: vec![repo_name.to_string()],
-            repo_id: vec![],
+            ..Default::default()
         };
         let (job_params, command) = setup_scrub(&repos, &self.app, &self.args)
             .await
@@ -64,7 +64,7 @@ impl RepoShardedProcess for WalkerValidateProcess {
         info!(&logger, "Setting up walker validate for repo {}", repo_name);
         let repos = MultiRepoArgs {
             repo_name: vec![repo_name.to_string()],
-            repo_id: vec![],
+            ..Default::default()
         };
         let (job_params, command) = setup_validate(&repos, &self.app, &self.args)
             .await
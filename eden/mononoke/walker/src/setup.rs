@@ -101,7 +101,7 @@ pub async fn setup_common<'a>(
 
     // There is no need to check if repos is empty: at least one repo arg
     // is required when running the command.
-    let repos = app.multi_repo_configs(repo_args.ids_or_names()?)?;
+    let repos = repo_args.resolve_repos(app.repo_configs())?;
     let repo_count = repos.len();
     if repo_count > 1 {
         info!(
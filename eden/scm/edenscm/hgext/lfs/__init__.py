@@ -16,9 +16,20 @@ Configs::
     # - file:///tmp/path
     #   local filesystem, usually for testing
     # if unset, lfs will prompt setting this when it must use this value.
+    # A comma-separated list of http(s) endpoints can be given. They are
+    # tried in order, endpoints that recently failed are tried last.
     # (default: unset)
     url = https://example.com/lfs
 
+    # How many blobs to download concurrently. Either a single value, or a
+    # comma-separated list with a value for each endpoint of lfs.url.
+    # (default: 4)
+    concurrentfetches = 4
+
+    # How long to avoid an endpoint after a failed request, in milliseconds.
+    # (default: 60000)
+    unhealthy-endpoint-timeout = 60000
+
     # Size of a file to make it use LFS
     threshold = 10M
 
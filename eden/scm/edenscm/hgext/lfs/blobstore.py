@@ -415,7 +415,9 @@ _storemap = {
 
 def remote(ui):
     """remotestore factory. return a store in _storemap depending on config"""
-    url = util.url(ui.config("lfs", "url"))
+    # Fallback endpoints are only supported by the Rust store.
+    urls = ui.configlist("lfs", "url")
+    url = util.url(urls[0] if urls else "")
     scheme = url.scheme
     if scheme not in _storemap:
         raise error.Abort(_("lfs: unknown url scheme: %s") % scheme)
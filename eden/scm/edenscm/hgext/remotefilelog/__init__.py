@@ -97,6 +97,10 @@ Configs:
     ``remotefilelog.manifestlimit`` limit the size of the manifest cache to this size.
     Manifests will be removed from oldest to newest during repack.
 
+    ``remotefilelog.previouscachepath`` the previous location of the hgcache,
+    while it is being moved to ``remotefilelog.cachepath``. Files and trees are
+    still read from the previous location, but are only written to the new one.
+
     ``remotefilelog.getpackversion`` version of the "getpack" wire protocol.
     Starting with 2, LFS blobs are supported.
 
@@ -1024,6 +1028,7 @@ def verifyremotefilelog(ui, path, **opts):
         ("", "long", None, _("print the long hashes")),
         ("", "node", "", _("dump the contents of node"), "NODE"),
         ("", "node-delta", "", _("dump the delta chain info of node"), "NODE"),
+        ("", "extstored", None, _("include LFS pointers stored in the pack")),
     ],
     _("hg debugdatapack <paths>"),
     norepo=True,
@@ -350,11 +350,12 @@ def debugdatastore(ui, store, verifynoduplicate=True, **opts):
 
 
 def debugdatapack(ui, *paths, **opts):
+    extstored = bool(opts.pop("extstored", False))
     for path in paths:
         if ".data" in path:
             path = path[: path.index(".data")]
         ui.write("%s:\n" % path)
-        dpack = revisionstore.datapack(path)
+        dpack = revisionstore.datapack(path, extstored=extstored)
         debugdatastore(ui, dpack, **opts)
 
 
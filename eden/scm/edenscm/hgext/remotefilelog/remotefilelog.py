@@ -157,6 +157,16 @@ class remotefilelog(object):
 
     def size(self, node):
         """return the size of a given revision"""
+        if self.repo.ui.configbool("scmstore", "status"):
+            # Status compares sizes before contents. Use the content that is
+            # already local, without going to the network like the
+            # contentstore would.
+            results = self.repo.fileslog.filescmstore.fetch(
+                [(self.filename, node)], fetch_mode="local-only", cause="status-lookup"
+            )
+            for _key, content in results.complete():
+                return len(content)
+
         try:
             meta = self.repo.fileslog.contentstore.metadata(self.filename, node)
             return meta["size"]
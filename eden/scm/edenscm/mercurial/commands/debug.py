@@ -654,6 +654,23 @@ def debugcolor(ui, **opts):
         return _debugdisplaycolor(ui)
 
 
+@command(
+    "debugcommitcomplete",
+    [("", "files", False, _("also require all the trees and files"))],
+    _("REV"),
+)
+def debugcommitcomplete(ui, repo, rev, **opts):
+    """test if a commit can be used without fetching anything
+
+    Print whether the commit and its root tree are present locally. With
+    --files, all the trees and files of the commit must be present too.
+    """
+    node = scmutil.revsingle(repo, rev).node()
+    complete = repo.hascommitcomplete(node, checkfiles=opts.get("files"))
+    ui.write(_x("%s\n") % (complete and "complete" or "incomplete"))
+    return 0 if complete else 1
+
+
 @command("debugcompactmetalog", [], "")
 def debugcompactmetalog(ui, repo):
     """compact the metalog by dropping history"""
@@ -114,6 +114,7 @@ def doctor(ui, **opts) -> typing.Optional[int]:
                 localpath,
                 suffix,
                 ui._uiconfig._rcfg,
+                verify=ui.configbool("doctor", "verify-revisionstore"),
             )
 
     ui.write(_("checking commit references\n"))
@@ -575,6 +576,7 @@ def runglobalindexedlogdoctor(ui):
                 None,
                 suffix,
                 ui._uiconfig._rcfg,
+                verify=ui.configbool("doctor", "verify-revisionstore"),
             )
 
 
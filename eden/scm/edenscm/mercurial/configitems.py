@@ -147,6 +147,7 @@ coreconfigitem("bundle2", "rechunkthreshold", default="1MB")
 # bundle.reorder: experimental config
 coreconfigitem("bundle", "reorder", default="auto")
 coreconfigitem("censor", "policy", default="abort")
+coreconfigitem("checkout", "conflict-policy", default="abort")
 coreconfigitem("chgserver", "idletimeout", default=3600)
 coreconfigitem("chgserver", "skiphash", default=False)
 coreconfigitem("clone", "prefer-edenapi-clonedata", default=True)
@@ -236,6 +237,7 @@ coreconfigitem("diff", "noprefix", default=False)
 coreconfigitem("doctor", "check-lag-name", "master")
 coreconfigitem("doctor", "check-lag-threshold", 50)
 coreconfigitem("doctor", "check-too-many-names-threshold", 20)
+coreconfigitem("doctor", "verify-revisionstore", False)
 coreconfigitem("edenfs", "tree-fetch-depth", default=3)
 coreconfigitem("email", "bcc", default=None)
 coreconfigitem("email", "cc", default=None)
@@ -601,9 +603,13 @@ coreconfigitem("worker", "enabled", default=True)
 coreconfigitem("worker", "numcpus", default=None)
 
 coreconfigitem("workingcopy", "enablerustwalker", default=False)
-coreconfigitem("workingcopy", "rustwalkerthreads", default=0)
+coreconfigitem("workingcopy", "rustwalkerthreads", default=8)
 coreconfigitem("workingcopy", "rustpendingchanges", default=False)
 coreconfigitem("workingcopy", "ruststatus", default=util.istest())
+coreconfigitem("workingcopy", "lookupcomparison", default="content")
+coreconfigitem("workingcopy", "mtimeracewindow", default=0)
+coreconfigitem("workingcopy", "opaquenestedrepos", default=False)
+coreconfigitem("workingcopy", "edenprefetchunloaded", default=False)
 
 # Rebase related configuration moved to core because other extension are doing
 # strange things. For example, shelve import the extensions to reuse some bit
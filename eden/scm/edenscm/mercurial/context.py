@@ -881,7 +881,7 @@ class basefilectx(object):
             self._repo.ui, "remotefilelog"
         ) and self._repo.ui.configbool("scmstore", "status"):
             return self._repo.fileslog.filescmstore.fetch_contentsha256(
-                [(self.path(), self.filenode())]
+                [(self.path(), self.filenode())], cause="status-lookup"
             )[0][1]
         return hashlib.sha256(self.data()).digest()
 
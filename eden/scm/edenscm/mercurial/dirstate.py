@@ -950,8 +950,8 @@ class dirstate(object):
         else:
             filesystem = "normal"
 
-        # TODO: Fix deadlock in normal filesystem crawler
-        if ignored or clean or filesystem == "normal":
+        # Only the normal filesystem evaluates ignore rules in Rust.
+        if clean or (ignored and filesystem != "normal"):
             raise self.FallbackToPythonStatus
 
         if filesystem == "eden":
@@ -972,12 +972,48 @@ class dirstate(object):
             #  treedirstatemap, treestatemap]` has no attribute `_tree`.
             tree = self._map._tree
 
-        # TODO: Handle the case that a file is ignored but is still tracked
-        # in p1.
-        match = matchmod.differencematcher(match, self._ignore)
+        if filesystem != "normal":
+            # The normal filesystem evaluates ignore rules while walking the
+            # working copy. Other filesystems do not.
+            # TODO: Handle the case that a file is ignored but is still tracked
+            # in p1.
+            match = matchmod.differencematcher(match, self._ignore)
+
+        # How to check files whose metadata isn't enough to tell whether they
+        # changed. "hash" and "size" use the aux data of the store instead of
+        # fetching and reading the content of the parent commit.
+        comparison = self._ui.config("workingcopy", "lookupcomparison")
+        if comparison == "size" and not getattr(
+            self._repo, "_warnedsizecomparison", False
+        ):
+            self._ui.warn(
+                _(
+                    "warning: comparing files by size only, modifications that keep the size of a file may be missed\n"
+                )
+            )
+            self._repo._warnedsizecomparison = True
+        auxstore = None
+        if comparison != "content":
+            auxstore = self._repo.fileslog.filescmstore
+
+        # Number of threads walking the working copy. 0 walks it in the
+        # current thread.
+        threadcount = self._ui.configint("workingcopy", "rustwalkerthreads")
+        # Files modified within this many seconds of the last dirstate write
+        # are compared by content, even if their size and mtime match.
+        mtimeracewindow = self._ui.configint("workingcopy", "mtimeracewindow")
+        # Do not walk nested Git repositories either, like nested Mercurial
+        # repositories.
+        opaquenestedrepos = self._ui.configbool("workingcopy", "opaquenestedrepos")
+        # Warm up the cold EdenFS directories touched by status in the
+        # background.
+        edenprefetchunloaded = self._ui.configbool(
+            "workingcopy", "edenprefetchunloaded"
+        )
 
         return bindings.workingcopy.status.status(
             self._root,
+            self._opener.base,
             self._repo[self.p1()].manifest(),
             self._repo.fileslog.filescmstore,
             tree,
@@ -985,6 +1021,14 @@ class dirstate(object):
             match,
             unknown,
             filesystem,
+            comparison,
+            auxstore,
+            threadcount,
+            mtimeracewindow,
+            ignored,
+            self._globalignorefiles(),
+            opaquenestedrepos,
+            edenprefetchunloaded,
         )
 
     @perftrace.tracefunc("Status")
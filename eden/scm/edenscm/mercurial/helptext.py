@@ -2291,6 +2291,11 @@ See :hg:`help templates` for details.
 ``treestate``
 -------------
 
+``backgroundcompact``
+    Whether to repack treestate files from a background process when they
+    exceed the repack threshold, instead of during the command writing them.
+    (default: true)
+
 ``mingcage``
     Seconds. Only files older than that would be garbage collected.
     (default: 1209600, 2 weeks)
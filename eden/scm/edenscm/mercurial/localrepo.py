@@ -1277,6 +1277,13 @@ class localrepository(object):
     def nullableedenapi(self):
         return self._getedenapi(nullable=True)
 
+    def hascommitcomplete(self, node, checkfiles=False):
+        """Test if the commit, its root tree and, with checkfiles, all its
+        trees and files are present locally, so it can be used without
+        fetching anything from the server.
+        """
+        return self._rsrepo.hascommitcomplete(node, checkfiles)
+
     def _constructmanifest(self):
         # This is a temporary function while we migrate from manifest to
         # manifestlog. It allows bundlerepo to intercept the manifest creation.
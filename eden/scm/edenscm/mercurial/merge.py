@@ -2728,6 +2728,35 @@ def makenativecheckoutplan(repo, p1, p2, matcher=None, updateprogresspath=None):
     )
 
 
+def _abortonpathconflicts(repo, conflicts):
+    """abort if the (kind, path) conflicts from checkoutplan.check_path_conflicts
+    are not empty"""
+    unknown = [(kind, f) for kind, f in conflicts if kind != "modified"]
+    if unknown:
+        for kind, f in unknown:
+            if kind == "untracked-directory":
+                repo.ui.warn(_("%s: untracked file conflicts with directory\n") % f)
+            else:
+                repo.ui.warn(_("%s: untracked file differs\n") % f)
+
+        raise error.Abort(
+            _(
+                "untracked files in working directory "
+                "differ from files in requested revision"
+            )
+        )
+
+    modified = [f for kind, f in conflicts if kind == "modified"]
+    if modified:
+        msg = _("%d conflicting file changes:\n") % len(modified)
+        msg += " " + "\n ".join(i18n.limititems(modified)) + "\n"
+        hint = _(
+            "commit, shelve, update --clean to discard all your changes"
+            ", or update --merge to merge them"
+        )
+        raise error.Abort(msg.strip(), hint=hint)
+
+
 @util.timefunction("donativecheckout", 0, "ui")
 def donativecheckout(repo, p1, p2, xp1, xp2, matcher, force, partial, wc, prerecrawls):
     repo.ui.debug("Using native checkout\n")
@@ -2752,32 +2781,18 @@ def donativecheckout(repo, p1, p2, xp1, xp2, matcher, force, partial, wc, prerec
             store = repo.fileslog.filescmstore
 
         status = nativestatus.status(repo.status(unknown=True))
-        unknown = plan.check_unknown_files(
+        conflicts = plan.check_path_conflicts(
             p2.manifest(),
             store,
             repo.dirstate._map._tree,
             status,
         )
-        if unknown:
-            for f in unknown:
-                repo.ui.warn(_("%s: untracked file differs\n") % f)
-
-            raise error.Abort(
-                _(
-                    "untracked files in working directory "
-                    "differ from files in requested revision"
-                )
-            )
-
-        conflicts = plan.check_conflicts(status)
-        if conflicts:
-            msg = _("%d conflicting file changes:\n") % len(conflicts)
-            msg += " " + "\n ".join(i18n.limititems(conflicts)) + "\n"
-            hint = _(
-                "commit, shelve, update --clean to discard all your changes"
-                ", or update --merge to merge them"
-            )
-            raise error.Abort(msg.strip(), hint=hint)
+        policy = repo.ui.config("checkout", "conflict-policy")
+        if policy == "abort":
+            _abortonpathconflicts(repo, conflicts)
+        tomerge = plan.resolve_path_conflicts(conflicts, policy)
+        if tomerge:
+            _abortonpathconflicts(repo, [("modified", f) for f in tomerge])
 
     # preserving checks as is, even though wc.isinmemory always false here
     if not partial and not wc.isinmemory():
@@ -155,6 +155,18 @@ def callcatch(ui, req, func):
                 # Log error info for all non-zero exits.
                 _uploadtraceback(ui, str(ex), util.smartformatexc())
 
+                # Errors from Rust carry a stable code. See bindings.error.codes.
+                errorcode = getattr(ex, "errorcode", None)
+                if errorcode is not None:
+                    ui.log(
+                        "error_code",
+                        "error code: %s (%s)\n",
+                        errorcode,
+                        ex.errorcategory,
+                        errorcode=errorcode,
+                        errorcategory=ex.errorcategory,
+                    )
+
                 raise
             finally:
                 # Print 'remote:' messages before 'abort:' messages.
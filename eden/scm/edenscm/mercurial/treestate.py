@@ -12,7 +12,7 @@ import os
 
 from bindings import treestate
 
-from . import error, node, pycompat, txnutil, util
+from . import error, node, progress, pycompat, txnutil, util
 from .i18n import _
 from .pycompat import decodeutf8, encodeutf8
 
@@ -78,7 +78,7 @@ def _error(self, *args, **kwargs):
 class emptytree(object):
     """an empty, read-only treestate"""
 
-    setmetadata = remove = insert = saveas = flush = _error
+    setmetadata = remove = insert = saveas = compact = flush = _error
     getmetadata = _fixed("")
     pathcomplete = invalidatemtime = get = _fixed(None)
     hasdir = __contains__ = _fixed(False)
@@ -106,6 +106,7 @@ class treestatemap(object):
 
     def __init__(self, ui, vfs, root, importdirstate=None):
         self._filename = None
+        self._compactrequested = False
         self._ui = ui
         self._vfs = vfs
         self._root = root
@@ -445,24 +446,39 @@ class treestatemap(object):
         self._vfs.makedirs("treestate")
 
         # repack and gc (with wlock acquired by parent functions)
-        if self._threshold > 0 and self._rootid > self._threshold:
-            path = self._setfilename()
-            self._ui.debug("creating treestate/%s\n" % (self._filename,))
+        due = self._threshold > 0 and self._rootid > self._threshold
+        if (
+            due
+            and not self._compactrequested
+            and self._ui.configbool("treestate", "backgroundcompact")
+        ):
+            # Compacting rewrites the whole file. Let another process do it, so
+            # this command does not wait for it. The threshold is recalculated
+            # below, so a failed compaction is retried once the file grows
+            # further.
+            self._threshold = 0
+            self._spawncompaction()
+            due = False
+        if self._compactrequested or due:
             # recalculate threshold
             self._threshold = 0
-            rootid = self._tree.saveas(path)
+            self._compactrequested = False
+            rootid = self._compact()
             self._gc()
         else:
             rootid = self._tree.flush()
 
         # calculate self._threshold
-        if self._threshold == 0 and rootid > self._ui.configbytes(
-            "treestate", "minrepackthreshold"
-        ):
-            factor = self._ui.configint("treestate", "repackfactor")
-            if factor:
-                self._threshold = rootid * factor
-            self._ui.debug("treestate repack threshold set to %s\n" % self._threshold)
+        if self._threshold == 0:
+            self._threshold = treestate.compactionthreshold(
+                rootid,
+                self._ui.configbytes("treestate", "minrepackthreshold"),
+                self._ui.configint("treestate", "repackfactor") or 0,
+            )
+            if self._threshold:
+                self._ui.debug(
+                    "treestate repack threshold set to %s\n" % self._threshold
+                )
 
         # write .hg/dirstate
         st.write(self._parents[0])
@@ -480,6 +496,34 @@ class treestatemap(object):
         st.close()
         self._rootid = rootid
 
+    def compact(self):
+        """Drop the garbage of the treestate file on the next write"""
+        self._compactrequested = True
+
+    def _spawncompaction(self):
+        self._ui.debug("compacting treestate in the background\n")
+        self._ui.log("treestate_compaction", treestate_compaction_background=True)
+        try:
+            util.spawndetached(
+                util.hgcmd() + ["debugtreestate", "repack"], cwd=self._root
+            )
+        except Exception as ex:
+            self._ui.debug("cannot start background compaction: %s\n" % ex)
+
+    def _compact(self):
+        path = self._setfilename()
+        self._ui.debug("creating treestate/%s\n" % (self._filename,))
+        start = util.timer()
+        with progress.spinner(self._ui, _("compacting treestate")):
+            rootid, oldsize, newsize = self._tree.compact(path)
+        self._ui.log(
+            "treestate_compaction",
+            treestate_old_size=oldsize,
+            treestate_new_size=newsize,
+            treestate_compaction_time_ms=int((util.timer() - start) * 1000),
+        )
+        return rootid
+
     @property
     def nonnormalset(self):
         return self.nonnormalsetfiltered(None)
@@ -697,7 +741,7 @@ def repack(ui, repo):
     version = currentversion(repo)
     if version > 0:
         with repo.wlock(), repo.lock(), repo.transaction("dirstate") as tr:
-            repo.dirstate._map._threshold = 1
+            repo.dirstate._map.compact()
             repo.dirstate._dirty = True
             repo.dirstate.write(tr)
     else:
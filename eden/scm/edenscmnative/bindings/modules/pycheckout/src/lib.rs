@@ -17,8 +17,10 @@ use checkout::ActionMap;
 use checkout::Checkout;
 use checkout::CheckoutPlan;
 use checkout::Conflict;
+use checkout::ConflictPolicy;
 use checkout::Merge;
 use checkout::MergeResult;
+use checkout::PathConflict;
 use cpython::*;
 use cpython_ext::convert::ImplInto;
 use cpython_ext::ExtractInnerRef;
@@ -36,6 +38,7 @@ use pypathmatcher::extract_option_matcher;
 use pystatus::status as PyStatus;
 use pytreestate::treestate as PyTreeState;
 use storemodel::ReadFileContents;
+use types::RepoPathBuf;
 use vfs::VFS;
 
 type ArcReadFileContents = Arc<dyn ReadFileContents<Error = anyhow::Error> + Send + Sync>;
@@ -117,6 +120,47 @@ py_class!(class checkoutplan |py| {
         Ok(unknown.into_iter().map(|p|p.to_string()).collect())
     }
 
+    /// Find the local changes the plan would lose, as a list of (kind, path).
+    def check_path_conflicts(
+        &self,
+        manifest: &treemanifest,
+        store: ImplInto<ArcReadFileContents>,
+        state: &PyTreeState,
+        status: &PyStatus,
+    ) -> PyResult<Vec<(&'static str, String)>> {
+        let plan = self.plan(py);
+        let state = state.get_state(py);
+        let manifest = manifest.get_underlying(py);
+        let store = store.into();
+        let status = status.extract_inner_ref(py);
+        let conflicts = py.allow_threads(move || -> Result<_> {
+            let mut option = state.lock();
+            let mut state = option.as_mut().expect("Attempted to operate on treestate from Python while Rust currently has ownership");
+            let manifest = manifest.read();
+            try_block_unless_interrupted(
+            plan.check_path_conflicts(&*manifest, store.as_ref(), &mut state, status))
+        }).map_pyerr(py)?;
+        Ok(conflicts.iter().map(|c| (c.kind(), c.path().to_string())).collect())
+    }
+
+    /// Handle the conflicts returned by `check_path_conflicts` according to `policy` ("abort",
+    /// "backup" or "merge"). Returns the files left to merge.
+    def resolve_path_conflicts(
+        &self,
+        conflicts: Vec<(String, String)>,
+        policy: &str,
+    ) -> PyResult<Vec<String>> {
+        let plan = self.plan(py);
+        let policy: ConflictPolicy = policy.parse().map_pyerr(py)?;
+        let conflicts = conflicts
+            .into_iter()
+            .map(|(kind, path)| PathConflict::from_kind(&kind, RepoPathBuf::from_string(path)?))
+            .collect::<Result<Vec<_>>>()
+            .map_pyerr(py)?;
+        let to_merge = py.allow_threads(|| plan.resolve_path_conflicts(conflicts, policy)).map_pyerr(py)?;
+        Ok(to_merge.into_iter().map(|p| p.to_string()).collect())
+    }
+
     def check_conflicts(&self, status: &PyStatus) -> PyResult<Vec<String>> {
         let status = status.extract_inner_ref(py);
         let plan = self.plan(py);
@@ -27,6 +27,7 @@ use dag::ops::ToIdSet;
 use dag::CloneData;
 use dag::Dag;
 use dag::DagAlgorithm;
+use dag::IntegrityReport;
 use dag::Vertex;
 use dag::VertexListWithOptions;
 use hgcommits::DagCommits;
@@ -212,6 +213,17 @@ py_class!(pub class commits |py| {
         Ok(problems)
     }
 
+    /// checkintegrity() -> {'missing_universal_ids': [id], 'segments': [str], 'idmap': [str]}
+    ///
+    /// Check for problems of universal ids, segments, and the IdMap.
+    /// Returns a dict of problems by category. A valid graph should
+    /// return empty lists.
+    def checkintegrity(&self) -> PyResult<Serde<IntegrityReport>> {
+        let inner = self.inner(py).read();
+        let report = block_on(inner.check_integrity()).map_pyerr(py)?;
+        Ok(Serde(report))
+    }
+
     /// checkisomorphicgraph(inner, heads) -> [str]
     ///
     /// Check for problems of segments such as cycles or wrong flags.
@@ -16,6 +16,7 @@ use cpython_ext::ResultPyErrExt;
 use cpython_ext::Str;
 use dag::DagAlgorithm;
 use dag::IdSegment;
+use dag::SegmentStats;
 use dag::Set;
 use dag::Vertex;
 
@@ -64,6 +65,21 @@ py_class!(pub class dagalgo |py| {
         Ok(parents.into_iter().map(|name| PyBytes::new(py, name.as_ref())).collect())
     }
 
+    /// Get parents of each name in `names`. Preserve the order.
+    /// Faster than calling `parentnames` for each name.
+    def parentsbatch(&self, names: Vec<PyBytes>) -> PyResult<Vec<Vec<PyBytes>>> {
+        let names: Vec<Vertex> = names.iter().map(|name| Vertex::copy_from(name.data(py))).collect();
+        let parents = block_on(self.dag(py).parents_batch(&names)).map_pyerr(py)?;
+        Ok(to_nested_pybytes(py, parents))
+    }
+
+    /// Get children of each name in `names`. Preserve the order of `names`.
+    def childrenbatch(&self, names: Vec<PyBytes>) -> PyResult<Vec<Vec<PyBytes>>> {
+        let names: Vec<Vertex> = names.iter().map(|name| Vertex::copy_from(name.data(py))).collect();
+        let children = block_on(self.dag(py).children_batch(&names)).map_pyerr(py)?;
+        Ok(to_nested_pybytes(py, children))
+    }
+
     /// The `n`-th first ancestor of `x`
     def firstancestornth(&self, x: PyBytes, n: u64) -> PyResult<Option<PyBytes>> {
         let result = block_on(self.dag(py).first_ancestor_nth(Vertex::copy_from(x.data(py)), n)).map_pyerr(py)?;
@@ -198,8 +214,29 @@ py_class!(pub class dagalgo |py| {
         let segments = id_dag.id_set_to_id_segments_with_max_level(&id_set, maxlevel).map_pyerr(py)?;
         Ok(Serde(segments))
     }
+
+    /// segmentstats() -> {'levels': [{'level': int, 'segment_count': int, ...}]}
+    /// Get per-level segment statistics, such as segment counts, average span
+    /// lengths, and gaps. Useful to report the health of the graph.
+    def segmentstats(&self) -> PyResult<Serde<SegmentStats>> {
+        let id_dag = self.dag(py).id_dag_snapshot().map_pyerr(py)?;
+        let stats = id_dag.segment_stats().map_pyerr(py)?;
+        Ok(Serde(stats))
+    }
 });
 
+fn to_nested_pybytes(py: Python, names: Vec<Vec<Vertex>>) -> Vec<Vec<PyBytes>> {
+    names
+        .into_iter()
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| PyBytes::new(py, name.as_ref()))
+                .collect()
+        })
+        .collect()
+}
+
 impl dagalgo {
     pub fn from_dag(py: Python, dag: impl DagAlgorithm + Send + Sync + 'static) -> PyResult<Self> {
         Self::create_instance(py, Arc::new(dag))
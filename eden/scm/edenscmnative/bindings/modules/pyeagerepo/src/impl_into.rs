@@ -8,6 +8,8 @@ use std::sync::Arc;
 
 use cpython::*;
 use cpython_ext::convert::register_into;
+use storemodel::ComputedFileAuxData;
+use storemodel::ReadFileAuxData;
 use storemodel::ReadFileContents;
 use storemodel::TreeStore;
 
@@ -16,6 +18,7 @@ use crate::EagerRepoStore;
 pub(crate) fn register(py: Python) {
     register_into(py, |py, obj: EagerRepoStore| obj.to_dyn_treestore(py));
     register_into(py, |py, obj: EagerRepoStore| obj.to_read_file_contents(py));
+    register_into(py, |py, obj: EagerRepoStore| obj.to_read_file_aux_data(py));
 }
 
 impl EagerRepoStore {
@@ -31,4 +34,12 @@ impl EagerRepoStore {
         let store = self.inner(py).clone();
         Arc::new(store)
     }
+
+    fn to_read_file_aux_data(
+        &self,
+        py: Python,
+    ) -> Arc<dyn ReadFileAuxData<Error = anyhow::Error> + Send + Sync> {
+        let store = self.inner(py).clone();
+        Arc::new(ComputedFileAuxData(store))
+    }
 }
@@ -46,16 +46,57 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     m.add(py, "NonUTF8Path", py.get_type::<NonUTF8Path>())?;
     m.add(py, "TlsError", py.get_type::<TlsError>())?;
 
+    let codes = PyDict::new(py);
+    for code in error_code::ErrorCode::ALL {
+        codes.set_item(py, code.name(), code.code())?;
+    }
+    m.add(py, "codes", codes)?;
+
     register_error_handlers();
 
     Ok(m)
 }
 
+/// Find the stable error code of `e`. See the `error-code` crate.
+fn error_code(e: &error::Error) -> Option<error_code::ErrorCode> {
+    revisionstore::error::error_code(e).or_else(|| {
+        error_code::chain(e).find_map(|e| {
+            if let Some(e) = e.downcast_ref::<dag::Error>() {
+                Some(e.error_code())
+            } else if let Some(hgcommits::Error::Dag(e)) = e.downcast_ref::<hgcommits::Error>() {
+                Some(e.error_code())
+            } else {
+                None
+            }
+        })
+    })
+}
+
+/// Expose the error code of `e` as the `errorcode` and `errorcategory` attributes of the
+/// Python exception.
+fn set_error_code(py: Python, e: &error::Error, mut err: PyErr) -> PyErr {
+    if let Some(code) = error_code(e) {
+        let instance = err.instance(py);
+        // Setting attributes on exception instances does not fail in practice. The error
+        // without a code is still better than no error.
+        let _ = instance.setattr(py, "errorcode", code.code());
+        let _ = instance.setattr(py, "errorcategory", code.category().name());
+    }
+    err
+}
+
 fn register_error_handlers() {
     fn specific_error_handler(py: Python, mut e: &error::Error) -> Option<PyErr> {
-        // We care about concrete errors, so peel away anyhow contextual layers.
-        while let Some(inner) = e.downcast_ref::<error::Error>() {
-            e = inner;
+        // We care about concrete errors, so peel away anyhow contextual layers and error codes.
+        // The error code is still found on the original error by `set_error_code`.
+        loop {
+            if let Some(inner) = e.downcast_ref::<error::Error>() {
+                e = inner;
+            } else if let Some(coded) = e.downcast_ref::<error_code::CodedError>() {
+                e = coded.inner();
+            } else {
+                break;
+            }
         }
 
         // Extract inner io::Error out.
@@ -214,6 +255,77 @@ fn register_error_handlers() {
         Some(PyErr::new::<RustError, _>(py, format!("{:?}", e)))
     }
 
-    error::register("010-specific", specific_error_handler);
-    error::register("999-fallback", fallback_error_handler);
+    fn coded_specific_error_handler(py: Python, e: &error::Error) -> Option<PyErr> {
+        specific_error_handler(py, e).map(|err| set_error_code(py, e, err))
+    }
+
+    fn coded_fallback_error_handler(py: Python, e: &error::Error) -> Option<PyErr> {
+        fallback_error_handler(py, e).map(|err| set_error_code(py, e, err))
+    }
+
+    error::register("010-specific", coded_specific_error_handler);
+    error::register("999-fallback", coded_fallback_error_handler);
+}
+
+#[cfg(test)]
+#[cfg(not(all(fbcode_build, feature = "python2")))]
+mod tests {
+    use std::io;
+
+    use anyhow::anyhow;
+    use anyhow::Context;
+    use cpython_ext::ResultPyErrExt;
+    use error_code::ErrorCode;
+    use error_code::WithErrorCode;
+
+    use super::*;
+
+    fn to_pyerr(py: Python, err: error::Error) -> PyErr {
+        register_error_handlers();
+        Err::<(), _>(err).map_pyerr(py).unwrap_err()
+    }
+
+    fn error_code_attr(py: Python, err: &mut PyErr) -> u32 {
+        let instance = err.instance(py);
+        instance
+            .getattr(py, "errorcode")
+            .unwrap()
+            .extract(py)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_coded_errors_keep_their_python_type() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        let err = Err::<(), _>(io::Error::new(io::ErrorKind::NotFound, "gone"))
+            .with_error_code(ErrorCode::WalkFailed)
+            .context("walking")
+            .unwrap_err();
+        let mut err = to_pyerr(py, err);
+        assert!(err.matches(py, py.get_type::<exc::IOError>()));
+        assert_eq!(error_code_attr(py, &mut err), 3001);
+
+        let err = Err::<(), _>(types::errors::NetworkError::wrap(anyhow!("refused")))
+            .with_error_code(ErrorCode::LfsTransferFailed)
+            .unwrap_err();
+        let mut err = to_pyerr(py, err);
+        assert!(err.matches(py, py.get_type::<HttpError>()));
+        assert_eq!(error_code_attr(py, &mut err), 1003);
+    }
+
+    #[test]
+    fn test_coded_error_message_is_not_repeated() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        let err = Err::<(), _>(anyhow!("boom"))
+            .with_error_code(ErrorCode::DagBug)
+            .unwrap_err();
+        let mut err = to_pyerr(py, err);
+        assert!(err.matches(py, py.get_type::<RustError>()));
+        let message = err.instance(py).str(py).unwrap().to_string(py).unwrap();
+        assert_eq!(message.matches("boom").count(), 1);
+    }
 }
@@ -11,6 +11,8 @@ use std::sync::Arc;
 
 use cpython::*;
 use cpython_ext::convert::register_into;
+use storemodel::ComputedFileAuxData;
+use storemodel::ReadFileAuxData;
 use storemodel::ReadFileContents;
 use storemodel::TreeStore;
 
@@ -19,6 +21,7 @@ use crate::gitstore;
 pub(crate) fn register(py: Python) {
     register_into(py, |py, g: gitstore| g.to_dyn_treestore(py));
     register_into(py, |py, g: gitstore| g.to_read_file_contents(py));
+    register_into(py, |py, g: gitstore| g.to_read_file_aux_data(py));
 }
 
 impl gitstore {
@@ -34,4 +37,12 @@ impl gitstore {
         let store = self.inner(py).clone();
         store as Arc<_>
     }
+
+    fn to_read_file_aux_data(
+        &self,
+        py: Python,
+    ) -> Arc<dyn ReadFileAuxData<Error = anyhow::Error> + Send + Sync> {
+        let store = self.inner(py).clone();
+        Arc::new(ComputedFileAuxData(store))
+    }
 }
@@ -75,4 +75,17 @@ py_class!(pub class repo |py| {
         repo_ref.invalidate_dag_commits();
         Ok(PyNone)
     }
+
+    /// Test if the commit can be used without fetching anything from the server.
+    /// With `checkfiles`, all its trees and files must be present locally too.
+    def hascommitcomplete(&self, node: PyBytes, checkfiles: bool = false) -> PyResult<bool> {
+        let vertex = node.data(py).to_vec().into();
+        let commits = {
+            let mut repo_ref = self.inner(py).write();
+            py.allow_threads(|| repo_ref.dag_commits()).map_pyerr(py)?
+        };
+        let repo_ref = self.inner(py).read();
+        py.allow_threads(|| repo_ref.has_commit_complete(&**commits.read(), &vertex, checkfiles))
+            .map_pyerr(py)
+    }
 });
@@ -5,6 +5,8 @@
  * GNU General Public License version 2.
  */
 
+use std::sync::Arc;
+
 use anyhow::format_err;
 use anyhow::Result;
 use cpython::PyBytes;
@@ -23,13 +25,17 @@ use cpython_ext::PyPathBuf;
 use cpython_ext::ResultPyErrExt;
 use revisionstore::datastore::Delta;
 use revisionstore::datastore::StoreResult;
+use revisionstore::CancellationToken;
 use revisionstore::ContentDataStore;
 use revisionstore::ContentHash;
+use revisionstore::FetchPriority;
 use revisionstore::HgIdDataStore;
 use revisionstore::HgIdMutableDeltaStore;
+use revisionstore::LocalStore;
 use revisionstore::RemoteDataStore;
 use revisionstore::StoreKey;
 use revisionstore::ToKeys;
+use types::Key;
 use types::Node;
 
 use crate::pythonutil::from_base;
@@ -44,20 +50,25 @@ use crate::pythonutil::to_metadata;
 
 pub trait HgIdDataStorePyExt {
     fn get_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyBytes>;
+    fn get_buffer_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<pybytes::Bytes>;
     fn get_delta_chain_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyList>;
     fn get_delta_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyObject>;
     fn get_meta_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyDict>;
     fn get_missing_py(&self, py: Python, keys: &mut PyIterator) -> PyResult<PyList>;
+    fn contains_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<bool>;
     fn refresh_py(&self, py: Python) -> PyResult<PyNone>;
 }
 
 pub trait ContentDataStorePyExt {
     fn blob_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyBytes>;
+    fn blob_buffer_py(&self, py: Python, name: &PyPath, node: &PyBytes)
+        -> PyResult<pybytes::Bytes>;
     fn metadata_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyDict>;
 }
 
 pub trait IterableHgIdDataStorePyExt {
-    fn iter_py(&self, py: Python) -> PyResult<Vec<PyTuple>>;
+    /// The `(name, node, deltabase, deltalen)` tuple of an entry of the store.
+    fn entry_py(&self, py: Python, key: Key) -> Result<PyTuple>;
 }
 
 pub trait HgIdMutableDeltaStorePyExt: HgIdDataStorePyExt {
@@ -76,6 +87,7 @@ pub trait HgIdMutableDeltaStorePyExt: HgIdDataStorePyExt {
 pub trait RemoteDataStorePyExt: RemoteDataStore {
     fn prefetch_py(&self, py: Python, keys: PyList) -> PyResult<PyObject>;
     fn upload_py(&self, py: Python, keys: PyList) -> PyResult<PyList>;
+    fn get_remote_missing_py(&self, py: Python, keys: PyList) -> PyResult<PyList>;
 }
 
 impl<T: HgIdDataStore + ?Sized> HgIdDataStorePyExt for T {
@@ -88,6 +100,15 @@ impl<T: HgIdDataStore + ?Sized> HgIdDataStorePyExt for T {
         }
     }
 
+    fn get_buffer_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<pybytes::Bytes> {
+        let key = StoreKey::hgid(to_key(py, name, node)?);
+        let result = py.allow_threads(|| self.get(key)).map_pyerr(py)?;
+        match result {
+            StoreResult::Found(data) => pybytes::Bytes::from_bytes(py, data.into()),
+            StoreResult::NotFound(key) => Err(key_error(py, &key)),
+        }
+    }
+
     fn get_delta_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyObject> {
         let key = to_key(py, name, node)?;
         let storekey = StoreKey::hgid(key.clone());
@@ -205,8 +226,13 @@ impl<T: HgIdDataStore + ?Sized> HgIdDataStorePyExt for T {
         Ok(results)
     }
 
+    fn contains_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<bool> {
+        let key = StoreKey::hgid(to_key(py, name, node)?);
+        py.allow_threads(|| self.contains(&key)).map_pyerr(py)
+    }
+
     fn refresh_py(&self, py: Python) -> PyResult<PyNone> {
-        self.refresh().map_pyerr(py)?;
+        py.allow_threads(|| self.refresh()).map_pyerr(py)?;
         Ok(PyNone)
     }
 }
@@ -221,6 +247,20 @@ impl<T: ContentDataStore + ?Sized> ContentDataStorePyExt for T {
         }
     }
 
+    fn blob_buffer_py(
+        &self,
+        py: Python,
+        name: &PyPath,
+        node: &PyBytes,
+    ) -> PyResult<pybytes::Bytes> {
+        let key = StoreKey::hgid(to_key(py, name, node)?);
+        let res = py.allow_threads(|| self.blob(key)).map_pyerr(py)?;
+        match res {
+            StoreResult::Found(blob) => pybytes::Bytes::from_bytes(py, blob),
+            StoreResult::NotFound(key) => Err(key_error(py, &key)),
+        }
+    }
+
     fn metadata_py(&self, py: Python, name: &PyPath, node: &PyBytes) -> PyResult<PyDict> {
         let key = StoreKey::hgid(to_key(py, name, node)?);
         let res = py.allow_threads(|| self.metadata(key)).map_pyerr(py)?;
@@ -244,31 +284,27 @@ impl<T: ContentDataStore + ?Sized> ContentDataStorePyExt for T {
 }
 
 impl<T: ToKeys + HgIdDataStore + ?Sized> IterableHgIdDataStorePyExt for T {
-    fn iter_py(&self, py: Python) -> PyResult<Vec<PyTuple>> {
-        let iter = py.allow_threads(|| self.to_keys()).into_iter().map(|res| {
-            let key = res?;
-            let res = py.allow_threads(|| self.get(StoreKey::hgid(key.clone())))?;
-            let data = match res {
-                StoreResult::Found(data) => data,
-                StoreResult::NotFound(_) => return Err(format_err!("Key {:?} not found", key)),
-            };
-            let delta = Delta {
-                data: data.into(),
-                base: None,
-                key: key.clone(),
-            };
-            let (name, node) = from_key(py, &key);
-            let (_, base_node) = from_base(py, &delta);
-            let tuple = (
-                name.to_py_object(py).into_object(),
-                node.into_object(),
-                base_node.into_object(),
-                delta.data.len().into_py_object(py),
-            )
-                .into_py_object(py);
-            Ok(tuple)
-        });
-        iter.collect::<Result<Vec<PyTuple>>>().map_pyerr(py)
+    fn entry_py(&self, py: Python, key: Key) -> Result<PyTuple> {
+        let res = py.allow_threads(|| self.get(StoreKey::hgid(key.clone())))?;
+        let data = match res {
+            StoreResult::Found(data) => data,
+            StoreResult::NotFound(_) => return Err(format_err!("Key {:?} not found", key)),
+        };
+        let delta = Delta {
+            data: data.into(),
+            base: None,
+            key: key.clone(),
+        };
+        let (name, node) = from_key(py, &key);
+        let (_, base_node) = from_base(py, &delta);
+        let tuple = (
+            name.to_py_object(py).into_object(),
+            node.into_object(),
+            base_node.into_object(),
+            delta.data.len().into_py_object(py),
+        )
+            .into_py_object(py);
+        Ok(tuple)
     }
 }
 
@@ -336,4 +372,75 @@ impl<T: RemoteDataStore + ?Sized> RemoteDataStorePyExt for T {
 
         Ok(results)
     }
+
+    fn get_remote_missing_py(&self, py: Python, keys: PyList) -> PyResult<PyList> {
+        let keys = keys
+            .iter(py)
+            .map(|tuple| Ok(StoreKey::from(from_tuple_to_key(py, &tuple)?)))
+            .collect::<PyResult<Vec<StoreKey>>>()?;
+        let missing = py
+            .allow_threads(|| self.get_remote_missing(&keys))
+            .map_pyerr(py)?;
+
+        let results = PyList::new(py, &[]);
+        for key in missing {
+            match key {
+                StoreKey::HgId(key) => {
+                    let key_tuple = from_key_to_tuple(py, &key);
+                    results.append(py, key_tuple.into_object());
+                }
+                StoreKey::Content(_, _) => {
+                    return Err(format_err!("Unsupported key: {:?}", key)).map_pyerr(py);
+                }
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+/// Like `prefetch_py`, but the prefetch can be interrupted with Ctrl-C. On interruption, the
+/// prefetch is cancelled, and an "interrupted" error is raised once the data fetched so far has
+/// been written to disk.
+///
+/// `priority` is "interactive" (the default) or "background". Background prefetches let the
+/// interactive ones go first.
+pub fn prefetch_interruptible_py(
+    py: Python,
+    store: Arc<dyn RemoteDataStore>,
+    keys: PyList,
+    priority: Option<String>,
+) -> PyResult<PyObject> {
+    let keys = keys
+        .iter(py)
+        .map(|tuple| Ok(StoreKey::from(from_tuple_to_key(py, &tuple)?)))
+        .collect::<PyResult<Vec<StoreKey>>>()?;
+    let priority = match priority {
+        Some(priority) => priority.parse::<FetchPriority>().map_pyerr(py)?,
+        None => FetchPriority::default(),
+    };
+
+    let cancel = CancellationToken::new();
+    py.allow_threads(|| -> Result<()> {
+        let mut task = {
+            let cancel = cancel.clone();
+            async_runtime::spawn_blocking(move || {
+                store.prefetch_with_priority(&keys, &cancel, priority)
+            })
+        };
+        match async_runtime::block_unless_interrupted(&mut task) {
+            Ok(result) => {
+                result??;
+                Ok(())
+            }
+            Err(interrupted) => {
+                // Let the prefetch flush what it already fetched before giving up.
+                cancel.cancel();
+                let _ = async_runtime::block_on(task);
+                Err(interrupted.into())
+            }
+        }
+    })
+    .map_pyerr(py)?;
+    Ok(Python::None(py))
 }
@@ -8,6 +8,7 @@
 use anyhow::format_err;
 use anyhow::Result;
 use cpython::PyBytes;
+use cpython::PyDict;
 use cpython::PyIterator;
 use cpython::PyList;
 use cpython::PyObject;
@@ -17,6 +18,7 @@ use cpython::Python;
 use cpython::PythonObject;
 use cpython::ToPyObject;
 use cpython_ext::PyNone;
+use cpython_ext::PyPath;
 use cpython_ext::PyPathBuf;
 use cpython_ext::ResultPyErrExt;
 use revisionstore::HgIdHistoryStore;
@@ -38,11 +40,17 @@ use crate::pythonutil::to_path;
 pub trait HgIdHistoryStorePyExt {
     fn get_missing_py(&self, py: Python, keys: &mut PyIterator) -> PyResult<PyList>;
     fn get_node_info_py(&self, py: Python, name: &PyPathBuf, node: &PyBytes) -> PyResult<PyTuple>;
+    fn get_node_info_batch_py(&self, py: Python, keys: &mut PyIterator) -> PyResult<PyList>;
+    fn get_linknode_batch_py(&self, py: Python, keys: &mut PyIterator) -> PyResult<PyDict>;
     fn refresh_py(&self, py: Python) -> PyResult<PyNone>;
 }
 
 pub trait IterableHgIdHistoryStorePyExt {
-    fn iter_py(&self, py: Python) -> PyResult<Vec<PyTuple>>;
+    fn iter_py(&self, py: Python, prefix: Option<&PyPath>) -> PyResult<Vec<PyTuple>>;
+    /// The keys of the store, only those under `prefix` if given.
+    fn keys_py(&self, py: Python, prefix: Option<&PyPath>) -> PyResult<Vec<Result<Key>>>;
+    /// The `(name, node, p1, p2, linknode, copyfrom)` tuple of an entry of the store.
+    fn entry_py(&self, py: Python, key: Key) -> Result<PyTuple>;
 }
 
 pub trait HgIdMutableHistoryStorePyExt: HgIdHistoryStorePyExt {
@@ -101,8 +109,47 @@ impl<T: HgIdHistoryStore + ?Sized> HgIdHistoryStorePyExt for T {
         Ok(from_node_info(py, &key, &info))
     }
 
+    fn get_node_info_batch_py(&self, py: Python, keys: &mut PyIterator) -> PyResult<PyList> {
+        let keys = keys
+            .map(|k| from_tuple_to_key(py, &k?))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let infos = py
+            .allow_threads(|| self.get_node_info_batch(&keys))
+            .map_pyerr(py)?;
+
+        let results = PyList::new(py, &[]);
+        for (key, info) in keys.iter().zip(infos) {
+            match info {
+                Some(info) => results.append(py, from_node_info(py, key, &info).into_object()),
+                None => results.append(py, py.None()),
+            }
+        }
+        Ok(results)
+    }
+
+    fn get_linknode_batch_py(&self, py: Python, keys: &mut PyIterator) -> PyResult<PyDict> {
+        let keys = keys
+            .map(|k| from_tuple_to_key(py, &k?))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let linknodes = py
+            .allow_threads(|| self.get_linknode_batch(&keys))
+            .map_pyerr(py)?;
+
+        let results = PyDict::new(py);
+        for (key, linknode) in keys.iter().zip(linknodes) {
+            if let Some(linknode) = linknode {
+                results.set_item(
+                    py,
+                    from_key_to_tuple(py, key),
+                    PyBytes::new(py, linknode.as_ref()),
+                )?;
+            }
+        }
+        Ok(results)
+    }
+
     fn refresh_py(&self, py: Python) -> PyResult<PyNone> {
-        self.refresh().map_pyerr(py)?;
+        py.allow_threads(|| self.refresh()).map_pyerr(py)?;
         Ok(PyNone)
     }
 }
@@ -163,33 +210,45 @@ fn to_node_info(
 }
 
 impl<T: ToKeys + HgIdHistoryStore + ?Sized> IterableHgIdHistoryStorePyExt for T {
-    fn iter_py(&self, py: Python) -> PyResult<Vec<PyTuple>> {
-        let iter = py.allow_threads(|| self.to_keys()).into_iter().map(|res| {
-            let key = res?;
-            let node_info = py.allow_threads(|| self.get_node_info(&key))?.unwrap();
-            let (name, node) = from_key(py, &key);
-            let copyfrom = if key.path != node_info.parents[0].path {
-                if node_info.parents[0].path.is_empty() {
-                    PyPathBuf::from(String::from(""))
-                } else {
-                    PyPathBuf::from(node_info.parents[0].path.as_repo_path())
-                }
-            } else {
-                PyPathBuf::from(String::from(""))
-            };
-            let tuple = (
-                name.to_py_object(py).into_object(),
-                node.into_object(),
-                PyBytes::new(py, node_info.parents[0].hgid.as_ref()),
-                PyBytes::new(py, node_info.parents[1].hgid.as_ref()),
-                PyBytes::new(py, node_info.linknode.as_ref().as_ref()),
-                copyfrom.to_py_object(py).into_object(),
-            )
-                .into_py_object(py);
-            Ok(tuple)
-        });
+    fn iter_py(&self, py: Python, prefix: Option<&PyPath>) -> PyResult<Vec<PyTuple>> {
+        let iter = self
+            .keys_py(py, prefix)?
+            .into_iter()
+            .map(|res| self.entry_py(py, res?));
         iter.collect::<Result<Vec<PyTuple>>>().map_pyerr(py)
     }
+
+    fn keys_py(&self, py: Python, prefix: Option<&PyPath>) -> PyResult<Vec<Result<Key>>> {
+        let prefix = prefix.map(|prefix| to_path(py, prefix)).transpose()?;
+        Ok(py.allow_threads(|| match prefix {
+            Some(prefix) => self.iter_prefix(&prefix),
+            None => self.to_keys(),
+        }))
+    }
+
+    fn entry_py(&self, py: Python, key: Key) -> Result<PyTuple> {
+        let node_info = py.allow_threads(|| self.get_node_info(&key))?.unwrap();
+        let (name, node) = from_key(py, &key);
+        let copyfrom = if key.path != node_info.parents[0].path {
+            if node_info.parents[0].path.is_empty() {
+                PyPathBuf::from(String::from(""))
+            } else {
+                PyPathBuf::from(node_info.parents[0].path.as_repo_path())
+            }
+        } else {
+            PyPathBuf::from(String::from(""))
+        };
+        let tuple = (
+            name.to_py_object(py).into_object(),
+            node.into_object(),
+            PyBytes::new(py, node_info.parents[0].hgid.as_ref()),
+            PyBytes::new(py, node_info.parents[1].hgid.as_ref()),
+            PyBytes::new(py, node_info.linknode.as_ref().as_ref()),
+            copyfrom.to_py_object(py).into_object(),
+        )
+            .into_py_object(py);
+        Ok(tuple)
+    }
 }
 
 impl<T: HgIdMutableHistoryStore + ?Sized> HgIdMutableHistoryStorePyExt for T {
@@ -22,6 +22,8 @@ use revisionstore::RemoteDataStore;
 use revisionstore::StoreKey;
 use revisionstore::StoreResult;
 use storemodel::minibytes::Bytes;
+use storemodel::ComputedFileAuxData;
+use storemodel::ReadFileAuxData;
 use storemodel::ReadFileContents;
 use storemodel::TreeStore;
 use types::Key;
@@ -42,6 +44,9 @@ pub(crate) fn register(py: Python) {
     register_into(py, |py, c: contentstore| c.to_read_file_contents(py));
     register_into(py, |py, f: filescmstore| f.to_read_file_contents(py));
     register_into(py, |py, p: pyfilescmstore| p.to_read_file_contents(py));
+
+    register_into(py, |py, f: filescmstore| f.to_read_file_aux_data(py));
+    register_into(py, |py, p: pyfilescmstore| p.to_read_file_aux_data(py));
 }
 
 impl contentstore {
@@ -69,6 +74,15 @@ impl filescmstore {
         let store = ArcFileStore(store);
         Arc::new(store)
     }
+
+    fn to_read_file_aux_data(
+        &self,
+        py: Python,
+    ) -> Arc<dyn ReadFileAuxData<Error = anyhow::Error> + Send + Sync> {
+        let store = self.extract_inner(py);
+        let store = ArcFileStore(store);
+        Arc::new(store)
+    }
 }
 
 impl pyfilescmstore {
@@ -78,6 +92,13 @@ impl pyfilescmstore {
     ) -> Arc<dyn ReadFileContents<Error = anyhow::Error> + Send + Sync> {
         self.extract_inner(py)
     }
+
+    fn to_read_file_aux_data(
+        &self,
+        py: Python,
+    ) -> Arc<dyn ReadFileAuxData<Error = anyhow::Error> + Send + Sync> {
+        Arc::new(ComputedFileAuxData(self.extract_inner(py)))
+    }
 }
 
 impl treescmstore {
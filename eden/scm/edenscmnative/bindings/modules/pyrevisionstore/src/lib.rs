@@ -9,11 +9,15 @@
 
 #![allow(non_camel_case_types)]
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs::read_dir;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::format_err;
 use anyhow::Error;
@@ -32,23 +36,39 @@ use cpython_ext::Str;
 use io::IO;
 use parking_lot::RwLock;
 use pyconfigparser::config;
+use revisionstore::archive::export_keys;
+use revisionstore::archive::import_archive;
+use revisionstore::archive::ArchiveEntryKind;
+use revisionstore::archive::ArchiveSource;
+use revisionstore::archive::ArchiveStats;
+use revisionstore::archive::ArchiveTarget;
+use revisionstore::error::FetchErrorKind;
+use revisionstore::gc;
 use revisionstore::repack;
 use revisionstore::scmstore::file_to_async_key_stream;
+use revisionstore::scmstore::FetchCause;
+use revisionstore::scmstore::FetchMode;
 use revisionstore::scmstore::FileAttributes;
+use revisionstore::scmstore::FileAuxData;
 use revisionstore::scmstore::FileStore;
 use revisionstore::scmstore::FileStoreBuilder;
+use revisionstore::scmstore::TreeAttributes;
 use revisionstore::scmstore::TreeStore;
 use revisionstore::scmstore::TreeStoreBuilder;
+use revisionstore::CancellationToken;
 use revisionstore::ContentStore;
 use revisionstore::ContentStoreBuilder;
 use revisionstore::CorruptionPolicy;
 use revisionstore::DataPack;
 use revisionstore::DataPackStore;
 use revisionstore::DataPackVersion;
+use revisionstore::DataStoreStats;
 use revisionstore::Delta;
 use revisionstore::EdenApiFileStore;
 use revisionstore::EdenApiTreeStore;
 use revisionstore::ExtStoredPolicy;
+use revisionstore::FetchPriority;
+use revisionstore::GcOptions;
 use revisionstore::HgIdDataStore;
 use revisionstore::HgIdHistoryStore;
 use revisionstore::HgIdMutableDeltaStore;
@@ -75,9 +95,12 @@ use revisionstore::RepackLocation;
 use revisionstore::StoreKey;
 use revisionstore::StoreResult;
 use revisionstore::StoreType;
+use revisionstore::ToKeys;
+use revisionstore::UnionHgIdHistoryStore;
 use types::Key;
 use types::NodeInfo;
 
+use crate::datastorepyext::prefetch_interruptible_py;
 use crate::datastorepyext::ContentDataStorePyExt;
 use crate::datastorepyext::HgIdDataStorePyExt;
 use crate::datastorepyext::HgIdMutableDeltaStorePyExt;
@@ -90,6 +113,10 @@ use crate::historystorepyext::RemoteHistoryStorePyExt;
 use crate::pythonutil::from_key;
 use crate::pythonutil::from_key_to_tuple;
 use crate::pythonutil::from_tuple_to_key;
+use crate::pythonutil::retry_keys_on_transient_error;
+use crate::pythonutil::retry_on_transient_error;
+use crate::pythonutil::to_key;
+use crate::pythonutil::to_path;
 
 mod datastorepyext;
 mod historystorepyext;
@@ -113,6 +140,8 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     m.add_class::<historypackstore>(py)?;
     m.add_class::<indexedlogdatastore>(py)?;
     m.add_class::<indexedloghistorystore>(py)?;
+    m.add_class::<entryiterator>(py)?;
+    m.add_class::<unionhistorystore>(py)?;
     m.add_class::<mutabledeltastore>(py)?;
     m.add_class::<mutablehistorystore>(py)?;
     m.add_class::<pyremotestore>(py)?;
@@ -122,6 +151,7 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     m.add_class::<filescmstore>(py)?;
     m.add_class::<treescmstore>(py)?;
     m.add_class::<pyfilescmstore>(py)?;
+    m.add_class::<fetchresults>(py)?;
     m.add(
         py,
         "repack",
@@ -145,7 +175,44 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
                 shared_path: &PyPath,
                 local_path: Option<&PyPath>,
                 suffix: Option<&PyPath>,
-                config: config
+                config: config,
+                verify: bool = false
+            )
+        ),
+    )?;
+
+    m.add(
+        py,
+        "gc",
+        py_fn!(
+            py,
+            gc_py(path: &PyPath, max_bytes: Option<u64>, max_age: Option<u64>)
+        ),
+    )?;
+    m.add(
+        py,
+        "exportarchive",
+        py_fn!(
+            py,
+            export_archive_py(
+                path: &PyPath,
+                keys: PyList,
+                filestore: Option<contentstore> = None,
+                treestore: Option<contentstore> = None,
+                historystore: Option<metadatastore> = None
+            )
+        ),
+    )?;
+    m.add(
+        py,
+        "importarchive",
+        py_fn!(
+            py,
+            import_archive_py(
+                path: &PyPath,
+                filestore: Option<contentstore> = None,
+                treestore: Option<contentstore> = None,
+                historystore: Option<metadatastore> = None
             )
         ),
     )?;
@@ -154,6 +221,108 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     Ok(m)
 }
 
+/// Garbage collect the shared indexedlog stores under `path`. `max_age` is in
+/// seconds. Returns a dict of GC statistics.
+fn gc_py(
+    py: Python,
+    path: &PyPath,
+    max_bytes: Option<u64>,
+    max_age: Option<u64>,
+) -> PyResult<PyDict> {
+    let options = GcOptions {
+        max_bytes,
+        max_age: max_age.map(Duration::from_secs),
+    };
+    let stats = py
+        .allow_threads(|| gc(path.as_path(), &options))
+        .map_pyerr(py)?;
+
+    let dict = PyDict::new(py);
+    dict.set_item(py, "scanned", stats.scanned)?;
+    dict.set_item(py, "evicted", stats.evicted)?;
+    dict.set_item(py, "bytes_before", stats.bytes_before)?;
+    dict.set_item(py, "bytes_after", stats.bytes_after)?;
+    Ok(dict)
+}
+
+fn archive_stats_to_dict(py: Python, stats: ArchiveStats) -> PyResult<PyDict> {
+    let dict = PyDict::new(py);
+    dict.set_item(py, "files", stats.files)?;
+    dict.set_item(py, "trees", stats.trees)?;
+    dict.set_item(py, "history", stats.history)?;
+    Ok(dict)
+}
+
+/// Write the entries of `keys`, a list of `(kind, path, node)` tuples where `kind` is "file",
+/// "tree" or "history", to a new archive at `path`. Returns the number of entries of each kind.
+fn export_archive_py(
+    py: Python,
+    path: &PyPath,
+    keys: PyList,
+    filestore: Option<contentstore>,
+    treestore: Option<contentstore>,
+    historystore: Option<metadatastore>,
+) -> PyResult<PyDict> {
+    let keys = keys
+        .iter(py)
+        .map(|item| {
+            let (kind, name, node) = <(String, PyPathBuf, PyBytes)>::extract(py, &item)?;
+            let kind = kind.parse::<ArchiveEntryKind>().map_pyerr(py)?;
+            Ok((kind, to_key(py, &name, &node)?))
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+    let files = filestore.map(|store| store.extract_inner(py));
+    let trees = treestore.map(|store| store.extract_inner(py));
+    let history = historystore.map(|store| store.extract_inner(py));
+
+    let stats = py
+        .allow_threads(|| {
+            let source = ArchiveSource {
+                files: files.as_ref().map(|s| s.as_ref() as &dyn HgIdDataStore),
+                trees: trees.as_ref().map(|s| s.as_ref() as &dyn HgIdDataStore),
+                history: history
+                    .as_ref()
+                    .map(|s| s.as_ref() as &dyn HgIdHistoryStore),
+            };
+            export_keys(&source, &keys, path.as_path())
+        })
+        .map_pyerr(py)?;
+    archive_stats_to_dict(py, stats)
+}
+
+/// Add the entries of the archive at `path` to the given stores. Nothing is added if the archive
+/// is corrupted, or has entries without a store to add them to. Returns the number of entries of
+/// each kind.
+fn import_archive_py(
+    py: Python,
+    path: &PyPath,
+    filestore: Option<contentstore>,
+    treestore: Option<contentstore>,
+    historystore: Option<metadatastore>,
+) -> PyResult<PyDict> {
+    let files = filestore.map(|store| store.extract_inner(py));
+    let trees = treestore.map(|store| store.extract_inner(py));
+    let history = historystore.map(|store| store.extract_inner(py));
+
+    let stats = py
+        .allow_threads(|| {
+            let target = ArchiveTarget {
+                files: files
+                    .as_ref()
+                    .map(|s| s.as_ref() as &dyn HgIdMutableDeltaStore),
+                trees: trees
+                    .as_ref()
+                    .map(|s| s.as_ref() as &dyn HgIdMutableDeltaStore),
+                history: history
+                    .as_ref()
+                    .map(|s| s.as_ref() as &dyn HgIdMutableHistoryStore),
+            };
+            import_archive(&target, path.as_path())
+        })
+        .map_pyerr(py)?;
+    archive_stats_to_dict(py, stats)
+}
+
 fn repack_py(
     py: Python,
     packpath: &PyPath,
@@ -198,21 +367,25 @@ fn repair(
     local_path: Option<&PyPath>,
     suffix: Option<&PyPath>,
     config: config,
+    verify: bool,
 ) -> PyResult<Str> {
     let config = config.get_cfg(py);
     py.allow_threads::<Result<String>, _>(|| {
-        let mut message = ContentStore::repair(
+        // The history stores are repaired first, since verifying the content reads them.
+        let mut message = MetadataStore::repair(
             shared_path.as_path(),
             local_path.map(|p| p.as_path()),
             suffix.map(|p| p.as_path()),
             &config,
+            verify,
         )?;
         message.push_str(
-            MetadataStore::repair(
+            ContentStore::repair(
                 shared_path.as_path(),
                 local_path.map(|p| p.as_path()),
                 suffix.map(|p| p.as_path()),
                 &config,
+                verify,
             )?
             .as_str(),
         );
@@ -223,15 +396,16 @@ fn repair(
 }
 
 py_class!(class datapack |py| {
-    data store: Box<DataPack>;
+    data store: Arc<DataPack>;
 
     def __new__(
         _cls,
-        path: &PyPath
+        path: &PyPath,
+        extstored: bool = false
     ) -> PyResult<datapack> {
         datapack::create_instance(
             py,
-            Box::new(DataPack::new(path, ExtStoredPolicy::Ignore).map_pyerr(py)?),
+            Arc::new(DataPack::new(path, extstored_policy(extstored)).map_pyerr(py)?),
         )
     }
 
@@ -252,6 +426,14 @@ py_class!(class datapack |py| {
         store.get_py(py, &name, node)
     }
 
+    /// Like `get`, but return a `bytes.Bytes` that shares the data with the store instead of
+    /// copying it. Use its `asref()` to read the data as a `memoryview` while the returned
+    /// object is alive.
+    def getbuffer(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<pybytes::Bytes> {
+        let store = self.store(py);
+        store.get_buffer_py(py, &name, node)
+    }
+
     def getdelta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyObject> {
         let store = self.store(py);
         store.get_delta_py(py, &name, node)
@@ -272,20 +454,70 @@ py_class!(class datapack |py| {
         store.get_missing_py(py, &mut keys.iter(py)?)
     }
 
-    def iterentries(&self) -> PyResult<Vec<PyTuple>> {
+    /// Whether the data for this key is in the store. Only the indexes are consulted, the
+    /// content isn't read.
+    def contains(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<bool> {
+        let store = self.store(py);
+        store.contains_py(py, &name, node)
+    }
+
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
+    }
+
+    /// Iterate over the `(name, node, deltabase, deltalen)` tuples of the entries.
+    def iterentries(&self) -> PyResult<entryiterator> {
+        let store = self.store(py).clone();
+        let keys = py.allow_threads(|| store.to_keys());
+        entryiterator::new(py, keys, move |py, key| store.entry_py(py, key))
+    }
+
+    /// Delta chain and size statistics of the pack.
+    def getstats(&self) -> PyResult<PyDict> {
         let store = self.store(py);
-        store.iter_py(py)
+        let stats = py.allow_threads(|| store.analyze()).map_pyerr(py)?;
+        stats_to_dict(py, &stats)
     }
 });
 
-/// Scan the filesystem for files with `extensions`, and compute their size.
+/// Convert the statistics returned by `analyze` into the dict returned by `getstats`.
+fn stats_to_dict(py: Python, stats: &DataStoreStats) -> PyResult<PyDict> {
+    let chainlengths = PyDict::new(py);
+    for (len, count) in stats.chain_len_histogram() {
+        chainlengths.set_item(py, len, count)?;
+    }
+
+    let res = PyDict::new(py);
+    res.set_item(py, "entries", stats.entries.len())?;
+    res.set_item(py, "maxchainlength", stats.max_chain_len())?;
+    res.set_item(py, "chainlengths", chainlengths)?;
+    res.set_item(py, "compressedsize", stats.compressed_size())?;
+    res.set_item(py, "uncompressedsize", stats.uncompressed_size())?;
+    res.set_item(py, "deadentries", stats.dead_entries)?;
+    res.set_item(py, "deadsize", stats.dead_bytes)?;
+    res.set_item(py, "scattereddeltas", stats.scattered_deltas)?;
+    Ok(res)
+}
+
+/// Whether LFS pointers stored in packs should be returned, as requested by the `extstored`
+/// constructor argument. They are ignored by default.
+fn extstored_policy(extstored: bool) -> ExtStoredPolicy {
+    if extstored {
+        ExtStoredPolicy::Use
+    } else {
+        ExtStoredPolicy::Ignore
+    }
+}
+
+/// Scan the filesystem for files with `extensions`, and compute their size. With no
+/// `extensions`, the size of all the files is computed, including those in subdirectories.
 fn compute_store_size<P: AsRef<Path>>(
     storepath: P,
     extensions: Vec<&str>,
 ) -> Result<(usize, usize)> {
     let dirents = read_dir(storepath)?;
 
-    assert_eq!(extensions.len(), 2);
+    assert!(extensions.is_empty() || extensions.len() == 2);
 
     let mut count = 0;
     let mut size = 0;
@@ -294,7 +526,17 @@ fn compute_store_size<P: AsRef<Path>>(
         let dirent = dirent?;
         let path = dirent.path();
 
-        if let Some(file_ext) = path.extension() {
+        if extensions.is_empty() {
+            let metadata = dirent.metadata()?;
+            if metadata.is_dir() {
+                let (dir_size, dir_count) = compute_store_size(&path, vec![])?;
+                size += dir_size as u64;
+                count += dir_count;
+            } else {
+                size += metadata.len();
+                count += 1;
+            }
+        } else if let Some(file_ext) = path.extension() {
             for extension in &extensions {
                 if extension == &file_ext {
                     size += dirent.metadata()?.len();
@@ -306,7 +548,9 @@ fn compute_store_size<P: AsRef<Path>>(
     }
 
     // We did count the indexes too, but we do not want them counted.
-    count /= 2;
+    if !extensions.is_empty() {
+        count /= 2;
+    }
 
     Ok((size as usize, count))
 }
@@ -314,40 +558,45 @@ fn compute_store_size<P: AsRef<Path>>(
 py_class!(class datapackstore |py| {
     data store: Box<DataPackStore>;
     data path: PathBuf;
+    data autorefresh: bool;
 
-    def __new__(_cls, path: &PyPath, deletecorruptpacks: bool = false, maxbytes: Option<u64> = None) -> PyResult<datapackstore> {
+    def __new__(_cls, path: &PyPath, deletecorruptpacks: bool = false, maxbytes: Option<u64> = None, autorefresh: bool = false, extstored: bool = false) -> PyResult<datapackstore> {
         let corruption_policy = if deletecorruptpacks {
             CorruptionPolicy::REMOVE
         } else {
             CorruptionPolicy::IGNORE
         };
 
-        datapackstore::create_instance(py, Box::new(DataPackStore::new(path, corruption_policy, maxbytes, ExtStoredPolicy::Ignore)), path.to_path_buf())
+        datapackstore::create_instance(py, Box::new(DataPackStore::new(path, corruption_policy, maxbytes, extstored_policy(extstored))), path.to_path_buf(), autorefresh)
     }
 
     def get(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyBytes> {
-        self.store(py).get_py(py, &name, node)
+        let store = self.store(py);
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_py(py, &name, node))
     }
 
     def getmeta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyDict> {
-        self.store(py).get_meta_py(py, &name, node)
+        let store = self.store(py);
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_meta_py(py, &name, node))
     }
 
     def getdelta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyObject> {
-        self.store(py).get_delta_py(py, &name, node)
+        let store = self.store(py);
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_delta_py(py, &name, node))
     }
 
     def getdeltachain(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyList> {
-        self.store(py).get_delta_chain_py(py, &name, node)
+        let store = self.store(py);
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_delta_chain_py(py, &name, node))
     }
 
     def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
-        self.store(py).get_missing_py(py, &mut keys.iter(py)?)
+        let store = self.store(py);
+        retry_keys_on_transient_error(py, *self.autorefresh(py), keys, || store.refresh_py(py), |keys| store.get_missing_py(py, keys))
     }
 
-    def markforrefresh(&self) -> PyResult<PyObject> {
-        self.store(py).force_rescan();
-        Ok(Python::None(py))
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
     }
 
     def getmetrics(&self) -> PyResult<PyDict> {
@@ -393,42 +642,48 @@ py_class!(class historypack |py| {
         store.get_missing_py(py, &mut keys.iter(py)?)
     }
 
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
+    }
+
     def getnodeinfo(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyTuple> {
         let store = self.store(py);
         store.get_node_info_py(py, &name, node)
     }
 
-    def iterentries(&self) -> PyResult<Vec<PyTuple>> {
+    def iterentries(&self, prefix: Option<PyPathBuf> = None) -> PyResult<Vec<PyTuple>> {
         let store = self.store(py);
-        store.iter_py(py)
+        store.iter_py(py, prefix.as_deref())
     }
 });
 
 py_class!(class historypackstore |py| {
     data store: Box<HistoryPackStore>;
     data path: PathBuf;
+    data autorefresh: bool;
 
-    def __new__(_cls, path: PyPathBuf, deletecorruptpacks: bool = false, maxbytes: Option<u64> = None) -> PyResult<historypackstore> {
+    def __new__(_cls, path: PyPathBuf, deletecorruptpacks: bool = false, maxbytes: Option<u64> = None, autorefresh: bool = false) -> PyResult<historypackstore> {
         let corruption_policy = if deletecorruptpacks {
             CorruptionPolicy::REMOVE
         } else {
             CorruptionPolicy::IGNORE
         };
 
-        historypackstore::create_instance(py, Box::new(HistoryPackStore::new(path.as_path(), corruption_policy, maxbytes)), path.to_path_buf())
+        historypackstore::create_instance(py, Box::new(HistoryPackStore::new(path.as_path(), corruption_policy, maxbytes)), path.to_path_buf(), autorefresh)
     }
 
     def getnodeinfo(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyTuple> {
-        self.store(py).get_node_info_py(py, &name, node)
+        let store = self.store(py);
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_node_info_py(py, &name, node))
     }
 
     def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
-        self.store(py).get_missing_py(py, &mut keys.iter(py)?)
+        let store = self.store(py);
+        retry_keys_on_transient_error(py, *self.autorefresh(py), keys, || store.refresh_py(py), |keys| store.get_missing_py(py, keys))
     }
 
-    def markforrefresh(&self) -> PyResult<PyObject> {
-        self.store(py).force_rescan();
-        Ok(Python::None(py))
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
     }
 
     def getmetrics(&self) -> PyResult<PyDict> {
@@ -445,78 +700,229 @@ py_class!(class historypackstore |py| {
 });
 
 py_class!(class indexedlogdatastore |py| {
-    data store: Box<IndexedLogHgIdDataStore>;
-
-    def __new__(_cls, path: &PyPath, config: config) -> PyResult<indexedlogdatastore> {
-        let config = IndexedLogHgIdDataStoreConfig { max_log_count: None, max_bytes_per_log: None, max_bytes: None };
+    data store: Arc<IndexedLogHgIdDataStore>;
+    data autorefresh: bool;
+
+    def __new__(_cls, path: &PyPath, config: config, shared: bool = false, autorefresh: bool = false) -> PyResult<indexedlogdatastore> {
+        let (store_config, store_type) = if shared {
+            let config = config.get_cfg(py);
+            let store_config = IndexedLogHgIdDataStoreConfig {
+                max_log_count: config.get_opt("indexedlog", "data.max-log-count").map_pyerr(py)?,
+                max_bytes_per_log: config.get_opt("indexedlog", "data.max-bytes-per-log").map_pyerr(py)?,
+                max_bytes: config.get_opt("remotefilelog", "cachelimit").map_pyerr(py)?,
+            };
+            (store_config, StoreType::Shared)
+        } else {
+            let store_config = IndexedLogHgIdDataStoreConfig { max_log_count: None, max_bytes_per_log: None, max_bytes: None };
+            (store_config, StoreType::Local)
+        };
         indexedlogdatastore::create_instance(
             py,
-            Box::new(IndexedLogHgIdDataStore::new(path.as_path(), ExtStoredPolicy::Ignore, &config, StoreType::Local).map_pyerr(py)?),
+            Arc::new(IndexedLogHgIdDataStore::new(path.as_path(), ExtStoredPolicy::Ignore, &store_config, store_type).map_pyerr(py)?),
+            autorefresh,
         )
     }
 
+    def add(&self, name: PyPathBuf, node: &PyBytes, deltabasenode: &PyBytes, delta: &PyBytes, metadata: Option<PyDict> = None) -> PyResult<PyObject> {
+        let store = self.store(py);
+        store.add_py(py, &name, node, deltabasenode, delta, metadata)
+    }
+
+    def flush(&self) -> PyResult<Option<Vec<PyPathBuf>>> {
+        let store = self.store(py);
+        store.flush_py(py)
+    }
+
     def getdelta(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyObject> {
         let store = self.store(py);
-        store.get_delta_py(py, name, node)
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_delta_py(py, name, node))
     }
 
     def getdeltachain(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyList> {
         let store = self.store(py);
-        store.get_delta_chain_py(py, name, node)
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_delta_chain_py(py, name, node))
     }
 
     def getmeta(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyDict> {
         let store = self.store(py);
-        store.get_meta_py(py, name, node)
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_meta_py(py, name, node))
     }
 
     def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
         let store = self.store(py);
-        store.get_missing_py(py, &mut keys.iter(py)?)
+        retry_keys_on_transient_error(py, *self.autorefresh(py), keys, || store.refresh_py(py), |keys| store.get_missing_py(py, keys))
     }
 
-    def markforrefresh(&self) -> PyResult<PyObject> {
+    /// Whether the data for this key is in the store. Only the indexes are consulted, the
+    /// content isn't read.
+    def contains(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<bool> {
         let store = self.store(py);
-        store.flush_py(py)?;
-        Ok(Python::None(py))
+        store.contains_py(py, &name, node)
     }
 
-    def iterentries(&self) -> PyResult<Vec<PyTuple>> {
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
+    }
+
+    /// Iterate over the `(name, node, deltabase, deltalen)` tuples of the entries.
+    def iterentries(&self) -> PyResult<entryiterator> {
+        let store = self.store(py).clone();
+        let keys = py.allow_threads(|| store.to_keys());
+        entryiterator::new(py, keys, move |py, key| store.entry_py(py, key))
+    }
+
+    /// Size statistics of the store.
+    def getstats(&self) -> PyResult<PyDict> {
         let store = self.store(py);
-        store.iter_py(py)
+        let stats = py.allow_threads(|| store.analyze()).map_pyerr(py)?;
+        stats_to_dict(py, &stats)
     }
 });
 
 py_class!(class indexedloghistorystore |py| {
-    data store: Box<IndexedLogHgIdHistoryStore>;
+    data store: Arc<IndexedLogHgIdHistoryStore>;
+    data autorefresh: bool;
 
-    def __new__(_cls, path: &PyPath, config: config) -> PyResult<indexedloghistorystore> {
+    def __new__(_cls, path: &PyPath, config: config, shared: bool = false, autorefresh: bool = false) -> PyResult<indexedloghistorystore> {
         let config = config.get_cfg(py);
+        let store_type = if shared { StoreType::Shared } else { StoreType::Local };
         indexedloghistorystore::create_instance(
             py,
-            Box::new(IndexedLogHgIdHistoryStore::new(path.as_path(), &config, StoreType::Local).map_pyerr(py)?),
+            Arc::new(IndexedLogHgIdHistoryStore::new(path.as_path(), &config, store_type).map_pyerr(py)?),
+            autorefresh,
         )
     }
 
+    def add(&self, name: PyPathBuf, node: &PyBytes, p1: &PyBytes, p2: &PyBytes, linknode: &PyBytes, copyfrom: Option<PyPathBuf>) -> PyResult<PyObject> {
+        let store = self.store(py);
+        store.add_py(py, &name, node, p1, p2, linknode, copyfrom.as_ref())
+    }
+
+    def flush(&self) -> PyResult<Option<Vec<PyPathBuf>>> {
+        let store = self.store(py);
+        store.flush_py(py)
+    }
+
     def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
         let store = self.store(py);
-        store.get_missing_py(py, &mut keys.iter(py)?)
+        retry_keys_on_transient_error(py, *self.autorefresh(py), keys, || store.refresh_py(py), |keys| store.get_missing_py(py, keys))
     }
 
     def getnodeinfo(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyTuple> {
         let store = self.store(py);
-        store.get_node_info_py(py, &name, node)
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_node_info_py(py, &name, node))
     }
 
-    def markforrefresh(&self) -> PyResult<PyObject> {
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
+    }
+
+    /// Iterate over the `(name, node, p1, p2, linknode, copyfrom)` tuples of the entries, only
+    /// those under `prefix` if given.
+    def iterentries(&self, prefix: Option<PyPathBuf> = None) -> PyResult<entryiterator> {
+        let store = self.store(py).clone();
+        let keys = store.keys_py(py, prefix.as_deref())?;
+        entryiterator::new(py, keys, move |py, key| store.entry_py(py, key))
+    }
+});
+
+/// Iterator over the entries of a store. All the keys are collected when the iterator is created,
+/// but an entry is only read once the iterator gets to it, so memory grows with the number of
+/// keys rather than with the size of the entries.
+py_class!(class entryiterator |py| {
+    data keys: RefCell<std::vec::IntoIter<Result<Key>>>;
+    data entry: Box<dyn Fn(Python, Key) -> Result<PyTuple> + Send>;
+
+    def __next__(&self) -> PyResult<Option<PyTuple>> {
+        let key = match self.keys(py).borrow_mut().next() {
+            Some(key) => key.map_pyerr(py)?,
+            None => return Ok(None),
+        };
+        (self.entry(py))(py, key).map_pyerr(py).map(Some)
+    }
+
+    def __iter__(&self) -> PyResult<entryiterator> {
+        Ok(self.clone_ref(py))
+    }
+});
+
+impl entryiterator {
+    fn new(
+        py: Python,
+        keys: Vec<Result<Key>>,
+        entry: impl Fn(Python, Key) -> Result<PyTuple> + Send + 'static,
+    ) -> PyResult<Self> {
+        entryiterator::create_instance(py, RefCell::new(keys.into_iter()), Box::new(entry))
+    }
+}
+
+py_class!(class unionhistorystore |py| {
+    data store: Box<UnionHgIdHistoryStore<Arc<dyn HgIdHistoryStore>>>;
+    data packpath: PathBuf;
+    data indexedlogpath: PathBuf;
+    data autorefresh: bool;
+
+    def __new__(
+        _cls,
+        packpath: PyPathBuf,
+        indexedlogpath: PyPathBuf,
+        config: config,
+        shared: bool = false,
+        deletecorruptpacks: bool = false,
+        maxbytes: Option<u64> = None,
+        autorefresh: bool = false
+    ) -> PyResult<unionhistorystore> {
+        let config = config.get_cfg(py);
+        let store_type = if shared { StoreType::Shared } else { StoreType::Local };
+        let corruption_policy = if deletecorruptpacks {
+            CorruptionPolicy::REMOVE
+        } else {
+            CorruptionPolicy::IGNORE
+        };
+
+        let indexedlog = IndexedLogHgIdHistoryStore::new(indexedlogpath.as_path(), &config, store_type).map_pyerr(py)?;
+        let packs = HistoryPackStore::new(packpath.as_path(), corruption_policy, maxbytes);
+
+        let mut store: UnionHgIdHistoryStore<Arc<dyn HgIdHistoryStore>> = UnionHgIdHistoryStore::new();
+        store.add(Arc::new(indexedlog));
+        store.add(Arc::new(packs));
+
+        unionhistorystore::create_instance(
+            py,
+            Box::new(store),
+            packpath.to_path_buf(),
+            indexedlogpath.to_path_buf(),
+            autorefresh,
+        )
+    }
+
+    def getnodeinfo(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyTuple> {
         let store = self.store(py);
-        store.flush_py(py)?;
-        Ok(Python::None(py))
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_node_info_py(py, &name, node))
     }
 
-    def iterentries(&self) -> PyResult<Vec<PyTuple>> {
+    def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
         let store = self.store(py);
-        store.iter_py(py)
+        retry_keys_on_transient_error(py, *self.autorefresh(py), keys, || store.refresh_py(py), |keys| store.get_missing_py(py, keys))
+    }
+
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
+    }
+
+    def getmetrics(&self) -> PyResult<PyDict> {
+        let (packsize, count) = match compute_store_size(self.packpath(py), vec!["histpack", "histidx"]) {
+            Ok((size, count)) => (size, count),
+            Err(_) => (0, 0),
+        };
+        let indexedlogsize = compute_store_size(self.indexedlogpath(py), vec![]).map_or(0, |(size, _)| size);
+
+        let res = PyDict::new(py);
+        res.set_item(py, "numpacks", count)?;
+        res.set_item(py, "totalpacksize", packsize)?;
+        res.set_item(py, "indexedlogsize", indexedlogsize)?;
+        res.set_item(py, "totalsize", packsize + indexedlogsize)?;
+        Ok(res)
     }
 });
 
@@ -586,6 +992,10 @@ py_class!(pub class mutabledeltastore |py| {
         let store = self.store(py);
         store.get_missing_py(py, &mut keys.iter(py)?)
     }
+
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
+    }
 });
 
 impl ExtractInnerRef for mutabledeltastore {
@@ -686,6 +1096,10 @@ py_class!(pub class mutablehistorystore |py| {
         let store = self.store(py);
         store.get_missing_py(py, &mut keys.iter(py)?)
     }
+
+    def markforrefresh(&self) -> PyResult<PyNone> {
+        self.store(py).refresh_py(py)
+    }
 });
 
 impl ExtractInnerRef for mutablehistorystore {
@@ -955,6 +1369,7 @@ impl ExtractInnerRef for edenapitreestore {
 
 py_class!(pub class contentstore |py| {
     data store: Arc<ContentStore>;
+    data autorefresh: bool;
 
     def __new__(_cls,
         path: Option<PyPathBuf>,
@@ -963,7 +1378,9 @@ py_class!(pub class contentstore |py| {
         memcache: Option<memcachestore>,
         edenapi: Option<edenapifilestore> = None,
         suffix: Option<String> = None,
-        correlator: Option<String> = None
+        correlator: Option<String> = None,
+        readonly: bool = false,
+        autorefresh: bool = false
     ) -> PyResult<contentstore> {
         let remotestore = remote.extract_inner(py);
         let config = config.get_cfg(py);
@@ -994,33 +1411,50 @@ py_class!(pub class contentstore |py| {
             builder
         };
 
+        if readonly {
+            builder = builder.read_only();
+        }
+
         let contentstore = builder.build().map_pyerr(py)?;
-        contentstore::create_instance(py, Arc::new(contentstore))
+        contentstore::create_instance(py, Arc::new(contentstore), autorefresh)
     }
 
     def get(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyBytes> {
         let store = self.store(py);
-        store.get_py(py, &name, node)
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_py(py, &name, node))
+    }
+
+    /// Like `get`, but without copying the data. See `datapack.getbuffer`.
+    def getbuffer(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<pybytes::Bytes> {
+        let store = self.store(py);
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_buffer_py(py, &name, node))
     }
 
     def getdelta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyObject> {
         let store = self.store(py);
-        store.get_delta_py(py, &name, node)
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_delta_py(py, &name, node))
     }
 
     def getdeltachain(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyList> {
         let store = self.store(py);
-        store.get_delta_chain_py(py, &name, node)
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_delta_chain_py(py, &name, node))
     }
 
     def getmeta(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyDict> {
         let store = self.store(py);
-        store.get_meta_py(py, &name, node)
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_meta_py(py, &name, node))
     }
 
     def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
         let store = self.store(py);
-        store.get_missing_py(py, &mut keys.iter(py)?)
+        retry_keys_on_transient_error(py, *self.autorefresh(py), keys, || store.refresh_py(py), |keys| store.get_missing_py(py, keys))
+    }
+
+    /// Whether the data for this key is in the store. Only the indexes are consulted, the
+    /// content isn't read.
+    def contains(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<bool> {
+        let store = self.store(py);
+        store.contains_py(py, &name, node)
     }
 
     def add(&self, name: PyPathBuf, node: &PyBytes, deltabasenode: &PyBytes, delta: &PyBytes, metadata: Option<PyDict> = None) -> PyResult<PyObject> {
@@ -1033,9 +1467,9 @@ py_class!(pub class contentstore |py| {
         store.flush_py(py)
     }
 
-    def prefetch(&self, keys: PyList) -> PyResult<PyObject> {
-        let store = self.store(py);
-        store.prefetch_py(py, keys)
+    def prefetch(&self, keys: PyList, priority: Option<String> = None) -> PyResult<PyObject> {
+        let store = self.store(py).clone();
+        prefetch_interruptible_py(py, store, keys, priority)
     }
 
     def markforrefresh(&self) -> PyResult<PyNone> {
@@ -1048,11 +1482,24 @@ py_class!(pub class contentstore |py| {
         store.upload_py(py, keys)
     }
 
+    /// Return the keys that the server does not confirm having. Local data must only be
+    /// dropped for keys that are not returned.
+    def getremotemissing(&self, keys: PyList) -> PyResult<PyList> {
+        let store = self.store(py);
+        store.get_remote_missing_py(py, keys)
+    }
+
     def blob(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyBytes> {
         let store = self.store(py);
         store.blob_py(py, name, node)
     }
 
+    /// Like `blob`, but without copying the data. See `datapack.getbuffer`.
+    def blobbuffer(&self, name: &PyPath, node: &PyBytes) -> PyResult<pybytes::Bytes> {
+        let store = self.store(py);
+        store.blob_buffer_py(py, name, node)
+    }
+
     def metadata(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyDict> {
         let store = self.store(py);
         store.metadata_py(py, name, node)
@@ -1079,6 +1526,7 @@ impl ExtractInnerRef for contentstore {
 
 py_class!(class metadatastore |py| {
     data store: Arc<MetadataStore>;
+    data autorefresh: bool;
 
     def __new__(_cls,
         path: Option<PyPathBuf>,
@@ -1086,7 +1534,9 @@ py_class!(class metadatastore |py| {
         remote: pyremotestore,
         memcache: Option<memcachestore>,
         edenapi: Option<edenapifilestore> = None,
-        suffix: Option<String> = None
+        suffix: Option<String> = None,
+        readonly: bool = false,
+        autorefresh: bool = false
     ) -> PyResult<metadatastore> {
         let remotestore = remote.extract_inner(py);
         let config = config.get_cfg(py);
@@ -1117,16 +1567,56 @@ py_class!(class metadatastore |py| {
             builder
         };
 
+        if readonly {
+            builder = builder.read_only();
+        }
+
         let metadatastore = Arc::new(builder.build().map_pyerr(py)?);
-        metadatastore::create_instance(py, metadatastore)
+        metadatastore::create_instance(py, metadatastore, autorefresh)
     }
 
     def getnodeinfo(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyTuple> {
-        self.store(py).get_node_info_py(py, &name, node)
+        let store = self.store(py);
+        retry_on_transient_error(py, *self.autorefresh(py), || store.refresh_py(py), || store.get_node_info_py(py, &name, node))
+    }
+
+    /// Look up the node info of several `(name, node)` keys at once. Returns a list in the same
+    /// order as `keys`, with `None` for the keys that weren't found.
+    def getnodeinfos(&self, keys: &PyObject) -> PyResult<PyList> {
+        let store = self.store(py);
+        retry_keys_on_transient_error(py, *self.autorefresh(py), keys, || store.refresh_py(py), |keys| store.get_node_info_batch_py(py, keys))
+    }
+
+    /// Look up only the linknodes of several `(name, node)` keys at once. Returns a dict mapping
+    /// the keys that were found to their linknode.
+    def getlinknodes(&self, keys: &PyObject) -> PyResult<PyDict> {
+        self.store(py).get_linknode_batch_py(py, &mut keys.iter(py)?)
+    }
+
+    /// Follow the renames of `(name, node)`, prefetching history as needed. Returns the list of
+    /// `(name, node)` keys starting with the given one, followed by each copy source. Tracing
+    /// stops at the first copy source whose path is in `stoppaths`.
+    def tracerename(&self, name: PyPathBuf, node: &PyBytes, stoppaths: Vec<PyPathBuf>) -> PyResult<PyList> {
+        let store = self.store(py);
+        let key = to_key(py, &name, node)?;
+        let stop_paths = stoppaths
+            .iter()
+            .map(|path| to_path(py, path))
+            .collect::<PyResult<HashSet<_>>>()?;
+        let chain = py
+            .allow_threads(|| store.trace_rename(&key, &stop_paths))
+            .map_pyerr(py)?;
+
+        let results = PyList::new(py, &[]);
+        for key in chain {
+            results.append(py, from_key_to_tuple(py, &key).into_object());
+        }
+        Ok(results)
     }
 
     def getmissing(&self, keys: &PyObject) -> PyResult<PyList> {
-        self.store(py).get_missing_py(py, &mut keys.iter(py)?)
+        let store = self.store(py);
+        retry_keys_on_transient_error(py, *self.autorefresh(py), keys, || store.refresh_py(py), |keys| store.get_missing_py(py, keys))
     }
 
     def add(&self, name: PyPathBuf, node: &PyBytes, p1: &PyBytes, p2: &PyBytes, linknode: &PyBytes, copyfrom: Option<PyPathBuf>) -> PyResult<PyObject> {
@@ -1169,8 +1659,23 @@ py_class!(pub class memcachestore |py| {
     def __new__(_cls, config: config) -> PyResult<memcachestore> {
         let config = config.get_cfg(py);
         let memcache = Arc::new(MemcacheStore::new(&config).map_pyerr(py)?);
+        memcache.configure_circuit_breaker(&config).map_pyerr(py)?;
         memcachestore::create_instance(py, memcache)
     }
+
+    /// Read and write statistics, and the state of the circuit breaker.
+    def getmetrics(&self) -> PyResult<PyDict> {
+        let metrics = self.memcache(py).metrics();
+        let dict = PyDict::new(py);
+        dict.set_item(py, "hits", metrics.hits)?;
+        dict.set_item(py, "misses", metrics.misses)?;
+        dict.set_item(py, "errors", metrics.errors)?;
+        dict.set_item(py, "writes", metrics.writes)?;
+        dict.set_item(py, "skipped", metrics.skipped)?;
+        dict.set_item(py, "trips", metrics.trips)?;
+        dict.set_item(py, "breakeropen", metrics.open)?;
+        Ok(dict)
+    }
 });
 
 impl ExtractInnerRef for memcachestore {
@@ -1292,12 +1797,14 @@ py_class!(pub class filescmstore |py| {
 
     def test_fetch(&self, path: PyPathBuf) -> PyResult<PyNone> {
         let keys: Vec<_> = block_on_stream(block_on(file_to_async_key_stream(path.to_path_buf())).map_pyerr(py)?).collect();
-        let fetch_result = self.store(py).fetch(keys.into_iter(), FileAttributes { content: true, aux_data: true } );
+        let store = self.store(py).clone();
+        let (found, missing, _errors) = py.allow_threads(|| {
+            store.fetch(keys.into_iter(), FileAttributes { content: true, aux_data: true }).consume()
+        });
 
         let io = IO::main().map_pyerr(py)?;
         let mut stdout = io.output();
 
-        let (found, missing, _errors) = fetch_result.consume();
 
         for (_, file) in found.into_iter() {
             write!(stdout, "Successfully fetched file: {:#?}\n", file).map_pyerr(py)?;
@@ -1309,15 +1816,57 @@ py_class!(pub class filescmstore |py| {
         Ok(PyNone)
     }
 
-    def fetch_contentsha256(&self, keys: PyList) -> PyResult<PyList> {
+    /// Fetch the content of `keys`, only from the stores allowed by `fetch_mode`: "local-only",
+    /// "allow-remote" (the default) or "remote-only". The complete keys are `(key, content)`
+    /// tuples.
+    def fetch(&self, keys: PyList, fetch_mode: Option<String> = None, cause: Option<String> = None) -> PyResult<fetchresults> {
         let keys = keys
             .iter(py)
             .map(|tuple| from_tuple_to_key(py, &tuple))
             .collect::<PyResult<Vec<Key>>>()?;
-        let results = PyList::new(py, &[]);
-        let fetch_result = self.store(py).fetch(keys.into_iter(), FileAttributes { content: false, aux_data: true} );
+        let mode = parse_fetch_mode(py, fetch_mode)?;
+        let cause = cause.map_or_else(FetchCause::unspecified, FetchCause::new);
+        let store = self.store(py).clone();
+        let (found, mut missing, errors) = py.allow_threads(|| {
+            store
+                .fetch_with_mode(
+                    keys.into_iter(),
+                    FileAttributes { content: true, aux_data: false },
+                    cause,
+                    CancellationToken::new(),
+                    FetchPriority::Interactive,
+                    mode,
+                )
+                .consume()
+        });
+        let complete = PyList::new(py, &[]);
+        for (key, mut storefile) in found.into_iter() {
+            match storefile.file_content() {
+                Ok(content) => {
+                    let key_tuple = from_key_to_tuple(py, &key).into_object();
+                    let content = PyBytes::new(py, content.as_ref());
+                    let result_tuple = PyTuple::new(py, &[key_tuple, content.into_object()]);
+                    complete.append(py, result_tuple.into_object());
+                }
+                Err(err) => {
+                    missing.insert(key, vec![err]);
+                }
+            }
+        }
+        fetchresults::from_results(py, complete, missing, errors)
+    }
 
-        let (found, missing, _errors) = fetch_result.consume();
+    def fetch_contentsha256(&self, keys: PyList, cause: Option<String> = None) -> PyResult<PyList> {
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let results = PyList::new(py, &[]);
+        let cause = cause.map_or_else(FetchCause::unspecified, FetchCause::new);
+        let store = self.store(py).clone();
+        let (found, missing, _errors) = py.allow_threads(|| {
+            store.fetch_with_cause(keys.into_iter(), FileAttributes { content: false, aux_data: true }, cause).consume()
+        });
         // TODO(meyer): FileStoreFetch should have utility methods to various consumer cases like this (get complete, get missing, transform to Result<EntireBatch>, transform to iterator of Result<IndividualFetch>, etc)
         // For now we just error with the first incomplete key, passing on the last recorded error if any are available.
         if let Some((key, mut errors)) = missing.into_iter().next() {
@@ -1343,6 +1892,35 @@ py_class!(pub class filescmstore |py| {
         Ok(results)
     }
 
+    /// Like `fetch_contentsha256`, but return a `fetchresults` with the errors of all the keys
+    /// which could not be fetched instead of raising the first one.
+    def fetch_contentsha256_results(&self, keys: PyList, cause: Option<String> = None) -> PyResult<fetchresults> {
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let cause = cause.map_or_else(FetchCause::unspecified, FetchCause::new);
+        let store = self.store(py).clone();
+        let (found, mut missing, errors) = py.allow_threads(|| {
+            store.fetch_with_cause(keys.into_iter(), FileAttributes { content: false, aux_data: true }, cause).consume()
+        });
+        let complete = PyList::new(py, &[]);
+        for (key, storefile) in found.into_iter() {
+            match storefile.aux_data() {
+                Ok(aux_data) => {
+                    let key_tuple = from_key_to_tuple(py, &key).into_object();
+                    let content_sha256 = PyBytes::new(py, &aux_data.content_sha256.into_inner());
+                    let result_tuple = PyTuple::new(py, &[key_tuple, content_sha256.into_object()]);
+                    complete.append(py, result_tuple.into_object());
+                }
+                Err(err) => {
+                    missing.insert(key, vec![err]);
+                }
+            }
+        }
+        fetchresults::from_results(py, complete, missing, errors)
+    }
+
     def get(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyBytes> {
         let store = self.store(py);
         store.get_py(py, &name, node)
@@ -1368,6 +1946,13 @@ py_class!(pub class filescmstore |py| {
         store.get_missing_py(py, &mut keys.iter(py)?)
     }
 
+    /// Whether the data for this key is in the store. Only the indexes are consulted, the
+    /// content isn't read.
+    def contains(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<bool> {
+        let store = self.store(py);
+        store.contains_py(py, &name, node)
+    }
+
     def add(&self, name: PyPathBuf, node: &PyBytes, deltabasenode: &PyBytes, delta: &PyBytes, metadata: Option<PyDict> = None) -> PyResult<PyObject> {
         let store = self.store(py);
         store.add_py(py, &name, node, deltabasenode, delta, metadata)
@@ -1378,9 +1963,39 @@ py_class!(pub class filescmstore |py| {
         store.flush_py(py)
     }
 
-    def prefetch(&self, keys: PyList) -> PyResult<PyObject> {
-        let store = self.store(py);
-        store.prefetch_py(py, keys)
+    /// Without `fetch_mode`, prefetch through the legacy stores. With it, fetch the content of
+    /// `keys` only from the stores it allows, like `fetch`, and raise the first error.
+    def prefetch(&self, keys: PyList, priority: Option<String> = None, fetch_mode: Option<String> = None) -> PyResult<PyObject> {
+        if fetch_mode.is_none() {
+            let store = self.store(py).clone();
+            return prefetch_interruptible_py(py, store, keys, priority);
+        }
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let priority = match priority {
+            Some(priority) => priority.parse::<FetchPriority>().map_pyerr(py)?,
+            None => FetchPriority::default(),
+        };
+        let mode = parse_fetch_mode(py, fetch_mode)?;
+        let store = self.store(py).clone();
+        py.allow_threads(|| -> Result<()> {
+            let fetch_result = store.fetch_with_mode(
+                keys.into_iter(),
+                FileAttributes { content: true, aux_data: false },
+                FetchCause::new("prefetch"),
+                CancellationToken::new(),
+                priority,
+                mode,
+            );
+            for result in fetch_result {
+                result?;
+            }
+            Ok(())
+        })
+        .map_pyerr(py)?;
+        Ok(Python::None(py))
     }
 
     def markforrefresh(&self) -> PyResult<PyNone> {
@@ -1388,11 +2003,22 @@ py_class!(pub class filescmstore |py| {
         store.refresh_py(py)
     }
 
+    /// Upload the LFS blobs of the given keys to the LFS server. Return the keys that were not
+    /// uploaded because they are not LFS files in the local store.
+    ///
+    /// Upload progress, in bytes, and per-object retries are reported on the progress bars.
     def upload(&self, keys: PyList) -> PyResult<PyList> {
         let store = self.store(py);
         store.upload_py(py, keys)
     }
 
+    /// Return the keys that the server does not confirm having. Local data must only be
+    /// dropped for keys that are not returned.
+    def getremotemissing(&self, keys: PyList) -> PyResult<PyList> {
+        let store = self.store(py);
+        store.get_remote_missing_py(py, keys)
+    }
+
     def blob(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyBytes> {
         let store = self.store(py);
         store.blob_py(py, name, node)
@@ -1421,10 +2047,82 @@ py_class!(pub class filescmstore |py| {
         }).collect::<Vec<PyTuple>>())
     }
 
+    /// Fetch counters (hits, misses, bytes and time spent per backend) as a dict.
+    def metrics(&self) -> PyResult<PyDict> {
+        let res = PyDict::new(py);
+        for (k, v) in self.store(py).metrics() {
+            res.set_item(py, k, v)?;
+        }
+        Ok(res)
+    }
+
     def getsharedmutable(&self) -> PyResult<mutabledeltastore> {
         let store = self.store(py);
         mutabledeltastore::create_instance(py, store.get_shared_mutable())
     }
+
+    /// Pin the (path, node) keys in the shared cache, so they are never evicted.
+    def pin(&self, keys: PyList) -> PyResult<PyNone> {
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let store = self.store(py);
+        py.allow_threads(|| store.pin(&keys)).map_pyerr(py)?;
+        Ok(PyNone)
+    }
+
+    /// Allow the (path, node) keys to be evicted from the shared cache again.
+    def unpin(&self, keys: PyList) -> PyResult<PyNone> {
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let store = self.store(py);
+        py.allow_threads(|| store.unpin(&keys)).map_pyerr(py)?;
+        Ok(PyNone)
+    }
+
+    /// Write the aux data of `keys`, a list of `(path, node)` tuples, to a sidecar file at
+    /// `path`. Returns the number of entries written.
+    def exportauxsidecar(&self, keys: PyList, path: &PyPath) -> PyResult<usize> {
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let store = self.store(py);
+        py.allow_threads(|| store.export_aux_sidecar(&keys, path.as_path())).map_pyerr(py)
+    }
+
+    /// Add the aux data of a sidecar file written by `exportauxsidecar`. Returns the number of
+    /// entries added.
+    def importauxsidecar(&self, path: &PyPath) -> PyResult<usize> {
+        let store = self.store(py);
+        py.allow_threads(|| store.import_aux_sidecar(path.as_path())).map_pyerr(py)
+    }
+
+    /// Estimate how similar the contents of two files are, from 0.0 to 1.0. The keys are
+    /// `(path, node)` tuples.
+    def similarity(&self, a: &PyObject, b: &PyObject) -> PyResult<f64> {
+        let a = from_tuple_to_key(py, a)?;
+        let b = from_tuple_to_key(py, b)?;
+        let store = self.store(py);
+        py.allow_threads(|| store.similarity(&a, &b)).map_pyerr(py)
+    }
+
+    /// Like `similarity`, for a list of `(key, key)` pairs. Pairs whose sizes rule out reaching
+    /// `threshold` score 0.0.
+    def similaritybatch(&self, pairs: PyList, threshold: f64 = 0.0) -> PyResult<Vec<f64>> {
+        let pairs = pairs
+            .iter(py)
+            .map(|pair| {
+                let (a, b) = <(PyObject, PyObject)>::extract(py, &pair)?;
+                Ok((from_tuple_to_key(py, &a)?, from_tuple_to_key(py, &b)?))
+            })
+            .collect::<PyResult<Vec<(Key, Key)>>>()?;
+        let store = self.store(py);
+        py.allow_threads(|| store.similarity_batch(&pairs, threshold)).map_pyerr(py)
+    }
 });
 
 impl ExtractInnerRef for filescmstore {
@@ -1545,12 +2243,14 @@ py_class!(pub class treescmstore |py| {
 
     def test_fetch(&self, path: PyPathBuf) -> PyResult<PyNone> {
         let keys: Vec<_> = block_on_stream(block_on(file_to_async_key_stream(path.to_path_buf())).map_pyerr(py)?).collect();
-        let fetch_result = self.store(py).fetch_batch(keys.into_iter()).map_pyerr(py)?;
+        let store = self.store(py).clone();
+        let (found, missing, _errors) = py.allow_threads(|| {
+            store.fetch_batch(keys.into_iter()).map(|fetch_result| fetch_result.consume())
+        }).map_pyerr(py)?;
 
         let io = IO::main().map_pyerr(py)?;
         let mut stdout = io.output();
 
-        let (found, missing, _errors) = fetch_result.consume();
         for complete in found.into_iter() {
             write!(stdout, "Successfully fetched tree: {:#?}\n", complete).map_pyerr(py)?;
         }
@@ -1561,6 +2261,97 @@ py_class!(pub class treescmstore |py| {
         Ok(PyNone)
     }
 
+    /// Fetch the content of `keys`, only from the stores allowed by `fetch_mode`: "local-only",
+    /// "allow-remote" (the default) or "remote-only". The complete keys are `(key, content)`
+    /// tuples.
+    def fetch(&self, keys: PyList, fetch_mode: Option<String> = None, cause: Option<String> = None) -> PyResult<fetchresults> {
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let mode = parse_fetch_mode(py, fetch_mode)?;
+        let cause = cause.map_or_else(FetchCause::unspecified, FetchCause::new);
+        let store = self.store(py).clone();
+        let (found, mut missing, errors) = py.allow_threads(|| {
+            store
+                .fetch_batch_with_mode(keys.into_iter(), TreeAttributes::CONTENT, cause, mode)
+                .map(|fetch_result| fetch_result.consume())
+        }).map_pyerr(py)?;
+        let complete = PyList::new(py, &[]);
+        for (key, mut storetree) in found.into_iter() {
+            match storetree.manifest_tree_entry() {
+                Ok(entry) => {
+                    let key_tuple = from_key_to_tuple(py, &key).into_object();
+                    let content = PyBytes::new(py, entry.0.as_ref());
+                    let result_tuple = PyTuple::new(py, &[key_tuple, content.into_object()]);
+                    complete.append(py, result_tuple.into_object());
+                }
+                Err(err) => {
+                    missing.insert(key, vec![err]);
+                }
+            }
+        }
+        fetchresults::from_results(py, complete, missing, errors)
+    }
+
+    /// Fetch the aux data of trees. The aux data of the file children of the trees is only listed
+    /// if `children` is True, which requires fetching the tree content.
+    def fetch_aux(&self, keys: PyList, cause: Option<String> = None, children: bool = false) -> PyResult<PyList> {
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let results = PyList::new(py, &[]);
+        let cause = cause.map_or_else(FetchCause::unspecified, FetchCause::new);
+        let attrs = if children {
+            TreeAttributes::AUX | TreeAttributes::CONTENT
+        } else {
+            TreeAttributes::AUX
+        };
+        let store = self.store(py).clone();
+        let (found, missing, _errors) = py.allow_threads(|| {
+            store
+                .fetch_batch_with_attrs(keys.into_iter(), attrs, cause)
+                .map(|fetch_result| fetch_result.consume())
+        }).map_pyerr(py)?;
+        if let Some((key, mut errors)) = missing.into_iter().next() {
+            if let Some(err) = errors.pop() {
+                return Err(err.context(format!("failed to fetch {}, received error", key))).map_pyerr(py);
+            } else {
+                return Err(format_err!("failed to fetch {}", key)).map_pyerr(py);
+            }
+        }
+        for (key, storetree) in found.into_iter() {
+            let key_tuple = from_key_to_tuple(py, &key).into_object();
+            let aux_data = storetree.aux_data().map_pyerr(py)?;
+            let child_list = PyList::new(py, &[]);
+            for (child_key, child_aux) in aux_data.child_metadata.iter() {
+                let child_tuple = PyTuple::new(
+                    py,
+                    &[
+                        from_key_to_tuple(py, child_key).into_object(),
+                        file_aux_to_dict(py, child_aux)?.into_object(),
+                    ],
+                );
+                child_list.append(py, child_tuple.into_object());
+            }
+            let aux_dict = PyDict::new(py);
+            aux_dict.set_item(py, "size", aux_data.total_size)?;
+            aux_dict.set_item(py, "sha256", PyBytes::new(py, aux_data.content_sha256.as_ref()))?;
+            aux_dict.set_item(py, "blake3", PyBytes::new(py, aux_data.content_blake3.as_ref()))?;
+            aux_dict.set_item(py, "children", child_list)?;
+            if let Some(aggregates) = aux_data.aggregates {
+                aux_dict.set_item(py, "entrycount", aggregates.entry_count)?;
+                aux_dict.set_item(py, "descendantfilecount", aggregates.descendant_file_count)?;
+                aux_dict.set_item(py, "descendantsize", aggregates.descendant_size)?;
+                aux_dict.set_item(py, "maxdepth", aggregates.max_depth)?;
+            }
+            let result_tuple = PyTuple::new(py, &[key_tuple, aux_dict.into_object()]);
+            results.append(py, result_tuple.into_object());
+        }
+        Ok(results)
+    }
+
     def get(&self, name: PyPathBuf, node: &PyBytes) -> PyResult<PyBytes> {
         let store = self.store(py);
         store.get_py(py, &name, node)
@@ -1596,9 +2387,28 @@ py_class!(pub class treescmstore |py| {
         store.flush_py(py)
     }
 
-    def prefetch(&self, keys: PyList) -> PyResult<PyObject> {
-        let store = self.store(py);
-        store.prefetch_py(py, keys)
+    /// Without `fetch_mode`, prefetch through the legacy stores. With it, fetch `keys` only
+    /// from the stores it allows, like `fetch`, and raise the first error.
+    def prefetch(&self, keys: PyList, fetch_mode: Option<String> = None) -> PyResult<PyObject> {
+        if fetch_mode.is_none() {
+            let store = self.store(py);
+            return store.prefetch_py(py, keys);
+        }
+        let keys = keys
+            .iter(py)
+            .map(|tuple| from_tuple_to_key(py, &tuple))
+            .collect::<PyResult<Vec<Key>>>()?;
+        let mode = parse_fetch_mode(py, fetch_mode)?;
+        let store = self.store(py).clone();
+        py.allow_threads(|| -> Result<()> {
+            let fetch_result = store.fetch_batch_with_mode(keys.into_iter(), TreeAttributes::CONTENT, FetchCause::new("prefetch"), mode)?;
+            for result in fetch_result {
+                result?;
+            }
+            Ok(())
+        })
+        .map_pyerr(py)?;
+        Ok(Python::None(py))
     }
 
     def markforrefresh(&self) -> PyResult<PyNone> {
@@ -1611,6 +2421,13 @@ py_class!(pub class treescmstore |py| {
         store.upload_py(py, keys)
     }
 
+    /// Return the keys that the server does not confirm having. Local data must only be
+    /// dropped for keys that are not returned.
+    def getremotemissing(&self, keys: PyList) -> PyResult<PyList> {
+        let store = self.store(py);
+        store.get_remote_missing_py(py, keys)
+    }
+
     def blob(&self, name: &PyPath, node: &PyBytes) -> PyResult<PyBytes> {
         let store = self.store(py);
         store.blob_py(py, name, node)
@@ -1627,6 +2444,15 @@ py_class!(pub class treescmstore |py| {
         Ok(Vec::new())
     }
 
+    /// Fetch counters (hits, misses, bytes and time spent per backend) as a dict.
+    def metrics(&self) -> PyResult<PyDict> {
+        let res = PyDict::new(py);
+        for (k, v) in self.store(py).metrics() {
+            res.set_item(py, k, v)?;
+        }
+        Ok(res)
+    }
+
     def getsharedmutable(&self) -> PyResult<mutabledeltastore> {
         let store = self.store(py);
         mutabledeltastore::create_instance(py, store.get_shared_mutable())
@@ -1640,3 +2466,92 @@ impl ExtractInnerRef for treescmstore {
         self.store(py)
     }
 }
+
+/// Results of a batch fetch. Unlike the fetch methods raising the first error, it keeps the
+/// errors of every key, so callers can retry or report them selectively.
+py_class!(pub class fetchresults |py| {
+    data completelist: PyList;
+    data incompletelist: PyList;
+    data errordict: PyDict;
+    data othererrorlist: PyList;
+
+    /// The fetched keys, as `(key, value)` tuples.
+    def complete(&self) -> PyResult<PyList> {
+        Ok(self.completelist(py).clone_ref(py))
+    }
+
+    /// The keys which could not be fetched.
+    def incomplete(&self) -> PyResult<PyList> {
+        Ok(self.incompletelist(py).clone_ref(py))
+    }
+
+    /// Map the incomplete keys to their errors, as `(kind, message)` tuples. The kind is one of
+    /// "network", "not-found", "corrupt", "lfs-pointer-missing" or "other". A key which was not
+    /// found in any store, without any other error, has a single "not-found" error.
+    def errors(&self) -> PyResult<PyDict> {
+        self.errordict(py).copy(py)
+    }
+
+    /// The errors which do not apply to a single key, as `(kind, message)` tuples.
+    def othererrors(&self) -> PyResult<PyList> {
+        Ok(self.othererrorlist(py).clone_ref(py))
+    }
+});
+
+impl fetchresults {
+    fn from_results(
+        py: Python,
+        complete: PyList,
+        missing: HashMap<Key, Vec<Error>>,
+        errors: Vec<Error>,
+    ) -> PyResult<Self> {
+        let incomplete = PyList::new(py, &[]);
+        let error_dict = PyDict::new(py);
+        for (key, key_errors) in missing.into_iter() {
+            let key_tuple = from_key_to_tuple(py, &key).into_object();
+            let key_errors = if key_errors.is_empty() {
+                vec![(
+                    FetchErrorKind::NotFound.name(),
+                    format!("{} not found", key),
+                )]
+            } else {
+                key_errors.iter().map(describe_fetch_error).collect()
+            };
+            incomplete.append(py, key_tuple.clone_ref(py));
+            error_dict.set_item(py, key_tuple, key_errors)?;
+        }
+        let other_errors = PyList::new(py, &[]);
+        for err in errors.iter() {
+            other_errors.append(py, describe_fetch_error(err).to_py_object(py).into_object());
+        }
+        fetchresults::create_instance(py, complete, incomplete, error_dict, other_errors)
+    }
+}
+
+fn describe_fetch_error(err: &Error) -> (&'static str, String) {
+    (FetchErrorKind::of(err).name(), format!("{:#}", err))
+}
+
+fn parse_fetch_mode(py: Python, fetch_mode: Option<String>) -> PyResult<FetchMode> {
+    match fetch_mode {
+        Some(fetch_mode) => fetch_mode.parse::<FetchMode>().map_pyerr(py),
+        None => Ok(FetchMode::default()),
+    }
+}
+
+fn file_aux_to_dict(py: Python, aux_data: &FileAuxData) -> PyResult<PyDict> {
+    let dict = PyDict::new(py);
+    dict.set_item(py, "size", aux_data.total_size)?;
+    dict.set_item(py, "sha1", PyBytes::new(py, aux_data.content_sha1.as_ref()))?;
+    dict.set_item(
+        py,
+        "sha256",
+        PyBytes::new(py, aux_data.content_sha256.as_ref()),
+    )?;
+    dict.set_item(
+        py,
+        "content_id",
+        PyBytes::new(py, aux_data.content_id.as_ref()),
+    )?;
+    Ok(dict)
+}
@@ -9,10 +9,13 @@ use std::sync::Arc;
 
 use cpython::exc;
 use cpython::FromPyObject;
+use cpython::ObjectProtocol;
 use cpython::PyBytes;
 use cpython::PyClone;
 use cpython::PyDict;
 use cpython::PyErr;
+use cpython::PyIterator;
+use cpython::PyList;
 use cpython::PyObject;
 use cpython::PyResult;
 use cpython::PyTuple;
@@ -21,6 +24,7 @@ use cpython::PythonObject;
 use cpython::PythonObjectWithCheckedDowncast;
 use cpython::ToPyObject;
 use cpython_ext::ExtractInner;
+use cpython_ext::PyNone;
 use cpython_ext::PyPath;
 use cpython_ext::PyPathBuf;
 use cpython_ext::ResultPyErrExt;
@@ -154,3 +158,131 @@ pub fn to_metadata(py: Python, meta: &PyDict) -> PyResult<Metadata> {
 pub fn key_error(py: Python, key: &StoreKey) -> PyErr {
     PyErr::new::<exc::KeyError, _>(py, format!("Key not found {:?}", key))
 }
+
+/// Whether `err` may go away after refreshing the store, such as a pack or log file that was
+/// removed by a concurrent repack. A missing key (`KeyError`) is not transient.
+fn is_transient(py: Python, err: &PyErr) -> bool {
+    err.matches(py, py.get_type::<exc::IOError>())
+}
+
+/// Run `fetch`. If it fails with a transient error and `autorefresh` is set, `refresh` the
+/// store and run `fetch` once more, to pick up the changes made by other processes.
+pub fn retry_on_transient_error<T>(
+    py: Python,
+    autorefresh: bool,
+    refresh: impl FnOnce() -> PyResult<PyNone>,
+    fetch: impl Fn() -> PyResult<T>,
+) -> PyResult<T> {
+    match fetch() {
+        Err(err) if autorefresh && is_transient(py, &err) => {
+            refresh()?;
+            fetch()
+        }
+        res => res,
+    }
+}
+
+/// Like `retry_on_transient_error`, for batch methods such as `getmissing`, whose `keys` may be
+/// a one-shot iterator.
+pub fn retry_keys_on_transient_error(
+    py: Python,
+    autorefresh: bool,
+    keys: &PyObject,
+    refresh: impl FnOnce() -> PyResult<PyNone>,
+    fetch: impl Fn(&mut PyIterator) -> PyResult<PyList>,
+) -> PyResult<PyList> {
+    if !autorefresh {
+        return fetch(&mut keys.iter(py)?);
+    }
+    let keys = keys.iter(py)?.collect::<PyResult<Vec<PyObject>>>()?;
+    let keys = PyList::new(py, &keys).into_object();
+    retry_on_transient_error(py, autorefresh, refresh, || fetch(&mut keys.iter(py)?))
+}
+
+// fbcode has a whitelist of python2 executables, not including tests here
+#[cfg(test)]
+#[cfg(not(all(fbcode_build, feature = "python2")))]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn retry(py: Python, autorefresh: bool, errors: &[PyErr]) -> (PyResult<usize>, usize) {
+        let refreshes = Cell::new(0);
+        let attempts = Cell::new(0);
+        let res = retry_on_transient_error(
+            py,
+            autorefresh,
+            || {
+                refreshes.set(refreshes.get() + 1);
+                Ok(PyNone)
+            },
+            || {
+                let attempt = attempts.get();
+                attempts.set(attempt + 1);
+                match errors.get(attempt) {
+                    Some(err) => Err(err.clone_ref(py)),
+                    None => Ok(attempt),
+                }
+            },
+        );
+        (res, refreshes.get())
+    }
+
+    #[test]
+    fn test_retry_on_transient_error() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let io_error = || PyErr::new::<exc::IOError, _>(py, "pack removed");
+        let key_error = || PyErr::new::<exc::KeyError, _>(py, "key not found");
+
+        // Success, nothing to retry.
+        let (res, refreshes) = retry(py, true, &[]);
+        assert_eq!(res.unwrap(), 0);
+        assert_eq!(refreshes, 0);
+
+        // A transient error is retried once after a refresh.
+        let (res, refreshes) = retry(py, true, &[io_error()]);
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(refreshes, 1);
+        let (res, refreshes) = retry(py, true, &[io_error(), io_error()]);
+        assert!(res.unwrap_err().matches(py, py.get_type::<exc::IOError>()));
+        assert_eq!(refreshes, 1);
+
+        // A missing key is not retried.
+        let (res, refreshes) = retry(py, true, &[key_error()]);
+        assert!(res.unwrap_err().matches(py, py.get_type::<exc::KeyError>()));
+        assert_eq!(refreshes, 0);
+
+        // Nothing is retried without autorefresh.
+        let (res, refreshes) = retry(py, false, &[io_error()]);
+        assert!(res.is_err());
+        assert_eq!(refreshes, 0);
+    }
+
+    #[test]
+    fn test_retry_keys_on_transient_error() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let keys = py.eval("iter([1, 2, 3])", None, None).unwrap();
+        let attempts = Cell::new(0);
+        let missing = retry_keys_on_transient_error(
+            py,
+            true,
+            &keys,
+            || Ok(PyNone),
+            |keys| {
+                attempts.set(attempts.get() + 1);
+                let keys = keys.collect::<PyResult<Vec<_>>>()?;
+                if attempts.get() == 1 {
+                    return Err(PyErr::new::<exc::IOError, _>(py, "pack removed"));
+                }
+                Ok(PyList::new(py, &keys))
+            },
+        )
+        .unwrap();
+        // The keys are still available on retry, even though they came from an iterator.
+        assert_eq!(attempts.get(), 2);
+        assert_eq!(missing.len(py), 3);
+    }
+}
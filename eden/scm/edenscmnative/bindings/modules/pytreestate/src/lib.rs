@@ -32,6 +32,7 @@ use ::treestate::store::BlockId;
 use ::treestate::tree::KeyRef;
 use ::treestate::tree::VisitorResult;
 use ::treestate::treedirstate::TreeDirstate;
+use ::treestate::treestate::CompactionPolicy;
 use ::treestate::treestate::TreeState;
 use anyhow::Error;
 use cpython::*;
@@ -60,6 +61,11 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
     m.add(py, "NEED_CHECK", StateFlags::NEED_CHECK.to_bits())?;
     m.add(py, "COPIED", StateFlags::COPIED.to_bits())?;
     m.add(py, "tohgstate", py_fn!(py, flags_to_hg_state(flags: u16)))?;
+    m.add(
+        py,
+        "compactionthreshold",
+        py_fn!(py, compaction_threshold(live_size: u64, min_size: u64, factor: u64)),
+    )?;
     Ok(m)
 }
 
@@ -479,6 +485,15 @@ py_class!(pub class treestate |py| {
         Ok(root_id.0)
     }
 
+    def compact(&self, path: &PyPath) -> PyResult<(u64, u64, u64)> {
+        // Save the live entries as a new file, dropping the garbage of the current one. Return
+        // `(root_id, old_size, new_size)`.
+        let mut option = self.state(py).lock();
+        let state = option.as_mut().expect("TreeState is never taken outside of lock");
+        let (root_id, stats) = convert_result(py, state.compact(path))?;
+        Ok((root_id.0, stats.old_size, stats.new_size))
+    }
+
     def __len__(&self) -> PyResult<usize> {
         let mut option = self.state(py).lock();
         let state = option.as_mut().expect("TreeState is never taken outside of lock");
@@ -807,6 +822,11 @@ fn flags_to_hg_state(_py: Python, flags: u16) -> PyResult<&'static str> {
     )
 }
 
+/// Size past which a treestate file should be compacted, see `CompactionPolicy`
+fn compaction_threshold(_py: Python, live_size: u64, min_size: u64, factor: u64) -> PyResult<u64> {
+    Ok(CompactionPolicy { min_size, factor }.threshold(live_size))
+}
+
 /// Convert a Result to PyResult
 fn convert_result<T>(py: Python, result: Result<T>) -> PyResult<T> {
     result.map_pyerr(py)
@@ -8,6 +8,7 @@
 #![allow(non_camel_case_types)]
 
 use std::cell::RefCell;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::anyhow;
@@ -23,7 +24,10 @@ use pypathmatcher::extract_matcher;
 use pypathmatcher::extract_option_matcher;
 use pytreestate::treestate;
 use storemodel::ReadFileContents;
+use workingcopy::comparison::ArcReadFileAuxData;
+use workingcopy::comparison::ComparisonStrategy;
 use workingcopy::walker::WalkError;
+use workingcopy::walker::WalkProgress;
 use workingcopy::walker::Walker;
 
 type ArcReadFileContents = Arc<dyn ReadFileContents<Error = anyhow::Error> + Send + Sync>;
@@ -39,9 +43,12 @@ pub fn init_module(py: Python, package: &str) -> PyResult<PyModule> {
 py_class!(class walker |py| {
     data inner: RefCell<Walker<Arc<dyn Matcher + Sync + Send>>>;
     data _errors: RefCell<Vec<Error>>;
-    def __new__(_cls, root: PyPathBuf, pymatcher: PyObject, include_directories: bool, thread_count: u8) -> PyResult<walker> {
+    def __new__(_cls, root: PyPathBuf, pymatcher: PyObject, include_directories: bool, thread_count: usize) -> PyResult<walker> {
         let matcher = extract_matcher(py, pymatcher)?;
-        let walker = Walker::new(root.to_path_buf(), matcher, include_directories, thread_count).map_pyerr(py)?;
+        let thread_count = clamp_thread_count(thread_count);
+        let walker = Walker::new(root.to_path_buf(), matcher, include_directories, thread_count)
+            .map_pyerr(py)?
+            .with_progress(WalkProgress::register_new());
         walker::create_instance(py, RefCell::new(walker), RefCell::new(Vec::new()))
     }
 
@@ -72,6 +79,7 @@ py_class!(class status |py| {
     @staticmethod
     def status(
         pyroot: PyPathBuf,
+        pydotdir: PyPathBuf,
         pymanifest: treemanifest,
         pystore: ImplInto<ArcReadFileContents>,
         pytreestate: treestate,
@@ -79,11 +87,25 @@ py_class!(class status |py| {
         pymatcher: Option<PyObject>,
         listunknown: bool,
         filesystem: &str,
+        comparison: &str = "content",
+        auxstore: Option<ImplInto<ArcReadFileAuxData>> = None,
+        threadcount: usize = 8,
+        mtimeracewindow: u64 = 0,
+        listignored: bool = false,
+        ignorefiles: Vec<PyPathBuf> = Vec::new(),
+        opaquenestedrepos: bool = false,
+        edenprefetchunloaded: bool = false,
     ) -> PyResult<PyObject> {
         let root = pyroot.to_path_buf();
+        let dot_dir = pydotdir.to_path_buf();
+        let threadcount = clamp_thread_count(threadcount);
         let manifest = pymanifest.get_underlying(py);
         let store = pystore.into();
+        let comparison = ComparisonStrategy::from_str(comparison)
+            .and_then(|strategy| strategy.build(store, auxstore.map(|s| s.into())))
+            .map_pyerr(py)?;
         let last_write = last_write.into();
+        let ignore_files = ignorefiles.iter().map(|p| p.to_path_buf()).collect();
         let matcher = extract_option_matcher(py, pymatcher)?;
         let filesystem = match filesystem {
             "normal" => {
@@ -104,13 +126,21 @@ py_class!(class status |py| {
 
         let (treestate, status) = py.allow_threads(|| workingcopy::status::status(
             root,
+            dot_dir,
             filesystem,
             manifest,
-            store,
+            comparison,
             treestate,
             last_write,
             matcher,
             listunknown,
+            mtimeracewindow,
+            threadcount,
+            ignore_files,
+            listignored,
+            opaquenestedrepos,
+            edenprefetchunloaded,
+            Some(WalkProgress::register_new()),
         ));
 
         option.replace(treestate);
@@ -118,3 +148,8 @@ py_class!(class status |py| {
         pystatus::to_python_status(py, &status)
     }
 });
+
+/// Limit the configured walker thread count to what the walker supports.
+fn clamp_thread_count(thread_count: usize) -> u8 {
+    u8::try_from(thread_count).unwrap_or(u8::MAX)
+}
@@ -27,6 +27,7 @@ use treestate::dirstate::Dirstate;
 use treestate::dirstate::TreeStateFields;
 use treestate::metadata::Metadata;
 use treestate::serialization::Serializable;
+use treestate::treestate::CompactionPolicy;
 use treestate::treestate::TreeState;
 use types::hgid::NULL_ID;
 use types::HgId;
@@ -255,15 +256,15 @@ pub fn flush_dirstate(
         .file_name()
         .ok_or_else(|| anyhow!("bad treestate path: {:?}", ts.path()))?;
 
-    let mut threshold = 0;
-    let min_repack_threshold = config
-        .get_or_default::<ByteCount>("treestate", "minrepackthreshold")?
-        .value();
-    if tree_root_id.0 > min_repack_threshold {
-        if let Some(factor) = config.get_nonempty_opt::<u64>("treestate", "repackfactor")? {
-            threshold = tree_root_id.0 * factor;
-        }
-    }
+    let policy = CompactionPolicy {
+        min_size: config
+            .get_or_default::<ByteCount>("treestate", "minrepackthreshold")?
+            .value(),
+        factor: config
+            .get_nonempty_opt::<u64>("treestate", "repackfactor")?
+            .unwrap_or(0),
+    };
+    let threshold = policy.threshold(tree_root_id.0);
     let ds = Dirstate {
         p0: target,
         p1: NULL_ID,
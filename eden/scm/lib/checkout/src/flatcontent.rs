@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Flat files holding the content of working copy files.
+//!
+//! On filesystems that support cloning files (reflinks), checkout writes the content of each file
+//! once into this store, and clones it into the working copy. Clones share their blocks on disk
+//! until one of them is modified, so checking out the same content again, or in several places,
+//! doesn't copy any data.
+
+use std::fs;
+use std::fs::create_dir_all;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use types::HgId;
+
+/// Name of the store directory, inside the `.hg` directory so that it lives on the same
+/// filesystem as the working copy.
+pub(crate) const FLAT_CONTENT_DIR: &str = "flatcontent";
+
+/// Default limit of the total size of the flat files.
+pub(crate) const DEFAULT_MAX_BYTES: u64 = 1 << 30;
+
+const PROBE_NAME: &str = "probe";
+
+pub(crate) struct FlatContentStore {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl FlatContentStore {
+    pub(crate) fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self { dir, max_bytes }
+    }
+
+    /// Whether files of the store can be cloned. Filesystems that may support cloning can still
+    /// have it disabled, for example XFS without reflinks. Since clone failures fall back to
+    /// writing the content, the content would be written twice for every file.
+    pub(crate) fn can_clone(&self) -> bool {
+        let source = self.dir.join(PROBE_NAME);
+        let dest = self.dir.join(format!("{}-clone", PROBE_NAME));
+        let result = create_dir_all(&self.dir)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(fs::write(&source, b"probe")?))
+            .and_then(|_| vfs::clone_file(&source, &dest));
+        let _ = fs::remove_file(&source);
+        let _ = fs::remove_file(&dest);
+        result.is_ok()
+    }
+
+    /// Path of the flat file holding the content of `hgid`, writing `content` into it first if
+    /// it doesn't exist yet.
+    pub(crate) fn get_or_write(&self, hgid: &HgId, content: &[u8]) -> Result<PathBuf> {
+        let path = self.dir.join(hgid.to_hex());
+        if !path.exists() {
+            create_dir_all(&self.dir)?;
+            util::file::atomic_write(&path, |f| f.write_all(content))?;
+        }
+        Ok(path)
+    }
+
+    /// Remove the oldest flat files until their total size is within the limit. Clones don't
+    /// depend on their source, so removing a flat file only means its content will be written
+    /// again by the next checkout needing it.
+    pub(crate) fn prune(&self) -> Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                files.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        // Newest first.
+        files.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        let mut total = 0;
+        for (_, len, path) in files {
+            total += len;
+            if total > self.max_bytes {
+                match fs::remove_file(&path) {
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use filetime::set_file_mtime;
+    use filetime::FileTime;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_get_or_write() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = FlatContentStore::new(tempdir.path().join(FLAT_CONTENT_DIR), 100);
+        let hgid = HgId::from_byte_array([1; HgId::len()]);
+
+        let path = store.get_or_write(&hgid, b"content")?;
+        assert_eq!(std::fs::read(&path)?, b"content");
+        assert_eq!(store.get_or_write(&hgid, b"content")?, path);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = FlatContentStore::new(tempdir.path().join(FLAT_CONTENT_DIR), 10);
+        let old = store.get_or_write(&HgId::from_byte_array([1; HgId::len()]), b"old")?;
+        let middle = store.get_or_write(&HgId::from_byte_array([2; HgId::len()]), b"middle")?;
+        // Make sure the modification times differ.
+        set_file_mtime(&old, FileTime::from_unix_time(1000, 0))?;
+        set_file_mtime(&middle, FileTime::from_unix_time(2000, 0))?;
+        let new = store.get_or_write(&HgId::from_byte_array([3; HgId::len()]), b"new")?;
+
+        store.prune()?;
+        assert!(new.exists());
+        assert!(middle.exists());
+        assert!(!old.exists());
+
+        // Nothing to do without a store directory.
+        FlatContentStore::new(tempdir.path().join("missing"), 0).prune()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_can_clone() {
+        let tempdir = TempDir::new().unwrap();
+        let store = FlatContentStore::new(tempdir.path().join(FLAT_CONTENT_DIR), 0);
+        // The result depends on the filesystem the test runs on, but the probe leaves nothing.
+        store.can_clone();
+        assert_eq!(fs::read_dir(&store.dir).unwrap().count(), 0);
+    }
+}
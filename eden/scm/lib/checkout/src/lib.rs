@@ -54,16 +54,24 @@ mod actions;
 pub mod clone;
 #[allow(dead_code)]
 mod conflict;
+mod flatcontent;
 #[allow(dead_code)]
 mod merge;
+mod pathconflict;
 
 pub use actions::Action;
 pub use actions::ActionMap;
+use configmodel::convert::ByteCount;
 use configmodel::Config;
 use configmodel::ConfigExt;
 pub use conflict::Conflict;
+use flatcontent::FlatContentStore;
+use flatcontent::FLAT_CONTENT_DIR;
 pub use merge::Merge;
 pub use merge::MergeResult;
+pub use pathconflict::ConflictPolicy;
+pub use pathconflict::PathConflict;
+pub use pathconflict::PathConflictError;
 use status::FileStatus;
 use status::Status;
 use tokio::runtime::Handle;
@@ -127,6 +135,17 @@ const MAX_CHECK_UNKNOWN: usize = 5000;
 pub struct Checkout {
     vfs: VFS,
     concurrency: usize,
+    materialize: Materialize,
+}
+
+/// How the content of updated files is put into the working copy.
+#[derive(Clone)]
+enum Materialize {
+    /// Write the content into each file.
+    Write,
+    /// Clone each file from a flat file holding its content, falling back to writing the content
+    /// when cloning fails.
+    Clone(Arc<FlatContentStore>),
 }
 
 impl Checkout {
@@ -134,6 +153,7 @@ impl Checkout {
         Self {
             vfs,
             concurrency: DEFAULT_CONCURRENCY,
+            materialize: Materialize::Write,
         }
     }
 
@@ -142,7 +162,34 @@ impl Checkout {
             .get_opt("nativecheckout", "concurrency")
             .map_err(|e| format_err!("Failed to parse nativecheckout.concurrency: {}", e))?;
         let concurrency = concurrency.unwrap_or(DEFAULT_CONCURRENCY);
-        Ok(Self { vfs, concurrency })
+        let use_reflink: bool = config
+            .get_or_default("nativecheckout", "use-reflink")
+            .map_err(|e| format_err!("Failed to parse nativecheckout.use-reflink: {}", e))?;
+        let max_bytes: Option<ByteCount> = config
+            .get_opt("nativecheckout", "flatcontent-max-bytes")
+            .map_err(|e| {
+                format_err!(
+                    "Failed to parse nativecheckout.flatcontent-max-bytes: {}",
+                    e
+                )
+            })?;
+        let materialize = if use_reflink && vfs.supports_clone() {
+            let dir = vfs.root().join(".hg").join(FLAT_CONTENT_DIR);
+            let max_bytes = max_bytes.map_or(flatcontent::DEFAULT_MAX_BYTES, |b| b.value());
+            let flat = FlatContentStore::new(dir, max_bytes);
+            if flat.can_clone() {
+                Materialize::Clone(Arc::new(flat))
+            } else {
+                Materialize::Write
+            }
+        } else {
+            Materialize::Write
+        };
+        Ok(Self {
+            vfs,
+            concurrency,
+            materialize,
+        })
     }
 
     pub fn plan_action_map(&self, map: ActionMap) -> CheckoutPlan {
@@ -252,11 +299,20 @@ impl CheckoutPlan {
         });
 
         let progress_ref = self.progress.as_ref();
+        let materialize = &self.checkout.materialize;
         let update_content = update_content
             .chunks(VFS_BATCH_SIZE)
             .map(|actions| async move {
                 let actions: Result<Vec<_>, _> = actions.into_iter().collect();
-                Self::write_files(async_vfs, stats_ref, actions?, progress_ref, bar).await
+                Self::write_files(
+                    async_vfs,
+                    stats_ref,
+                    actions?,
+                    materialize,
+                    progress_ref,
+                    bar,
+                )
+                .await
             });
 
         let update_content = update_content.buffer_unordered(self.checkout.concurrency);
@@ -271,6 +327,16 @@ impl CheckoutPlan {
 
         try_join!(update_content, update_meta)?;
 
+        if let Materialize::Clone(flat) = &self.checkout.materialize {
+            let flat = flat.clone();
+            let pruned = Handle::current()
+                .spawn_blocking(move || flat.prune())
+                .await?;
+            if let Err(err) = pruned {
+                debug!("Can't prune flat content: {:?}", err);
+            }
+        }
+
         Ok(stats)
     }
 
@@ -449,6 +515,7 @@ impl CheckoutPlan {
         async_vfs: &AsyncVfsWriter,
         stats: &CheckoutStats,
         actions: Vec<(RepoPathBuf, HgId, Bytes, UpdateFlag)>,
+        materialize: &Materialize,
         progress: Option<&Mutex<CheckoutProgress>>,
         bar: &Arc<ProgressBar>,
     ) -> Result<()> {
@@ -465,10 +532,15 @@ impl CheckoutPlan {
             .iter()
             .map(|(path, hgid, _, _)| (hgid.clone(), path.as_repo_path().to_owned()))
             .collect();
-        let actions = actions
-            .into_iter()
-            .map(|(path, _, content, flag)| (path, content, flag));
-        let w = async_vfs.write_batch(actions).await?;
+        let w = match materialize {
+            Materialize::Write => {
+                let actions = actions
+                    .into_iter()
+                    .map(|(path, _, content, flag)| (path, content, flag));
+                async_vfs.write_batch(actions).await?
+            }
+            Materialize::Clone(flat) => Self::clone_files(async_vfs, flat.clone(), actions).await?,
+        };
         stats.updated.fetch_add(count, Ordering::Relaxed);
         stats.written_bytes.fetch_add(w, Ordering::Relaxed);
 
@@ -481,6 +553,44 @@ impl CheckoutPlan {
         Ok(())
     }
 
+    /// Clone the files from their flat content file. Symlinks, and files whose flat content file
+    /// can't be written, are written directly.
+    async fn clone_files(
+        async_vfs: &AsyncVfsWriter,
+        flat: Arc<FlatContentStore>,
+        actions: Vec<(RepoPathBuf, HgId, Bytes, UpdateFlag)>,
+    ) -> Result<usize> {
+        let (clones, writes) = Handle::current()
+            .spawn_blocking(move || {
+                let mut clones = vec![];
+                let mut writes = vec![];
+                for (path, hgid, content, flag) in actions {
+                    if matches!(flag, UpdateFlag::Symlink) {
+                        writes.push((path, content, flag));
+                        continue;
+                    }
+                    match flat.get_or_write(&hgid, &content) {
+                        Ok(source) => clones.push((path, source, content, flag)),
+                        Err(err) => {
+                            debug!("Can't write flat content of {}: {:?}", path, err);
+                            writes.push((path, content, flag));
+                        }
+                    }
+                }
+                (clones, writes)
+            })
+            .await?;
+
+        let mut written = 0;
+        if !clones.is_empty() {
+            written += async_vfs.clone_batch(clones).await?;
+        }
+        if !writes.is_empty() {
+            written += async_vfs.write_batch(writes).await?;
+        }
+        Ok(written)
+    }
+
     async fn remove_files(
         async_vfs: &AsyncVfsWriter,
         stats: &CheckoutStats,
@@ -785,6 +895,47 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_clone_checkout() -> Result<()> {
+        let from = [(rp("A"), FileMetadata::regular(hgid(1)))];
+        let to = [
+            (rp("A"), FileMetadata::regular(hgid(2))),
+            (rp("B"), FileMetadata::executable(hgid(2))),
+            (rp("C"), FileMetadata::symlink(hgid(3))),
+        ];
+
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        roll_out_fs(&vfs, &from)?;
+
+        let store = Arc::new(TestStore::new());
+        let matcher = AlwaysMatcher::new();
+        let left_tree = make_tree_manifest_from_meta(store.clone(), from.iter().cloned());
+        let right_tree = make_tree_manifest_from_meta(store, to.iter().cloned());
+        let diff = Diff::new(&left_tree, &right_tree, &matcher)?;
+        let flat = FlatContentStore::new(
+            tempdir.path().join(FLAT_CONTENT_DIR),
+            flatcontent::DEFAULT_MAX_BYTES,
+        );
+        let checkout = Checkout {
+            materialize: Materialize::Clone(Arc::new(flat)),
+            ..Checkout::default_config(vfs)
+        };
+        let plan = checkout.plan_action_map(ActionMap::from_diff(diff)?);
+        plan.apply_store(&DummyFileContentStore).await?;
+
+        // Files are written instead if the filesystem can't clone them.
+        assert_fs(&working_path, &to)?;
+        assert!(tempdir
+            .path()
+            .join(FLAT_CONTENT_DIR)
+            .join(hgid(2).to_hex())
+            .exists());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_checkout_generated() -> Result<()> {
         let trees = generate_trees(6, 50);
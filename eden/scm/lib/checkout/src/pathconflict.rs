@@ -0,0 +1,337 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Local changes that would be lost by applying a `CheckoutPlan`.
+//!
+//! The conflicts are found before the working copy is touched, then handled according to a
+//! `ConflictPolicy` chosen by the caller.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use manifest::Manifest;
+use status::Status;
+use storemodel::ReadFileContents;
+use thiserror::Error;
+use treestate::treestate::TreeState;
+use types::RepoPath;
+use types::RepoPathBuf;
+
+use crate::CheckoutPlan;
+
+/// Suffix of the copies of the conflicting files made by `ConflictPolicy::Backup`.
+const BACKUP_SUFFIX: &str = ".orig";
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum PathConflict {
+    /// A modified tracked file would be overwritten or removed.
+    Modified(RepoPathBuf),
+    /// An untracked file differs from the file to be written at its path.
+    Untracked(RepoPathBuf),
+    /// An untracked file is where the plan needs to create a directory.
+    UntrackedInPlaceOfDirectory(RepoPathBuf),
+}
+
+impl PathConflict {
+    pub fn path(&self) -> &RepoPath {
+        match self {
+            PathConflict::Modified(path)
+            | PathConflict::Untracked(path)
+            | PathConflict::UntrackedInPlaceOfDirectory(path) => path,
+        }
+    }
+
+    /// Name of the kind of conflict, used to pass conflicts through the Python bindings.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PathConflict::Modified(_) => "modified",
+            PathConflict::Untracked(_) => "untracked",
+            PathConflict::UntrackedInPlaceOfDirectory(_) => "untracked-directory",
+        }
+    }
+
+    /// Inverse of `kind`.
+    pub fn from_kind(kind: &str, path: RepoPathBuf) -> Result<Self> {
+        Ok(match kind {
+            "modified" => PathConflict::Modified(path),
+            "untracked" => PathConflict::Untracked(path),
+            "untracked-directory" => PathConflict::UntrackedInPlaceOfDirectory(path),
+            _ => bail!("unknown path conflict kind: {}", kind),
+        })
+    }
+}
+
+impl fmt::Display for PathConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathConflict::Modified(path) => {
+                write!(f, "{}: modified file would be overwritten", path)
+            }
+            PathConflict::Untracked(path) => write!(f, "{}: untracked file differs", path),
+            PathConflict::UntrackedInPlaceOfDirectory(path) => {
+                write!(f, "{}: untracked file is in the way of a directory", path)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("{} conflicting paths in the working copy", .0.len())]
+pub struct PathConflictError(pub Vec<PathConflict>);
+
+/// What to do with the conflicting paths before applying a plan.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    /// Fail with a `PathConflictError`.
+    Abort,
+    /// Back up the conflicting files to `<path>.orig`, then let the plan overwrite them.
+    Backup,
+    /// Back up the untracked files, unless there are modified files, which are then left for the
+    /// caller to merge.
+    Merge,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "abort" => ConflictPolicy::Abort,
+            "backup" => ConflictPolicy::Backup,
+            "merge" => ConflictPolicy::Merge,
+            _ => bail!("unknown conflict policy: {}", s),
+        })
+    }
+}
+
+impl CheckoutPlan {
+    /// Find the local changes that applying the plan would lose, without touching the working
+    /// copy. Conflicts are sorted by path.
+    pub async fn check_path_conflicts(
+        &self,
+        manifest: &impl Manifest,
+        store: &dyn ReadFileContents<Error = anyhow::Error>,
+        tree_state: &mut TreeState,
+        status: &Status,
+    ) -> Result<Vec<PathConflict>> {
+        let mut conflicts: Vec<_> = self
+            .check_conflicts(status)
+            .into_iter()
+            .map(|path| PathConflict::Modified(path.to_owned()))
+            .collect();
+        let unknown = self
+            .check_unknown_files(manifest, store, tree_state, status)
+            .await?;
+        conflicts.extend(unknown.into_iter().map(PathConflict::Untracked));
+        conflicts.extend(self.check_directory_conflicts());
+        conflicts.sort();
+        conflicts.dedup();
+        Ok(conflicts)
+    }
+
+    /// Find the files that are in the place of a directory the plan creates, and that the plan
+    /// does not remove.
+    fn check_directory_conflicts(&self) -> Vec<PathConflict> {
+        let vfs = &self.checkout.vfs;
+        let removed: HashSet<&RepoPath> = self.remove.iter().map(|p| p.as_repo_path()).collect();
+        let mut checked: HashSet<&RepoPath> = HashSet::new();
+        let mut conflicts = vec![];
+        for action in self.new_file_actions() {
+            // Skip the repo root.
+            for dir in action.path.parents().skip(1) {
+                if !checked.insert(dir) || removed.contains(dir) {
+                    continue;
+                }
+                match vfs.metadata(dir) {
+                    Ok(metadata) if metadata.is_dir() => {}
+                    Ok(_) => {
+                        conflicts.push(PathConflict::UntrackedInPlaceOfDirectory(dir.to_owned()));
+                        break;
+                    }
+                    // Nothing under a missing directory can conflict.
+                    Err(_) => break,
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Prepare the working copy for applying the plan, according to `policy`. Returns the
+    /// modified files left for the caller to merge with `ConflictPolicy::Merge`: when it is not
+    /// empty, nothing was backed up and the plan must not be applied.
+    pub fn resolve_path_conflicts(
+        &self,
+        conflicts: Vec<PathConflict>,
+        policy: ConflictPolicy,
+    ) -> Result<Vec<RepoPathBuf>> {
+        if conflicts.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut to_merge = vec![];
+        match policy {
+            ConflictPolicy::Abort => return Err(PathConflictError(conflicts).into()),
+            ConflictPolicy::Backup => {
+                for conflict in conflicts.iter() {
+                    self.backup(conflict)?;
+                }
+            }
+            ConflictPolicy::Merge => {
+                to_merge.extend(conflicts.iter().filter_map(|conflict| match conflict {
+                    PathConflict::Modified(path) => Some(path.clone()),
+                    _ => None,
+                }));
+                // Nothing is backed up before knowing that the plan can be applied.
+                if to_merge.is_empty() {
+                    for conflict in conflicts.iter() {
+                        self.backup(conflict)?;
+                    }
+                }
+            }
+        }
+        Ok(to_merge)
+    }
+
+    /// Copy the conflicting file to `<path>.orig`, or `<path>.orig.<n>` if earlier backups exist.
+    /// Files in the place of a directory are moved instead, so the directory can be created.
+    fn backup(&self, conflict: &PathConflict) -> Result<()> {
+        let source = self.checkout.vfs.join(conflict.path());
+        let mut backup = source.clone().into_os_string();
+        backup.push(BACKUP_SUFFIX);
+        let mut n = 0;
+        let base = backup.clone();
+        // `symlink_metadata` so that dangling symlinks are not overwritten either.
+        while Path::new(&backup).symlink_metadata().is_ok() {
+            n += 1;
+            backup = base.clone();
+            backup.push(format!(".{}", n));
+        }
+        let result = match conflict {
+            PathConflict::UntrackedInPlaceOfDirectory(_) => std::fs::rename(&source, &backup),
+            _ => std::fs::copy(&source, &backup).map(|_| ()),
+        };
+        result.with_context(|| format!("Can't back up {} to {:?}", conflict.path(), backup))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::create_dir;
+    use std::sync::Arc;
+
+    use manifest::FileMetadata;
+    use manifest_tree::testutil::make_tree_manifest_from_meta;
+    use manifest_tree::testutil::TestStore;
+    use manifest_tree::Diff;
+    use pathmatcher::AlwaysMatcher;
+    use types::HgId;
+    use vfs::UpdateFlag;
+    use vfs::VFS;
+
+    use super::*;
+    use crate::ActionMap;
+    use crate::Checkout;
+
+    fn rp(p: &str) -> RepoPathBuf {
+        RepoPathBuf::from_string(p.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_directory_conflicts() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+
+        // "a" is an untracked file, "b" a tracked file replaced by a directory.
+        let from = [(rp("b"), FileMetadata::regular(HgId::null_id().clone()))];
+        let to = [
+            (rp("a/x"), FileMetadata::regular(HgId::null_id().clone())),
+            (rp("b/y"), FileMetadata::regular(HgId::null_id().clone())),
+            (rp("c/z"), FileMetadata::regular(HgId::null_id().clone())),
+        ];
+        vfs.write(&rp("a"), b"untracked", UpdateFlag::Regular)?;
+        vfs.write(&rp("b"), b"tracked", UpdateFlag::Regular)?;
+
+        let store = Arc::new(TestStore::new());
+        let left_tree = make_tree_manifest_from_meta(store.clone(), from.iter().cloned());
+        let right_tree = make_tree_manifest_from_meta(store, to.iter().cloned());
+        let diff = Diff::new(&left_tree, &right_tree, &AlwaysMatcher::new())?;
+        let plan = Checkout::default_config(vfs).plan_action_map(ActionMap::from_diff(diff)?);
+
+        let conflicts = plan.check_directory_conflicts();
+        assert_eq!(
+            conflicts,
+            vec![PathConflict::UntrackedInPlaceOfDirectory(rp("a"))]
+        );
+
+        assert!(plan
+            .resolve_path_conflicts(conflicts.clone(), ConflictPolicy::Abort)
+            .unwrap_err()
+            .is::<PathConflictError>());
+        assert_eq!(
+            plan.resolve_path_conflicts(conflicts, ConflictPolicy::Backup)?,
+            Vec::<RepoPathBuf>::new()
+        );
+        assert!(!working_path.join("a").exists());
+        assert_eq!(std::fs::read(working_path.join("a.orig"))?, b"untracked");
+        assert!(plan.check_directory_conflicts().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_backup_policies() -> Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let working_path = tempdir.path().join("workingdir");
+        create_dir(&working_path)?;
+        let vfs = VFS::new(working_path.clone())?;
+        vfs.write(&rp("m"), b"modified", UpdateFlag::Regular)?;
+        vfs.write(&rp("u"), b"untracked", UpdateFlag::Regular)?;
+        vfs.write(&rp("u.orig"), b"earlier backup", UpdateFlag::Regular)?;
+
+        let store = Arc::new(TestStore::new());
+        let tree = make_tree_manifest_from_meta(store, std::iter::empty());
+        let diff = Diff::new(&tree, &tree, &AlwaysMatcher::new())?;
+        let plan = Checkout::default_config(vfs).plan_action_map(ActionMap::from_diff(diff)?);
+        let conflicts = vec![
+            PathConflict::Modified(rp("m")),
+            PathConflict::Untracked(rp("u")),
+        ];
+
+        // With modified files to merge, nothing is backed up.
+        assert_eq!(
+            plan.resolve_path_conflicts(conflicts.clone(), ConflictPolicy::Merge)?,
+            vec![rp("m")]
+        );
+        assert!(!working_path.join("m.orig").exists());
+        assert!(!working_path.join("u.orig.1").exists());
+
+        assert_eq!(
+            plan.resolve_path_conflicts(conflicts[1..].to_vec(), ConflictPolicy::Merge)?,
+            Vec::<RepoPathBuf>::new()
+        );
+        assert!(!working_path.join("m.orig").exists());
+        // Earlier backups are kept.
+        assert_eq!(
+            std::fs::read(working_path.join("u.orig"))?,
+            b"earlier backup"
+        );
+        assert_eq!(std::fs::read(working_path.join("u.orig.1"))?, b"untracked");
+
+        assert_eq!(
+            plan.resolve_path_conflicts(conflicts, ConflictPolicy::Backup)?,
+            Vec::<RepoPathBuf>::new()
+        );
+        assert_eq!(std::fs::read(working_path.join("m.orig"))?, b"modified");
+        assert_eq!(std::fs::read(working_path.join("u.orig.2"))?, b"untracked");
+        Ok(())
+    }
+}
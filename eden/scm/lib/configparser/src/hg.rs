@@ -1292,6 +1292,7 @@ UltraCompare.diffargs=$child $parent -title1 $clabel -title2 $plabel1
 // only available in Python. They have the lowest priority.
 static HG_PY_CORE_CONFIG: &str = r#"
 [treestate]
+backgroundcompact=true
 mingcage=900
 minrepackthreshold=10M
 repackfactor=3
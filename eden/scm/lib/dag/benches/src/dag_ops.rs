@@ -193,6 +193,26 @@ fn bench_with_iddag<S: IdDagStore + Persist>(get_empty_iddag: impl Fn() -> IdDag
         })
     });
 
+    bench("only (2 ids)", || {
+        elapsed(|| {
+            for set in &sample_two_ids {
+                let ids: Vec<_> = set.iter_desc().collect();
+                dag.only(ids[0].into(), ids[1].into()).unwrap();
+            }
+        })
+    });
+
+    bench("only (2 ids, ancestors difference)", || {
+        elapsed(|| {
+            for set in &sample_two_ids {
+                let ids: Vec<_> = set.iter_desc().collect();
+                let reachable = dag.ancestors(ids[0].into()).unwrap();
+                let unreachable = dag.ancestors(ids[1].into()).unwrap();
+                reachable.difference(&unreachable);
+            }
+        })
+    });
+
     bench("is_ancestor", || {
         elapsed(|| {
             for set in &sample_two_ids {
@@ -306,6 +326,14 @@ fn bench_many_heads_namedag() {
         })
     });
 
+    bench("only (draft - master)", || {
+        elapsed(|| {
+            for (head, root) in &head_root_pairs {
+                nbr(dag.only(head.clone(), root.clone())).unwrap();
+            }
+        })
+    });
+
     let heads = nbr(dag.heads(nbr(dag.all()).unwrap())).unwrap();
     let root_list: Vec<Set> = ((M - 64)..M).map(|i| to_set(&format!("N{}", i))).collect();
     bench("range (recent_draft::drafts)", || {
@@ -13,6 +13,7 @@ use futures::StreamExt;
 use futures::TryStreamExt;
 
 use crate::errors::programming;
+use crate::errors::NotFoundError;
 use crate::namedag::MemNameDag;
 use crate::nameset::hints::Hints;
 use crate::ops::DagAddHeads;
@@ -228,6 +229,36 @@ pub(crate) async fn parents(this: &(impl DagAlgorithm + ?Sized), set: NameSet) -
     Ok(NameSet::from_static_names(result))
 }
 
+pub(crate) async fn parents_batch(
+    this: &(impl DagAlgorithm + ?Sized),
+    names: &[VertexName],
+) -> Result<Vec<Vec<VertexName>>> {
+    let mut result = Vec::with_capacity(names.len());
+    for name in names {
+        result.push(this.parent_names(name.clone()).await?);
+    }
+    Ok(result)
+}
+
+pub(crate) async fn children_batch(
+    this: &(impl DagAlgorithm + ?Sized),
+    names: &[VertexName],
+) -> Result<Vec<Vec<VertexName>>> {
+    let mut result = Vec::with_capacity(names.len());
+    for name in names {
+        let children = this
+            .children(NameSet::from_static_names(vec![name.clone()]))
+            .await?;
+        let mut iter = children.iter().await?;
+        let mut names = Vec::new();
+        while let Some(name) = iter.next().await {
+            names.push(name?);
+        }
+        result.push(names);
+    }
+    Ok(result)
+}
+
 pub(crate) async fn first_ancestor_nth(
     this: &(impl DagAlgorithm + ?Sized),
     name: VertexName,
@@ -309,6 +340,68 @@ pub(crate) async fn heads_ancestors(
     this.heads(this.ancestors(set).await?).await
 }
 
+pub(crate) async fn range_paged(
+    this: &(impl DagAlgorithm + ?Sized),
+    roots: NameSet,
+    heads: NameSet,
+    cursor: Option<VertexName>,
+    limit: u64,
+) -> Result<NameSet> {
+    let set = this.sort(&this.range(roots, heads).await?).await?;
+    let set = match cursor {
+        None => set,
+        Some(cursor) => {
+            // Skip vertexes up to the cursor.
+            let mut count = 0;
+            let mut iter = set.iter().await?;
+            loop {
+                match iter.next().await {
+                    Some(name) => {
+                        count += 1;
+                        if name? == cursor {
+                            break;
+                        }
+                    }
+                    None => return cursor.not_found(),
+                }
+            }
+            set.skip(count)
+        }
+    };
+    Ok(set.take(limit))
+}
+
+pub(crate) async fn suggest_bisect(
+    this: &(impl DagAlgorithm + ?Sized),
+    roots: NameSet,
+    heads: NameSet,
+    skip: NameSet,
+) -> Result<Option<VertexName>> {
+    let candidates = this.only(heads.clone(), roots).await?;
+    let total = candidates.count().await?;
+    if total <= 1 {
+        return Ok(None);
+    }
+    // Known bad vertexes are not worth testing.
+    let choices = this.sort(&(candidates.clone() - heads - skip)).await?;
+    let mut best: Option<(usize, VertexName)> = None;
+    let mut iter = choices.iter().await?;
+    while let Some(name) = iter.next().await {
+        let name = name?;
+        // Remaining candidates if `name` is bad, or good.
+        let ancestors = this.ancestors(name.clone().into()).await?;
+        let bad = (ancestors & candidates.clone()).count().await?;
+        let cost = bad.max(total - bad);
+        if best
+            .as_ref()
+            .map_or(true, |(best_cost, _)| cost < *best_cost)
+        {
+            best = Some((cost, name));
+        }
+    }
+    Ok(best.map(|(_, name)| name))
+}
+
 pub(crate) async fn only(
     this: &(impl DagAlgorithm + ?Sized),
     reachable: NameSet,
@@ -391,6 +484,71 @@ pub(crate) async fn is_ancestor(
     Ok(false)
 }
 
+/// Id space versions of some functions above, to cross-check lazy graphs in
+/// tests. They only follow the parents in the local segments, so vertexes are
+/// never resolved remotely.
+#[cfg(test)]
+pub(crate) mod id_space {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    pub(crate) fn to_ids(set: &IdSet) -> BTreeSet<Id> {
+        set.iter_desc().collect()
+    }
+
+    fn ancestors(dag: &dyn IdDagAlgorithm, set: &IdSet) -> Result<BTreeSet<Id>> {
+        let mut to_visit: Vec<Id> = set.iter_desc().collect();
+        let mut visited: BTreeSet<Id> = to_visit.iter().copied().collect();
+        while let Some(id) = to_visit.pop() {
+            for parent in dag.parent_ids(id)? {
+                if visited.insert(parent) {
+                    to_visit.push(parent);
+                }
+            }
+        }
+        Ok(visited)
+    }
+
+    fn heads(dag: &dyn IdDagAlgorithm, set: BTreeSet<Id>) -> Result<BTreeSet<Id>> {
+        let mut heads = set.clone();
+        for &id in &set {
+            for parent in dag.parent_ids(id)? {
+                heads.remove(&parent);
+            }
+        }
+        Ok(heads)
+    }
+
+    pub(crate) fn common_ancestors(dag: &dyn IdDagAlgorithm, set: &IdSet) -> Result<BTreeSet<Id>> {
+        let mut result: Option<BTreeSet<Id>> = None;
+        for id in set.iter_desc() {
+            let ancestors = ancestors(dag, &id.into())?;
+            result = Some(match result {
+                None => ancestors,
+                Some(result) => result.intersection(&ancestors).copied().collect(),
+            });
+        }
+        Ok(result.unwrap_or_default())
+    }
+
+    pub(crate) fn gca_all(dag: &dyn IdDagAlgorithm, set: &IdSet) -> Result<BTreeSet<Id>> {
+        heads(dag, common_ancestors(dag, set)?)
+    }
+
+    pub(crate) fn heads_ancestors(dag: &dyn IdDagAlgorithm, set: &IdSet) -> Result<BTreeSet<Id>> {
+        heads(dag, ancestors(dag, set)?)
+    }
+
+    pub(crate) fn is_ancestor(
+        dag: &dyn IdDagAlgorithm,
+        ancestor: Id,
+        descendant: Id,
+    ) -> Result<bool> {
+        Ok(ancestors(dag, &descendant.into())?.contains(&ancestor))
+    }
+}
+
 #[tracing::instrument(skip(this), level=tracing::Level::DEBUG)]
 pub(crate) async fn hint_subdag_for_insertion(
     this: &(impl Parents + ?Sized),
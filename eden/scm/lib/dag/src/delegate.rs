@@ -131,6 +131,20 @@ macro_rules! delegate {
             {
                 self.$($t)*.parent_names(name)
             }
+            fn parents_batch<'a: 's, 'b: 's, 's>(&'a self, names: &'b [$crate::Vertex])
+                -> std::pin::Pin<Box<dyn std::future::Future<Output=
+                        $crate::Result<Vec<Vec<$crate::Vertex>>>
+                    > + Send + 's>> where Self: 's
+            {
+                self.$($t)*.parents_batch(names)
+            }
+            fn children_batch<'a: 's, 'b: 's, 's>(&'a self, names: &'b [$crate::Vertex])
+                -> std::pin::Pin<Box<dyn std::future::Future<Output=
+                        $crate::Result<Vec<Vec<$crate::Vertex>>>
+                    > + Send + 's>> where Self: 's
+            {
+                self.$($t)*.children_batch(names)
+            }
             fn all<'a: 's, 's>(&'a self)
                 -> std::pin::Pin<Box<dyn std::future::Future<Output=
                         $crate::Result<$crate::Set>
@@ -243,6 +257,22 @@ macro_rules! delegate {
             {
                 self.$($t)*.range(roots, heads)
             }
+            fn range_paged<'a: 's, 's>(&'a self, roots: $crate::Set, heads: $crate::Set,
+                cursor: Option<$crate::Vertex>, limit: u64)
+                -> std::pin::Pin<Box<dyn std::future::Future<Output=
+                        $crate::Result<$crate::Set>
+                    > + Send + 's>> where Self: 's
+            {
+                self.$($t)*.range_paged(roots, heads, cursor, limit)
+            }
+            fn suggest_bisect<'a: 's, 's>(&'a self, roots: $crate::Set, heads: $crate::Set,
+                skip: $crate::Set)
+                -> std::pin::Pin<Box<dyn std::future::Future<Output=
+                        $crate::Result<Option<$crate::Vertex>>
+                    > + Send + 's>> where Self: 's
+            {
+                self.$($t)*.suggest_bisect(roots, heads, skip)
+            }
             fn only<'a: 's, 's>(&'a self, reachable: $crate::Set, unreachable: $crate::Set)
                 -> std::pin::Pin<Box<dyn std::future::Future<Output=
                         $crate::Result<$crate::Set>
@@ -324,6 +354,20 @@ macro_rules! delegate {
             {
                 self.$($t)*.check_segments()
             }
+            fn check_idmap<'a: 's, 's>(&'a self)
+                -> std::pin::Pin<Box<dyn std::future::Future<Output=
+                        $crate::Result<Vec<String>>
+                    > + Send + 's>> where Self: 's
+            {
+                self.$($t)*.check_idmap()
+            }
+            fn check_integrity<'a: 's, 's>(&'a self)
+                -> std::pin::Pin<Box<dyn std::future::Future<Output=
+                        $crate::Result<$crate::IntegrityReport>
+                    > + Send + 's>> where Self: 's
+            {
+                self.$($t)*.check_integrity()
+            }
             fn check_isomorphic_graph<'a: 's, 'b: 's, 's> (
                 &'a self,
                 other: &'b dyn $crate::ops::DagAlgorithm,
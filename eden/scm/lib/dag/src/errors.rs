@@ -7,6 +7,7 @@
 
 use std::io;
 
+use error_code::ErrorCode;
 use thiserror::Error;
 
 use crate::Group;
@@ -64,6 +65,25 @@ pub enum BackendError {
     Other(#[from] anyhow::Error),
 }
 
+impl DagError {
+    /// The stable error code of this error, for error handling outside Rust.
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            DagError::VertexNotFound(_) => ErrorCode::VertexNotFound,
+            DagError::IdNotFound(_) => ErrorCode::IdNotFound,
+            DagError::NeedSlowPath(_) => ErrorCode::NeedSlowPath,
+            DagError::Programming(_) | DagError::Bug(_) => ErrorCode::DagBug,
+            DagError::Backend(e) => match e.as_ref() {
+                BackendError::Other(e) => {
+                    error_code::error_code(e).unwrap_or(ErrorCode::DagBackendFailed)
+                }
+                _ => ErrorCode::DagBackendFailed,
+            },
+            DagError::IdOverflow(_) => ErrorCode::IdOverflow,
+        }
+    }
+}
+
 impl From<BackendError> for DagError {
     fn from(err: BackendError) -> DagError {
         DagError::Backend(Box::new(err))
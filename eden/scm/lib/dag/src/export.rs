@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! # export
+//!
+//! Portable serialization of [`CloneData`] (segments + IdMap).
+//!
+//! The format is versioned and independent from the on-disk formats of
+//! `IdDag` and `IdMap`. It can be used to ship graphs between servers and
+//! clients, or to dump a graph for offline analysis.
+//!
+//! CBOR is the main encoding. JSON is supported for debugging. In JSON,
+//! vertex names are hex strings so the output is human-readable.
+
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::errors::BackendError;
+use crate::id::Id;
+use crate::id::VertexName;
+use crate::segment::FlatSegment;
+use crate::segment::PreparedFlatSegments;
+use crate::CloneData;
+use crate::Result;
+
+/// Version of the portable format. Bump this on incompatible changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Encoding of the portable format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Compact binary encoding.
+    Cbor,
+    /// Human-readable encoding for debugging.
+    Json,
+}
+
+/// The portable graph. `N` is the type of vertex names.
+#[derive(Serialize, Deserialize)]
+struct PortableGraph<N> {
+    version: u32,
+    segments: Vec<PortableSegment>,
+    names: Vec<(u64, N)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PortableSegment {
+    low: u64,
+    high: u64,
+    parents: Vec<u64>,
+}
+
+/// Used to check the version before decoding other fields.
+#[derive(Deserialize)]
+struct PortableVersion {
+    version: u32,
+}
+
+/// Serialize `data` to the portable format.
+pub fn export_clone_data(data: &CloneData<VertexName>, format: ExportFormat) -> Result<Vec<u8>> {
+    let segments: Vec<PortableSegment> = data
+        .flat_segments
+        .segments
+        .iter()
+        .map(|s| PortableSegment {
+            low: s.low.0,
+            high: s.high.0,
+            parents: s.parents.iter().map(|p| p.0).collect(),
+        })
+        .collect();
+    let bytes = match format {
+        ExportFormat::Cbor => {
+            let graph = PortableGraph {
+                version: FORMAT_VERSION,
+                segments,
+                names: data.idmap.iter().map(|(id, v)| (id.0, v)).collect(),
+            };
+            serde_cbor::to_vec(&graph).map_err(|e| encode_error("CBOR", e))?
+        }
+        ExportFormat::Json => {
+            let graph = PortableGraph {
+                version: FORMAT_VERSION,
+                segments,
+                names: data
+                    .idmap
+                    .iter()
+                    .map(|(id, v)| (id.0, v.to_hex()))
+                    .collect(),
+            };
+            serde_json::to_vec_pretty(&graph).map_err(|e| encode_error("JSON", e))?
+        }
+    };
+    Ok(bytes)
+}
+
+/// Deserialize `data` from the portable format. The encoding is detected
+/// automatically.
+pub fn import_clone_data(data: &[u8]) -> Result<CloneData<VertexName>> {
+    let (segments, idmap) = if data.first() == Some(&b'{') {
+        check_version(serde_json::from_slice(data).map_err(|e| decode_error("JSON", e))?)?;
+        let graph: PortableGraph<String> =
+            serde_json::from_slice(data).map_err(|e| decode_error("JSON", e))?;
+        let mut idmap = BTreeMap::new();
+        for (id, hex) in graph.names {
+            let name = VertexName::from_hex(hex.as_bytes())?;
+            idmap.insert(Id(id), name);
+        }
+        (graph.segments, idmap)
+    } else {
+        check_version(serde_cbor::from_slice(data).map_err(|e| decode_error("CBOR", e))?)?;
+        let graph: PortableGraph<VertexName> =
+            serde_cbor::from_slice(data).map_err(|e| decode_error("CBOR", e))?;
+        let idmap = graph.names.into_iter().map(|(id, v)| (Id(id), v)).collect();
+        (graph.segments, idmap)
+    };
+
+    let mut flat_segments = PreparedFlatSegments::default();
+    for seg in segments {
+        if seg.low > seg.high {
+            return Err(BackendError::Generic(format!(
+                "exported graph has invalid segment {}..={}",
+                seg.low, seg.high
+            ))
+            .into());
+        }
+        flat_segments.segments.insert(FlatSegment {
+            low: Id(seg.low),
+            high: Id(seg.high),
+            parents: seg.parents.into_iter().map(Id).collect(),
+        });
+    }
+
+    Ok(CloneData {
+        flat_segments,
+        idmap,
+    })
+}
+
+fn check_version(header: PortableVersion) -> Result<()> {
+    if header.version > FORMAT_VERSION {
+        return Err(BackendError::Generic(format!(
+            "exported graph has unsupported version {} (supported: {})",
+            header.version, FORMAT_VERSION
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn encode_error(encoding: &str, err: impl ToString) -> BackendError {
+    BackendError::Generic(format!(
+        "cannot encode graph as {}: {}",
+        encoding,
+        err.to_string()
+    ))
+}
+
+fn decode_error(encoding: &str, err: impl ToString) -> BackendError {
+    BackendError::Generic(format!(
+        "cannot decode {} graph: {}",
+        encoding,
+        err.to_string()
+    ))
+}
@@ -0,0 +1,483 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! # git
+//!
+//! Interop with git `commit-graph` files.
+//!
+//! A `commit-graph` file stores commit hashes, parents, root trees and
+//! commit times. A split commit-graph uses a `commit-graph-chain` file to
+//! list layers stored as `graph-{hash}.graph`. Each layer can refer to
+//! commits in the layers before it.
+//!
+//! Both forms can be read. Only the chunks describing the graph (`OIDF`,
+//! `OIDL`, `CDAT`, `EDGE`, `BASE`) are used. Written files use SHA1 and
+//! topological levels as generation numbers.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use byteorder::BigEndian;
+use byteorder::ByteOrder;
+use futures::StreamExt;
+use sha1::Digest;
+use sha1::Sha1;
+
+use crate::errors::BackendError;
+use crate::ops::DagAlgorithm;
+use crate::ops::DagPersistent;
+use crate::Group;
+use crate::NameSet;
+use crate::Result;
+use crate::VertexListWithOptions;
+use crate::VertexName;
+
+/// A commit in a git commit-graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GitCommit {
+    /// Hash of the commit.
+    pub oid: VertexName,
+
+    /// Hash of the root tree.
+    pub tree: VertexName,
+
+    /// Parents of the commit, in order.
+    pub parents: Vec<VertexName>,
+
+    /// Commit time, in seconds since epoch. Limited to 34 bits.
+    pub commit_time: u64,
+}
+
+/// File name of the split commit-graph chain.
+pub const CHAIN_FILE_NAME: &str = "commit-graph-chain";
+
+const SIGNATURE: &[u8] = b"CGPH";
+const VERSION: u8 = 1;
+const HASH_VERSION_SHA1: u8 = 1;
+const HASH_VERSION_SHA256: u8 = 2;
+const SHA1_LEN: usize = 20;
+
+const CHUNK_OID_FANOUT: [u8; 4] = *b"OIDF";
+const CHUNK_OID_LOOKUP: [u8; 4] = *b"OIDL";
+const CHUNK_COMMIT_DATA: [u8; 4] = *b"CDAT";
+const CHUNK_EXTRA_EDGES: [u8; 4] = *b"EDGE";
+const CHUNK_BASE_GRAPHS: [u8; 4] = *b"BASE";
+
+const HEADER_LEN: usize = 8;
+const CHUNK_ENTRY_LEN: usize = 12;
+
+/// Marks a missing parent in `CDAT`.
+const PARENT_NONE: u32 = 0x7000_0000;
+/// In `CDAT`, marks the second parent as an index into `EDGE`.
+/// In `EDGE`, marks the last parent.
+const PARENT_EDGE: u32 = 0x8000_0000;
+
+const GENERATION_MAX: u64 = 0x3fff_ffff;
+const COMMIT_TIME_MAX: u64 = (1 << 34) - 1;
+
+/// Read commits from a `commit-graph` file, or from a `commit-graph-chain`
+/// file and the layers it lists.
+pub fn read_commit_graph(path: &Path) -> Result<Vec<GitCommit>> {
+    if path.file_name() == Some(OsStr::new(CHAIN_FILE_NAME)) {
+        read_commit_graph_chain(path)
+    } else {
+        parse_commit_graph(&fs::read(path)?)
+    }
+}
+
+/// Parse a `commit-graph` file that does not depend on other layers.
+pub fn parse_commit_graph(data: &[u8]) -> Result<Vec<GitCommit>> {
+    parse_layer(data, &[], &[])
+}
+
+/// Serialize `commits` to a `commit-graph` file.
+///
+/// Parents must be included in `commits`. Hashes must be SHA1.
+pub fn write_commit_graph(commits: &[GitCommit]) -> Result<Vec<u8>> {
+    encode_layer(commits, &mut WrittenLayers::default())
+}
+
+/// Write a split commit-graph to `dir`. Commits in a layer can use commits
+/// in previous layers as parents.
+pub fn write_commit_graph_chain(dir: &Path, layers: &[Vec<GitCommit>]) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut written = WrittenLayers::default();
+    let mut chain = String::new();
+    for commits in layers {
+        let data = encode_layer(commits, &mut written)?;
+        let hash = data[data.len() - SHA1_LEN..].to_vec();
+        let hex = VertexName::copy_from(&hash).to_hex();
+        fs::write(dir.join(format!("graph-{}.graph", hex)), &data)?;
+        chain.push_str(&hex);
+        chain.push('\n');
+        written.hashes.push(hash);
+    }
+    fs::write(dir.join(CHAIN_FILE_NAME), chain)?;
+    Ok(())
+}
+
+/// Add `commits` to the master group of `dag` and write it to disk.
+///
+/// Parents must be included in `commits` or already exist in `dag`.
+pub async fn import_commit_graph(
+    dag: &mut (impl DagPersistent + ?Sized),
+    commits: &[GitCommit],
+) -> Result<()> {
+    let parents: HashMap<VertexName, Vec<VertexName>> = commits
+        .iter()
+        .map(|c| (c.oid.clone(), c.parents.clone()))
+        .collect();
+    let non_heads: HashSet<&VertexName> = commits.iter().flat_map(|c| &c.parents).collect();
+    let mut heads: Vec<VertexName> = commits
+        .iter()
+        .map(|c| &c.oid)
+        .filter(|v| !non_heads.contains(v))
+        .cloned()
+        .collect();
+    heads.sort_unstable();
+    let heads = VertexListWithOptions::from(heads).with_highest_group(Group::MASTER);
+    dag.add_heads_and_flush(&parents, &heads).await
+}
+
+/// Collect `set` and its ancestors in `dag` as git commits.
+///
+/// `commit_info` provides the root tree and commit time, which are not
+/// tracked by the dag.
+pub async fn export_commit_graph(
+    dag: &(impl DagAlgorithm + ?Sized),
+    set: NameSet,
+    commit_info: impl Fn(&VertexName) -> Result<(VertexName, u64)>,
+) -> Result<Vec<GitCommit>> {
+    let set = dag.ancestors(set).await?;
+    let mut commits = Vec::new();
+    let mut iter = set.iter().await?;
+    while let Some(oid) = iter.next().await {
+        let oid = oid?;
+        let parents = dag.parent_names(oid.clone()).await?;
+        let (tree, commit_time) = commit_info(&oid)?;
+        commits.push(GitCommit {
+            oid,
+            tree,
+            parents,
+            commit_time,
+        });
+    }
+    Ok(commits)
+}
+
+fn read_commit_graph_chain(path: &Path) -> Result<Vec<GitCommit>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let chain = fs::read_to_string(path)?;
+    let mut base_oids: Vec<VertexName> = Vec::new();
+    let mut base_hashes: Vec<&str> = Vec::new();
+    let mut commits = Vec::new();
+    for hash in chain.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let data = fs::read(dir.join(format!("graph-{}.graph", hash)))?;
+        let layer = parse_layer(&data, &base_oids, &base_hashes)?;
+        base_oids.extend(layer.iter().map(|c| c.oid.clone()));
+        base_hashes.push(hash);
+        commits.extend(layer);
+    }
+    Ok(commits)
+}
+
+/// Parse a layer. `base_oids` are commits in previous layers, in position
+/// order. `base_hashes` are hex hashes of previous layers.
+fn parse_layer(
+    data: &[u8],
+    base_oids: &[VertexName],
+    base_hashes: &[&str],
+) -> Result<Vec<GitCommit>> {
+    if data.len() < HEADER_LEN || !data.starts_with(SIGNATURE) {
+        return Err(invalid("signature mismatch"));
+    }
+    if data[4] != VERSION {
+        return Err(invalid(format!("unsupported version {}", data[4])));
+    }
+    let hash_len = match data[5] {
+        HASH_VERSION_SHA1 => SHA1_LEN,
+        HASH_VERSION_SHA256 => 32,
+        v => return Err(invalid(format!("unsupported hash version {}", v))),
+    };
+    let chunk_count = data[6] as usize;
+    let base_count = data[7] as usize;
+    if base_count != base_hashes.len() {
+        return Err(invalid(format!(
+            "expects {} base layers, got {}",
+            base_count,
+            base_hashes.len()
+        )));
+    }
+
+    // The table has an extra entry marking the end of the last chunk.
+    let table_len = (chunk_count + 1) * CHUNK_ENTRY_LEN;
+    let table = data
+        .get(HEADER_LEN..HEADER_LEN + table_len)
+        .ok_or_else(|| invalid("truncated chunk table"))?;
+    let mut chunks: HashMap<[u8; 4], &[u8]> = HashMap::new();
+    let entries = table.chunks(CHUNK_ENTRY_LEN);
+    for (entry, next) in entries.clone().zip(entries.skip(1)) {
+        let id: [u8; 4] = entry[..4].try_into().unwrap();
+        let start = BigEndian::read_u64(&entry[4..]) as usize;
+        let end = BigEndian::read_u64(&next[4..]) as usize;
+        let chunk = data
+            .get(start..end)
+            .ok_or_else(|| invalid("chunk out of range"))?;
+        chunks.insert(id, chunk);
+    }
+    let get_chunk = |id: [u8; 4]| -> Result<&[u8]> {
+        chunks
+            .get(&id)
+            .copied()
+            .ok_or_else(|| invalid(format!("missing {} chunk", String::from_utf8_lossy(&id))))
+    };
+
+    let oid_lookup = get_chunk(CHUNK_OID_LOOKUP)?;
+    let commit_data = get_chunk(CHUNK_COMMIT_DATA)?;
+    let edges = chunks.get(&CHUNK_EXTRA_EDGES).copied().unwrap_or_default();
+    let record_len = hash_len + 16;
+    let count = oid_lookup.len() / hash_len;
+    if oid_lookup.len() % hash_len != 0 || commit_data.len() != count * record_len {
+        return Err(invalid("chunk size mismatch"));
+    }
+    if base_count > 0 {
+        let base = get_chunk(CHUNK_BASE_GRAPHS)?;
+        let listed: Vec<String> = base
+            .chunks(hash_len)
+            .map(|h| VertexName::copy_from(h).to_hex())
+            .collect();
+        if listed != base_hashes {
+            return Err(invalid(format!(
+                "base layers {:?} do not match the chain {:?}",
+                listed, base_hashes
+            )));
+        }
+    }
+
+    let oids: Vec<VertexName> = oid_lookup
+        .chunks(hash_len)
+        .map(VertexName::copy_from)
+        .collect();
+    let resolve = |pos: u32| -> Result<VertexName> {
+        let pos = pos as usize;
+        match pos.checked_sub(base_oids.len()) {
+            None => Ok(base_oids[pos].clone()),
+            Some(local) => oids
+                .get(local)
+                .cloned()
+                .ok_or_else(|| invalid(format!("parent position {} out of range", pos))),
+        }
+    };
+
+    let mut commits = Vec::with_capacity(count);
+    for (oid, record) in oids.iter().zip(commit_data.chunks(record_len)) {
+        let tree = VertexName::copy_from(&record[..hash_len]);
+        let record = &record[hash_len..];
+        let p1 = BigEndian::read_u32(&record[0..]);
+        let p2 = BigEndian::read_u32(&record[4..]);
+        let time_high = BigEndian::read_u32(&record[8..]) & 0x3;
+        let time_low = BigEndian::read_u32(&record[12..]);
+        let commit_time = ((time_high as u64) << 32) | time_low as u64;
+
+        let mut parents = Vec::new();
+        if p1 != PARENT_NONE {
+            parents.push(resolve(p1)?);
+        }
+        if p2 & PARENT_EDGE != 0 {
+            let mut index = (p2 & !PARENT_EDGE) as usize;
+            loop {
+                let edge = edges
+                    .get(index * 4..index * 4 + 4)
+                    .ok_or_else(|| invalid("extra edge out of range"))?;
+                let edge = BigEndian::read_u32(edge);
+                parents.push(resolve(edge & !PARENT_EDGE)?);
+                if edge & PARENT_EDGE != 0 {
+                    break;
+                }
+                index += 1;
+            }
+        } else if p2 != PARENT_NONE {
+            parents.push(resolve(p2)?);
+        }
+
+        commits.push(GitCommit {
+            oid: oid.clone(),
+            tree,
+            parents,
+            commit_time,
+        });
+    }
+    Ok(commits)
+}
+
+/// State of layers written so far.
+#[derive(Default)]
+struct WrittenLayers {
+    /// Position and generation number of written commits.
+    commits: HashMap<VertexName, (u32, u64)>,
+
+    /// Raw hashes of written layers.
+    hashes: Vec<Vec<u8>>,
+}
+
+/// Serialize `commits` as a layer on top of `written`, then add `commits`
+/// to `written`.
+fn encode_layer(commits: &[GitCommit], written: &mut WrittenLayers) -> Result<Vec<u8>> {
+    let mut sorted: Vec<&GitCommit> = commits.iter().collect();
+    sorted.sort_unstable_by(|a, b| a.oid.cmp(&b.oid));
+    for (i, commit) in sorted.iter().enumerate() {
+        if commit.oid.as_ref().len() != SHA1_LEN || commit.tree.as_ref().len() != SHA1_LEN {
+            return Err(invalid(format!("{:?} is not a SHA1 commit", &commit.oid)));
+        }
+        if commit.commit_time > COMMIT_TIME_MAX {
+            return Err(invalid(format!(
+                "{:?} has an invalid commit time",
+                &commit.oid
+            )));
+        }
+        if (i > 0 && sorted[i - 1].oid == commit.oid) || written.commits.contains_key(&commit.oid) {
+            return Err(invalid(format!("{:?} is duplicated", &commit.oid)));
+        }
+    }
+    let base_count = written.commits.len();
+    if base_count + sorted.len() >= PARENT_NONE as usize || written.hashes.len() > u8::MAX as usize
+    {
+        return Err(invalid("too many commits or layers"));
+    }
+    let local: HashMap<&VertexName, usize> = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (&c.oid, i))
+        .collect();
+    let position = |oid: &VertexName| -> Result<u32> {
+        match (local.get(oid), written.commits.get(oid)) {
+            (Some(&i), _) => Ok((base_count + i) as u32),
+            (None, Some(&(pos, _))) => Ok(pos),
+            (None, None) => Err(invalid(format!("parent {:?} is missing", oid))),
+        }
+    };
+
+    // Generation numbers. `stack` is a path to a commit whose generation
+    // is unknown, so it cannot be longer than `sorted` without a cycle.
+    let mut generations: Vec<u64> = vec![0; sorted.len()];
+    for i in 0..sorted.len() {
+        let mut stack = vec![i];
+        'next: while let Some(&j) = stack.last() {
+            if stack.len() > sorted.len() {
+                return Err(invalid(format!("{:?} is in a cycle", &sorted[j].oid)));
+            }
+            let mut generation = 0;
+            for parent in &sorted[j].parents {
+                let parent_generation = match (local.get(parent), written.commits.get(parent)) {
+                    (Some(&k), _) if generations[k] == 0 => {
+                        stack.push(k);
+                        continue 'next;
+                    }
+                    (Some(&k), _) => generations[k],
+                    (None, Some(&(_, g))) => g,
+                    (None, None) => return Err(invalid(format!("parent {:?} is missing", parent))),
+                };
+                generation = generation.max(parent_generation);
+            }
+            generations[j] = (generation + 1).min(GENERATION_MAX);
+            stack.pop();
+        }
+    }
+
+    let mut fanout = [0u32; 256];
+    let mut oid_lookup = Vec::with_capacity(sorted.len() * SHA1_LEN);
+    let mut commit_data = Vec::with_capacity(sorted.len() * (SHA1_LEN + 16));
+    let mut edges: Vec<u8> = Vec::new();
+    for (commit, &generation) in sorted.iter().zip(&generations) {
+        oid_lookup.extend_from_slice(commit.oid.as_ref());
+        commit_data.extend_from_slice(commit.tree.as_ref());
+        let parents = commit
+            .parents
+            .iter()
+            .map(position)
+            .collect::<Result<Vec<u32>>>()?;
+        let p1 = parents.first().copied().unwrap_or(PARENT_NONE);
+        let p2 = match parents.len() {
+            0 | 1 => PARENT_NONE,
+            2 => parents[1],
+            _ => {
+                let index = (edges.len() / 4) as u32;
+                let last = parents.len() - 1;
+                for (i, &p) in parents.iter().enumerate().skip(1) {
+                    let edge = if i == last { p | PARENT_EDGE } else { p };
+                    edges.extend_from_slice(&edge.to_be_bytes());
+                }
+                index | PARENT_EDGE
+            }
+        };
+        commit_data.extend_from_slice(&p1.to_be_bytes());
+        commit_data.extend_from_slice(&p2.to_be_bytes());
+        let time_high = (commit.commit_time >> 32) as u32;
+        commit_data.extend_from_slice(&(((generation as u32) << 2) | time_high).to_be_bytes());
+        commit_data.extend_from_slice(&(commit.commit_time as u32).to_be_bytes());
+    }
+    for commit in &sorted {
+        fanout[commit.oid.as_ref()[0] as usize] += 1;
+    }
+    let fanout: Vec<u8> = fanout
+        .iter()
+        .scan(0, |total, &count| {
+            *total += count;
+            Some(*total)
+        })
+        .flat_map(u32::to_be_bytes)
+        .collect();
+
+    let mut chunks: Vec<([u8; 4], Vec<u8>)> = vec![
+        (CHUNK_OID_FANOUT, fanout),
+        (CHUNK_OID_LOOKUP, oid_lookup),
+        (CHUNK_COMMIT_DATA, commit_data),
+    ];
+    if !edges.is_empty() {
+        chunks.push((CHUNK_EXTRA_EDGES, edges));
+    }
+    if !written.hashes.is_empty() {
+        chunks.push((CHUNK_BASE_GRAPHS, written.hashes.concat()));
+    }
+
+    let mut data = SIGNATURE.to_vec();
+    data.extend_from_slice(&[
+        VERSION,
+        HASH_VERSION_SHA1,
+        chunks.len() as u8,
+        written.hashes.len() as u8,
+    ]);
+    let mut offset = (HEADER_LEN + (chunks.len() + 1) * CHUNK_ENTRY_LEN) as u64;
+    for (id, chunk) in &chunks {
+        data.extend_from_slice(id);
+        data.extend_from_slice(&offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&offset.to_be_bytes());
+    for (_, chunk) in &chunks {
+        data.extend_from_slice(chunk);
+    }
+    let checksum = Sha1::digest(&data);
+    data.extend_from_slice(&checksum);
+
+    for (i, (commit, &generation)) in sorted.iter().zip(&generations).enumerate() {
+        let pos = (base_count + i) as u32;
+        written
+            .commits
+            .insert(commit.oid.clone(), (pos, generation));
+    }
+    Ok(data)
+}
+
+fn invalid(msg: impl fmt::Display) -> crate::Error {
+    BackendError::Generic(format!("invalid commit-graph: {}", msg)).into()
+}
@@ -34,9 +34,11 @@ use crate::ops::Persist;
 #[cfg(any(test, feature = "indexedlog-backend"))]
 use crate::ops::TryClone;
 use crate::segment::FlatSegment;
+use crate::segment::LevelSegmentStats;
 use crate::segment::PreparedFlatSegments;
 use crate::segment::Segment;
 use crate::segment::SegmentFlags;
+use crate::segment::SegmentStats;
 use crate::spanset;
 use crate::types_ext::PreparedFlatSegmentsExt;
 use crate::Error::Programming;
@@ -587,72 +589,110 @@ pub trait IdDagAlgorithm: IdDagStore {
         self.all_ids_in_groups(&[Group::MASTER])
     }
 
-    /// Calculate all ancestors reachable from any id from the given set.
+    /// Calculate statistics about segments at each level.
     ///
-    /// ```plain,ignore
-    /// union(ancestors(i) for i in set)
-    /// ```
-    fn ancestors(&self, mut set: IdSet) -> Result<IdSet> {
-        fn trace(msg: &dyn Fn() -> String) {
-            trace!(target: "dag::algo::ancestors", "{}", msg());
-        }
-        debug!(target: "dag::algo::ancestors", "ancestors({:?})", &set);
-        if set.count() > 2 {
-            // Try to (greatly) reduce the size of the `set` to make calculation cheaper.
-            set = self.heads_ancestors(set)?;
-            trace(&|| format!("simplified to {:?}", &set));
-        }
-        let mut result = IdSet::empty();
-        let mut to_visit: BinaryHeap<_> = set.iter_desc().collect();
-        let max_level = self.max_level()?;
-        'outer: while let Some(id) = to_visit.pop() {
-            if result.contains(id) {
-                // If `id` is in `result`, then `ancestors(id)` are all in `result`.
-                continue;
-            }
-            trace(&|| format!(" lookup {:?}", id));
-            let flat_seg = self.find_flat_segment_including_id(id)?;
-            if let Some(ref s) = flat_seg {
-                if s.only_head()? {
-                    // Fast path.
-                    trace(&|| format!(" push ..={:?} (only head fast path)", id));
-                    result.push_span((Id::MIN..=id).into());
-                    break 'outer;
-                }
-            }
-            for level in (1..=max_level).rev() {
-                let seg = self.find_segment_by_head_and_level(id, level)?;
-                if let Some(seg) = seg {
-                    let span = seg.span()?.into();
-                    trace(&|| format!(" push lv{} {:?}", level, &span));
-                    result.push_span(span);
-                    let parents = seg.parents()?;
-                    trace(&|| format!(" follow parents {:?}", &parents));
-                    for parent in parents {
-                        to_visit.push(parent);
+    /// Useful to report the health of the graph, and decide whether
+    /// high-level segments need to be rebuilt.
+    ///
+    /// Complexity: `O(segments)`.
+    fn segment_stats(&self) -> Result<SegmentStats> {
+        let mut levels = Vec::new();
+        let mut flat_id_count = 0;
+        for level in 0..=self.max_level()? {
+            let mut stats = LevelSegmentStats {
+                level,
+                ..Default::default()
+            };
+            let mut last_high: Option<Id> = None;
+            for seg in self.iter_segments_ascending(Id::MIN, level)? {
+                let seg = seg?;
+                let span = seg.span()?;
+                stats.segment_count += 1;
+                stats.id_count += span.count();
+                stats.max_parent_count = stats.max_parent_count.max(seg.parent_count()?);
+                if let Some(high) = last_high {
+                    if high.group() == span.low.group() && high + 1 != span.low {
+                        stats.gap_count += 1;
                     }
-                    continue 'outer;
                 }
+                last_high = Some(span.high);
             }
-            if let Some(seg) = flat_seg {
-                let span = (seg.span()?.low..=id).into();
-                trace(&|| format!(" push lv0 {:?}", &span));
-                result.push_span(span);
-                let parents = seg.parents()?;
-                trace(&|| format!(" follow parents {:?}", &parents));
-                for parent in parents {
-                    to_visit.push(parent);
-                }
+            if stats.segment_count > 0 {
+                stats.average_span_length = stats.id_count as f64 / stats.segment_count as f64;
+            }
+            if level == 0 {
+                flat_id_count = stats.id_count;
             } else {
-                return bug("flat segments are expected to cover everything but they are not");
+                stats.uncovered_id_count = flat_id_count.saturating_sub(stats.id_count);
             }
+            levels.push(stats);
         }
+        Ok(SegmentStats { levels })
+    }
 
-        trace(&|| format!(" result: {:?}", &result));
-
+    /// Find long linear chains in the `NON_MASTER` group that other ids were
+    /// built on top of.
+    ///
+    /// Such chains are unlikely to be rewritten, and are good candidates to
+    /// be moved to the `MASTER` group, where ids are not fragmented. Heads of
+    /// the `NON_MASTER` group are excluded. Chains shorter than `min_length`
+    /// ids are ignored.
+    ///
+    /// Complexity: `O(flat segments in the NON_MASTER group)`.
+    fn suggest_master_candidates(&self, min_length: u64) -> Result<IdSet> {
+        let non_master = self.all_ids_in_groups(&[Group::NON_MASTER])?;
+        let heads = self.heads(non_master)?;
+        let mut result = IdSet::empty();
+        let mut push_chain = |mut chain: IdSpan| {
+            if heads.contains(chain.high) {
+                if chain.low == chain.high {
+                    return;
+                }
+                chain.high = chain.high - 1;
+            }
+            if chain.count() >= min_length {
+                result.push(chain);
+            }
+        };
+        // Flat segments are linear. Adjacent segments continuing each other
+        // are parts of a same chain.
+        let mut chain: Option<IdSpan> = None;
+        for seg in self.iter_segments_ascending(Group::NON_MASTER.min_id(), 0)? {
+            let seg = seg?;
+            let span = seg.span()?;
+            if span.low.group() != Group::NON_MASTER {
+                break;
+            }
+            chain = match chain {
+                Some(mut current)
+                    if span.low == current.high + 1 && seg.parents()? == [current.high] =>
+                {
+                    current.high = span.high;
+                    Some(current)
+                }
+                Some(current) => {
+                    push_chain(current);
+                    Some(span)
+                }
+                None => Some(span),
+            };
+        }
+        if let Some(current) = chain {
+            push_chain(current);
+        }
         Ok(result)
     }
 
+    /// Calculate all ancestors reachable from any id from the given set.
+    ///
+    /// ```plain,ignore
+    /// union(ancestors(i) for i in set)
+    /// ```
+    fn ancestors(&self, set: IdSet) -> Result<IdSet> {
+        debug!(target: "dag::algo::ancestors", "ancestors({:?})", &set);
+        ancestors_excluding(self, set, &IdSet::empty())
+    }
+
     /// Like `ancestors` but follows only the first parents.
     fn first_ancestors(&self, set: IdSet) -> Result<IdSet> {
         fn trace(msg: &dyn Fn() -> String) {
@@ -825,6 +865,69 @@ pub trait IdDagAlgorithm: IdDagStore {
         }
     }
 
+    /// Get parents of each id in `ids`. Preserve the order of `ids` and of the parents.
+    ///
+    /// Faster than calling `parent_ids` for each id. Ids are visited in
+    /// ascending order so a flat segment is looked up once for all the ids
+    /// it covers.
+    fn parent_ids_batch(&self, ids: &[Id]) -> Result<Vec<Vec<Id>>> {
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.sort_unstable_by_key(|&i| ids[i]);
+        let mut result = vec![Vec::new(); ids.len()];
+        let mut last: Option<(IdSpan, Segment)> = None;
+        for i in order {
+            let id = ids[i];
+            if !matches!(&last, Some((span, _)) if span.low <= id && id <= span.high) {
+                let seg = match self.find_flat_segment_including_id(id)? {
+                    Some(seg) => seg,
+                    None => return id.not_found(),
+                };
+                last = Some((seg.span()?, seg));
+            }
+            if let Some((span, seg)) = &last {
+                result[i] = if id == span.low {
+                    seg.parents()?
+                } else {
+                    vec![id - 1]
+                };
+            }
+        }
+        Ok(result)
+    }
+
+    /// Get children of each id in `ids`, in ascending order. Preserve the
+    /// order of `ids`.
+    ///
+    /// Like `parent_ids_batch`, a flat segment is looked up once for all the
+    /// ids it covers.
+    fn children_ids_batch(&self, ids: &[Id]) -> Result<Vec<Vec<Id>>> {
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.sort_unstable_by_key(|&i| ids[i]);
+        let mut result = vec![Vec::new(); ids.len()];
+        let mut last: Option<IdSpan> = None;
+        for i in order {
+            let id = ids[i];
+            if !matches!(&last, Some(span) if span.low <= id && id <= span.high) {
+                let seg = match self.find_flat_segment_including_id(id)? {
+                    Some(seg) => seg,
+                    None => return id.not_found(),
+                };
+                last = Some(seg.span()?);
+            }
+            let mut children = BTreeSet::new();
+            for seg in self.iter_flat_segments_with_parent(id)? {
+                children.insert(seg?.low()?);
+            }
+            if let Some(span) = &last {
+                if span.high != id {
+                    children.insert(id + 1);
+                }
+            }
+            result[i] = children.into_iter().collect();
+        }
+        Ok(result)
+    }
+
     /// Calculate the n-th first ancestor. If `n` is 0, return `id` unchanged.
     /// If `n` is 1, return the first parent of `id`.
     fn first_ancestor_nth(&self, id: Id, n: u64) -> Result<Id> {
@@ -1332,6 +1435,25 @@ pub trait IdDagAlgorithm: IdDagStore {
         Ok(result)
     }
 
+    /// Calculate `ancestors(reachable) - ancestors(unreachable)`.
+    ///
+    /// This is cheaper than calculating both ancestors and taking the
+    /// difference, since the traversal from `reachable` stops at segments
+    /// that are ancestors of `unreachable`.
+    fn only(&self, reachable: IdSet, unreachable: IdSet) -> Result<IdSet> {
+        let (only, _) = self.only_both(reachable, unreachable)?;
+        Ok(only)
+    }
+
+    /// Calculate `ancestors(reachable) - ancestors(unreachable)`, and
+    /// `ancestors(unreachable)`.
+    fn only_both(&self, reachable: IdSet, unreachable: IdSet) -> Result<(IdSet, IdSet)> {
+        debug!(target: "dag::algo::only", "only({:?}, {:?})", &reachable, &unreachable);
+        let unreachable = self.ancestors(unreachable)?;
+        let only = ancestors_excluding(self, reachable, &unreachable)?;
+        Ok((only, unreachable))
+    }
+
     /// Calculate the "dag range" - ids reachable from both sides.
     ///
     /// ```plain,ignore
@@ -1382,11 +1504,71 @@ pub trait IdDagAlgorithm: IdDagStore {
     fn descendants(&self, set: IdSet) -> Result<IdSet> {
         debug!(target: "dag::algo::descendants", "descendants({:?})", &set);
         let roots = set;
-        let result = self.descendants_intersection(&roots, &self.all()?)?;
+        let result = self.descendants_intersection(&roots, &self.master_group()?)?;
+        let result = result.union(&self.non_master_descendants(&roots, &result)?);
+
+        #[cfg(test)]
+        {
+            let expected = self.descendants_intersection(&roots, &self.all()?)?;
+            assert_eq!(result.as_spans(), expected.as_spans());
+        }
+
         trace!(target: "dag::algo::descendants", " result: {:?}", &result);
         Ok(result)
     }
 
+    /// Calculate the non-master part of `descendants(roots)`.
+    ///
+    /// `master_descendants` is the master part of `descendants(roots)`.
+    ///
+    /// Unlike `descendants_intersection`, this does not scan all non-master
+    /// segments. Instead, it follows the parent-child index of flat segments
+    /// from the roots. This is O(visited flat segments). The non-master group
+    /// can have lots of heads that are unrelated to `roots`.
+    fn non_master_descendants(&self, roots: &IdSet, master_descendants: &IdSet) -> Result<IdSet> {
+        fn trace(msg: &dyn Fn() -> String) {
+            trace!(target: "dag::algo::descendants", "{}", msg());
+        }
+
+        let non_master = IdSet::from(Group::NON_MASTER.min_id()..=Group::NON_MASTER.max_id());
+        let mut result = IdSet::empty();
+        // Spans in the result set. Their children need to be visited.
+        let mut to_visit: Vec<IdSpan> = master_descendants.as_spans().iter().copied().collect();
+
+        // Non-master roots and the rest of their flat segments.
+        for span in roots.intersection(&non_master).as_spans() {
+            let mut id = span.low;
+            while id <= span.high {
+                let seg = match self.find_flat_segment_including_id(id)? {
+                    Some(seg) => seg,
+                    None => break,
+                };
+                let seg_span = seg.span()?;
+                let span = IdSpan::from(id..=seg_span.high);
+                trace(&|| format!(" root {:?}", &span));
+                result.push(span);
+                to_visit.push(span);
+                id = seg_span.high + 1;
+            }
+        }
+
+        // Children of visited spans.
+        while let Some(span) = to_visit.pop() {
+            for entry in self.iter_flat_segments_with_parent_span(span)? {
+                let (_parent_id, child_seg) = entry?;
+                let child_span = child_seg.span()?;
+                if child_span.low.group() != Group::NON_MASTER || result.contains(child_span.low) {
+                    continue;
+                }
+                trace(&|| format!("  push {:?}", &child_span));
+                result.push(child_span);
+                to_visit.push(child_span);
+            }
+        }
+
+        Ok(result)
+    }
+
     /// Calculate (descendants(roots) & ancestors).
     ///
     /// This is O(flat segments), or O(merges).
@@ -1664,6 +1846,80 @@ pub trait IdDagAlgorithm: IdDagStore {
 
 impl<S: IdDagStore> IdDagAlgorithm for S {}
 
+/// Calculate `ancestors(set) - excluded`. `excluded` must be ancestor-closed,
+/// that is, `ancestors(excluded)` is `excluded`. The traversal does not go
+/// past ids in `excluded`.
+fn ancestors_excluding(
+    this: &(impl IdDagAlgorithm + ?Sized),
+    mut set: IdSet,
+    excluded: &IdSet,
+) -> Result<IdSet> {
+    fn trace(msg: &dyn Fn() -> String) {
+        trace!(target: "dag::algo::ancestors", "{}", msg());
+    }
+    if set.count() > 2 {
+        // Try to (greatly) reduce the size of the `set` to make calculation cheaper.
+        set = this.heads_ancestors(set)?;
+        trace(&|| format!("simplified to {:?}", &set));
+    }
+    let mut result = IdSet::empty();
+    let mut to_visit: BinaryHeap<_> = set.iter_desc().collect();
+    let max_level = this.max_level()?;
+    'outer: while let Some(id) = to_visit.pop() {
+        if result.contains(id) {
+            // If `id` is in `result`, then `ancestors(id)` are all in `result`.
+            continue;
+        }
+        if excluded.contains(id) {
+            // `ancestors(id)` are all in `excluded`.
+            continue;
+        }
+        trace(&|| format!(" lookup {:?}", id));
+        let flat_seg = this.find_flat_segment_including_id(id)?;
+        if let Some(ref s) = flat_seg {
+            if s.only_head()? {
+                // Fast path.
+                trace(&|| format!(" push ..={:?} (only head fast path)", id));
+                result.push_span((Id::MIN..=id).into());
+                break 'outer;
+            }
+        }
+        for level in (1..=max_level).rev() {
+            let seg = this.find_segment_by_head_and_level(id, level)?;
+            if let Some(seg) = seg {
+                let span = seg.span()?.into();
+                trace(&|| format!(" push lv{} {:?}", level, &span));
+                result.push_span(span);
+                let parents = seg.parents()?;
+                trace(&|| format!(" follow parents {:?}", &parents));
+                for parent in parents {
+                    to_visit.push(parent);
+                }
+                continue 'outer;
+            }
+        }
+        if let Some(seg) = flat_seg {
+            let span = (seg.span()?.low..=id).into();
+            trace(&|| format!(" push lv0 {:?}", &span));
+            result.push_span(span);
+            let parents = seg.parents()?;
+            trace(&|| format!(" follow parents {:?}", &parents));
+            for parent in parents {
+                to_visit.push(parent);
+            }
+        } else {
+            return bug("flat segments are expected to cover everything but they are not");
+        }
+    }
+
+    if !excluded.is_empty() {
+        result = result.difference(excluded);
+    }
+    trace(&|| format!(" result: {:?}", &result));
+
+    Ok(result)
+}
+
 impl<Store: IdDagStore> Deref for IdDag<Store> {
     type Target = dyn IdDagAlgorithm;
 
@@ -1762,6 +2018,23 @@ impl<Store: IdDagStore> IdDag<Store> {
         self.store.remove_non_master()
     }
 
+    /// Rebuild all high-level segments with the given segment size and
+    /// maximum level. Flat segments, and therefore `Id`s, are preserved.
+    ///
+    /// Return number of high-level segments inserted.
+    pub fn rebuild_high_level_segments(
+        &mut self,
+        segment_size: usize,
+        max_level: Level,
+    ) -> Result<usize> {
+        // Non-append-only change. Use a new incompatible version.
+        self.version = VerLink::new();
+        self.set_new_segment_size(segment_size);
+        self.store.remove_high_level_segments()?;
+        let flat_id_set = self.all()?;
+        self.build_all_high_level_segments(max_level, flat_id_set)
+    }
+
     /// Remove `set` and their descendants. Return `descendents(set)`.
     ///
     /// The returned `descendants(set)` is usually used to remove
@@ -2049,6 +2322,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parent_children_ids_batch() {
+        let mut dag = IdDag::new_in_process();
+        dag.build_segments(Id(100), &get_parents).unwrap();
+
+        let ids = [Id(10), Id(3), Id(2), Id(10), Id(5)];
+        let parents = dag.parent_ids_batch(&ids).unwrap();
+        for (id, parents) in ids.iter().zip(parents) {
+            assert_eq!(parents, dag.parent_ids(*id).unwrap());
+        }
+        let children = dag.children_ids_batch(&ids).unwrap();
+        for (id, children) in ids.iter().zip(children) {
+            let expected: Vec<Id> = dag.children_id(*id).unwrap().iter_asc().collect();
+            assert_eq!(children, expected);
+        }
+        assert!(dag.parent_ids_batch(&[Id(1000)]).is_err());
+    }
+
     #[test]
     fn test_all() {
         let dir = tempdir().unwrap();
@@ -2112,6 +2403,104 @@ mod tests {
         assert_eq!(subset_flat_segments.segments.len(), 3);
     }
 
+    #[test]
+    fn test_segment_stats() {
+        let mut dag = IdDag::new_in_process();
+        let stats = dag.segment_stats().unwrap();
+        assert_eq!(stats.levels.len(), 1);
+        assert_eq!(stats.levels[0].segment_count, 0);
+
+        let prepared = PreparedFlatSegments {
+            segments: vec![
+                FlatSegment {
+                    low: Id(0),
+                    high: Id(10),
+                    parents: vec![],
+                },
+                FlatSegment {
+                    low: Id(11),
+                    high: Id(15),
+                    parents: vec![Id(5)],
+                },
+                FlatSegment {
+                    low: Id(20),
+                    high: Id(30),
+                    parents: vec![Id(10), Id(15)],
+                },
+            ]
+            .into_iter()
+            .collect(),
+        };
+        dag.set_new_segment_size(2);
+        dag.build_segments_from_prepared_flat_segments(&prepared)
+            .unwrap();
+
+        let stats = dag.segment_stats().unwrap();
+        let flat = &stats.levels[0];
+        assert_eq!(flat.segment_count, 3);
+        assert_eq!(flat.id_count, 27);
+        assert_eq!(flat.average_span_length, 9.0);
+        assert_eq!(flat.max_parent_count, 2);
+        assert_eq!(flat.gap_count, 1);
+        assert_eq!(flat.uncovered_id_count, 0);
+        for level_stats in &stats.levels[1..] {
+            assert_eq!(
+                level_stats.uncovered_id_count,
+                flat.id_count - level_stats.id_count
+            );
+        }
+    }
+
+    #[test]
+    fn test_suggest_master_candidates() {
+        let mut dag = IdDag::new_in_process();
+        let n = Group::NON_MASTER.min_id();
+        let prepared = PreparedFlatSegments {
+            segments: vec![
+                FlatSegment {
+                    low: Id(0),
+                    high: Id(10),
+                    parents: vec![],
+                },
+                // A long chain: n..=n+19, split in 2 segments.
+                FlatSegment {
+                    low: n,
+                    high: n + 9,
+                    parents: vec![Id(10)],
+                },
+                FlatSegment {
+                    low: n + 10,
+                    high: n + 19,
+                    parents: vec![n + 9],
+                },
+                // A short branch from the middle of the chain.
+                FlatSegment {
+                    low: n + 20,
+                    high: n + 22,
+                    parents: vec![n + 5],
+                },
+                // Work on top of the chain.
+                FlatSegment {
+                    low: n + 23,
+                    high: n + 25,
+                    parents: vec![n + 19],
+                },
+            ]
+            .into_iter()
+            .collect(),
+        };
+        dag.build_segments_from_prepared_flat_segments(&prepared)
+            .unwrap();
+
+        // The branches are too short once their heads are excluded.
+        let candidates = dag.suggest_master_candidates(5).unwrap();
+        assert_eq!(candidates.as_spans(), &[IdSpan::new(n, n + 19)]);
+        let candidates = dag.suggest_master_candidates(2).unwrap();
+        assert_eq!(candidates.count(), 24);
+        assert!(!candidates.contains(n + 22));
+        assert!(dag.suggest_master_candidates(30).unwrap().is_empty());
+    }
+
     #[test]
     fn test_discontinous_flat_segment_only_head() {
         let prepared = PreparedFlatSegments {
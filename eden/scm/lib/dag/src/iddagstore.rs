@@ -276,6 +276,9 @@ pub trait IdDagStore: Send + Sync + 'static {
     /// Remove all non master Group identifiers from the DAG.
     fn remove_non_master(&mut self) -> Result<()>;
 
+    /// Remove all high-level segments. Flat segments are not affected.
+    fn remove_high_level_segments(&mut self) -> Result<()>;
+
     /// Attempt to merge the flat `segment` with the last flat segment to reduce
     /// fragmentation.
     ///
@@ -865,6 +868,33 @@ pub(crate) mod tests {
         );
     }
 
+    fn test_remove_high_level_segments(store: &mut dyn IdDagStore) {
+        let flat_segments = fmt_iter(store.iter_segments_descending(nid(6), 0).unwrap());
+        store.remove_high_level_segments().unwrap();
+
+        assert_eq!(store.max_level().unwrap(), 0);
+        assert!(
+            store
+                .find_segment_by_head_and_level(Id(13), 1 as Level)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            store
+                .find_segment_by_head_and_level(nid(6), 1 as Level)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            fmt_iter(store.iter_segments_descending(nid(6), 0).unwrap()),
+            flat_segments
+        );
+        assert_eq!(
+            fmt(store.all_ids_in_groups(&Group::ALL).unwrap()),
+            "0..=13 N0..=N6"
+        );
+    }
+
     pub(crate) fn test_remove_segment(store: &mut dyn IdDagStore) {
         // Prepare segments, 3 segments per group.
         let parents_nid_3_4 = [nid(4), nid(3)];
@@ -1150,6 +1180,11 @@ P->C: 50->N100, 50->N300"#
         for_each_store(|store| test_remove_non_master(store));
     }
 
+    #[test]
+    fn test_multi_stores_remove_high_level_segments() {
+        for_each_store(|store| test_remove_high_level_segments(store));
+    }
+
     #[test]
     fn test_multi_stores_discontinuous_merges() {
         for_each_empty_store(|store| test_discontinuous_merges(store));
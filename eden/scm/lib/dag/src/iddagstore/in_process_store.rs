@@ -10,6 +10,7 @@ use std::collections::BTreeSet;
 use std::fmt;
 use std::iter;
 use std::result::Result as StdResult;
+use std::sync::Arc;
 
 use serde::de::Error;
 use serde::de::SeqAccess;
@@ -33,6 +34,12 @@ use crate::Result;
 
 #[derive(Clone)]
 pub struct InProcessStore {
+    // Shared by clones. Copied on write so cloning (ex. for snapshots) is cheap.
+    inner: Arc<InProcessStoreInner>,
+}
+
+#[derive(Clone)]
+struct InProcessStoreInner {
     master_segments: Vec<Segment>,
     non_master_segments: Vec<Segment>,
     // level -> head -> serialized Segment
@@ -55,7 +62,7 @@ enum StoreId {
 
 impl IdDagStore for InProcessStore {
     fn max_level(&self) -> Result<Level> {
-        Ok((self.level_head_index.len().max(1) - 1) as Level)
+        Ok((self.inner.level_head_index.len().max(1) - 1) as Level)
     }
 
     fn find_segment_by_head_and_level(&self, head: Id, level: Level) -> Result<Option<Segment>> {
@@ -109,7 +116,7 @@ impl IdDagStore for InProcessStore {
 
             // Sanity check. This should pass since `last_store_id` should not be
             // obtained from indexes if it's already removed.
-            if self.removed_store_ids.contains(&last_store_id) {
+            if self.inner.removed_store_ids.contains(&last_store_id) {
                 return bug("insert_segment: reused removed store_id for segment merging");
             }
 
@@ -124,36 +131,37 @@ impl IdDagStore for InProcessStore {
             // No need to update "parents" index.
 
             // Update "covered" IdSet.
-            self.id_set_by_group[group.0].push(span);
+            self.inner_mut().id_set_by_group[group.0].push(span);
 
             return Ok(());
         }
 
+        let inner = self.inner_mut();
         let store_id = match high.group() {
             Group::MASTER => {
-                self.master_segments.push(segment);
-                StoreId::Master(self.master_segments.len() - 1)
+                inner.master_segments.push(segment);
+                StoreId::Master(inner.master_segments.len() - 1)
             }
             _ => {
-                self.non_master_segments.push(segment);
-                StoreId::NonMaster(self.non_master_segments.len() - 1)
+                inner.non_master_segments.push(segment);
+                StoreId::NonMaster(inner.non_master_segments.len() - 1)
             }
         };
         if level == 0 {
             for parent in parents {
-                let children = self
+                let children = inner
                     .parent_index
                     .entry((group, parent))
                     .or_insert_with(BTreeSet::new);
                 children.insert(span.low);
             }
             // Update "covered" IdSet.
-            self.id_set_by_group[group.0].push(span);
+            inner.id_set_by_group[group.0].push(span);
         }
         self.get_head_index_mut(level).insert(high, store_id);
 
         // Sanity check. This should pass because `store_id` is auto incremental.
-        if self.removed_store_ids.contains(&store_id) {
+        if self.inner.removed_store_ids.contains(&store_id) {
             return bug("insert_segment: reused removed store_id");
         }
 
@@ -162,9 +170,11 @@ impl IdDagStore for InProcessStore {
 
     fn remove_flat_segment_unchecked(&mut self, segment: &Segment) -> Result<()> {
         let span = segment.span()?;
-        for level in 0..=self.max_level()? {
+        let max_level = self.max_level()?;
+        let inner = self.inner_mut();
+        for level in 0..=max_level {
             // Remove from "level_head_index".
-            let index = match self.level_head_index.get_mut(level as usize) {
+            let index = match inner.level_head_index.get_mut(level as usize) {
                 Some(index) => index,
                 None => continue,
             };
@@ -179,7 +189,7 @@ impl IdDagStore for InProcessStore {
                 let child_group = child.group();
                 for parent in parents {
                     let index_key = (child_group, parent);
-                    let index = match self.parent_index.get_mut(&index_key) {
+                    let index = match inner.parent_index.get_mut(&index_key) {
                         Some(index) => index,
                         None => continue,
                     };
@@ -187,7 +197,7 @@ impl IdDagStore for InProcessStore {
                 }
             }
             // Mark as removed.
-            self.removed_store_ids.insert(store_id);
+            inner.removed_store_ids.insert(store_id);
         }
         // Update "id_set_by_group".
         //
@@ -198,7 +208,7 @@ impl IdDagStore for InProcessStore {
         // update lazy (i.e. track what id_set to remove, and update
         // `id_set_by_group` only when `id_set_by_group` needs to
         // be accessed.
-        let id_set = &mut self.id_set_by_group[span.low.group().0 as usize];
+        let id_set = &mut inner.id_set_by_group[span.low.group().0 as usize];
         *id_set = id_set.difference(&span.into());
         // Not updating self.non_master_segments and master_segments
         // to keep existing StoreIds valid. Removed entries will be
@@ -207,29 +217,40 @@ impl IdDagStore for InProcessStore {
     }
 
     fn remove_non_master(&mut self) -> Result<()> {
-        for segment in self.non_master_segments.iter() {
+        let inner = self.inner_mut();
+        for segment in inner.non_master_segments.iter() {
             let level = segment.level()?;
             let head = segment.head()?;
-            self.level_head_index
+            inner
+                .level_head_index
                 .get_mut(level as usize)
                 .map(|head_index| head_index.remove(&head));
         }
         let group = Group::NON_MASTER;
-        for (_key, children) in self
+        for (_key, children) in inner
             .parent_index
             .range_mut((group, group.min_id())..=(group, group.max_id()))
         {
             children.clear();
         }
-        self.non_master_segments = Vec::new();
-        self.id_set_by_group[Group::NON_MASTER.0] = IdSet::empty();
+        inner.non_master_segments = Vec::new();
+        inner.id_set_by_group[Group::NON_MASTER.0] = IdSet::empty();
+        Ok(())
+    }
+
+    fn remove_high_level_segments(&mut self) -> Result<()> {
+        let inner = self.inner_mut();
+        for head_index in inner.level_head_index.iter().skip(1) {
+            inner.removed_store_ids.extend(head_index.values().copied());
+        }
+        inner.level_head_index.truncate(1);
         Ok(())
     }
 
     fn all_ids_in_groups(&self, groups: &[Group]) -> Result<IdSet> {
         let mut result = IdSet::empty();
         for group in groups {
-            result = result.union(&self.id_set_by_group[group.0]);
+            result = result.union(&self.inner.id_set_by_group[group.0]);
         }
         Ok(result)
     }
@@ -288,20 +309,19 @@ impl IdDagStore for InProcessStore {
             Box::new(std::iter::empty());
         for group in Group::ALL {
             let range = (group, parent_span.low)..=(group, parent_span.high);
-            let group_iter =
-                self.parent_index
-                    .range(range)
-                    .flat_map(move |((_group, parent_id), child_ids)| {
-                        let parent_id = *parent_id;
-                        child_ids.iter().filter_map(move |&id| {
-                            let seg = match self.find_flat_segment_including_id(id) {
-                                Ok(Some(s)) => s,
-                                Err(e) => return Some(Err(e)),
-                                Ok(None) => return None,
-                            };
-                            Some(Ok((parent_id, seg)))
-                        })
-                    });
+            let group_iter = self.inner.parent_index.range(range).flat_map(
+                move |((_group, parent_id), child_ids)| {
+                    let parent_id = *parent_id;
+                    child_ids.iter().filter_map(move |&id| {
+                        let seg = match self.find_flat_segment_including_id(id) {
+                            Ok(Some(s)) => s,
+                            Err(e) => return Some(Err(e)),
+                            Ok(None) => return None,
+                        };
+                        Some(Ok((parent_id, seg)))
+                    })
+                },
+            );
             iter = Box::new(iter.chain(group_iter));
         }
         Ok(Box::new(iter))
@@ -312,7 +332,7 @@ impl IdDagStore for InProcessStore {
         parent: Id,
     ) -> Result<Box<dyn Iterator<Item = Result<Segment>> + 'a>> {
         let get_iter = |group: Group| -> Result<Box<dyn Iterator<Item = Result<_>> + 'a>> {
-            match self.parent_index.get(&(group, parent)) {
+            match self.inner.parent_index.get(&(group, parent)) {
                 None => Ok(Box::new(iter::empty())),
                 Some(children) => {
                     let iter = children.iter().filter_map(move |&id| {
@@ -350,41 +370,51 @@ impl Persist for InProcessStore {
 
 impl InProcessStore {
     fn get_head_index(&self, level: Level) -> Option<&BTreeMap<Id, StoreId>> {
-        self.level_head_index.get(level as usize)
+        self.inner.level_head_index.get(level as usize)
     }
 
     fn get_head_index_mut(&mut self, level: Level) -> &mut BTreeMap<Id, StoreId> {
-        if self.level_head_index.len() <= level as usize {
-            self.level_head_index
+        let inner = self.inner_mut();
+        if inner.level_head_index.len() <= level as usize {
+            inner
+                .level_head_index
                 .resize(level as usize + 1, BTreeMap::new());
         }
-        &mut self.level_head_index[level as usize]
+        &mut inner.level_head_index[level as usize]
     }
 
     fn get_segment(&self, store_id: &StoreId) -> Segment {
         match store_id {
-            &StoreId::Master(offset) => self.master_segments[offset].clone(),
-            &StoreId::NonMaster(offset) => self.non_master_segments[offset].clone(),
+            &StoreId::Master(offset) => self.inner.master_segments[offset].clone(),
+            &StoreId::NonMaster(offset) => self.inner.non_master_segments[offset].clone(),
         }
     }
 
     fn set_segment(&mut self, store_id: &StoreId, segment: Segment) {
+        let inner = self.inner_mut();
         match store_id {
-            &StoreId::Master(offset) => self.master_segments[offset] = segment,
-            &StoreId::NonMaster(offset) => self.non_master_segments[offset] = segment,
+            &StoreId::Master(offset) => inner.master_segments[offset] = segment,
+            &StoreId::NonMaster(offset) => inner.non_master_segments[offset] = segment,
         }
     }
+
+    fn inner_mut(&mut self) -> &mut InProcessStoreInner {
+        Arc::make_mut(&mut self.inner)
+    }
 }
 
 impl InProcessStore {
     pub fn new() -> Self {
-        InProcessStore {
+        let inner = InProcessStoreInner {
             master_segments: Vec::new(),
             non_master_segments: Vec::new(),
             level_head_index: Vec::new(),
             parent_index: BTreeMap::new(),
             id_set_by_group: [IdSet::empty(), IdSet::empty()],
             removed_store_ids: Default::default(),
+        };
+        InProcessStore {
+            inner: Arc::new(inner),
         }
     }
 }
@@ -395,16 +425,20 @@ impl Serialize for InProcessStore {
         S: Serializer,
     {
         let mut seq = serializer.serialize_seq(Some(
-            self.master_segments.len() + self.non_master_segments.len()
-                - self.removed_store_ids.len(),
+            self.inner.master_segments.len() + self.inner.non_master_segments.len()
+                - self.inner.removed_store_ids.len(),
         ))?;
-        for (i, e) in self.master_segments.iter().enumerate() {
-            if !self.removed_store_ids.contains(&StoreId::Master(i)) {
+        for (i, e) in self.inner.master_segments.iter().enumerate() {
+            if !self.inner.removed_store_ids.contains(&StoreId::Master(i)) {
                 seq.serialize_element(e)?;
             }
         }
-        for (i, e) in self.non_master_segments.iter().enumerate() {
-            if !self.removed_store_ids.contains(&StoreId::NonMaster(i)) {
+        for (i, e) in self.inner.non_master_segments.iter().enumerate() {
+            if !self
+                .inner
+                .removed_store_ids
+                .contains(&StoreId::NonMaster(i))
+            {
                 seq.serialize_element(e)?;
             }
         }
@@ -452,7 +486,7 @@ mod tests {
         // Test remove_flat_segment is still effective after serialize->deserialize.
         let mut store = InProcessStore::new();
         test_remove_segment(&mut store);
-        assert!(!store.removed_store_ids.is_empty());
+        assert!(!store.inner.removed_store_ids.is_empty());
 
         let all = store.all_ids_in_groups(&Group::ALL).unwrap();
         let old_state = dump_store_state(&store, &all);
@@ -460,9 +494,25 @@ mod tests {
         // Check store state after serialize -> deserialize round-trip.
         let data = mincode::serialize(&store).unwrap();
         let store: InProcessStore = mincode::deserialize(&data).unwrap();
-        assert!(store.removed_store_ids.is_empty());
+        assert!(store.inner.removed_store_ids.is_empty());
 
         let new_state = dump_store_state(&store, &all);
         assert_eq!(old_state, new_state);
     }
+
+    #[test]
+    fn test_clone_on_write() {
+        let mut store = InProcessStore::new();
+        test_remove_segment(&mut store);
+        let all = store.all_ids_in_groups(&Group::ALL).unwrap();
+        let old_state = dump_store_state(&store, &all);
+
+        // Clones share segments until one of them is changed.
+        let cloned = store.clone();
+        assert!(Arc::ptr_eq(&store.inner, &cloned.inner));
+        store.remove_non_master().unwrap();
+        assert!(!Arc::ptr_eq(&store.inner, &cloned.inner));
+        assert_eq!(dump_store_state(&cloned, &all), old_state);
+        assert_ne!(dump_store_state(&store, &all), old_state);
+    }
 }
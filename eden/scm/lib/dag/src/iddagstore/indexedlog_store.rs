@@ -383,6 +383,38 @@ impl IdDagStore for IndexedLogStore {
         }
         Ok(())
     }
+
+    fn remove_high_level_segments(&mut self) -> Result<()> {
+        // Only use entries that older versions understand. Removing a
+        // high-level segment also removes the indexes of flat segments sharing
+        // its head, root or ids. So remove all segments, then insert the flat
+        // segments again.
+        let max_level = self.max_level()?;
+        if max_level == 0 {
+            return Ok(());
+        }
+        let mut segments = Vec::new();
+        for level in 0..=max_level {
+            for seg in self.iter_segments_ascending(Id::MIN, level)? {
+                segments.push(seg?);
+            }
+        }
+        for seg in &segments {
+            let mut data =
+                Vec::with_capacity(seg.0.len() + Self::MAGIC_REMOVE_SEGMENT.len() + LEVEL_BYTES);
+            data.extend_from_slice(Self::MAGIC_REMOVE_SEGMENT);
+            data.push(max_level);
+            data.extend_from_slice(seg.0.as_ref());
+            self.log.append(data)?;
+        }
+        for seg in &segments {
+            if seg.level()? == 0 {
+                self.log.append(seg.0.as_ref())?;
+            }
+        }
+        self.cached_max_level.store(MAX_LEVEL_UNKNOWN, Release);
+        Ok(())
+    }
 }
 
 impl Persist for IndexedLogStore {
@@ -8,6 +8,7 @@
 use std::collections::BTreeMap;
 use std::sync::atomic;
 use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
 
 use super::IdMapWrite;
 use crate::errors::NotFoundError;
@@ -24,7 +25,8 @@ use crate::VerLink;
 ///
 /// Private. Stored in memory.
 pub struct MemIdMap {
-    core: CoreMemIdMap,
+    // Shared by clones. Copied on write so cloning (ex. for snapshots) is cheap.
+    core: Arc<CoreMemIdMap>,
     map_id: String,
     map_version: VerLink,
 }
@@ -51,7 +53,7 @@ impl MemIdMap {
 impl Clone for MemIdMap {
     fn clone(&self) -> Self {
         Self {
-            core: self.core.clone(),
+            core: Arc::clone(&self.core),
             map_id: self.map_id.clone(),
             map_version: self.map_version.clone(),
         }
@@ -171,13 +173,13 @@ impl IdConvert for MemIdMap {
 impl IdMapWrite for MemIdMap {
     async fn insert(&mut self, id: Id, name: &[u8]) -> Result<()> {
         let vertex_name = VertexName::copy_from(name);
-        self.core.insert_vertex_id_name(id, vertex_name);
+        Arc::make_mut(&mut self.core).insert_vertex_id_name(id, vertex_name);
         self.map_version.bump();
         Ok(())
     }
     async fn remove_range(&mut self, low: Id, high: Id) -> Result<Vec<VertexName>> {
         self.map_version = VerLink::new();
-        self.core.remove_range(low, high)
+        Arc::make_mut(&mut self.core).remove_range(low, high)
     }
 }
 
@@ -212,3 +214,24 @@ fn next_id() -> u64 {
     static ID: AtomicU64 = AtomicU64::new(0);
     ID.fetch_add(1, atomic::Ordering::AcqRel)
 }
+
+#[cfg(test)]
+mod tests {
+    use nonblocking::non_blocking_result as r;
+
+    use super::*;
+
+    #[test]
+    fn test_clone_on_write() {
+        let mut map = MemIdMap::new();
+        r(map.insert(Id(0), b"a")).unwrap();
+
+        // Clones share the map until one of them is changed.
+        let cloned = map.clone();
+        assert!(Arc::ptr_eq(&map.core, &cloned.core));
+        r(map.insert(Id(1), b"b")).unwrap();
+        assert!(!Arc::ptr_eq(&map.core, &cloned.core));
+        assert!(!r(cloned.contains_vertex_name(&"b".into())).unwrap());
+        assert!(r(map.contains_vertex_name(&"b".into())).unwrap());
+    }
+}
@@ -0,0 +1,452 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! # idset_map
+//!
+//! Fixed-size metadata per [`Id`], stored in memory-mapped files.
+//!
+//! Values are addressed by `Id` directly. Lookups are O(1) and do not need
+//! the `IdMap`. This is suitable for compact per-vertex attributes, like
+//! phases, obsolescence hints, or commit dates.
+//!
+//! Each [`Group`] uses a separate file:
+//!
+//! ```plain,ignore
+//! MAGIC (4B) + VALUE_SIZE (u32 BE) + RECORD * N
+//! RECORD := PRESENT (u8) + VERTEX_HASH (u64 BE, NON_MASTER only) + VALUE
+//! ```
+//!
+//! The N-th record is for the N-th `Id` in the group. Non-master `Id`s are
+//! re-assigned when the graph is flushed or stripped, so their records also
+//! store a hash of the vertex name to tell stale values apart. See
+//! [`IdSetMap::get_for_vertex`].
+//!
+//! Changes are buffered in memory and written by [`IdSetMap::flush`], which
+//! updates the changed records in place.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use byteorder::BigEndian;
+use byteorder::ByteOrder;
+use fs2::FileExt;
+use indexedlog::utils::atomic_write_plain;
+use indexedlog::utils::mmap_bytes;
+use minibytes::Bytes;
+use sha1::Digest;
+use sha1::Sha1;
+
+use crate::errors::programming;
+use crate::errors::BackendError;
+use crate::Group;
+use crate::Id;
+use crate::Result;
+use crate::VertexName;
+
+/// Fixed-size values keyed by [`Id`], backed by memory-mapped files.
+///
+/// Ids far away from the start of their group take space for all the Ids
+/// before them.
+pub struct IdSetMap {
+    dir: PathBuf,
+    value_size: usize,
+
+    /// Memory-mapped files, including headers. Indexed by `Group`.
+    mmaps: Vec<Bytes>,
+
+    /// Changes not yet written to disk. `None` means removal.
+    pending: BTreeMap<Id, Option<PendingValue>>,
+
+    /// Groups to clear before applying `pending`.
+    pending_clear: [bool; Group::COUNT],
+}
+
+/// A value to write, and the hash of its vertex for non-master `Id`s.
+struct PendingValue {
+    vertex_hash: u64,
+    value: Box<[u8]>,
+}
+
+const MAGIC: &[u8] = b"IDSM";
+const HEADER_LEN: usize = MAGIC.len() + 4;
+const PRESENT: u8 = 1;
+const VERTEX_HASH_LEN: usize = 8;
+
+impl IdSetMap {
+    /// Open the map at the given directory. Create it on demand.
+    ///
+    /// `value_size` is the size of each value in bytes. It must match the
+    /// size used to create the map.
+    pub fn open(dir: impl AsRef<Path>, value_size: usize) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        if value_size > u32::MAX as usize {
+            return programming(format!("IdSetMap value size {} is too large", value_size));
+        }
+        fs::create_dir_all(&dir)?;
+        let mut map = Self {
+            dir,
+            value_size,
+            mmaps: Vec::new(),
+            pending: Default::default(),
+            pending_clear: Default::default(),
+        };
+        map.reload()?;
+        Ok(map)
+    }
+
+    /// Size of each value in bytes.
+    pub fn value_size(&self) -> usize {
+        self.value_size
+    }
+
+    /// Get the value of `id`. Return `None` if `id` has no value.
+    ///
+    /// Values of non-master `Id`s might have been set for vertexes that used
+    /// to have the `Id`. Use [`IdSetMap::get_for_vertex`] to skip them.
+    pub fn get(&self, id: Id) -> Option<&[u8]> {
+        self.get_with_hash(id).map(|(_, value)| value)
+    }
+
+    /// Get the value of `id`, if it was set for `vertex`.
+    pub fn get_for_vertex(&self, id: Id, vertex: &VertexName) -> Option<&[u8]> {
+        let (hash, value) = self.get_with_hash(id)?;
+        if id.group() == Group::NON_MASTER && hash != vertex_hash(vertex) {
+            return None;
+        }
+        Some(value)
+    }
+
+    /// Set the value of `id`. The value must be `value_size` bytes.
+    ///
+    /// The change is not written to disk until [`IdSetMap::flush`].
+    pub fn insert(&mut self, id: Id, value: &[u8]) -> Result<()> {
+        self.insert_with_hash(id, 0, value)
+    }
+
+    /// Set the value of `id`, which is assigned to `vertex`.
+    pub fn insert_for_vertex(&mut self, id: Id, vertex: &VertexName, value: &[u8]) -> Result<()> {
+        self.insert_with_hash(id, vertex_hash(vertex), value)
+    }
+
+    /// Remove the value of `id`.
+    pub fn remove(&mut self, id: Id) {
+        self.pending.insert(id, None);
+    }
+
+    /// Remove values of all `Id`s in the given group.
+    pub fn remove_group(&mut self, group: Group) {
+        self.pending_clear[group.0] = true;
+        let range = group.min_id()..=group.max_id();
+        self.pending.retain(|id, _| !range.contains(id));
+    }
+
+    /// Write pending changes to disk.
+    ///
+    /// Changed records are updated in place. A record is marked absent while
+    /// its value is written, so an interrupted flush does not leave partially
+    /// written values. Changes written by other `IdSetMap`s of the same
+    /// directory are preserved unless they are overwritten.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() && !self.pending_clear.iter().any(|&b| b) {
+            return Ok(());
+        }
+
+        let lock = self.lock()?;
+        // Pick up changes by other writers.
+        self.reload()?;
+
+        for group in Group::ALL {
+            let cleared = self.pending_clear[group.0];
+            let mut changes = self
+                .pending
+                .range(group.min_id()..=group.max_id())
+                .peekable();
+            if !cleared && changes.peek().is_none() {
+                continue;
+            }
+
+            let path = self.group_path(group);
+            if self.mmaps[group.0].is_empty() {
+                // The only time the file is replaced. It is not mapped yet.
+                atomic_write_plain(&path, &self.header(), false)?;
+            }
+            // Files are never truncated, since that fails on Windows while
+            // they are mapped.
+            let mut file = fs::OpenOptions::new().write(true).open(&path)?;
+            let len = self.mmaps[group.0].len();
+            if cleared && len > HEADER_LEN {
+                file.seek(SeekFrom::Start(HEADER_LEN as u64))?;
+                file.write_all(&vec![0; len - HEADER_LEN])?;
+            }
+
+            let record_size = self.record_size(group);
+            for (id, value) in changes {
+                let index = id.0 - group.min_id().0;
+                let start = HEADER_LEN as u64 + index * record_size as u64;
+                file.seek(SeekFrom::Start(start))?;
+                match value {
+                    Some(value) => {
+                        let mut record = Vec::with_capacity(record_size);
+                        record.push(0);
+                        if group == Group::NON_MASTER {
+                            record.extend_from_slice(&value.vertex_hash.to_be_bytes());
+                        }
+                        record.extend_from_slice(&value.value);
+                        file.write_all(&record)?;
+                        file.seek(SeekFrom::Start(start))?;
+                        file.write_all(&[PRESENT])?;
+                    }
+                    None => {
+                        if start < len as u64 {
+                            file.write_all(&[0])?;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.pending.clear();
+        self.pending_clear = Default::default();
+        self.reload()?;
+        drop(lock);
+        Ok(())
+    }
+
+    fn get_with_hash(&self, id: Id) -> Option<(u64, &[u8])> {
+        if let Some(value) = self.pending.get(&id) {
+            return value
+                .as_ref()
+                .map(|value| (value.vertex_hash, &value.value[..]));
+        }
+        let group = id.group();
+        if self.pending_clear[group.0] {
+            return None;
+        }
+        let record_size = self.record_size(group);
+        let index = usize::try_from(id.0 - group.min_id().0).ok()?;
+        let start = index.checked_mul(record_size)?.checked_add(HEADER_LEN)?;
+        let record = self.mmaps[group.0].get(start..start + record_size)?;
+        if record[0] != PRESENT {
+            return None;
+        }
+        if group == Group::NON_MASTER {
+            let hash = BigEndian::read_u64(&record[1..1 + VERTEX_HASH_LEN]);
+            Some((hash, &record[1 + VERTEX_HASH_LEN..]))
+        } else {
+            Some((0, &record[1..]))
+        }
+    }
+
+    fn insert_with_hash(&mut self, id: Id, vertex_hash: u64, value: &[u8]) -> Result<()> {
+        if value.len() != self.value_size {
+            return programming(format!(
+                "IdSetMap expects {}-byte values, got {} bytes for {:?}",
+                self.value_size,
+                value.len(),
+                id
+            ));
+        }
+        let value = PendingValue {
+            vertex_hash,
+            value: value.into(),
+        };
+        self.pending.insert(id, Some(value));
+        Ok(())
+    }
+
+    fn record_size(&self, group: Group) -> usize {
+        if group == Group::NON_MASTER {
+            1 + VERTEX_HASH_LEN + self.value_size
+        } else {
+            1 + self.value_size
+        }
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&(self.value_size as u32).to_be_bytes());
+        header
+    }
+
+    fn group_path(&self, group: Group) -> PathBuf {
+        self.dir.join(format!("group{}", group.0))
+    }
+
+    fn lock(&self) -> Result<File> {
+        let path = self.dir.join("wlock");
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        file.lock_exclusive()?;
+        Ok(file)
+    }
+
+    /// Re-read files from disk. Pending changes are kept.
+    fn reload(&mut self) -> Result<()> {
+        let mut mmaps = Vec::with_capacity(Group::COUNT);
+        for group in Group::ALL {
+            let path = self.group_path(group);
+            let bytes = match File::open(&path) {
+                Ok(file) => mmap_bytes(&file, None)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Bytes::new(),
+                Err(e) => return Err(e.into()),
+            };
+            if !bytes.is_empty() {
+                self.check_file(&path, &bytes)?;
+            }
+            mmaps.push(bytes);
+        }
+        self.mmaps = mmaps;
+        Ok(())
+    }
+
+    fn check_file(&self, path: &Path, bytes: &[u8]) -> Result<()> {
+        let error = |msg: String| -> Result<()> {
+            Err(BackendError::Generic(format!("IdSetMap file {:?} {}", path, msg)).into())
+        };
+        if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+            return error("has an invalid header".to_string());
+        }
+        let value_size = BigEndian::read_u32(&bytes[MAGIC.len()..HEADER_LEN]) as usize;
+        if value_size != self.value_size {
+            return error(format!(
+                "has value size {}, expected {}",
+                value_size, self.value_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Hash of `vertex` stored with values of non-master `Id`s.
+fn vertex_hash(vertex: &VertexName) -> u64 {
+    let digest = Sha1::digest(vertex.as_ref());
+    BigEndian::read_u64(&digest[..VERTEX_HASH_LEN])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_get_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut map = IdSetMap::open(dir.path(), 2).unwrap();
+        assert_eq!(map.get(Id(0)), None);
+
+        map.insert(Id(3), b"ab").unwrap();
+        map.insert(Group::NON_MASTER.min_id() + 1, b"cd").unwrap();
+        assert_eq!(map.get(Id(3)), Some(&b"ab"[..]));
+        map.flush().unwrap();
+
+        let mut map = IdSetMap::open(dir.path(), 2).unwrap();
+        assert_eq!(map.get(Id(0)), None);
+        assert_eq!(map.get(Id(3)), Some(&b"ab"[..]));
+        assert_eq!(map.get(Id(4)), None);
+        assert_eq!(map.get(Group::NON_MASTER.min_id()), None);
+        assert_eq!(map.get(Group::NON_MASTER.min_id() + 1), Some(&b"cd"[..]));
+
+        // Remove values.
+        map.remove(Id(3));
+        map.remove(Id(100));
+        map.remove_group(Group::NON_MASTER);
+        assert_eq!(map.get(Id(3)), None);
+        assert_eq!(map.get(Group::NON_MASTER.min_id() + 1), None);
+        map.flush().unwrap();
+
+        let map = IdSetMap::open(dir.path(), 2).unwrap();
+        assert_eq!(map.get(Id(3)), None);
+        assert_eq!(map.get(Group::NON_MASTER.min_id() + 1), None);
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut map1 = IdSetMap::open(dir.path(), 1).unwrap();
+        let mut map2 = IdSetMap::open(dir.path(), 1).unwrap();
+
+        map1.insert(Id(1), b"x").unwrap();
+        map2.insert(Id(2), b"y").unwrap();
+        map1.flush().unwrap();
+        map2.flush().unwrap();
+
+        assert_eq!(map2.get(Id(1)), Some(&b"x"[..]));
+        assert_eq!(map2.get(Id(2)), Some(&b"y"[..]));
+    }
+
+    #[test]
+    fn test_non_master_vertex() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut map = IdSetMap::open(dir.path(), 1).unwrap();
+        let id = Group::NON_MASTER.min_id();
+        let a = VertexName::copy_from(b"a");
+        let b = VertexName::copy_from(b"b");
+        map.insert_for_vertex(id, &a, b"x").unwrap();
+        map.insert_for_vertex(Id(1), &a, b"y").unwrap();
+        map.flush().unwrap();
+
+        // The Id was re-assigned to another vertex.
+        let map = IdSetMap::open(dir.path(), 1).unwrap();
+        assert_eq!(map.get_for_vertex(id, &a), Some(&b"x"[..]));
+        assert_eq!(map.get_for_vertex(id, &b), None);
+        assert_eq!(map.get(id), Some(&b"x"[..]));
+
+        // Master Ids are not re-assigned.
+        assert_eq!(map.get_for_vertex(Id(1), &b), Some(&b"y"[..]));
+    }
+
+    #[test]
+    fn test_flush_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut map1 = IdSetMap::open(dir.path(), 1).unwrap();
+        map1.insert(Id(1), b"x").unwrap();
+        map1.insert(Id(2), b"y").unwrap();
+        map1.flush().unwrap();
+
+        // Flushing with the file mapped by other maps.
+        let mut map2 = IdSetMap::open(dir.path(), 1).unwrap();
+        map2.insert(Id(1), b"z").unwrap();
+        map2.remove(Id(2));
+        map2.insert(Id(5), b"w").unwrap();
+        map2.flush().unwrap();
+
+        let map3 = IdSetMap::open(dir.path(), 1).unwrap();
+        assert_eq!(map3.get(Id(1)), Some(&b"z"[..]));
+        assert_eq!(map3.get(Id(2)), None);
+        assert_eq!(map3.get(Id(5)), Some(&b"w"[..]));
+
+        // Clearing a group keeps the file size.
+        let len = fs::metadata(map2.group_path(Group::MASTER)).unwrap().len();
+        map2.remove_group(Group::MASTER);
+        map2.flush().unwrap();
+        assert_eq!(map2.get(Id(1)), None);
+        assert_eq!(map2.get(Id(5)), None);
+        let path = map2.group_path(Group::MASTER);
+        assert_eq!(fs::metadata(path).unwrap().len(), len);
+    }
+
+    #[test]
+    fn test_value_size_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut map = IdSetMap::open(dir.path(), 2).unwrap();
+        assert!(map.insert(Id(0), b"a").is_err());
+
+        map.insert(Id(0), b"ab").unwrap();
+        map.flush().unwrap();
+        let err = IdSetMap::open(dir.path(), 4).err().unwrap();
+        assert!(err.to_string().contains("has value size 2, expected 4"));
+    }
+}
@@ -11,6 +11,8 @@ use std::collections::BTreeSet;
 
 use futures::StreamExt;
 use futures::TryStreamExt;
+use serde::Deserialize;
+use serde::Serialize;
 
 use crate::iddag::IdDag;
 use crate::iddagstore::IdDagStore;
@@ -25,9 +27,31 @@ use crate::ops::TryClone;
 use crate::segment::SegmentFlags;
 use crate::Group;
 use crate::Id;
+use crate::IdSet;
 use crate::Result;
 use crate::VertexName;
 
+/// Problems found by [`CheckIntegrity::check_integrity`].
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    /// Universally known `Id`s that are missing locally.
+    /// See [`CheckIntegrity::check_universal_ids`].
+    pub missing_universal_ids: Vec<Id>,
+
+    /// Problems about segments. See [`CheckIntegrity::check_segments`].
+    pub segments: Vec<String>,
+
+    /// Problems about the `IdMap`. See [`CheckIntegrity::check_idmap`].
+    pub idmap: Vec<String>,
+}
+
+impl IntegrityReport {
+    /// Returns `true` if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.missing_universal_ids.is_empty() && self.segments.is_empty() && self.idmap.is_empty()
+    }
+}
+
 #[async_trait::async_trait]
 impl<IS, M, P, S> CheckIntegrity for AbstractNameDag<IdDag<IS>, M, P, S>
 where
@@ -168,6 +192,70 @@ where
         Ok(problems)
     }
 
+    async fn check_idmap(&self) -> Result<Vec<String>> {
+        let mut problems = Vec::new();
+        let all = self.dag.all()?;
+
+        for group in Group::ALL {
+            let group_span = IdSet::from_spans(vec![group.min_id()..=group.max_id()]);
+            let mut ids: Vec<Id> = all.intersection(&group_span).iter_asc().collect();
+            if group == Group::MASTER && self.is_vertex_lazy() {
+                // Only check what is known locally. Missing universally known
+                // `Id`s are reported by `check_universal_ids`.
+                let exists = self.map.contains_vertex_id_locally(&ids).await?;
+                ids = ids
+                    .into_iter()
+                    .zip(exists)
+                    .filter_map(|(id, b)| if b { Some(id) } else { None })
+                    .collect();
+            }
+            tracing::debug!("checking {} ids in {}", ids.len(), group);
+
+            // Id -> Name.
+            let mut found_ids = Vec::with_capacity(ids.len());
+            let mut found_names = Vec::with_capacity(ids.len());
+            for (id, name) in ids.iter().zip(self.map.vertex_name_batch(&ids).await?) {
+                match name {
+                    Ok(name) => {
+                        found_ids.push(*id);
+                        found_names.push(name);
+                    }
+                    Err(_) => problems.push(format!("{:?} in {} has no name", id, group)),
+                }
+            }
+
+            // Name -> Id. Should round-trip.
+            let resolved = self.map.vertex_id_batch(&found_names).await?;
+            for ((id, name), resolved) in found_ids.into_iter().zip(found_names).zip(resolved) {
+                match resolved {
+                    Ok(resolved) if resolved == id => {}
+                    Ok(resolved) if resolved.group() != group => problems.push(format!(
+                        "{:?} ({:?}) resolves to {:?} in a different group",
+                        &name, id, resolved
+                    )),
+                    Ok(resolved) => problems.push(format!(
+                        "{:?} ({:?}) resolves to a different {:?}",
+                        &name, id, resolved
+                    )),
+                    Err(_) => problems.push(format!(
+                        "{:?} ({:?}) cannot be resolved back to an id",
+                        &name, id
+                    )),
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    async fn check_integrity(&self) -> Result<IntegrityReport> {
+        Ok(IntegrityReport {
+            missing_universal_ids: self.check_universal_ids().await?,
+            segments: self.check_segments().await?,
+            idmap: self.check_idmap().await?,
+        })
+    }
+
     async fn check_isomorphic_graph(
         &self,
         other: &dyn DagAlgorithm,
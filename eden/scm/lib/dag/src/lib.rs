@@ -16,10 +16,14 @@ mod bsearch;
 mod default_impl;
 mod delegate;
 pub mod errors;
+pub mod export;
 mod fmt;
+pub mod git;
 mod iddag;
 pub mod iddagstore;
 pub mod idmap;
+#[cfg(any(test, feature = "indexedlog-backend"))]
+pub mod idset_map;
 mod integrity;
 pub mod namedag;
 pub mod nameset;
@@ -27,7 +31,7 @@ pub mod ops;
 pub mod protocol;
 pub mod render;
 pub mod segment;
-mod spanset;
+pub mod spanset;
 pub(crate) mod types_ext;
 pub mod utils;
 mod verlink;
@@ -47,13 +51,18 @@ pub use iddagstore::IdDagStore;
 #[cfg(any(test, feature = "indexedlog-backend"))]
 pub use idmap::IdMap;
 #[cfg(any(test, feature = "indexedlog-backend"))]
+pub use idset_map::IdSetMap;
+pub use integrity::IntegrityReport;
+#[cfg(any(test, feature = "indexedlog-backend"))]
 pub use namedag::NameDag;
 pub use namedag::NameDagBuilder;
 pub use nameset::NameSet;
 pub use ops::DagAlgorithm;
 pub use segment::FlatSegment;
 pub use segment::IdSegment;
+pub use segment::LevelSegmentStats;
 pub use segment::PreparedFlatSegments;
+pub use segment::SegmentStats;
 pub use verlink::VerLink;
 pub use vertex_options::VertexListWithOptions;
 pub use vertex_options::VertexOptions;
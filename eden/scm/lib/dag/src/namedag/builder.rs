@@ -91,6 +91,7 @@ where
 
             snapshot: Default::default(),
             pending_heads: Default::default(),
+            pending_promotions: Default::default(),
             persisted_id_set,
             overlay_map: Default::default(),
             overlay_map_id_set,
@@ -15,14 +15,20 @@ use indexedlog::OpenWithRepair;
 use super::AbstractNameDag;
 use super::NameDagBuilder;
 use crate::errors::bug;
+use crate::errors::programming;
+use crate::errors::BackendError;
 use crate::iddag::IdDag;
 use crate::iddagstore::IndexedLogStore;
 use crate::idmap::IdMap;
+use crate::idset_map::IdSetMap;
+use crate::ops::DagCheckpoint;
+use crate::ops::IdConvert;
 use crate::ops::IntVersion;
 use crate::ops::Open;
 use crate::ops::Persist;
 use crate::ops::TryClone;
 use crate::Result;
+use crate::VertexName;
 
 /// A DAG that uses VertexName instead of ids as vertexes.
 ///
@@ -81,6 +87,122 @@ impl NameDag {
         let path = IndexedLogNameDagPath(path);
         path.open()
     }
+
+    /// Quickly check whether the on-disk `NameDag` at `path` can be opened.
+    ///
+    /// This checks lock availability and that the files have headers this
+    /// version understands, without reading the graph. Intended to run at
+    /// command start so a problem (ex. the directory was written by a newer
+    /// version) is reported once, clearly, instead of surfacing as confusing
+    /// errors later.
+    pub fn probe(path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let problems = NameDag::default_open_options().probe(path);
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let msg = format!(
+            "NameDag at {:?} cannot be used:\n  {}",
+            path,
+            problems.join("\n  ")
+        );
+        Err(BackendError::Generic(msg).into())
+    }
+
+    /// Open a named per-vertex attribute table stored with the graph.
+    ///
+    /// `value_size` is the size of each value in bytes. It must be the same
+    /// each time the table is opened. See [`IdSetMap`] for details.
+    pub fn open_attribute_table(&self, name: &str, value_size: usize) -> Result<IdSetMap> {
+        let is_valid = !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+        if !is_valid {
+            return programming(format!("invalid attribute table name: {:?}", name));
+        }
+        IdSetMap::open(self.path.0.join("attrs").join(name), value_size)
+    }
+
+    /// Get the attribute of `vertex` from `table`.
+    /// Return `None` if the vertex does not have the attribute, including
+    /// when the attribute was set for another vertex that used to have the
+    /// same non-master `Id`.
+    pub async fn get_attribute<'a>(
+        &self,
+        table: &'a IdSetMap,
+        vertex: &VertexName,
+    ) -> Result<Option<&'a [u8]>> {
+        let id = self.vertex_id(vertex.clone()).await?;
+        Ok(table.get_for_vertex(id, vertex))
+    }
+
+    /// Set the attribute of `vertex` in `table`.
+    ///
+    /// Call [`IdSetMap::flush`] to write changes to disk. Non-master vertexes
+    /// can be assigned to different `Id`s by `flush` of the graph, which
+    /// drops their attributes.
+    pub async fn set_attribute(
+        &self,
+        table: &mut IdSetMap,
+        vertex: &VertexName,
+        value: &[u8],
+    ) -> Result<()> {
+        let id = self.vertex_id(vertex.clone()).await?;
+        table.insert_for_vertex(id, vertex, value)
+    }
+}
+
+impl DagCheckpoint for NameDag {
+    fn checkpoint(&mut self, name: &str) -> Result<()> {
+        let mlog = self.state.mlog_mut()?;
+        let lock = mlog.lock()?;
+        mlog.checkpoint(name, &lock)?;
+        Ok(())
+    }
+
+    fn rollback(&mut self, name: &str) -> Result<()> {
+        if !self.pending_heads.is_empty() {
+            return programming(format!(
+                "rollback does not support pending heads ({:?})",
+                &self.pending_heads.vertexes(),
+            ));
+        }
+
+        let mlog = self.state.mlog_mut()?;
+        let lock = mlog.lock()?;
+        mlog.rollback(name, &lock)?;
+        drop(lock);
+
+        // The in-memory IdMap and IdDag are newer than the on-disk state. Reload them.
+        let mut new = self.path.open()?;
+        new.set_remote_protocol(self.remote_protocol.clone());
+        *self = new;
+        Ok(())
+    }
+
+    fn remove_checkpoint(&mut self, name: &str) -> Result<()> {
+        let mlog = self.state.mlog_mut()?;
+        let lock = mlog.lock()?;
+        mlog.remove_checkpoint(name, &lock)?;
+        Ok(())
+    }
+
+    fn checkpoints(&self) -> Result<Vec<String>> {
+        match &self.state.mlog {
+            Some(mlog) => Ok(mlog.checkpoints()?),
+            None => bug("MultiLog should be Some for read-write NameDag"),
+        }
+    }
+}
+
+impl NameDagState {
+    fn mlog_mut(&mut self) -> Result<&mut multi::MultiLog> {
+        match self.mlog.as_mut() {
+            Some(mlog) => Ok(mlog),
+            None => bug("MultiLog should be Some for read-write NameDag"),
+        }
+    }
 }
 
 impl Persist for NameDagState {
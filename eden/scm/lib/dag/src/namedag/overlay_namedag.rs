@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use super::NameDag;
+use crate::delegate;
+use crate::ops::DagAddHeads;
+use crate::ops::DagAlgorithm;
+use crate::ops::Parents;
+use crate::NameSet;
+use crate::Result;
+use crate::VertexListWithOptions;
+
+/// A [`NameDag`] with an in-memory overlay for speculative vertexes.
+///
+/// Vertexes added by `add_heads` only exist in the overlay. They are never
+/// written to disk. Queries see both the persistent graph and the overlay.
+/// This is useful to answer ancestry questions about commits that do not
+/// exist yet, like a preview of a rebase.
+pub struct OverlayNameDag {
+    base: NameDag,
+    overlay: NameDag,
+}
+
+impl OverlayNameDag {
+    /// Create an empty overlay on top of `base`.
+    pub fn new(base: NameDag) -> Result<Self> {
+        let overlay = base.try_clone_graph()?;
+        Ok(Self { base, overlay })
+    }
+
+    /// The graph without the overlay.
+    pub fn base(&self) -> &NameDag {
+        &self.base
+    }
+
+    /// Vertexes added to the overlay.
+    pub async fn overlay_vertexes(&self) -> Result<NameSet> {
+        let base_all = self.base.all().await?;
+        Ok(self.overlay.all().await? - base_all)
+    }
+
+    /// Remove all vertexes added to the overlay.
+    pub fn discard(&mut self) -> Result<()> {
+        self.overlay = self.base.try_clone_graph()?;
+        Ok(())
+    }
+
+    /// Discard the overlay and return the graph without it.
+    pub fn into_base(self) -> NameDag {
+        self.base
+    }
+}
+
+#[async_trait::async_trait]
+impl DagAddHeads for OverlayNameDag {
+    /// Add vertexes and their ancestors to the overlay.
+    async fn add_heads(
+        &mut self,
+        parents: &dyn Parents,
+        heads: &VertexListWithOptions,
+    ) -> Result<bool> {
+        self.overlay.add_heads(parents, heads).await
+    }
+}
+
+delegate!(CheckIntegrity | IdConvert | IdMapSnapshot | PrefixLookup | DagAlgorithm, OverlayNameDag => self.overlay);
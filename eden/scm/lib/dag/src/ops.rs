@@ -15,10 +15,12 @@ use futures::TryStreamExt;
 use crate::clone::CloneData;
 use crate::default_impl;
 use crate::errors::NotFoundError;
+use crate::export::ExportFormat;
 use crate::id::Group;
 use crate::id::Id;
 use crate::id::VertexName;
 pub use crate::iddag::IdDagAlgorithm;
+use crate::integrity::IntegrityReport;
 use crate::namedag::MemNameDag;
 use crate::nameset::id_lazy::IdLazySet;
 use crate::nameset::id_static::IdStaticSet;
@@ -54,6 +56,18 @@ pub trait DagAlgorithm: Send + Sync {
     /// Get ordered parent vertexes.
     async fn parent_names(&self, name: VertexName) -> Result<Vec<VertexName>>;
 
+    /// Get ordered parent vertexes of each vertex in `names`.
+    /// The result has the same order as `names`.
+    async fn parents_batch(&self, names: &[VertexName]) -> Result<Vec<Vec<VertexName>>> {
+        default_impl::parents_batch(self, names).await
+    }
+
+    /// Get children of each vertex in `names`.
+    /// The result has the same order as `names`.
+    async fn children_batch(&self, names: &[VertexName]) -> Result<Vec<Vec<VertexName>>> {
+        default_impl::children_batch(self, names).await
+    }
+
     /// Returns a set that covers all vertexes tracked by this DAG.
     async fn all(&self) -> Result<NameSet>;
 
@@ -141,6 +155,40 @@ pub trait DagAlgorithm: Send + Sync {
     /// Calculates the "dag range" - vertexes reachable from both sides.
     async fn range(&self, roots: NameSet, heads: NameSet) -> Result<NameSet>;
 
+    /// Calculates a page of `range(roots, heads)`, in the same topological
+    /// order as `sort`.
+    ///
+    /// `cursor` is the last vertex of the previous page, or `None` for the
+    /// first page. It must be in the range. The page includes at most
+    /// `limit` vertexes after the cursor. A page with less than `limit`
+    /// vertexes is the last page.
+    async fn range_paged(
+        &self,
+        roots: NameSet,
+        heads: NameSet,
+        cursor: Option<VertexName>,
+        limit: u64,
+    ) -> Result<NameSet> {
+        default_impl::range_paged(self, roots, heads, cursor, limit).await
+    }
+
+    /// Suggests the vertex to test next for bisecting.
+    ///
+    /// `roots` are known good. `heads` are known bad. The first bad vertex is
+    /// in `only(heads, roots)`. The suggested vertex minimizes the worst-case
+    /// count of remaining candidates after testing it. Vertexes in `skip`
+    /// (ex. cannot be tested) are not suggested.
+    ///
+    /// Returns `None` if there is nothing left to test.
+    async fn suggest_bisect(
+        &self,
+        roots: NameSet,
+        heads: NameSet,
+        skip: NameSet,
+    ) -> Result<Option<VertexName>> {
+        default_impl::suggest_bisect(self, roots, heads, skip).await
+    }
+
     /// Calculates `ancestors(reachable) - ancestors(unreachable)`.
     async fn only(&self, reachable: NameSet, unreachable: NameSet) -> Result<NameSet> {
         default_impl::only(self, reachable, unreachable).await
@@ -318,11 +366,37 @@ pub trait DagStrip {
     async fn strip(&mut self, set: &NameSet) -> Result<()>;
 }
 
+/// Named checkpoints of the on-disk DAG, as a safety net for changes that
+/// are not append-only, like `strip`.
+pub trait DagCheckpoint {
+    /// Save the on-disk state of the DAG as `name`, replacing an existing
+    /// checkpoint with the same name. In-memory changes are not included.
+    fn checkpoint(&mut self, name: &str) -> Result<()>;
+
+    /// Restore the on-disk state saved by `checkpoint`, then reload `self`.
+    ///
+    /// Other processes using the DAG need to reopen it to see the change.
+    fn rollback(&mut self, name: &str) -> Result<()>;
+
+    /// Remove the checkpoint `name`, if it exists.
+    fn remove_checkpoint(&mut self, name: &str) -> Result<()>;
+
+    /// List names of existing checkpoints.
+    fn checkpoints(&self) -> Result<Vec<String>>;
+}
+
 /// Import a generated `CloneData` object into an empty DAG.
 #[async_trait::async_trait]
 pub trait DagImportCloneData {
     /// Updates the DAG using a `CloneData` object.
     async fn import_clone_data(&mut self, clone_data: CloneData<VertexName>) -> Result<()>;
+
+    /// Updates the DAG using `CloneData` in the portable format.
+    /// See [`crate::export`].
+    async fn import_portable_clone_data(&mut self, data: &[u8]) -> Result<()> {
+        let clone_data = crate::export::import_clone_data(data)?;
+        self.import_clone_data(clone_data).await
+    }
 }
 
 /// Import a generated incremental `CloneData` object into an existing DAG.
@@ -343,6 +417,13 @@ pub trait DagImportPullData {
 pub trait DagExportCloneData {
     /// Export `CloneData` for vertexes in the master group.
     async fn export_clone_data(&self) -> Result<CloneData<VertexName>>;
+
+    /// Export `CloneData` for vertexes in the master group in the portable
+    /// format. See [`crate::export`].
+    async fn export_portable_clone_data(&self, format: ExportFormat) -> Result<Vec<u8>> {
+        let clone_data = self.export_clone_data().await?;
+        crate::export::export_clone_data(&clone_data, format)
+    }
 }
 
 #[async_trait::async_trait]
@@ -489,6 +570,18 @@ pub trait CheckIntegrity {
     /// No messages indicates there are no problems detected.
     async fn check_segments(&self) -> Result<Vec<String>>;
 
+    /// Check that the `IdMap` is consistent with segments: `Id`s covered by
+    /// segments (that are known locally) can be translated to names and
+    /// back, and the translated `Id`s stay in the same group.
+    ///
+    /// Returns human readable messages about problems.
+    /// No messages indicates there are no problems detected.
+    async fn check_idmap(&self) -> Result<Vec<String>>;
+
+    /// Run `check_universal_ids`, `check_segments` and `check_idmap`.
+    /// This is the "fsck" of the graph.
+    async fn check_integrity(&self) -> Result<IntegrityReport>;
+
     /// Check that the subset of the current graph (ancestors of `heads`)
     /// is isomorphic with the subset in the `other` graph.
     ///
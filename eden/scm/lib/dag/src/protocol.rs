@@ -17,13 +17,22 @@
 //! - Name -> Id: Name -> RequestNameToLocation -> ResponseIdNamePair -> Id
 
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fmt;
+use std::sync::Arc;
 use std::thread_local;
+use std::time::Duration;
 
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use futures::future::Shared;
 use futures::stream;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
+use parking_lot::Mutex;
 
+use crate::errors::BackendError;
+use crate::errors::DagError;
 use crate::id::VertexName;
 use crate::iddag::FirstAncestorConstraint;
 use crate::iddag::IdDag;
@@ -156,6 +165,164 @@ impl RemoteIdConvertProtocol for () {
     }
 }
 
+/// Batches concurrent [`RemoteIdConvertProtocol::resolve_names_to_relative_paths`]
+/// calls into a single remote call.
+///
+/// The first call waits for `window`. Calls with the same `heads` made in the
+/// meantime join its batch, until the batch has `max_batch_size` names. Each
+/// caller only gets the results about the names it asked for, or the error
+/// of the batch. `resolve_relative_paths_to_names` is not batched.
+///
+/// Calls made outside a tokio runtime, which cannot wait for `window`, are
+/// not batched either.
+pub struct CoalescingProtocol {
+    inner: Arc<dyn RemoteIdConvertProtocol>,
+    window: Duration,
+    max_batch_size: usize,
+    pending: Arc<Mutex<Option<PendingBatch>>>,
+}
+
+type SharedResolveResult = Shared<
+    BoxFuture<'static, std::result::Result<Vec<(AncestorPath, Vec<VertexName>)>, Arc<DagError>>>,
+>;
+
+/// The batch new calls join, and its result.
+type PendingBatch = (Arc<NameBatch>, SharedResolveResult);
+
+/// Names to resolve in a single remote call.
+struct NameBatch {
+    heads: Vec<VertexName>,
+    names: Mutex<Vec<VertexName>>,
+}
+
+impl CoalescingProtocol {
+    /// Default number of names resolved in one remote call at most.
+    const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+
+    pub fn new(inner: Arc<dyn RemoteIdConvertProtocol>, window: Duration) -> Self {
+        Self {
+            inner,
+            window,
+            max_batch_size: Self::DEFAULT_MAX_BATCH_SIZE,
+            pending: Default::default(),
+        }
+    }
+
+    /// Set the number of names resolved in one remote call at most.
+    pub fn max_batch_size(mut self, size: usize) -> Self {
+        self.max_batch_size = size.max(1);
+        self
+    }
+
+    /// Add `names` to the pending batch, or start a new batch.
+    fn join_batch(&self, heads: Vec<VertexName>, names: &[VertexName]) -> SharedResolveResult {
+        let mut pending = self.pending.lock();
+        if let Some((batch, result)) = pending.as_ref() {
+            let mut batch_names = batch.names.lock();
+            if batch.heads == heads && batch_names.len() + names.len() <= self.max_batch_size {
+                batch_names.extend_from_slice(names);
+                return result.clone();
+            }
+        }
+
+        let batch = Arc::new(NameBatch {
+            heads,
+            names: Mutex::new(names.to_vec()),
+        });
+        let result = {
+            let batch = batch.clone();
+            let inner = self.inner.clone();
+            let window = self.window;
+            let pending = self.pending.clone();
+            async move {
+                tokio::time::sleep(window).await;
+                // Close the batch so new calls start another one.
+                {
+                    let mut pending = pending.lock();
+                    if matches!(pending.as_ref(), Some((b, _)) if Arc::ptr_eq(b, &batch)) {
+                        *pending = None;
+                    }
+                }
+                let names = std::mem::take(&mut *batch.names.lock());
+                inner
+                    .resolve_names_to_relative_paths(batch.heads.clone(), names)
+                    .await
+                    .map_err(Arc::new)
+            }
+            .boxed()
+            .shared()
+        };
+        *pending = Some((batch, result.clone()));
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl RemoteIdConvertProtocol for CoalescingProtocol {
+    async fn resolve_names_to_relative_paths(
+        &self,
+        heads: Vec<VertexName>,
+        names: Vec<VertexName>,
+    ) -> Result<Vec<(AncestorPath, Vec<VertexName>)>> {
+        if self.window.is_zero()
+            || names.len() >= self.max_batch_size
+            || tokio::runtime::Handle::try_current().is_err()
+        {
+            return self
+                .inner
+                .resolve_names_to_relative_paths(heads, names)
+                .await;
+        }
+        let result = self.join_batch(heads, &names).await;
+        let resolved = result.map_err(|e| batch_error(&e))?;
+        let names: HashSet<&VertexName> = names.iter().collect();
+        Ok(resolved
+            .into_iter()
+            .filter(|(_, resolved_names)| resolved_names.iter().any(|n| names.contains(n)))
+            .collect())
+    }
+
+    async fn resolve_relative_paths_to_names(
+        &self,
+        paths: Vec<AncestorPath>,
+    ) -> Result<Vec<(AncestorPath, Vec<VertexName>)>> {
+        self.inner.resolve_relative_paths_to_names(paths).await
+    }
+
+    fn is_local(&self) -> bool {
+        self.inner.is_local()
+    }
+}
+
+/// Give the error of a batch to one of its callers.
+fn batch_error(err: &Arc<DagError>) -> DagError {
+    match err.as_ref() {
+        DagError::VertexNotFound(name) => DagError::VertexNotFound(name.clone()),
+        DagError::IdNotFound(id) => DagError::IdNotFound(*id),
+        DagError::NeedSlowPath(msg) => DagError::NeedSlowPath(msg.clone()),
+        DagError::Programming(msg) => DagError::Programming(msg.clone()),
+        DagError::Bug(msg) => DagError::Bug(msg.clone()),
+        DagError::IdOverflow(group) => DagError::IdOverflow(*group),
+        DagError::Backend(_) => BackendError::Other(SharedError(err.clone()).into()).into(),
+    }
+}
+
+/// A backend error, which cannot be cloned, shared by the callers of a batch.
+#[derive(Debug)]
+struct SharedError(Arc<DagError>);
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for SharedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        std::error::Error::source(self.0.as_ref())
+    }
+}
+
 // Traits --------------------------------------------------------------------
 
 /// Similar to `From::from(I) -> O`, but with `self` as context.
@@ -29,6 +29,10 @@ pub use self::render::LinkLine;
 pub use self::render::NodeLine;
 pub use self::render::PadLine;
 pub use self::render::Renderer;
+#[cfg(any(test, feature = "indexedlog-backend"))]
+pub use self::render_utils::render_dot;
+#[cfg(any(test, feature = "indexedlog-backend"))]
+pub use self::render_utils::render_mermaid;
 pub use self::render_utils::render_namedag;
 #[cfg(any(test, feature = "indexedlog-backend"))]
 pub use self::render_utils::render_segment_dag;
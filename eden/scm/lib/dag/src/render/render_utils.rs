@@ -18,10 +18,14 @@ use super::render::Renderer;
 use crate::nameset::SyncNameSetQuery;
 #[cfg(any(test, feature = "indexedlog-backend"))]
 use crate::ops::IdConvert;
+#[cfg(any(test, feature = "indexedlog-backend"))]
+use crate::segment::SegmentFlags;
 use crate::DagAlgorithm;
 #[cfg(any(test, feature = "indexedlog-backend"))]
 use crate::Group;
 #[cfg(any(test, feature = "indexedlog-backend"))]
+use crate::Id;
+#[cfg(any(test, feature = "indexedlog-backend"))]
 use crate::IdSpan;
 #[cfg(any(test, feature = "indexedlog-backend"))]
 use crate::Level;
@@ -121,3 +125,95 @@ pub fn render_segment_dag(
 
     Ok(())
 }
+
+/// Vertexes and edges of a NameDag, grouped by level 0 segments.
+#[cfg(any(test, feature = "indexedlog-backend"))]
+struct SegmentGraph {
+    /// Segment labels and their vertexes as `(node, label)`, heads first.
+    segments: Vec<(String, Vec<(String, String)>)>,
+    /// Edges as `(child node, parent node)`.
+    edges: Vec<(String, String)>,
+}
+
+#[cfg(any(test, feature = "indexedlog-backend"))]
+impl SegmentGraph {
+    fn from_namedag(dag: &NameDag) -> Result<Self> {
+        let node = |id: Id| format!("v{}", id);
+        let label = |id: Id| match non_blocking_result(dag.vertex_name(id)) {
+            Ok(name) => format!("{:.12?} {}", name, id),
+            Err(_) => id.to_string(),
+        };
+
+        let mut segments = Vec::new();
+        let mut edges = Vec::new();
+        for &group in Group::ALL.iter() {
+            for seg in dag.dag.next_segments(group.min_id(), 0)?.into_iter().rev() {
+                let span = seg.span()?;
+                let flags = seg.flags()?;
+                let mut seg_label = format!("{}-{}", span.low, span.high);
+                if flags.contains(SegmentFlags::HAS_ROOT) {
+                    seg_label += " Root";
+                }
+                if flags.contains(SegmentFlags::ONLY_HEAD) {
+                    seg_label += " OnlyHead";
+                }
+                let vertexes = (span.low.0..=span.high.0)
+                    .rev()
+                    .map(|i| (node(Id(i)), label(Id(i))))
+                    .collect();
+                segments.push((seg_label, vertexes));
+
+                for i in span.low.0 + 1..=span.high.0 {
+                    edges.push((node(Id(i)), node(Id(i - 1))));
+                }
+                for parent in seg.parents()? {
+                    edges.push((node(span.low), node(parent)));
+                }
+            }
+        }
+
+        Ok(Self { segments, edges })
+    }
+}
+
+/// Render a NameDag in the Graphviz DOT format. Level 0 segments are
+/// rendered as clusters, labeled with their id ranges and flags.
+#[cfg(any(test, feature = "indexedlog-backend"))]
+pub fn render_dot(dag: &NameDag) -> Result<String> {
+    let escape = |s: &str| s.replace('"', "\\\"");
+    let graph = SegmentGraph::from_namedag(dag)?;
+    let mut out = String::from("digraph {\n  rankdir=BT;\n");
+    for (i, (seg_label, vertexes)) in graph.segments.iter().enumerate() {
+        out += &format!("  subgraph cluster_{} {{\n", i);
+        out += &format!("    label=\"{}\";\n", escape(seg_label));
+        for (node, label) in vertexes {
+            out += &format!("    {} [label=\"{}\"];\n", node, escape(label));
+        }
+        out += "  }\n";
+    }
+    for (child, parent) in graph.edges.iter() {
+        out += &format!("  {} -> {};\n", child, parent);
+    }
+    out += "}\n";
+    Ok(out)
+}
+
+/// Render a NameDag in the Mermaid flowchart format. Level 0 segments are
+/// rendered as subgraphs, labeled with their id ranges and flags.
+#[cfg(any(test, feature = "indexedlog-backend"))]
+pub fn render_mermaid(dag: &NameDag) -> Result<String> {
+    let escape = |s: &str| s.replace('"', "#quot;");
+    let graph = SegmentGraph::from_namedag(dag)?;
+    let mut out = String::from("flowchart BT\n");
+    for (i, (seg_label, vertexes)) in graph.segments.iter().enumerate() {
+        out += &format!("  subgraph s{} [\"{}\"]\n", i, escape(seg_label));
+        for (node, label) in vertexes {
+            out += &format!("    {}[\"{}\"]\n", node, escape(label));
+        }
+        out += "  end\n";
+    }
+    for (child, parent) in graph.edges.iter() {
+        out += &format!("  {} --> {}\n", child, parent);
+    }
+    Ok(out)
+}
@@ -57,6 +57,44 @@ pub struct IdSegment {
     pub level: Level,
 }
 
+/// Statistics about segments in an [`IdDag`]. See
+/// [`IdDagAlgorithm::segment_stats`](crate::IdDagAlgorithm::segment_stats).
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SegmentStats {
+    /// Statistics for each level, starting from the flat segments (level 0).
+    pub levels: Vec<LevelSegmentStats>,
+}
+
+/// Statistics about segments of a single level.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LevelSegmentStats {
+    pub level: Level,
+
+    /// Number of segments in this level.
+    pub segment_count: usize,
+
+    /// Number of ids covered by segments in this level.
+    pub id_count: u64,
+
+    /// Average number of ids covered by a segment in this level.
+    pub average_span_length: f64,
+
+    /// Maximum number of parents of a segment in this level.
+    pub max_parent_count: usize,
+
+    /// Number of gaps between adjacent segments in a same group.
+    ///
+    /// Gaps are caused by discontinuous id assignment, for example, after
+    /// `strip` or lazy pull.
+    pub gap_count: usize,
+
+    /// Number of ids covered by flat segments but not by this level.
+    ///
+    /// Always 0 for flat segments. A large value for a high level suggests
+    /// high-level segments are not built optimally.
+    pub uncovered_id_count: u64,
+}
+
 // Serialization format for Segment:
 //
 // ```plain,ignore
@@ -29,6 +29,8 @@ use serde::Serialize;
 use crate::bsearch::BinarySearchBy;
 use crate::id::Id;
 
+pub mod serialize;
+
 /// Range `low..=high`. `low` must be <= `high`.
 #[derive(Copy, Clone, Debug, Eq, Serialize, Deserialize)]
 pub struct Span {
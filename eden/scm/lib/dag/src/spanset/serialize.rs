@@ -0,0 +1,322 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+//! # serialize
+//!
+//! Compact serialization of [`SpanSet`]. Intended for exchanging sets, like
+//! the "common" set during discovery.
+//!
+//! ```plain,ignore
+//! VERSION (u8) + SPAN * N
+//! SPAN := GAP (VLQ) + LEN - 1 (VLQ)
+//! ```
+//!
+//! Spans are in ascending order. `GAP` is the distance from the end of the
+//! previous span (or `Id(0)` for the first span) to `low`.
+//!
+//! Serialized sets can be intersected or subtracted directly, by streaming
+//! spans without building `SpanSet`s.
+
+use vlqencoding::VLQDecodeAt;
+use vlqencoding::VLQEncode;
+
+use super::Span;
+use super::SpanSet;
+use crate::errors::BackendError;
+use crate::nameset::NameSet;
+use crate::ops::DagAlgorithm;
+use crate::ops::IdMapSnapshot;
+use crate::ops::ToIdSet;
+use crate::Id;
+use crate::Result;
+
+/// Version of the format.
+pub const VERSION: u8 = 1;
+
+/// Serialize `set`.
+pub fn serialize(set: &SpanSet) -> Vec<u8> {
+    let mut writer = SpanWriter::default();
+    for span in set.iter_span_asc() {
+        writer.push(*span);
+    }
+    writer.finish()
+}
+
+/// Deserialize a set from `data`.
+pub fn deserialize(data: &[u8]) -> Result<SpanSet> {
+    let mut spans = iter_span_asc(data)?.collect::<Result<Vec<Span>>>()?;
+    spans.reverse();
+    Ok(SpanSet::from_sorted_spans(spans))
+}
+
+/// Iterate spans of serialized `data` in ascending order.
+pub fn iter_span_asc(data: &[u8]) -> Result<impl Iterator<Item = Result<Span>> + '_> {
+    match data.first() {
+        Some(&VERSION) => {}
+        Some(v) => return Err(invalid(format!("unsupported version {}", v))),
+        None => return Err(invalid("empty data")),
+    }
+    Ok(SpanReader {
+        data,
+        offset: 1,
+        next_low: Some(0),
+    })
+}
+
+/// Calculate the intersection of serialized sets.
+pub fn intersection(lhs: &[u8], rhs: &[u8]) -> Result<Vec<u8>> {
+    let mut lhs = iter_span_asc(lhs)?.peekable();
+    let mut rhs = iter_span_asc(rhs)?.peekable();
+    let mut writer = SpanWriter::default();
+    loop {
+        let (l, r) = match (lhs.peek(), rhs.peek()) {
+            (Some(Ok(l)), Some(Ok(r))) => (*l, *r),
+            (Some(Err(_)), _) => return Err(lhs.next().unwrap().unwrap_err()),
+            (_, Some(Err(_))) => return Err(rhs.next().unwrap().unwrap_err()),
+            _ => break,
+        };
+        let low = l.low.max(r.low);
+        let high = l.high.min(r.high);
+        if low <= high {
+            writer.push(Span::new(low, high));
+        }
+        // Advance the span that ends first.
+        if l.high <= r.high {
+            lhs.next();
+        }
+        if r.high <= l.high {
+            rhs.next();
+        }
+    }
+    Ok(writer.finish())
+}
+
+/// Calculate `lhs - rhs` of serialized sets.
+pub fn difference(lhs: &[u8], rhs: &[u8]) -> Result<Vec<u8>> {
+    let mut rhs = iter_span_asc(rhs)?.peekable();
+    let mut writer = SpanWriter::default();
+    for l in iter_span_asc(lhs)? {
+        let l = l?;
+        // Remaining part of `l` to check. `None` means nothing is left.
+        let mut low = Some(l.low);
+        while let Some(current_low) = low {
+            let r = match rhs.peek() {
+                Some(Ok(r)) => *r,
+                Some(Err(_)) => return Err(rhs.next().unwrap().unwrap_err()),
+                None => break,
+            };
+            if r.high < current_low {
+                rhs.next();
+                continue;
+            }
+            if r.low > l.high {
+                break;
+            }
+            if r.low > current_low {
+                writer.push(Span::new(current_low, r.low - 1));
+            }
+            if r.high >= l.high {
+                // `r` might overlap with the next `l`. Do not advance it.
+                low = None;
+            } else {
+                low = Some(r.high + 1);
+                rhs.next();
+            }
+        }
+        if let Some(low) = low {
+            writer.push(Span::new(low, l.high));
+        }
+    }
+    Ok(writer.finish())
+}
+
+/// Serialize a [`NameSet`] using Ids from `dag`.
+pub async fn serialize_name_set(dag: &(impl ToIdSet + ?Sized), set: &NameSet) -> Result<Vec<u8>> {
+    let id_set = dag.to_id_set(set).await?;
+    Ok(serialize(&id_set))
+}
+
+/// Deserialize a [`NameSet`] using Ids from `dag`.
+pub fn deserialize_name_set(
+    dag: &(impl DagAlgorithm + IdMapSnapshot),
+    data: &[u8],
+) -> Result<NameSet> {
+    let id_set = deserialize(data)?;
+    NameSet::from_spans_dag(id_set, dag)
+}
+
+/// Decodes spans lazily.
+struct SpanReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    /// Lowest possible `low` of the next span. `None` means overflow.
+    next_low: Option<u64>,
+}
+
+impl<'a> SpanReader<'a> {
+    fn read_vlq(&mut self) -> Result<u64> {
+        let (value, len) = self
+            .data
+            .read_vlq_at(self.offset)
+            .map_err(|_| invalid(format!("truncated at offset {}", self.offset)))?;
+        self.offset += len;
+        Ok(value)
+    }
+
+    fn read_span(&mut self) -> Result<Span> {
+        let gap = self.read_vlq()?;
+        let len = self.read_vlq()?;
+        let low = self.next_low.and_then(|v| v.checked_add(gap));
+        let high = low.and_then(|v| v.checked_add(len));
+        match (low, high) {
+            (Some(low), Some(high)) if high <= Id::MAX.0 => {
+                self.next_low = high.checked_add(1);
+                Ok(Span::new(Id(low), Id(high)))
+            }
+            _ => Err(invalid(format!("overflow at offset {}", self.offset))),
+        }
+    }
+}
+
+impl<'a> Iterator for SpanReader<'a> {
+    type Item = Result<Span>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.data.len() {
+            return None;
+        }
+        let result = self.read_span();
+        if result.is_err() {
+            // Stop on errors.
+            self.offset = self.data.len();
+        }
+        Some(result)
+    }
+}
+
+/// Encodes spans pushed in ascending order. Adjacent spans are merged.
+struct SpanWriter {
+    data: Vec<u8>,
+    /// The span not yet written. It might be merged with the next span.
+    pending: Option<Span>,
+    /// `low` of the next span is relative to this.
+    next_low: u64,
+}
+
+impl Default for SpanWriter {
+    fn default() -> Self {
+        Self {
+            data: vec![VERSION],
+            pending: None,
+            next_low: 0,
+        }
+    }
+}
+
+impl SpanWriter {
+    fn push(&mut self, span: Span) {
+        match self.pending {
+            Some(pending) if pending.high + 1 == span.low => {
+                self.pending = Some(Span::new(pending.low, span.high));
+            }
+            _ => {
+                self.flush_pending();
+                self.pending = Some(span);
+            }
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        if let Some(span) = self.pending.take() {
+            debug_assert!(span.low.0 >= self.next_low);
+            self.data.write_vlq(span.low.0 - self.next_low).unwrap();
+            self.data.write_vlq(span.high.0 - span.low.0).unwrap();
+            self.next_low = span.high.0 + 1;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.flush_pending();
+        self.data
+    }
+}
+
+fn invalid(msg: impl ToString) -> crate::Error {
+    BackendError::Generic(format!("invalid serialized IdSet: {}", msg.to_string())).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(spans: &[(u64, u64)]) -> SpanSet {
+        SpanSet::from_spans(spans.iter().map(|&(l, h)| Span::new(Id(l), Id(h))))
+    }
+
+    #[test]
+    fn test_serialize_format() {
+        assert_eq!(serialize(&SpanSet::empty()), [VERSION]);
+        let data = serialize(&set(&[(3, 5), (10, 10), (200, 1000)]));
+        assert_eq!(data, [VERSION, 3, 2, 4, 0, 189, 1, 160, 6]);
+        assert_eq!(
+            format!("{:?}", deserialize(&data).unwrap()),
+            "3 4 5 10 200..=1000"
+        );
+
+        // Non-master Ids are large but still compact.
+        let non_master = crate::Group::NON_MASTER.min_id().0;
+        let data = serialize(&set(&[(non_master, non_master + 10)]));
+        assert_eq!(data.len(), 11);
+        assert_eq!(deserialize(&data).unwrap().count(), 11);
+    }
+
+    #[test]
+    fn test_serialize_invalid() {
+        let err = |data: &[u8]| deserialize(data).unwrap_err().to_string();
+        assert_eq!(err(&[]), "invalid serialized IdSet: empty data");
+        assert_eq!(err(&[9]), "invalid serialized IdSet: unsupported version 9");
+        assert_eq!(
+            err(&[VERSION, 3]),
+            "invalid serialized IdSet: truncated at offset 2"
+        );
+        let mut data = vec![VERSION];
+        data.write_vlq(u64::MAX).unwrap();
+        data.write_vlq(0u64).unwrap();
+        assert_eq!(
+            err(&data),
+            "invalid serialized IdSet: overflow at offset 12"
+        );
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let a = serialize(&set(&[(0, 10), (20, 30), (40, 40)]));
+        let b = serialize(&set(&[(5, 25), (30, 50)]));
+        let check = |data: Vec<u8>| format!("{:?}", deserialize(&data).unwrap());
+        assert_eq!(check(intersection(&a, &b).unwrap()), "5..=10 20..=25 30 40");
+        assert_eq!(check(difference(&a, &b).unwrap()), "0..=4 26..=29");
+        assert_eq!(
+            check(difference(&b, &a).unwrap()),
+            "11..=19 31..=39 41..=50"
+        );
+    }
+
+    quickcheck::quickcheck! {
+        fn test_set_operations_quickcheck(a: Vec<(u64, u64)>, b: Vec<(u64, u64)>) -> bool {
+            let to_set = |spans: Vec<(u64, u64)>| {
+                let spans: Vec<(u64, u64)> =
+                    spans.into_iter().map(|(l, h)| (l.min(h) % 500, l.max(h) % 500)).filter(|(l, h)| l <= h).collect();
+                set(&spans)
+            };
+            let (a, b) = (to_set(a), to_set(b));
+            let (sa, sb) = (serialize(&a), serialize(&b));
+            deserialize(&sa).unwrap().as_spans() == a.as_spans()
+                && intersection(&sa, &sb).unwrap() == serialize(&a.intersection(&b))
+                && difference(&sa, &sb).unwrap() == serialize(&a.difference(&b))
+        }
+    }
+}
@@ -7,6 +7,7 @@
 
 use nonblocking::non_blocking_result as r;
 use tempfile::tempdir;
+pub use test_dag::ProtocolFaults;
 pub use test_dag::TestDag;
 
 pub use self::drawdag::DrawDag;
@@ -27,9 +28,18 @@ use crate::Result;
 mod drawdag;
 mod test_dag;
 
+#[cfg(test)]
+mod test_export;
+
+#[cfg(test)]
+mod test_git;
+
 #[cfg(test)]
 mod test_integrity;
 
+#[cfg(test)]
+mod test_protocol;
+
 #[cfg(test)]
 mod test_sparse;
 
@@ -54,8 +64,14 @@ pub(crate) use test_dag::ProtocolMonitor;
 #[cfg(test)]
 use crate::iddag::FirstAncestorConstraint;
 use crate::namedag::MemNameDag;
+#[cfg(test)]
+use crate::namedag::OverlayNameDag;
+#[cfg(test)]
+use crate::ops::CheckIntegrity;
 use crate::ops::IdConvert;
 #[cfg(test)]
+use crate::ops::PrefixLookup;
+#[cfg(test)]
 use crate::protocol::Process;
 #[cfg(test)]
 use crate::protocol::RequestLocationToName;
@@ -132,6 +148,16 @@ fn test_generic_dag1<T: DagAlgorithm + DagAddHeads + IdConvert>(dag: T) -> Resul
     assert_eq!(expand(r(dag.first_ancestors(nameset("F")))?), "A B E F");
     assert_eq!(expand(r(dag.parents(nameset("H I E")))?), "B D G");
     assert_eq!(expand(r(dag.children(nameset("G D L")))?), "E H I");
+    let names: Vec<VertexName> = ["E", "A", "G", "L"].iter().map(|&s| s.into()).collect();
+    assert_eq!(
+        format!("{:?}", r(dag.parents_batch(&names))?),
+        "[[B, D], [], [F], [K]]"
+    );
+    let names: Vec<VertexName> = ["G", "D", "L"].iter().map(|&s| s.into()).collect();
+    assert_eq!(
+        format!("{:?}", r(dag.children_batch(&names))?),
+        "[[H, I], [E], []]"
+    );
     assert_eq!(expand(r(dag.merges(r(dag.all())?))?), "E K");
     assert_eq!(expand(r(dag.merges(nameset("E F J K")))?), "E K");
     assert_eq!(expand(r(dag.merges(nameset("A B D F H J L")))?), "");
@@ -200,6 +226,115 @@ fn test_generic_dag_beautify<D: DagAlgorithm + DagAddHeads>(
     Ok(())
 }
 
+fn test_generic_dag_range_paged(dag: impl DagAlgorithm + DagAddHeads) -> Result<()> {
+    let dag = from_ascii(dag, ASCII_DAG1);
+    let (roots, heads) = (nameset("B"), nameset("I L"));
+    let to_vec =
+        |set: NameSet| -> Vec<VertexName> { set.iter().unwrap().map(|v| v.unwrap()).collect() };
+    let expected = to_vec(r(dag.sort(&r(dag.range(roots.clone(), heads.clone()))?))?);
+    assert_eq!(expected.len(), 9);
+
+    // Fetch pages until a page is not full.
+    let mut pages = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = to_vec(r(dag.range_paged(
+            roots.clone(),
+            heads.clone(),
+            cursor.clone(),
+            4,
+        ))?);
+        // Specialized implementations match the default implementation.
+        let default_page = to_vec(r(crate::default_impl::range_paged(
+            &dag,
+            roots.clone(),
+            heads.clone(),
+            cursor,
+            4,
+        ))?);
+        assert_eq!(page, default_page);
+        let is_last = page.len() < 4;
+        cursor = page.last().cloned();
+        pages.push(page);
+        if is_last {
+            break;
+        }
+    }
+    assert_eq!(pages.iter().map(|p| p.len()).collect::<Vec<_>>(), [4, 4, 1]);
+    assert_eq!(pages.concat(), expected);
+
+    // The cursor must be in the range.
+    assert!(r(dag.range_paged(roots, heads, Some("A".into()), 4)).is_err());
+    Ok(())
+}
+
+fn test_generic_dag_snapshot_isolation(dag: impl DagAlgorithm + DagAddHeads) -> Result<()> {
+    use std::sync::mpsc;
+    use std::thread;
+
+    let dag = from_ascii(dag, "A-B-C");
+    let snapshot = dag.dag_snapshot()?;
+    let set = r(snapshot.all())?;
+
+    // Iterate the old snapshot in another thread, while adding heads.
+    let (started_tx, started_rx) = mpsc::channel();
+    let (added_tx, added_rx) = mpsc::channel();
+    let reader = thread::spawn(move || -> Result<Vec<VertexName>> {
+        let mut iter = set.iter()?;
+        let mut names = vec![iter.next().unwrap()?];
+        started_tx.send(()).unwrap();
+        added_rx.recv().unwrap();
+        for name in iter {
+            names.push(name?);
+        }
+        Ok(names)
+    });
+    started_rx.recv().unwrap();
+    let dag = from_ascii(dag, "C-D-E B-F");
+    added_tx.send(()).unwrap();
+
+    let names = reader.join().unwrap()?;
+    assert_eq!(format!("{:?}", names), "[C, B, A]");
+    assert_eq!(expand(r(snapshot.all())?), "A B C");
+    assert_eq!(expand(r(dag.all())?), "A B C D E F");
+    assert_eq!(expand(r(dag.dag_snapshot()?.all())?), "A B C D E F");
+
+    Ok(())
+}
+
+fn test_generic_dag_suggest_bisect(dag: impl DagAlgorithm + DagAddHeads) -> Result<()> {
+    let dag = from_ascii(dag, ASCII_DAG2);
+    let suggest = |good: &str, bad: &str, skip: &str| -> Result<String> {
+        let suggested = r(dag.suggest_bisect(nameset(good), nameset(bad), nameset(skip)))?;
+        Ok(suggested.map_or_else(String::new, |v| format!("{:?}", v)))
+    };
+    assert_eq!(suggest("A", "W", "")?, "O");
+    assert_eq!(suggest("A", "W", "O")?, "N");
+    assert_eq!(suggest("A", "B", "")?, "");
+    assert_eq!(suggest("A", "C", "B")?, "");
+
+    // Bisect finds the first bad vertex.
+    let candidates = r(dag.only(nameset("W"), nameset("A")))?;
+    let mut max_steps = 0;
+    for culprit in candidates.iter()? {
+        let culprit = culprit?;
+        let (mut good, mut bad) = (nameset("A"), nameset("W"));
+        let mut steps = 0;
+        while let Some(v) = r(dag.suggest_bisect(good.clone(), bad.clone(), nameset("")))? {
+            if r(dag.is_ancestor(culprit.clone(), v.clone()))? {
+                bad = v.into();
+            } else {
+                good = good | v.into();
+            }
+            steps += 1;
+        }
+        assert_eq!(expand(r(dag.only(bad, good))?), format!("{:?}", culprit));
+        max_steps = max_steps.max(steps);
+    }
+    assert_eq!(max_steps, 5);
+    Ok(())
+}
+
 fn test_generic_dag_reachable_roots(dag: impl DagAlgorithm + DagAddHeads) -> Result<()> {
     let ascii = r#"
          Z
@@ -345,6 +480,10 @@ fn test_generic_dag2<T: DagAlgorithm + DagAddHeads>(dag: T) -> Result<T> {
     assert_eq!(expand(r(dag.parents(nameset("H I E")))?), "A B D E F");
     assert_eq!(r(dag.first_ancestor_nth(v("H"), 2))?.unwrap(), v("A"));
     assert!(r(dag.first_ancestor_nth(v("H"), 3))?.is_none());
+    assert_eq!(r(dag.first_ancestor_nth(v("J"), 0))?.unwrap(), v("J"));
+    assert_eq!(r(dag.first_ancestor_nth(v("J"), 1))?.unwrap(), v("G"));
+    assert_eq!(r(dag.first_ancestor_nth(v("J"), 3))?.unwrap(), v("A"));
+    assert!(r(dag.first_ancestor_nth(v("J"), 4))?.is_none());
     assert_eq!(expand(r(dag.heads(nameset("E H F K I D")))?), "K");
     assert_eq!(expand(r(dag.children(nameset("E F I")))?), "G H I J K");
     assert_eq!(expand(r(dag.merges(r(dag.all())?))?), "E F H I J K");
@@ -395,6 +534,46 @@ fn test_namedag() {
     test_specific_dag_import(new_dag()).unwrap();
 }
 
+#[test]
+fn test_overlay_namedag() -> Result<()> {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    let dir = tempdir().unwrap();
+    let counter = AtomicUsize::new(0);
+    let new_dag = move || {
+        let count = counter.fetch_add(1, Ordering::AcqRel);
+        let base = NameDag::open(dir.path().join(count.to_string())).unwrap();
+        OverlayNameDag::new(base).unwrap()
+    };
+    test_generic_dag(&new_dag);
+
+    // Speculative vertexes are visible in the overlay, not the base.
+    let dir = tempdir().unwrap();
+    let mut base = NameDag::open(dir.path())?;
+    base.import_ascii("A-B-C")?;
+    r(base.flush(&Default::default()))?;
+    let mut dag = OverlayNameDag::new(base)?;
+    dag.import_ascii("C-D B-E")?;
+    assert_eq!(expand(r(dag.overlay_vertexes())?), "D E");
+    assert_eq!(expand(r(dag.all())?), "A B C D E");
+    assert_eq!(expand(r(dag.gca_all(nameset("D E")))?), "B");
+    assert_eq!(expand(r(dag.base().all())?), "A B C");
+
+    // Discard the overlay.
+    dag.discard()?;
+    assert_eq!(expand(r(dag.overlay_vertexes())?), "");
+    assert_eq!(expand(r(dag.all())?), "A B C");
+    assert!(!r(dag.contains_vertex_name(&VertexName::copy_from(b"D")))?);
+
+    // Nothing is written to disk.
+    dag.import_ascii("C-F")?;
+    drop(dag);
+    let dag = NameDag::open(dir.path())?;
+    assert_eq!(expand(r(dag.all())?), "A B C");
+    Ok(())
+}
+
 #[test]
 fn test_protocols() {
     let mut built = build_segments(ASCII_DAG1, "A C E L", 3);
@@ -525,6 +704,35 @@ Lv1: RN0-N12[]"#
     );
 }
 
+#[test]
+fn test_descendants_non_master() {
+    let mut dag = TestDag::new();
+    dag.drawdag(
+        r#"
+        A--B--C--D--E
+            \     \
+             F--G  H--I
+              \     \
+               J--K  L"#,
+        &["E"],
+    );
+    dag.drawdag(
+        r#"
+        C--M--N
+            \
+             O
+        X--Y--Z"#,
+        &[],
+    );
+    let descendants = |set: &str| expand(r(dag.dag.descendants(nameset(set))).unwrap());
+    assert_eq!(descendants("B"), "B C D E F G H I J K L M N O");
+    assert_eq!(descendants("D"), "D E H I L");
+    assert_eq!(descendants("F"), "F G J K");
+    assert_eq!(descendants("G M"), "G M N O");
+    assert_eq!(descendants("H Y"), "H I L Y Z");
+    assert_eq!(descendants("X"), "X Y Z");
+}
+
 #[test]
 fn test_segment_examples() {
     assert_eq!(
@@ -755,6 +963,76 @@ fn test_namedag_reassign_master() -> crate::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_namedag_probe() -> crate::Result<()> {
+    let dir = tempdir().unwrap();
+    NameDag::probe(dir.path().join("missing"))?;
+
+    let mut dag = NameDag::open(&dir.path())?;
+    dag = from_ascii(dag, "A-B-C");
+    r(dag.flush(&Default::default()))?;
+    drop(dag);
+    NameDag::probe(dir.path())?;
+
+    // Pretend the log was written by a newer version.
+    let log_path = dir.path().join("iddag").join("log");
+    let mut data = std::fs::read(&log_path)?;
+    data[10] = b'9';
+    std::fs::write(&log_path, data)?;
+    let err = NameDag::probe(dir.path()).unwrap_err().to_string();
+    assert!(err.contains("created by a newer version"), "{}", err);
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_vertexes_by_hex_prefix() -> crate::Result<()> {
+    let dir = tempdir().unwrap();
+    let mut dag = NameDag::open(&dir.path())?;
+    dag = from_ascii(dag, "AA-AB-BA");
+
+    // "AA" is "4141" in hex, "AB" is "4142", "BA" is "4241".
+    let lookup = |dag: &NameDag, prefix: &[u8], limit: usize| -> crate::Result<String> {
+        Ok(format!(
+            "{:?}",
+            r(dag.vertexes_by_hex_prefix(prefix, limit))?
+        ))
+    };
+    assert_eq!(lookup(&dag, b"414", 10)?, "[AA, AB]");
+
+    // The prefix index is persisted.
+    r(dag.flush(&Default::default()))?;
+    let dag = NameDag::open(&dir.path())?;
+    assert_eq!(lookup(&dag, b"414", 10)?, "[AA, AB]");
+    assert_eq!(lookup(&dag, b"414", 1)?, "[AA]");
+    assert_eq!(lookup(&dag, b"4241", 10)?, "[BA]");
+    assert_eq!(lookup(&dag, b"43", 10)?, "[]");
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_promote_to_master() -> crate::Result<()> {
+    let dir = tempdir().unwrap();
+    let mut dag = NameDag::open(&dir.path())?;
+    dag = from_ascii(dag, "A-B-C-D");
+    r(dag.flush(&Default::default())).unwrap();
+
+    // D is a head, and is not suggested.
+    assert!(dag.suggest_master_candidates(4)?.is_empty()?);
+    let candidates = dag.suggest_master_candidates(3)?;
+    assert_eq!(candidates.count()?, 3);
+    assert!(!candidates.contains(&"D".into())?);
+
+    r(dag.promote_to_master(&candidates))?;
+    r(dag.flush(&Default::default())).unwrap();
+    assert_eq!(format!("{:?}", r(dag.vertex_id("A".into()))?), "0");
+    assert_eq!(format!("{:?}", r(dag.vertex_id("C".into()))?), "2");
+    assert_eq!(format!("{:?}", r(dag.vertex_id("D".into()))?), "N0");
+
+    Ok(())
+}
+
 #[test]
 fn test_namedag_reassign_non_master() {
     let mut t = TestDag::new();
@@ -1195,6 +1473,58 @@ Lv2: R0-3[] R4-6[1]"#
     }
 }
 
+#[test]
+fn test_render_dot_mermaid() {
+    let mut dag = TestDag::draw("A-B-C B-D # master: C");
+    dag.drawdag("C-E", &[]);
+
+    assert_eq!(
+        dag.render_graph_dot(),
+        r#"digraph {
+  rankdir=BT;
+  subgraph cluster_0 {
+    label="0-2 Root OnlyHead";
+    v2 [label="C 2"];
+    v1 [label="B 1"];
+    v0 [label="A 0"];
+  }
+  subgraph cluster_1 {
+    label="N1-N1";
+    vN1 [label="E N1"];
+  }
+  subgraph cluster_2 {
+    label="N0-N0";
+    vN0 [label="D N0"];
+  }
+  v1 -> v0;
+  v2 -> v1;
+  vN1 -> v2;
+  vN0 -> v1;
+}
+"#
+    );
+    assert_eq!(
+        dag.render_graph_mermaid(),
+        r#"flowchart BT
+  subgraph s0 ["0-2 Root OnlyHead"]
+    v2["C 2"]
+    v1["B 1"]
+    v0["A 0"]
+  end
+  subgraph s1 ["N1-N1"]
+    vN1["E N1"]
+  end
+  subgraph s2 ["N0-N0"]
+    vN0["D N0"]
+  end
+  v1 --> v0
+  v2 --> v1
+  vN1 --> v2
+  vN0 --> v1
+"#
+    );
+}
+
 #[test]
 fn test_render_segment_dag() {
     // For reference in below graphs.
@@ -1427,9 +1757,126 @@ pub fn test_generic_dag<D: DagAddHeads + DagAlgorithm + IdConvert + Send + Sync
     test_generic_dag1(new_dag()).unwrap();
     test_generic_dag2(new_dag()).unwrap();
     test_generic_dag_reachable_roots(new_dag()).unwrap();
+    test_generic_dag_range_paged(new_dag()).unwrap();
+    test_generic_dag_snapshot_isolation(new_dag()).unwrap();
+    test_generic_dag_suggest_bisect(new_dag()).unwrap();
     test_generic_dag_beautify(new_dag).unwrap();
 }
 
 fn render(dag: &(impl DagAlgorithm + ?Sized)) -> String {
     render_namedag(dag, |_| None).unwrap()
 }
+
+#[tokio::test]
+async fn test_rebuild_segments() {
+    let mut dag = TestDag::new_with_segment_size(2);
+    let ascii: Vec<String> = (0..12)
+        .map(|i| format!("M{}-M{} M{}-S{}-M{}", i, i + 1, i, i, i + 1))
+        .collect();
+    dag.drawdag(&ascii.join("\n"), &["M12"]);
+
+    let names: Vec<VertexName> = ["M0", "S5", "M12"].iter().map(|&s| s.into()).collect();
+    let ids = |dag: &TestDag| -> Vec<Id> {
+        r(dag.dag.vertex_id_batch(&names))
+            .unwrap()
+            .into_iter()
+            .map(|id| id.unwrap())
+            .collect()
+    };
+    let ids_before = ids(&dag);
+    assert_eq!(dag.dag.dag().max_level().unwrap(), 2);
+    assert_eq!(
+        dag.debug_segments(1, Group::MASTER),
+        r#"
+        M10+20 : S11+23 [M9+18, S9+19]
+        M8+16 : S9+19 [M7+14, S7+15]
+        M6+12 : S7+15 [M5+10, S5+11]
+        M4+8 : S5+11 [M3+6, S3+7]
+        M2+4 : S3+7 [M1+2, S1+3]
+        M0+0 : S1+3 [] Root"#
+    );
+
+    dag.dag.rebuild_segments(4, 1).unwrap();
+    assert_eq!(dag.dag.dag().max_level().unwrap(), 1);
+    assert_eq!(
+        dag.debug_segments(1, Group::MASTER),
+        r#"
+        M8+16 : S11+23 [M7+14, S7+15]
+        M4+8 : S7+15 [M3+6, S3+7]
+        M0+0 : S3+7 [] Root"#
+    );
+    assert_eq!(dag.dag.check_segments().await.unwrap(), [] as [String; 0]);
+    assert_eq!(ids(&dag), ids_before);
+
+    // Changes are persisted.
+    dag.reopen();
+    assert_eq!(dag.dag.dag().max_level().unwrap(), 1);
+    assert_eq!(
+        dag.debug_segments(1, Group::MASTER),
+        r#"
+        M8+16 : S11+23 [M7+14, S7+15]
+        M4+8 : S7+15 [M3+6, S3+7]
+        M0+0 : S3+7 [] Root"#
+    );
+    assert_eq!(ids(&dag), ids_before);
+}
+
+#[tokio::test]
+async fn test_attribute_table() {
+    let dag = TestDag::draw("A-B-C B-E # master: C");
+    let mut phases = dag.dag.open_attribute_table("phase", 1).unwrap();
+    let c: VertexName = "C".into();
+    dag.dag
+        .set_attribute(&mut phases, &"A".into(), b"p")
+        .await
+        .unwrap();
+    dag.dag.set_attribute(&mut phases, &c, b"d").await.unwrap();
+    phases.flush().unwrap();
+    assert!(dag.dag.set_attribute(&mut phases, &c, b"xy").await.is_err());
+
+    let phases = dag.dag.open_attribute_table("phase", 1).unwrap();
+    let get = |name: &'static str| r(dag.dag.get_attribute(&phases, &name.into())).unwrap();
+    assert_eq!(get("A"), Some(&b"p"[..]));
+    assert_eq!(get("B"), None);
+    assert_eq!(get("C"), Some(&b"d"[..]));
+    assert!(dag.dag.get_attribute(&phases, &"Z".into()).await.is_err());
+
+    // The attribute was set for another vertex with the non-master Id.
+    let mut phases = dag.dag.open_attribute_table("phase", 1).unwrap();
+    let e: VertexName = "E".into();
+    let id = dag.dag.vertex_id(e.clone()).await.unwrap();
+    assert_eq!(id.group(), Group::NON_MASTER);
+    phases.insert_for_vertex(id, &"F".into(), b"d").unwrap();
+    assert_eq!(r(dag.dag.get_attribute(&phases, &e)).unwrap(), None);
+    dag.dag.set_attribute(&mut phases, &e, b"d").await.unwrap();
+    assert_eq!(
+        r(dag.dag.get_attribute(&phases, &e)).unwrap(),
+        Some(&b"d"[..])
+    );
+
+    assert!(dag.dag.open_attribute_table("../x", 1).is_err());
+    assert!(dag.dag.open_attribute_table("phase", 2).is_err());
+}
+
+#[tokio::test]
+async fn test_serialize_name_set() {
+    use crate::spanset::serialize;
+
+    let dag = TestDag::draw("A-B-C-D B-E # master: D");
+    let local = dag.dag.ancestors(nameset("D")).await.unwrap();
+    let remote = dag.dag.ancestors(nameset("C E")).await.unwrap();
+    let local = serialize::serialize_name_set(&dag.dag, &local)
+        .await
+        .unwrap();
+    let remote = serialize::serialize_name_set(&dag.dag, &remote)
+        .await
+        .unwrap();
+
+    let to_set = |data: Vec<u8>| expand(serialize::deserialize_name_set(&dag.dag, &data).unwrap());
+    assert_eq!(to_set(local.clone()), "A B C D");
+    assert_eq!(
+        to_set(serialize::intersection(&local, &remote).unwrap()),
+        "A B C"
+    );
+    assert_eq!(to_set(serialize::difference(&remote, &local).unwrap()), "E");
+}
@@ -5,7 +5,10 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::StreamExt;
 use futures::TryStreamExt;
@@ -14,6 +17,7 @@ use nonblocking::non_blocking_result;
 use parking_lot::Mutex;
 use tracing::debug;
 
+use crate::errors::BackendError;
 use crate::ops::CheckIntegrity;
 use crate::ops::DagAddHeads;
 use crate::ops::DagAlgorithm;
@@ -229,6 +233,12 @@ impl TestDag {
         self.dag.set_remote_protocol(remote);
     }
 
+    /// Similar to `set_remote`, but the remote protocol injects `faults`.
+    pub fn set_remote_with_faults(&mut self, server_dag: &Self, faults: ProtocolFaults) {
+        let remote = server_dag.remote_protocol_with_faults(self.output.clone(), faults);
+        self.dag.set_remote_protocol(remote);
+    }
+
     /// Alternative syntax of `set_remote`.
     pub fn with_remote(mut self, server_dag: &Self) -> Self {
         self.set_remote(server_dag);
@@ -279,14 +289,36 @@ impl TestDag {
     pub fn remote_protocol(
         &self,
         output: Arc<Mutex<Vec<String>>>,
+    ) -> Arc<dyn RemoteIdConvertProtocol> {
+        self.remote_protocol_with_faults(output, Default::default())
+    }
+
+    /// Similar to `remote_protocol`, but simulates an unreliable network
+    /// using `faults`. Injected faults are also written to `output`.
+    pub fn remote_protocol_with_faults(
+        &self,
+        output: Arc<Mutex<Vec<String>>>,
+        faults: ProtocolFaults,
     ) -> Arc<dyn RemoteIdConvertProtocol> {
         let remote = ProtocolMonitor {
             inner: Box::new(self.dag.try_snapshot().unwrap()),
             output,
+            faults,
+            calls: Default::default(),
         };
         Arc::new(remote)
     }
 
+    /// Render the graph in the Graphviz DOT format, with segment boundaries.
+    pub fn render_graph_dot(&self) -> String {
+        crate::render::render_dot(&self.dag).unwrap()
+    }
+
+    /// Render the graph in the Mermaid format, with segment boundaries.
+    pub fn render_graph_mermaid(&self) -> String {
+        crate::render::render_mermaid(&self.dag).unwrap()
+    }
+
     /// Describe segments at the given level and group as a string.
     pub fn debug_segments(&self, level: Level, group: Group) -> String {
         let lines = crate::namedag::debug_segments_by_level_group(
@@ -412,9 +444,57 @@ impl TestDag {
     }
 }
 
+/// Faults injected by the remote protocol of a `TestDag`.
+/// The default does not inject faults.
+#[derive(Clone, Debug, Default)]
+pub struct ProtocolFaults {
+    /// Fail every N-th remote call. 0 means never.
+    pub fail_every: usize,
+
+    /// Delay of each remote call.
+    pub latency: Duration,
+
+    /// Cut responses off after N items, like a connection closed in the
+    /// middle of a response. The cut off response is an error, since names
+    /// omitted in a successful response are confirmed missing by the server.
+    pub max_results: Option<usize>,
+}
+
 pub(crate) struct ProtocolMonitor {
     pub(crate) inner: Box<dyn RemoteIdConvertProtocol>,
     pub(crate) output: Arc<Mutex<Vec<String>>>,
+    pub(crate) faults: ProtocolFaults,
+    pub(crate) calls: AtomicUsize,
+}
+
+impl ProtocolMonitor {
+    /// Apply `faults` to a remote call, then run it.
+    async fn call<T>(
+        &self,
+        func: impl std::future::Future<Output = Result<Vec<T>>>,
+    ) -> Result<Vec<T>> {
+        let count = self.calls.fetch_add(1, Ordering::AcqRel) + 1;
+        if !self.faults.latency.is_zero() {
+            tokio::time::sleep(self.faults.latency).await;
+        }
+        if self.faults.fail_every > 0 && count.is_multiple_of(self.faults.fail_every) {
+            self.output
+                .lock()
+                .push(format!("fault: fail call {}", count));
+            let msg = format!("injected network failure at call {}", count);
+            return Err(BackendError::Generic(msg).into());
+        }
+        let result = func.await?;
+        if let Some(max) = self.faults.max_results {
+            if result.len() > max {
+                let msg = format!("fault: truncate {} results to {}", result.len(), max);
+                self.output.lock().push(msg);
+                let msg = format!("response truncated after {} of {} items", max, result.len());
+                return Err(BackendError::Generic(msg).into());
+            }
+        }
+        Ok(result)
+    }
 }
 
 #[async_trait::async_trait]
@@ -426,8 +506,7 @@ impl RemoteIdConvertProtocol for ProtocolMonitor {
     ) -> Result<Vec<(protocol::AncestorPath, Vec<Vertex>)>> {
         let msg = format!("resolve names: {:?}, heads: {:?}", &names, &heads);
         self.output.lock().push(msg);
-        self.inner
-            .resolve_names_to_relative_paths(heads, names)
+        self.call(self.inner.resolve_names_to_relative_paths(heads, names))
             .await
     }
 
@@ -437,7 +516,8 @@ impl RemoteIdConvertProtocol for ProtocolMonitor {
     ) -> Result<Vec<(protocol::AncestorPath, Vec<Vertex>)>> {
         let msg = format!("resolve paths: {:?}", &paths);
         self.output.lock().push(msg);
-        self.inner.resolve_relative_paths_to_names(paths).await
+        self.call(self.inner.resolve_relative_paths_to_names(paths))
+            .await
     }
 }
 
@@ -0,0 +1,68 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use super::TestDag;
+use crate::export::export_clone_data;
+use crate::export::import_clone_data;
+use crate::export::ExportFormat;
+use crate::ops::DagExportCloneData;
+use crate::ops::DagImportCloneData;
+
+#[tokio::test]
+async fn test_export_roundtrip() {
+    let server = TestDag::draw("A-B-C-D B-E-D # master: D");
+    let clone_data = server.dag.export_clone_data().await.unwrap();
+
+    for format in [ExportFormat::Cbor, ExportFormat::Json] {
+        let bytes = export_clone_data(&clone_data, format).unwrap();
+        assert_eq!(import_clone_data(&bytes).unwrap(), clone_data);
+
+        let mut client = server.client().await;
+        let bytes = server.dag.export_portable_clone_data(format).await.unwrap();
+        client.dag.import_portable_clone_data(&bytes).await.unwrap();
+        assert_eq!(client.render_graph(), server.render_graph());
+    }
+}
+
+#[tokio::test]
+async fn test_export_json() {
+    let server = TestDag::draw("A-B # master: B");
+    let bytes = server
+        .dag
+        .export_portable_clone_data(ExportFormat::Json)
+        .await
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(bytes).unwrap(),
+        r#"{
+  "version": 1,
+  "segments": [
+    {
+      "low": 0,
+      "high": 1,
+      "parents": []
+    }
+  ],
+  "names": [
+    [
+      1,
+      "42"
+    ]
+  ]
+}"#
+    );
+}
+
+#[tokio::test]
+async fn test_export_unsupported_version() {
+    let data = br#"{"version": 100, "segments": "changed"}"#;
+    let err = import_clone_data(data).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "exported graph has unsupported version 100 (supported: 1)"
+    );
+}
@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::HashMap;
+
+use super::TestDag;
+use crate::git::export_commit_graph;
+use crate::git::import_commit_graph;
+use crate::git::parse_commit_graph;
+use crate::git::read_commit_graph;
+use crate::git::write_commit_graph;
+use crate::git::write_commit_graph_chain;
+use crate::git::GitCommit;
+use crate::git::CHAIN_FILE_NAME;
+use crate::DagAlgorithm;
+use crate::NameSet;
+use crate::VertexName;
+
+/// Fake SHA1 hash for a short name.
+fn oid(name: &str) -> VertexName {
+    let mut bytes = [0u8; 20];
+    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    VertexName::copy_from(&bytes)
+}
+
+/// Commits from "name: parents" lines.
+fn commits(spec: &str) -> Vec<GitCommit> {
+    spec.lines()
+        .filter_map(|line| line.trim().split_once(':'))
+        .enumerate()
+        .map(|(i, (name, parents))| GitCommit {
+            oid: oid(name),
+            tree: oid(&format!("tree-{}", name)),
+            parents: parents.split_whitespace().map(oid).collect(),
+            commit_time: (1 << 33) + i as u64,
+        })
+        .collect()
+}
+
+fn sorted(mut commits: Vec<GitCommit>) -> Vec<GitCommit> {
+    commits.sort_by(|a, b| a.oid.cmp(&b.oid));
+    commits
+}
+
+const SPEC: &str = r#"
+    A:
+    B: A
+    C: B
+    D: A
+    E: C D
+    F: B D E
+    G: F"#;
+
+#[tokio::test]
+async fn test_commit_graph_roundtrip() {
+    let commits = commits(SPEC);
+    let data = write_commit_graph(&commits).unwrap();
+    assert_eq!(
+        sorted(parse_commit_graph(&data).unwrap()),
+        sorted(commits.clone())
+    );
+
+    // Populate a NameDag.
+    let mut dag = TestDag::new();
+    import_commit_graph(&mut dag.dag, &commits).await.unwrap();
+    assert_eq!(dag.dag.all().await.unwrap().count().await.unwrap(), 7);
+    assert_eq!(
+        dag.dag.parent_names(oid("F")).await.unwrap(),
+        vec![oid("B"), oid("D"), oid("E")]
+    );
+
+    // Export from the NameDag.
+    let info: HashMap<VertexName, (VertexName, u64)> = commits
+        .iter()
+        .map(|c| (c.oid.clone(), (c.tree.clone(), c.commit_time)))
+        .collect();
+    let heads = NameSet::from_static_names(vec![oid("G")]);
+    let exported = export_commit_graph(&dag.dag, heads, |v| Ok(info[v].clone()))
+        .await
+        .unwrap();
+    assert_eq!(sorted(exported.clone()), sorted(commits));
+    assert_eq!(write_commit_graph(&sorted(exported)).unwrap(), data);
+}
+
+#[tokio::test]
+async fn test_commit_graph_chain() {
+    let commits = commits(SPEC);
+    let layers = vec![commits[..3].to_vec(), commits[3..].to_vec()];
+    let dir = tempfile::tempdir().unwrap();
+    write_commit_graph_chain(dir.path(), &layers).unwrap();
+
+    let read = read_commit_graph(&dir.path().join(CHAIN_FILE_NAME)).unwrap();
+    assert_eq!(sorted(read.clone()), sorted(commits));
+
+    let mut dag = TestDag::new();
+    import_commit_graph(&mut dag.dag, &read).await.unwrap();
+    assert_eq!(dag.dag.all().await.unwrap().count().await.unwrap(), 7);
+
+    // The second layer cannot be written without its base.
+    let err = write_commit_graph(&layers[1]).unwrap_err();
+    assert!(err.to_string().contains("is missing"));
+}
+
+#[test]
+fn test_commit_graph_invalid() {
+    let data = write_commit_graph(&commits(SPEC)).unwrap();
+    let err = |data: &[u8]| parse_commit_graph(data).unwrap_err().to_string();
+    assert_eq!(err(b"CGPX"), "invalid commit-graph: signature mismatch");
+    assert_eq!(
+        err(&data[..20]),
+        "invalid commit-graph: truncated chunk table"
+    );
+    assert_eq!(
+        err(&data[..100]),
+        "invalid commit-graph: chunk out of range"
+    );
+
+    let mut cycle = commits("A: B\nB: A");
+    assert!(write_commit_graph(&cycle)
+        .unwrap_err()
+        .to_string()
+        .contains("cycle"));
+    cycle[0].parents.clear();
+    assert!(write_commit_graph(&cycle).is_ok());
+}
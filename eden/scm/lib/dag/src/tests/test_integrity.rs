@@ -8,7 +8,10 @@
 use super::TestDag;
 use crate::ops::CheckIntegrity;
 use crate::ops::DagAlgorithm;
+use crate::segment::SegmentFlags;
 use crate::Group;
+use crate::Id;
+use crate::IntegrityReport;
 
 #[tokio::test]
 async fn test_isomorphic_graph_with_different_segments() {
@@ -98,3 +101,59 @@ async fn quick_check_graphs(ascii1: &str, ascii2: &str) -> Vec<String> {
         .await
         .unwrap()
 }
+
+#[tokio::test]
+async fn test_check_integrity() {
+    let mut server = TestDag::new();
+    server.drawdag(
+        r#"
+        A--B--C--D--E    K--L--M
+         \     \     \
+          F--G--H--I--J"#,
+        &["J", "M"],
+    );
+    let report = server.dag.check_integrity().await.unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report, IntegrityReport::default());
+
+    // Lazy graph with non-master vertexes.
+    let mut client = server.client_cloned_data().await;
+    client.drawdag("J--X--Y", &["Y"]);
+    assert!(client.dag.is_vertex_lazy());
+    let report = client.dag.check_integrity().await.unwrap();
+    assert_eq!(report, IntegrityReport::default());
+}
+
+#[tokio::test]
+async fn test_check_integrity_corrupted() {
+    let mut dag = TestDag::draw("A--B--C # master: C");
+
+    // A segment overlapping existing segments, and a segment covering `Id`s
+    // that have no names.
+    let iddag = &mut dag.dag.dag;
+    iddag
+        .insert(SegmentFlags::empty(), 0, Id(1), Id(1), &[Id(0)])
+        .unwrap();
+    let low = Group::NON_MASTER.min_id();
+    iddag
+        .insert(SegmentFlags::empty(), 0, low, low + 1, &[Id(2)])
+        .unwrap();
+
+    let report = dag.dag.check_integrity().await.unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(
+        report.segments,
+        [
+            "Level 0 segment RH0-2[] has unexpected span (Span { low: 0, high: 2 }), expected low (2)",
+            "Level 0 segment RH0-2[] overlapped segments: Span { low: 0, high: 2 } with previous head 1",
+            "Level 0 segment RH0-2[] has unexpected flags: HAS_ROOT | ONLY_HEAD (expected: min: HAS_ROOT, max: HAS_ROOT)"
+        ]
+    );
+    assert_eq!(
+        report.idmap,
+        [
+            "N0 in Group Non-Master has no name",
+            "N1 in Group Non-Master has no name"
+        ]
+    );
+}
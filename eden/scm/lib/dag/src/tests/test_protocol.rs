@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use super::ProtocolFaults;
+use super::TestDag;
+use crate::errors::DagError;
+use crate::ops::IdConvert;
+use crate::protocol::CoalescingProtocol;
+use crate::protocol::RemoteIdConvertProtocol;
+use crate::Id;
+use crate::VertexName;
+
+#[tokio::test]
+async fn test_coalescing_protocol() {
+    let server = TestDag::draw("A--B--C--D--E # master: E");
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let protocol = CoalescingProtocol::new(
+        server.remote_protocol(output.clone()),
+        Duration::from_millis(10),
+    )
+    .max_batch_size(3);
+    let resolve = |heads: &'static str, names: &'static [&'static str]| {
+        let heads = vec![VertexName::copy_from(heads.as_bytes())];
+        let names = names
+            .iter()
+            .map(|n| VertexName::copy_from(n.as_bytes()))
+            .collect();
+        let protocol = &protocol;
+        async move {
+            let resolved = protocol
+                .resolve_names_to_relative_paths(heads, names)
+                .await
+                .unwrap();
+            format!("{:?}", resolved)
+        }
+    };
+
+    // Concurrent calls with the same heads are sent together. Each caller
+    // only gets the names it asked for.
+    let (b, d) = futures::join!(resolve("E", &["B"]), resolve("E", &["D"]));
+    assert_eq!(b, "[(E~3, [B])]");
+    assert_eq!(d, "[(E~1, [D])]");
+    assert_eq!(
+        std::mem::take(&mut *output.lock()),
+        ["resolve names: [B, D], heads: [E]"]
+    );
+
+    // Calls with different heads, or that do not fit in the batch, are not.
+    let (bc, c, ad) = futures::join!(
+        resolve("E", &["B", "C"]),
+        resolve("D", &["C"]),
+        resolve("E", &["A", "D"]),
+    );
+    assert_eq!(bc, "[(E~3, [B]), (E~2, [C])]");
+    assert_eq!(c, "[(D~1, [C])]");
+    assert_eq!(ad, "[(E~4, [A]), (E~1, [D])]");
+    assert_eq!(
+        std::mem::take(&mut *output.lock()),
+        [
+            "resolve names: [B, C], heads: [E]",
+            "resolve names: [C], heads: [D]",
+            "resolve names: [A, D], heads: [E]"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_coalescing_protocol_error() {
+    let server = TestDag::draw("A--B--C--D--E # master: E");
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let faults = ProtocolFaults {
+        fail_every: 1,
+        ..Default::default()
+    };
+    let protocol = CoalescingProtocol::new(
+        server.remote_protocol_with_faults(output.clone(), faults),
+        Duration::from_millis(10),
+    );
+    let resolve = |name: &'static str| {
+        protocol.resolve_names_to_relative_paths(vec!["E".into()], vec![name.into()])
+    };
+
+    // Every caller of the batch gets its error.
+    let (b, d) = futures::join!(resolve("B"), resolve("D"));
+    for result in [b, d] {
+        let err = result.unwrap_err();
+        assert!(matches!(err, DagError::Backend(_)), "{:?}", err);
+        assert_eq!(err.to_string(), "injected network failure at call 1");
+    }
+    assert_eq!(
+        std::mem::take(&mut *output.lock()),
+        ["resolve names: [B, D], heads: [E]", "fault: fail call 1"]
+    );
+}
+
+#[test]
+fn test_coalescing_protocol_without_runtime() {
+    let server = TestDag::draw("A--B--C # master: C");
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let protocol = CoalescingProtocol::new(
+        server.remote_protocol(output.clone()),
+        Duration::from_millis(10),
+    );
+
+    // Without a tokio runtime to wait for other calls, names are resolved
+    // right away.
+    let resolved = futures::executor::block_on(
+        protocol.resolve_names_to_relative_paths(vec!["C".into()], vec!["B".into()]),
+    )
+    .unwrap();
+    assert_eq!(format!("{:?}", resolved), "[(C~1, [B])]");
+    assert_eq!(
+        std::mem::take(&mut *output.lock()),
+        ["resolve names: [B], heads: [C]"]
+    );
+}
+
+#[tokio::test]
+async fn test_protocol_faults_failure_is_not_cached() {
+    let server = TestDag::draw("A-B-C-D-E-F-G # master: G");
+    let mut client = server.client_cloned_data().await;
+    let faults = ProtocolFaults {
+        fail_every: 2,
+        ..Default::default()
+    };
+    client.set_remote_with_faults(&server, faults);
+
+    // The 1st call succeeds. The 2nd call fails. Retrying succeeds.
+    assert_eq!(client.dag.vertex_id("B".into()).await.unwrap(), Id(1));
+    let err = client.dag.vertex_id("C".into()).await.unwrap_err();
+    assert_eq!(err.to_string(), "injected network failure at call 2");
+    assert_eq!(client.dag.vertex_id("C".into()).await.unwrap(), Id(2));
+
+    // Same for the other direction.
+    assert!(client.dag.vertex_name(Id(4)).await.is_err());
+    assert_eq!(client.dag.vertex_name(Id(4)).await.unwrap(), "E".into());
+
+    assert_eq!(
+        client.output(),
+        [
+            "resolve names: [B], heads: [G]",
+            "resolve names: [C], heads: [G]",
+            "fault: fail call 2",
+            "resolve names: [C], heads: [G]",
+            "resolve paths: [G~2]",
+            "fault: fail call 4",
+            "resolve paths: [G~2]"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_protocol_faults_partial_results() {
+    let server = TestDag::draw("A-B-C-D-E-F-G # master: G");
+    let mut client = server.client_cloned_data().await;
+    let faults = ProtocolFaults {
+        max_results: Some(1),
+        ..Default::default()
+    };
+    client.set_remote_with_faults(&server, faults);
+
+    // A truncated response fails the request.
+    let names: Vec<VertexName> = vec!["B".into(), "E".into()];
+    let err = client.dag.vertex_id_batch(&names).await.unwrap_err();
+    assert_eq!(err.to_string(), "response truncated after 1 of 2 items");
+    assert_eq!(
+        client.output(),
+        [
+            "resolve names: [B, E], heads: [G]",
+            "fault: truncate 2 results to 1"
+        ]
+    );
+
+    // Names omitted by it are not cached as missing.
+    client.set_remote(&server);
+    let resolved = client.dag.vertex_id_batch(&names).await.unwrap();
+    assert_eq!(format!("{:?}", resolved), "[Ok(1), Ok(4)]");
+    assert_eq!(client.output(), ["resolve names: [B, E], heads: [G]"]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_protocol_faults_latency() {
+    let server = TestDag::draw("A-B-C-D-E # master: E");
+    let mut client = server.client_cloned_data().await;
+    let faults = ProtocolFaults {
+        latency: Duration::from_secs(3),
+        ..Default::default()
+    };
+    client.set_remote_with_faults(&server, faults);
+
+    let start = tokio::time::Instant::now();
+    assert_eq!(client.dag.vertex_id("B".into()).await.unwrap(), Id(1));
+    assert_eq!(client.dag.vertex_name(Id(2)).await.unwrap(), "C".into());
+    assert_eq!(start.elapsed(), Duration::from_secs(6));
+
+    // Cached results do not pay the latency again.
+    assert_eq!(client.dag.vertex_id("B".into()).await.unwrap(), Id(1));
+    assert_eq!(start.elapsed(), Duration::from_secs(6));
+}
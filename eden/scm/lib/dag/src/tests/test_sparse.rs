@@ -11,6 +11,7 @@ use futures::TryStreamExt;
 
 use super::ProtocolMonitor;
 use super::TestDag;
+use crate::ops::CheckIntegrity;
 use crate::ops::DagAddHeads;
 use crate::ops::DagAlgorithm;
 use crate::ops::DagExportPullData;
@@ -19,6 +20,7 @@ use crate::ops::DagPersistent;
 use crate::ops::IdConvert;
 use crate::Group;
 use crate::Id;
+use crate::Set;
 use crate::VertexListWithOptions;
 use crate::VertexName;
 
@@ -74,6 +76,8 @@ async fn test_sparse_dag() {
             let protocol = ProtocolMonitor {
                 inner: Box::new(remote_dag),
                 output: client.output.clone(),
+                faults: Default::default(),
+                calls: Default::default(),
             };
             client.dag.set_remote_protocol(Arc::new(protocol));
         }
@@ -298,6 +302,43 @@ async fn test_pull_overlap() {
     );
 }
 
+#[tokio::test]
+async fn test_pull_overlap_non_master() {
+    let mut server = TestDag::draw("A # master: A");
+    let mut client = server.client_cloned_data().await;
+
+    // The client has B, C, D in the NON_MASTER group.
+    client.drawdag("A-B-C-D A-E", &[]);
+    client.flush("").await;
+
+    // B, C become public on the server, with a merge from F.
+    server.drawdag("A-B-C-G A-F-G", &["G"]);
+    client.pull_ff_master(&server, "A", "G").await.unwrap();
+
+    // B, C are re-mapped to the MASTER group. D, E stay in NON_MASTER.
+    assert_eq!(
+        client.render_graph(),
+        r#"
+            D  N1
+            │
+            │ E  N0
+            │ │
+            │ │ G  4
+            ╭───┤
+            │ │ F  3
+            │ ├─╯
+            C │  2
+            │ │
+            B │  1
+            ├─╯
+            A  0"#
+    );
+    assert_eq!(
+        client.dag.check_segments().await.unwrap(),
+        Vec::<String>::new()
+    );
+}
+
 #[tokio::test]
 async fn test_pull_lazy_with_merges() {
     // Test fast-forward pull on a lazy graph with merges.
@@ -692,3 +733,58 @@ async fn test_local_cache_missing_vertex_to_id() {
     ));
     assert!(client.output().is_empty());
 }
+
+#[tokio::test]
+async fn test_lazy_gca_round_trips() {
+    // GCA related calculations happen in the Id space. Only inputs and
+    // answers are resolved remotely.
+    let server = TestDag::draw(
+        r#"
+        A-B-C-D-E-F-G-H-I-J
+             \       \
+              K-L-M-N-O-P-Q
+        # master: J Q"#,
+    );
+    let client = server.client_cloned_data().await;
+    assert!(!client.contains_vertex_locally("C"));
+    assert!(!client.contains_vertex_locally("H"));
+
+    let set = Set::from_static_names(vec!["J".into(), "Q".into()]);
+    let names = |set: Set| async move {
+        let names: Vec<VertexName> = set.iter().await.unwrap().try_collect().await.unwrap();
+        names.iter().map(|v| format!("{:?}", v)).collect::<Vec<_>>()
+    };
+
+    let gca = client.dag.gca_all(set.clone()).await.unwrap();
+    assert_eq!(names(gca).await, ["G"]);
+    let gca = client.dag.gca_one(set.clone()).await.unwrap();
+    assert_eq!(gca, Some("G".into()));
+    let heads = client.dag.heads_ancestors(set.clone()).await.unwrap();
+    assert_eq!(names(heads).await, ["Q", "J"]);
+    let ancestors = client.dag.common_ancestors(set.clone()).await.unwrap();
+    assert_eq!(ancestors.count().await.unwrap(), 7);
+    assert!(client.output().is_empty());
+
+    // Lazy inputs are resolved in one round-trip each.
+    let set = Set::from_static_names(vec!["H".into(), "P".into()]);
+    let gca = client.dag.gca_one(set).await.unwrap();
+    assert_eq!(gca, Some("G".into()));
+    assert!(client
+        .dag
+        .is_ancestor("C".into(), "Q".into())
+        .await
+        .unwrap());
+    assert_eq!(
+        client.output(),
+        [
+            "resolve names: [H, P], heads: [Q, J]",
+            "resolve names: [C], heads: [Q, J]"
+        ]
+    );
+
+    // Lazy answers are resolved on demand.
+    let set = Set::from_static_names(vec!["H".into(), "I".into()]);
+    let gca = client.dag.gca_one(set).await.unwrap();
+    assert_eq!(gca, Some("H".into()));
+    assert_eq!(client.output(), ["resolve names: [I], heads: [Q, J]"]);
+}
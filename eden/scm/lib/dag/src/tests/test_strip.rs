@@ -6,6 +6,7 @@
  */
 
 use super::TestDag;
+use crate::ops::DagCheckpoint;
 
 #[tokio::test]
 async fn test_strip_basic() {
@@ -59,6 +60,39 @@ P->C: 0->N0
     );
 }
 
+#[tokio::test]
+async fn test_strip_checkpoint_rollback() {
+    let mut dag = TestDag::draw(
+        r#"
+        A--B--C--D
+         \
+          E--F
+        # master: D"#,
+    );
+    let state_before_strip = dag.dump_state().await;
+
+    dag.dag.checkpoint("pre-strip").unwrap();
+    assert_eq!(dag.dag.checkpoints().unwrap(), vec!["pre-strip"]);
+
+    dag.strip("C E").await;
+    assert_ne!(&dag.dump_state().await, &state_before_strip);
+
+    // Rollback restores the stripped vertexes, including on disk.
+    dag.dag.rollback("pre-strip").unwrap();
+    assert_eq!(&dag.dump_state().await, &state_before_strip);
+    dag.reopen();
+    assert_eq!(&dag.dump_state().await, &state_before_strip);
+
+    // The DAG is still writable after rollback.
+    dag.drawdag("D--G", &["G"]);
+    dag.reopen();
+    assert!(dag.dump_state().await.contains("->G"));
+
+    dag.dag.remove_checkpoint("pre-strip").unwrap();
+    assert!(dag.dag.checkpoints().unwrap().is_empty());
+    assert!(dag.dag.rollback("pre-strip").is_err());
+}
+
 #[tokio::test]
 async fn test_strip_branch_and_parent_remains_lazy() {
     // Test that stripping a (broken) branch does not resolve
@@ -191,3 +225,39 @@ async fn test_reinsert_then_create_higher_level() {
         Lv3: |N0 N1 N2|  |N4 N5 N6 N7 N8 N9 N10 N11|"#
     );
 }
+
+#[tokio::test]
+async fn test_strip_non_master_incrementally() {
+    // Strip non-master vertexes one branch at a time. The master group is
+    // untouched, and the stripped vertexes can be re-assigned later.
+    let mut dag = TestDag::draw(
+        r#"
+        A--B--C--D
+            \
+             E--F--G
+        # master: D"#,
+    );
+    let master_state = dag.dump_state().await;
+
+    dag.strip("F").await;
+    let state_after_strip = dag.dump_state().await;
+    assert_eq!(
+        &state_after_strip,
+        r#"<spans [E+N0, A:D+0:3]>
+Lv0: RH0-3[], N0-N0[1]
+P->C: 1->N0
+0->A 1->B 2->C 3->D N0->E"#
+    );
+    dag.reopen();
+    assert_eq!(&dag.dump_state().await, &state_after_strip);
+
+    dag.strip("E").await;
+    assert_eq!(
+        dag.dump_state().await,
+        "<spans [A:D+0:3]>\nLv0: RH0-3[]\n0->A 1->B 2->C 3->D"
+    );
+
+    // Re-assign the stripped vertexes.
+    dag.drawdag("B--E--F--G", &[]);
+    assert_eq!(dag.dump_state().await, master_state);
+}
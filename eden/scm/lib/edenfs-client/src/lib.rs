@@ -7,4 +7,5 @@
 
 //! # Communicating to EdenFS via Thrift
 
+pub mod prefetch;
 pub mod status;
@@ -0,0 +1,200 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Warm up cold EdenFS directories by asking EdenFS to prefetch them.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::stream;
+use futures::StreamExt;
+use futures::TryStreamExt;
+use thrift_types::edenfs as eden;
+use thrift_types::edenfs::client::EdenService;
+use thrift_types::fbthrift::binary_protocol::BinaryProtocol;
+use types::RepoPathBuf;
+
+use crate::status::get_eden_root;
+use crate::status::get_socket_transport;
+
+/// Number of concurrent `debugInodeStatus` requests sent to EdenFS.
+const INODE_STATUS_CONCURRENCY: usize = 16;
+
+/// Return the directories in `dirs` that EdenFS has not loaded yet.
+///
+/// Accessing such directories requires EdenFS to fetch their trees, which is
+/// slow in a cold mount.
+pub fn get_unloaded_directories(
+    repo_root: &Path,
+    dirs: &[RepoPathBuf],
+) -> Result<Vec<RepoPathBuf>> {
+    async_runtime::block_on(async {
+        let eden_root = get_eden_root(repo_root)?;
+        let transport = get_socket_transport(repo_root).await?;
+        let client = <dyn EdenService>::new(BinaryProtocol, transport);
+        get_unloaded_directories_internal(&client, &eden_root, dirs).await
+    })
+}
+
+/// Ask EdenFS to prefetch the files in `dirs`, recursively.
+///
+/// If `background` is set, return without waiting for the prefetch to finish.
+pub fn prefetch_directories(
+    repo_root: &Path,
+    dirs: &[RepoPathBuf],
+    background: bool,
+) -> Result<()> {
+    if dirs.is_empty() {
+        return Ok(());
+    }
+
+    async_runtime::block_on(async {
+        let eden_root = get_eden_root(repo_root)?;
+        let transport = get_socket_transport(repo_root).await?;
+        let client = <dyn EdenService>::new(BinaryProtocol, transport);
+        prefetch_directories_internal(&client, &eden_root, dirs, background).await
+    })
+}
+
+/// Prefetch the directories in `dirs` that EdenFS has not loaded yet.
+///
+/// This does not wait for the directories to be checked or prefetched. It is
+/// best-effort: errors are only logged.
+pub fn spawn_prefetch_unloaded_directories(repo_root: PathBuf, dirs: Vec<RepoPathBuf>) {
+    if dirs.is_empty() {
+        return;
+    }
+
+    async_runtime::spawn(async move {
+        let result: Result<Vec<RepoPathBuf>> = async {
+            let eden_root = get_eden_root(&repo_root)?;
+            let transport = get_socket_transport(&repo_root).await?;
+            let client = <dyn EdenService>::new(BinaryProtocol, transport);
+            let unloaded = get_unloaded_directories_internal(&client, &eden_root, &dirs).await?;
+            let unloaded = outermost_directories(unloaded);
+            prefetch_directories_internal(&client, &eden_root, &unloaded, true).await?;
+            Ok(unloaded)
+        }
+        .await;
+        match result {
+            Ok(prefetched) => {
+                tracing::debug!(count = prefetched.len(), "prefetching unloaded directories")
+            }
+            Err(err) => tracing::debug!(?err, "cannot prefetch unloaded directories"),
+        }
+    });
+}
+
+async fn get_unloaded_directories_internal(
+    client: &Arc<impl EdenService>,
+    eden_root: &str,
+    dirs: &[RepoPathBuf],
+) -> Result<Vec<RepoPathBuf>> {
+    let flags = eden::consts::DIS_ENABLE_FLAGS | eden::consts::DIS_REQUIRE_LOADED;
+    // Do not wait for the working copy to be synchronized. Stale answers are
+    // fine for prefetching.
+    let sync = eden::SyncBehavior {
+        syncTimeoutSeconds: Some(0),
+        ..Default::default()
+    };
+    let eden_root = eden_root.as_bytes().to_vec();
+
+    let unloaded: Vec<Option<RepoPathBuf>> = stream::iter(dirs)
+        .map(|dir| {
+            let (eden_root, sync) = (&eden_root, &sync);
+            async move {
+                let path = dir.as_str().as_bytes().to_vec();
+                let inodes = client
+                    .debugInodeStatus(eden_root, &path, flags, sync)
+                    .await?;
+                let loaded = inodes.iter().any(|inode| inode.path == path);
+                Ok::<_, anyhow::Error>(if loaded { None } else { Some(dir.clone()) })
+            }
+        })
+        .buffered(INODE_STATUS_CONCURRENCY)
+        .try_collect()
+        .await?;
+    Ok(unloaded.into_iter().flatten().collect())
+}
+
+/// Drop the directories that are below another directory of `dirs`.
+///
+/// Prefetching is recursive, so prefetching the outermost directories is
+/// enough.
+fn outermost_directories(mut dirs: Vec<RepoPathBuf>) -> Vec<RepoPathBuf> {
+    dirs.sort();
+    dirs.dedup();
+    let mut outermost: Vec<RepoPathBuf> = Vec::with_capacity(dirs.len());
+    for dir in dirs {
+        // Sorted, so an ancestor is always right before its descendants.
+        let covered = outermost.last().map_or(false, |last| {
+            last.is_empty() || dir.as_str().starts_with(&format!("{}/", last))
+        });
+        if !covered {
+            outermost.push(dir);
+        }
+    }
+    outermost
+}
+
+async fn prefetch_directories_internal(
+    client: &Arc<impl EdenService>,
+    eden_root: &str,
+    dirs: &[RepoPathBuf],
+    background: bool,
+) -> Result<()> {
+    if dirs.is_empty() {
+        return Ok(());
+    }
+    let globs = dirs
+        .iter()
+        .map(|dir| {
+            if dir.is_empty() {
+                "**".to_string()
+            } else {
+                format!("{}/**", dir)
+            }
+        })
+        .collect();
+    let params = eden::GlobParams {
+        mountPoint: eden_root.as_bytes().to_vec(),
+        globs,
+        prefetchFiles: true,
+        suppressFileList: true,
+        background,
+        ..Default::default()
+    };
+    client.globFiles(&params).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<RepoPathBuf> {
+        paths
+            .iter()
+            .map(|p| RepoPathBuf::from_string(p.to_string()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_outermost_directories() {
+        assert_eq!(
+            outermost_directories(paths(&["a/b", "a", "a/b/c", "ab", "b/c", "b/c"])),
+            paths(&["a", "ab", "b/c"])
+        );
+        assert_eq!(
+            outermost_directories(paths(&["a/b", "", "c"])),
+            paths(&[""])
+        );
+        assert!(outermost_directories(Vec::new()).is_empty());
+    }
+}
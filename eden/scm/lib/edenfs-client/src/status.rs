@@ -72,7 +72,7 @@ pub fn maybe_status_fastpath(
     rt.block_on(maybe_status_fastpath_internal(repo_root, io, list_ignored))
 }
 
-fn get_eden_root(repo_root: &Path) -> Result<String> {
+pub(crate) fn get_eden_root(repo_root: &Path) -> Result<String> {
     // Look up the mount point name where Eden thinks this repository is
     // located.  This may be different from repo_root if a parent directory
     // of the Eden mount has been bind mounted to another location, resulting
@@ -85,7 +85,7 @@ fn get_eden_root(repo_root: &Path) -> Result<String> {
         .map_err(|_| anyhow!("Failed to get eden root"))
 }
 
-async fn get_socket_transport(repo_root: &Path) -> Result<SocketTransport<UnixStream>> {
+pub(crate) async fn get_socket_transport(repo_root: &Path) -> Result<SocketTransport<UnixStream>> {
     // Look up Eden's socket address.
     let sock_addr = repo_root.join(".eden").join("socket");
     let sock_addr = read_link(sock_addr)?;
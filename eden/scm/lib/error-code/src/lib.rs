@@ -0,0 +1,280 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Stable error codes shared by the store, dag and working copy crates.
+//!
+//! Errors are tagged with an [`ErrorCode`] by wrapping them in a [`CodedError`],
+//! usually through [`WithErrorCode::with_error_code`]. The code survives further
+//! `context` layers, so command-level error handling, telemetry and hints can use
+//! [`error_code`] instead of matching error messages.
+//!
+//! The numeric codes are persisted in logs and compared by Python code. Never
+//! renumber or reuse a code. New codes go to the end of the range of their crate.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use anyhow::Error;
+
+/// Broad classes of errors, for telemetry and for picking user-facing hints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The server could not be reached, or did not answer properly.
+    Network,
+    /// The requested data does not exist.
+    NotFound,
+    /// Data on disk is inconsistent.
+    Corruption,
+    /// The local filesystem failed.
+    Io,
+    /// An external service (ex. Watchman, EdenFS) failed.
+    External,
+    /// The operation was cancelled.
+    Interrupted,
+    /// A bug.
+    Internal,
+}
+
+impl ErrorCategory {
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorCategory::Network => "network",
+            ErrorCategory::NotFound => "not-found",
+            ErrorCategory::Corruption => "corruption",
+            ErrorCategory::Io => "io",
+            ErrorCategory::External => "external",
+            ErrorCategory::Interrupted => "interrupted",
+            ErrorCategory::Internal => "internal",
+        }
+    }
+}
+
+macro_rules! error_codes {
+    ( $( $(#[$meta:meta])* $name:ident = $code:literal, $category:ident; )* ) => {
+        /// Error codes. See the module documentation.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        #[repr(u32)]
+        pub enum ErrorCode {
+            $( $(#[$meta])* $name = $code, )*
+        }
+
+        impl ErrorCode {
+            /// All error codes, in numeric order.
+            pub const ALL: &'static [ErrorCode] = &[ $( ErrorCode::$name, )* ];
+
+            pub fn category(self) -> ErrorCategory {
+                match self {
+                    $( ErrorCode::$name => ErrorCategory::$category, )*
+                }
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $( ErrorCode::$name => stringify!($name), )*
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    // revisionstore: 1000-1999
+
+    /// A key is missing both locally and remotely.
+    KeyNotFound = 1001, NotFound;
+    /// Fetching from the server (EdenAPI, Memcache, legacy protocols) failed.
+    RemoteFetchFailed = 1002, Network;
+    /// Downloading or uploading LFS blobs failed.
+    LfsTransferFailed = 1003, Network;
+    /// A pack file or indexedlog store is corrupted.
+    StoreCorrupted = 1004, Corruption;
+    /// Writing to a store that was opened read-only.
+    ReadOnlyStore = 1005, Internal;
+    /// A fetch was cancelled by the caller.
+    FetchCancelled = 1006, Interrupted;
+    /// A file is stored in LFS, but its pointer is missing or invalid.
+    LfsPointerMissing = 1007, NotFound;
+
+    // dag: 2000-2999
+
+    /// A commit hash cannot be found.
+    VertexNotFound = 2001, NotFound;
+    /// A segment id cannot be found.
+    IdNotFound = 2002, NotFound;
+    /// A lazy graph needs the server to answer a query.
+    NeedSlowPath = 2003, Network;
+    /// The graph storage failed.
+    DagBackendFailed = 2004, Io;
+    /// The graph is inconsistent, or is used incorrectly.
+    DagBug = 2005, Internal;
+    /// No more ids can be allocated in a group.
+    IdOverflow = 2006, Internal;
+
+    // workingcopy: 3000-3999
+
+    /// Walking the working copy failed.
+    WalkFailed = 3001, Io;
+    /// Querying Watchman failed.
+    WatchmanFailed = 3002, External;
+    /// Querying EdenFS failed.
+    EdenFsFailed = 3003, External;
+    /// Reading the tracked files from the treestate failed.
+    TreeStateFailed = 3004, Corruption;
+    /// Reading a sparse profile failed.
+    SparseProfileFailed = 3005, NotFound;
+}
+
+impl ErrorCode {
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|c| c.code() == code)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{} ({})", self.code(), self.name())
+    }
+}
+
+/// A tagging error attaching an [`ErrorCode`] to an error.
+///
+/// It is transparent: the message and the source are the ones of the wrapped
+/// error, so the message is not repeated when printing the error chain. Use
+/// [`CodedError::inner`] to downcast the wrapped error itself.
+#[derive(Debug)]
+pub struct CodedError {
+    pub code: ErrorCode,
+    pub error: Error,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl StdError for CodedError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
+impl CodedError {
+    pub fn wrap(code: ErrorCode, err: impl Into<Error>) -> Error {
+        Self {
+            code,
+            error: err.into(),
+        }
+        .into()
+    }
+
+    /// The wrapped error.
+    pub fn inner(&self) -> &Error {
+        &self.error
+    }
+}
+
+/// Like `err.chain()`, but yield the errors wrapped by [`CodedError`] instead of
+/// the wrappers, so they can be downcast.
+pub fn chain(err: &Error) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+    err.chain().map(|mut e| {
+        while let Some(coded) = e.downcast_ref::<CodedError>() {
+            e = coded.inner().as_ref();
+        }
+        e
+    })
+}
+
+/// Find the outermost error code attached to `err`.
+pub fn error_code(err: &Error) -> Option<ErrorCode> {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<CodedError>())
+        .map(|e| e.code)
+}
+
+pub trait WithErrorCode<T> {
+    /// Attach `code` to the error, unless it already has one.
+    fn with_error_code(self, code: ErrorCode) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> WithErrorCode<T> for Result<T, E> {
+    fn with_error_code(self, code: ErrorCode) -> Result<T, Error> {
+        self.map_err(|e| {
+            let e = e.into();
+            if error_code(&e).is_some() {
+                e
+            } else {
+                CodedError::wrap(code, e)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io;
+
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_codes_are_unique() {
+        let codes: HashSet<u32> = ErrorCode::ALL.iter().map(|c| c.code()).collect();
+        assert_eq!(codes.len(), ErrorCode::ALL.len());
+        assert_eq!(ErrorCode::from_code(1001), Some(ErrorCode::KeyNotFound));
+        assert_eq!(ErrorCode::from_code(0), None);
+    }
+
+    #[test]
+    fn test_error_code() {
+        let err: Result<(), io::Error> = Err(io::Error::from(io::ErrorKind::Other));
+        let err = err
+            .with_error_code(ErrorCode::WalkFailed)
+            .context("walking")
+            .unwrap_err();
+        assert_eq!(error_code(&err), Some(ErrorCode::WalkFailed));
+
+        // An existing code is kept.
+        let err = Err::<(), _>(err)
+            .with_error_code(ErrorCode::KeyNotFound)
+            .unwrap_err();
+        assert_eq!(error_code(&err), Some(ErrorCode::WalkFailed));
+
+        assert_eq!(error_code(&anyhow::anyhow!("plain")), None);
+        assert_eq!(
+            ErrorCode::WalkFailed.to_string(),
+            "E3001 (WalkFailed)".to_string()
+        );
+    }
+
+    #[test]
+    fn test_coded_error_is_transparent() {
+        let err = Err::<(), _>(anyhow::anyhow!("root cause"))
+            .context("reading file")
+            .with_error_code(ErrorCode::WalkFailed)
+            .context("walking")
+            .unwrap_err();
+        assert_eq!(format!("{:#}", err), "walking: reading file: root cause");
+        assert_eq!(err.chain().count(), 3);
+
+        let coded = err.downcast_ref::<CodedError>().unwrap();
+        assert_eq!(coded.inner().to_string(), "reading file");
+
+        let err = Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+            .with_error_code(ErrorCode::WalkFailed)
+            .context("walking")
+            .unwrap_err();
+        assert!(!err.chain().any(|e| e.is::<io::Error>()));
+        assert!(chain(&err).any(|e| e.is::<io::Error>()));
+    }
+}
@@ -41,7 +41,7 @@ pub fn run(opts: DebugScmStoreReplayOpts, io: &IO, repo: &mut Repo) -> Result<u8
             activitylogger::ActivityType::FileFetch => {
                 key_count += log.keys.len();
                 fetch_count += 1;
-                let result = store.fetch(log.keys.into_iter(), log.attrs);
+                let result = store.fetch_with_cause(log.keys.into_iter(), log.attrs, log.cause);
                 match result.missing() {
                     Ok(failed) => {
                         if failed.len() > 0 {
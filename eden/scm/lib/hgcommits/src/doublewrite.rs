@@ -73,6 +73,13 @@ impl ReadCommitText for DoubleWriteCommits {
         }
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        match self.commits.get_commit_raw_text_locally(vertex).await {
+            Ok(None) => self.revlog.get_commit_raw_text_locally(vertex).await,
+            result => result,
+        }
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         self.revlog.to_dyn_read_commit_text()
     }
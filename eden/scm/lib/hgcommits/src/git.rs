@@ -270,6 +270,10 @@ impl ReadCommitText for GitSegmentedCommits {
         self.git_repo.get_commit_raw_text(vertex).await
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        self.git_repo.get_commit_raw_text_locally(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         self.git_repo.to_dyn_read_commit_text()
     }
@@ -300,6 +304,11 @@ impl ReadCommitText for Arc<Mutex<git2::Repository>> {
         Ok(Some(text))
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        // Never fetched from a server.
+        self.get_commit_raw_text(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         Arc::new(self.clone())
     }
@@ -135,6 +135,10 @@ impl ReadCommitText for HgCommits {
         self.commits.get_commit_raw_text(vertex).await
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        self.commits.get_commit_raw_text_locally(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         self.commits.to_dyn_read_commit_text()
     }
@@ -150,6 +154,11 @@ impl ReadCommitText for Arc<RwLock<Zstore>> {
         }
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        // Never fetched from a server.
+        self.get_commit_raw_text(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         Arc::new(self.clone())
     }
@@ -11,6 +11,7 @@ use std::path::Path;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::SeqCst;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use dag::delegate;
@@ -19,6 +20,7 @@ use dag::ops::DagImportCloneData;
 use dag::ops::DagImportPullData;
 use dag::ops::DagPersistent;
 use dag::protocol::AncestorPath;
+use dag::protocol::CoalescingProtocol;
 use dag::protocol::RemoteIdConvertProtocol;
 use dag::CloneData;
 use dag::Location;
@@ -66,6 +68,11 @@ pub struct HybridCommits {
 const EDENSCM_DISABLE_REMOTE_RESOLVE: &str = "EDENSCM_DISABLE_REMOTE_RESOLVE";
 const EDENSCM_REMOTE_ID_THRESHOLD: &str = "EDENSCM_REMOTE_ID_THRESHOLD";
 const EDENSCM_REMOTE_NAME_THRESHOLD: &str = "EDENSCM_REMOTE_NAME_THRESHOLD";
+const EDENSCM_REMOTE_NAME_BATCH_WINDOW_MS: &str = "EDENSCM_REMOTE_NAME_BATCH_WINDOW_MS";
+
+/// How long name resolutions wait for concurrent ones to be sent together,
+/// unless set by `EDENSCM_REMOTE_NAME_BATCH_WINDOW_MS`. 0 disables batching.
+const DEFAULT_REMOTE_NAME_BATCH_WINDOW: Duration = Duration::from_millis(2);
 
 struct EdenApiProtocol {
     client: Arc<dyn EdenApi>,
@@ -235,6 +242,12 @@ impl HybridCommits {
         } else {
             None
         };
+        let batch_window = match std::env::var(EDENSCM_REMOTE_NAME_BATCH_WINDOW_MS) {
+            Ok(env) => env
+                .parse::<u64>()
+                .map_or(DEFAULT_REMOTE_NAME_BATCH_WINDOW, Duration::from_millis),
+            Err(_) => DEFAULT_REMOTE_NAME_BATCH_WINDOW,
+        };
         let protocol = EdenApiProtocol {
             client: self.client.clone(),
             disabled_names,
@@ -243,6 +256,7 @@ impl HybridCommits {
             remote_name_threshold,
             remote_name_current: Default::default(),
         };
+        let protocol = CoalescingProtocol::new(Arc::new(protocol), batch_window);
         self.commits.dag.set_remote_protocol(Arc::new(protocol));
         self.lazy_hash_desc = format!("lazy, using EdenAPI");
     }
@@ -356,6 +370,10 @@ impl ReadCommitText for HybridCommits {
             .await
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        self.commits.get_commit_raw_text_locally(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         Arc::new(self.to_hybrid_commit_text())
     }
@@ -371,6 +389,10 @@ impl ReadCommitText for HybridCommitTextReader {
         Ok(commits)
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        self.zstore.get_commit_raw_text_locally(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         Arc::new(self.clone())
     }
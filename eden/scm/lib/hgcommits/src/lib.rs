@@ -39,6 +39,13 @@ pub trait ReadCommitText: Sync {
         Ok(Some(list.into_iter().next().unwrap()))
     }
 
+    /// Read raw text for a commit, without fetching it from a remote server. Return `None` if
+    /// the text is not present locally.
+    ///
+    /// There is no default implementation, so backends able to fetch commits have to opt out of
+    /// fetching explicitly.
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>>;
+
     /// Read commit text in batch. Any of the missing commits would cause an error.
     async fn get_commit_raw_text_list(&self, vertexes: &[Vertex]) -> Result<Vec<Bytes>> {
         try_join_all(vertexes.iter().map(|v| async move {
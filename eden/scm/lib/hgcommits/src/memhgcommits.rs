@@ -109,6 +109,10 @@ impl ReadCommitText for MemHgCommits {
         self.commits.get_commit_raw_text(vertex).await
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        self.commits.get_commit_raw_text_locally(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         self.commits.to_dyn_read_commit_text()
     }
@@ -120,6 +124,11 @@ impl ReadCommitText for HashMap<Vertex, Bytes> {
         Ok(self.get(vertex).cloned())
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        // Never fetched from a server.
+        self.get_commit_raw_text(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         Arc::new(self.clone())
     }
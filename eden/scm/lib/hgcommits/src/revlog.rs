@@ -100,6 +100,11 @@ impl ReadCommitText for RevlogCommits {
         }
     }
 
+    async fn get_commit_raw_text_locally(&self, vertex: &Vertex) -> Result<Option<Bytes>> {
+        // Never fetched from a server.
+        self.get_commit_raw_text(vertex).await
+    }
+
     fn to_dyn_read_commit_text(&self) -> Arc<dyn ReadCommitText + Send + Sync> {
         Arc::new(self.clone())
     }
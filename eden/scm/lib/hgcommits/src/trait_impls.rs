@@ -36,7 +36,8 @@ impl ReadRootTreeIds for ArcReadCommitText {
     }
 }
 
-fn extract_tree_root_id_from_raw_hg_text(text: &[u8]) -> anyhow::Result<HgId> {
+/// Parse the root tree id from the raw text of a commit in hg format.
+pub fn extract_tree_root_id_from_raw_hg_text(text: &[u8]) -> anyhow::Result<HgId> {
     // The first 40-bytes are hex tree id.
     let hex_tree_id = match text.get(0..HgId::hex_len()) {
         Some(id) => id,
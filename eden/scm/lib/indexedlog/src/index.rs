@@ -193,7 +193,7 @@ fn read_vlq_reverse(buf: &[u8], end_offset: usize) -> io::Result<(u64, usize)> {
 // written to disk. Offsets < DIRTY_OFFSET are on-disk offsets.
 const DIRTY_OFFSET: u64 = 1u64 << 63;
 
-const TYPE_HEAD: u8 = 0;
+pub(crate) const TYPE_HEAD: u8 = 0;
 const TYPE_ROOT: u8 = 1;
 const TYPE_RADIX: u8 = 2;
 const TYPE_LEAF: u8 = 3;
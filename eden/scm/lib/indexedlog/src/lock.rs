@@ -63,6 +63,14 @@ pub struct DirLockOptions {
     pub file_name: &'static str,
 }
 
+/// Lock used by `probe`s to test that a directory can be locked, without
+/// waiting for other processes holding the lock.
+pub(crate) static PROBE_LOCK_OPTS: DirLockOptions = DirLockOptions {
+    exclusive: false,
+    non_blocking: true,
+    file_name: "",
+};
+
 /// Lock used to indicate that a reader is alive.
 ///
 /// This crate generally depends on "append-only" for lock-free reads
@@ -154,6 +162,17 @@ impl ScopedDirLock {
     }
 }
 
+/// Check that `dir` can be locked. Push a problem to `problems` otherwise.
+///
+/// The lock being held by another process is not a problem.
+pub(crate) fn probe_lock(dir: &Path, problems: &mut Vec<String>) {
+    if let Err(e) = ScopedDirLock::new_with_options(dir, &PROBE_LOCK_OPTS) {
+        if e.io_error_kind() != fs2::lock_contended_error().kind() {
+            problems.push(format!("{:?}: cannot be locked: {}", dir, e));
+        }
+    }
+}
+
 impl Drop for ScopedDirLock {
     fn drop(&mut self) {
         self.file.unlock().expect("unlock");
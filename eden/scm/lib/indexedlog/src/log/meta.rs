@@ -149,6 +149,10 @@ impl LogMetadata {
         }
     }
 
+    /// Headers of the metadata file written by this version.
+    pub(crate) const HEADERS: [&'static [u8]; 2] =
+        [HeaderVersion::HEADER_V0, HeaderVersion::HEADER_V1];
+
     /// Test if two Metadata is compatible, aka. having the same length
     /// and epoch.
     pub(crate) fn is_compatible_with(&self, other: &Self) -> bool {
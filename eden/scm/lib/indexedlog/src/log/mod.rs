@@ -77,6 +77,7 @@ mod fold;
 mod meta;
 mod open_options;
 mod path;
+mod probe;
 mod repair;
 #[cfg(test)]
 pub(crate) mod tests;
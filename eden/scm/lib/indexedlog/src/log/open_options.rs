@@ -24,7 +24,7 @@ use crate::log::Log;
 use crate::log::LogMetadata;
 use crate::log::PRIMARY_START_OFFSET;
 
-const INDEX_FILE_PREFIX: &str = "index2-";
+pub(crate) const INDEX_FILE_PREFIX: &str = "index2-";
 const META_PREFIX: &str = "2-";
 
 /// Definition of an index. It includes: name, function to extract index keys,
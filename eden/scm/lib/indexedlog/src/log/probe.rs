@@ -0,0 +1,149 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use crate::index::TYPE_HEAD;
+use crate::lock::probe_lock;
+use crate::log::open_options::INDEX_FILE_PREFIX;
+use crate::log::GenericPath;
+use crate::log::LogMetadata;
+use crate::log::OpenOptions;
+use crate::log::META_FILE;
+use crate::log::PRIMARY_FILE;
+use crate::log::PRIMARY_HEADER;
+use crate::utils;
+
+// Probe
+impl OpenOptions {
+    /// Quickly check whether the [`Log`](crate::log::Log) at the given
+    /// directory can be opened, without opening it.
+    ///
+    /// This checks that the directory can be locked, and that the log,
+    /// metadata and index files have headers this version understands.
+    /// Return the problems found, in human readable form.
+    ///
+    /// Missing or truncated files are not reported, since `open_with_repair`
+    /// takes care of them. A file written by a newer version is reported,
+    /// since repairing it would throw its data away.
+    pub fn probe(&self, dir: impl Into<GenericPath>) -> Vec<String> {
+        let dir = dir.into();
+        let dir = match dir.as_opt_path() {
+            Some(dir) if dir.exists() => dir,
+            _ => return Vec::new(),
+        };
+
+        let mut problems = Vec::new();
+        probe_lock(dir, &mut problems);
+
+        let primary_path = dir.join(PRIMARY_FILE);
+        if let Some(header) = read_header(&primary_path, PRIMARY_HEADER.len(), &mut problems) {
+            // The header is "indexedlog" + version + "\0".
+            let name = &PRIMARY_HEADER[..PRIMARY_HEADER.len() - 2];
+            probe_header(
+                &primary_path,
+                &header,
+                name,
+                &[PRIMARY_HEADER],
+                &mut problems,
+            );
+        }
+
+        let meta_path = dir.join(META_FILE);
+        let meta_header_len = LogMetadata::HEADERS[0].len();
+        match utils::atomic_read(&meta_path) {
+            Ok(data) if data.len() >= meta_header_len => {
+                let header = &data[..meta_header_len];
+                // The header is "meta" + version.
+                let name = &LogMetadata::HEADERS[0][..meta_header_len - 1];
+                probe_header(
+                    &meta_path,
+                    header,
+                    name,
+                    &LogMetadata::HEADERS,
+                    &mut problems,
+                );
+            }
+            Ok(_) => {}
+            Err(e) => push_io_problem(&meta_path, e, &mut problems),
+        }
+
+        if let Ok(entries) = dir.read_dir() {
+            for entry in entries.flatten() {
+                let is_index = entry
+                    .file_name()
+                    .to_str()
+                    .map_or(false, |name| name.starts_with(INDEX_FILE_PREFIX));
+                if !is_index {
+                    continue;
+                }
+                let index_path = entry.path();
+                if let Some(header) = read_header(&index_path, 1, &mut problems) {
+                    if header[0] != TYPE_HEAD {
+                        problems.push(format!(
+                            "{:?}: unexpected index header {:?}",
+                            index_path, header
+                        ));
+                    }
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+/// Read the first `len` bytes of the file at `path`.
+///
+/// Return `None` if the file is missing or shorter than `len`.
+fn read_header(path: &Path, len: usize, problems: &mut Vec<String>) -> Option<Vec<u8>> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            push_io_problem(path, e, problems);
+            return None;
+        }
+    };
+    let mut header = vec![0; len];
+    file.read_exact(&mut header).ok()?;
+    Some(header)
+}
+
+/// Check that `header` is one of `known`. A header starting with `name` is
+/// assumed to be written by a newer version.
+fn probe_header(
+    path: &Path,
+    header: &[u8],
+    name: &[u8],
+    known: &[&[u8]],
+    problems: &mut Vec<String>,
+) {
+    if known.contains(&header) {
+        return;
+    }
+    if header.starts_with(name) {
+        problems.push(format!(
+            "{:?}: created by a newer version (header {:?})",
+            path,
+            String::from_utf8_lossy(header)
+        ));
+    } else {
+        problems.push(format!(
+            "{:?}: unexpected header {:?}",
+            path,
+            String::from_utf8_lossy(header)
+        ));
+    }
+}
+
+fn push_io_problem(path: &Path, e: std::io::Error, problems: &mut Vec<String>) {
+    if e.kind() != std::io::ErrorKind::NotFound {
+        problems.push(format!("{:?}: cannot be read: {}", path, e));
+    }
+}
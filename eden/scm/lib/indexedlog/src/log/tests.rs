@@ -1014,6 +1014,40 @@ fn test_repair_noop() {
     assert_eq!(meta_before, meta_after);
 }
 
+#[test]
+fn test_probe() {
+    let dir = tempdir().unwrap();
+    let index_def = IndexDef::new("idx", |_| vec![IndexOutput::Reference(0..1)]).lag_threshold(0);
+    let opts = OpenOptions::new().create(true).index_defs(vec![index_def]);
+
+    // A missing Log is not a problem. It is created on open.
+    assert!(opts.probe(dir.path().join("missing")).is_empty());
+
+    let mut log = opts.open(dir.path()).unwrap();
+    log.append(b"abc").unwrap();
+    log.sync().unwrap();
+    drop(log);
+    assert!(opts.probe(dir.path()).is_empty());
+
+    // Metadata written by a newer version.
+    let meta_path = dir.path().join(META_FILE);
+    let mut meta = utils::atomic_read(&meta_path).unwrap();
+    meta[4] = 9;
+    utils::atomic_write_plain(&meta_path, &meta, false).unwrap();
+    let problems = opts.probe(dir.path());
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("created by a newer version"));
+
+    // Index with an unexpected header.
+    let index_path = dir.path().join("index2-idx");
+    let mut index = fs::read(&index_path).unwrap();
+    index[0] = 42;
+    fs::write(&index_path, index).unwrap();
+    let problems = opts.probe(dir.path());
+    assert_eq!(problems.len(), 2);
+    assert!(problems[1].contains("unexpected index header"));
+}
+
 #[test]
 fn test_repair_and_delete_content() {
     let dir = tempdir().unwrap();
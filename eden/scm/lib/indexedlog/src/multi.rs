@@ -9,6 +9,7 @@
 
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::fs;
 use std::io;
 use std::mem;
 use std::ops;
@@ -22,6 +23,7 @@ use vlqencoding::VLQEncode;
 
 use crate::errors::IoResultExt;
 use crate::errors::ResultExt;
+use crate::lock::probe_lock;
 use crate::lock::ScopedDirLock;
 use crate::lock::READER_LOCK_OPTS;
 use crate::log;
@@ -196,6 +198,23 @@ impl OpenOptions {
 
         result.context("in multi::OpenOptions::open")
     }
+
+    /// Quickly check whether the [`MultiLog`] at `path` can be opened,
+    /// without opening it. Return the problems found, in human readable form.
+    ///
+    /// See [`log::OpenOptions::probe`] for what is checked.
+    pub fn probe(&self, path: &Path) -> Vec<String> {
+        if !path.exists() {
+            return Vec::new();
+        }
+        let mut problems = Vec::new();
+        probe_lock(path, &mut problems);
+        problems.extend(multi_meta_log_open_options().probe(multi_meta_log_path(path)));
+        for (name, opts) in self.name_open_options.iter() {
+            problems.extend(opts.probe(path.join(name)));
+        }
+        problems
+    }
 }
 
 impl MultiLog {
@@ -220,14 +239,7 @@ impl MultiLog {
     /// A lock must be provided to prove that there is no race condition.
     /// The lock is usually obtained via `lock()`.
     pub fn write_meta(&mut self, lock: &LockGuard) -> crate::Result<()> {
-        if lock.0.path() != self.path {
-            let msg = format!(
-                "Invalid lock used to write_meta (Lock path = {:?}, MultiLog path = {:?})",
-                lock.0.path(),
-                &self.path
-            );
-            return Err(crate::Error::programming(msg));
-        }
+        self.check_lock(lock, "write_meta")?;
         let result: crate::Result<_> = (|| {
             self.multimeta.bump_version();
             if !self.leacy_multimeta_source {
@@ -244,6 +256,80 @@ impl MultiLog {
         result.context("in MultiLog::write_meta")
     }
 
+    /// Save the metadata as a named checkpoint.
+    ///
+    /// [`Log`]s are append-only, so the metadata is enough to restore them to
+    /// their current state using [`MultiLog::rollback`] later. An existing
+    /// checkpoint with the same name is replaced.
+    ///
+    /// A lock must be provided so the saved metadata is the latest on disk.
+    pub fn checkpoint(&mut self, name: &str, lock: &LockGuard) -> crate::Result<()> {
+        self.check_lock(lock, "checkpoint")?;
+        let result: crate::Result<_> = (|| {
+            let path = multi_meta_checkpoint_path(&self.path, name)?;
+            self.multimeta.write_file(&path)?;
+            Ok(())
+        })();
+        result.context(|| format!("in MultiLog::checkpoint({:?})", name))
+    }
+
+    /// Restore the metadata saved by [`MultiLog::checkpoint`] and write it to
+    /// disk, so content added to [`Log`]s after the checkpoint is dropped.
+    ///
+    /// Unlike other metadata changes, this is not append-only. The version
+    /// is reset, and other processes, as well as detached [`Log`]s, need to
+    /// reload to see the change.
+    pub fn rollback(&mut self, name: &str, lock: &LockGuard) -> crate::Result<()> {
+        self.check_lock(lock, "rollback")?;
+        let result: crate::Result<_> = (|| {
+            let path = multi_meta_checkpoint_path(&self.path, name)?;
+            let buf = match utils::atomic_read(&path) {
+                Ok(buf) => buf,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(crate::Error::path(&path, "checkpoint does not exist"));
+                }
+                Err(e) => return Err(e).context(&path, "cannot read checkpoint"),
+            };
+            self.multimeta
+                .read(&buf[..])
+                .context(&path, "when decoding MultiMeta")?;
+            self.multimeta.version = (rand_u64(), 0);
+            self.write_meta(lock)
+        })();
+        result.context(|| format!("in MultiLog::rollback({:?})", name))
+    }
+
+    /// Remove a checkpoint. Removing a checkpoint that does not exist is not
+    /// an error.
+    pub fn remove_checkpoint(&mut self, name: &str, lock: &LockGuard) -> crate::Result<()> {
+        self.check_lock(lock, "remove_checkpoint")?;
+        let path = multi_meta_checkpoint_path(&self.path, name)?;
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).context(&path, "cannot remove checkpoint")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// List names of checkpoints, sorted.
+    pub fn checkpoints(&self) -> crate::Result<Vec<String>> {
+        let mut names = Vec::new();
+        let entries = fs::read_dir(&self.path).context(&self.path, "cannot read directory")?;
+        for entry in entries {
+            let entry = entry.context(&self.path, "cannot read directory entry")?;
+            if let Some(name) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.strip_prefix(CHECKPOINT_PREFIX))
+            {
+                names.push(name.to_string());
+            }
+        }
+        names.sort_unstable();
+        Ok(names)
+    }
+
     /// Return the version in `(a, b)` form.
     ///
     /// Version `(a, b)` only has append-only data than version `(c, d)`, if
@@ -256,6 +342,19 @@ impl MultiLog {
         self.multimeta.version
     }
 
+    fn check_lock(&self, lock: &LockGuard, func_name: &str) -> crate::Result<()> {
+        if lock.0.path() != self.path {
+            let msg = format!(
+                "Invalid lock used to {} (Lock path = {:?}, MultiLog path = {:?})",
+                func_name,
+                lock.0.path(),
+                &self.path
+            );
+            return Err(crate::Error::programming(msg));
+        }
+        Ok(())
+    }
+
     /// Reload meta from disk so they become visible to Logs.
     ///
     /// This is called automatically by `lock` so it's not part of the
@@ -498,6 +597,17 @@ fn multi_meta_log_path(dir: &Path) -> PathBuf {
     dir.join("multimetalog")
 }
 
+/// Prefix of checkpoint file names.
+const CHECKPOINT_PREFIX: &str = "multimeta.checkpoint.";
+
+fn multi_meta_checkpoint_path(dir: &Path, name: &str) -> crate::Result<PathBuf> {
+    if name.is_empty() || name.contains('/') || name.contains('\\') {
+        let msg = format!("invalid checkpoint name {:?}", name);
+        return Err(crate::Error::programming(msg));
+    }
+    Ok(dir.join(format!("{}{}", CHECKPOINT_PREFIX, name)))
+}
+
 /// Indent lines by 2 spaces.
 fn indent(s: &str) -> String {
     s.lines()
@@ -765,6 +875,50 @@ mod tests {
         assert_eq!(mlog2[1].iter().count(), 1);
     }
 
+    #[test]
+    fn test_checkpoint_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        let mut mlog = simple_multilog(path);
+        mlog[0].append(b"1").unwrap();
+        mlog.sync().unwrap();
+
+        let lock = mlog.lock().unwrap();
+        mlog.checkpoint("a", &lock).unwrap();
+        drop(lock);
+        assert_eq!(mlog.checkpoints().unwrap(), vec!["a".to_string()]);
+
+        mlog[0].append(b"2").unwrap();
+        mlog[1].append(b"x").unwrap();
+        mlog.sync().unwrap();
+        let version = mlog.version();
+
+        let lock = mlog.lock().unwrap();
+        mlog.rollback("a", &lock).unwrap();
+        assert!(mlog.rollback("b", &lock).is_err());
+        assert!(mlog.checkpoint("a/b", &lock).is_err());
+        drop(lock);
+        assert_ne!(mlog.version().0, version.0);
+
+        // Content after the checkpoint is dropped.
+        let mut mlog = simple_multilog(path);
+        assert_eq!(mlog[0].iter().count(), 1);
+        assert_eq!(mlog[1].iter().count(), 0);
+
+        // Logs can still be appended to after rollback.
+        mlog[0].append(b"3").unwrap();
+        mlog.sync().unwrap();
+        let mut mlog = simple_multilog(path);
+        let entries: Vec<_> = mlog[0].iter().map(|e| e.unwrap().to_vec()).collect();
+        assert_eq!(entries, vec![b"1".to_vec(), b"3".to_vec()]);
+
+        let lock = mlog.lock().unwrap();
+        mlog.remove_checkpoint("a", &lock).unwrap();
+        mlog.remove_checkpoint("a", &lock).unwrap();
+        drop(lock);
+        assert!(mlog.checkpoints().unwrap().is_empty());
+    }
+
     #[test]
     fn test_new_index_built_only_once() {
         let dir = tempfile::tempdir().unwrap();
@@ -23,6 +23,7 @@ use tracing::trace;
 
 use crate::errors::IoResultExt;
 use crate::errors::ResultExt;
+use crate::lock::probe_lock;
 use crate::lock::ScopedDirLock;
 use crate::lock::READER_LOCK_OPTS;
 use crate::log;
@@ -315,6 +316,35 @@ impl OpenOptions {
         })()
         .context(|| format!("in rotate::OpenOptions::repair({:?})", dir))
     }
+
+    /// Quickly check whether the [`RotateLog`] at `dir` can be opened,
+    /// without opening it. Return the problems found, in human readable form.
+    ///
+    /// Only the latest log, which `open` loads eagerly, is checked. See
+    /// [`log::OpenOptions::probe`] for what is checked.
+    pub fn probe(&self, dir: impl AsRef<Path>) -> Vec<String> {
+        let dir = dir.as_ref();
+        if !dir.exists() {
+            return Vec::new();
+        }
+        let mut problems = Vec::new();
+        probe_lock(dir, &mut problems);
+        match read_latest_raw(dir) {
+            Ok(latest) => {
+                problems.extend(self.log_open_options.probe(dir.join(latest.to_string())));
+            }
+            // Repaired on open.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound
+                        | io::ErrorKind::InvalidData
+                        | io::ErrorKind::UnexpectedEof
+                ) => {}
+            Err(e) => problems.push(format!("{:?}: cannot read latest: {}", dir, e)),
+        }
+        problems
+    }
 }
 
 impl OpenOptionsRepair for OpenOptions {
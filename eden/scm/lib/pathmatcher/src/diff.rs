@@ -0,0 +1,155 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Compare two matchers to find the directories whose matching status changed.
+//!
+//! This is used when updating a sparse profile: only the returned directories need to be
+//! visited to find the files to materialize or to remove.
+
+use anyhow::Result;
+use types::RepoPath;
+use types::RepoPathBuf;
+
+use crate::DirectoryMatch;
+use crate::Matcher;
+use crate::XorMatcher;
+
+/// How the matching status of a directory changed.
+#[derive(Debug, Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum DirectoryChange {
+    /// Every file of the directory is matched by the new matcher, while some were not matched by
+    /// the old one.
+    Added,
+    /// No file of the directory is matched by the new matcher, while some were matched by the old
+    /// one.
+    Removed,
+    /// Some of the files directly inside the directory may have changed, and need to be checked
+    /// with `matches_file`. Its subdirectories are reported separately.
+    Files,
+}
+
+/// Return the directories whose matching status differs between `old` and `new`.
+///
+/// `list_dir` returns the subdirectories of a directory. It is only called on directories that
+/// are partially changed: directories where both matchers agree are skipped with their whole
+/// subtree, and `Added` or `Removed` directories are not descended into.
+pub fn diff_matchers(
+    old: &dyn Matcher,
+    new: &dyn Matcher,
+    mut list_dir: impl FnMut(&RepoPath) -> Result<Vec<RepoPathBuf>>,
+) -> Result<Vec<(RepoPathBuf, DirectoryChange)>> {
+    let xor = XorMatcher::new(old, new);
+    let mut changes = Vec::new();
+    let mut to_visit = vec![RepoPathBuf::new()];
+    while let Some(dir) = to_visit.pop() {
+        if xor.matches_directory(&dir)? == DirectoryMatch::Nothing {
+            continue;
+        }
+        let change = match (old.matches_directory(&dir)?, new.matches_directory(&dir)?) {
+            (old, new) if old == new && old != DirectoryMatch::ShouldTraverse => continue,
+            (_, DirectoryMatch::Everything) => DirectoryChange::Added,
+            (_, DirectoryMatch::Nothing) => DirectoryChange::Removed,
+            _ => {
+                to_visit.extend(list_dir(&dir)?);
+                DirectoryChange::Files
+            }
+        };
+        changes.push((dir, change));
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::TreeMatcher;
+
+    fn path(s: &str) -> RepoPathBuf {
+        RepoPathBuf::from_string(s.to_string()).unwrap()
+    }
+
+    /// List the subdirectories of `dir` among `dirs`, and record the visit.
+    fn lister<'a>(
+        dirs: &'a [&'a str],
+        visited: &'a mut BTreeSet<String>,
+    ) -> impl FnMut(&RepoPath) -> Result<Vec<RepoPathBuf>> + 'a {
+        move |dir| {
+            visited.insert(dir.as_str().to_string());
+            Ok(dirs
+                .iter()
+                .map(|d| path(d))
+                .filter(|d| d.parent() == Some(dir))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_diff_matchers() -> Result<()> {
+        let dirs = ["a", "a/x", "a/y", "b", "b/z", "c"];
+        let old = TreeMatcher::from_rules(["a/x/**", "b/**"].iter())?;
+        let new = TreeMatcher::from_rules(["a/**", "b/**", "!a/y/**"].iter())?;
+
+        let mut visited = BTreeSet::new();
+        let mut changes = diff_matchers(&old, &new, lister(&dirs, &mut visited))?;
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                (path(""), DirectoryChange::Files),
+                (path("a"), DirectoryChange::Files)
+            ]
+        );
+        // The unchanged "b" and "c" are not listed.
+        assert_eq!(
+            visited.into_iter().collect::<Vec<_>>(),
+            vec!["".to_string(), "a".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_matchers_added_removed() -> Result<()> {
+        let dirs = ["a", "a/x", "b"];
+        let old = TreeMatcher::from_rules(["b/**"].iter())?;
+        let new = TreeMatcher::from_rules(["a/**"].iter())?;
+
+        let mut visited = BTreeSet::new();
+        let mut changes = diff_matchers(&old, &new, lister(&dirs, &mut visited))?;
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                (path(""), DirectoryChange::Files),
+                (path("a"), DirectoryChange::Added),
+                (path("b"), DirectoryChange::Removed),
+            ]
+        );
+        assert_eq!(
+            visited.into_iter().collect::<Vec<_>>(),
+            vec!["".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_matchers_same() -> Result<()> {
+        let dirs = ["a", "a/x", "b"];
+        let matcher = TreeMatcher::from_rules(["a/**"].iter())?;
+
+        // The root is only partially matched, so it can't be skipped without listing it.
+        let mut visited = BTreeSet::new();
+        let changes = diff_matchers(&matcher, &matcher, lister(&dirs, &mut visited))?;
+        assert_eq!(changes, vec![(path(""), DirectoryChange::Files)]);
+        assert_eq!(
+            visited.into_iter().collect::<Vec<_>>(),
+            vec!["".to_string()]
+        );
+        Ok(())
+    }
+}
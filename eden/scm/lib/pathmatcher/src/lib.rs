@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 
+mod diff;
 mod exact_matcher;
 mod gitignore_matcher;
 mod tree_matcher;
@@ -198,6 +199,8 @@ impl Matcher for UnionMatcher {
     }
 }
 
+pub use diff::diff_matchers;
+pub use diff::DirectoryChange;
 pub use exact_matcher::ExactMatcher;
 pub use gitignore_matcher::GitignoreMatcher;
 pub use tree_matcher::TreeMatcher;
@@ -68,6 +68,11 @@ pub(crate) fn open_dag_commits(
     Ok(commits)
 }
 
+/// Quickly check that the segments of the commit graph can be opened, see `NameDag::probe`.
+pub(crate) fn probe_dag_commits(store_path: &Path) -> dag::Result<()> {
+    dag::NameDag::probe(calculate_segments_path(store_path))
+}
+
 fn open_git(
     store_path: &Path,
     metalog: Arc<RwLock<MetaLog>>,
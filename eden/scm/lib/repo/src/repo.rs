@@ -12,14 +12,23 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::Result;
+use async_runtime::block_on;
 use configparser::config::ConfigSet;
 use configparser::Config;
+use dag::ops::IdConvert;
+use dag::Vertex;
 use edenapi::Builder;
 use edenapi::EdenApi;
 use edenapi::EdenApiError;
+use hgcommits::trait_impls::extract_tree_root_id_from_raw_hg_text;
 use hgcommits::DagCommits;
+use hgcommits::ReadCommitText;
+use manifest_tree::Flag;
 use metalog::MetaLog;
+use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
+use revisionstore::scmstore;
+use revisionstore::scmstore::FileAttributes;
 use revisionstore::scmstore::FileStoreBuilder;
 use revisionstore::scmstore::TreeStoreBuilder;
 use revisionstore::trait_impls::ArcFileStore;
@@ -29,9 +38,12 @@ use revisionstore::MemcacheStore;
 use storemodel::ReadFileContents;
 use storemodel::TreeStore;
 use types::HgId;
+use types::Key;
+use types::RepoPathBuf;
 use util::path::absolute;
 
 use crate::commits::open_dag_commits;
+use crate::commits::probe_dag_commits;
 use crate::errors;
 use crate::init;
 use crate::requirements::Requirements;
@@ -51,6 +63,7 @@ pub struct Repo {
     dag_commits: Option<Arc<RwLock<Box<dyn DagCommits + Send + 'static>>>>,
     file_store: Option<Arc<dyn ReadFileContents<Error = anyhow::Error> + Send + Sync>>,
     tree_store: Option<Arc<dyn TreeStore + Send + Sync>>,
+    local_stores: OnceCell<(scmstore::FileStore, scmstore::TreeStore)>,
 }
 
 impl Repo {
@@ -114,6 +127,17 @@ impl Repo {
         let requirements = Requirements::open(&dot_hg_path.join("requires"))?;
         let store_requirements = Requirements::open(&store_path.join("requires"))?;
 
+        // Report stores that can't be used (ex. written by a newer version) once, instead of as
+        // confusing failures when they are first read.
+        probe_dag_commits(&store_path)?;
+        FileStoreBuilder::new(&config)
+            .local_path(&store_path)
+            .probe()?;
+        TreeStoreBuilder::new(&config)
+            .local_path(&store_path)
+            .suffix("manifests")
+            .probe()?;
+
         Ok(Repo {
             path,
             config,
@@ -129,6 +153,7 @@ impl Repo {
             dag_commits: None,
             file_store: None,
             tree_store: None,
+            local_stores: OnceCell::new(),
         })
     }
 
@@ -292,6 +317,82 @@ impl Repo {
         self.tree_store = Some(ts.clone());
         Ok(ts)
     }
+
+    /// Test if a commit of `commits`, as returned by `dag_commits`, can be used without fetching
+    /// anything: the dag knows it, and its text and root tree are present locally. With
+    /// `check_files`, all the trees and file contents of the commit must be present too.
+    ///
+    /// Commands use this to decide between operating locally and fetching from the server.
+    pub fn has_commit_complete(
+        &self,
+        commits: &(dyn DagCommits + Send),
+        vertex: &Vertex,
+        check_files: bool,
+    ) -> Result<bool> {
+        let text = block_on(async {
+            let known = commits
+                .contains_vertex_name_locally(&[vertex.clone()])
+                .await?;
+            if !known[0] {
+                return Ok(None);
+            }
+            commits.get_commit_raw_text_locally(vertex).await
+        })?;
+        let root_tree = match text {
+            Some(text) => extract_tree_root_id_from_raw_hg_text(&text)?,
+            None => return Ok(false),
+        };
+
+        let (file_store, tree_store) = self.local_stores()?;
+        let mut trees = vec![Key::new(RepoPathBuf::new(), root_tree)];
+        let mut files = vec![];
+        while !trees.is_empty() {
+            let (found, missing, mut errors) = tree_store.fetch_batch(trees.into_iter())?.consume();
+            if let Some(err) = errors.pop() {
+                return Err(err);
+            }
+            if !missing.is_empty() {
+                return Ok(false);
+            }
+            if !check_files {
+                return Ok(true);
+            }
+
+            trees = vec![];
+            for (key, mut tree) in found {
+                for element in tree.manifest_tree_entry()?.elements() {
+                    let element = element?;
+                    let mut path = key.path.clone();
+                    path.push(element.component.as_path_component());
+                    match element.flag {
+                        Flag::Directory => trees.push(Key::new(path, element.hgid)),
+                        Flag::File(_) => files.push(Key::new(path, element.hgid)),
+                    }
+                }
+            }
+        }
+
+        let missing = file_store
+            .fetch(files.into_iter(), FileAttributes::CONTENT)
+            .missing()?;
+        Ok(missing.is_empty())
+    }
+
+    /// The file and tree stores without their remote stores, built once.
+    fn local_stores(&self) -> Result<&(scmstore::FileStore, scmstore::TreeStore)> {
+        self.local_stores.get_or_try_init(|| {
+            let file_store = FileStoreBuilder::new(self.config())
+                .local_path(self.store_path())
+                .build()?
+                .local();
+            let tree_store = TreeStoreBuilder::new(self.config())
+                .local_path(self.store_path())
+                .suffix("manifests")
+                .build()?
+                .local();
+            Ok((file_store, tree_store))
+        })
+    }
 }
 
 fn read_sharedpath(path: &Path) -> Result<PathBuf> {
@@ -330,3 +431,44 @@ impl std::fmt::Debug for Repo {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use configparser::config::Options;
+    use hgcommits::AppendCommits;
+    use hgcommits::HgCommit;
+    use types::Parents;
+
+    use super::*;
+
+    #[test]
+    fn test_has_commit_complete() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut config = ConfigSet::new();
+        config.set(
+            "format",
+            "use-segmented-changelog",
+            Some("true"),
+            &Options::default(),
+        );
+        let mut repo = Repo::init(tmp.path(), &config, None, &[])?;
+        let commits = repo.dag_commits()?;
+
+        let root_tree = HgId::from_byte_array([2; 20]);
+        let raw_text = format!("{}\ntest\n0 0\n\nmessage", root_tree.to_hex());
+        let hgid = HgId::from_content(raw_text.as_bytes(), Parents::None);
+        let vertex = Vertex::copy_from(hgid.as_ref());
+        assert!(!repo.has_commit_complete(&**commits.read(), &vertex, false)?);
+
+        // The commit is known, but not its root tree.
+        let commit = HgCommit {
+            vertex: vertex.clone(),
+            parents: Vec::new(),
+            raw_text: raw_text.into_bytes().into(),
+        };
+        block_on(commits.write().add_commits(&[commit]))?;
+        assert!(!repo.has_commit_complete(&**commits.read(), &vertex, false)?);
+        assert!(!repo.has_commit_complete(&**commits.read(), &vertex, true)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use minibench::bench;
+use minibench::elapsed;
+use minibytes::Bytes;
+use revisionstore::scmstore::FileAttributes;
+use revisionstore::scmstore::FileStore;
+use revisionstore::scmstore::FileStoreBuilder;
+use revisionstore::testutil::make_config;
+use tempfile::tempdir;
+use types::testutil::key;
+use types::Key;
+
+const N: usize = 200000;
+
+/// Get a local-only `FileStore` which splits local lookups across `shards` threads.
+fn file_store(dir: &tempfile::TempDir, shards: usize) -> FileStore {
+    let mut config = make_config(dir.path().join("cache"));
+    config.set(
+        "scmstore",
+        "localfetchshards",
+        Some(shards.to_string()),
+        &Default::default(),
+    );
+    FileStoreBuilder::new(&config)
+        .local_path(dir.path().join("local"))
+        .build()
+        .unwrap()
+}
+
+fn main() {
+    let dir = tempdir().unwrap();
+    let keys: Vec<Key> = (1..=N)
+        .map(|i| key(&format!("f{}", i), &format!("{:x}", i)))
+        .collect();
+
+    let store = file_store(&dir, 1);
+    store
+        .write_batch(keys.iter().map(|k| {
+            let content = Bytes::from(format!("content of {}", k.path));
+            (k.clone(), content, Default::default())
+        }))
+        .unwrap();
+    store.flush().unwrap();
+    drop(store);
+
+    for shards in [1, 2, 4, 8] {
+        let store = file_store(&dir, shards);
+        bench(format!("local fetch ({} shards)", shards), || {
+            elapsed(|| {
+                let (found, _, _) = store
+                    .fetch(keys.clone().into_iter(), FileAttributes::CONTENT)
+                    .consume();
+                assert_eq!(found.len(), N);
+            })
+        });
+    }
+}
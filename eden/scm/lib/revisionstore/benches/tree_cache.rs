@@ -0,0 +1,95 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use configparser::config::ConfigSet;
+use minibench::bench;
+use minibench::elapsed;
+use minibytes::Bytes;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use revisionstore::indexedlogdatastore::Entry;
+use revisionstore::treepagestore::TreePageStore;
+use revisionstore::ExtStoredPolicy;
+use revisionstore::IndexedLogHgIdDataStore;
+use revisionstore::IndexedLogHgIdDataStoreConfig;
+use revisionstore::StoreType;
+use tempfile::tempdir;
+use types::testutil::key;
+use types::Key;
+
+const N: usize = 200000;
+
+/// Trees of a few entries, in the order they would be fetched.
+fn trees() -> Vec<(Key, Bytes)> {
+    (1..=N)
+        .map(|i| {
+            let key = key(&format!("d{}", i), &format!("{:x}", i));
+            let tree = Bytes::from(format!("file{}\0{}\n", i, key.hgid).repeat(4));
+            (key, tree)
+        })
+        .collect()
+}
+
+fn main() {
+    let trees = trees();
+    let mut lookups: Vec<Key> = trees.iter().map(|(key, _)| key.clone()).collect();
+    lookups.shuffle(&mut ChaChaRng::seed_from_u64(0));
+
+    let dir = tempdir().unwrap();
+    let config = IndexedLogHgIdDataStoreConfig {
+        max_log_count: None,
+        max_bytes_per_log: None,
+        max_bytes: None,
+    };
+    let store = IndexedLogHgIdDataStore::new(
+        dir.path().join("indexedlog"),
+        ExtStoredPolicy::Use,
+        &config,
+        StoreType::Shared,
+    )
+    .unwrap();
+    for (key, tree) in trees.iter() {
+        store
+            .put_entry(Entry::new(key.clone(), tree.clone(), Default::default()))
+            .unwrap();
+    }
+    store.flush_log().unwrap();
+    bench("random tree lookups (indexedlog)", || {
+        elapsed(|| {
+            for key in lookups.iter() {
+                assert!(store.get_entry(key.clone()).unwrap().is_some());
+            }
+        })
+    });
+
+    for page_size in ["16KB", "64KB", "256KB"] {
+        let mut config = ConfigSet::new();
+        config.set(
+            "scmstore",
+            "tree-page-size",
+            Some(page_size),
+            &Default::default(),
+        );
+        let path = dir.path().join(format!("pages-{}", page_size));
+        let store = TreePageStore::new(&path, &config).unwrap();
+        for (key, tree) in trees.iter() {
+            store.put(key.hgid, tree.clone()).unwrap();
+        }
+        store.flush().unwrap();
+        bench(
+            format!("random tree lookups (pages of {})", page_size),
+            || {
+                elapsed(|| {
+                    for key in lookups.iter() {
+                        assert!(store.get(&key.hgid).unwrap().is_some());
+                    }
+                })
+            },
+        );
+    }
+}
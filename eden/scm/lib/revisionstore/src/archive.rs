@@ -0,0 +1,530 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Self-contained archives of store entries, to mirror caches between machines.
+//!
+//! An archive starts with [`MAGIC`], followed by the entries, each a big-endian `u32` length and
+//! the mincode encoding of the entry. A zero length ends the entries, and is followed by the
+//! SHA-256 of everything before it. [`import_archive`] checks the whole archive before adding
+//! anything to the stores, so a truncated or corrupted archive leaves them untouched.
+
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::bail;
+use anyhow::format_err;
+use anyhow::Context;
+use anyhow::Result;
+use byteorder::BigEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use mincode::deserialize;
+use mincode::serialize;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use types::Key;
+use types::NodeInfo;
+
+use crate::datastore::Delta;
+use crate::datastore::HgIdDataStore;
+use crate::datastore::HgIdMutableDeltaStore;
+use crate::datastore::Metadata;
+use crate::datastore::StoreResult;
+use crate::historystore::HgIdHistoryStore;
+use crate::historystore::HgIdMutableHistoryStore;
+use crate::types::StoreKey;
+
+const MAGIC: &[u8] = b"HGARCHIVE\x00\x01";
+const CHECKSUM_LEN: usize = 32;
+
+/// The kinds of entries an archive holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ArchiveEntryKind {
+    File,
+    Tree,
+    History,
+}
+
+impl FromStr for ArchiveEntryKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "file" => Ok(ArchiveEntryKind::File),
+            "tree" => Ok(ArchiveEntryKind::Tree),
+            "history" => Ok(ArchiveEntryKind::History),
+            _ => bail!("unknown archive entry kind '{}'", s),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveEntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveEntryKind::File => write!(f, "file"),
+            ArchiveEntryKind::Tree => write!(f, "tree"),
+            ArchiveEntryKind::History => write!(f, "history"),
+        }
+    }
+}
+
+/// The stores `export_keys` reads from. Exporting a kind of entry without a store fails.
+#[derive(Default)]
+pub struct ArchiveSource<'a> {
+    pub files: Option<&'a dyn HgIdDataStore>,
+    pub trees: Option<&'a dyn HgIdDataStore>,
+    pub history: Option<&'a dyn HgIdHistoryStore>,
+}
+
+/// The stores `import_archive` writes to. Importing an archive with a kind of entry without a
+/// store fails.
+#[derive(Default)]
+pub struct ArchiveTarget<'a> {
+    pub files: Option<&'a dyn HgIdMutableDeltaStore>,
+    pub trees: Option<&'a dyn HgIdMutableDeltaStore>,
+    pub history: Option<&'a dyn HgIdMutableHistoryStore>,
+}
+
+/// Number of entries of each kind exported or imported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    pub files: usize,
+    pub trees: usize,
+    pub history: usize,
+}
+
+impl ArchiveStats {
+    fn count(&mut self, kind: ArchiveEntryKind) {
+        match kind {
+            ArchiveEntryKind::File => self.files += 1,
+            ArchiveEntryKind::Tree => self.trees += 1,
+            ArchiveEntryKind::History => self.history += 1,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Entry {
+    File {
+        key: Key,
+        data: Vec<u8>,
+        metadata: Metadata,
+    },
+    Tree {
+        key: Key,
+        data: Vec<u8>,
+        metadata: Metadata,
+    },
+    History {
+        key: Key,
+        info: NodeInfo,
+    },
+}
+
+impl Entry {
+    fn kind(&self) -> ArchiveEntryKind {
+        match self {
+            Entry::File { .. } => ArchiveEntryKind::File,
+            Entry::Tree { .. } => ArchiveEntryKind::Tree,
+            Entry::History { .. } => ArchiveEntryKind::History,
+        }
+    }
+}
+
+fn read_data(store: &dyn HgIdDataStore, key: &Key) -> Result<(Vec<u8>, Metadata)> {
+    let store_key = StoreKey::hgid(key.clone());
+    let data = match store.get(store_key.clone())? {
+        StoreResult::Found(data) => data,
+        StoreResult::NotFound(_) => bail!("{} not found", key),
+    };
+    let metadata = match store.get_meta(store_key)? {
+        StoreResult::Found(metadata) => metadata,
+        StoreResult::NotFound(_) => bail!("metadata of {} not found", key),
+    };
+    Ok((data, metadata))
+}
+
+fn read_entry(source: &ArchiveSource, kind: ArchiveEntryKind, key: &Key) -> Result<Entry> {
+    let missing_store = || format_err!("no {} store to export {} from", kind, key);
+    Ok(match kind {
+        ArchiveEntryKind::File => {
+            let (data, metadata) = read_data(source.files.ok_or_else(missing_store)?, key)?;
+            Entry::File {
+                key: key.clone(),
+                data,
+                metadata,
+            }
+        }
+        ArchiveEntryKind::Tree => {
+            let (data, metadata) = read_data(source.trees.ok_or_else(missing_store)?, key)?;
+            Entry::Tree {
+                key: key.clone(),
+                data,
+                metadata,
+            }
+        }
+        ArchiveEntryKind::History => {
+            let store = source.history.ok_or_else(missing_store)?;
+            let info = store
+                .get_node_info(key)?
+                .ok_or_else(|| format_err!("history of {} not found", key))?;
+            Entry::History {
+                key: key.clone(),
+                info,
+            }
+        }
+    })
+}
+
+/// Write the entries of `keys` to a new archive at `path`. All the keys must be found in the
+/// stores of `source`.
+pub fn export_keys(
+    source: &ArchiveSource,
+    keys: &[(ArchiveEntryKind, Key)],
+    path: &Path,
+) -> Result<ArchiveStats> {
+    let mut stats = ArchiveStats::default();
+    let mut buf = Vec::new();
+    // The archive is written to a temporary file renamed to `path` once complete, so that a
+    // failed export doesn't leave a truncated archive, or replace an existing one.
+    let mut file = util::file::atomic_open(path)?;
+    let mut writer = BufWriter::new(file.as_file());
+    let mut hasher = Sha256::new();
+
+    let mut write = |bytes: &[u8]| -> Result<()> {
+        hasher.update(bytes);
+        writer.write_all(bytes)?;
+        Ok(())
+    };
+
+    write(MAGIC)?;
+    for (kind, key) in keys {
+        let entry = read_entry(source, *kind, key)
+            .with_context(|| format!("exporting {} {}", kind, key))?;
+        let bytes = serialize(&entry)?;
+        buf.clear();
+        buf.write_u32::<BigEndian>(bytes.len().try_into()?)?;
+        write(&buf)?;
+        write(&bytes)?;
+        stats.count(*kind);
+    }
+    write(&0u32.to_be_bytes())?;
+
+    let checksum = hasher.finalize();
+    writer.write_all(&checksum)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    file.save()?;
+    Ok(stats)
+}
+
+/// Reads the entries of an archive, checking the checksum once the last one is read.
+struct ArchiveReader {
+    reader: BufReader<File>,
+    hasher: Sha256,
+    done: bool,
+}
+
+impl ArchiveReader {
+    fn open(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .context("reading archive header")?;
+        if magic != MAGIC {
+            bail!("{} is not an archive", path.display());
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(MAGIC);
+        Ok(ArchiveReader {
+            reader,
+            hasher,
+            done: false,
+        })
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let len = self
+            .reader
+            .read_u32::<BigEndian>()
+            .context("archive is truncated")?;
+        self.hasher.update(len.to_be_bytes());
+        if len == 0 {
+            self.done = true;
+            self.check_trailer()?;
+            return Ok(None);
+        }
+
+        // The length is read from the file, so the entry is only allocated as it is read.
+        let mut bytes = Vec::new();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() != len as usize {
+            bail!("archive is truncated");
+        }
+        self.hasher.update(&bytes);
+        Ok(Some(deserialize(&bytes).context("archive is corrupted")?))
+    }
+
+    fn check_trailer(&mut self) -> Result<()> {
+        let mut checksum = [0u8; CHECKSUM_LEN];
+        self.reader
+            .read_exact(&mut checksum)
+            .context("archive is truncated")?;
+        let expected = std::mem::take(&mut self.hasher).finalize();
+        if checksum[..] != expected[..] {
+            bail!("archive checksum mismatch");
+        }
+        match self.reader.read(&mut [0u8])? {
+            0 => Ok(()),
+            _ => bail!("archive has trailing data"),
+        }
+    }
+}
+
+/// Add the entries of the archive at `path` to the stores of `target`, and flush them.
+///
+/// The archive is checked first: nothing is added if it's corrupted, or if it has entries of a
+/// kind `target` has no store for.
+pub fn import_archive(target: &ArchiveTarget, path: &Path) -> Result<ArchiveStats> {
+    let mut reader = ArchiveReader::open(path)?;
+    let mut stats = ArchiveStats::default();
+    while let Some(entry) = reader.next_entry()? {
+        stats.count(entry.kind());
+    }
+    for (kind, count, present) in [
+        (ArchiveEntryKind::File, stats.files, target.files.is_some()),
+        (ArchiveEntryKind::Tree, stats.trees, target.trees.is_some()),
+        (
+            ArchiveEntryKind::History,
+            stats.history,
+            target.history.is_some(),
+        ),
+    ] {
+        if count > 0 && !present {
+            bail!("no {} store to import {} entries into", kind, count);
+        }
+    }
+
+    let mut reader = ArchiveReader::open(path)?;
+    while let Some(entry) = reader.next_entry()? {
+        match entry {
+            Entry::File {
+                key,
+                data,
+                metadata,
+            } => add_data(target.files, key, data, metadata)?,
+            Entry::Tree {
+                key,
+                data,
+                metadata,
+            } => add_data(target.trees, key, data, metadata)?,
+            Entry::History { key, info } => {
+                if let Some(store) = target.history {
+                    store.add(&key, &info)?;
+                }
+            }
+        }
+    }
+
+    for store in [target.files, target.trees].into_iter().flatten() {
+        store.flush()?;
+    }
+    if let Some(store) = target.history {
+        store.flush()?;
+    }
+    Ok(stats)
+}
+
+fn add_data(
+    store: Option<&dyn HgIdMutableDeltaStore>,
+    key: Key,
+    data: Vec<u8>,
+    metadata: Metadata,
+) -> Result<()> {
+    if let Some(store) = store {
+        let delta = Delta {
+            data: data.into(),
+            base: None,
+            key,
+        };
+        store.add(&delta, &metadata)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use configparser::config::ConfigSet;
+    use minibytes::Bytes;
+    use tempfile::TempDir;
+    use types::testutil::*;
+
+    use super::*;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+    use crate::indexedloghistorystore::IndexedLogHgIdHistoryStore;
+    use crate::indexedlogutil::StoreType;
+    use crate::localstore::ExtStoredPolicy;
+    use crate::localstore::LocalStore;
+
+    fn data_store(dir: &Path) -> Result<IndexedLogHgIdDataStore> {
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        IndexedLogHgIdDataStore::new(dir, ExtStoredPolicy::Use, &config, StoreType::Shared)
+    }
+
+    #[test]
+    fn test_export_import() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let files = data_store(&tempdir.path().join("files"))?;
+        let history = IndexedLogHgIdHistoryStore::new(
+            tempdir.path().join("history"),
+            &ConfigSet::new(),
+            StoreType::Shared,
+        )?;
+
+        let file_key = key("a", "1");
+        let delta = Delta {
+            data: Bytes::from(&b"content"[..]),
+            base: None,
+            key: file_key.clone(),
+        };
+        files.add(&delta, &Default::default())?;
+        let info = NodeInfo {
+            parents: [key("a", "2"), null_key("a")],
+            linknode: hgid("3"),
+        };
+        history.add(&file_key, &info)?;
+
+        let path = tempdir.path().join("archive");
+        let source = ArchiveSource {
+            files: Some(&files),
+            history: Some(&history),
+            ..Default::default()
+        };
+        let keys = vec![
+            (ArchiveEntryKind::File, file_key.clone()),
+            (ArchiveEntryKind::History, file_key.clone()),
+        ];
+        let stats = export_keys(&source, &keys, &path)?;
+        assert_eq!((stats.files, stats.history), (1, 1));
+
+        // A key that isn't in the stores fails the export.
+        let missing = vec![(ArchiveEntryKind::File, key("b", "4"))];
+        assert!(export_keys(&source, &missing, &tempdir.path().join("missing")).is_err());
+
+        let other_files = data_store(&tempdir.path().join("other_files"))?;
+        let other_history = IndexedLogHgIdHistoryStore::new(
+            tempdir.path().join("other_history"),
+            &ConfigSet::new(),
+            StoreType::Shared,
+        )?;
+
+        // Without a history store, nothing is imported.
+        let target = ArchiveTarget {
+            files: Some(&other_files),
+            ..Default::default()
+        };
+        assert!(import_archive(&target, &path).is_err());
+        assert!(other_files.get_missing(&[StoreKey::from(&file_key)])?.len() == 1);
+
+        let target = ArchiveTarget {
+            files: Some(&other_files),
+            history: Some(&other_history),
+            ..Default::default()
+        };
+        assert_eq!(import_archive(&target, &path)?, stats);
+        assert_eq!(
+            other_files.get(StoreKey::from(&file_key))?,
+            StoreResult::Found(b"content".to_vec())
+        );
+        assert_eq!(other_history.get_node_info(&file_key)?, Some(info));
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_archive() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let files = data_store(&tempdir.path().join("files"))?;
+        let file_key = key("a", "1");
+        let delta = Delta {
+            data: Bytes::from(&b"content"[..]),
+            base: None,
+            key: file_key.clone(),
+        };
+        files.add(&delta, &Default::default())?;
+
+        let path = tempdir.path().join("archive");
+        let source = ArchiveSource {
+            files: Some(&files),
+            ..Default::default()
+        };
+        export_keys(&source, &[(ArchiveEntryKind::File, file_key)], &path)?;
+
+        let mut bytes = std::fs::read(&path)?;
+        let len = bytes.len();
+        // Flip a byte of the file content, just before the metadata and the end marker.
+        bytes[len - CHECKSUM_LEN - 4 - 2 - 1] ^= 0xff;
+        std::fs::write(&path, &bytes)?;
+
+        let other_files = data_store(&tempdir.path().join("other_files"))?;
+        let target = ArchiveTarget {
+            files: Some(&other_files),
+            ..Default::default()
+        };
+        let err = import_archive(&target, &path).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{:?}", err);
+
+        // A huge entry length doesn't allocate more than the file holds.
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+        bytes.extend_from_slice(b"entry");
+        std::fs::write(&path, &bytes)?;
+        let err = import_archive(&target, &path).unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{:?}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_export() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let files = data_store(&tempdir.path().join("files"))?;
+        let source = ArchiveSource {
+            files: Some(&files),
+            ..Default::default()
+        };
+        let path = tempdir.path().join("archive");
+        export_keys(&source, &[], &path)?;
+        let archive = std::fs::read(&path)?;
+
+        // Exporting a missing key fails without touching the existing archive.
+        let missing = (ArchiveEntryKind::File, key("a", "1"));
+        assert!(export_keys(&source, &[missing], &path).is_err());
+        assert_eq!(std::fs::read(&path)?, archive);
+        Ok(())
+    }
+}
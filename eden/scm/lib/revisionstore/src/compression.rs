@@ -0,0 +1,544 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Compression codecs for the content stored in datapacks and indexedlog data stores.
+//!
+//! Content has historically been compressed with lz4, in the `lz4-pyframe` format. Such content
+//! is still written and read without any header, so stores written by older versions remain
+//! readable, and stores written with the default codec remain readable by older versions.
+//!
+//! Content compressed with any other codec is prefixed with a header:
+//!
+//! ```text
+//!     header = <magic: 4 bytes> <version: 1 byte> <codec: 1 byte>
+//!              <dictionary id: 4 bytes, big-endian> <uncompressed len: 8 bytes, big-endian>
+//! ```
+//!
+//! Entries with a version other than `FORMAT_VERSION` are rejected, rather than misread, so the
+//! header can evolve without breaking older readers silently.
+//!
+//! The lz4-pyframe format starts with the uncompressed length as a 4 bytes little-endian
+//! integer, and lz4 can't compress more than `LZ4_MAX_INPUT_SIZE` bytes. The magic decodes to a
+//! larger length, so it can never be mistaken for the start of lz4 content. Since every entry
+//! identifies its codec, a store can contain a mix of codecs, and all of them are readable
+//! regardless of the configured codec.
+//!
+//! zstd can use a dictionary trained on samples of similar content, which greatly improves the
+//! compression of small entries. The dictionaries used by a store are kept alongside it in a
+//! `dictionaries` directory, and loaded when the store is opened. Entries only record the id of
+//! their dictionary, so two different dictionaries with the same id are an error.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Cursor;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::format_err;
+use anyhow::Context;
+use anyhow::Result;
+use byteorder::BigEndian;
+use byteorder::LittleEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use configparser::config::ConfigSet;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use util::file::atomic_write;
+use util::path::create_dir;
+
+use crate::sliceext::SliceExt;
+
+const MAGIC: &[u8; 4] = b"RSC\xff";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 18;
+
+/// The uncompressed length recorded in the header isn't trusted: it must not exceed the
+/// compressed length times the best ratio zstd can achieve, nor the largest entry lz4 can
+/// compress.
+const MAX_COMPRESSION_RATIO: usize = 1 << 15;
+const MAX_DECOMPRESSED_LEN: usize = 0x7E000000;
+
+/// Magic number at the start of dictionaries produced by zstd's dictionary trainer.
+const ZSTD_DICTIONARY_MAGIC: u32 = 0xEC30A437;
+
+const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionCodec {
+    Lz4,
+    Zstd,
+}
+
+impl CompressionCodec {
+    fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(CompressionCodec::Lz4),
+            2 => Ok(CompressionCodec::Zstd),
+            _ => bail!("unknown compression codec '{}'", value),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            CompressionCodec::Lz4 => 1,
+            CompressionCodec::Zstd => 2,
+        }
+    }
+}
+
+impl FromStr for CompressionCodec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lz4" => Ok(CompressionCodec::Lz4),
+            "zstd" => Ok(CompressionCodec::Zstd),
+            _ => bail!("unknown compression codec '{}'", s),
+        }
+    }
+}
+
+impl fmt::Display for CompressionCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompressionCodec::Lz4 => write!(f, "lz4"),
+            CompressionCodec::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// A zstd dictionary, as produced by `Dictionary::train` or `zstd --train`.
+pub struct Dictionary {
+    id: u32,
+    bytes: Vec<u8>,
+}
+
+impl Dictionary {
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        let mut cur = Cursor::new(&bytes);
+        if cur.read_u32::<LittleEndian>().ok() != Some(ZSTD_DICTIONARY_MAGIC) {
+            bail!("not a zstd dictionary");
+        }
+        let id = cur.read_u32::<LittleEndian>()?;
+        if id == 0 {
+            bail!("zstd dictionary has no id");
+        }
+        Ok(Dictionary { id, bytes })
+    }
+
+    /// Train a dictionary of at most `max_size` bytes on `samples` of typical content.
+    pub fn train(samples: &[impl AsRef<[u8]>], max_size: usize) -> Result<Self> {
+        let bytes = zstd::dict::from_samples(samples, max_size)?;
+        Dictionary::from_bytes(bytes)
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// The dictionaries known to this process, by id. Entries only record the id of their
+/// dictionary, so the dictionaries of all opened stores are registered here.
+static DICTIONARIES: Lazy<RwLock<HashMap<u32, Arc<Dictionary>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+fn register_dictionary(dictionary: Arc<Dictionary>) -> Result<()> {
+    let mut dictionaries = DICTIONARIES.write();
+    let registered = dictionaries
+        .entry(dictionary.id)
+        .or_insert_with(|| dictionary.clone());
+    if registered.bytes != dictionary.bytes {
+        bail!("conflicting zstd dictionaries with id '{}'", dictionary.id);
+    }
+    Ok(())
+}
+
+fn get_dictionary(id: u32) -> Result<Arc<Dictionary>> {
+    DICTIONARIES
+        .read()
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| format_err!("unknown zstd dictionary '{}'", id))
+}
+
+fn get_dictionaries_path(dir: &Path) -> PathBuf {
+    dir.join("dictionaries")
+}
+
+/// Register the dictionaries kept alongside the store in `dir`.
+pub(crate) fn load_dictionaries(dir: &Path) -> Result<()> {
+    let entries = match fs::read_dir(get_dictionaries_path(dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let bytes = fs::read(&path)?;
+        let dictionary = Dictionary::from_bytes(bytes)
+            .with_context(|| format!("invalid dictionary {}", path.display()))?;
+        register_dictionary(Arc::new(dictionary))
+            .with_context(|| format!("cannot load dictionary {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Copy the dictionaries kept alongside the store in `from` to the store in `to`, which is
+/// about to replace it.
+pub(crate) fn copy_dictionaries(from: &Path, to: &Path) -> Result<()> {
+    let entries = match fs::read_dir(get_dictionaries_path(from)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let to = get_dictionaries_path(to);
+    create_dir(&to)?;
+    for entry in entries {
+        let entry = entry?;
+        fs::copy(entry.path(), to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// How new content is compressed.
+#[derive(Clone)]
+pub struct Compression {
+    codec: CompressionCodec,
+    level: i32,
+    dictionary: Option<Arc<Dictionary>>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::lz4()
+    }
+}
+
+impl Compression {
+    /// The historical lz4 compression.
+    pub fn lz4() -> Self {
+        Compression {
+            codec: CompressionCodec::Lz4,
+            level: 0,
+            dictionary: None,
+        }
+    }
+
+    pub fn zstd(level: i32, dictionary: Option<Dictionary>) -> Result<Self> {
+        let dictionary = dictionary.map(Arc::new);
+        if let Some(dictionary) = &dictionary {
+            register_dictionary(dictionary.clone())?;
+        }
+        Ok(Compression {
+            codec: CompressionCodec::Zstd,
+            level,
+            dictionary,
+        })
+    }
+
+    /// Read the compression from the `format.compression`, `format.compression-level` and
+    /// `format.compression-dictionary` configs.
+    pub fn from_config(config: &ConfigSet) -> Result<Self> {
+        let codec = config
+            .get_opt::<String>("format", "compression")?
+            .map(|codec| codec.parse())
+            .transpose()?
+            .unwrap_or(CompressionCodec::Lz4);
+        Ok(match codec {
+            CompressionCodec::Lz4 => Compression::lz4(),
+            CompressionCodec::Zstd => {
+                let level = config.get_or("format", "compression-level", || DEFAULT_ZSTD_LEVEL)?;
+                let dictionary = config
+                    .get_opt::<PathBuf>("format", "compression-dictionary")?
+                    .map(|path| {
+                        let bytes = fs::read(&path).with_context(|| {
+                            format!("cannot read compression dictionary {}", path.display())
+                        })?;
+                        Dictionary::from_bytes(bytes)
+                    })
+                    .transpose()?;
+                Compression::zstd(level, dictionary)?
+            }
+        })
+    }
+
+    pub fn codec(&self) -> CompressionCodec {
+        self.codec
+    }
+
+    /// Prepare the store in `dir` to be written to with this compression, by keeping a copy of
+    /// the configured dictionary alongside it.
+    pub fn open_store(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        if let Some(dictionary) = &self.dictionary {
+            let path = get_dictionaries_path(dir);
+            create_dir(&path)?;
+            let path = path.join(dictionary.id.to_string());
+            if !path.exists() {
+                atomic_write(&path, |f| f.write_all(&dictionary.bytes))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self.codec {
+            CompressionCodec::Lz4 => Ok(lz4_pyframe::compress(data)?),
+            CompressionCodec::Zstd => {
+                let dictionary_id = self.dictionary.as_ref().map_or(0, |d| d.id);
+                let mut compressor = match &self.dictionary {
+                    Some(dictionary) => {
+                        zstd::bulk::Compressor::with_dictionary(self.level, &dictionary.bytes)?
+                    }
+                    None => zstd::bulk::Compressor::new(self.level)?,
+                };
+                let compressed = compressor.compress(data)?;
+
+                let mut buf = Vec::with_capacity(HEADER_LEN + compressed.len());
+                buf.extend_from_slice(MAGIC);
+                buf.write_u8(FORMAT_VERSION)?;
+                buf.write_u8(self.codec.to_u8())?;
+                buf.write_u32::<BigEndian>(dictionary_id)?;
+                buf.write_u64::<BigEndian>(data.len() as u64)?;
+                buf.extend_from_slice(&compressed);
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Whether the `compressed` content of an existing entry should be re-encoded before being
+    /// written with this compression.
+    pub(crate) fn should_recompress(&self, compressed: &[u8]) -> bool {
+        match codec_of(compressed) {
+            Ok(CompressionCodec::Lz4) => self.codec != CompressionCodec::Lz4,
+            Ok(CompressionCodec::Zstd) => {
+                self.codec != CompressionCodec::Zstd
+                    || dictionary_id(compressed) != self.dictionary.as_ref().map(|d| d.id)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+fn has_header(compressed: &[u8]) -> bool {
+    compressed.len() >= HEADER_LEN && compressed.starts_with(MAGIC)
+}
+
+fn dictionary_id(compressed: &[u8]) -> Option<u32> {
+    if !has_header(compressed) {
+        return None;
+    }
+    let id = Cursor::new(&compressed[MAGIC.len() + 2..])
+        .read_u32::<BigEndian>()
+        .ok()?;
+    if id == 0 {
+        None
+    } else {
+        Some(id)
+    }
+}
+
+/// The codec `compressed` was compressed with.
+pub fn codec_of(compressed: &[u8]) -> Result<CompressionCodec> {
+    if has_header(compressed) {
+        check_version(compressed[MAGIC.len()])?;
+        CompressionCodec::from_u8(compressed[MAGIC.len() + 1])
+    } else {
+        Ok(CompressionCodec::Lz4)
+    }
+}
+
+fn check_version(version: u8) -> Result<()> {
+    if version != FORMAT_VERSION {
+        bail!("unsupported compression format version '{}'", version);
+    }
+    Ok(())
+}
+
+/// Decompress content compressed with any codec.
+pub fn decompress(compressed: &[u8]) -> Result<Vec<u8>> {
+    if !has_header(compressed) {
+        return Ok(lz4_pyframe::decompress(compressed)?);
+    }
+
+    let mut cur = Cursor::new(compressed.get_err(MAGIC.len()..HEADER_LEN)?);
+    check_version(cur.read_u8()?)?;
+    let codec = CompressionCodec::from_u8(cur.read_u8()?)?;
+    let dictionary_id = cur.read_u32::<BigEndian>()?;
+    let len = cur.read_u64::<BigEndian>()?;
+    let data = &compressed[HEADER_LEN..];
+
+    let max_len = data
+        .len()
+        .saturating_mul(MAX_COMPRESSION_RATIO)
+        .min(MAX_DECOMPRESSED_LEN);
+    if len > max_len as u64 {
+        bail!(
+            "invalid uncompressed length {} for {} bytes of compressed content",
+            len,
+            data.len()
+        );
+    }
+    let len = len as usize;
+
+    match codec {
+        CompressionCodec::Lz4 => Ok(lz4_pyframe::decompress(data)?),
+        CompressionCodec::Zstd => {
+            let mut decompressor = if dictionary_id == 0 {
+                zstd::bulk::Decompressor::new()?
+            } else {
+                let dictionary = get_dictionary(dictionary_id)?;
+                zstd::bulk::Decompressor::with_dictionary(&dictionary.bytes)?
+            };
+            Ok(decompressor.decompress(data, len)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn samples() -> Vec<Vec<u8>> {
+        (0..1000)
+            .map(|i| {
+                format!(
+                    "# Copyright (c) Meta Platforms, Inc. and affiliates.\n\nfn function_{}() -> u64 {{\n    {}\n}}\n",
+                    i,
+                    i * 7
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lz4_has_no_header() -> Result<()> {
+        let data = b"some content";
+        let compressed = Compression::lz4().compress(data)?;
+        assert_eq!(compressed, lz4_pyframe::compress(data)?);
+        assert_eq!(codec_of(&compressed)?, CompressionCodec::Lz4);
+        assert_eq!(decompress(&compressed)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_roundtrip() -> Result<()> {
+        let data = b"some content, some content, some content";
+        let compressed = Compression::zstd(DEFAULT_ZSTD_LEVEL, None)?.compress(data)?;
+        assert_eq!(codec_of(&compressed)?, CompressionCodec::Zstd);
+        assert_eq!(decompress(&compressed)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_zstd_dictionary() -> Result<()> {
+        let samples = samples();
+        let dictionary = Dictionary::train(&samples, 4096)?;
+        let id = dictionary.id();
+        let compression = Compression::zstd(DEFAULT_ZSTD_LEVEL, Some(dictionary))?;
+
+        let data = &samples[42];
+        let with_dictionary = compression.compress(data)?;
+        let without_dictionary = Compression::zstd(DEFAULT_ZSTD_LEVEL, None)?.compress(data)?;
+        assert!(with_dictionary.len() < without_dictionary.len());
+        assert_eq!(dictionary_id(&with_dictionary), Some(id));
+        assert_eq!(&decompress(&with_dictionary)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dictionary_kept_with_store() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let dictionary = Dictionary::train(&samples(), 4096)?;
+        let id = dictionary.id();
+        let dictionary_path = tempdir.path().join("dict");
+        fs::File::create(&dictionary_path)?.write_all(dictionary.as_bytes())?;
+
+        let mut config = ConfigSet::new();
+        config.set("format", "compression", Some("zstd"), &Default::default());
+        config.set(
+            "format",
+            "compression-dictionary",
+            Some(dictionary_path.to_str().unwrap()),
+            &Default::default(),
+        );
+        let compression = Compression::from_config(&config)?;
+        assert_eq!(compression.codec(), CompressionCodec::Zstd);
+
+        let store = tempdir.path().join("store");
+        fs::create_dir(&store)?;
+        compression.open_store(&store)?;
+        assert!(get_dictionaries_path(&store).join(id.to_string()).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_untrusted_length() -> Result<()> {
+        let data = b"some content, some content, some content";
+        let mut compressed = Compression::zstd(DEFAULT_ZSTD_LEVEL, None)?.compress(data)?;
+        compressed[HEADER_LEN - 8..HEADER_LEN].copy_from_slice(&u64::MAX.to_be_bytes());
+        let err = decompress(&compressed).unwrap_err();
+        assert!(err.to_string().starts_with("invalid uncompressed length"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_version() -> Result<()> {
+        let data = b"some content";
+        let mut compressed = Compression::zstd(DEFAULT_ZSTD_LEVEL, None)?.compress(data)?;
+        compressed[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(codec_of(&compressed).is_err());
+        let err = decompress(&compressed).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "unsupported compression format version '{}'",
+                FORMAT_VERSION + 1
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflicting_dictionaries() -> Result<()> {
+        let dictionary = Dictionary::train(&samples(), 4096)?;
+        let mut bytes = dictionary.as_bytes().to_vec();
+        Compression::zstd(DEFAULT_ZSTD_LEVEL, Some(dictionary))?;
+
+        // Same id, different content.
+        *bytes.last_mut().unwrap() ^= 0xff;
+        let conflicting = Dictionary::from_bytes(bytes)?;
+        assert!(Compression::zstd(DEFAULT_ZSTD_LEVEL, Some(conflicting)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_should_recompress() -> Result<()> {
+        let data = b"some content";
+        let lz4 = Compression::lz4();
+        let zstd = Compression::zstd(DEFAULT_ZSTD_LEVEL, None)?;
+        assert!(!lz4.should_recompress(&lz4.compress(data)?));
+        assert!(zstd.should_recompress(&lz4.compress(data)?));
+        assert!(lz4.should_recompress(&zstd.compress(data)?));
+        assert!(!zstd.should_recompress(&zstd.compress(data)?));
+        Ok(())
+    }
+}
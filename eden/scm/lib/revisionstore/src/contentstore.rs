@@ -22,6 +22,8 @@ use tracing::info_span;
 use types::Key;
 use types::RepoPathBuf;
 
+use crate::cancel::CancellationToken;
+use crate::compression::Compression;
 use crate::datastore::strip_metadata;
 use crate::datastore::ContentDataStore;
 use crate::datastore::ContentMetadata;
@@ -33,8 +35,12 @@ use crate::datastore::Metadata;
 use crate::datastore::RemoteDataStore;
 use crate::datastore::ReportingRemoteDataStore;
 use crate::datastore::StoreResult;
+use crate::dualwrite::DualWrite;
+use crate::dualwrite::DualWriteStore;
+use crate::historystore::HgIdHistoryStore;
 use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
 use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+use crate::indexedloghistorystore::IndexedLogHgIdHistoryStore;
 use crate::indexedlogutil::StoreType;
 use crate::lfs::LfsFallbackRemoteStore;
 use crate::lfs::LfsMultiplexer;
@@ -44,20 +50,31 @@ use crate::localstore::ExtStoredPolicy;
 use crate::localstore::LocalStore;
 use crate::memcache::MemcacheStore;
 use crate::multiplexstore::MultiplexDeltaStore;
+use crate::negativecache::NegativeCache;
+use crate::negativecache::NegativeCacheRemoteDataStore;
 use crate::packstore::CorruptionPolicy;
+use crate::packstore::DataPackStore;
+use crate::packstore::HistoryPackStore;
 use crate::packstore::MutableDataPackStore;
+use crate::priority::FetchPriority;
+use crate::readonlystore::ReadOnlyStore;
 use crate::remotestore::HgIdRemoteStore;
 use crate::repack::RepackLocation;
 use crate::types::StoreKey;
 use crate::uniondatastore::UnionContentDataStore;
 use crate::uniondatastore::UnionHgIdDataStore;
+use crate::unionhistorystore::UnionHgIdHistoryStore;
 use crate::util::check_run_once;
-use crate::util::get_cache_packs_path;
-use crate::util::get_cache_path;
+use crate::util::get_existing_cache_packs_path;
+use crate::util::get_existing_cache_path;
 use crate::util::get_indexedlogdatastore_path;
+use crate::util::get_indexedloghistorystore_path;
 use crate::util::get_local_path;
 use crate::util::get_packs_path;
 use crate::util::RUN_ONCE_FILENAME;
+use crate::verify::recover_rewrite;
+use crate::verify::verify_datapacks;
+use crate::CacheLayout;
 
 /// A `ContentStore` aggregate all the local and remote stores and expose them as one. Both local and
 /// remote stores can be queried and accessed via the `HgIdDataStore` trait. The local store can also
@@ -65,11 +82,16 @@ use crate::util::RUN_ONCE_FILENAME;
 /// commit data.
 pub struct ContentStore {
     datastore: UnionHgIdDataStore<Arc<dyn HgIdDataStore>>,
+    /// The stores of `datastore` that don't go to the network.
+    local_and_cache_datastore: UnionHgIdDataStore<Arc<dyn HgIdDataStore>>,
     local_mutabledatastore: Option<Arc<dyn HgIdMutableDeltaStore>>,
     shared_mutabledatastore: Arc<dyn HgIdMutableDeltaStore>,
     remote_store: Option<Arc<ReportingRemoteDataStore>>,
 
     blob_stores: UnionContentDataStore<Arc<dyn ContentDataStore>>,
+
+    /// Set when local writes are mirrored between the local packs and indexedlog.
+    dualwrite: Option<Arc<DualWrite>>,
 }
 
 impl ContentStore {
@@ -81,6 +103,10 @@ impl ContentStore {
 
     /// Attempt to repair the underlying stores that the `ContentStore` is comprised of.
     ///
+    /// With `verify`, the hgid of every indexedlog and datapack entry is also recomputed from its
+    /// content, and entries that do not match are quarantined along with their history. See
+    /// [`crate::verify`]. The history stores are read, so `MetadataStore::repair` must run first.
+    ///
     /// As this may violate some of the stores asumptions, care must be taken to call this only
     /// when no other `ContentStore` have been created for the `shared_path`.
     pub fn repair(
@@ -88,12 +114,22 @@ impl ContentStore {
         local_path: Option<impl AsRef<Path>>,
         suffix: Option<impl AsRef<Path>>,
         config: &ConfigSet,
+        verify: bool,
     ) -> Result<String> {
         let mut repair_str = String::new();
+        let packs_path = |path: &Path| {
+            let mut path = path.join("packs");
+            if let Some(suffix) = suffix.as_ref() {
+                path.push(suffix);
+            }
+            path
+        };
         let mut shared_path = shared_path.as_ref().to_path_buf();
+        let shared_packs_path = packs_path(&shared_path);
         if let Some(suffix) = suffix.as_ref() {
             shared_path.push(suffix);
         }
+        let local_packs_path = local_path.as_ref().map(|p| packs_path(p.as_ref()));
         let local_path = local_path
             .map(|p| get_local_path(p.as_ref().to_path_buf(), &suffix))
             .transpose()?;
@@ -102,28 +138,74 @@ impl ContentStore {
         let max_bytes_per_log =
             config.get_opt::<ByteCount>("indexedlog", "data.max-bytes-per-log")?;
         let max_bytes = config.get_opt::<ByteCount>("remotefilelog", "cachelimit")?;
-        let config = IndexedLogHgIdDataStoreConfig {
+        let store_config = IndexedLogHgIdDataStoreConfig {
             max_log_count,
             max_bytes_per_log,
             max_bytes,
         };
 
-        repair_str += &IndexedLogHgIdDataStore::repair(
-            get_indexedlogdatastore_path(&shared_path)?,
-            &config,
-            StoreType::Shared,
-        )?;
-        if let Some(local_path) = local_path {
-            repair_str += &IndexedLogHgIdDataStore::repair(
-                get_indexedlogdatastore_path(local_path)?,
-                &config,
-                StoreType::Local,
-            )?;
+        let mut paths = vec![(shared_path.clone(), shared_packs_path, StoreType::Shared)];
+        if let (Some(local_path), Some(local_packs_path)) = (local_path, local_packs_path) {
+            paths.push((local_path, local_packs_path, StoreType::Local));
+        }
+        for (path, packs_path, store_type) in paths {
+            let data_path = get_indexedlogdatastore_path(&path)?;
+            recover_rewrite(&data_path)?;
+            repair_str +=
+                &IndexedLogHgIdDataStore::repair(data_path.clone(), &store_config, store_type)?;
+            if verify {
+                let history_path = get_indexedloghistorystore_path(&path)?;
+                let history =
+                    IndexedLogHgIdHistoryStore::open_read_only(&history_path, config, store_type);
+                let history = match history {
+                    Ok(history) => history,
+                    Err(err) => {
+                        repair_str += &format!("Cannot verify {}: {}\n", data_path.display(), err);
+                        continue;
+                    }
+                };
+                let (summary, corrupt) = IndexedLogHgIdDataStore::verify(
+                    data_path,
+                    &store_config,
+                    store_type,
+                    &history,
+                )?;
+                repair_str += &summary;
+
+                // Datapacks only have their history in historypacks, or in the indexedlog if
+                // they were migrated.
+                let mut histories: UnionHgIdHistoryStore<Box<dyn HgIdHistoryStore>> =
+                    UnionHgIdHistoryStore::new();
+                histories.add(Box::new(HistoryPackStore::new(
+                    &packs_path,
+                    CorruptionPolicy::IGNORE,
+                    None,
+                )));
+                histories.add(Box::new(history));
+                repair_str += &verify_datapacks(&packs_path, &histories)?.summary(&packs_path);
+                drop(histories);
+
+                repair_str += &IndexedLogHgIdHistoryStore::remove_hgids(
+                    history_path,
+                    config,
+                    store_type,
+                    &corrupt,
+                )?;
+            }
         }
         repair_str += &LfsStore::repair(shared_path)?;
 
         Ok(repair_str)
     }
+
+    pub(crate) fn dualwrite(&self) -> Option<&Arc<DualWrite>> {
+        self.dualwrite.as_ref()
+    }
+
+    /// The local and cache stores, to read data without ever going to the remote store.
+    pub fn local_and_cache(&self) -> &dyn HgIdDataStore {
+        &self.local_and_cache_datastore
+    }
 }
 
 impl LegacyStore for ContentStore {
@@ -198,12 +280,34 @@ impl HgIdDataStore for ContentStore {
 
 impl RemoteDataStore for ContentStore {
     fn prefetch(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.prefetch_cancellable(keys, &CancellationToken::new())
+    }
+
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        self.prefetch_with_priority(keys, cancel, FetchPriority::Interactive)
+    }
+
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
         if let Some(remote_store) = self.remote_store.as_ref() {
             let missing = self.get_missing(keys)?;
             if missing == vec![] {
                 Ok(vec![])
             } else {
-                remote_store.prefetch(&missing)
+                let result = remote_store.prefetch_with_priority(&missing, cancel, priority);
+                if cancel.is_cancelled() {
+                    // Persist what was fetched before the cancellation.
+                    self.shared_mutabledatastore.flush()?;
+                }
+                result
             }
         } else {
             // There is no remote store, let's pretend everything is fine.
@@ -218,6 +322,15 @@ impl RemoteDataStore for ContentStore {
             Ok(keys.to_vec())
         }
     }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        if let Some(remote_store) = self.remote_store.as_ref() {
+            remote_store.get_remote_missing(keys)
+        } else {
+            // Without a remote store, nothing can be verified.
+            Ok(keys.to_vec())
+        }
+    }
 }
 
 impl LocalStore for ContentStore {
@@ -225,6 +338,10 @@ impl LocalStore for ContentStore {
         let span = info_span!("Get Missing", keys = keys.len(),);
         span.in_scope(|| self.datastore.get_missing(keys))
     }
+
+    fn contains(&self, key: &StoreKey) -> Result<bool> {
+        self.datastore.contains(key)
+    }
 }
 
 impl Drop for ContentStore {
@@ -285,6 +402,7 @@ pub struct ContentStoreBuilder<'a> {
     shared_indexedlog_shared: Option<Arc<IndexedLogHgIdDataStore>>,
     shared_lfs_local: Option<Arc<LfsStore>>,
     shared_lfs_shared: Option<Arc<LfsStore>>,
+    read_only: bool,
 }
 
 impl<'a> ContentStoreBuilder<'a> {
@@ -301,6 +419,7 @@ impl<'a> ContentStoreBuilder<'a> {
             shared_indexedlog_local: None,
             shared_lfs_shared: None,
             shared_lfs_local: None,
+            read_only: false,
         }
     }
 
@@ -359,17 +478,97 @@ impl<'a> ContentStoreBuilder<'a> {
         self
     }
 
+    /// Open the existing stores without ever writing to disk: no directories, lock files or
+    /// mutable packs are created, and writes fail with `ReadOnlyStoreError`.
+    ///
+    /// Since fetched data couldn't be stored, the remote and memcache stores are not used. LFS
+    /// blobs are not available either.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    fn build_read_only(self) -> Result<ContentStore> {
+        let extstored_policy = extstored_policy(self.config)?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+
+        let mut datastore: UnionHgIdDataStore<Arc<dyn HgIdDataStore>> = UnionHgIdDataStore::new();
+        let mut add_stores = |indexedlog_path: PathBuf, packs_path: PathBuf, store_type| {
+            if indexedlog_path.exists() {
+                datastore.add(Arc::new(IndexedLogHgIdDataStore::open_read_only(
+                    indexedlog_path,
+                    extstored_policy,
+                    &config,
+                    store_type,
+                )?));
+            }
+            if packs_path.exists() {
+                datastore.add(Arc::new(DataPackStore::new(
+                    packs_path,
+                    CorruptionPolicy::IGNORE,
+                    None,
+                    extstored_policy,
+                )));
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+
+        let cache_path = get_existing_cache_path(self.config, &self.suffix)?;
+        add_stores(
+            cache_path.join("indexedlogdatastore"),
+            get_existing_cache_packs_path(self.config, &self.suffix)?,
+            StoreType::Shared,
+        )?;
+
+        if let Some(unsuffixed_local_path) = self.local_path {
+            let mut local_path = unsuffixed_local_path.clone();
+            let mut packs_path = unsuffixed_local_path.join("packs");
+            if let Some(suffix) = &self.suffix {
+                local_path.push(suffix);
+                packs_path.push(suffix);
+            }
+            add_stores(
+                local_path.join("indexedlogdatastore"),
+                packs_path,
+                StoreType::Local,
+            )?;
+        } else if !self.no_local_store {
+            return Err(format_err!(
+                "a ContentStore cannot be built without a local store"
+            ));
+        }
+
+        Ok(ContentStore {
+            local_and_cache_datastore: datastore.clone(),
+            datastore,
+            local_mutabledatastore: Some(Arc::new(ReadOnlyStore)),
+            shared_mutabledatastore: Arc::new(ReadOnlyStore),
+            remote_store: None,
+            blob_stores: UnionContentDataStore::new(),
+            dualwrite: None,
+        })
+    }
+
     pub fn build(self) -> Result<ContentStore> {
+        if self.read_only {
+            return self.build_read_only();
+        }
+
         let local_path = self
             .local_path
             .as_ref()
             .map(|p| get_local_path(p.clone(), &self.suffix))
             .transpose()?;
-        let cache_path = get_cache_path(self.config, &self.suffix)?;
+        let layout = CacheLayout::from_config(self.config, &self.suffix)?;
+        let cache_path = layout.store_path()?;
         check_cache_buster(&self.config, &cache_path);
 
         // Do this after the cache busting, since this will recreate the necessary directories.
-        let cache_packs_path = get_cache_packs_path(self.config, &self.suffix)?;
+        let cache_packs_path = layout.packs_path()?;
         let max_pending_bytes = self
             .config
             .get_or("packs", "maxdatapendingbytes", || {
@@ -387,18 +586,8 @@ impl<'a> ContentStoreBuilder<'a> {
             UnionContentDataStore::new();
 
         let enable_lfs = self.config.get_or_default::<bool>("remotefilelog", "lfs")?;
-        let extstored_policy = if enable_lfs {
-            if self
-                .config
-                .get_or_default::<bool>("remotefilelog", "useextstored")?
-            {
-                ExtStoredPolicy::Use
-            } else {
-                ExtStoredPolicy::Ignore
-            }
-        } else {
-            ExtStoredPolicy::Use
-        };
+        let extstored_policy = extstored_policy(self.config)?;
+        let compression = Compression::from_config(self.config)?;
 
         let shared_pack_store = Arc::new(MutableDataPackStore::new(
             &cache_packs_path,
@@ -425,12 +614,15 @@ impl<'a> ContentStoreBuilder<'a> {
                     max_bytes_per_log,
                     max_bytes,
                 };
-                Arc::new(IndexedLogHgIdDataStore::new(
-                    get_indexedlogdatastore_path(&cache_path)?,
-                    extstored_policy,
-                    &config,
-                    StoreType::Shared,
-                )?)
+                Arc::new(
+                    IndexedLogHgIdDataStore::new(
+                        layout.indexedlogdatastore_path()?,
+                        extstored_policy,
+                        &config,
+                        StoreType::Shared,
+                    )?
+                    .with_compression(compression.clone())?,
+                )
             };
 
         // The shared stores should precede the local one since we expect both the number of blobs,
@@ -446,7 +638,7 @@ impl<'a> ContentStoreBuilder<'a> {
         let shared_lfs_store = if let Some(shared_lfs_shared) = self.shared_lfs_shared {
             shared_lfs_shared
         } else {
-            Arc::new(LfsStore::shared(&cache_path, self.config)?)
+            Arc::new(LfsStore::shared(layout.lfs_store_path()?, self.config)?)
         };
         blob_stores.add(shared_lfs_store.clone());
 
@@ -466,6 +658,26 @@ impl<'a> ContentStoreBuilder<'a> {
             };
         datastore.add(shared_lfs_store.clone());
 
+        // While the cache is being relocated, the stores of the previous root are still read.
+        if let Some(path) = layout.previous_indexedlogdatastore_path() {
+            let config = IndexedLogHgIdDataStoreConfig {
+                max_log_count: None,
+                max_bytes_per_log: None,
+                max_bytes: None,
+            };
+            datastore.add(Arc::new(IndexedLogHgIdDataStore::open_read_only(
+                path,
+                extstored_policy,
+                &config,
+                StoreType::Shared,
+            )?));
+        }
+        if let Some(path) = layout.previous_lfs_store_path() {
+            let previous_lfs_store = Arc::new(LfsStore::open_read_only(path, self.config)?);
+            blob_stores.add(previous_lfs_store.clone());
+            datastore.add(previous_lfs_store);
+        }
+
         let shared_mutabledatastore: Arc<dyn HgIdMutableDeltaStore> = {
             if let Some(lfs_threshold) = lfs_threshold {
                 let lfs_store = Arc::new(LfsMultiplexer::new(
@@ -479,6 +691,7 @@ impl<'a> ContentStoreBuilder<'a> {
             }
         };
 
+        let mut dualwrite = None;
         let (local_mutabledatastore, local_lfs_store): (Option<Arc<dyn HgIdMutableDeltaStore>>, _) =
             if let Some(unsuffixed_local_path) = self.local_path {
                 let local_pack_store = Arc::new(MutableDataPackStore::new(
@@ -497,12 +710,15 @@ impl<'a> ContentStoreBuilder<'a> {
                             max_bytes_per_log: None,
                             max_bytes: None,
                         };
-                        Arc::new(IndexedLogHgIdDataStore::new(
-                            get_indexedlogdatastore_path(local_path.as_ref().unwrap())?,
-                            extstored_policy,
-                            &config,
-                            StoreType::Local,
-                        )?)
+                        Arc::new(
+                            IndexedLogHgIdDataStore::new(
+                                get_indexedlogdatastore_path(local_path.as_ref().unwrap())?,
+                                extstored_policy,
+                                &config,
+                                StoreType::Local,
+                            )?
+                            .with_compression(compression.clone())?,
+                        )
                     };
 
                 let primary: Arc<dyn HgIdMutableDeltaStore> =
@@ -516,8 +732,16 @@ impl<'a> ContentStoreBuilder<'a> {
                         local_indexedlogdatastore
                     } else {
                         datastore.add(local_pack_store.clone());
-                        datastore.add(local_indexedlogdatastore);
-                        local_pack_store
+                        datastore.add(local_indexedlogdatastore.clone());
+                        dualwrite = DualWrite::new(
+                            self.config,
+                            local_pack_store.clone(),
+                            local_indexedlogdatastore,
+                        )?;
+                        match &dualwrite {
+                            Some(dualwrite) => Arc::new(DualWriteStore(dualwrite.clone())),
+                            None => local_pack_store,
+                        }
                     };
 
                 let local_lfs_store = if let Some(shared_lfs_local) = self.shared_lfs_local {
@@ -550,6 +774,7 @@ impl<'a> ContentStoreBuilder<'a> {
                 (None, None)
             };
 
+        let local_and_cache_datastore = datastore.clone();
         let remote_store: Option<Arc<ReportingRemoteDataStore>> = if let Some(remotestore) =
             self.remotestore
         {
@@ -592,7 +817,16 @@ impl<'a> ContentStoreBuilder<'a> {
 
             // Second, the slower remotestore. For LFS blobs, the LFS pointers will be fetched
             // at this step and be written to the LFS store.
-            let filenode_remotestore = remotestore.datastore(shared_store.clone());
+            let mut filenode_remotestore = remotestore.datastore(shared_store.clone());
+            // Skip keys the server recently told us it doesn't have.
+            if let Some(negative_cache) =
+                NegativeCache::new(layout.negativecache_path()?, self.config)?
+            {
+                filenode_remotestore = NegativeCacheRemoteDataStore::new(
+                    filenode_remotestore,
+                    Arc::new(negative_cache),
+                );
+            }
             remotestores.add(filenode_remotestore.clone());
 
             // Third, the LFS remote store. The previously fetched LFS pointers will be used to
@@ -626,14 +860,27 @@ impl<'a> ContentStoreBuilder<'a> {
 
         Ok(ContentStore {
             datastore,
+            local_and_cache_datastore,
             local_mutabledatastore,
             shared_mutabledatastore,
             remote_store,
             blob_stores,
+            dualwrite,
         })
     }
 }
 
+fn extstored_policy(config: &ConfigSet) -> Result<ExtStoredPolicy> {
+    let enable_lfs = config.get_or_default::<bool>("remotefilelog", "lfs")?;
+    Ok(
+        if enable_lfs && !config.get_or_default::<bool>("remotefilelog", "useextstored")? {
+            ExtStoredPolicy::Ignore
+        } else {
+            ExtStoredPolicy::Use
+        },
+    )
+}
+
 /// Reads the configs and deletes the hgcache if a hgcache-purge.$KEY=$DATE value hasn't already
 /// been processed.
 pub fn check_cache_buster(config: &ConfigSet, store_path: &Path) {
@@ -688,6 +935,7 @@ mod tests {
     use util::path::create_dir;
 
     use super::*;
+    use crate::error::ReadOnlyStoreError;
     use crate::metadatastore::MetadataStore;
     use crate::repack::repack;
     use crate::repack::RepackKind;
@@ -738,6 +986,100 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_read_only() -> Result<()> {
+        let cachedir = TempDir::new()?;
+        let localdir = TempDir::new()?;
+        let config = make_config(&cachedir);
+
+        let k1 = key("a", "2");
+        let delta = Delta {
+            data: Bytes::from(&[1, 2, 3, 4][..]),
+            base: None,
+            key: k1.clone(),
+        };
+        {
+            let store = ContentStore::new(&localdir, &config)?;
+            store.add(&delta, &Default::default())?;
+            store.flush()?;
+        }
+
+        let store = ContentStoreBuilder::new(&config)
+            .local_path(&localdir)
+            .read_only()
+            .build()?;
+        let stored = store.get(StoreKey::hgid(k1))?;
+        assert_eq!(stored, StoreResult::Found(delta.data.as_ref().to_vec()));
+
+        let err = store.add(&delta, &Default::default()).unwrap_err();
+        assert!(err.is::<ReadOnlyStoreError>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_previous_cache_root() -> Result<()> {
+        let old = TempDir::new()?;
+        let new = TempDir::new()?;
+        let localdir = TempDir::new()?;
+
+        let k1 = key("a", "2");
+        let delta1 = Delta {
+            data: Bytes::from(&[1, 2, 3, 4][..]),
+            base: None,
+            key: k1.clone(),
+        };
+        let k2 = key("b", "3");
+        let delta2 = Delta {
+            data: Bytes::from(&[5, 6, 7, 8][..]),
+            base: None,
+            key: k2.clone(),
+        };
+        {
+            let old_config = make_config(&old);
+            let layout = CacheLayout::from_config(&old_config, &None::<PathBuf>)?;
+            let config = IndexedLogHgIdDataStoreConfig {
+                max_log_count: None,
+                max_bytes_per_log: None,
+                max_bytes: None,
+            };
+            let indexedlog = IndexedLogHgIdDataStore::new(
+                layout.indexedlogdatastore_path()?,
+                ExtStoredPolicy::Use,
+                &config,
+                StoreType::Shared,
+            )?;
+            indexedlog.add(&delta1, &Default::default())?;
+            indexedlog.flush()?;
+            let lfs = LfsStore::shared(layout.lfs_store_path()?, &old_config)?;
+            lfs.add(&delta2, &Default::default())?;
+            lfs.flush()?;
+        }
+        let old_history = old.path().join("test").join("indexedloghistorystore");
+        assert!(!old_history.exists());
+
+        let mut config = make_config(&new);
+        config.set(
+            "remotefilelog",
+            "previouscachepath",
+            Some(old.path().to_str().unwrap()),
+            &Default::default(),
+        );
+        let store = ContentStore::new(&localdir, &config)?;
+        assert_eq!(
+            store.get(StoreKey::hgid(k1))?,
+            StoreResult::Found(delta1.data.as_ref().to_vec())
+        );
+        assert_eq!(
+            store.get(StoreKey::hgid(k2))?,
+            StoreResult::Found(delta2.data.as_ref().to_vec())
+        );
+
+        // The previous root is only read.
+        let _history = MetadataStore::new(&localdir, &config)?;
+        assert!(!old_history.exists());
+        Ok(())
+    }
+
     #[test]
     fn test_add_dropped() -> Result<()> {
         let cachedir = TempDir::new()?;
@@ -866,6 +1208,77 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_remote_missing() -> Result<()> {
+        let cachedir = TempDir::new()?;
+        let localdir = TempDir::new()?;
+        let config = make_config(&cachedir);
+
+        let k = key("a", "1");
+        let local_only = key("b", "2");
+
+        let mut map = HashMap::new();
+        map.insert(k.clone(), (Bytes::from(&[1, 2, 3, 4][..]), None));
+
+        let mut remotestore = FakeHgIdRemoteStore::new();
+        remotestore.data(map);
+
+        let store = ContentStoreBuilder::new(&config)
+            .local_path(&localdir)
+            .remotestore(Arc::new(remotestore))
+            .build()?;
+        let keys = vec![StoreKey::hgid(k), StoreKey::hgid(local_only.clone())];
+        assert_eq!(
+            store.get_remote_missing(&keys)?,
+            vec![StoreKey::hgid(local_only)]
+        );
+
+        // Nothing is verified without a remote store.
+        let store = ContentStore::new(&localdir, &config)?;
+        assert_eq!(store.get_remote_missing(&keys)?, keys);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_and_cache() -> Result<()> {
+        let cachedir = TempDir::new()?;
+        let localdir = TempDir::new()?;
+        let config = make_config(&cachedir);
+
+        let remote = key("a", "1");
+        let local = key("b", "2");
+
+        let mut map = HashMap::new();
+        map.insert(remote.clone(), (Bytes::from(&[1, 2, 3, 4][..]), None));
+        let mut remotestore = FakeHgIdRemoteStore::new();
+        remotestore.data(map);
+
+        let store = ContentStoreBuilder::new(&config)
+            .local_path(&localdir)
+            .remotestore(Arc::new(remotestore))
+            .build()?;
+        let delta = Delta {
+            data: Bytes::from(&[5, 6][..]),
+            base: None,
+            key: local.clone(),
+        };
+        store.add(&delta, &Default::default())?;
+
+        // Data only in the remote store isn't fetched.
+        let remote = StoreKey::hgid(remote);
+        assert_eq!(
+            store.local_and_cache().get(remote.clone())?,
+            StoreResult::NotFound(remote.clone())
+        );
+        assert_eq!(
+            store.local_and_cache().get(StoreKey::hgid(local))?,
+            StoreResult::Found(vec![5, 6])
+        );
+        assert_eq!(store.get(remote)?, StoreResult::Found(vec![1, 2, 3, 4]));
+        Ok(())
+    }
+
     #[test]
     fn test_not_in_remote_store() -> Result<()> {
         let cachedir = TempDir::new()?;
@@ -94,7 +94,6 @@ use anyhow::Error;
 use anyhow::Result;
 use byteorder::BigEndian;
 use byteorder::ReadBytesExt;
-use lz4_pyframe::decompress;
 use memmap::Mmap;
 use memmap::MmapOptions;
 use minibytes::Bytes;
@@ -105,8 +104,11 @@ use types::Key;
 use types::RepoPath;
 use util::path::remove_file;
 
+use crate::compression::decompress;
 use crate::dataindex::DataIndex;
 use crate::dataindex::DeltaBaseOffset;
+use crate::datastats::DataEntryStats;
+use crate::datastats::DataStoreStats;
 use crate::datastore::Delta;
 use crate::datastore::HgIdDataStore;
 use crate::datastore::Metadata;
@@ -364,6 +366,52 @@ impl DataPack {
 
         Ok(Some(chain))
     }
+
+    /// Compute the delta chain length and sizes of every entry of the pack. See
+    /// [`DataStoreStats`]. Delta chains are only followed within the pack.
+    pub fn analyze(&self) -> Result<DataStoreStats> {
+        let mut stats = DataStoreStats::default();
+        let mut offset = 1; // Skip the header byte
+        while (offset as usize) < self.len() {
+            let entry = self.read_entry(offset)?;
+            let index_entry = match self.index.get_entry(entry.hgid())? {
+                Some(index_entry) if index_entry.pack_entry_offset() == offset => index_entry,
+                // The index points to another copy of this entry.
+                _ => {
+                    stats.dead_entries += 1;
+                    stats.dead_bytes += entry.next_offset - offset;
+                    offset = entry.next_offset;
+                    continue;
+                }
+            };
+
+            let mut chain_len = 1;
+            let mut next_entry = index_entry;
+            while let DeltaBaseOffset::Offset(base_offset) = next_entry.delta_base_offset() {
+                let base = self.index.read_entry(base_offset as usize)?;
+                if chain_len == 1
+                    && base.pack_entry_offset() + base.pack_entry_size() != entry.offset()
+                {
+                    stats.scattered_deltas += 1;
+                }
+                chain_len += 1;
+                // See `get_delta_chain`.
+                if chain_len > 1000 {
+                    return Err(format_err!("Delta chain too long"));
+                }
+                next_entry = base;
+            }
+
+            stats.entries.push(DataEntryStats {
+                key: Key::new(entry.filename().to_owned(), entry.hgid().clone()),
+                chain_len,
+                compressed_size: entry.compressed_data.len() as u64,
+                uncompressed_size: entry.delta()?.len() as u64,
+            });
+            offset = entry.next_offset;
+        }
+        Ok(stats)
+    }
 }
 
 impl HgIdDataStore for DataPack {
@@ -666,6 +714,45 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_analyze() -> Result<()> {
+        let tempdir = TempDir::new()?;
+
+        let mut revisions = vec![(
+            Delta {
+                data: Bytes::from(&[1, 2, 3, 4][..]),
+                base: None,
+                key: key("a", "1"),
+            },
+            Default::default(),
+        )];
+        for (i, name) in ["2", "3"].iter().enumerate() {
+            let base = revisions[i].0.key.clone();
+            revisions.push((
+                Delta {
+                    data: Bytes::from(&[1, 2][..]),
+                    base: Some(base),
+                    key: key("a", name),
+                },
+                Default::default(),
+            ));
+        }
+
+        let pack = make_datapack(&tempdir, &revisions);
+        let stats = pack.analyze()?;
+        let chain_lens = stats
+            .entries
+            .iter()
+            .map(|e| e.chain_len)
+            .collect::<Vec<_>>();
+        assert_eq!(chain_lens, vec![1, 2, 3]);
+        assert_eq!(stats.max_chain_len(), 3);
+        assert_eq!(stats.uncompressed_size(), 8);
+        assert_eq!(stats.dead_entries, 0);
+        assert_eq!(stats.scattered_deltas, 0);
+        Ok(())
+    }
+
     #[test]
     fn test_iter() {
         let tempdir = TempDir::new().unwrap();
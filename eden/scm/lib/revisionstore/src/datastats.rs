@@ -0,0 +1,63 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Storage statistics of the data stores, used to tune repack.
+
+use std::collections::BTreeMap;
+
+use types::Key;
+
+/// Statistics about a single entry of a data store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataEntryStats {
+    pub key: Key,
+    /// Number of deltas to apply to get the full text, including the entry itself. Full texts
+    /// have a chain of length 1.
+    pub chain_len: usize,
+    /// Size of the entry content on disk.
+    pub compressed_size: u64,
+    /// Size of the entry content once decompressed. This is the size of the delta, not of the
+    /// full text.
+    pub uncompressed_size: u64,
+}
+
+/// Statistics about all the entries of a data store, as returned by `analyze`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DataStoreStats {
+    /// Statistics of every readable entry.
+    pub entries: Vec<DataEntryStats>,
+    /// Number of entries that are stored but can no longer be read, like older copies of an entry
+    /// that was added again.
+    pub dead_entries: usize,
+    /// Bytes used by the dead entries.
+    pub dead_bytes: u64,
+    /// Number of deltas whose base isn't stored right before them. Reading them requires a seek.
+    pub scattered_deltas: usize,
+}
+
+impl DataStoreStats {
+    pub fn max_chain_len(&self) -> usize {
+        self.entries.iter().map(|e| e.chain_len).max().unwrap_or(0)
+    }
+
+    pub fn compressed_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.compressed_size).sum()
+    }
+
+    pub fn uncompressed_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.uncompressed_size).sum()
+    }
+
+    /// Number of entries for each delta chain length.
+    pub fn chain_len_histogram(&self) -> BTreeMap<usize, usize> {
+        let mut histogram = BTreeMap::new();
+        for entry in self.entries.iter() {
+            *histogram.entry(entry.chain_len).or_default() += 1;
+        }
+        histogram
+    }
+}
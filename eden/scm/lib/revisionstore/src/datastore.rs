@@ -25,8 +25,10 @@ use types::Key;
 use types::RepoPath;
 use types::RepoPathBuf;
 
+use crate::cancel::CancellationToken;
 use crate::fetch_logger::FetchLogger;
 use crate::localstore::LocalStore;
+use crate::priority::FetchPriority;
 use crate::types::ContentHash;
 use crate::types::StoreKey;
 pub use crate::Metadata;
@@ -73,8 +75,42 @@ pub trait RemoteDataStore: HgIdDataStore + Send + Sync {
     /// avoid fetching data that is already present locally.
     fn prefetch(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>>;
 
+    /// Like `prefetch`, but stop early with a `FetchCancelled` error once `cancel` is cancelled.
+    /// The data fetched until then is kept.
+    ///
+    /// Stores that can't be interrupted only check `cancel` before fetching anything.
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        cancel.check()?;
+        self.prefetch(keys)
+    }
+
+    /// Like `prefetch_cancellable`, but tells the store how urgently the data is needed. Stores
+    /// that batch their remote requests let `Interactive` fetches go ahead of `Background` ones.
+    ///
+    /// Stores without priority support ignore `priority`.
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        _priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
+        self.prefetch_cancellable(keys, cancel)
+    }
+
     /// Send all the blobs referenced by the keys to the remote store.
     fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>>;
+
+    /// Return the keys that the remote store does not confirm having, without fetching them.
+    ///
+    /// This is used before dropping local data, so stores that cannot ask the server return all
+    /// the keys: nothing is known to be on the server until it says so.
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        Ok(keys.to_vec())
+    }
 }
 
 pub trait HgIdMutableDeltaStore: HgIdDataStore + Send + Sync {
@@ -167,9 +203,30 @@ impl<T: RemoteDataStore + ?Sized, U: Deref<Target = T> + Send + Sync> RemoteData
         T::prefetch(self, keys)
     }
 
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        T::prefetch_cancellable(self, keys, cancel)
+    }
+
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
+        T::prefetch_with_priority(self, keys, cancel, priority)
+    }
+
     fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         T::upload(self, keys)
     }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        T::get_remote_missing(self, keys)
+    }
 }
 
 impl<T: HgIdMutableDeltaStore + ?Sized, U: Deref<Target = T> + Send + Sync> HgIdMutableDeltaStore
@@ -303,9 +360,32 @@ impl RemoteDataStore for ReportingRemoteDataStore {
         self.store.prefetch(keys)
     }
 
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        self.report_keys(keys);
+        self.store.prefetch_cancellable(keys, cancel)
+    }
+
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
+        self.report_keys(keys);
+        self.store.prefetch_with_priority(keys, cancel, priority)
+    }
+
     fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         self.store.upload(keys)
     }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.store.get_remote_missing(keys)
+    }
 }
 
 #[cfg(test)]
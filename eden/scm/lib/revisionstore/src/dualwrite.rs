@@ -0,0 +1,207 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Dual-write between the legacy `ContentStore` local packs and the local indexedlog used by
+//! scmstore's `FileStore`.
+//!
+//! While writers migrate from `ContentStore` to `FileStore`, enabling `scmstore.dualwrite` makes
+//! every local write through either of them also go to the other one, so that the legacy store
+//! can be decommissioned, or the migration rolled back, without losing data. Failures to write
+//! the mirrored copy are counted rather than failing the write. With `scmstore.dualwrite-verify`,
+//! the mirrored copy is also read back and compared to the written data. Both are reported as
+//! `scmstore.dualwrite.*` metrics, which should stay at zero before cutting over.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use configparser::config::ConfigSet;
+use tracing::warn;
+
+use crate::datastore::Delta;
+use crate::datastore::HgIdDataStore;
+use crate::datastore::HgIdMutableDeltaStore;
+use crate::datastore::Metadata;
+use crate::datastore::StoreResult;
+use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
+use crate::localstore::LocalStore;
+use crate::types::StoreKey;
+
+/// The pair of stores kept in sync by dual-writes.
+pub struct DualWrite {
+    legacy: Arc<dyn HgIdMutableDeltaStore>,
+    scmstore: Arc<IndexedLogHgIdDataStore>,
+    verify: bool,
+}
+
+impl DualWrite {
+    /// Returns `None` unless `scmstore.dualwrite` is set.
+    pub(crate) fn new(
+        config: &ConfigSet,
+        legacy: Arc<dyn HgIdMutableDeltaStore>,
+        scmstore: Arc<IndexedLogHgIdDataStore>,
+    ) -> Result<Option<Arc<Self>>> {
+        if !config.get_or_default::<bool>("scmstore", "dualwrite")? {
+            return Ok(None);
+        }
+        let verify = config.get_or_default::<bool>("scmstore", "dualwrite-verify")?;
+        Ok(Some(Arc::new(DualWrite {
+            legacy,
+            scmstore,
+            verify,
+        })))
+    }
+
+    /// Mirror a write made to the scmstore indexedlog into the legacy store.
+    pub(crate) fn mirror_to_legacy(&self, delta: &Delta, metadata: &Metadata) {
+        self.mirror(self.legacy.as_ref(), delta, metadata)
+    }
+
+    /// Flush the legacy store, after the scmstore indexedlog was flushed.
+    pub(crate) fn flush_legacy(&self) -> Result<()> {
+        self.legacy.flush()?;
+        Ok(())
+    }
+
+    fn mirror(&self, store: &dyn HgIdMutableDeltaStore, delta: &Delta, metadata: &Metadata) {
+        hg_metrics::increment_counter("scmstore.dualwrite.writes", 1);
+        if let Err(err) = store.add(delta, metadata) {
+            hg_metrics::increment_counter("scmstore.dualwrite.errors", 1);
+            warn!(%err, key = %delta.key, "dual-write failed");
+            return;
+        }
+        if self.verify {
+            let matches = match store.get(StoreKey::hgid(delta.key.clone())) {
+                Ok(StoreResult::Found(data)) => data == delta.data.as_ref(),
+                _ => false,
+            };
+            if !matches {
+                hg_metrics::increment_counter("scmstore.dualwrite.diverged", 1);
+                warn!(key = %delta.key, "dual-written data diverged");
+            }
+        }
+    }
+}
+
+/// The local mutable store of a dual-writing `ContentStore`. Writes go to the legacy store, and
+/// are mirrored into the scmstore indexedlog.
+pub(crate) struct DualWriteStore(pub(crate) Arc<DualWrite>);
+
+impl HgIdMutableDeltaStore for DualWriteStore {
+    fn add(&self, delta: &Delta, metadata: &Metadata) -> Result<()> {
+        self.0.legacy.add(delta, metadata)?;
+        self.0.mirror(self.0.scmstore.as_ref(), delta, metadata);
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<Option<Vec<PathBuf>>> {
+        self.0.scmstore.flush()?;
+        self.0.legacy.flush()
+    }
+}
+
+impl HgIdDataStore for DualWriteStore {
+    fn get(&self, key: StoreKey) -> Result<StoreResult<Vec<u8>>> {
+        self.0.legacy.get(key)
+    }
+
+    fn get_meta(&self, key: StoreKey) -> Result<StoreResult<Metadata>> {
+        self.0.legacy.get_meta(key)
+    }
+
+    fn refresh(&self) -> Result<()> {
+        self.0.legacy.refresh()
+    }
+}
+
+impl LocalStore for DualWriteStore {
+    fn get_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.0.legacy.get_missing(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use types::testutil::*;
+
+    use super::*;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+    use crate::indexedlogutil::StoreType;
+    use crate::localstore::ExtStoredPolicy;
+    use crate::testutil::*;
+    use crate::DataPackVersion;
+    use crate::MutableDataPack;
+
+    #[test]
+    fn test_disabled_by_default() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let legacy = Arc::new(MutableDataPack::new(&tempdir, DataPackVersion::One));
+        let scmstore = Arc::new(IndexedLogHgIdDataStore::new(
+            &tempdir,
+            ExtStoredPolicy::Use,
+            &IndexedLogHgIdDataStoreConfig {
+                max_log_count: None,
+                max_bytes_per_log: None,
+                max_bytes: None,
+            },
+            StoreType::Local,
+        )?);
+        assert!(DualWrite::new(&ConfigSet::new(), legacy, scmstore)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dual_write() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let packdir = TempDir::new()?;
+        let legacy = Arc::new(MutableDataPack::new(&packdir, DataPackVersion::One));
+        let scmstore = Arc::new(IndexedLogHgIdDataStore::new(
+            &tempdir,
+            ExtStoredPolicy::Use,
+            &IndexedLogHgIdDataStoreConfig {
+                max_log_count: None,
+                max_bytes_per_log: None,
+                max_bytes: None,
+            },
+            StoreType::Local,
+        )?);
+
+        let mut config = ConfigSet::new();
+        config.set("scmstore", "dualwrite", Some("true"), &Default::default());
+        config.set(
+            "scmstore",
+            "dualwrite-verify",
+            Some("true"),
+            &Default::default(),
+        );
+        let dualwrite = DualWrite::new(&config, legacy.clone(), scmstore.clone())?.unwrap();
+
+        // Writes through the ContentStore side reach the scmstore indexedlog.
+        let k = key("a", "1");
+        let d = delta("1234", None, k.clone());
+        DualWriteStore(dualwrite.clone()).add(&d, &Default::default())?;
+        assert_eq!(
+            scmstore.get(StoreKey::hgid(k.clone()))?,
+            StoreResult::Found(d.data.to_vec())
+        );
+        assert_eq!(
+            legacy.get(StoreKey::hgid(k))?,
+            StoreResult::Found(d.data.to_vec())
+        );
+
+        // Writes through the FileStore side reach the legacy store.
+        let k = key("b", "2");
+        let d = delta("5678", None, k.clone());
+        dualwrite.mirror_to_legacy(&d, &Default::default());
+        assert_eq!(
+            legacy.get(StoreKey::hgid(k))?,
+            StoreResult::Found(d.data.to_vec())
+        );
+        Ok(())
+    }
+}
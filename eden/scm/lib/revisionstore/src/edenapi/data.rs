@@ -10,6 +10,7 @@ use std::sync::Arc;
 use anyhow::Result;
 use async_runtime::block_on;
 use async_runtime::spawn_blocking;
+use edenapi::Stats;
 use futures::prelude::*;
 use progress_model::ProgressBar;
 use tracing::field;
@@ -19,12 +20,14 @@ use super::EdenApiRemoteStore;
 use super::EdenApiStoreKind;
 use super::File;
 use super::Tree;
+use crate::cancel::CancellationToken;
 use crate::datastore::HgIdDataStore;
 use crate::datastore::HgIdMutableDeltaStore;
 use crate::datastore::Metadata;
 use crate::datastore::RemoteDataStore;
 use crate::datastore::StoreResult;
 use crate::localstore::LocalStore;
+use crate::priority::FetchPriority;
 use crate::types::StoreKey;
 use crate::util;
 
@@ -49,8 +52,27 @@ impl<T: EdenApiStoreKind> EdenApiDataStore<T> {
 
 impl RemoteDataStore for EdenApiDataStore<File> {
     fn prefetch(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.prefetch_cancellable(keys, &CancellationToken::new())
+    }
+
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        self.prefetch_with_priority(keys, cancel, FetchPriority::Interactive)
+    }
+
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
         let client = self.remote.client.clone();
         let hgidkeys = hgid_keys(keys);
+        let lanes = self.remote.lanes();
+        let _interactive = lanes.enter(priority);
 
         let response = async move {
             let prog = ProgressBar::register_new(
@@ -59,34 +81,42 @@ impl RemoteDataStore for EdenApiDataStore<File> {
                 "files",
             );
 
-            let response = File::prefetch_files(client, hgidkeys).await?;
-            // store.add_file() may compress the data before writing it to the store. This can slow
-            // things down enough that we don't pull responses off the queue fast enough and
-            // edenapi starts queueing all the responses in memory. Let's write to the store in
-            // parallel, so we have at least a few threads doing decompression for us.
-            let mut entries = response
-                .entries
-                .map(|entry| {
-                    let store = self.store.clone();
-                    spawn_blocking(move || {
-                        entry.map(|e| {
-                            if let Ok(entry) = e.result {
-                                store.add_file(&entry)
-                            } else {
-                                Ok(())
-                            }
+            let mut stats = Stats::default();
+            for batch in hgidkeys.chunks(priority.batch_size(hgidkeys.len())) {
+                if priority == FetchPriority::Background {
+                    lanes.yield_to_interactive().await;
+                }
+
+                let response = File::prefetch_files(client.clone(), batch.to_vec()).await?;
+                // store.add_file() may compress the data before writing it to the store. This can
+                // slow things down enough that we don't pull responses off the queue fast enough
+                // and edenapi starts queueing all the responses in memory. Let's write to the
+                // store in parallel, so we have at least a few threads doing decompression for us.
+                let mut entries = response
+                    .entries
+                    .map(|entry| {
+                        let store = self.store.clone();
+                        spawn_blocking(move || {
+                            entry.map(|e| {
+                                if let Ok(entry) = e.result {
+                                    store.add_file(&entry)
+                                } else {
+                                    Ok(())
+                                }
+                            })
                         })
                     })
-                })
-                .buffer_unordered(4);
+                    .buffer_unordered(4);
 
-            while let Some(result) = entries.try_next().await? {
-                let _ = result??;
-                prog.increase_position(1);
+                while let Some(result) = entries.try_next().await? {
+                    let _ = result??;
+                    prog.increase_position(1);
+                }
+                add_stats(&mut stats, response.stats.await?);
             }
             // Explicitly force the result type here, since otherwise it can't infer the error
             // type.
-            let result: Result<_> = Ok((self.store.get_missing(keys)?, response.stats.await?));
+            let result: Result<_> = Ok((self.store.get_missing(keys)?, stats));
             result
         };
 
@@ -101,7 +131,8 @@ impl RemoteDataStore for EdenApiDataStore<File> {
             scmstore = false,
         );
         let _enter = span.enter();
-        let (keys, stats) = block_on(response)?;
+        // Dropping the response on cancellation aborts the requests in flight.
+        let (keys, stats) = block_on(cancel.run(response))?;
         util::record_edenapi_stats(&span, &stats);
         Ok(keys)
     }
@@ -110,12 +141,35 @@ impl RemoteDataStore for EdenApiDataStore<File> {
         // XXX: EdenAPI does not presently support uploads.
         Ok(keys.to_vec())
     }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.remote.lookup_missing(keys)
+    }
 }
 
 impl RemoteDataStore for EdenApiDataStore<Tree> {
     fn prefetch(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.prefetch_cancellable(keys, &CancellationToken::new())
+    }
+
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        self.prefetch_with_priority(keys, cancel, FetchPriority::Interactive)
+    }
+
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
         let client = self.remote.client.clone();
         let hgidkeys = hgid_keys(keys);
+        let lanes = self.remote.lanes();
+        let _interactive = lanes.enter(priority);
 
         let response = async move {
             let prog = ProgressBar::register_new(
@@ -124,14 +178,23 @@ impl RemoteDataStore for EdenApiDataStore<Tree> {
                 "trees",
             );
 
-            let mut response = Tree::prefetch_trees(client, hgidkeys, None).await?;
-            while let Some(Ok(entry)) = response.entries.try_next().await? {
-                self.store.add_tree(&entry)?;
-                prog.increase_position(1);
+            let mut stats = Stats::default();
+            for batch in hgidkeys.chunks(priority.batch_size(hgidkeys.len())) {
+                if priority == FetchPriority::Background {
+                    lanes.yield_to_interactive().await;
+                }
+
+                let mut response =
+                    Tree::prefetch_trees(client.clone(), batch.to_vec(), None).await?;
+                while let Some(Ok(entry)) = response.entries.try_next().await? {
+                    self.store.add_tree(&entry)?;
+                    prog.increase_position(1);
+                }
+                add_stats(&mut stats, response.stats.await?);
             }
             // Explicitly force the result type here, since otherwise it can't infer the error
             // type.
-            let result: Result<_> = Ok((self.store.get_missing(keys)?, response.stats.await?));
+            let result: Result<_> = Ok((self.store.get_missing(keys)?, stats));
             result
         };
 
@@ -146,7 +209,8 @@ impl RemoteDataStore for EdenApiDataStore<Tree> {
             scmstore = false,
         );
         let _enter = span.enter();
-        let (keys, stats) = block_on(response)?;
+        // Dropping the response on cancellation aborts the requests in flight.
+        let (keys, stats) = block_on(cancel.run(response))?;
         util::record_edenapi_stats(&span, &stats);
         Ok(keys)
     }
@@ -155,6 +219,10 @@ impl RemoteDataStore for EdenApiDataStore<Tree> {
         // XXX: EdenAPI does not presently support uploads.
         Ok(keys.to_vec())
     }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.remote.lookup_missing(keys)
+    }
 }
 
 impl HgIdDataStore for EdenApiDataStore<File> {
@@ -189,6 +257,16 @@ impl HgIdDataStore for EdenApiDataStore<Tree> {
     }
 }
 
+/// Accumulate the stats of the batches of a prefetch. The batches are sent one after the other,
+/// so their times add up.
+fn add_stats(total: &mut Stats, stats: Stats) {
+    total.downloaded += stats.downloaded;
+    total.uploaded += stats.uploaded;
+    total.requests += stats.requests;
+    total.time += stats.time;
+    total.latency = total.latency.max(stats.latency);
+}
+
 impl<T: EdenApiStoreKind> LocalStore for EdenApiDataStore<T> {
     fn get_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         Ok(keys.to_vec())
@@ -203,24 +281,44 @@ mod tests {
     use edenapi_types::ContentId;
     use edenapi_types::Sha1;
     use maplit::hashmap;
+    use minibytes::Bytes;
     use tempfile::TempDir;
     use types::testutil::*;
+    use types::Key;
     use types::Sha256;
 
     use super::*;
     use crate::edenapi::File;
     use crate::edenapi::Tree;
     use crate::indexedlogauxstore::AuxStore;
+    use crate::indexedlogdatastore::Entry;
     use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
     use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
     use crate::indexedlogutil::StoreType;
     use crate::localstore::ExtStoredPolicy;
+    use crate::scmstore::FetchCause;
+    use crate::scmstore::FetchMode;
     use crate::scmstore::FileAttributes;
     use crate::scmstore::FileAuxData;
     use crate::scmstore::FileStore;
+    use crate::scmstore::TreeAttributes;
     use crate::scmstore::TreeStore;
     use crate::testutil::*;
 
+    fn local_store(tmp: &TempDir) -> Result<Arc<IndexedLogHgIdDataStore>> {
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        Ok(Arc::new(IndexedLogHgIdDataStore::new(
+            tmp,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Local,
+        )?))
+    }
+
     #[test]
     fn test_get_file() -> Result<()> {
         // Set up mocked EdenAPI file and tree stores.
@@ -396,4 +494,128 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_file_fetch_mode() -> Result<()> {
+        let local_key = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
+        let remote_key = key("b", "1f5cfa7f1c4ec1c3bd2b5d1a1b2c1d3e4f5a6b7c");
+
+        // The remote store has other content for the local key, to tell which store was read.
+        let files = hashmap! {
+            local_key.clone() => Bytes::from_static(b"remote"),
+            remote_key.clone() => Bytes::from_static(b"remote"),
+        };
+        let client = FakeEdenApi::new().files(files).into_arc();
+
+        let tmp = TempDir::new()?;
+        let local = local_store(&tmp)?;
+        local.put_entry(Entry::new(
+            local_key.clone(),
+            Bytes::from_static(b"local"),
+            Metadata::default(),
+        ))?;
+
+        let mut store = FileStore::empty();
+        store.indexedlog_local = Some(local);
+        store.edenapi = Some(EdenApiRemoteStore::<File>::new(client));
+        store.cache_to_local_cache = false;
+
+        let fetch = |key: &Key, mode| -> Result<Option<Vec<u8>>> {
+            let fetched = store
+                .fetch_with_mode(
+                    std::iter::once(key.clone()),
+                    FileAttributes::CONTENT,
+                    FetchCause::unspecified(),
+                    CancellationToken::new(),
+                    FetchPriority::Interactive,
+                    mode,
+                )
+                .single()?;
+            Ok(match fetched {
+                Some(mut file) => Some(file.file_content()?.to_vec()),
+                None => None,
+            })
+        };
+
+        assert_eq!(
+            fetch(&local_key, FetchMode::LocalOnly)?,
+            Some(b"local".to_vec())
+        );
+        assert_eq!(fetch(&remote_key, FetchMode::LocalOnly)?, None);
+        assert_eq!(
+            fetch(&local_key, FetchMode::RemoteOnly)?,
+            Some(b"remote".to_vec())
+        );
+        assert_eq!(
+            fetch(&local_key, FetchMode::AllowRemote)?,
+            Some(b"local".to_vec())
+        );
+        assert_eq!(
+            fetch(&remote_key, FetchMode::AllowRemote)?,
+            Some(b"remote".to_vec())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_fetch_mode() -> Result<()> {
+        let local_key = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
+        let remote_key = key("b", "1f5cfa7f1c4ec1c3bd2b5d1a1b2c1d3e4f5a6b7c");
+
+        // The remote store has other content for the local key, to tell which store was read.
+        let trees = hashmap! {
+            local_key.clone() => Bytes::from_static(b"remote"),
+            remote_key.clone() => Bytes::from_static(b"remote"),
+        };
+        let client = FakeEdenApi::new().trees(trees).into_arc();
+
+        let tmp = TempDir::new()?;
+        let local = local_store(&tmp)?;
+        local.put_entry(Entry::new(
+            local_key.clone(),
+            Bytes::from_static(b"local"),
+            Metadata::default(),
+        ))?;
+
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(local);
+        store.edenapi = Some(EdenApiRemoteStore::<Tree>::new(client));
+        store.cache_to_local_cache = false;
+
+        let fetch = |key: &Key, mode| -> Result<Option<Vec<u8>>> {
+            let fetched = store
+                .fetch_batch_with_mode(
+                    std::iter::once(key.clone()),
+                    TreeAttributes::CONTENT,
+                    FetchCause::unspecified(),
+                    mode,
+                )?
+                .single()?;
+            Ok(match fetched {
+                Some(mut tree) => Some(tree.manifest_tree_entry()?.0.to_vec()),
+                None => None,
+            })
+        };
+
+        assert_eq!(
+            fetch(&local_key, FetchMode::LocalOnly)?,
+            Some(b"local".to_vec())
+        );
+        assert_eq!(fetch(&remote_key, FetchMode::LocalOnly)?, None);
+        assert_eq!(
+            fetch(&local_key, FetchMode::RemoteOnly)?,
+            Some(b"remote".to_vec())
+        );
+        assert_eq!(
+            fetch(&local_key, FetchMode::AllowRemote)?,
+            Some(b"local".to_vec())
+        );
+        assert_eq!(
+            fetch(&remote_key, FetchMode::AllowRemote)?,
+            Some(b"remote".to_vec())
+        );
+
+        Ok(())
+    }
 }
@@ -5,25 +5,35 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
 use std::marker::PhantomData;
 use std::sync::Arc;
 
+use anyhow::Result;
+use async_runtime::block_on;
 use async_trait::async_trait;
 use edenapi::BlockingResponse;
 use edenapi::EdenApi;
 use edenapi::EdenApiError;
+use edenapi::Entries;
 use edenapi::Response;
+use edenapi_types::AnyId;
 use edenapi_types::EdenApiServerError;
 use edenapi_types::FileResponse;
 use edenapi_types::FileSpec;
+use edenapi_types::LookupResult;
 use edenapi_types::TreeAttributes;
 use edenapi_types::TreeEntry;
+use futures::prelude::*;
+use types::HgId;
 use types::Key;
 
 use crate::datastore::HgIdMutableDeltaStore;
 use crate::datastore::RemoteDataStore;
 use crate::historystore::HgIdMutableHistoryStore;
 use crate::historystore::RemoteHistoryStore;
+use crate::priority::PriorityLanes;
+use crate::priority::BACKGROUND_BATCH_SIZE;
 use crate::remotestore::HgIdRemoteStore;
 use crate::types::StoreKey;
 
@@ -48,6 +58,7 @@ pub type EdenApiTreeStore = EdenApiRemoteStore<Tree>;
 #[derive(Clone)]
 pub struct EdenApiRemoteStore<T> {
     client: Arc<dyn EdenApi>,
+    lanes: Arc<PriorityLanes>,
     _phantom: PhantomData<T>,
 }
 
@@ -69,9 +80,46 @@ impl<T: EdenApiStoreKind> EdenApiRemoteStore<T> {
     pub fn new(client: Arc<dyn EdenApi>) -> Arc<Self> {
         Arc::new(Self {
             client,
+            lanes: Arc::new(PriorityLanes::new()),
             _phantom: PhantomData,
         })
     }
+
+    /// The interactive fetches in flight on this store, shared by all the data stores created
+    /// from it.
+    pub(crate) fn lanes(&self) -> &Arc<PriorityLanes> {
+        &self.lanes
+    }
+
+    /// Ask the server which of the `keys` it has. Content keys cannot be looked up, so they are
+    /// always returned.
+    pub(crate) fn lookup_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        let ids = hgid_keys(keys)
+            .into_iter()
+            .map(|key| T::lookup_id(key.hgid))
+            .collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(keys.to_vec());
+        }
+
+        let responses = block_on(self.client.lookup_batch(ids, None, None))?;
+        let present = responses
+            .into_iter()
+            .filter_map(|response| match response.result {
+                LookupResult::Present(token) => Some(token.data.id),
+                LookupResult::NotPresent(_) => None,
+            })
+            .collect::<HashSet<_>>();
+
+        Ok(keys
+            .iter()
+            .filter(|key| match key {
+                StoreKey::HgId(key) => !present.contains(&T::lookup_id(key.hgid)),
+                StoreKey::Content(..) => true,
+            })
+            .cloned()
+            .collect())
+    }
 }
 
 impl HgIdRemoteStore for EdenApiRemoteStore<File> {
@@ -133,6 +181,31 @@ impl EdenApiFileStore {
     ) -> Result<Response<FileResponse>, EdenApiError> {
         self.client.files_attrs(reqs).await
     }
+
+    /// Like `files_attrs`, but send `reqs` in batches, and wait for the interactive fetches in
+    /// flight on this store to complete before each batch.
+    pub(crate) fn files_attrs_background(&self, reqs: Vec<FileSpec>) -> Entries<FileResponse> {
+        let client = self.client.clone();
+        let lanes = self.lanes.clone();
+        let batches: Vec<Vec<FileSpec>> = reqs
+            .chunks(BACKGROUND_BATCH_SIZE)
+            .map(|batch| batch.to_vec())
+            .collect();
+        stream::iter(batches)
+            .then(move |batch| {
+                let client = client.clone();
+                let lanes = lanes.clone();
+                async move {
+                    lanes.yield_to_interactive().await;
+                    client
+                        .files_attrs(batch)
+                        .await
+                        .map(|response| response.entries)
+                }
+            })
+            .try_flatten()
+            .boxed()
+    }
 }
 
 impl EdenApiTreeStore {
@@ -149,6 +222,9 @@ impl EdenApiTreeStore {
 /// methods on an EdenAPI client.
 #[async_trait]
 pub trait EdenApiStoreKind: Send + Sync + 'static {
+    /// Identifier of `hgid` in `lookup_batch` requests.
+    fn lookup_id(hgid: HgId) -> AnyId;
+
     async fn prefetch_files(
         _client: Arc<dyn EdenApi>,
         _keys: Vec<Key>,
@@ -167,6 +243,10 @@ pub trait EdenApiStoreKind: Send + Sync + 'static {
 
 #[async_trait]
 impl EdenApiStoreKind for File {
+    fn lookup_id(hgid: HgId) -> AnyId {
+        AnyId::HgFilenodeId(hgid)
+    }
+
     async fn prefetch_files(
         client: Arc<dyn EdenApi>,
         keys: Vec<Key>,
@@ -177,6 +257,10 @@ impl EdenApiStoreKind for File {
 
 #[async_trait]
 impl EdenApiStoreKind for Tree {
+    fn lookup_id(hgid: HgId) -> AnyId {
+        AnyId::HgTreeId(hgid)
+    }
+
     async fn prefetch_trees(
         client: Arc<dyn EdenApi>,
         keys: Vec<Key>,
@@ -9,17 +9,42 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Error;
+use error_code::ErrorCategory;
+use error_code::ErrorCode;
 use http::header::HeaderMap;
 use http::status::StatusCode;
 use http_client::HttpClientError;
 use http_client::Method;
 use thiserror::Error;
+use types::errors::NetworkError;
 use url::Url;
 
+use crate::scmstore::KeyFetchError;
+
 #[derive(Debug, Error)]
 #[error("Empty Mutable Pack")]
 pub struct EmptyMutablePack;
 
+#[derive(Debug, Error)]
+#[error("Cannot write to a store opened read-only")]
+pub struct ReadOnlyStoreError;
+
+#[derive(Debug, Error)]
+#[error("Fetch was cancelled")]
+pub struct FetchCancelled;
+
+/// Marks an error caused by a missing or invalid LFS pointer. The original error is kept as the
+/// source so that both remain visible in the chain.
+#[derive(Debug, Error)]
+#[error("LFS pointer is missing or invalid")]
+pub struct LfsPointerMissing(#[source] pub Error);
+
+impl LfsPointerMissing {
+    pub fn wrap(err: impl Into<Error>) -> Error {
+        Self(err.into()).into()
+    }
+}
+
 #[derive(Error, Debug)]
 #[error("Fetch failed: {} {}", .url, .method)]
 pub struct FetchError {
@@ -97,10 +122,141 @@ pub enum TransferError {
     InvalidResponse(Error),
 }
 
+/// The stable error code of a revisionstore error, for error handling outside Rust.
+///
+/// Codes attached with `WithErrorCode` take precedence. Otherwise, the code is derived from the
+/// errors of this crate found in the chain.
+pub fn error_code(err: &Error) -> Option<ErrorCode> {
+    if let Some(code) = error_code::error_code(err) {
+        return Some(code);
+    }
+
+    let code = error_code::chain(err).find_map(|e| {
+        if e.is::<FetchCancelled>() {
+            Some(ErrorCode::FetchCancelled)
+        } else if e.is::<ReadOnlyStoreError>() {
+            Some(ErrorCode::ReadOnlyStore)
+        } else if e.is::<LfsPointerMissing>() {
+            Some(ErrorCode::LfsPointerMissing)
+        } else if e.is::<FetchError>() || e.is::<NetworkError>() {
+            Some(ErrorCode::RemoteFetchFailed)
+        } else if let Some(e) = e.downcast_ref::<indexedlog::Error>() {
+            e.is_corruption().then_some(ErrorCode::StoreCorrupted)
+        } else {
+            None
+        }
+    });
+
+    // Keys that failed without a more specific error are reported as missing.
+    code.or_else(|| {
+        error_code::chain(err)
+            .any(|e| {
+                matches!(
+                    e.downcast_ref::<KeyFetchError>(),
+                    Some(KeyFetchError::KeyedError { .. })
+                )
+            })
+            .then_some(ErrorCode::KeyNotFound)
+    })
+}
+
+/// Coarse kind of the error of a key that failed to fetch, for callers choosing between
+/// retrying and reporting the failure.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FetchErrorKind {
+    Network,
+    NotFound,
+    Corrupt,
+    LfsPointerMissing,
+    Other,
+}
+
+impl FetchErrorKind {
+    pub fn of(err: &Error) -> Self {
+        match error_code(err) {
+            Some(ErrorCode::LfsPointerMissing) => FetchErrorKind::LfsPointerMissing,
+            Some(code) => match code.category() {
+                ErrorCategory::Network => FetchErrorKind::Network,
+                ErrorCategory::NotFound => FetchErrorKind::NotFound,
+                ErrorCategory::Corruption => FetchErrorKind::Corrupt,
+                _ => FetchErrorKind::Other,
+            },
+            None => FetchErrorKind::Other,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FetchErrorKind::Network => "network",
+            FetchErrorKind::NotFound => "not-found",
+            FetchErrorKind::Corrupt => "corrupt",
+            FetchErrorKind::LfsPointerMissing => "lfs-pointer-missing",
+            FetchErrorKind::Other => "other",
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_error_code() {
+        use error_code::WithErrorCode;
+
+        let err: Error = ReadOnlyStoreError.into();
+        assert_eq!(error_code(&err), Some(ErrorCode::ReadOnlyStore));
+
+        let err = LfsPointerMissing::wrap(anyhow::anyhow!("no oid stored in pointer"));
+        assert_eq!(error_code(&err), Some(ErrorCode::LfsPointerMissing));
+        assert!(format!("{:#}", err).contains("no oid stored in pointer"));
+
+        // The marker is found when nested in a keyed error, as returned by the file store.
+        let err: Error = KeyFetchError::KeyedError {
+            key: Default::default(),
+            errors: vec![LfsPointerMissing::wrap(anyhow::anyhow!("bad pointer"))],
+        }
+        .into();
+        assert_eq!(error_code(&err), Some(ErrorCode::LfsPointerMissing));
+
+        let err = LfsPointerMissing::wrap(anyhow::anyhow!("bad pointer")).context("fetching file");
+        assert_eq!(error_code(&err), Some(ErrorCode::LfsPointerMissing));
+
+        let err: Error = KeyFetchError::KeyedError {
+            key: Default::default(),
+            errors: vec![FetchCancelled.into()],
+        }
+        .into();
+        assert_eq!(error_code(&err), Some(ErrorCode::FetchCancelled));
+
+        let err: Error = KeyFetchError::KeyedError {
+            key: Default::default(),
+            errors: vec![],
+        }
+        .into();
+        assert_eq!(error_code(&err), Some(ErrorCode::KeyNotFound));
+
+        let err = Err::<(), _>(ReadOnlyStoreError)
+            .with_error_code(ErrorCode::LfsTransferFailed)
+            .unwrap_err();
+        assert_eq!(error_code(&err), Some(ErrorCode::LfsTransferFailed));
+        assert!(error_code::chain(&err).any(|e| e.is::<ReadOnlyStoreError>()));
+
+        assert_eq!(error_code(&anyhow::anyhow!("plain")), None);
+    }
+
+    #[test]
+    fn test_fetch_error_kind() {
+        let err = LfsPointerMissing::wrap(anyhow::anyhow!("bad pointer"));
+        assert_eq!(FetchErrorKind::of(&err), FetchErrorKind::LfsPointerMissing);
+
+        let err: Error = NetworkError::wrap(anyhow::anyhow!("timeout"));
+        assert_eq!(FetchErrorKind::of(&err), FetchErrorKind::Network);
+
+        let err: Error = ReadOnlyStoreError.into();
+        assert_eq!(FetchErrorKind::of(&err), FetchErrorKind::Other);
+    }
+
     #[test]
     fn test_clonable_source() {
         let clonable: anyhow::Error = ClonableError::new(EmptyMutablePack {}.into()).into();
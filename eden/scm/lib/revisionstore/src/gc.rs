@@ -0,0 +1,621 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Size- and age-based garbage collection for the shared indexedlog stores.
+//!
+//! Shared stores are `RotateLog`s, which already drop their oldest generation
+//! when they rotate. [`gc`] drops rotated-out generations early, oldest first,
+//! until the cache fits in `max_bytes` and no generation was last written more
+//! than `max_age` ago. Before a generation is dropped, the entries read since
+//! it was last written, and the entries pinned with [`PinStore`], are copied to
+//! the latest generation, so the cache is trimmed in least recently used order
+//! at the granularity of a generation. The latest generation is never dropped.
+//!
+//! Generations are removed the same way rotation removes them, so readers which
+//! already mapped them keep working, and generations which can't be removed yet
+//! (i.e. on Windows) are removed by a later rotation.
+//!
+//! Shared stores record when each of their entries was last read in an
+//! access-time index that lives next to the `RotateLog` generations, and is a
+//! `RotateLog` itself so that it doesn't grow forever.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use indexedlog::lock::DirLockOptions;
+use indexedlog::lock::ScopedDirLock;
+use indexedlog::log;
+use indexedlog::log::IndexDef;
+use indexedlog::log::IndexOutput;
+use indexedlog::rotate;
+use indexedlog::rotate::RotateLog;
+use parking_lot::Mutex;
+use tracing::debug;
+use tracing::warn;
+
+use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
+use crate::indexedloghistorystore::IndexedLogHgIdHistoryStore;
+use crate::indexedlogutil::StoreOpenOptions;
+use crate::pinstore::PinStore;
+
+const ACCESS_INDEX_DIR: &str = "accesslog";
+const TIMESTAMP_LEN: usize = 8;
+
+/// Number of keys remembered by `AccessIndex::touch` to avoid recording the
+/// same key over and over again.
+const RECENTLY_TOUCHED_SLOTS: usize = 1 << 14;
+
+/// Held while collecting a store, so concurrent GCs don't copy the same
+/// entries twice. Independent of the `RotateLog` lock, which is still taken
+/// to write to the store.
+const GC_LOCK_OPTS: DirLockOptions = DirLockOptions {
+    exclusive: true,
+    non_blocking: false,
+    file_name: "gclock",
+};
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Records the last time entries of a shared store were read.
+///
+/// Each record is the store key followed by a big-endian timestamp in
+/// seconds. Recently recorded keys are remembered in a small fixed-size table,
+/// without locking, to avoid turning every read into a write.
+pub(crate) struct AccessIndex {
+    log: Mutex<RotateLog>,
+    recently_touched: Box<[AtomicU64]>,
+}
+
+impl AccessIndex {
+    /// Open the access-time index of the shared store at `store_path`.
+    pub(crate) fn open(store_path: impl AsRef<Path>) -> Result<Self> {
+        let log = Self::open_options().open(store_path.as_ref().join(ACCESS_INDEX_DIR))?;
+        Ok(AccessIndex {
+            log: Mutex::new(log),
+            recently_touched: (0..RECENTLY_TOUCHED_SLOTS)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        })
+    }
+
+    fn open_options() -> rotate::OpenOptions {
+        // Old records are rotated out. Entries whose records are gone are
+        // treated as never read.
+        rotate::OpenOptions::new()
+            .create(true)
+            .max_log_count(3)
+            .max_bytes_per_log(16 * 1024 * 1024)
+            .auto_sync_threshold(Some(1024 * 1024))
+            .index_defs(vec![IndexDef::new("key", |data| {
+                vec![IndexOutput::Reference(
+                    0..data.len().saturating_sub(TIMESTAMP_LEN) as u64,
+                )]
+            })])
+    }
+
+    /// Record that `key` was just read. Errors are logged and ignored since
+    /// the access-time index is only a GC hint.
+    pub(crate) fn touch(&self, key: &[u8]) {
+        // Keys start with a node, which is already a good hash.
+        let mut prefix = [0u8; 8];
+        let len = key.len().min(prefix.len());
+        prefix[..len].copy_from_slice(&key[..len]);
+        let hash = u64::from_le_bytes(prefix) | 1;
+        let slot = &self.recently_touched[hash as usize % RECENTLY_TOUCHED_SLOTS];
+        if slot.swap(hash, Ordering::Relaxed) == hash {
+            return;
+        }
+        if let Err(err) = Self::append(&mut self.log.lock(), key, now()) {
+            warn!(%err, "cannot record access time");
+        }
+    }
+
+    fn append(log: &mut RotateLog, key: &[u8], timestamp: u64) -> Result<()> {
+        let mut buf = Vec::with_capacity(key.len() + TIMESTAMP_LEN);
+        buf.extend_from_slice(key);
+        buf.extend_from_slice(&timestamp.to_be_bytes());
+        Ok(log.append(buf)?)
+    }
+
+    /// Last recorded time `key` was read, if any.
+    fn last_access(&self, key: &[u8]) -> Result<Option<u64>> {
+        let log = self.log.lock();
+        let mut iter = log.lookup(0, key.to_vec())?;
+        match iter.next() {
+            Some(buf) => {
+                let buf = buf?;
+                let mut timestamp = [0u8; TIMESTAMP_LEN];
+                timestamp.copy_from_slice(&buf[buf.len() - TIMESTAMP_LEN..]);
+                Ok(Some(u64::from_be_bytes(timestamp)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn flush(&self) -> Result<()> {
+        self.log.lock().sync()?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GcOptions {
+    /// Maximum number of bytes the stores may use after GC.
+    pub max_bytes: Option<u64>,
+    /// Entries not read for longer than this are evicted.
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Entries of the dropped generations.
+    pub scanned: usize,
+    pub evicted: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+struct GcStore {
+    /// Directory of the `RotateLog`.
+    path: PathBuf,
+    /// Directory of the store owning the access-time index. Differs from `path`
+    /// for the shards of a store.
+    access_path: PathBuf,
+    open_options: fn() -> Result<StoreOpenOptions>,
+    key_len: usize,
+    /// Keys of the entries that are never evicted.
+    pinned: HashSet<Vec<u8>>,
+}
+
+/// A generation of a shared store, i.e. one of the `Log`s of its `RotateLog`.
+struct Generation {
+    store: usize,
+    id: u8,
+    /// Distance from the latest generation.
+    rank: u8,
+    bytes: u64,
+    /// Last time the generation was written to, in seconds since the epoch.
+    written: u64,
+}
+
+/// The nodes of the files pinned in the cache at `cache_path`. The pin-set is compacted first,
+/// unless it is in use.
+fn pinned_nodes(cache_path: &Path) -> Result<HashSet<Vec<u8>>> {
+    if let Err(err) = PinStore::compact(cache_path) {
+        debug!(%err, "not compacting the pin-set");
+    }
+    let pins = match PinStore::open_existing(cache_path)? {
+        Some(pins) => pins,
+        None => return Ok(HashSet::new()),
+    };
+    Ok(pins
+        .pinned()
+        .into_iter()
+        .map(|key| key.hgid.as_ref().to_vec())
+        .collect())
+}
+
+/// The `shard-<n>` subdirectories of the data store at `path`.
+fn shard_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut shards = Vec::new();
+    match fs::read_dir(path) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().starts_with("shard-") {
+                    shards.push(entry.path());
+                }
+            }
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    shards.sort();
+    Ok(shards)
+}
+
+fn gc_stores(cache_path: &Path) -> Result<Vec<GcStore>> {
+    let mut stores = Vec::new();
+    // Only files are pinned.
+    let pinned = [
+        (PathBuf::new(), pinned_nodes(cache_path)?),
+        (PathBuf::from("manifests"), HashSet::new()),
+    ];
+    for (prefix, pinned) in pinned {
+        let path = cache_path.join(&prefix);
+        let data_path = path.join("indexedlogdatastore");
+        for log_path in std::iter::once(data_path.clone()).chain(shard_paths(&data_path)?) {
+            stores.push(GcStore {
+                path: log_path,
+                access_path: data_path.clone(),
+                open_options: || Ok(IndexedLogHgIdDataStore::gc_open_options()),
+                key_len: IndexedLogHgIdDataStore::GC_KEY_LEN,
+                pinned: pinned.clone(),
+            });
+        }
+        let history_path = path.join("indexedloghistorystore");
+        stores.push(GcStore {
+            path: history_path.clone(),
+            access_path: history_path,
+            open_options: IndexedLogHgIdHistoryStore::gc_open_options,
+            key_len: IndexedLogHgIdHistoryStore::GC_KEY_LEN,
+            pinned: HashSet::new(),
+        });
+    }
+    Ok(stores
+        .into_iter()
+        .filter(|store| store.path.join("latest").exists())
+        .collect())
+}
+
+/// The generations of the `RotateLog` at `path`, from the latest to the oldest.
+fn generations(store: usize, path: &Path) -> Result<Vec<Generation>> {
+    let latest: u8 = fs::read_to_string(path.join("latest"))?.trim().parse()?;
+    let mut generations: Vec<Generation> = Vec::new();
+    for rank in 0..=u8::MAX {
+        let id = latest.wrapping_sub(rank);
+        let log_path = path.join(id.to_string());
+        if !log_path.is_dir() {
+            break;
+        }
+        let mut bytes = 0;
+        for entry in fs::read_dir(&log_path)? {
+            bytes += entry?.metadata()?.len();
+        }
+        let written = fs::metadata(log_path.join("log"))
+            .or_else(|_| fs::metadata(&log_path))?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        // Older generations are dropped first, so make sure they never look
+        // more recently written than newer ones.
+        let written = generations
+            .last()
+            .map_or(written, |g| g.written.min(written));
+        generations.push(Generation {
+            store,
+            id,
+            rank,
+            bytes,
+            written,
+        });
+    }
+    Ok(generations)
+}
+
+/// Garbage collect the shared indexedlog stores of the cache at `cache_path`.
+///
+/// Generations last written more than `max_age` ago are dropped, then the
+/// oldest generations are dropped until the stores use at most `max_bytes`.
+/// Entries read since their generation was last written (and within
+/// `max_age`), and pinned entries, are kept by copying them to the latest
+/// generation, so the stores may still use more than `max_bytes` afterwards.
+pub fn gc(cache_path: impl AsRef<Path>, options: &GcOptions) -> Result<GcStats> {
+    let stores = gc_stores(cache_path.as_ref())?;
+    let _locks = stores
+        .iter()
+        .map(|store| ScopedDirLock::new_with_options(&store.path, &GC_LOCK_OPTS))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut stats = GcStats::default();
+    let mut candidates = Vec::new();
+    for (index, store) in stores.iter().enumerate() {
+        let generations = generations(index, &store.path)?;
+        stats.bytes_before += generations.iter().map(|g| g.bytes).sum::<u64>();
+        // The latest generation is still written to.
+        candidates.extend(generations.into_iter().skip(1));
+    }
+    // Oldest first. Within a store, generations are then dropped from the
+    // oldest to the newest.
+    candidates.sort_by(|a, b| a.written.cmp(&b.written).then(b.rank.cmp(&a.rank)));
+
+    let min_access = options
+        .max_age
+        .map(|age| now().saturating_sub(age.as_secs()));
+    let max_bytes = options.max_bytes.unwrap_or(u64::MAX);
+    stats.bytes_after = stats.bytes_before;
+    let mut access_indexes: Vec<(PathBuf, AccessIndex)> = Vec::new();
+    for generation in candidates {
+        let expired = min_access.map_or(false, |min_access| generation.written <= min_access);
+        if !expired && stats.bytes_after <= max_bytes {
+            // Candidates are sorted by age, so the remaining ones are kept too.
+            break;
+        }
+        let store = &stores[generation.store];
+        let position = access_indexes
+            .iter()
+            .position(|(path, _)| path == &store.access_path);
+        let access = match position {
+            Some(index) => &access_indexes[index].1,
+            None => {
+                let access = AccessIndex::open(&store.access_path)?;
+                access_indexes.push((store.access_path.clone(), access));
+                &access_indexes.last().unwrap().1
+            }
+        };
+        let (scanned, kept, kept_bytes) = drop_generation(store, access, &generation, min_access)?;
+        debug!(path = ?store.path, id = generation.id, scanned, kept, "gc generation");
+        stats.scanned += scanned;
+        stats.evicted += scanned - kept;
+        stats.bytes_after = stats.bytes_after - generation.bytes + kept_bytes;
+    }
+
+    Ok(stats)
+}
+
+/// Drop `generation` from `store`, after copying its pinned entries, and its
+/// entries read since it was last written, to the latest generation. Returns
+/// the number of entries in the generation, and the number and size of the
+/// entries that were kept.
+fn drop_generation(
+    store: &GcStore,
+    access: &AccessIndex,
+    generation: &Generation,
+    min_access: Option<u64>,
+) -> Result<(usize, usize, u64)> {
+    let open_options = (store.open_options)()?.into_shared_open_options();
+    let mut rotate_log = open_options.clone().open(&store.path)?;
+    let min_access = min_access.unwrap_or(0).max(generation.written);
+
+    let mut scanned = 0;
+    let mut kept = HashSet::new();
+    let mut kept_bytes = 0;
+    {
+        let log = log::OpenOptions::new().open(store.path.join(generation.id.to_string()))?;
+        for buf in log.iter() {
+            let buf = buf?;
+            scanned += 1;
+            if buf.len() < store.key_len {
+                continue;
+            }
+            let key = &buf[..store.key_len];
+            let keep = store.pinned.contains(key)
+                || access
+                    .last_access(key)?
+                    .map_or(false, |last_access| last_access >= min_access);
+            if keep && kept.insert(key.to_vec()) {
+                rotate_log.append(buf)?;
+                kept_bytes += buf.len() as u64;
+            }
+        }
+    }
+    // Syncing may rotate, so the distance to the latest generation is only
+    // known afterwards.
+    let latest = rotate_log.sync()?;
+    drop(rotate_log);
+
+    // Like rotation, only keep the generations newer than the dropped one.
+    let newer = latest.wrapping_sub(generation.id);
+    if newer >= 1 && newer <= generation.rank.saturating_add(1) {
+        let mut rotate_log = open_options.max_log_count(newer).open(&store.path)?;
+        rotate_log.remove_old_logs()?;
+    }
+
+    Ok((scanned, kept.len(), kept_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use configparser::convert::ByteCount;
+    use minibytes::Bytes;
+    use tempfile::TempDir;
+    use types::testutil::*;
+
+    use super::*;
+    use crate::datastore::Delta;
+    use crate::datastore::HgIdDataStore;
+    use crate::datastore::HgIdMutableDeltaStore;
+    use crate::datastore::Metadata;
+    use crate::datastore::StoreResult;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+    use crate::indexedlogutil::StoreType;
+    use crate::localstore::ExtStoredPolicy;
+    use crate::types::StoreKey;
+
+    fn config() -> IndexedLogHgIdDataStoreConfig {
+        // Rotate on every flush.
+        IndexedLogHgIdDataStoreConfig {
+            max_log_count: Some(10),
+            max_bytes_per_log: Some(ByteCount::from(1)),
+            max_bytes: None,
+        }
+    }
+
+    fn open_store(path: &Path) -> Result<IndexedLogHgIdDataStore> {
+        IndexedLogHgIdDataStore::new(
+            path.join("indexedlogdatastore"),
+            ExtStoredPolicy::Use,
+            &config(),
+            StoreType::Shared,
+        )
+    }
+
+    fn add(store: &IndexedLogHgIdDataStore, name: &str) -> Result<Delta> {
+        let delta = Delta {
+            data: Bytes::from(vec![b'x'; 100]),
+            base: None,
+            key: key(name, "1"),
+        };
+        store.add(&delta, &Metadata::default())?;
+        Ok(delta)
+    }
+
+    fn record_access(path: &Path, delta: &Delta, timestamp: u64) -> Result<()> {
+        let path = path.join("indexedlogdatastore").join(ACCESS_INDEX_DIR);
+        let mut log = AccessIndex::open_options().open(path)?;
+        AccessIndex::append(&mut log, delta.key.hgid.as_ref(), timestamp)?;
+        log.sync()?;
+        Ok(())
+    }
+
+    fn is_found(store: &IndexedLogHgIdDataStore, delta: &Delta) -> Result<bool> {
+        Ok(matches!(
+            store.get(StoreKey::hgid(delta.key.clone()))?,
+            StoreResult::Found(_)
+        ))
+    }
+
+    #[test]
+    fn test_gc_noop() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = open_store(tempdir.path())?;
+        add(&store, "a")?;
+        add(&store, "b")?;
+        store.flush()?;
+        drop(store);
+
+        let stats = gc(tempdir.path(), &GcOptions::default())?;
+        assert_eq!(stats.scanned, 0);
+        assert_eq!(stats.evicted, 0);
+        assert_eq!(stats.bytes_before, stats.bytes_after);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_max_bytes_drops_oldest() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = open_store(tempdir.path())?;
+        let old = add(&store, "old")?;
+        store.flush()?;
+        let new = add(&store, "new")?;
+        store.flush()?;
+        drop(store);
+
+        let before = gc(tempdir.path(), &GcOptions::default())?;
+        let options = GcOptions {
+            max_bytes: Some(before.bytes_before - 1),
+            max_age: None,
+        };
+        let stats = gc(tempdir.path(), &options)?;
+        assert_eq!(stats.scanned, 1);
+        assert_eq!(stats.evicted, 1);
+        assert!(stats.bytes_after <= before.bytes_before - 1);
+
+        let store = open_store(tempdir.path())?;
+        assert!(is_found(&store, &new)?);
+        assert!(!is_found(&store, &old)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_keeps_recently_read() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = open_store(tempdir.path())?;
+        let old = add(&store, "old")?;
+        store.flush()?;
+        let new = add(&store, "new")?;
+        store.flush()?;
+        drop(store);
+
+        // "old" was read after it was written, "new" was never read.
+        record_access(tempdir.path(), &old, now())?;
+
+        let options = GcOptions {
+            max_bytes: Some(0),
+            max_age: None,
+        };
+        let stats = gc(tempdir.path(), &options)?;
+        assert_eq!(stats.scanned, 2);
+        assert_eq!(stats.evicted, 1);
+
+        let store = open_store(tempdir.path())?;
+        assert!(is_found(&store, &old)?);
+        assert!(!is_found(&store, &new)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_max_age() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = open_store(tempdir.path())?;
+        let old = add(&store, "old")?;
+        store.flush()?;
+        drop(store);
+
+        record_access(tempdir.path(), &old, 1)?;
+
+        let options = GcOptions {
+            max_bytes: None,
+            max_age: Some(Duration::from_secs(0)),
+        };
+        let stats = gc(tempdir.path(), &options)?;
+        assert_eq!(stats.evicted, 1);
+        assert!(stats.bytes_after < stats.bytes_before);
+
+        let store = open_store(tempdir.path())?;
+        assert!(!is_found(&store, &old)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_keeps_pinned() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = open_store(tempdir.path())?;
+        let pinned = add(&store, "pinned")?;
+        let other = add(&store, "other")?;
+        store.flush()?;
+        drop(store);
+
+        let pins = PinStore::new(tempdir.path())?;
+        pins.pin(&[pinned.key.clone()])?;
+        pins.flush()?;
+
+        let options = GcOptions {
+            max_bytes: Some(0),
+            max_age: None,
+        };
+        let stats = gc(tempdir.path(), &options)?;
+        assert_eq!(stats.evicted, 1);
+
+        let store = open_store(tempdir.path())?;
+        assert!(is_found(&store, &pinned)?);
+        assert!(!is_found(&store, &other)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_keeps_shards() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = open_store(tempdir.path())?.with_shards(2, &config())?;
+        let kept = add(&store, "kept")?;
+        add(&store, "a")?;
+        add(&store, "b")?;
+        store.flush()?;
+        drop(store);
+
+        record_access(tempdir.path(), &kept, now())?;
+
+        let options = GcOptions {
+            max_bytes: Some(0),
+            max_age: None,
+        };
+        let stats = gc(tempdir.path(), &options)?;
+        assert_eq!(stats.scanned, 3);
+        assert_eq!(stats.evicted, 2);
+
+        let path = tempdir.path().join("indexedlogdatastore");
+        assert!(path.join("shard-0").join("latest").exists());
+        assert!(path.join("shard-1").join("latest").exists());
+
+        let store = open_store(tempdir.path())?.with_shards(2, &config())?;
+        assert!(is_found(&store, &kept)?);
+        Ok(())
+    }
+}
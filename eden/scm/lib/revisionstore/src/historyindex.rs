@@ -357,6 +357,13 @@ impl HistoryIndex {
             .map(Some)
     }
 
+    /// All the file entries, in the order of the index (ie. sorted by the hash of the file
+    /// names).
+    pub fn file_entries(&self) -> impl Iterator<Item = Result<FileIndexEntry>> + '_ {
+        let count = (self.index_end - self.index_start) / FILE_ENTRY_LEN;
+        (0..count).map(move |i| self.read_file_entry(i * FILE_ENTRY_LEN))
+    }
+
     fn read_file_entry(&self, offset: usize) -> Result<FileIndexEntry> {
         FileIndexEntry::read(self.read_data(offset, FILE_ENTRY_LEN)?)
     }
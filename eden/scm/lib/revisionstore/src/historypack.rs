@@ -115,6 +115,7 @@ use crate::repack::Repackable;
 use crate::repack::ToKeys;
 use crate::sliceext::SliceExt;
 use crate::types::StoreKey;
+use crate::util::path_has_prefix;
 
 #[derive(Debug, Error)]
 #[error("Historypack Error: {0:?}")]
@@ -381,6 +382,33 @@ impl ToKeys for HistoryPack {
     fn to_keys(&self) -> Vec<Result<Key>> {
         HistoryPackIterator::new(self).collect()
     }
+
+    /// The file sections are located with the index, and only the name of the file is read for
+    /// the sections that don't match.
+    fn iter_prefix(&self, prefix: &RepoPath) -> Vec<Result<Key>> {
+        let mut keys = vec![];
+        for file_entry in self.index.file_entries() {
+            let file_entry = match file_entry {
+                Ok(file_entry) => file_entry,
+                Err(e) => {
+                    keys.push(Err(e));
+                    break;
+                }
+            };
+            let start = file_entry.file_section_offset;
+            match self.read_file_section_header(start) {
+                Ok(header) if !path_has_prefix(header.file_name, prefix) => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    keys.push(Err(e));
+                    continue;
+                }
+            }
+            let end = start + file_entry.file_section_size;
+            keys.extend(HistoryPackIterator::with_range(self, start, end));
+        }
+        keys
+    }
 }
 
 impl Repackable for HistoryPack {
@@ -408,15 +436,23 @@ impl Repackable for HistoryPack {
 struct HistoryPackIterator<'a> {
     pack: &'a HistoryPack,
     offset: u64,
+    end: u64,
     current_name: RepoPathBuf,
     current_remaining: u32,
 }
 
 impl<'a> HistoryPackIterator<'a> {
     pub fn new(pack: &'a HistoryPack) -> Self {
+        // Start after the header byte
+        HistoryPackIterator::with_range(pack, 1, pack.len() as u64)
+    }
+
+    /// Iterate over the file sections between `start` and `end`.
+    fn with_range(pack: &'a HistoryPack, start: u64, end: u64) -> Self {
         HistoryPackIterator {
             pack,
-            offset: 1, // Start after the header byte
+            offset: start,
+            end: end.min(pack.len() as u64),
             current_name: RepoPathBuf::new(),
             current_remaining: 0,
         }
@@ -427,7 +463,7 @@ impl<'a> Iterator for HistoryPackIterator<'a> {
     type Item = Result<Key>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.current_remaining == 0 && (self.offset as usize) < self.pack.len() {
+        while self.current_remaining == 0 && self.offset < self.end {
             let file_header = self.pack.read_file_section_header(self.offset);
             match file_header {
                 Ok(header) => {
@@ -437,13 +473,13 @@ impl<'a> Iterator for HistoryPackIterator<'a> {
                     self.offset += 4 + 2 + file_name_slice.len() as u64;
                 }
                 Err(e) => {
-                    self.offset = self.pack.len() as u64;
+                    self.offset = self.end;
                     return Some(Err(e));
                 }
             };
         }
 
-        if self.offset as usize >= self.pack.len() {
+        if self.offset >= self.end {
             return None;
         }
 
@@ -461,7 +497,7 @@ impl<'a> Iterator for HistoryPackIterator<'a> {
             Err(e) => {
                 // The entry is corrupted, and we have no way to know where the next one is
                 // located, let's forcibly stop the iteration.
-                self.offset = self.pack.len() as u64;
+                self.offset = self.end;
                 Err(e)
             }
         })
@@ -595,6 +631,33 @@ pub mod tests {
         assert_eq!(iter_keys, keys,);
     }
 
+    #[test]
+    fn test_iter_prefix() {
+        let mut rng = ChaChaRng::from_seed([0u8; 32]);
+        let tempdir = TempDir::new().unwrap();
+
+        let nodes = get_nodes(&mut rng);
+        let pack = make_historypack(&tempdir, &nodes);
+
+        let iter_prefix = |prefix: &str| {
+            let mut keys = pack
+                .iter_prefix(RepoPath::from_str(prefix).unwrap())
+                .into_iter()
+                .collect::<Result<Vec<Key>>>()
+                .unwrap();
+            keys.sort_unstable();
+            keys
+        };
+        let mut keys: Vec<Key> = nodes.keys().cloned().collect();
+        keys.sort_unstable();
+
+        assert_eq!(iter_prefix(""), keys);
+        assert_eq!(iter_prefix("path"), keys);
+        keys.retain(|k| k.path.as_str() == "path/file");
+        assert_eq!(iter_prefix("path/file"), keys);
+        assert_eq!(iter_prefix("pa"), Vec::<Key>::new());
+    }
+
     #[test]
     fn test_open_v0() {
         let mut rng = ChaChaRng::from_seed([0u8; 32]);
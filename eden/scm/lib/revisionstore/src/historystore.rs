@@ -10,6 +10,7 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use edenapi_types::HistoryEntry;
+use types::HgId;
 use types::Key;
 use types::NodeInfo;
 
@@ -19,6 +20,23 @@ use crate::types::StoreKey;
 pub trait HgIdHistoryStore: LocalStore + Send + Sync {
     fn get_node_info(&self, key: &Key) -> Result<Option<NodeInfo>>;
     fn refresh(&self) -> Result<()>;
+
+    /// Look up the `NodeInfo` of several keys at once. The result is in the same order as `keys`,
+    /// with `None` for the keys that weren't found.
+    fn get_node_info_batch(&self, keys: &[Key]) -> Result<Vec<Option<NodeInfo>>> {
+        keys.iter().map(|key| self.get_node_info(key)).collect()
+    }
+
+    /// Look up only the linknodes of several keys at once. The result is in the same order as
+    /// `keys`, with `None` for the keys that weren't found. Stores may implement this without
+    /// decoding the parents and copy information.
+    fn get_linknode_batch(&self, keys: &[Key]) -> Result<Vec<Option<HgId>>> {
+        Ok(self
+            .get_node_info_batch(keys)?
+            .into_iter()
+            .map(|info| info.map(|info| info.linknode))
+            .collect())
+    }
 }
 
 pub trait HgIdMutableHistoryStore: HgIdHistoryStore + Send + Sync {
@@ -51,6 +69,14 @@ impl<T: HgIdHistoryStore + ?Sized, U: Deref<Target = T> + Send + Sync> HgIdHisto
     fn refresh(&self) -> Result<()> {
         T::refresh(self)
     }
+
+    fn get_node_info_batch(&self, keys: &[Key]) -> Result<Vec<Option<NodeInfo>>> {
+        T::get_node_info_batch(self, keys)
+    }
+
+    fn get_linknode_batch(&self, keys: &[Key]) -> Result<Vec<Option<HgId>>> {
+        T::get_linknode_batch(self, keys)
+    }
 }
 
 impl<T: HgIdMutableHistoryStore + ?Sized, U: Deref<Target = T> + Send + Sync>
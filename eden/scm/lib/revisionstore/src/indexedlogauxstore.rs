@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 
+use std::fs;
 use std::io::Cursor;
 use std::io::Read;
 use std::io::Write;
@@ -23,8 +24,11 @@ use edenapi_types::Sha256;
 use indexedlog::log::IndexOutput;
 use minibytes::Bytes;
 use parking_lot::RwLock;
+use sha2::Digest;
+use sha2::Sha256 as Sha256Hasher;
 use types::hgid::ReadHgIdExt;
 use types::HgId;
+use util::file::atomic_write;
 use vlqencoding::VLQDecode;
 use vlqencoding::VLQEncode;
 
@@ -32,6 +36,9 @@ use crate::indexedlogutil::Store;
 use crate::indexedlogutil::StoreOpenOptions;
 use crate::indexedlogutil::StoreType;
 
+const SIDECAR_MAGIC: &[u8] = b"HGAUXSIDECAR\x00\x01";
+const SIDECAR_CHECKSUM_LEN: usize = 32;
+
 /// See edenapi_types::FileAuxData and mononoke_types::ContentMetadata
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub struct Entry {
@@ -80,19 +87,26 @@ impl Entry {
     /// - total_size <u64 VLQ, 1-9 bytes>
     fn serialize(&self, hgid: HgId) -> Result<Bytes> {
         let mut buf = Vec::new();
+        self.write_to(hgid, &mut buf)?;
+        Ok(buf.into())
+    }
+
+    fn write_to(&self, hgid: HgId, buf: &mut Vec<u8>) -> Result<()> {
         buf.write_all(hgid.as_ref())?;
         buf.write_u8(0)?; // write version
         buf.write_all(self.content_id.as_ref())?;
         buf.write_all(self.content_sha1.as_ref())?;
         buf.write_all(self.content_sha256.as_ref())?;
         buf.write_vlq(self.total_size)?;
-        Ok(buf.into())
+        Ok(())
     }
 
     fn deserialize(bytes: Bytes) -> Result<(HgId, Self)> {
         let data: &[u8] = bytes.as_ref();
-        let mut cur = Cursor::new(data);
+        Self::read_from(&mut Cursor::new(data))
+    }
 
+    fn read_from(cur: &mut Cursor<&[u8]>) -> Result<(HgId, Self)> {
         let hgid = cur.read_hgid()?;
 
         let version = cur.read_u8()?;
@@ -138,6 +152,16 @@ impl AuxStore {
         Ok(AuxStore(RwLock::new(log)))
     }
 
+    /// Open an existing `AuxStore` without writing to disk.
+    pub fn open_read_only(
+        path: impl AsRef<Path>,
+        config: &ConfigSet,
+        store_type: StoreType,
+    ) -> Result<Self> {
+        let log = AuxStore::open_options(config)?.read_only(&path, store_type)?;
+        Ok(AuxStore(RwLock::new(log)))
+    }
+
     fn open_options(config: &ConfigSet) -> Result<StoreOpenOptions> {
         let mut open_options = StoreOpenOptions::new()
             .max_log_count(4)
@@ -188,6 +212,55 @@ impl AuxStore {
         self.0.write().flush()
     }
 
+    /// Write the entries of `hgids` to a sidecar file at `path`, to seed the aux data of another
+    /// machine with `import_sidecar`. The hgids without aux data are skipped. Returns the number
+    /// of entries written.
+    ///
+    /// The file is the magic header, the entries in their indexedlog format, and the SHA-256 of
+    /// everything before it.
+    pub fn export_sidecar(&self, hgids: &[HgId], path: &Path) -> Result<usize> {
+        let mut buf = SIDECAR_MAGIC.to_vec();
+        let mut count = 0;
+        for hgid in hgids {
+            if let Some(entry) = self.get(*hgid)? {
+                entry.write_to(*hgid, &mut buf)?;
+                count += 1;
+            }
+        }
+        let checksum = Sha256Hasher::digest(&buf);
+        buf.extend_from_slice(&checksum);
+        atomic_write(path, |f| f.write_all(&buf))?;
+        Ok(count)
+    }
+
+    /// Add the entries of a sidecar file written by `export_sidecar` to the store. The whole
+    /// file is checked first, nothing is added if it's corrupted. Returns the number of entries
+    /// added.
+    pub fn import_sidecar(&self, path: &Path) -> Result<usize> {
+        let data = fs::read(path)?;
+        if data.len() < SIDECAR_MAGIC.len() + SIDECAR_CHECKSUM_LEN
+            || !data.starts_with(SIDECAR_MAGIC)
+        {
+            bail!("{} is not an aux data sidecar file", path.display());
+        }
+        let (content, checksum) = data.split_at(data.len() - SIDECAR_CHECKSUM_LEN);
+        if Sha256Hasher::digest(content)[..] != checksum[..] {
+            bail!("aux data sidecar checksum mismatch");
+        }
+
+        let mut cur = Cursor::new(&content[SIDECAR_MAGIC.len()..]);
+        let mut entries = Vec::new();
+        while (cur.position() as usize) < cur.get_ref().len() {
+            entries.push(Entry::read_from(&mut cur)?);
+        }
+
+        for (hgid, entry) in entries.iter() {
+            self.put(*hgid, entry)?;
+        }
+        self.flush()?;
+        Ok(entries.len())
+    }
+
     #[cfg(test)]
     pub(crate) fn hgids(&self) -> Result<Vec<HgId>> {
         let log = self.0.read();
@@ -307,6 +380,49 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sidecar() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = AuxStore::new(
+            tempdir.path().join("a"),
+            &ConfigSet::new(),
+            StoreType::Shared,
+        )?;
+
+        let mut entry = Entry::default();
+        entry.total_size = 1000;
+        entry.content_sha1 = single_byte_sha1(1);
+        let k1 = key("a", "1");
+        let k2 = key("b", "2");
+        store.put(k1.hgid, &entry)?;
+
+        let path = tempdir.path().join("sidecar");
+        // Keys without aux data are skipped.
+        assert_eq!(store.export_sidecar(&[k1.hgid, k2.hgid], &path)?, 1);
+
+        let other = AuxStore::new(
+            tempdir.path().join("b"),
+            &ConfigSet::new(),
+            StoreType::Shared,
+        )?;
+        assert_eq!(other.import_sidecar(&path)?, 1);
+        assert_eq!(other.get(k1.hgid)?, Some(entry));
+        assert_eq!(other.get(k2.hgid)?, None);
+
+        // A corrupted file is rejected.
+        let mut data = fs::read(&path)?;
+        data[SIDECAR_MAGIC.len() + 1] ^= 0xff;
+        fs::write(&path, &data)?;
+        let third = AuxStore::new(
+            tempdir.path().join("c"),
+            &ConfigSet::new(),
+            StoreType::Shared,
+        )?;
+        assert!(third.import_sidecar(&path).is_err());
+        assert_eq!(third.get(k1.hgid)?, None);
+        Ok(())
+    }
+
     #[test]
     fn test_scmstore_read() -> Result<()> {
         let tmp = TempDir::new()?;
@@ -334,6 +450,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_scmstore_similarity_from_aux() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let aux = Arc::new(AuxStore::new(&tmp, &ConfigSet::new(), StoreType::Shared)?);
+
+        let entry = |total_size, sha256: &str| {
+            let mut entry = Entry::default();
+            entry.total_size = total_size;
+            entry.content_sha256 = Sha256::from_str(&sha256.repeat(64)).unwrap();
+            entry
+        };
+        let (small, large, same) = (key("a", "1"), key("b", "2"), key("c", "3"));
+        aux.put(small.hgid, &entry(10, "1"))?;
+        aux.put(large.hgid, &entry(1000, "2"))?;
+        aux.put(same.hgid, &entry(10, "1"))?;
+        aux.flush()?;
+
+        // The store has no contents: the scores must come from the aux data alone.
+        let mut store = FileStore::empty();
+        store.aux_local = Some(aux.clone());
+
+        let pairs = [(small.clone(), large), (small.clone(), same)];
+        assert_eq!(store.similarity_batch(&pairs, 0.5)?, vec![0.0, 1.0]);
+
+        // Pairs which need the contents fail to fetch them.
+        assert!(store.similarity_batch(&pairs, 0.0).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_scmstore_compute_read() -> Result<()> {
         let k = key("a", "def6f29d7b61f9cb70b2f14f79cd5c43c38e21b2");
@@ -5,6 +5,9 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
+use std::fs;
+use std::io;
 use std::io::Cursor;
 use std::io::Write;
 use std::path::Path;
@@ -19,9 +22,8 @@ use byteorder::WriteBytesExt;
 use configparser::convert::ByteCount;
 use edenapi_types::FileEntry;
 use edenapi_types::TreeEntry;
+use indexedlog::lock::ScopedDirLock;
 use indexedlog::log::IndexOutput;
-use lz4_pyframe::compress;
-use lz4_pyframe::decompress;
 use minibytes::Bytes;
 use parking_lot::RwLock;
 use tracing::warn;
@@ -29,12 +31,20 @@ use types::hgid::ReadHgIdExt;
 use types::HgId;
 use types::Key;
 use types::RepoPath;
+use util::file::atomic_write;
 
+use crate::compression::decompress;
+use crate::compression::load_dictionaries;
+use crate::compression::Compression;
+use crate::datastats::DataEntryStats;
+use crate::datastats::DataStoreStats;
 use crate::datastore::Delta;
 use crate::datastore::HgIdDataStore;
 use crate::datastore::HgIdMutableDeltaStore;
 use crate::datastore::Metadata;
 use crate::datastore::StoreResult;
+use crate::gc::AccessIndex;
+use crate::historystore::HgIdHistoryStore;
 use crate::indexedlogutil::Store;
 use crate::indexedlogutil::StoreOpenOptions;
 use crate::indexedlogutil::StoreType;
@@ -44,6 +54,60 @@ use crate::missing::MissingInjection;
 use crate::repack::ToKeys;
 use crate::sliceext::SliceExt;
 use crate::types::StoreKey;
+use crate::verify::hgid_matches;
+use crate::verify::remove_hgids;
+use crate::verify::verify_store;
+use crate::verify::Verdict;
+use crate::verify::VerifyStats;
+
+/// Records how a store is sharded, as "v<SHARDS_VERSION> <count>". Once written, the number of
+/// shards of a store never changes, since the shard of an entry depends on it.
+const SHARDS_FILE: &str = "shards";
+
+/// Version of the sharded layout: entries are written to `shard-<n>`, where `n` is the FNV-1a
+/// hash of their hgid modulo the number of shards. Stores recorded with another version are
+/// refused instead of being read with the wrong layout.
+const SHARDS_VERSION: u32 = 1;
+
+/// The number of shards recorded for the store at `path`, if it is sharded.
+fn read_shard_count(path: &Path) -> Result<Option<usize>> {
+    let content = match fs::read_to_string(path.join(SHARDS_FILE)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let version = format!("v{}", SHARDS_VERSION);
+    let mut words = content.split_whitespace();
+    match (words.next(), words.next().map(str::parse::<usize>)) {
+        (Some(v), Some(Ok(count))) if v == version => Ok(Some(count)),
+        _ => bail!(
+            "{} uses an unsupported sharding format: {:?}",
+            path.display(),
+            content.trim()
+        ),
+    }
+}
+
+/// Record that the store at `path` has `count` shards, unless another number of shards was
+/// recorded first. Return the recorded number of shards.
+fn record_shard_count(path: &Path, count: usize) -> Result<usize> {
+    let _lock = ScopedDirLock::new(path)?;
+    if let Some(existing) = read_shard_count(path)? {
+        return Ok(existing);
+    }
+    atomic_write(&path.join(SHARDS_FILE), |f| {
+        writeln!(f, "v{} {}", SHARDS_VERSION, count)
+    })?;
+    Ok(count)
+}
+
+/// The `shard-<n>` directories of the store at `path`.
+fn shard_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    let count = read_shard_count(path)?.unwrap_or(0);
+    Ok((0..count)
+        .map(|shard| path.join(format!("shard-{}", shard)))
+        .collect())
+}
 
 pub struct IndexedLogHgIdDataStoreConfig {
     pub max_log_count: Option<u8>,
@@ -52,9 +116,16 @@ pub struct IndexedLogHgIdDataStoreConfig {
 }
 
 pub struct IndexedLogHgIdDataStore {
+    path: PathBuf,
     store: RwLock<Store>,
+    /// When not empty, new entries are written to one of these logs, picked by the hash of
+    /// their hgid. `store` is then only read, to find the entries written before the store was
+    /// sharded. See `with_shards`.
+    shards: Vec<RwLock<Store>>,
     extstored_policy: ExtStoredPolicy,
+    compression: Compression,
     missing: MissingInjection,
+    access: Option<AccessIndex>,
 }
 
 #[derive(Clone, Debug)]
@@ -95,7 +166,7 @@ impl Entry {
     /// - Path: <Path len> bytes
     /// - Metadata: metadata-list
     /// - Content len: 8 unsigned bytes, big-endian
-    /// - Content: <Content len> bytes, lz4 compressed
+    /// - Content: <Content len> bytes, compressed as described in [`crate::compression`]
     ///
     /// The metadata-list is a list of Metadata, encode with:
     /// - Flag: 1 byte,
@@ -144,7 +215,9 @@ impl Entry {
     }
 
     /// Write an entry to the IndexedLog. See [`from_log`] for the detail about the on-disk format.
-    pub fn write_to_log(self, log: &RwLock<Store>) -> Result<()> {
+    ///
+    /// Content that was compressed differently than `compression` is re-encoded.
+    pub fn write_to_log(self, log: &RwLock<Store>, compression: &Compression) -> Result<()> {
         let mut buf = Vec::new();
         buf.write_all(self.key.hgid.as_ref())?;
         let path_slice = self.key.path.as_byte_slice();
@@ -152,14 +225,9 @@ impl Entry {
         buf.write_all(path_slice)?;
         self.metadata.write(&mut buf)?;
 
-        let compressed = if let Some(compressed) = self.compressed_content {
-            compressed
-        } else {
-            if let Some(raw) = self.content {
-                compress(&raw)?.into()
-            } else {
-                bail!("No content");
-            }
+        let compressed = match &self.compressed_content {
+            Some(compressed) if !compression.should_recompress(compressed) => compressed.clone(),
+            _ => compression.compress(&self.content_inner()?)?.into(),
         };
 
         buf.write_u64::<BigEndian>(compressed.len() as u64)?;
@@ -191,6 +259,15 @@ impl Entry {
         &self.metadata
     }
 
+    /// Size of the content as stored, i.e. compressed if the entry was read from a log.
+    pub(crate) fn stored_len(&self) -> usize {
+        match (&self.compressed_content, &self.content) {
+            (Some(compressed), _) => compressed.len(),
+            (None, Some(content)) => content.len(),
+            (None, None) => 0,
+        }
+    }
+
     pub fn key(&self) -> &Key {
         &self.key
     }
@@ -215,16 +292,193 @@ impl IndexedLogHgIdDataStore {
         store_type: StoreType,
     ) -> Result<Self> {
         let open_options = IndexedLogHgIdDataStore::open_options(config);
+        load_dictionaries(path.as_ref())?;
 
         let log = match store_type {
             StoreType::Local => open_options.local(&path),
             StoreType::Shared => open_options.shared(&path),
         }?;
 
+        // Only the shared cache is garbage collected.
+        let access = match store_type {
+            StoreType::Local => None,
+            StoreType::Shared => match AccessIndex::open(&path) {
+                Ok(access) => Some(access),
+                Err(err) => {
+                    warn!(%err, "cannot open access-time index");
+                    None
+                }
+            },
+        };
+
+        let mut store = IndexedLogHgIdDataStore {
+            path: path.as_ref().to_path_buf(),
+            store: RwLock::new(log),
+            shards: Vec::new(),
+            extstored_policy,
+            compression: Compression::default(),
+            missing: MissingInjection::new_from_env("MISSING_FILES"),
+            access,
+        };
+        if let Some(count) = read_shard_count(path.as_ref())? {
+            store.open_shards(count, config, store_type)?;
+        }
+        Ok(store)
+    }
+
+    /// Compress new entries with `compression` instead of lz4.
+    pub fn with_compression(mut self, compression: Compression) -> Result<Self> {
+        compression.open_store(&self.path)?;
+        self.compression = compression;
+        Ok(self)
+    }
+
+    /// Split new entries across `count` logs, stored in the `shard-<n>` subdirectories. Each shard
+    /// has its own lock and is rotated independently, with `config` limits divided between the
+    /// shards.
+    ///
+    /// Entries are assigned to a shard by the hash of their hgid. Entries written before the store
+    /// was sharded are still read from the unsharded log. The number of shards is recorded in the
+    /// store the first time it is sharded, and is used from then on, whatever `count` is.
+    pub fn with_shards(
+        mut self,
+        count: usize,
+        config: &IndexedLogHgIdDataStoreConfig,
+    ) -> Result<Self> {
+        if !self.shards.is_empty() {
+            if count != self.shards.len() {
+                warn!(
+                    path = %self.path.display(),
+                    configured = count,
+                    recorded = self.shards.len(),
+                    "the number of shards of a store cannot change"
+                );
+            }
+            return Ok(self);
+        }
+        if count <= 1 {
+            return Ok(self);
+        }
+        let count = record_shard_count(&self.path, count)?;
+        let store_type = if self.store.read().is_local() {
+            StoreType::Local
+        } else {
+            StoreType::Shared
+        };
+        self.open_shards(count, config, store_type)?;
+        Ok(self)
+    }
+
+    fn open_shards(
+        &mut self,
+        count: usize,
+        config: &IndexedLogHgIdDataStoreConfig,
+        store_type: StoreType,
+    ) -> Result<()> {
+        let divide = |bytes: ByteCount| ByteCount::from((bytes.value() / count as u64).max(1));
+        let shard_config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: config.max_log_count,
+            max_bytes_per_log: config.max_bytes_per_log.map(divide),
+            max_bytes: config.max_bytes.map(divide),
+        };
+        self.shards = (0..count)
+            .map(|shard| {
+                let path = self.path.join(format!("shard-{}", shard));
+                Ok(RwLock::new(Self::open_store(
+                    &path,
+                    &shard_config,
+                    store_type,
+                )?))
+            })
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    /// The log new entries for `key` are written to.
+    fn log_for(&self, key: &Key) -> &RwLock<Store> {
+        if self.shards.is_empty() {
+            return &self.store;
+        }
+        // FNV-1a: the shard of a key must not change between versions, see `SHARDS_VERSION`. Only
+        // the hgid is hashed, since entries can be looked up with another path.
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in key.hgid.as_ref() {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    /// All the logs of the store: the unsharded log, then the shards.
+    fn logs(&self) -> impl Iterator<Item = &RwLock<Store>> {
+        std::iter::once(&self.store).chain(self.shards.iter())
+    }
+
+    /// Like `lookup`, but only reads the index.
+    fn lookup_index(&self, key: &Key) -> Result<bool> {
+        let in_log = |log: &RwLock<Store>| -> Result<bool> {
+            match log.read().lookup(0, key.hgid.as_ref().to_vec())?.next() {
+                None => Ok(false),
+                Some(slice) => slice.map(|_| true).map_err(Into::into),
+            }
+        };
+        let log = self.log_for(key);
+        if in_log(log)? {
+            return Ok(true);
+        }
+        if !std::ptr::eq(log, &self.store) {
+            return in_log(&self.store);
+        }
+        Ok(false)
+    }
+
+    /// Look `key` up in its shard, then in the unsharded log.
+    fn lookup(&self, key: &Key) -> Result<Option<Entry>> {
+        let log = self.log_for(key);
+        match Entry::from_log(key, log)? {
+            None if !std::ptr::eq(log, &self.store) => Entry::from_log(key, &self.store),
+            entry => Ok(entry),
+        }
+    }
+
+    /// Open an existing `IndexedLogHgIdDataStore` without writing to disk.
+    pub fn open_read_only(
+        path: impl AsRef<Path>,
+        extstored_policy: ExtStoredPolicy,
+        config: &IndexedLogHgIdDataStoreConfig,
+        store_type: StoreType,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        load_dictionaries(path)?;
+        let log = IndexedLogHgIdDataStore::open_options(config).read_only(path, store_type)?;
+        let shards = shard_paths(path)?
+            .into_iter()
+            .map(|path| {
+                let log =
+                    IndexedLogHgIdDataStore::open_options(config).read_only(path, store_type)?;
+                Ok(RwLock::new(log))
+            })
+            .collect::<Result<_>>()?;
         Ok(IndexedLogHgIdDataStore {
+            path: path.to_path_buf(),
             store: RwLock::new(log),
+            shards,
             extstored_policy,
+            compression: Compression::default(),
             missing: MissingInjection::new_from_env("MISSING_FILES"),
+            access: None,
+        })
+    }
+
+    /// Length of the prefix identifying an entry, used by the GC.
+    pub(crate) const GC_KEY_LEN: usize = HgId::len();
+
+    /// Open options used by the GC to rewrite the shared store.
+    pub(crate) fn gc_open_options() -> StoreOpenOptions {
+        IndexedLogHgIdDataStore::open_options(&IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
         })
     }
 
@@ -251,14 +505,106 @@ impl IndexedLogHgIdDataStore {
         open_options
     }
 
+    /// Repair the store at `path`, and its shards.
     pub fn repair(
         path: PathBuf,
         config: &IndexedLogHgIdDataStoreConfig,
         store_type: StoreType,
     ) -> Result<String> {
+        let mut repair_str = String::new();
+        let shards = shard_paths(&path)?;
+        for path in std::iter::once(path).chain(shards) {
+            let open_options = IndexedLogHgIdDataStore::open_options(config);
+            repair_str += &match store_type {
+                StoreType::Local => open_options.repair_local(path)?,
+                StoreType::Shared => open_options.repair_shared(path)?,
+            };
+        }
+        Ok(repair_str)
+    }
+
+    /// Recompute the hgid of every entry of the store at `path` and its shards using the parents
+    /// found in `history`, and quarantine the entries that do not match. Return a summary and the
+    /// hgids of the corrupt entries. See [`crate::verify`].
+    pub(crate) fn verify(
+        path: PathBuf,
+        config: &IndexedLogHgIdDataStoreConfig,
+        store_type: StoreType,
+        history: &dyn HgIdHistoryStore,
+    ) -> Result<(String, HashSet<HgId>)> {
+        load_dictionaries(&path)?;
+        let mut stats = VerifyStats::default();
+        let mut corrupt = HashSet::new();
+        for log_path in std::iter::once(path.clone()).chain(shard_paths(&path)?) {
+            let (log_stats, log_corrupt) =
+                Self::verify_log(&log_path, config, store_type, history)?;
+            stats.checked += log_stats.checked;
+            stats.corrupt += log_stats.corrupt;
+            stats.unverifiable += log_stats.unverifiable;
+            corrupt.extend(log_corrupt);
+        }
+        Ok((stats.summary(&path), corrupt))
+    }
+
+    fn verify_log(
+        path: &Path,
+        config: &IndexedLogHgIdDataStoreConfig,
+        store_type: StoreType,
+        history: &dyn HgIdHistoryStore,
+    ) -> Result<(VerifyStats, HashSet<HgId>)> {
+        verify_store(
+            path,
+            |path| Self::open_store(path, config, store_type),
+            |bytes| {
+                let mut entry = Entry::from_bytes(bytes)?;
+                // LFS pointers are not hashed like regular content.
+                if entry.metadata.is_lfs() {
+                    return Ok(Verdict::Unverifiable);
+                }
+                let info = match history.get_node_info(&entry.key) {
+                    Ok(Some(info)) => info,
+                    _ => return Ok(Verdict::Unverifiable),
+                };
+                let content = entry.content()?;
+                let (p1, p2) = (info.parents[0].hgid, info.parents[1].hgid);
+                if hgid_matches(&entry.key, &content, p1, p2) {
+                    Ok(Verdict::Valid)
+                } else {
+                    Ok(Verdict::Corrupt(entry.key))
+                }
+            },
+        )
+    }
+
+    /// Quarantine the entries of the store at `path` with one of the `hgids` found corrupt in the
+    /// history store. See [`crate::verify`].
+    pub(crate) fn remove_hgids(
+        path: PathBuf,
+        config: &IndexedLogHgIdDataStoreConfig,
+        store_type: StoreType,
+        hgids: &HashSet<HgId>,
+    ) -> Result<String> {
+        load_dictionaries(&path)?;
+        let mut summaries = Vec::new();
+        for path in std::iter::once(path.clone()).chain(shard_paths(&path)?) {
+            summaries.push(remove_hgids(
+                &path,
+                |path| Self::open_store(path, config, store_type),
+                hgids,
+                |bytes| Ok(Entry::from_bytes(bytes)?.key),
+            )?);
+        }
+        Ok(summaries.concat())
+    }
+
+    fn open_store(
+        path: &Path,
+        config: &IndexedLogHgIdDataStoreConfig,
+        store_type: StoreType,
+    ) -> Result<Store> {
         match store_type {
-            StoreType::Local => IndexedLogHgIdDataStore::open_options(config).repair_local(path),
-            StoreType::Shared => IndexedLogHgIdDataStore::open_options(config).repair_shared(path),
+            StoreType::Local => Self::open_options(config).local(path),
+            StoreType::Shared => Self::open_options(config).shared(path),
         }
     }
 
@@ -270,19 +616,107 @@ impl IndexedLogHgIdDataStore {
     // TODO(meyer): Make IndexedLogHgIdDataStore "directly" lockable so we can lock and do a batch of operations (RwLock Guard pattern)
     /// Attempt to read an Entry from IndexedLog, without overwriting the Key (return Key path may not match the request Key path)
     pub(crate) fn get_raw_entry(&self, key: &Key) -> Result<Option<Entry>> {
-        Entry::from_log(key, &self.store)
+        let entry = self.lookup(key)?;
+        if let (Some(access), Some(_)) = (&self.access, &entry) {
+            access.touch(key.hgid.as_ref());
+        }
+        Ok(entry)
     }
 
     /// Write an entry to the IndexedLog
     pub fn put_entry(&self, entry: Entry) -> Result<()> {
-        entry.write_to_log(&self.store)
+        let log = self.log_for(&entry.key);
+        entry.write_to_log(log, &self.compression)
+    }
+
+    /// Copy the entries of `keys` that are only in the older generations of a shared store to its
+    /// newest generation. Rotation drops the oldest generation, so calling this after each flush
+    /// keeps these entries in stores of at least 3 generations. Returns the number of copied
+    /// entries, which still need to be flushed.
+    pub fn retain(&self, keys: &[Key]) -> Result<usize> {
+        let mut copied = 0;
+        for key in keys {
+            let log = self.log_for(key);
+            if log.read().contains_latest(0, key.hgid.as_ref())? {
+                continue;
+            }
+            if let Some(entry) = self.lookup(key)? {
+                entry.write_to_log(log, &self.compression)?;
+                copied += 1;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Compute the sizes of every entry of the store. See [`DataStoreStats`]. Entries are full
+    /// texts, so their delta chain length is always 1.
+    pub fn analyze(&self) -> Result<DataStoreStats> {
+        let mut stats = DataStoreStats::default();
+        for log in self.logs() {
+            let log = log.read();
+            for buf in log.iter() {
+                let buf = buf?;
+                // Lookups return the most recent copy of an entry, older copies are dead.
+                let latest = log
+                    .lookup(0, buf.get_err(..HgId::len())?)?
+                    .next()
+                    .transpose()?;
+                if latest.map(|latest| latest.as_ptr()) != Some(buf.as_ptr()) {
+                    stats.dead_entries += 1;
+                    stats.dead_bytes += buf.len() as u64;
+                    continue;
+                }
+
+                let entry = Entry::from_bytes(log.slice_to_bytes(buf))?;
+                stats.entries.push(DataEntryStats {
+                    key: entry.key.clone(),
+                    chain_len: 1,
+                    compressed_size: entry.stored_len() as u64,
+                    uncompressed_size: entry.content_inner()?.len() as u64,
+                });
+            }
+        }
+        Ok(stats)
     }
 
     /// Flush the underlying IndexedLog
     pub fn flush_log(&self) -> Result<()> {
-        self.store.write().flush()?;
+        for log in self.logs() {
+            log.write().flush()?;
+        }
+        if let Some(access) = &self.access {
+            access.flush()?;
+        }
         Ok(())
     }
+
+    /// Quickly check that the store at `path` can be opened, without opening or creating it: the
+    /// directories can be locked and the files were written by a version that understands them.
+    /// Return the problems found, in human readable form.
+    pub fn probe(path: &Path, store_type: StoreType) -> Vec<String> {
+        let mut problems = StoreOpenOptions::new().probe(path, store_type);
+        match shard_paths(path) {
+            Ok(shards) => {
+                for shard in shards {
+                    problems.extend(StoreOpenOptions::new().probe(&shard, store_type));
+                }
+            }
+            Err(e) => problems.push(e.to_string()),
+        }
+        problems
+    }
+}
+
+/// Turn the problems found by `probe`s of the stores of `what` into a single error.
+pub(crate) fn probe_result(what: &str, problems: Vec<String>) -> Result<()> {
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let mut msg = format!("{} cannot be used:\n  {}", what, problems.join("\n  "));
+    if problems.iter().any(|p| p.contains("newer version")) {
+        msg += "\n(the cache directory was created by a newer version, upgrade or use a different cache path)";
+    }
+    bail!(msg)
 }
 
 impl From<crate::memcache::McData> for Entry {
@@ -354,7 +788,7 @@ impl LocalStore for IndexedLogHgIdDataStore {
                         warn!("Force missing: {}", k.path);
                         return true;
                     }
-                    match Entry::from_log(k, &self.store) {
+                    match self.lookup(k) {
                         Ok(None) | Err(_) => true,
                         Ok(Some(_)) => false,
                     }
@@ -365,6 +799,16 @@ impl LocalStore for IndexedLogHgIdDataStore {
             .collect();
         Ok(missing)
     }
+
+    /// Only reads the index, the entry itself isn't read.
+    fn contains(&self, key: &StoreKey) -> Result<bool> {
+        match key {
+            StoreKey::HgId(key) => {
+                Ok(!self.missing.is_missing(&key.path) && self.lookup_index(key)?)
+            }
+            StoreKey::Content(_, _) => Ok(false),
+        }
+    }
 }
 
 impl HgIdDataStore for IndexedLogHgIdDataStore {
@@ -413,19 +857,25 @@ impl HgIdDataStore for IndexedLogHgIdDataStore {
 
 impl ToKeys for IndexedLogHgIdDataStore {
     fn to_keys(&self) -> Vec<Result<Key>> {
-        let log = &self.store.read();
-        log.iter()
-            .map(|entry| {
-                let bytes = log.slice_to_bytes(entry?);
-                Entry::from_bytes(bytes)
+        self.logs()
+            .flat_map(|log| {
+                let log = &log.read();
+                log.iter()
+                    .map(|entry| {
+                        let bytes = log.slice_to_bytes(entry?);
+                        Entry::from_bytes(bytes)
+                    })
+                    .map(|entry| Ok(entry?.key))
+                    .collect::<Vec<_>>()
             })
-            .map(|entry| Ok(entry?.key))
             .collect()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::collections::HashSet;
     use std::fs::remove_file;
     use std::sync::Arc;
 
@@ -434,8 +884,11 @@ mod tests {
     use types::testutil::*;
 
     use super::*;
+    use crate::compression::codec_of;
+    use crate::compression::CompressionCodec;
     use crate::scmstore::FileAttributes;
     use crate::scmstore::FileStore;
+    use crate::scmstore::FileStoreBuilder;
     use crate::testutil::*;
 
     #[test]
@@ -483,6 +936,150 @@ mod tests {
         log.flush().unwrap();
     }
 
+    #[test]
+    fn test_analyze() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &tempdir,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Shared,
+        )?;
+
+        let delta = Delta {
+            data: Bytes::from(&[1, 2, 3, 4][..]),
+            base: None,
+            key: key("a", "1"),
+        };
+        log.add(&delta, &Default::default())?;
+        log.add(&delta, &Default::default())?;
+        log.flush()?;
+
+        let stats = log.analyze()?;
+        assert_eq!(stats.entries.len(), 1);
+        assert_eq!(stats.entries[0].key, delta.key);
+        assert_eq!(stats.entries[0].chain_len, 1);
+        assert_eq!(stats.entries[0].uncompressed_size, 4);
+        assert_eq!(stats.dead_entries, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shards() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let open = || {
+            IndexedLogHgIdDataStore::new(&tempdir, ExtStoredPolicy::Use, &config, StoreType::Shared)
+        };
+
+        // Written before the store is sharded.
+        let unsharded = Delta {
+            data: Bytes::from(&[1, 2, 3, 4][..]),
+            base: None,
+            key: key("a", "1"),
+        };
+        let log = open()?;
+        log.add(&unsharded, &Default::default())?;
+        log.flush()?;
+        drop(log);
+
+        let log = open()?.with_shards(4, &config)?;
+        let deltas: Vec<Delta> = (2..10)
+            .map(|i| Delta {
+                data: Bytes::from(vec![i as u8; 4]),
+                base: None,
+                key: key(&format!("f{}", i), &i.to_string()),
+            })
+            .collect();
+        for delta in deltas.iter() {
+            log.add(delta, &Default::default())?;
+        }
+        log.flush()?;
+        drop(log);
+
+        let log = open()?.with_shards(4, &config)?;
+        for delta in deltas.iter().chain(std::iter::once(&unsharded)) {
+            let content = log.get(StoreKey::hgid(delta.key.clone()))?;
+            assert_eq!(content, StoreResult::Found(delta.data.as_ref().to_vec()));
+        }
+        assert_eq!(log.to_keys().len(), deltas.len() + 1);
+        assert_eq!(log.analyze()?.entries.len(), deltas.len() + 1);
+
+        // The recorded shards are opened without asking for them, and their number never changes.
+        let log = open()?;
+        assert_eq!(log.shards.len(), 4);
+        assert!(log
+            .get_missing(&[StoreKey::hgid(deltas[0].key.clone())])?
+            .is_empty());
+        let log = open()?.with_shards(8, &config)?;
+        assert_eq!(log.shards.len(), 4);
+        assert_eq!(read_shard_count(tempdir.path())?, Some(4));
+        assert!(tempdir.path().join("shard-3").is_dir());
+        assert!(!tempdir.path().join("shard-4").exists());
+
+        // The shard of an entry only depends on its hgid.
+        let content = log.get(StoreKey::hgid(key("renamed", "2")))?;
+        assert_eq!(
+            content,
+            StoreResult::Found(deltas[0].data.as_ref().to_vec())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_retain() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        // Rotate on every flush.
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: Some(3),
+            max_bytes_per_log: Some(ByteCount::from(1)),
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &tempdir,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Shared,
+        )?;
+        let delta = |name: &str, node: &str| Delta {
+            data: Bytes::from(name.as_bytes().to_vec()),
+            base: None,
+            key: key(name, node),
+        };
+        let pinned = delta("pinned", "1");
+        let other = delta("other", "2");
+        log.add(&pinned, &Default::default())?;
+        log.add(&other, &Default::default())?;
+        log.flush()?;
+
+        for i in 3..10 {
+            log.add(&delta("new", &i.to_string()), &Default::default())?;
+            log.flush()?;
+            if log.retain(&[pinned.key.clone()])? > 0 {
+                log.flush()?;
+            }
+        }
+
+        assert_eq!(
+            log.get(StoreKey::hgid(pinned.key.clone()))?,
+            StoreResult::Found(pinned.data.as_ref().to_vec())
+        );
+        assert_eq!(
+            log.get(StoreKey::hgid(other.key.clone()))?,
+            StoreResult::NotFound(StoreKey::hgid(other.key))
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_add_get() {
         let tempdir = TempDir::new().unwrap();
@@ -545,6 +1142,33 @@ mod tests {
         assert_eq!(log.get(key.clone()).unwrap(), StoreResult::NotFound(key));
     }
 
+    #[test]
+    fn test_contains() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = IndexedLogHgIdDataStore::new(
+            &tempdir,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Shared,
+        )?;
+
+        let delta = Delta {
+            data: Bytes::from(&[1, 2, 3, 4][..]),
+            base: None,
+            key: key("a", "1"),
+        };
+        log.add(&delta, &Default::default())?;
+
+        assert!(log.contains(&StoreKey::hgid(delta.key))?);
+        assert!(!log.contains(&StoreKey::hgid(key("a", "2")))?);
+        Ok(())
+    }
+
     #[test]
     fn test_add_chain() -> Result<()> {
         let tempdir = TempDir::new()?;
@@ -919,4 +1543,185 @@ mod tests {
         assert_eq!(missing[&lfs_key].len(), 1);
         Ok(())
     }
+
+    #[test]
+    fn test_mixed_compression() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let open = |compression: Compression| -> Result<IndexedLogHgIdDataStore> {
+            IndexedLogHgIdDataStore::new(
+                &tempdir,
+                ExtStoredPolicy::Use,
+                &config,
+                StoreType::Shared,
+            )?
+            .with_compression(compression)
+        };
+
+        let lz4_delta = delta("1234", None, key("a", "1"));
+        let log = open(Compression::lz4())?;
+        log.add(&lz4_delta, &Default::default())?;
+        log.flush()?;
+        drop(log);
+
+        // Entries written with either codec are readable.
+        let zstd_delta = delta("5678", None, key("b", "2"));
+        let log = open(Compression::zstd(3, None)?)?;
+        log.add(&zstd_delta, &Default::default())?;
+        assert_eq!(
+            log.get_entry(lz4_delta.key.clone())?.unwrap().content()?,
+            lz4_delta.data
+        );
+        assert_eq!(
+            log.get_entry(zstd_delta.key.clone())?.unwrap().content()?,
+            zstd_delta.data
+        );
+
+        // Copying an entry into a store with a different codec re-encodes it.
+        let other = TempDir::new()?;
+        let lz4_log =
+            IndexedLogHgIdDataStore::new(&other, ExtStoredPolicy::Use, &config, StoreType::Shared)?;
+        lz4_log.put_entry(log.get_entry(zstd_delta.key.clone())?.unwrap())?;
+        let mut entry = lz4_log.get_entry(zstd_delta.key.clone())?.unwrap();
+        assert_eq!(
+            codec_of(entry.compressed_content.as_ref().unwrap())?,
+            CompressionCodec::Lz4
+        );
+        assert_eq!(entry.content()?, zstd_delta.data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_sharded_local_fetch() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = Arc::new(IndexedLogHgIdDataStore::new(
+            &tempdir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Shared,
+        )?);
+
+        // Enough keys to be split across several shards, half of which are present.
+        let keys: Vec<Key> = (1..=10000)
+            .map(|i| key(&format!("f{}", i), &format!("{:x}", i)))
+            .collect();
+        for (i, k) in keys.iter().enumerate().step_by(2) {
+            let content = Bytes::from(format!("content {}", i));
+            log.put_entry(Entry::new(k.clone(), content, Default::default()))?;
+        }
+
+        let fetch = |shards| -> Result<_> {
+            let mut store = FileStore::empty();
+            store.indexedlog_local = Some(log.clone());
+            store.local_fetch_shards = shards;
+            let (found, missing, errors) = store
+                .fetch(keys.clone().into_iter(), FileAttributes::CONTENT)
+                .consume();
+            assert!(errors.is_empty());
+            let found = found
+                .into_iter()
+                .map(|(k, mut file)| Ok((k, file.file_content()?)))
+                .collect::<Result<HashMap<_, _>>>()?;
+            let missing = missing.into_keys().collect::<HashSet<_>>();
+            Ok((found, missing))
+        };
+
+        let (found, missing) = fetch(1)?;
+        assert_eq!(found.len(), 5000);
+        assert_eq!(missing.len(), 5000);
+        assert_eq!(fetch(4)?, (found, missing));
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_probe() -> Result<()> {
+        let cachedir = TempDir::new()?;
+        let localdir = TempDir::new()?;
+        let config = make_config(&cachedir);
+        let probe = || FileStoreBuilder::new(&config).local_path(&localdir).probe();
+
+        // Missing stores are fine, and are not created.
+        probe()?;
+        assert!(!cachedir.path().join("test").exists());
+
+        let store_config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let store_path = localdir.path().join("indexedlogdatastore");
+        let log = IndexedLogHgIdDataStore::new(
+            &store_path,
+            ExtStoredPolicy::Ignore,
+            &store_config,
+            StoreType::Local,
+        )?;
+        log.put_entry(Entry::new(
+            key("a", "1"),
+            Bytes::from("a"),
+            Default::default(),
+        ))?;
+        log.flush_log()?;
+        drop(log);
+        probe()?;
+
+        // Pretend the metadata was written by a newer version.
+        let meta_path = store_path.join("meta");
+        let mut meta = fs::read(&meta_path)?;
+        meta[4] = 9;
+        fs::write(&meta_path, meta)?;
+        let err = probe().unwrap_err().to_string();
+        assert!(err.contains("created by a newer version"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scmstore_fetch_metrics() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let log = Arc::new(IndexedLogHgIdDataStore::new(
+            &tempdir,
+            ExtStoredPolicy::Ignore,
+            &config,
+            StoreType::Local,
+        )?);
+
+        let k1 = key("a", "1");
+        let k2 = key("b", "2");
+        log.put_entry(Entry::new(
+            k1.clone(),
+            Bytes::from("1234"),
+            Default::default(),
+        ))?;
+
+        let mut store = FileStore::empty();
+        store.indexedlog_local = Some(log.clone());
+        let _ = store
+            .fetch(vec![k1, k2].into_iter(), FileAttributes::CONTENT)
+            .consume();
+
+        let metrics: HashMap<_, _> = store.metrics().into_iter().collect();
+        let local =
+            |name: &str| metrics.get(&format!("scmstore.file.fetch.indexedlog.local.{}", name));
+        assert_eq!(local("requests"), Some(&1));
+        assert_eq!(local("keys"), Some(&2));
+        assert_eq!(local("hits"), Some(&1));
+        assert_eq!(local("misses"), Some(&1));
+        assert!(local("bytes").is_some());
+        assert_eq!(local("errors"), None);
+        Ok(())
+    }
 }
@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
 use std::io::Cursor;
 use std::io::Write;
 use std::path::Path;
@@ -21,6 +22,7 @@ use minibytes::Bytes;
 use parking_lot::RwLock;
 use sha1::Digest;
 use sha1::Sha1;
+use tracing::warn;
 use types::hgid::ReadHgIdExt;
 use types::hgid::WriteHgIdExt;
 use types::HgId;
@@ -29,6 +31,9 @@ use types::NodeInfo;
 use types::RepoPath;
 use types::RepoPathBuf;
 
+use crate::datastore::HgIdDataStore;
+use crate::datastore::StoreResult;
+use crate::gc::AccessIndex;
 use crate::historystore::HgIdHistoryStore;
 use crate::historystore::HgIdMutableHistoryStore;
 use crate::indexedlogutil::Store;
@@ -38,9 +43,15 @@ use crate::localstore::LocalStore;
 use crate::repack::ToKeys;
 use crate::sliceext::SliceExt;
 use crate::types::StoreKey;
+use crate::util::path_has_prefix;
+use crate::verify::hgid_matches;
+use crate::verify::remove_hgids;
+use crate::verify::verify_store;
+use crate::verify::Verdict;
 
 pub struct IndexedLogHgIdHistoryStore {
     log: RwLock<Store>,
+    access: Option<AccessIndex>,
 }
 
 struct Entry {
@@ -138,18 +149,43 @@ impl Entry {
         })
     }
 
+    /// Read only the path of an entry. See [`from_slice`] for the on-disk format.
+    fn path_from_slice(data: &[u8]) -> Result<&RepoPath> {
+        let mut cur = Cursor::new(data);
+        // Jump over the hgid and hashed path.
+        cur.set_position(40);
+        let path_len = cur.read_u16::<BigEndian>()? as usize;
+        let path_slice = data.get_err(42..42 + path_len)?;
+        Ok(RepoPath::from_utf8(path_slice)?)
+    }
+
+    /// Read only the linknode of an entry, without decoding the path, parents or copy
+    /// information. See [`from_slice`] for the on-disk format.
+    fn linknode_from_slice(data: &[u8]) -> Result<HgId> {
+        let mut cur = Cursor::new(data);
+        // Jump over the hgid and hashed path.
+        cur.set_position(40);
+        let path_len = cur.read_u16::<BigEndian>()? as u64;
+        // Jump over the path, p1 and p2.
+        cur.set_position(cur.position() + path_len + 2 * HgId::len() as u64);
+        Ok(cur.read_hgid()?)
+    }
+
     /// Read an entry from the `IndexedLog` and deserialize it.
     pub fn from_log(key: &Key, log: &RwLock<Store>) -> Result<Option<Self>> {
+        Self::from_store(key, &log.read())
+    }
+
+    /// Like `from_log`, for callers already holding the lock.
+    fn from_store(key: &Key, log: &Store) -> Result<Option<Self>> {
         let index_key = Self::key_to_index_key(key);
 
-        let log = log.read();
         let mut log_entry = log.lookup(0, index_key)?;
         let buf = match log_entry.next() {
             None => return Ok(None),
             Some(buf) => buf?,
         };
         let buf = log.slice_to_bytes(buf);
-        drop(log);
         Self::from_slice(buf).map(Some)
     }
 
@@ -199,11 +235,46 @@ impl IndexedLogHgIdHistoryStore {
             StoreType::Local => open_options.local(&path),
             StoreType::Shared => open_options.shared(&path),
         }?;
+
+        // Only the shared cache is garbage collected.
+        let access = match store_type {
+            StoreType::Local => None,
+            StoreType::Shared => match AccessIndex::open(&path) {
+                Ok(access) => Some(access),
+                Err(err) => {
+                    warn!(%err, "cannot open access-time index");
+                    None
+                }
+            },
+        };
+
+        Ok(IndexedLogHgIdHistoryStore {
+            log: RwLock::new(log),
+            access,
+        })
+    }
+
+    /// Open an existing `IndexedLogHgIdHistoryStore` without writing to disk.
+    pub fn open_read_only(
+        path: impl AsRef<Path>,
+        config: &ConfigSet,
+        store_type: StoreType,
+    ) -> Result<Self> {
+        let log = Self::open_options(config)?.read_only(path, store_type)?;
         Ok(IndexedLogHgIdHistoryStore {
             log: RwLock::new(log),
+            access: None,
         })
     }
 
+    /// Length of the prefix identifying an entry, used by the GC.
+    pub(crate) const GC_KEY_LEN: usize = HgId::len() * 2;
+
+    /// Open options used by the GC to rewrite the shared store.
+    pub(crate) fn gc_open_options() -> Result<StoreOpenOptions> {
+        Self::open_options(&ConfigSet::new())
+    }
+
     fn open_options(config: &ConfigSet) -> Result<StoreOpenOptions> {
         let mut open_options = StoreOpenOptions::new()
             .max_log_count(4)
@@ -212,6 +283,16 @@ impl IndexedLogHgIdHistoryStore {
             .create(true)
             .index("node_and_path", |_| {
                 vec![IndexOutput::Reference(0..(HgId::len() * 2) as u64)]
+            })
+            .index("path", |data| {
+                // Skip the hgid and hashed path, see `Entry::from_slice`.
+                match data.get(40..42) {
+                    Some(len) => {
+                        let len = u16::from_be_bytes([len[0], len[1]]) as u64;
+                        vec![IndexOutput::Reference(42..42 + len)]
+                    }
+                    None => vec![],
+                }
             });
 
         if let Some(max_bytes_per_log) =
@@ -225,6 +306,60 @@ impl IndexedLogHgIdHistoryStore {
         Ok(open_options)
     }
 
+    /// Recompute the hgid of every entry of the store at `path` using the content found in `data`,
+    /// and quarantine the entries that do not match. Return a summary and the hgids of the corrupt
+    /// entries. See [`crate::verify`].
+    pub(crate) fn verify(
+        path: PathBuf,
+        config: &ConfigSet,
+        store_type: StoreType,
+        data: &dyn HgIdDataStore,
+    ) -> Result<(String, HashSet<HgId>)> {
+        let (stats, corrupt) = verify_store(
+            &path,
+            |path| Self::open_store(path, config, store_type),
+            |bytes| {
+                let entry = Entry::from_slice(bytes)?;
+                if entry.key.hgid == entry.p1 || entry.key.hgid == entry.p2 {
+                    return Ok(Verdict::Corrupt(entry.key));
+                }
+                let content = match data.get(StoreKey::hgid(entry.key.clone())) {
+                    Ok(StoreResult::Found(content)) => content,
+                    _ => return Ok(Verdict::Unverifiable),
+                };
+                if hgid_matches(&entry.key, &content, entry.p1, entry.p2) {
+                    Ok(Verdict::Valid)
+                } else {
+                    Ok(Verdict::Corrupt(entry.key))
+                }
+            },
+        )?;
+        Ok((stats.summary(&path), corrupt))
+    }
+
+    /// Quarantine the entries of the store at `path` with one of the `hgids` found corrupt in the
+    /// data store. See [`crate::verify`].
+    pub(crate) fn remove_hgids(
+        path: PathBuf,
+        config: &ConfigSet,
+        store_type: StoreType,
+        hgids: &HashSet<HgId>,
+    ) -> Result<String> {
+        remove_hgids(
+            &path,
+            |path| Self::open_store(path, config, store_type),
+            hgids,
+            |bytes| Ok(Entry::from_slice(bytes)?.key),
+        )
+    }
+
+    fn open_store(path: &Path, config: &ConfigSet, store_type: StoreType) -> Result<Store> {
+        match store_type {
+            StoreType::Local => Self::open_options(config)?.local(path),
+            StoreType::Shared => Self::open_options(config)?.shared(path),
+        }
+    }
+
     pub fn repair(path: PathBuf, config: &ConfigSet, store_type: StoreType) -> Result<String> {
         match store_type {
             StoreType::Local => {
@@ -259,12 +394,51 @@ impl HgIdHistoryStore for IndexedLogHgIdHistoryStore {
             None => return Ok(None),
             Some(entry) => entry,
         };
+        if let Some(access) = &self.access {
+            access.touch(&Entry::key_to_index_key(key));
+        }
         Ok(Some(entry.node_info()))
     }
 
     fn refresh(&self) -> Result<()> {
+        // Syncing the log also picks up entries written by other processes.
+        HgIdMutableHistoryStore::flush(self)?;
         Ok(())
     }
+
+    fn get_node_info_batch(&self, keys: &[Key]) -> Result<Vec<Option<NodeInfo>>> {
+        let log = self.log.read();
+        keys.iter()
+            .map(|key| {
+                let entry = match Entry::from_store(key, &log)? {
+                    None => return Ok(None),
+                    Some(entry) => entry,
+                };
+                if let Some(access) = &self.access {
+                    access.touch(&Entry::key_to_index_key(key));
+                }
+                Ok(Some(entry.node_info()))
+            })
+            .collect()
+    }
+
+    fn get_linknode_batch(&self, keys: &[Key]) -> Result<Vec<Option<HgId>>> {
+        let log = self.log.read();
+        keys.iter()
+            .map(|key| {
+                let index_key = Entry::key_to_index_key(key);
+                let buf = match log.lookup(0, &index_key)?.next() {
+                    None => return Ok(None),
+                    Some(buf) => buf?,
+                };
+                let linknode = Entry::linknode_from_slice(buf)?;
+                if let Some(access) = &self.access {
+                    access.touch(&index_key);
+                }
+                Ok(Some(linknode))
+            })
+            .collect()
+    }
 }
 
 impl HgIdMutableHistoryStore for IndexedLogHgIdHistoryStore {
@@ -275,6 +449,9 @@ impl HgIdMutableHistoryStore for IndexedLogHgIdHistoryStore {
 
     fn flush(&self) -> Result<Option<Vec<PathBuf>>> {
         self.log.write().flush()?;
+        if let Some(access) = &self.access {
+            access.flush()?;
+        }
         Ok(None)
     }
 }
@@ -290,6 +467,28 @@ impl ToKeys for IndexedLogHgIdHistoryStore {
             .map(|entry| Ok(entry?.key))
             .collect()
     }
+
+    /// The entries are found with the "path" index. The paths sharing `prefix` without being
+    /// under it, like "ab" for "a", are skipped after reading only their path.
+    fn iter_prefix(&self, prefix: &RepoPath) -> Vec<Result<Key>> {
+        let log = &self.log.read();
+        let entries = match log.lookup_prefix(1, prefix.as_byte_slice()) {
+            Ok(entries) => entries,
+            Err(e) => return vec![Err(e)],
+        };
+        entries
+            .into_iter()
+            .filter_map(|entry| {
+                match Entry::path_from_slice(entry) {
+                    Ok(path) if !path_has_prefix(path, prefix) => return None,
+                    Ok(_) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+                let bytes = log.slice_to_bytes(entry);
+                Some(Entry::from_slice(bytes).map(|entry| entry.key))
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -345,6 +544,33 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_linknode_batch() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let log = IndexedLogHgIdHistoryStore::new(&tempdir, &ConfigSet::new(), StoreType::Shared)?;
+        let copied = key("b", "1");
+        log.add(
+            &copied,
+            &NodeInfo {
+                parents: [key("a", "2"), null_key("b")],
+                linknode: hgid("3"),
+            },
+        )?;
+        let plain = key("a", "2");
+        log.add(
+            &plain,
+            &NodeInfo {
+                parents: [null_key("a"), null_key("a")],
+                linknode: hgid("4"),
+            },
+        )?;
+        log.flush()?;
+
+        let linknodes = log.get_linknode_batch(&[copied, key("c", "5"), plain])?;
+        assert_eq!(linknodes, vec![Some(hgid("3")), None, Some(hgid("4"))]);
+        Ok(())
+    }
+
     #[test]
     fn test_corrupted() -> Result<()> {
         let tempdir = TempDir::new()?;
@@ -388,4 +614,36 @@ mod tests {
         assert!(log.to_keys().into_iter().all(|e| e.unwrap() == k));
         Ok(())
     }
+
+    #[test]
+    fn test_iter_prefix() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let log = IndexedLogHgIdHistoryStore::new(&tempdir, &ConfigSet::new(), StoreType::Shared)?;
+        let nodeinfo = |path| NodeInfo {
+            parents: [key(path, "2"), null_key(path)],
+            linknode: hgid("3"),
+        };
+        log.add(&key("a", "1"), &nodeinfo("a"))?;
+        log.add(&key("a/b", "1"), &nodeinfo("a/b"))?;
+        log.add(&key("ab", "1"), &nodeinfo("ab"))?;
+
+        let iter_prefix = |log: &IndexedLogHgIdHistoryStore, prefix| {
+            log.iter_prefix(RepoPath::from_str(prefix)?)
+                .into_iter()
+                .collect::<Result<Vec<_>>>()
+        };
+        assert_eq!(
+            iter_prefix(&log, "a")?,
+            vec![key("a", "1"), key("a/b", "1")]
+        );
+        assert_eq!(iter_prefix(&log, "")?.len(), 3);
+        assert!(iter_prefix(&log, "b")?.is_empty());
+
+        // The path index is persisted.
+        log.flush()?;
+        drop(log);
+        let log = IndexedLogHgIdHistoryStore::new(&tempdir, &ConfigSet::new(), StoreType::Shared)?;
+        assert_eq!(iter_prefix(&log, "a/b")?, vec![key("a/b", "1")]);
+        Ok(())
+    }
 }
@@ -0,0 +1,213 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Cache of the aux data of trees, see `TreeAuxData`, so it can be served without fetching the
+//! tree content.
+
+use std::io::Cursor;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use configparser::config::ConfigSet;
+use configparser::convert::ByteCount;
+use indexedlog::log::IndexOutput;
+use minibytes::Bytes;
+use parking_lot::RwLock;
+use types::hgid::ReadHgIdExt;
+use types::sha::ReadSha256Ext;
+use types::sha::WriteSha256Ext;
+use types::Blake3;
+use types::HgId;
+use vlqencoding::VLQDecode;
+use vlqencoding::VLQEncode;
+
+use crate::indexedlogutil::Store;
+use crate::indexedlogutil::StoreOpenOptions;
+use crate::scmstore::tree::types::DirectoryAggregates;
+use crate::scmstore::tree::types::TreeAuxData;
+
+/// Version of the entries written by this version. Version 0 entries only had the directory
+/// aggregates, and are ignored.
+const VERSION: u8 = 1;
+
+/// Serialize the aux data of a tree, except its `child_metadata`.
+///
+/// The serialization format is as follows:
+/// - HgId <20 bytes>
+/// - Version <1 byte> (for compatibility)
+/// - total_size <u64 VLQ>
+/// - content_sha256 <32 bytes>
+/// - content_blake3 <32 bytes>
+/// - has_aggregates <1 byte>
+/// - if has_aggregates is 1:
+///   - entry_count <u64 VLQ>
+///   - descendant_file_count <u64 VLQ>
+///   - descendant_size <u64 VLQ>
+///   - max_depth <u64 VLQ>
+fn serialize(hgid: HgId, aux_data: &TreeAuxData) -> Result<Bytes> {
+    let mut buf = Vec::new();
+    buf.write_all(hgid.as_ref())?;
+    buf.write_u8(VERSION)?;
+    buf.write_vlq(aux_data.total_size)?;
+    buf.write_sha256(&aux_data.content_sha256)?;
+    buf.write_all(aux_data.content_blake3.as_ref())?;
+    match aux_data.aggregates {
+        None => buf.write_u8(0)?,
+        Some(aggregates) => {
+            buf.write_u8(1)?;
+            buf.write_vlq(aggregates.entry_count)?;
+            buf.write_vlq(aggregates.descendant_file_count)?;
+            buf.write_vlq(aggregates.descendant_size)?;
+            buf.write_vlq(aggregates.max_depth)?;
+        }
+    }
+    Ok(buf.into())
+}
+
+fn deserialize(bytes: Bytes) -> Result<Option<(HgId, TreeAuxData)>> {
+    let data: &[u8] = bytes.as_ref();
+    let mut cur = Cursor::new(data);
+
+    let hgid = cur.read_hgid()?;
+
+    let version = cur.read_u8()?;
+    if version != VERSION {
+        return Ok(None);
+    }
+
+    let total_size = cur.read_vlq()?;
+    let content_sha256 = cur.read_sha256()?;
+    let mut content_blake3 = [0u8; Blake3::len()];
+    std::io::Read::read_exact(&mut cur, &mut content_blake3)?;
+    let aggregates = match cur.read_u8()? {
+        0 => None,
+        _ => Some(DirectoryAggregates {
+            entry_count: cur.read_vlq()?,
+            descendant_file_count: cur.read_vlq()?,
+            descendant_size: cur.read_vlq()?,
+            max_depth: cur.read_vlq()?,
+        }),
+    };
+
+    Ok(Some((
+        hgid,
+        TreeAuxData {
+            total_size,
+            content_sha256,
+            content_blake3: Blake3::from_byte_array(content_blake3),
+            child_metadata: Vec::new(),
+            aggregates,
+        },
+    )))
+}
+
+pub struct TreeAuxStore(RwLock<Store>);
+
+impl TreeAuxStore {
+    pub fn new(path: impl AsRef<Path>, config: &ConfigSet) -> Result<Self> {
+        let log = TreeAuxStore::open_options(config)?.shared(&path)?;
+        Ok(TreeAuxStore(RwLock::new(log)))
+    }
+
+    fn open_options(config: &ConfigSet) -> Result<StoreOpenOptions> {
+        let mut open_options = StoreOpenOptions::new()
+            .max_log_count(4)
+            .max_bytes_per_log(100 * 1000 * 1000 / 4)
+            .auto_sync_threshold(10 * 1024 * 1024)
+            .create(true)
+            .index("node", |_| {
+                vec![IndexOutput::Reference(0..HgId::len() as u64)]
+            });
+
+        if let Some(max_bytes_per_log) =
+            config.get_opt::<ByteCount>("indexedlog", "tree-aux.max-bytes-per-log")?
+        {
+            open_options = open_options.max_bytes_per_log(max_bytes_per_log.value());
+        }
+        Ok(open_options)
+    }
+
+    /// The cached aux data of the tree `hgid`. Its `child_metadata` is always empty.
+    pub fn get(&self, hgid: HgId) -> Result<Option<TreeAuxData>> {
+        let log = self.0.read();
+        let mut entries = log.lookup(0, &hgid)?;
+
+        let slice = match entries.next() {
+            None => return Ok(None),
+            Some(slice) => slice?,
+        };
+        let bytes = log.slice_to_bytes(slice);
+        drop(log);
+
+        Ok(deserialize(bytes)?.map(|(_hgid, aux_data)| aux_data))
+    }
+
+    pub fn put(&self, hgid: HgId, aux_data: &TreeAuxData) -> Result<()> {
+        let serialized = serialize(hgid, aux_data)?;
+        self.0.write().append(&serialized)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.0.write().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use types::testutil::*;
+    use types::Sha256;
+
+    use super::*;
+
+    #[test]
+    fn test_add_get() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = TreeAuxStore::new(&tempdir, &ConfigSet::new())?;
+
+        let aux_data = TreeAuxData {
+            total_size: 123,
+            content_sha256: Sha256::from_byte_array([1; 32]),
+            content_blake3: Blake3::from_byte_array([2; 32]),
+            child_metadata: Vec::new(),
+            aggregates: Some(DirectoryAggregates {
+                entry_count: 3,
+                descendant_file_count: 10,
+                descendant_size: 1 << 40,
+                max_depth: 2,
+            }),
+        };
+        let without_aggregates = TreeAuxData {
+            aggregates: None,
+            ..aux_data.clone()
+        };
+        store.put(hgid("1"), &aux_data)?;
+        store.put(hgid("3"), &without_aggregates)?;
+        store.flush()?;
+        drop(store);
+
+        let store = TreeAuxStore::new(&tempdir, &ConfigSet::new())?;
+        assert_eq!(store.get(hgid("1"))?, Some(aux_data));
+        assert_eq!(store.get(hgid("2"))?, None);
+        assert_eq!(store.get(hgid("3"))?, Some(without_aggregates));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_old_version() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let store = TreeAuxStore::new(&tempdir, &ConfigSet::new())?;
+        let mut entry = hgid("1").as_ref().to_vec();
+        entry.extend_from_slice(&[0, 1, 2, 3, 4]);
+        store.0.write().append(&entry)?;
+        assert_eq!(store.get(hgid("1"))?, None);
+        Ok(())
+    }
+}
@@ -18,6 +18,7 @@ use indexedlog::log::LogLookupIter;
 use indexedlog::rotate;
 use indexedlog::rotate::RotateLog;
 use indexedlog::rotate::RotateLogLookupIter;
+use indexedlog::rotate::RotateLowLevelExt;
 use indexedlog::OpenWithRepair;
 use indexedlog::Result as IndexedlogResult;
 use minibytes::Bytes;
@@ -57,6 +58,35 @@ impl Store {
         }
     }
 
+    /// The entries indexed by `index_id` under a key starting with `prefix`, newest generation
+    /// first.
+    pub fn lookup_prefix(&self, index_id: usize, prefix: impl AsRef<[u8]>) -> Result<Vec<&[u8]>> {
+        let logs = match self {
+            Store::Local(log) => vec![log],
+            Store::Shared(log) => log.logs(),
+        };
+        let mut entries = Vec::new();
+        for log in logs {
+            for item in log.lookup_prefix(index_id, prefix.as_ref())? {
+                let (_key, iter) = item?;
+                for entry in iter {
+                    entries.push(entry?);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Whether the newest generation of the store has an entry for `key`. Local stores only have
+    /// one generation.
+    pub fn contains_latest(&self, index_id: usize, key: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        match self {
+            Store::Local(log) => Ok(log.lookup(index_id, key)?.next().is_some()),
+            Store::Shared(log) => Ok(log.lookup_latest(index_id, key)?.next().is_some()),
+        }
+    }
+
     /// Add the buffer to the store.
     pub fn append(&mut self, buf: impl AsRef<[u8]>) -> Result<()> {
         match self {
@@ -223,6 +253,31 @@ impl StoreOpenOptions {
         Ok(Store::Shared(rotate_log))
     }
 
+    /// Open an existing `Store` without writing to disk.
+    ///
+    /// Unlike `local` and `shared`, this never creates the store, repairs it, or removes old
+    /// logs.
+    pub fn read_only(self, path: impl AsRef<Path>, store_type: StoreType) -> Result<Store> {
+        let options = self.create(false);
+        Ok(match store_type {
+            StoreType::Local => {
+                Store::Local(options.into_local_open_options().open(path.as_ref())?)
+            }
+            StoreType::Shared => {
+                Store::Shared(options.into_shared_open_options().open(path.as_ref())?)
+            }
+        })
+    }
+
+    /// Quickly check whether the `Store` at `path` can be opened, without opening it. Return the
+    /// problems found, in human readable form. See `indexedlog::log::OpenOptions::probe`.
+    pub fn probe(self, path: impl AsRef<Path>, store_type: StoreType) -> Vec<String> {
+        match store_type {
+            StoreType::Local => self.into_local_open_options().probe(path.as_ref()),
+            StoreType::Shared => self.into_shared_open_options().probe(path.as_ref()),
+        }
+    }
+
     /// Attempts to repair corruption in a local indexedlog store.
     ///
     /// Note, this may delete data, though it should only delete data that is unreadable.
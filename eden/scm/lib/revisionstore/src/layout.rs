@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Layout of the shared cache directory (aka "hgcache").
+//!
+//! All the stores of a repository live under `<remotefilelog.cachepath>/<reponame>`, with the
+//! trees under an additional "manifests" suffix. The cache can be moved to a new root by setting
+//! `remotefilelog.cachepath` to the new location and `remotefilelog.previouscachepath` to the old
+//! one: new data is only written to the new root, while the stores of the previous root keep
+//! being read until the new root is populated and the setting is removed.
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use configparser::config::ConfigSet;
+use util::path::create_shared_dir;
+
+use crate::util::get_cache_path;
+use crate::util::get_indexedlogdatastore_aux_path;
+use crate::util::get_indexedlogdatastore_path;
+use crate::util::get_indexedloghistorystore_path;
+use crate::util::get_negativecache_path;
+use crate::util::get_packs_path;
+use crate::util::get_repo_name;
+use crate::util::get_treeauxstore_path;
+use crate::util::get_treepagestore_path;
+
+#[derive(Clone, Debug)]
+pub struct CacheLayout {
+    /// `<cachepath>/<reponame>`.
+    root: PathBuf,
+    /// `<previouscachepath>/<reponame>`, if it exists.
+    previous_root: Option<PathBuf>,
+    suffix: Option<PathBuf>,
+}
+
+impl CacheLayout {
+    /// Compute the layout from the config. The root of the cache is created if needed, the
+    /// previous root is never created.
+    pub fn from_config(config: &ConfigSet, suffix: &Option<impl AsRef<Path>>) -> Result<Self> {
+        let root = get_cache_path(config, &None::<PathBuf>)?;
+        let previous_root = match config.get_opt::<PathBuf>("remotefilelog", "previouscachepath")? {
+            Some(previous) => {
+                let previous = previous.join(get_repo_name(config)?);
+                if previous.is_dir() && previous != root {
+                    Some(previous)
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+        Ok(Self {
+            root,
+            previous_root,
+            suffix: suffix.as_ref().map(|s| s.as_ref().to_path_buf()),
+        })
+    }
+
+    /// Root of the stores for this suffix, ex. `<cachepath>/<reponame>/manifests`.
+    pub fn store_path(&self) -> Result<PathBuf> {
+        get_cache_path_at(&self.root, &self.suffix)
+    }
+
+    pub fn indexedlogdatastore_path(&self) -> Result<PathBuf> {
+        get_indexedlogdatastore_path(self.store_path()?)
+    }
+
+    pub fn indexedlogdatastore_aux_path(&self) -> Result<PathBuf> {
+        get_indexedlogdatastore_aux_path(self.store_path()?)
+    }
+
+    pub fn indexedloghistorystore_path(&self) -> Result<PathBuf> {
+        get_indexedloghistorystore_path(self.store_path()?)
+    }
+
+    pub fn negativecache_path(&self) -> Result<PathBuf> {
+        get_negativecache_path(self.store_path()?)
+    }
+
+    pub fn treepagestore_path(&self) -> Result<PathBuf> {
+        get_treepagestore_path(self.store_path()?)
+    }
+
+    pub fn treeauxstore_path(&self) -> Result<PathBuf> {
+        get_treeauxstore_path(self.store_path()?)
+    }
+
+    /// The LFS stores are created by `LfsStore::shared` under this directory.
+    pub fn lfs_store_path(&self) -> Result<PathBuf> {
+        self.store_path()
+    }
+
+    /// Unlike the other stores, the packs are stored as `<reponame>/packs/<suffix>`.
+    pub fn packs_path(&self) -> Result<PathBuf> {
+        get_packs_path(&self.root, &self.suffix)
+    }
+
+    /// Whether the cache is being relocated from a previous root.
+    pub fn is_relocating(&self) -> bool {
+        self.previous_root.is_some()
+    }
+
+    /// Path of `name` in the stores of the previous root, if it exists. Nothing is created.
+    fn previous_path(&self, name: &str) -> Option<PathBuf> {
+        let mut path = self.previous_root.clone()?;
+        if let Some(ref suffix) = self.suffix {
+            path.push(suffix);
+        }
+        path.push(name);
+        path.is_dir().then_some(path)
+    }
+
+    /// The indexedlog data store of the previous root, if it exists. It should only be read.
+    pub fn previous_indexedlogdatastore_path(&self) -> Option<PathBuf> {
+        self.previous_path("indexedlogdatastore")
+    }
+
+    /// The indexedlog aux store of the previous root, if it exists. It should only be read.
+    pub fn previous_indexedlogdatastore_aux_path(&self) -> Option<PathBuf> {
+        self.previous_path("indexedlogdatastore_aux")
+    }
+
+    /// The indexedlog history store of the previous root, if it exists. It should only be read.
+    pub fn previous_indexedloghistorystore_path(&self) -> Option<PathBuf> {
+        self.previous_path("indexedloghistorystore")
+    }
+
+    /// The directory of the LFS stores of the previous root, if they exist. They should only be
+    /// read, see `LfsStore::open_read_only`.
+    pub fn previous_lfs_store_path(&self) -> Option<PathBuf> {
+        let lfs = self.previous_path("lfs")?;
+        let complete = lfs.join("pointers").is_dir() && lfs.join("blobs").is_dir();
+        complete.then(|| lfs.parent().unwrap().to_path_buf())
+    }
+}
+
+fn get_cache_path_at(root: &Path, suffix: &Option<PathBuf>) -> Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    if let Some(ref suffix) = suffix {
+        path.push(suffix);
+        create_shared_dir(&path)?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::testutil::make_config;
+
+    #[test]
+    fn test_layout() -> Result<()> {
+        let dir = TempDir::new()?;
+        let config = make_config(&dir);
+
+        let layout = CacheLayout::from_config(&config, &Some("manifests"))?;
+        assert_eq!(
+            layout.indexedlogdatastore_path()?,
+            dir.path().join("test/manifests/indexedlogdatastore")
+        );
+        assert_eq!(
+            layout.packs_path()?,
+            dir.path().join("test/packs/manifests")
+        );
+        assert!(!layout.is_relocating());
+        assert_eq!(layout.previous_indexedlogdatastore_path(), None);
+        assert_eq!(layout.previous_lfs_store_path(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_relocation() -> Result<()> {
+        let old = TempDir::new()?;
+        let new = TempDir::new()?;
+
+        let old_layout = CacheLayout::from_config(&make_config(&old), &None::<PathBuf>)?;
+        let old_path = old_layout.indexedlogdatastore_path()?;
+        let old_history_path = old_layout.indexedloghistorystore_path()?;
+        let old_aux_path = old_layout.indexedlogdatastore_aux_path()?;
+
+        let mut config = make_config(&new);
+        config.set(
+            "remotefilelog",
+            "previouscachepath",
+            Some(old.path().to_str().unwrap()),
+            &Default::default(),
+        );
+        let layout = CacheLayout::from_config(&config, &None::<PathBuf>)?;
+        assert!(layout.is_relocating());
+        assert_eq!(
+            layout.indexedlogdatastore_path()?,
+            new.path().join("test/indexedlogdatastore")
+        );
+        assert_eq!(layout.previous_indexedlogdatastore_path(), Some(old_path));
+        assert_eq!(
+            layout.previous_indexedloghistorystore_path(),
+            Some(old_history_path)
+        );
+        assert_eq!(
+            layout.previous_indexedlogdatastore_aux_path(),
+            Some(old_aux_path)
+        );
+        // The LFS stores were never created in the previous root.
+        assert_eq!(layout.previous_lfs_store_path(), None);
+        assert!(!old.path().join("test/lfs").exists());
+
+        // The previous trees were never written to.
+        let layout = CacheLayout::from_config(&config, &Some("manifests"))?;
+        assert_eq!(layout.previous_indexedlogdatastore_path(), None);
+        Ok(())
+    }
+}
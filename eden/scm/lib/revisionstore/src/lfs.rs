@@ -38,6 +38,8 @@ use async_runtime::stream_to_iter;
 use auth::AuthSection;
 use configparser::config::ConfigSet;
 use configparser::convert::ByteCount;
+use error_code::ErrorCode;
+use error_code::WithErrorCode;
 use futures::future::FutureExt;
 use futures::stream::iter;
 use futures::stream::FuturesUnordered;
@@ -70,6 +72,7 @@ use mincode::serialize;
 use minibytes::Bytes;
 use parking_lot::Mutex;
 use parking_lot::RwLock;
+use progress_model::ProgressBar;
 use rand::thread_rng;
 use rand::Rng;
 use serde_derive::Deserialize;
@@ -104,6 +107,7 @@ use crate::historystore::HgIdMutableHistoryStore;
 use crate::historystore::RemoteHistoryStore;
 use crate::indexedlogutil::Store;
 use crate::indexedlogutil::StoreOpenOptions;
+use crate::indexedlogutil::StoreType;
 use crate::localstore::LocalStore;
 use crate::redacted;
 use crate::redacted::is_redacted;
@@ -139,11 +143,39 @@ pub(crate) enum LfsBlobsStore {
 }
 
 pub(crate) struct HttpLfsRemote {
-    url: Url,
+    /// Servers to send the batch requests to, in order of preference.
+    endpoints: Vec<LfsEndpoint>,
     client: Arc<HttpClient>,
-    concurrent_fetches: usize,
     download_chunk_size: Option<NonZeroU64>,
     http_options: Arc<HttpOptions>,
+    /// How long an endpoint is skipped after a failed batch request.
+    unhealthy_timeout: Duration,
+}
+
+/// One of the LFS servers listed in `lfs.url`.
+struct LfsEndpoint {
+    url: Url,
+    concurrent_fetches: usize,
+    /// Set when a batch request to this endpoint failed. Until then, the other endpoints are
+    /// tried first.
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl LfsEndpoint {
+    fn is_healthy(&self) -> bool {
+        match *self.unhealthy_until.lock() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    fn mark_healthy(&self) {
+        *self.unhealthy_until.lock() = None;
+    }
+
+    fn mark_unhealthy(&self, timeout: Duration) {
+        *self.unhealthy_until.lock() = Some(Instant::now() + timeout);
+    }
 }
 
 struct HttpOptions {
@@ -274,6 +306,14 @@ impl LfsPointersStore {
         Ok(Self(LfsPointersStore::open_options(config)?.shared(path)?))
     }
 
+    /// Open an existing shared `LfsPointersStore` without writing to disk.
+    fn open_read_only(path: &Path, config: &ConfigSet) -> Result<Self> {
+        let path = path.join("lfs").join("pointers");
+        Ok(Self(
+            LfsPointersStore::open_options(config)?.read_only(path, StoreType::Shared)?,
+        ))
+    }
+
     /// Read an entry from the slice and deserialize it.
     fn get_from_slice(data: &[u8]) -> Result<LfsPointersEntry> {
         Ok(deserialize(data)?)
@@ -359,6 +399,19 @@ impl LfsIndexedLogBlobsStore {
         })
     }
 
+    /// Open an existing shared `LfsIndexedLogBlobsStore` without writing to disk.
+    fn open_read_only(path: &Path, config: &ConfigSet) -> Result<Self> {
+        let path = path.join("lfs").join("blobs");
+        Ok(Self {
+            inner: RwLock::new(
+                LfsIndexedLogBlobsStore::open_options(config)?
+                    .read_only(path, StoreType::Shared)?,
+            ),
+            chunk_size: LfsIndexedLogBlobsStore::chunk_size(config)?,
+            skip_hash_on_read: config.get_or("lfs", "skiphashonread", || false)?,
+        })
+    }
+
     pub fn get(&self, hash: &Sha256, total_size: u64) -> Result<Option<Bytes>> {
         let store = self.inner.read();
         let chunks_iter = store
@@ -501,6 +554,19 @@ impl LfsBlobsStore {
         Ok(LfsBlobsStore::union(indexedlog, loose))
     }
 
+    /// Like `shared`, but open an existing store without writing to disk.
+    fn open_read_only(path: &Path, config: &ConfigSet) -> Result<Self> {
+        let indexedlog = Box::new(LfsBlobsStore::IndexedLog(
+            LfsIndexedLogBlobsStore::open_read_only(path, config)?,
+        ));
+        let loose = Box::new(LfsBlobsStore::Loose(
+            path.join("lfs").join("objects"),
+            false,
+        ));
+
+        Ok(LfsBlobsStore::union(indexedlog, loose))
+    }
+
     /// Loose shared blob store. Intended to be used when the remote store destination is FS
     /// backed instead of HTTP backed.
     fn loose(path: PathBuf) -> Self {
@@ -657,6 +723,15 @@ impl LfsStore {
         LfsStore::new(pointers, blobs)
     }
 
+    /// Open an existing shared `LfsStore` without writing to disk, ex. the one of a previous
+    /// cache root. See `CacheLayout::previous_lfs_store_path`.
+    pub fn open_read_only(path: impl AsRef<Path>, config: &ConfigSet) -> Result<Self> {
+        let path = path.as_ref();
+        let pointers = LfsPointersStore::open_read_only(path, config)?;
+        let blobs = LfsBlobsStore::open_read_only(path, config)?;
+        LfsStore::new(pointers, blobs)
+    }
+
     pub fn repair(path: impl AsRef<Path>) -> Result<String> {
         let path = path.as_ref();
         let mut repair_str = String::new();
@@ -1076,7 +1151,7 @@ impl LfsRemoteInner {
         error_handler: impl FnMut(Sha256, Error),
     ) -> Result<()> {
         let read_from_store = |_sha256, _size| unreachable!();
-        match self {
+        let result = match self {
             LfsRemoteInner::Http(http) => Self::batch_http(
                 http,
                 objs,
@@ -1084,19 +1159,23 @@ impl LfsRemoteInner {
                 read_from_store,
                 write_to_store,
                 error_handler,
+                None,
             ),
             LfsRemoteInner::File(file) => Self::batch_fetch_file(file, objs, write_to_store),
-        }
+        };
+        result.with_error_code(ErrorCode::LfsTransferFailed)
     }
 
+    /// Upload blobs, advancing `progress` by the size of each uploaded blob.
     pub fn batch_upload(
         &self,
         objs: &HashSet<(Sha256, usize)>,
         read_from_store: impl Fn(Sha256, u64) -> Result<Option<Bytes>> + Send + Clone + 'static,
         error_handler: impl FnMut(Sha256, Error),
+        progress: Arc<ProgressBar>,
     ) -> Result<()> {
         let write_to_store = |_, _| unreachable!();
-        match self {
+        let result = match self {
             LfsRemoteInner::Http(http) => Self::batch_http(
                 http,
                 objs,
@@ -1104,9 +1183,13 @@ impl LfsRemoteInner {
                 read_from_store,
                 write_to_store,
                 error_handler,
+                Some(progress),
             ),
-            LfsRemoteInner::File(file) => Self::batch_upload_file(file, objs, read_from_store),
-        }
+            LfsRemoteInner::File(file) => {
+                Self::batch_upload_file(file, objs, read_from_store, &progress)
+            }
+        };
+        result.with_error_code(ErrorCode::LfsTransferFailed)
     }
 
     async fn send_with_retry(
@@ -1116,6 +1199,7 @@ impl LfsRemoteInner {
         add_extra: impl Fn(Request) -> Request,
         check_status: impl Fn(StatusCode) -> Result<(), TransferError>,
         http_options: Arc<HttpOptions>,
+        on_retry: impl Fn(usize),
     ) -> Result<Bytes, FetchError> {
         if http_options.missing_client_certs {
             return Err(FetchError {
@@ -1270,6 +1354,7 @@ impl LfsRemoteInner {
                         );
                         sleep(sleep_time).await;
                     }
+                    on_retry(attempt + 1);
                     continue;
                 }
 
@@ -1294,6 +1379,7 @@ impl LfsRemoteInner {
 
     fn send_batch_request(
         http: &HttpLfsRemote,
+        endpoint: &LfsEndpoint,
         objs: &HashSet<(Sha256, usize)>,
         operation: Operation,
     ) -> Result<Option<ResponseBatch>> {
@@ -1317,7 +1403,7 @@ impl LfsRemoteInner {
 
         let batch_json = serde_json::to_string(&batch)?;
 
-        let batch_url = http.url.join("objects/batch")?;
+        let batch_url = endpoint.url.join("objects/batch")?;
 
         let response_fut = async move {
             LfsRemoteInner::send_with_retry(
@@ -1327,6 +1413,7 @@ impl LfsRemoteInner {
                 move |builder| builder.body(batch_json.clone()),
                 |_| Ok(()),
                 http.http_options.clone(),
+                |_| {},
             )
             .await
         };
@@ -1342,8 +1429,10 @@ impl LfsRemoteInner {
         size: u64,
         read_from_store: impl Fn(Sha256, u64) -> Result<Option<Bytes>> + Send + 'static,
         http_options: Arc<HttpOptions>,
+        progress: Option<Arc<ProgressBar>>,
     ) -> Result<()> {
         let body = spawn_blocking(move || read_from_store(oid, size)).await??;
+        let body_len = body.as_ref().map_or(0, |body| body.len() as u64);
 
         let url = Url::from_str(&action.href.to_string())?;
         LfsRemoteInner::send_with_retry(
@@ -1361,9 +1450,18 @@ impl LfsRemoteInner {
             },
             |_| Ok(()),
             http_options,
+            |attempt| {
+                hg_metrics::increment_counter("lfs.upload.retries", 1);
+                if let Some(ref progress) = progress {
+                    progress.set_message(format!("retrying {} (attempt {})", oid, attempt));
+                }
+            },
         )
         .await?;
 
+        if let Some(ref progress) = progress {
+            progress.increase_position(body_len);
+        }
         Ok(())
     }
 
@@ -1410,6 +1508,7 @@ impl LfsRemoteInner {
                                 })
                             },
                             http_options.clone(),
+                            |_| {},
                         )
                         .await?;
 
@@ -1430,6 +1529,7 @@ impl LfsRemoteInner {
                     |builder| add_action_headers_to_request(builder, &action),
                     |_| Ok(()),
                     http_options,
+                    |_| {},
                 )
                 .await
             }
@@ -1450,6 +1550,56 @@ impl LfsRemoteInner {
         Ok((oid, data))
     }
 
+    /// Whether `error` means that the endpoint itself is broken: the transfer failed or the server
+    /// answered with a 5xx. Other errors, like a 4xx, would happen with any endpoint.
+    fn is_endpoint_failure(error: &Error) -> bool {
+        match error.downcast_ref::<FetchError>().map(|e| &e.error) {
+            Some(TransferError::HttpStatus(status, _))
+            | Some(TransferError::UnexpectedHttpStatus {
+                received: status, ..
+            }) => status.is_server_error(),
+            Some(TransferError::HttpClientError(_))
+            | Some(TransferError::EndOfStream)
+            | Some(TransferError::Timeout(_))
+            | Some(TransferError::ChunkTimeout { .. }) => true,
+            Some(TransferError::InvalidResponse(_)) | None => false,
+        }
+    }
+
+    /// Send the batch request to the first endpoint that answers it.
+    ///
+    /// Endpoints are tried in configuration order, except that the ones that recently failed are
+    /// only tried once all the others failed. Only transport errors and 5xx responses mark an
+    /// endpoint as failed.
+    fn send_batch_request_with_failover<'a>(
+        http: &'a HttpLfsRemote,
+        objs: &HashSet<(Sha256, usize)>,
+        operation: Operation,
+    ) -> Result<(&'a LfsEndpoint, Option<ResponseBatch>)> {
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) =
+            http.endpoints.iter().partition(|e| e.is_healthy());
+
+        let mut last_error = None;
+        for endpoint in healthy.into_iter().chain(unhealthy) {
+            match LfsRemoteInner::send_batch_request(http, endpoint, objs, operation) {
+                Ok(response) => {
+                    endpoint.mark_healthy();
+                    return Ok((endpoint, response));
+                }
+                Err(error) => {
+                    tracing::warn!(url = %endpoint.url, ?error, "LFS endpoint failed");
+                    if LfsRemoteInner::is_endpoint_failure(&error) {
+                        hg_metrics::increment_counter("lfs.endpoint_failure", 1);
+                        endpoint.mark_unhealthy(http.unhealthy_timeout);
+                    }
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("no LFS endpoint configured")))
+    }
+
     /// Fetch and Upload blobs from the LFS server.
     ///
     /// When uploading, the `write_to_store` is guaranteed not to be called, similarly when fetching,
@@ -1463,8 +1613,10 @@ impl LfsRemoteInner {
         read_from_store: impl Fn(Sha256, u64) -> Result<Option<Bytes>> + Send + Clone + 'static,
         mut write_to_store: impl FnMut(Sha256, Bytes) -> Result<()>,
         mut error_handler: impl FnMut(Sha256, Error),
+        upload_progress: Option<Arc<ProgressBar>>,
     ) -> Result<()> {
-        let response = LfsRemoteInner::send_batch_request(http, objs, operation)?;
+        let (endpoint, response) =
+            LfsRemoteInner::send_batch_request_with_failover(http, objs, operation)?;
         let response = match response {
             None => return Ok(()),
             Some(response) => response,
@@ -1499,6 +1651,7 @@ impl LfsRemoteInner {
                         object.object.size,
                         read_from_store.clone(),
                         http.http_options.clone(),
+                        upload_progress.clone(),
                     )
                     .map(|_| None)
                     .left_future(),
@@ -1519,7 +1672,7 @@ impl LfsRemoteInner {
         }
 
         // Request blobs concurrently.
-        let stream = stream_to_iter(iter(futures).buffer_unordered(http.concurrent_fetches));
+        let stream = stream_to_iter(iter(futures).buffer_unordered(endpoint.concurrent_fetches));
 
         // It's awkward that the futures are shared for uploading and downloading. We use Some(_)
         // to indicate if the result came from the download path, and 'flatten' filters out the
@@ -1551,10 +1704,13 @@ impl LfsRemoteInner {
         file: &LfsBlobsStore,
         objs: &HashSet<(Sha256, usize)>,
         read_from_store: impl Fn(Sha256, u64) -> Result<Option<Bytes>>,
+        progress: &ProgressBar,
     ) -> Result<()> {
         for (sha256, size) in objs {
             if let Some(blob) = read_from_store(*sha256, *size as u64)? {
+                let len = blob.len() as u64;
                 file.add(sha256, blob)?;
+                progress.increase_position(len);
             }
         }
 
@@ -1569,16 +1725,28 @@ impl LfsRemote {
         config: &ConfigSet,
         correlator: Option<String>,
     ) -> Result<Self> {
-        let mut url = get_str_config(config, "lfs", "url")?;
-        // A trailing '/' needs to be present so that `Url::join` doesn't remove the reponame
-        // present at the end of the config.
-        url.push('/');
-        let url = Url::parse(&url)?;
+        let urls = get_str_config(config, "lfs", "url")?
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(|url| {
+                // A trailing '/' needs to be present so that `Url::join` doesn't remove the
+                // reponame present at the end of the config.
+                Ok(Url::parse(&format!("{}/", url))?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let url = match urls.first() {
+            Some(url) => url.clone(),
+            None => bail!("lfs.url is empty"),
+        };
 
         let move_after_upload = config.get_or("lfs", "moveafterupload", || false)?;
         let ignore_prefetch_errors = config.get_or("lfs", "ignore-prefetch-errors", || false)?;
 
         if url.scheme() == "file" {
+            if urls.len() > 1 {
+                bail!("Unsupported url: {} (file urls cannot have fallbacks)", url);
+            }
             let path = url.to_file_path().unwrap();
             create_dir(&path)?;
             let file = LfsBlobsStore::loose(path);
@@ -1590,8 +1758,10 @@ impl LfsRemote {
                 remote: LfsRemoteInner::File(file),
             })
         } else {
-            if !["http", "https"].contains(&url.scheme()) {
-                bail!("Unsupported url: {}", url);
+            for url in urls.iter() {
+                if !["http", "https"].contains(&url.scheme()) {
+                    bail!("Unsupported url: {}", url);
+                }
             }
 
             let use_client_certs = config.get_or("lfs", "use-client-certs", || true)?;
@@ -1608,7 +1778,15 @@ impl LfsRemote {
                 format!("EdenSCM/{}", ::version::VERSION)
             })?;
 
-            let concurrent_fetches = config.get_or("lfs", "concurrentfetches", || 4)?;
+            // Either a single value for all the endpoints, or one value per endpoint.
+            let concurrent_fetches: Vec<usize> =
+                config.get_or("lfs", "concurrentfetches", || vec![4])?;
+
+            let unhealthy_timeout = Duration::from_millis(config.get_or(
+                "lfs",
+                "unhealthy-endpoint-timeout",
+                || 60_000,
+            )?);
 
             let backoff_times = config.get_or("lfs", "backofftimes", || vec![1f32, 4f32, 8f32])?;
 
@@ -1659,9 +1837,19 @@ impl LfsRemote {
                 move_after_upload,
                 ignore_prefetch_errors,
                 remote: LfsRemoteInner::Http(HttpLfsRemote {
-                    url,
+                    endpoints: urls
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, url)| LfsEndpoint {
+                            url,
+                            concurrent_fetches: concurrent_fetches
+                                .get(index)
+                                .or_else(|| concurrent_fetches.last())
+                                .map_or(4, |n| (*n).max(1)),
+                            unhealthy_until: Mutex::new(None),
+                        })
+                        .collect(),
                     client: Arc::new(client),
-                    concurrent_fetches,
                     download_chunk_size,
                     http_options: Arc::new(HttpOptions {
                         accept_zstd,
@@ -1674,6 +1862,7 @@ impl LfsRemote {
                         request_timeout,
                         missing_client_certs,
                     }),
+                    unhealthy_timeout,
                 }),
             })
         }
@@ -1694,8 +1883,87 @@ impl LfsRemote {
         read_from_store: impl Fn(Sha256, u64) -> Result<Option<Bytes>> + Send + Clone + 'static,
         error_handler: impl FnMut(Sha256, Error),
     ) -> Result<()> {
+        let total = objs.iter().map(|(_, size)| *size as u64).sum();
+        let progress = ProgressBar::register_new("uploading", total, "bytes");
+        progress.set_message("LFS".to_string());
         self.remote
-            .batch_upload(objs, read_from_store, error_handler)
+            .batch_upload(objs, read_from_store, error_handler, progress.clone())?;
+        // Blobs that the server already had were not uploaded.
+        progress.set_position(total);
+        Ok(())
+    }
+
+    /// Upload the blobs of the LFS files in `keys` from the local store to the server.
+    ///
+    /// Returns the keys that were not found in the local store and thus not uploaded.
+    pub(crate) fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        let local_store = match self.local.as_ref() {
+            None => return Ok(keys.to_vec()),
+            Some(local) => local,
+        };
+
+        let mut not_found = Vec::new();
+
+        let objs = keys
+            .iter()
+            .map(|k| {
+                if let Some(pointer) = local_store.pointers.read().entry(k)? {
+                    match pointer.content_hashes.get(&ContentHashType::Sha256) {
+                        None => Ok(None),
+                        Some(content_hash) => Ok(Some((
+                            content_hash.clone().unwrap_sha256(),
+                            pointer.size.try_into()?,
+                        ))),
+                    }
+                } else {
+                    not_found.push(k.clone());
+                    Ok(None)
+                }
+            })
+            .filter_map(|res| res.transpose())
+            .collect::<Result<HashSet<_>>>()?;
+
+        if !objs.is_empty() {
+            let span = info_span!("LfsRemote::upload", num_blobs = objs.len(), size = &0);
+            let _guard = span.enter();
+
+            let size = Arc::new(AtomicUsize::new(0));
+
+            self.batch_upload(
+                &objs,
+                {
+                    let local_store = local_store.clone();
+                    let size = size.clone();
+                    move |sha256, _size| {
+                        let key = StoreKey::from(ContentHash::Sha256(sha256));
+
+                        match local_store.blob(key)? {
+                            StoreResult::Found(blob) => {
+                                size.fetch_add(blob.len(), Ordering::Relaxed);
+                                Ok(Some(blob))
+                            }
+                            StoreResult::NotFound(_) => Ok(None),
+                        }
+                    }
+                },
+                |_, _| {},
+            )?;
+
+            span.record("size", &size.load(Ordering::Relaxed));
+        }
+
+        if self.move_after_upload {
+            let span = info_span!("LfsRemote::move_after_upload");
+            let _guard = span.enter();
+            // All the blobs were successfully uploaded, we can move the blobs from the local store
+            // to the shared store. This is safe to do as blobs will never be collected from the
+            // server once uploaded.
+            for obj in objs {
+                move_blob(&obj.0, obj.1 as u64, local_store, &self.shared)?;
+            }
+        }
+
+        Ok(not_found)
     }
 }
 
@@ -1837,73 +2105,7 @@ impl RemoteDataStore for LfsRemoteStore {
     }
 
     fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
-        let local_store = match self.remote.local.as_ref() {
-            None => return Ok(keys.to_vec()),
-            Some(local) => local,
-        };
-
-        let mut not_found = Vec::new();
-
-        let objs = keys
-            .iter()
-            .map(|k| {
-                if let Some(pointer) = local_store.pointers.read().entry(k)? {
-                    match pointer.content_hashes.get(&ContentHashType::Sha256) {
-                        None => Ok(None),
-                        Some(content_hash) => Ok(Some((
-                            content_hash.clone().unwrap_sha256(),
-                            pointer.size.try_into()?,
-                        ))),
-                    }
-                } else {
-                    not_found.push(k.clone());
-                    Ok(None)
-                }
-            })
-            .filter_map(|res| res.transpose())
-            .collect::<Result<HashSet<_>>>()?;
-
-        if !objs.is_empty() {
-            let span = info_span!("LfsRemoteStore::upload", num_blobs = objs.len(), size = &0);
-            let _guard = span.enter();
-
-            let size = Arc::new(AtomicUsize::new(0));
-
-            self.remote.batch_upload(
-                &objs,
-                {
-                    let local_store = local_store.clone();
-                    let size = size.clone();
-                    move |sha256, _size| {
-                        let key = StoreKey::from(ContentHash::Sha256(sha256));
-
-                        match local_store.blob(key)? {
-                            StoreResult::Found(blob) => {
-                                size.fetch_add(blob.len(), Ordering::Relaxed);
-                                Ok(Some(blob))
-                            }
-                            StoreResult::NotFound(_) => Ok(None),
-                        }
-                    }
-                },
-                |_, _| {},
-            )?;
-
-            span.record("size", &size.load(Ordering::Relaxed));
-        }
-
-        if self.remote.move_after_upload {
-            let span = info_span!("LfsRemoteStore::move_after_upload");
-            let _guard = span.enter();
-            // All the blobs were successfully uploaded, we can move the blobs from the local store
-            // to the shared store. This is safe to do as blobs will never be collected from the
-            // server once uploaded.
-            for obj in objs {
-                move_blob(&obj.0, obj.1 as u64, local_store, &self.remote.shared)?;
-            }
-        }
-
-        Ok(not_found)
+        self.remote.upload(keys)
     }
 }
 
@@ -3036,6 +3238,100 @@ mod tests {
             Ok(())
         }
 
+        #[test]
+        fn test_lfs_endpoint_failover() -> Result<()> {
+            let _env_lock = crate::env_lock();
+
+            let blob1 = example_blob();
+            let blobs = vec![&blob1];
+            let _m1 = get_lfs_batch_mock(200, &blobs);
+            let _m2 = get_lfs_download_mock(200, &blob1);
+
+            let cachedir = TempDir::new()?;
+            let lfsdir = TempDir::new()?;
+            let mut config = make_lfs_config(&cachedir, "test_lfs_endpoint_failover");
+            // Nothing listens on port 1, the second url is the mock server.
+            let url = format!("http://127.0.0.1:1/repo, {}/repo", mockito::server_url());
+            config.set("lfs", "url", Some(url), &Default::default());
+            config.set("lfs", "backofftimes", Some(""), &Default::default());
+            config.set("lfs", "concurrentfetches", Some("2,8"), &Default::default());
+
+            let lfs = Arc::new(LfsStore::shared(&lfsdir, &config)?);
+            let remote = LfsRemote::new(lfs, None, &config, None)?;
+
+            let objs = [(blob1.sha, blob1.size)]
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>();
+            let out = Arc::new(Mutex::new(Vec::new()));
+            remote.batch_fetch(
+                &objs,
+                {
+                    let out = out.clone();
+                    move |sha256, blob| {
+                        out.lock().push((sha256, blob));
+                        Ok(())
+                    }
+                },
+                |_, _| {},
+            )?;
+            assert_eq!(*out.lock(), vec![(blob1.sha, blob1.content.clone())]);
+
+            let endpoints = match &remote.remote {
+                LfsRemoteInner::Http(http) => &http.endpoints,
+                LfsRemoteInner::File(_) => unreachable!(),
+            };
+            assert_eq!(endpoints.len(), 2);
+            assert!(!endpoints[0].is_healthy());
+            assert!(endpoints[1].is_healthy());
+            assert_eq!(endpoints[0].concurrent_fetches, 2);
+            assert_eq!(endpoints[1].concurrent_fetches, 8);
+
+            Ok(())
+        }
+
+        #[test]
+        fn test_lfs_endpoint_client_error_keeps_endpoint_healthy() -> Result<()> {
+            let _env_lock = crate::env_lock();
+
+            let blob1 = example_blob();
+            let blobs = vec![&blob1];
+            let _m1 = mock("POST", "/denied/objects/batch")
+                .with_status(403)
+                .create();
+            let _m2 = get_lfs_batch_mock(200, &blobs);
+            let _m3 = get_lfs_download_mock(200, &blob1);
+
+            let cachedir = TempDir::new()?;
+            let lfsdir = TempDir::new()?;
+            let mut config = make_lfs_config(&cachedir, "test_lfs_endpoint_client_error");
+            let url = format!(
+                "{}/denied, {}/repo",
+                mockito::server_url(),
+                mockito::server_url()
+            );
+            config.set("lfs", "url", Some(url), &Default::default());
+            config.set("lfs", "backofftimes", Some(""), &Default::default());
+
+            let lfs = Arc::new(LfsStore::shared(&lfsdir, &config)?);
+            let remote = LfsRemote::new(lfs, None, &config, None)?;
+
+            let objs = [(blob1.sha, blob1.size)]
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>();
+            remote.batch_fetch(&objs, |_, _| Ok(()), |_, _| {})?;
+
+            let endpoints = match &remote.remote {
+                LfsRemoteInner::Http(http) => &http.endpoints,
+                LfsRemoteInner::File(_) => unreachable!(),
+            };
+            assert!(endpoints[0].is_healthy());
+            assert!(endpoints[1].is_healthy());
+
+            Ok(())
+        }
+
         #[test]
         fn test_lfs_remote_datastore() -> Result<()> {
             let _env_lock = crate::env_lock();
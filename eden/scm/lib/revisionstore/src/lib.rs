@@ -122,25 +122,37 @@
 //! The produced stores must implement the `HgIdDataStore` trait.
 //!
 
+pub mod archive;
+mod cancel;
+mod compression;
 mod contentstore;
 mod dataindex;
+mod datastats;
+mod dualwrite;
 #[cfg(all(fbcode_build, target_os = "linux"))]
 mod facebook;
 mod fanouttable;
 mod fetch_logger;
+mod gc;
 mod historyindex;
 mod indexedloghistorystore;
 mod indexedlogutil;
+mod layout;
 mod lfs;
 mod memcache;
 mod metadatastore;
 mod missing;
+mod negativecache;
+mod pinstore;
+mod priority;
+mod readonlystore;
 mod redacted;
 mod remotestore;
 mod repack;
 mod sliceext;
 mod types;
 mod unionstore;
+mod verify;
 
 pub mod datapack;
 pub mod datastore;
@@ -150,6 +162,7 @@ pub mod historypack;
 pub mod historystore;
 pub mod indexedlogauxstore;
 pub mod indexedlogdatastore;
+pub mod indexedlogtreeauxstore;
 pub mod localstore;
 pub mod multiplexstore;
 pub mod mutabledatapack;
@@ -159,17 +172,24 @@ pub mod packstore;
 pub mod packwriter;
 pub mod scmstore;
 pub mod trait_impls;
+pub mod treepagestore;
 pub mod uniondatastore;
 pub mod unionhistorystore;
 pub mod util;
 
 pub use revisionstore_types::*;
 
+pub use crate::cancel::CancellationToken;
+pub use crate::compression::Compression;
+pub use crate::compression::CompressionCodec;
+pub use crate::compression::Dictionary;
 pub use crate::contentstore::ContentStore;
 pub use crate::contentstore::ContentStoreBuilder;
 pub use crate::datapack::DataEntry;
 pub use crate::datapack::DataPack;
 pub use crate::datapack::DataPackVersion;
+pub use crate::datastats::DataEntryStats;
+pub use crate::datastats::DataStoreStats;
 pub use crate::datastore::ContentDataStore;
 pub use crate::datastore::ContentMetadata;
 pub use crate::datastore::Delta;
@@ -181,6 +201,9 @@ pub use crate::datastore::StoreResult;
 pub use crate::edenapi::EdenApiFileStore;
 pub use crate::edenapi::EdenApiRemoteStore;
 pub use crate::edenapi::EdenApiTreeStore;
+pub use crate::gc::gc;
+pub use crate::gc::GcOptions;
+pub use crate::gc::GcStats;
 pub use crate::historypack::HistoryEntry;
 pub use crate::historypack::HistoryPack;
 pub use crate::historypack::HistoryPackVersion;
@@ -191,9 +214,12 @@ pub use crate::indexedlogauxstore::AuxStore;
 pub use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
 pub use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
 pub use crate::indexedloghistorystore::IndexedLogHgIdHistoryStore;
+pub use crate::indexedlogtreeauxstore::TreeAuxStore;
 pub use crate::indexedlogutil::StoreType;
+pub use crate::layout::CacheLayout;
 pub use crate::localstore::ExtStoredPolicy;
 pub use crate::localstore::LocalStore;
+pub use crate::memcache::MemcacheMetrics;
 pub use crate::memcache::MemcacheStore;
 pub use crate::metadatastore::MetadataStore;
 pub use crate::metadatastore::MetadataStoreBuilder;
@@ -206,6 +232,8 @@ pub use crate::packstore::DataPackStore;
 pub use crate::packstore::HistoryPackStore;
 pub use crate::packstore::MutableDataPackStore;
 pub use crate::packstore::MutableHistoryPackStore;
+pub use crate::pinstore::PinStore;
+pub use crate::priority::FetchPriority;
 pub use crate::redacted::redact_if_needed;
 pub use crate::remotestore::HgIdRemoteStore;
 pub use crate::repack::repack;
@@ -216,6 +244,7 @@ pub use crate::repack::ToKeys;
 pub use crate::types::ContentHash;
 pub use crate::types::StoreKey;
 pub use crate::uniondatastore::UnionHgIdDataStore;
+pub use crate::unionhistorystore::UnionHgIdHistoryStore;
 pub use crate::util::Error;
 
 #[cfg(any(test, feature = "for-tests"))]
@@ -46,4 +46,8 @@ impl<T: LocalStore + ?Sized, U: Deref<Target = T> + Send + Sync> LocalStore for
     fn get_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         T::get_missing(self, keys)
     }
+
+    fn contains(&self, key: &StoreKey) -> Result<bool> {
+        T::contains(self, key)
+    }
 }
@@ -7,16 +7,26 @@
 
 //! Adapters around Memcache to be transparently used as HgIdDataStore or HgIdHistoryStore.
 
+use std::collections::HashSet;
 use std::mem::size_of;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
 use anyhow::Result;
+use configparser::config::ConfigSet;
+use crossbeam::channel::bounded;
+use crossbeam::channel::Sender;
 use minibytes::Bytes;
+use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use thiserror::Error;
 use tracing::info_span;
 use types::Key;
 use types::NodeInfo;
@@ -31,11 +41,12 @@ use crate::historystore::HgIdHistoryStore;
 use crate::historystore::HgIdMutableHistoryStore;
 use crate::historystore::RemoteHistoryStore;
 use crate::localstore::LocalStore;
+use crate::scmstore::FileAuxData;
 use crate::types::StoreKey;
 
 /// Type of blobs stored in Memcache.
 ///
-/// Whenever this type is changed, the `CODE_VERSION` value must be incremented to avoid
+/// Whenever this type is changed, `MC_FORMAT_VERSION` must be incremented to avoid
 /// incompatibilities.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub(crate) struct McData {
@@ -47,7 +58,7 @@ pub(crate) struct McData {
 
 /// Type of history info stored in Memcache.
 ///
-/// Whenever this type is changed, the `CODE_VERSION` value must be incremented to avoid
+/// Whenever this type is changed, `MC_FORMAT_VERSION` must be incremented to avoid
 /// incompatibilities.
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub(crate) struct McHist {
@@ -57,6 +68,99 @@ pub(crate) struct McHist {
     pub nodeinfo: NodeInfo,
 }
 
+/// Type of file aux data stored in Memcache.
+///
+/// Whenever this type is changed, `MC_FORMAT_VERSION` must be incremented to avoid
+/// incompatibilities.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct McAux {
+    #[serde(with = "types::serde_with::key::tuple")]
+    pub key: Key,
+    pub aux: FileAuxData,
+}
+
+/// Version of the serialization of `McData`, `McHist` and `McAux`.
+///
+/// The version is part of the memcache keys, so entries written in another format are never read
+/// instead of failing to decode, and both formats can be used at the same time during a rollout.
+pub(crate) const MC_FORMAT_VERSION: u32 = 1;
+
+// The memcache clients derive their keys with `mc_key` and (de)serialize entries with
+// `mc_encode` and `mc_decode`, so that the format version and decode errors are handled the same
+// way by all of them.
+
+/// Memcache key of the `kind` ("data", "hist" or "aux") entry of `key`.
+#[cfg_attr(not(all(fbcode_build, target_os = "linux")), allow(dead_code))]
+pub(crate) fn mc_key(prefix: &str, kind: &str, key: &Key) -> String {
+    format!(
+        "{}:{}:v{}:{}",
+        prefix,
+        kind,
+        MC_FORMAT_VERSION,
+        key.hgid.to_hex()
+    )
+}
+
+/// A memcache entry which could not be decoded, for instance because it was written by a client
+/// using the same format version with a different serialization.
+#[derive(Debug, Error)]
+#[error("failed to decode memcache entry for {key}")]
+pub(crate) struct McDecodeError {
+    pub key: Key,
+    #[source]
+    pub source: anyhow::Error,
+}
+
+/// Serialize an entry to be stored under `mc_key`.
+#[cfg_attr(not(all(fbcode_build, target_os = "linux")), allow(dead_code))]
+pub(crate) fn mc_encode<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+    Ok(mincode::serialize(value)?)
+}
+
+/// Deserialize the entry fetched for `key`. Failures are returned as `McDecodeError`, so that
+/// `record_fetch_error` quarantines the key.
+#[cfg_attr(not(all(fbcode_build, target_os = "linux")), allow(dead_code))]
+pub(crate) fn mc_decode<T: DeserializeOwned>(key: &Key, bytes: &[u8]) -> Result<T> {
+    mincode::deserialize(bytes).map_err(|err| {
+        McDecodeError {
+            key: key.clone(),
+            source: err.into(),
+        }
+        .into()
+    })
+}
+
+/// Keys whose memcache entry failed to decode. They are not requested from memcache again by
+/// this process, so they are always fetched from the next store layer.
+static QUARANTINE: Lazy<Mutex<HashSet<Key>>> = Lazy::new(Default::default);
+
+/// Record a memcache fetch error. Returns whether it is a decode error, in which case the key is
+/// quarantined and should be fetched from the next store layer instead of failing the fetch.
+pub(crate) fn record_fetch_error(err: &anyhow::Error) -> bool {
+    hg_metrics::increment_counter("scmstore.memcache.fetch_error", 1);
+    match err.downcast_ref::<McDecodeError>() {
+        Some(err) => {
+            hg_metrics::increment_counter("scmstore.memcache.decode_error", 1);
+            if QUARANTINE.lock().insert(err.key.clone()) {
+                hg_metrics::increment_counter("scmstore.memcache.quarantined", 1);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Remove the quarantined keys, which should not be requested from memcache.
+pub(crate) fn without_quarantined(mut keys: Vec<Key>) -> Vec<Key> {
+    let quarantine = QUARANTINE.lock();
+    if !quarantine.is_empty() {
+        let len = keys.len();
+        keys.retain(|key| !quarantine.contains(key));
+        hg_metrics::increment_counter("scmstore.memcache.quarantine_skipped", len - keys.len());
+    }
+    keys
+}
+
 #[cfg(not(all(fbcode_build, target_os = "linux")))]
 mod dummy {
     use std::iter::empty;
@@ -91,6 +195,8 @@ mod dummy {
         }
 
         pub(super) fn add_hist(&self, _key: &Key, _info: &NodeInfo) {}
+
+        pub(crate) fn add_mcaux(&self, _mcaux: McAux) {}
     }
 }
 
@@ -182,6 +288,216 @@ impl MemcacheStore {
     }
 }
 
+/// Read and write statistics of memcache, see `MemcacheStore::metrics`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemcacheMetrics {
+    pub hits: usize,
+    pub misses: usize,
+    pub errors: usize,
+    pub writes: usize,
+    /// Fetches and writes skipped because the circuit breaker was open.
+    pub skipped: usize,
+    /// Number of times the circuit breaker was opened.
+    pub trips: usize,
+    /// Whether the circuit breaker is currently open.
+    pub open: bool,
+}
+
+/// Stops using memcache after `threshold` consecutive failed fetches, and tries it again after
+/// `backoff`. A single failure after the backoff opens the breaker again.
+struct CircuitBreaker {
+    threshold: usize,
+    backoff: Duration,
+    consecutive_failures: usize,
+    open_until: Option<Instant>,
+    metrics: MemcacheMetrics,
+}
+
+impl CircuitBreaker {
+    fn is_open(&mut self) -> bool {
+        match self.open_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // Half-open: let requests through until the next failure.
+                self.open_until = None;
+                self.consecutive_failures = self.threshold.saturating_sub(1);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn record(&mut self, failed: bool) {
+        if !failed {
+            self.consecutive_failures = 0;
+            return;
+        }
+        self.consecutive_failures += 1;
+        if self.threshold > 0 && self.consecutive_failures >= self.threshold {
+            tracing::warn!(
+                "memcache failed {} times in a row, not using it for {:?}",
+                self.consecutive_failures,
+                self.backoff
+            );
+            hg_metrics::increment_counter("scmstore.memcache.breaker.trips", 1);
+            self.open_until = Some(Instant::now() + self.backoff);
+            self.consecutive_failures = 0;
+            self.metrics.trips += 1;
+        }
+    }
+}
+
+/// Memcache is a service shared by all the stores of the process, so is its health.
+static BREAKER: Lazy<Mutex<CircuitBreaker>> = Lazy::new(|| {
+    Mutex::new(CircuitBreaker {
+        threshold: 5,
+        backoff: Duration::from_secs(30),
+        consecutive_failures: 0,
+        open_until: None,
+        metrics: MemcacheMetrics::default(),
+    })
+});
+
+impl MemcacheStore {
+    /// Configure the circuit breaker with `scmstore.memcache-breaker-threshold` consecutive
+    /// failures (0 disables it) and `scmstore.memcache-breaker-backoff` seconds.
+    pub fn configure_circuit_breaker(&self, config: &ConfigSet) -> Result<()> {
+        let threshold = config.get_opt::<usize>("scmstore", "memcache-breaker-threshold")?;
+        let backoff = config.get_opt::<u64>("scmstore", "memcache-breaker-backoff")?;
+        let mut breaker = BREAKER.lock();
+        if let Some(threshold) = threshold {
+            breaker.threshold = threshold;
+        }
+        if let Some(backoff) = backoff {
+            breaker.backoff = Duration::from_secs(backoff);
+        }
+        Ok(())
+    }
+
+    /// Whether memcache should be used, or skipped because the circuit breaker is open.
+    pub(crate) fn available(&self) -> bool {
+        let mut breaker = BREAKER.lock();
+        let open = breaker.is_open();
+        if open {
+            breaker.metrics.skipped += 1;
+            hg_metrics::increment_counter("scmstore.memcache.breaker.skipped", 1);
+        }
+        !open
+    }
+
+    /// Record a fetch of `requested` keys, which returned `hits` entries and `errors` errors,
+    /// excluding decode errors. A fetch with errors counts as a failure for the circuit breaker.
+    pub(crate) fn record_fetch(&self, requested: usize, hits: usize, errors: usize) {
+        let mut breaker = BREAKER.lock();
+        breaker.metrics.hits += hits;
+        breaker.metrics.misses += requested.saturating_sub(hits);
+        breaker.metrics.errors += errors;
+        breaker.record(errors > 0);
+    }
+
+    pub(crate) fn record_write(&self) {
+        BREAKER.lock().metrics.writes += 1;
+    }
+
+    pub fn metrics(&self) -> MemcacheMetrics {
+        let breaker = BREAKER.lock();
+        let open = matches!(breaker.open_until, Some(until) if Instant::now() < until);
+        MemcacheMetrics {
+            open,
+            ..breaker.metrics.clone()
+        }
+    }
+}
+
+/// An entry written to memcache by `MemcacheWriteThrough`.
+pub(crate) enum McWrite {
+    Data(McData),
+    Aux(McAux),
+}
+
+impl McWrite {
+    fn apply(self, memcache: &MemcacheStore) {
+        if memcache.available() {
+            match self {
+                McWrite::Data(mcdata) => memcache.add_mcdata(mcdata),
+                McWrite::Aux(mcaux) => memcache.add_mcaux(mcaux),
+            }
+            memcache.record_write();
+        }
+    }
+}
+
+/// A bounded queue of items handled in order by a background thread.
+struct BackgroundQueue<T> {
+    sender: Sender<T>,
+}
+
+impl<T: Send + 'static> BackgroundQueue<T> {
+    fn spawn(name: &str, size: usize, handle: impl Fn(T) + Send + 'static) -> Result<Self> {
+        let (sender, receiver) = bounded::<T>(size);
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                for item in receiver {
+                    handle(item);
+                }
+            })?;
+        Ok(BackgroundQueue { sender })
+    }
+
+    /// Queue `item`, or give it back if the queue is full.
+    fn try_push(&self, item: T) -> Result<(), T> {
+        self.sender.try_send(item).map_err(|err| err.into_inner())
+    }
+}
+
+/// The write-through queue is shared by all the stores of the process, and its thread is only
+/// started by the first write. Its size is set by the first store to write.
+static WRITETHROUGH_QUEUE: OnceCell<BackgroundQueue<(Arc<MemcacheStore>, McWrite)>> =
+    OnceCell::new();
+
+/// Writes data to memcache from a background thread, so that populating memcache after a remote
+/// fetch doesn't delay the fetch itself. When the queue is full, or the thread can't be started,
+/// the data is written synchronously instead, as it would be without write-through.
+pub(crate) struct MemcacheWriteThrough {
+    memcache: Arc<MemcacheStore>,
+    queue_size: usize,
+}
+
+impl MemcacheWriteThrough {
+    pub(crate) fn new(memcache: Arc<MemcacheStore>, queue_size: usize) -> Self {
+        MemcacheWriteThrough {
+            memcache,
+            queue_size,
+        }
+    }
+
+    pub(crate) fn write(&self, write: McWrite) {
+        let queue = WRITETHROUGH_QUEUE.get_or_try_init(|| {
+            BackgroundQueue::spawn(
+                "memcache-writethrough",
+                self.queue_size,
+                |(memcache, write): (Arc<MemcacheStore>, McWrite)| write.apply(&memcache),
+            )
+        });
+        let write = match queue {
+            Ok(queue) => match queue.try_push((self.memcache.clone(), write)) {
+                Ok(()) => {
+                    hg_metrics::increment_counter("scmstore.memcache.writethrough.queued", 1);
+                    return;
+                }
+                Err((_, write)) => write,
+            },
+            Err(err) => {
+                tracing::warn!(%err, "cannot start the memcache write-through thread");
+                write
+            }
+        };
+        hg_metrics::increment_counter("scmstore.memcache.writethrough.inline", 1);
+        write.apply(&self.memcache);
+    }
+}
+
 struct MemcacheHgIdDataStore {
     store: Arc<dyn HgIdMutableDeltaStore>,
     memcache: Arc<MemcacheStore>,
@@ -229,8 +545,9 @@ impl HgIdDataStore for MemcacheHgIdDataStore {
 
 impl HgIdMutableDeltaStore for MemcacheHgIdDataStore {
     fn add(&self, delta: &Delta, metadata: &Metadata) -> Result<()> {
-        if self.use_memcache() {
+        if self.use_memcache() && self.memcache.available() {
             self.memcache.add_data(delta, metadata);
+            self.memcache.record_write();
         }
         Ok(())
     }
@@ -248,7 +565,7 @@ impl LocalStore for MemcacheHgIdDataStore {
 
 impl RemoteDataStore for MemcacheHgIdDataStore {
     fn prefetch(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
-        if !self.use_memcache() {
+        if !self.use_memcache() || !self.memcache.available() {
             return self.store.get_missing(keys);
         }
 
@@ -270,8 +587,15 @@ impl RemoteDataStore for MemcacheHgIdDataStore {
                 StoreKey::Content(_, _) => None,
             })
             .collect::<Vec<_>>();
+        let hgidkeys = without_quarantined(hgidkeys);
 
+        let mut errors = 0;
         for mcdata in self.memcache.get_data_iter(&hgidkeys)? {
+            if let Err(ref err) = mcdata {
+                if !record_fetch_error(err) {
+                    errors += 1;
+                }
+            }
             if let Ok(mcdata) = mcdata {
                 let metadata = mcdata.metadata;
                 let delta = Delta {
@@ -287,6 +611,7 @@ impl RemoteDataStore for MemcacheHgIdDataStore {
             }
         }
 
+        self.memcache.record_fetch(hgidkeys.len(), hits, errors);
         span.record("hit_count", &hits);
         span.record("size", &size);
 
@@ -333,8 +658,9 @@ impl HgIdHistoryStore for MemcacheHgIdHistoryStore {
 
 impl HgIdMutableHistoryStore for MemcacheHgIdHistoryStore {
     fn add(&self, key: &Key, info: &NodeInfo) -> Result<()> {
-        if self.use_memcache() {
+        if self.use_memcache() && self.memcache.available() {
             self.memcache.add_hist(key, info);
+            self.memcache.record_write();
         }
         Ok(())
     }
@@ -352,7 +678,7 @@ impl LocalStore for MemcacheHgIdHistoryStore {
 
 impl RemoteHistoryStore for MemcacheHgIdHistoryStore {
     fn prefetch(&self, keys: &[StoreKey]) -> Result<()> {
-        if !self.use_memcache() {
+        if !self.use_memcache() || !self.memcache.available() {
             return Ok(());
         }
 
@@ -371,11 +697,18 @@ impl RemoteHistoryStore for MemcacheHgIdHistoryStore {
                 StoreKey::Content(_, _) => None,
             })
             .collect::<Vec<_>>();
+        let keys = without_quarantined(keys);
 
         let mut hits = 0;
         let mut size = 0;
 
+        let mut errors = 0;
         for mchist in self.memcache.get_hist_iter(&keys)? {
+            if let Err(ref err) = mchist {
+                if !record_fetch_error(err) {
+                    errors += 1;
+                }
+            }
             if let Ok(mchist) = mchist {
                 self.store.add(&mchist.key, &mchist.nodeinfo)?;
 
@@ -384,9 +717,112 @@ impl RemoteHistoryStore for MemcacheHgIdHistoryStore {
             }
         }
 
+        self.memcache.record_fetch(keys.len(), hits, errors);
         span.record("hit_count", &hits);
         span.record("size", &size);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crossbeam::channel::unbounded;
+    use types::testutil::key;
+
+    use super::*;
+
+    #[test]
+    fn test_mc_key() {
+        let key = key("a", "1");
+        assert_eq!(
+            mc_key("hg", "data", &key),
+            format!("hg:data:v{}:{}", MC_FORMAT_VERSION, key.hgid.to_hex())
+        );
+    }
+
+    #[test]
+    fn test_mc_decode() -> Result<()> {
+        let mcdata = McData {
+            key: key("a", "2"),
+            data: Bytes::from_static(b"content"),
+            metadata: Default::default(),
+        };
+        let bytes = mc_encode(&mcdata)?;
+        assert_eq!(mc_decode::<McData>(&mcdata.key, &bytes)?, mcdata);
+
+        // An entry in another format fails to decode as a McDecodeError.
+        let err = mc_decode::<McData>(&mcdata.key, &bytes[..3]).unwrap_err();
+        assert!(err.is::<McDecodeError>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_error_quarantine() {
+        let quarantined = key("a", "3");
+        let other = key("a", "4");
+
+        // Other errors fail the fetch and don't quarantine the key.
+        assert!(!record_fetch_error(&anyhow::anyhow!("timeout")));
+        assert_eq!(
+            without_quarantined(vec![quarantined.clone(), other.clone()]).len(),
+            2
+        );
+
+        let err = mc_decode::<McHist>(&quarantined, b"").unwrap_err();
+        assert!(record_fetch_error(&err));
+        assert_eq!(
+            without_quarantined(vec![quarantined.clone(), other.clone()]),
+            vec![other]
+        );
+    }
+
+    #[test]
+    fn test_background_queue() -> Result<()> {
+        let (started_tx, started_rx) = unbounded();
+        let (resume_tx, resume_rx) = unbounded::<()>();
+        let (done_tx, done_rx) = unbounded();
+        let queue = BackgroundQueue::spawn("test", 1, move |item: u32| {
+            started_tx.send(item).unwrap();
+            resume_rx.recv().unwrap();
+            done_tx.send(item).unwrap();
+        })?;
+
+        // The thread is busy with the first item, the second one fills the queue.
+        assert_eq!(queue.try_push(1), Ok(()));
+        assert_eq!(started_rx.recv()?, 1);
+        assert_eq!(queue.try_push(2), Ok(()));
+        assert_eq!(queue.try_push(3), Err(3));
+
+        resume_tx.send(())?;
+        resume_tx.send(())?;
+        assert_eq!(done_rx.recv()?, 1);
+        assert_eq!(done_rx.recv()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut breaker = CircuitBreaker {
+            threshold: 2,
+            backoff: Duration::from_secs(3600),
+            consecutive_failures: 0,
+            open_until: None,
+            metrics: MemcacheMetrics::default(),
+        };
+        breaker.record(true);
+        breaker.record(false);
+        breaker.record(true);
+        assert!(!breaker.is_open());
+        breaker.record(true);
+        assert!(breaker.is_open());
+        assert_eq!(breaker.metrics.trips, 1);
+
+        // After the backoff, a single failure opens the breaker again.
+        breaker.open_until = Some(Instant::now());
+        assert!(!breaker.is_open());
+        breaker.record(true);
+        assert!(breaker.is_open());
+        assert_eq!(breaker.metrics.trips, 2);
+    }
+}
@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::HashSet;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -13,28 +14,38 @@ use anyhow::format_err;
 use anyhow::Result;
 use configparser::config::ConfigSet;
 use configparser::convert::ByteCount;
+use types::HgId;
 use types::Key;
 use types::NodeInfo;
+use types::RepoPathBuf;
 
 use crate::historystore::HgIdHistoryStore;
 use crate::historystore::HgIdMutableHistoryStore;
 use crate::historystore::RemoteHistoryStore;
+use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
+use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
 use crate::indexedloghistorystore::IndexedLogHgIdHistoryStore;
 use crate::indexedlogutil::StoreType;
+use crate::localstore::ExtStoredPolicy;
 use crate::localstore::LocalStore;
 use crate::memcache::MemcacheStore;
 use crate::multiplexstore::MultiplexHgIdHistoryStore;
 use crate::packstore::CorruptionPolicy;
+use crate::packstore::HistoryPackStore;
 use crate::packstore::MutableHistoryPackStore;
+use crate::readonlystore::ReadOnlyStore;
 use crate::remotestore::HgIdRemoteStore;
 use crate::repack::RepackLocation;
 use crate::types::StoreKey;
 use crate::unionhistorystore::UnionHgIdHistoryStore;
-use crate::util::get_cache_packs_path;
-use crate::util::get_cache_path;
+use crate::util::get_existing_cache_packs_path;
+use crate::util::get_existing_cache_path;
+use crate::util::get_indexedlogdatastore_path;
 use crate::util::get_indexedloghistorystore_path;
 use crate::util::get_local_path;
 use crate::util::get_packs_path;
+use crate::verify::recover_rewrite;
+use crate::CacheLayout;
 
 /// A `MetadataStore` aggregate all the local and remote stores and expose them as one. Both local and
 /// remote stores can be queried and accessed via the `HgIdHistoryStore` trait. The local store can also
@@ -56,6 +67,11 @@ impl MetadataStore {
 
     /// Attempt to repair the underlying stores that the `MetadataStore` is comprised of.
     ///
+    /// With `verify`, the hgid of every indexedlog entry is also recomputed from the content in the
+    /// matching data store, and entries that do not match are quarantined along with their content.
+    /// See [`crate::verify`]. This must run before `ContentStore::repair`, whose verification
+    /// reads the history stores.
+    ///
     /// As this may violate some of the stores asumptions, care must be taken to call this only
     /// when no other `MetadataStore` have been created for the `shared_path`.
     pub fn repair(
@@ -63,6 +79,7 @@ impl MetadataStore {
         local_path: Option<impl AsRef<Path>>,
         suffix: Option<impl AsRef<Path>>,
         config: &ConfigSet,
+        verify: bool,
     ) -> Result<String> {
         let mut repair_str = String::new();
         let mut shared_path = shared_path.as_ref().to_path_buf();
@@ -73,20 +90,82 @@ impl MetadataStore {
             .map(|p| get_local_path(p.as_ref().to_path_buf(), &suffix))
             .transpose()?;
 
-        repair_str += &IndexedLogHgIdHistoryStore::repair(
-            get_indexedloghistorystore_path(&shared_path)?,
-            config,
-            StoreType::Shared,
-        )?;
+        let mut paths = vec![(shared_path, StoreType::Shared)];
         if let Some(local_path) = local_path {
-            repair_str += &IndexedLogHgIdHistoryStore::repair(
-                get_indexedloghistorystore_path(local_path)?,
-                config,
-                StoreType::Local,
-            )?;
+            paths.push((local_path, StoreType::Local));
+        }
+        let data_config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: config.get_opt::<u8>("indexedlog", "data.max-log-count")?,
+            max_bytes_per_log: config
+                .get_opt::<ByteCount>("indexedlog", "data.max-bytes-per-log")?,
+            max_bytes: config.get_opt::<ByteCount>("remotefilelog", "cachelimit")?,
+        };
+        for (path, store_type) in paths {
+            let history_path = get_indexedloghistorystore_path(&path)?;
+            recover_rewrite(&history_path)?;
+            repair_str +=
+                &IndexedLogHgIdHistoryStore::repair(history_path.clone(), config, store_type)?;
+            if verify {
+                // LFS pointers are ignored since they cannot be verified.
+                let data_path = get_indexedlogdatastore_path(&path)?;
+                let data = IndexedLogHgIdDataStore::open_read_only(
+                    &data_path,
+                    ExtStoredPolicy::Ignore,
+                    &data_config,
+                    store_type,
+                );
+                let data = match data {
+                    Ok(data) => data,
+                    Err(err) => {
+                        repair_str +=
+                            &format!("Cannot verify {}: {}\n", history_path.display(), err);
+                        continue;
+                    }
+                };
+                let (summary, corrupt) =
+                    IndexedLogHgIdHistoryStore::verify(history_path, config, store_type, &data)?;
+                repair_str += &summary;
+                drop(data);
+                repair_str += &IndexedLogHgIdDataStore::remove_hgids(
+                    data_path,
+                    &data_config,
+                    store_type,
+                    &corrupt,
+                )?;
+            }
         }
         Ok(repair_str)
     }
+
+    /// Follow the copyfrom chain of `key`, as recorded by a first parent with a different path.
+    ///
+    /// Returns `key` followed by the source of each rename, most recent first. Tracing stops at
+    /// the first source whose path is in `stop_paths`, or when the history of the file ends.
+    /// Missing history is prefetched from the remote store, which fetches all the ancestors of
+    /// a key at once.
+    pub fn trace_rename(&self, key: &Key, stop_paths: &HashSet<RepoPathBuf>) -> Result<Vec<Key>> {
+        let mut chain = vec![key.clone()];
+        let mut seen = HashSet::new();
+        let mut current = key.clone();
+        while seen.insert(current.clone()) {
+            self.prefetch(&[StoreKey::hgid(current.clone())])?;
+            let info = self
+                .get_node_info(&current)?
+                .ok_or_else(|| format_err!("missing history for {}", current))?;
+            let p1 = info.parents[0].clone();
+            if p1.hgid.is_null() {
+                break;
+            }
+            if p1.path != current.path {
+                chain.push(p1.clone());
+                if stop_paths.contains(&p1.path) {
+                    break;
+                }
+            }
+            current = p1;
+        }
+        Ok(chain)
+    }
 }
 
 // Repack specific methods, not to be used directly but by the repack code.
@@ -123,6 +202,14 @@ impl HgIdHistoryStore for MetadataStore {
     fn refresh(&self) -> Result<()> {
         self.historystore.refresh()
     }
+
+    fn get_node_info_batch(&self, keys: &[Key]) -> Result<Vec<Option<NodeInfo>>> {
+        self.historystore.get_node_info_batch(keys)
+    }
+
+    fn get_linknode_batch(&self, keys: &[Key]) -> Result<Vec<Option<HgId>>> {
+        self.historystore.get_linknode_batch(keys)
+    }
 }
 
 impl RemoteHistoryStore for MetadataStore {
@@ -181,6 +268,7 @@ pub struct MetadataStoreBuilder<'a> {
     remotestore: Option<Arc<dyn HgIdRemoteStore>>,
     suffix: Option<PathBuf>,
     memcachestore: Option<Arc<MemcacheStore>>,
+    read_only: bool,
 }
 
 impl<'a> MetadataStoreBuilder<'a> {
@@ -192,6 +280,7 @@ impl<'a> MetadataStoreBuilder<'a> {
             remotestore: None,
             suffix: None,
             memcachestore: None,
+            read_only: false,
         }
     }
 
@@ -225,13 +314,81 @@ impl<'a> MetadataStoreBuilder<'a> {
         self
     }
 
+    /// Open the existing stores without ever writing to disk: no directories, lock files or
+    /// mutable packs are created, and writes fail with `ReadOnlyStoreError`.
+    ///
+    /// Since fetched data couldn't be stored, the remote and memcache stores are not used.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    fn build_read_only(self) -> Result<MetadataStore> {
+        let mut historystore: UnionHgIdHistoryStore<Arc<dyn HgIdHistoryStore>> =
+            UnionHgIdHistoryStore::new();
+        let config = self.config;
+        let mut add_stores = |indexedlog_path: PathBuf, packs_path: PathBuf, store_type| {
+            if indexedlog_path.exists() {
+                historystore.add(Arc::new(IndexedLogHgIdHistoryStore::open_read_only(
+                    indexedlog_path,
+                    config,
+                    store_type,
+                )?));
+            }
+            if packs_path.exists() {
+                historystore.add(Arc::new(HistoryPackStore::new(
+                    packs_path,
+                    CorruptionPolicy::IGNORE,
+                    None,
+                )));
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+
+        let cache_path = get_existing_cache_path(self.config, &self.suffix)?;
+        add_stores(
+            cache_path.join("indexedloghistorystore"),
+            get_existing_cache_packs_path(self.config, &self.suffix)?,
+            StoreType::Shared,
+        )?;
+
+        if let Some(unsuffixed_local_path) = self.local_path {
+            let mut local_path = unsuffixed_local_path.clone();
+            let mut packs_path = unsuffixed_local_path.join("packs");
+            if let Some(suffix) = &self.suffix {
+                local_path.push(suffix);
+                packs_path.push(suffix);
+            }
+            add_stores(
+                local_path.join("indexedloghistorystore"),
+                packs_path,
+                StoreType::Local,
+            )?;
+        } else if !self.no_local_store {
+            return Err(format_err!(
+                "a MetadataStore cannot be built without a local store"
+            ));
+        }
+
+        Ok(MetadataStore {
+            historystore,
+            local_mutablehistorystore: Some(Arc::new(ReadOnlyStore)),
+            shared_mutablehistorystore: Arc::new(ReadOnlyStore),
+            remote_store: None,
+        })
+    }
+
     pub fn build(self) -> Result<MetadataStore> {
+        if self.read_only {
+            return self.build_read_only();
+        }
+
         let local_path = self
             .local_path
             .as_ref()
             .map(|p| get_local_path(p.clone(), &self.suffix))
             .transpose()?;
-        let cache_path = get_cache_path(self.config, &self.suffix)?;
+        let layout = CacheLayout::from_config(self.config, &self.suffix)?;
         let max_pending: u64 = self
             .config
             .get_or("packs", "maxhistorypending", || 10000000)?;
@@ -240,7 +397,7 @@ impl<'a> MetadataStoreBuilder<'a> {
             .get_opt::<ByteCount>("packs", "maxhistorybytes")?
             .map(|v| v.value());
 
-        let cache_packs_path = get_cache_packs_path(self.config, &self.suffix)?;
+        let cache_packs_path = layout.packs_path()?;
         let shared_pack_store = Arc::new(MutableHistoryPackStore::new(
             &cache_packs_path,
             CorruptionPolicy::REMOVE,
@@ -251,7 +408,7 @@ impl<'a> MetadataStoreBuilder<'a> {
             UnionHgIdHistoryStore::new();
 
         let shared_indexedloghistorystore = Arc::new(IndexedLogHgIdHistoryStore::new(
-            get_indexedloghistorystore_path(&cache_path)?,
+            layout.indexedloghistorystore_path()?,
             &self.config,
             StoreType::Shared,
         )?);
@@ -277,6 +434,15 @@ impl<'a> MetadataStoreBuilder<'a> {
                 shared_pack_store
             };
 
+        // While the cache is being relocated, the history of the previous root is still read.
+        if let Some(path) = layout.previous_indexedloghistorystore_path() {
+            historystore.add(Arc::new(IndexedLogHgIdHistoryStore::open_read_only(
+                path,
+                &self.config,
+                StoreType::Shared,
+            )?));
+        }
+
         let local_mutablehistorystore: Option<Arc<dyn HgIdMutableHistoryStore>> =
             if let Some(unsuffixed_local_path) = self.local_path {
                 let local_pack_store = Arc::new(MutableHistoryPackStore::new(
@@ -375,6 +541,7 @@ mod tests {
     use types::testutil::*;
 
     use super::*;
+    use crate::error::ReadOnlyStoreError;
     use crate::testutil::make_config;
     use crate::testutil::FakeHgIdRemoteStore;
 
@@ -407,6 +574,102 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_node_info_batch() -> Result<()> {
+        let cachedir = TempDir::new()?;
+        let localdir = TempDir::new()?;
+        let config = make_config(&cachedir);
+
+        let store = MetadataStore::new(&localdir, &config)?;
+
+        let local_key = key("a", "1");
+        let local_info = NodeInfo {
+            parents: [key("a", "2"), null_key("a")],
+            linknode: hgid("3"),
+        };
+        store.add(&local_key, &local_info)?;
+
+        let shared_key = key("b", "4");
+        let shared_info = NodeInfo {
+            parents: [key("b", "5"), null_key("b")],
+            linknode: hgid("6"),
+        };
+        store.get_shared_mutable().add(&shared_key, &shared_info)?;
+
+        let infos =
+            store.get_node_info_batch(&[shared_key.clone(), key("c", "7"), local_key.clone()])?;
+        assert_eq!(infos, vec![Some(shared_info), None, Some(local_info)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace_rename() -> Result<()> {
+        let cachedir = TempDir::new()?;
+        let localdir = TempDir::new()?;
+        let config = make_config(&cachedir);
+
+        // c@4 was copied from b@3, which was copied from a@1 after a@2 was committed.
+        let mut map = HashMap::new();
+        for (k, p1) in [
+            (key("c", "4"), key("b", "3")),
+            (key("b", "3"), key("a", "2")),
+            (key("a", "2"), key("a", "1")),
+            (key("a", "1"), null_key("a")),
+        ] {
+            let info = NodeInfo {
+                parents: [p1, null_key(&k.path.to_string())],
+                linknode: hgid("5"),
+            };
+            map.insert(k, info);
+        }
+        let mut remotestore = FakeHgIdRemoteStore::new();
+        remotestore.hist(map);
+
+        let store = MetadataStoreBuilder::new(&config)
+            .local_path(&localdir)
+            .remotestore(Arc::new(remotestore))
+            .build()?;
+
+        assert_eq!(
+            store.trace_rename(&key("c", "4"), &HashSet::new())?,
+            vec![key("c", "4"), key("b", "3"), key("a", "2")]
+        );
+        let stop_paths = [key("b", "3").path].into_iter().collect();
+        assert_eq!(
+            store.trace_rename(&key("c", "4"), &stop_paths)?,
+            vec![key("c", "4"), key("b", "3")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only() -> Result<()> {
+        let cachedir = TempDir::new()?;
+        let localdir = TempDir::new()?;
+        let config = make_config(&cachedir);
+
+        let k = key("a", "1");
+        let nodeinfo = NodeInfo {
+            parents: [key("a", "2"), null_key("a")],
+            linknode: hgid("3"),
+        };
+        {
+            let store = MetadataStore::new(&localdir, &config)?;
+            store.add(&k, &nodeinfo)?;
+            store.flush()?;
+        }
+
+        let store = MetadataStoreBuilder::new(&config)
+            .local_path(&localdir)
+            .read_only()
+            .build()?;
+        assert_eq!(store.get_node_info(&k)?, Some(nodeinfo.clone()));
+
+        let err = store.add(&k, &nodeinfo).unwrap_err();
+        assert!(err.is::<ReadOnlyStoreError>());
+        Ok(())
+    }
+
     #[test]
     fn test_add_dropped() -> Result<()> {
         let cachedir = TempDir::new()?;
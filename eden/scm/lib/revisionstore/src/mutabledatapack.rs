@@ -20,7 +20,6 @@ use anyhow::Error;
 use anyhow::Result;
 use byteorder::BigEndian;
 use byteorder::WriteBytesExt;
-use lz4_pyframe::compress;
 use mpatch::mpatch::get_full_text;
 use parking_lot::Mutex;
 use sha1::Digest;
@@ -31,6 +30,7 @@ use thiserror::Error;
 use types::HgId;
 use types::Key;
 
+use crate::compression::Compression;
 use crate::dataindex::DataIndex;
 use crate::dataindex::DeltaLocation;
 use crate::datapack::DataEntry;
@@ -51,11 +51,13 @@ struct MutableDataPackInner {
     data_file: PackWriter<NamedTempFile>,
     mem_index: HashMap<HgId, DeltaLocation>,
     hasher: Sha1,
+    compression: Compression,
 }
 
 pub struct MutableDataPack {
     dir: PathBuf,
     version: DataPackVersion,
+    compression: Compression,
     inner: Mutex<Option<MutableDataPackInner>>,
 }
 
@@ -70,7 +72,11 @@ impl MutableDataPackInner {
     /// when flush() is called, at which point the MutableDataPack is consumed. If
     /// flush() is not called, the temporary file is cleaned up when the object is
     /// release.
-    pub fn new(dir: impl AsRef<Path>, version: DataPackVersion) -> Result<Self> {
+    pub fn new(
+        dir: impl AsRef<Path>,
+        version: DataPackVersion,
+        compression: Compression,
+    ) -> Result<Self> {
         let dir = dir.as_ref();
         if !dir.is_dir() {
             return Err(format_err!(
@@ -95,6 +101,7 @@ impl MutableDataPackInner {
             data_file,
             mem_index: HashMap::new(),
             hasher,
+            compression,
         })
     }
 
@@ -136,7 +143,7 @@ impl MutableDataPackInner {
 
         let offset = self.data_file.bytes_written();
 
-        let compressed = compress(&delta.data)?;
+        let compressed = self.compression.compress(&delta.data)?;
 
         // Preallocate with approximately the size we need:
         // (namelen(2) + name + hgid(20) + hgid(20) + datalen(8) + data + metadata(~22))
@@ -176,16 +183,28 @@ impl MutableDataPack {
         Self {
             dir: dir.as_ref().to_path_buf(),
             version,
+            compression: Compression::default(),
             inner: Mutex::new(None),
         }
     }
 
+    /// Compress new entries with `compression` instead of lz4.
+    pub fn with_compression(mut self, compression: Compression) -> Result<Self> {
+        compression.open_store(&self.dir)?;
+        self.compression = compression;
+        Ok(self)
+    }
+
     fn get_pack<'a>(
         &self,
         inner: &'a mut Option<MutableDataPackInner>,
     ) -> Result<&'a mut MutableDataPackInner> {
         if inner.is_none() {
-            inner.replace(MutableDataPackInner::new(&self.dir, self.version.clone())?);
+            inner.replace(MutableDataPackInner::new(
+                &self.dir,
+                self.version.clone(),
+                self.compression.clone(),
+            )?);
         }
         Ok(inner.as_mut().unwrap())
     }
@@ -0,0 +1,275 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Persistent cache of keys recently confirmed to be absent from a remote store.
+//!
+//! Operations such as rebase and absorb repeatedly ask for the same missing keys.
+//! Remembering the negative answers for a short while avoids a network round-trip
+//! for each of them.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use configparser::config::ConfigSet;
+use configparser::convert::ByteCount;
+use indexedlog::log::IndexOutput;
+use parking_lot::RwLock;
+use tracing::warn;
+use types::HgId;
+use util::path::create_shared_dir;
+
+use crate::cancel::CancellationToken;
+use crate::datastore::HgIdDataStore;
+use crate::datastore::Metadata;
+use crate::datastore::RemoteDataStore;
+use crate::datastore::StoreResult;
+use crate::indexedlogutil::Store;
+use crate::indexedlogutil::StoreOpenOptions;
+use crate::localstore::LocalStore;
+use crate::priority::FetchPriority;
+use crate::types::StoreKey;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// An indexedlog of `(hgid, timestamp)` records, each recording that the remote
+/// store didn't have `hgid` at `timestamp`.
+pub struct NegativeCache {
+    log: RwLock<Store>,
+    ttl: Duration,
+}
+
+impl NegativeCache {
+    /// Open the negative cache at `path`. Returns `None` if the cache is disabled,
+    /// which is the case unless `remotefilelog.negativecache-ttl` is set. The
+    /// directory is only created when the cache is enabled.
+    pub fn new(path: impl AsRef<Path>, config: &ConfigSet) -> Result<Option<Self>> {
+        let ttl = match config.get_opt::<Duration>("remotefilelog", "negativecache-ttl")? {
+            Some(ttl) if !ttl.is_zero() => ttl,
+            _ => return Ok(None),
+        };
+        let path = path.as_ref();
+        create_shared_dir(path)?;
+        let log = NegativeCache::open_options(config)?.shared(path)?;
+        Ok(Some(NegativeCache {
+            log: RwLock::new(log),
+            ttl,
+        }))
+    }
+
+    fn open_options(config: &ConfigSet) -> Result<StoreOpenOptions> {
+        let max_bytes = config
+            .get_or("remotefilelog", "negativecache-max-bytes", || {
+                ByteCount::from(10 * 1024 * 1024)
+            })?
+            .value();
+        Ok(StoreOpenOptions::new()
+            .max_log_count(4)
+            .max_bytes_per_log((max_bytes / 4).max(1))
+            .auto_sync_threshold(1024 * 1024)
+            .create(true)
+            .index("node", |_| {
+                vec![IndexOutput::Reference(0..HgId::len() as u64)]
+            }))
+    }
+
+    /// Returns true if `hgid` was recorded as missing less than the TTL ago.
+    pub fn is_missing(&self, hgid: &HgId) -> Result<bool> {
+        let log = self.log.read();
+        let mut entries = log.lookup(0, hgid)?;
+        let slice = match entries.next() {
+            None => return Ok(false),
+            Some(slice) => slice?,
+        };
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&slice[HgId::len()..HgId::len() + 8]);
+        let age = now().saturating_sub(u64::from_be_bytes(timestamp));
+        Ok(age < self.ttl.as_secs())
+    }
+
+    /// Record that the remote store doesn't have `hgid`.
+    pub fn add_missing(&self, hgid: &HgId) -> Result<()> {
+        let mut buf = Vec::with_capacity(HgId::len() + 8);
+        buf.extend_from_slice(hgid.as_ref());
+        buf.extend_from_slice(&now().to_be_bytes());
+        self.log.write().append(buf)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.log.write().flush()
+    }
+}
+
+/// A `RemoteDataStore` that skips keys the wrapped store recently reported as
+/// missing, and records the keys it reports as missing.
+pub struct NegativeCacheRemoteDataStore {
+    remote: Arc<dyn RemoteDataStore>,
+    cache: Arc<NegativeCache>,
+}
+
+impl NegativeCacheRemoteDataStore {
+    pub fn new(
+        remote: Arc<dyn RemoteDataStore>,
+        cache: Arc<NegativeCache>,
+    ) -> Arc<dyn RemoteDataStore> {
+        Arc::new(NegativeCacheRemoteDataStore { remote, cache })
+    }
+
+    fn is_missing(&self, key: &StoreKey) -> bool {
+        match key {
+            // Errors reading the cache shouldn't prevent fetching from the remote.
+            StoreKey::HgId(key) => self.cache.is_missing(&key.hgid).unwrap_or(false),
+            StoreKey::Content(..) => false,
+        }
+    }
+}
+
+impl RemoteDataStore for NegativeCacheRemoteDataStore {
+    fn prefetch(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.prefetch_cancellable(keys, &CancellationToken::new())
+    }
+
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        self.prefetch_with_priority(keys, cancel, FetchPriority::Interactive)
+    }
+
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
+        let (mut missing, to_fetch): (Vec<_>, Vec<_>) =
+            keys.iter().cloned().partition(|key| self.is_missing(key));
+        if to_fetch.is_empty() {
+            return Ok(missing);
+        }
+
+        let not_found = self
+            .remote
+            .prefetch_with_priority(&to_fetch, cancel, priority)?;
+        for key in not_found.iter() {
+            if let StoreKey::HgId(key) = key {
+                // The negative cache is only an optimization, failing to update it
+                // shouldn't fail the fetch.
+                if let Err(err) = self.cache.add_missing(&key.hgid) {
+                    warn!(%err, %key, "cannot record missing key in negative cache");
+                }
+            }
+        }
+        missing.extend(not_found);
+        Ok(missing)
+    }
+
+    fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.remote.upload(keys)
+    }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        // The negative cache may be stale, always ask the server.
+        self.remote.get_remote_missing(keys)
+    }
+}
+
+impl HgIdDataStore for NegativeCacheRemoteDataStore {
+    fn get(&self, key: StoreKey) -> Result<StoreResult<Vec<u8>>> {
+        if self.is_missing(&key) {
+            return Ok(StoreResult::NotFound(key));
+        }
+        self.remote.get(key)
+    }
+
+    fn get_meta(&self, key: StoreKey) -> Result<StoreResult<Metadata>> {
+        if self.is_missing(&key) {
+            return Ok(StoreResult::NotFound(key));
+        }
+        self.remote.get_meta(key)
+    }
+
+    fn refresh(&self) -> Result<()> {
+        self.cache.flush()?;
+        self.remote.refresh()
+    }
+}
+
+impl LocalStore for NegativeCacheRemoteDataStore {
+    fn get_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.remote.get_missing(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use types::testutil::*;
+
+    use super::*;
+
+    fn config(ttl: &str) -> ConfigSet {
+        let mut config = ConfigSet::new();
+        config.set(
+            "remotefilelog",
+            "negativecache-ttl",
+            Some(ttl),
+            &Default::default(),
+        );
+        config
+    }
+
+    #[test]
+    fn test_disabled_by_default() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let path = tempdir.path().join("negativecache");
+        assert!(NegativeCache::new(&path, &ConfigSet::new())?.is_none());
+        assert!(NegativeCache::new(&path, &config("0"))?.is_none());
+        assert!(!path.exists());
+
+        assert!(NegativeCache::new(&path, &config("3600"))?.is_some());
+        assert!(path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_missing() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let cache = NegativeCache::new(&tempdir, &config("3600"))?.unwrap();
+        let missing = hgid("1");
+        assert!(!cache.is_missing(&missing)?);
+        cache.add_missing(&missing)?;
+        assert!(cache.is_missing(&missing)?);
+        assert!(!cache.is_missing(&hgid("2"))?);
+
+        cache.flush()?;
+        drop(cache);
+        let cache = NegativeCache::new(&tempdir, &config("3600"))?.unwrap();
+        assert!(cache.is_missing(&missing)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let cache = NegativeCache::new(&tempdir, &config("0.5"))?.unwrap();
+        let missing = hgid("1");
+        cache.add_missing(&missing)?;
+        // The TTL is rounded down to whole seconds, so entries expire immediately.
+        assert!(!cache.is_missing(&missing)?);
+        Ok(())
+    }
+}
@@ -22,9 +22,11 @@ use std::time::Instant;
 
 use anyhow::Result;
 use parking_lot::Mutex;
+use tracing::warn;
 use types::Key;
 use types::NodeInfo;
 
+use crate::compression::load_dictionaries;
 use crate::datapack::DataPack;
 use crate::datapack::DataPackVersion;
 use crate::datastore::Delta;
@@ -253,6 +255,9 @@ impl DataPackStore {
         max_bytes: Option<u64>,
         extstored_policy: ExtStoredPolicy,
     ) -> Self {
+        if let Err(err) = load_dictionaries(pack_dir.as_ref()) {
+            warn!(%err, "cannot load compression dictionaries");
+        }
         PackStoreOptions::new()
             .directory(pack_dir)
             .corruption_policy(corruption_policy)
@@ -493,6 +498,13 @@ impl HgIdHistoryStore for HistoryPackStore {
         self.inner.lock().run(|store| store.get_node_info(key))
     }
 
+    fn get_node_info_batch(&self, keys: &[Key]) -> Result<Vec<Option<NodeInfo>>> {
+        let inner = self.inner.lock();
+        keys.iter()
+            .map(|key| inner.run(|store| store.get_node_info(key)))
+            .collect()
+    }
+
     fn refresh(&self) -> Result<()> {
         let inner = self.inner.lock();
         inner.last_scanned.replace(None);
@@ -671,6 +683,10 @@ impl HgIdHistoryStore for MutableHistoryPackStore {
     fn refresh(&self) -> Result<()> {
         self.inner.union_store.refresh()
     }
+
+    fn get_node_info_batch(&self, keys: &[Key]) -> Result<Vec<Option<NodeInfo>>> {
+        self.inner.union_store.get_node_info_batch(keys)
+    }
 }
 
 impl LocalStore for MutableHistoryPackStore {
@@ -0,0 +1,280 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Keys of the shared cache that must never be evicted.
+//!
+//! Tooling reading files outside of the working copy (ex. `.arcconfig`, scripts used by sparse
+//! profiles and virtual checkouts) pins their keys. Pinned entries of the shared data store are
+//! kept by [`crate::gc`], and are copied to the newest generation of the store when it rotates,
+//! so they are never dropped with its oldest generation.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use indexedlog::log::IndexOutput;
+use parking_lot::RwLock;
+use types::HgId;
+use types::Key;
+use types::RepoPath;
+
+use crate::indexedlogutil::Store;
+use crate::indexedlogutil::StoreOpenOptions;
+use crate::sliceext::SliceExt;
+use crate::verify::recover_rewrite;
+use crate::verify::rewrite_without;
+
+const PINNED_DIR: &str = "pinned";
+
+const UNPINNED: u8 = 0;
+const PINNED: u8 = 1;
+
+/// `PinStore::compact` leaves pin-sets with fewer outdated records alone.
+const COMPACT_MIN_OUTDATED: usize = 1024;
+
+/// An indexedlog of `(state, hgid, path)` records. The latest record of a key tells whether it
+/// is pinned. Unlike the shared stores, it is never rotated: [`PinStore::compact`] drops the
+/// records of the keys that were unpinned or pinned again instead.
+pub struct PinStore {
+    path: PathBuf,
+    /// Created by the first `pin`, so caches without pinned keys have no pin-set.
+    log: RwLock<Option<Store>>,
+    /// The pinned keys, read once when the store is opened, then updated by `pin` and `unpin`.
+    /// Keys pinned by other processes since then are not included.
+    pinned: RwLock<HashSet<Key>>,
+}
+
+impl PinStore {
+    /// Open the pin-set of the cache at `store_path`, ie. `<cachepath>/<reponame>`. Nothing is
+    /// written until a key is pinned.
+    pub fn new(store_path: impl AsRef<Path>) -> Result<Self> {
+        let path = store_path.as_ref().join(PINNED_DIR);
+        recover_rewrite(&path)?;
+        let (log, pinned) = if path.is_dir() {
+            let log = PinStore::open_options().local(&path)?;
+            let pinned = PinStore::read_states(&log)?
+                .into_iter()
+                .filter_map(|(key, (pinned, _))| pinned.then_some(key))
+                .collect();
+            (Some(log), pinned)
+        } else {
+            (None, HashSet::new())
+        };
+        Ok(PinStore {
+            path,
+            log: RwLock::new(log),
+            pinned: RwLock::new(pinned),
+        })
+    }
+
+    /// Like `new`, but returns `None` if no key was ever pinned in the cache.
+    pub(crate) fn open_existing(store_path: impl AsRef<Path>) -> Result<Option<Self>> {
+        if store_path.as_ref().join(PINNED_DIR).is_dir() {
+            PinStore::new(store_path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The latest state of every key of `log`, and the position of the record holding it.
+    fn read_states(log: &Store) -> Result<HashMap<Key, (bool, usize)>> {
+        let mut states = HashMap::new();
+        for (position, buf) in log.iter().enumerate() {
+            let buf = buf?;
+            let hgid = HgId::from_slice(buf.get_err(1..1 + HgId::len())?)?;
+            let path = RepoPath::from_utf8(buf.get_err(1 + HgId::len()..)?)?;
+            states.insert(
+                Key::new(path.to_owned(), hgid),
+                (buf[0] == PINNED, position),
+            );
+        }
+        Ok(states)
+    }
+
+    /// Rewrite the pin-set of the cache at `store_path` with only the latest record of the pinned
+    /// keys, once most of its records are outdated. Fails if the pin-set is open, ie. in use by
+    /// another process.
+    pub(crate) fn compact(store_path: impl AsRef<Path>) -> Result<()> {
+        let path = store_path.as_ref().join(PINNED_DIR);
+        recover_rewrite(&path)?;
+        if !path.is_dir() {
+            return Ok(());
+        }
+        let (records, kept) = {
+            let log = PinStore::open_options().local(&path)?;
+            let kept: HashSet<usize> = PinStore::read_states(&log)?
+                .into_values()
+                .filter_map(|(pinned, position)| pinned.then_some(position))
+                .collect();
+            (log.iter().count(), kept)
+        };
+        if records - kept.len() <= kept.len().max(COMPACT_MIN_OUTDATED) {
+            return Ok(());
+        }
+        let outdated = (0..records)
+            .filter(|position| !kept.contains(position))
+            .collect();
+        rewrite_without(
+            &path,
+            |path| PinStore::open_options().local(path),
+            &outdated,
+        )
+    }
+
+    fn open_options() -> StoreOpenOptions {
+        StoreOpenOptions::new()
+            .auto_sync_threshold(1024 * 1024)
+            .create(true)
+            .index("key", |data| {
+                vec![IndexOutput::Reference(1..data.len() as u64)]
+            })
+    }
+
+    fn index_key(key: &Key) -> Vec<u8> {
+        let path = key.path.as_byte_slice();
+        let mut index_key = Vec::with_capacity(HgId::len() + path.len());
+        index_key.extend_from_slice(key.hgid.as_ref());
+        index_key.extend_from_slice(path);
+        index_key
+    }
+
+    fn set_state(&self, keys: &[Key], state: u8) -> Result<()> {
+        let mut log = self.log.write();
+        if log.is_none() {
+            if state == UNPINNED {
+                return Ok(());
+            }
+            *log = Some(PinStore::open_options().local(&self.path)?);
+        }
+        let log = log.as_mut().expect("the pin-set was just opened");
+        let mut pinned = self.pinned.write();
+        for key in keys {
+            if state == PINNED {
+                pinned.insert(key.clone());
+            } else {
+                pinned.remove(key);
+            }
+            let index_key = PinStore::index_key(key);
+            let current = match log.lookup(0, &index_key)?.next() {
+                Some(buf) => buf?[0],
+                None => UNPINNED,
+            };
+            if current != state {
+                let mut buf = Vec::with_capacity(1 + index_key.len());
+                buf.push(state);
+                buf.extend_from_slice(&index_key);
+                log.append(buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn pin(&self, keys: &[Key]) -> Result<()> {
+        self.set_state(keys, PINNED)
+    }
+
+    pub fn unpin(&self, keys: &[Key]) -> Result<()> {
+        self.set_state(keys, UNPINNED)
+    }
+
+    pub fn is_pinned(&self, key: &Key) -> Result<bool> {
+        let log = self.log.read();
+        let log = match &*log {
+            Some(log) => log,
+            None => return Ok(false),
+        };
+        let mut records = log.lookup(0, PinStore::index_key(key))?;
+        Ok(match records.next() {
+            Some(buf) => buf?[0] == PINNED,
+            None => false,
+        })
+    }
+
+    /// The keys pinned when the store was opened or by this store since then, sorted. This does
+    /// not read the log.
+    pub fn pinned(&self) -> Vec<Key> {
+        let mut keys: Vec<Key> = self.pinned.read().iter().cloned().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        match &mut *self.log.write() {
+            Some(log) => log.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use types::testutil::*;
+
+    use super::*;
+
+    #[test]
+    fn test_pin_unpin() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        assert!(PinStore::open_existing(&tempdir)?.is_none());
+
+        let pins = PinStore::new(&tempdir)?;
+        let a = key("a", "1");
+        let b = key("b", "2");
+        pins.pin(&[a.clone(), b.clone()])?;
+        pins.unpin(&[a.clone()])?;
+        pins.flush()?;
+        drop(pins);
+
+        let pins = PinStore::open_existing(&tempdir)?.unwrap();
+        assert!(!pins.is_pinned(&a)?);
+        assert!(pins.is_pinned(&b)?);
+        assert_eq!(pins.pinned(), vec![b]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unpin_does_not_create() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let pins = PinStore::new(&tempdir)?;
+        pins.unpin(&[key("a", "1")])?;
+        pins.flush()?;
+        assert!(!tempdir.path().join(PINNED_DIR).exists());
+        assert!(PinStore::open_existing(&tempdir)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let pins = PinStore::new(&tempdir)?;
+        let a = key("a", "1");
+        let b = key("b", "2");
+        pins.pin(&[a.clone(), b.clone()])?;
+        for _ in 0..COMPACT_MIN_OUTDATED {
+            pins.unpin(&[a.clone()])?;
+            pins.pin(&[a.clone()])?;
+        }
+        pins.unpin(&[b.clone()])?;
+        pins.flush()?;
+
+        // The pin-set is in use.
+        assert!(PinStore::compact(&tempdir).is_err());
+        drop(pins);
+
+        PinStore::compact(&tempdir)?;
+        let pins = PinStore::new(&tempdir)?;
+        assert_eq!(pins.pinned(), vec![a.clone()]);
+        assert!(pins.is_pinned(&a)?);
+        assert!(!pins.is_pinned(&b)?);
+        let log = pins.log.read();
+        assert_eq!(log.as_ref().unwrap().iter().count(), 1);
+        Ok(())
+    }
+}
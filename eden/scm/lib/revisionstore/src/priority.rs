@@ -0,0 +1,158 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use anyhow::bail;
+use anyhow::Result;
+use tokio::sync::Notify;
+
+/// Number of keys a background prefetch requests at once. Interactive fetches can only jump the
+/// queue between batches, so this bounds how long they wait.
+pub(crate) const BACKGROUND_BATCH_SIZE: usize = 1000;
+
+/// How urgently the data asked for in a prefetch is needed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchPriority {
+    /// Someone is waiting on the result, like in `hg cat`.
+    Interactive,
+    /// Bulk fetches that can wait, like warming the cache. They are sent in batches, and
+    /// interactive fetches go first between batches.
+    Background,
+}
+
+impl Default for FetchPriority {
+    fn default() -> Self {
+        FetchPriority::Interactive
+    }
+}
+
+impl FetchPriority {
+    /// Number of keys to request at once for a fetch of `len` keys.
+    pub(crate) fn batch_size(self, len: usize) -> usize {
+        match self {
+            FetchPriority::Interactive => len.max(1),
+            FetchPriority::Background => BACKGROUND_BATCH_SIZE,
+        }
+    }
+}
+
+impl FromStr for FetchPriority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "interactive" => Ok(FetchPriority::Interactive),
+            "background" => Ok(FetchPriority::Background),
+            _ => bail!("unknown fetch priority '{}'", s),
+        }
+    }
+}
+
+impl fmt::Display for FetchPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchPriority::Interactive => write!(f, "interactive"),
+            FetchPriority::Background => write!(f, "background"),
+        }
+    }
+}
+
+/// Tracks the interactive fetches in flight on a remote store so that background fetches can
+/// step aside for them.
+#[derive(Default)]
+pub(crate) struct PriorityLanes {
+    interactive: AtomicUsize,
+    notify: Notify,
+}
+
+/// Marks an interactive fetch as in flight until dropped.
+pub(crate) struct InteractiveFetch<'a> {
+    lanes: &'a PriorityLanes,
+}
+
+impl PriorityLanes {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    /// Register an interactive fetch. Background fetches wait between batches until the returned
+    /// guard is dropped.
+    pub(crate) fn interactive(&self) -> InteractiveFetch<'_> {
+        self.interactive.fetch_add(1, Ordering::AcqRel);
+        InteractiveFetch { lanes: self }
+    }
+
+    /// Register a fetch of the given priority. Only interactive fetches hold a guard.
+    pub(crate) fn enter(&self, priority: FetchPriority) -> Option<InteractiveFetch<'_>> {
+        match priority {
+            FetchPriority::Interactive => Some(self.interactive()),
+            FetchPriority::Background => None,
+        }
+    }
+
+    pub(crate) fn interactive_in_flight(&self) -> usize {
+        self.interactive.load(Ordering::Acquire)
+    }
+
+    /// Wait until no interactive fetch is in flight.
+    pub(crate) async fn yield_to_interactive(&self) {
+        loop {
+            // Register before checking the count so a concurrent guard drop can't be missed.
+            let notified = self.notify.notified();
+            if self.interactive_in_flight() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for InteractiveFetch<'_> {
+    fn drop(&mut self) {
+        if self.lanes.interactive.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.lanes.notify.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_priority() -> Result<()> {
+        assert_eq!(
+            "interactive".parse::<FetchPriority>()?,
+            FetchPriority::Interactive
+        );
+        assert_eq!(
+            "background".parse::<FetchPriority>()?,
+            FetchPriority::Background
+        );
+        assert!("urgent".parse::<FetchPriority>().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_background_waits_for_interactive() {
+        let lanes = PriorityLanes::new();
+        let guard = lanes.interactive();
+        let waited =
+            tokio::time::timeout(Duration::from_millis(10), lanes.yield_to_interactive()).await;
+        assert!(waited.is_err());
+
+        drop(guard);
+        assert_eq!(lanes.interactive_in_flight(), 0);
+        lanes.yield_to_interactive().await;
+    }
+}
@@ -0,0 +1,77 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use types::Key;
+use types::NodeInfo;
+
+use crate::datastore::Delta;
+use crate::datastore::HgIdDataStore;
+use crate::datastore::HgIdMutableDeltaStore;
+use crate::datastore::Metadata;
+use crate::datastore::StoreResult;
+use crate::error::ReadOnlyStoreError;
+use crate::historystore::HgIdHistoryStore;
+use crate::historystore::HgIdMutableHistoryStore;
+use crate::localstore::LocalStore;
+use crate::types::StoreKey;
+
+/// Takes the place of the mutable stores of a `ContentStore` or `MetadataStore` opened
+/// read-only. It is always empty, and all writes to it fail with `ReadOnlyStoreError`.
+pub(crate) struct ReadOnlyStore;
+
+impl LocalStore for ReadOnlyStore {
+    fn get_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        Ok(keys.to_vec())
+    }
+}
+
+impl HgIdDataStore for ReadOnlyStore {
+    fn get(&self, key: StoreKey) -> Result<StoreResult<Vec<u8>>> {
+        Ok(StoreResult::NotFound(key))
+    }
+
+    fn get_meta(&self, key: StoreKey) -> Result<StoreResult<Metadata>> {
+        Ok(StoreResult::NotFound(key))
+    }
+
+    fn refresh(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl HgIdMutableDeltaStore for ReadOnlyStore {
+    fn add(&self, _delta: &Delta, _metadata: &Metadata) -> Result<()> {
+        Err(ReadOnlyStoreError.into())
+    }
+
+    fn flush(&self) -> Result<Option<Vec<PathBuf>>> {
+        Ok(None)
+    }
+}
+
+impl HgIdHistoryStore for ReadOnlyStore {
+    fn get_node_info(&self, _key: &Key) -> Result<Option<NodeInfo>> {
+        Ok(None)
+    }
+
+    fn refresh(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl HgIdMutableHistoryStore for ReadOnlyStore {
+    fn add(&self, _key: &Key, _info: &NodeInfo) -> Result<()> {
+        Err(ReadOnlyStoreError.into())
+    }
+
+    fn flush(&self) -> Result<Option<Vec<PathBuf>>> {
+        Ok(None)
+    }
+}
@@ -21,7 +21,10 @@ use configparser::convert::ByteCount;
 use minibytes::Bytes;
 use thiserror::Error;
 use types::Key;
+use types::RepoPath;
 
+use crate::compression::load_dictionaries;
+use crate::compression::Compression;
 use crate::datapack::DataPack;
 use crate::datapack::DataPackVersion;
 use crate::datastore::HgIdDataStore;
@@ -39,6 +42,7 @@ use crate::mutabledatapack::MutableDataPack;
 use crate::mutablehistorypack::MutableHistoryPack;
 use crate::mutablepack::MutablePack;
 use crate::types::StoreKey;
+use crate::util::path_has_prefix;
 use crate::LegacyStore;
 
 #[derive(Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
@@ -55,6 +59,18 @@ pub enum RepackKind {
 
 pub trait ToKeys {
     fn to_keys(&self) -> Vec<Result<Key>>;
+
+    /// Like `to_keys`, restricted to the files that are `prefix` or are under the `prefix`
+    /// directory. Stores with an index over the paths should avoid reading the other entries.
+    fn iter_prefix(&self, prefix: &RepoPath) -> Vec<Result<Key>> {
+        self.to_keys()
+            .into_iter()
+            .filter(|key| match key {
+                Ok(key) => path_has_prefix(&key.path, prefix),
+                Err(_) => true,
+            })
+            .collect()
+    }
 }
 
 pub trait Repackable {
@@ -184,11 +200,16 @@ fn repack_packs<T: MutablePack, U: LocalStore + Repackable + ToKeys + StoreFromP
     }
 }
 
+/// Repack the datapacks at `paths` into a single datapack in `outdir`. Entries are re-encoded
+/// with `compression`.
 fn repack_datapacks(
     paths: impl IntoIterator<Item = PathBuf> + Clone,
     outdir: &Path,
+    compression: Compression,
 ) -> Result<Option<PathBuf>> {
-    let mut_pack = MutableDataPack::new(outdir, DataPackVersion::One);
+    load_dictionaries(outdir)?;
+    let mut_pack =
+        MutableDataPack::new(outdir, DataPackVersion::One).with_compression(compression)?;
 
     repack_packs(paths, mut_pack, repack_datapack)
 }
@@ -310,7 +331,8 @@ fn repack_no_store(path: PathBuf, kind: RepackKind, config: &ConfigSet) -> Resul
         histpacks = filter_incrementalpacks(histpacks, "histpack", config)?;
     }
 
-    let datapack_res = repack_datapacks(datapacks, &path).map(|_| ());
+    let compression = Compression::from_config(config)?;
+    let datapack_res = repack_datapacks(datapacks, &path, compression).map(|_| ());
     let histpack_res = repack_historypacks(histpacks, &path).map(|_| ());
 
     datapack_res.and(histpack_res)
@@ -585,7 +607,7 @@ mod tests {
     fn test_repack_no_datapack() {
         let tempdir = TempDir::new().unwrap();
 
-        let newpath = repack_datapacks(vec![].into_iter(), tempdir.path());
+        let newpath = repack_datapacks(vec![].into_iter(), tempdir.path(), Compression::default());
         assert!(newpath.is_ok());
         let newpath = newpath.unwrap();
         assert_eq!(newpath, None);
@@ -608,6 +630,7 @@ mod tests {
         let newpath = repack_datapacks(
             vec![pack.base_path().to_path_buf()].into_iter(),
             tempdir.path(),
+            Compression::default(),
         );
         assert!(newpath.is_ok());
         let newpath2 = newpath.unwrap().unwrap();
@@ -628,6 +651,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_repack_recompresses() -> Result<()> {
+        let tempdir = TempDir::new()?;
+
+        let revisions = vec![(
+            Delta {
+                data: Bytes::from(&[1u8, 2, 3, 4][..]),
+                base: None,
+                key: key("a", "1"),
+            },
+            Default::default(),
+        )];
+
+        let pack = make_datapack(&tempdir, &revisions);
+        let outdir = TempDir::new()?;
+        let newpath = repack_datapacks(
+            vec![pack.base_path().to_path_buf()].into_iter(),
+            outdir.path(),
+            Compression::zstd(3, None)?,
+        )?
+        .unwrap();
+        let newpack = DataPack::new(&newpath, ExtStoredPolicy::Use)?;
+        let chain = newpack.get_delta_chain(&revisions[0].0.key)?.unwrap();
+        assert_eq!(chain[0].data, revisions[0].0.data);
+        // The content is now compressed with zstd, which is framed with a header.
+        let raw = std::fs::read(newpack.pack_path())?;
+        assert!(raw.windows(4).any(|w| w == b"RSC\xff"));
+        Ok(())
+    }
+
     #[test]
     fn test_repack_multiple_datapacks() {
         let tempdir = TempDir::new().unwrap();
@@ -660,7 +713,7 @@ mod tests {
             paths.push(path);
         }
 
-        let newpath = repack_datapacks(paths.into_iter(), tempdir.path());
+        let newpath = repack_datapacks(paths.into_iter(), tempdir.path(), Compression::default());
         assert!(newpath.is_ok());
         let newpack = DataPack::new(&newpath.unwrap().unwrap(), ExtStoredPolicy::Use).unwrap();
         assert_eq!(
@@ -682,7 +735,11 @@ mod tests {
         let tempdir = TempDir::new().unwrap();
 
         let paths = vec![PathBuf::from("foo.datapack"), PathBuf::from("bar.datapack")];
-        let res = repack_datapacks(paths.clone().into_iter(), tempdir.path());
+        let res = repack_datapacks(
+            paths.clone().into_iter(),
+            tempdir.path(),
+            Compression::default(),
+        );
 
         assert!(res.unwrap().is_none());
     }
@@ -731,7 +788,7 @@ mod tests {
         file.write_all(b"FOOBARBAZ").unwrap();
         drop(file);
 
-        let res = repack_datapacks(paths.into_iter(), tempdir.path())
+        let res = repack_datapacks(paths.into_iter(), tempdir.path(), Compression::default())
             .err()
             .unwrap();
 
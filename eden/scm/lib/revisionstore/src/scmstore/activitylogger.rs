@@ -18,6 +18,7 @@ use serde::Deserialize;
 use serde::Serialize;
 use types::Key;
 
+use super::FetchCause;
 use super::FileAttributes;
 
 pub(crate) struct ActivityLogger {
@@ -31,6 +32,8 @@ pub struct ActivityLog {
     pub attrs: FileAttributes,
     pub start_millis: u128,
     pub duration_millis: u128,
+    #[serde(default)]
+    pub cause: FetchCause,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -48,6 +51,7 @@ impl ActivityLogger {
         keys: Vec<Key>,
         attrs: FileAttributes,
         dur: Duration,
+        cause: FetchCause,
     ) -> Result<()> {
         serde_json::to_writer(
             &mut self.f,
@@ -59,6 +63,7 @@ impl ActivityLogger {
                     .duration_since(SystemTime::UNIX_EPOCH)?
                     .as_millis(),
                 duration_millis: dur.as_millis(),
+                cause,
             },
         )?;
         self.f.write_all(&[b'\n'])?;
@@ -20,27 +20,36 @@ use parking_lot::Mutex;
 use progress_model::AggregatingProgressBar;
 use regex::Regex;
 
+use crate::compression::Compression;
 use crate::contentstore::check_cache_buster;
 use crate::fetch_logger::FetchLogger;
 use crate::indexedlogauxstore::AuxStore;
+use crate::indexedlogdatastore::probe_result;
 use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
 use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+use crate::indexedlogtreeauxstore::TreeAuxStore;
 use crate::indexedlogutil::StoreType;
 use crate::lfs::LfsRemote;
 use crate::lfs::LfsStore;
+use crate::memcache::MemcacheWriteThrough;
 use crate::scmstore::activitylogger::ActivityLogger;
 use crate::scmstore::file::FileStoreMetrics;
+use crate::scmstore::tree::TreeStoreMetrics;
 use crate::scmstore::FileStore;
 use crate::scmstore::TreeStore;
-use crate::util::get_cache_path;
+use crate::treepagestore::TreePageStore;
+use crate::util::get_existing_cache_path;
 use crate::util::get_indexedlogdatastore_aux_path;
 use crate::util::get_indexedlogdatastore_path;
 use crate::util::get_local_path;
+use crate::util::get_repo_name;
+use crate::CacheLayout;
 use crate::ContentStore;
 use crate::EdenApiFileStore;
 use crate::EdenApiTreeStore;
 use crate::ExtStoredPolicy;
 use crate::MemcacheStore;
+use crate::PinStore;
 
 pub struct FileStoreBuilder<'a> {
     config: &'a ConfigSet,
@@ -200,19 +209,25 @@ impl<'a> FileStoreBuilder<'a> {
                 max_bytes_per_log: None,
                 max_bytes: None,
             };
-            Some(Arc::new(IndexedLogHgIdDataStore::new(
-                get_indexedlogdatastore_path(&local_path)?,
-                self.get_extstored_policy()?,
-                &config,
-                StoreType::Local,
-            )?))
+            Some(Arc::new(
+                IndexedLogHgIdDataStore::new(
+                    get_indexedlogdatastore_path(&local_path)?,
+                    self.get_extstored_policy()?,
+                    &config,
+                    StoreType::Local,
+                )?
+                .with_compression(Compression::from_config(self.config)?)?,
+            ))
         } else {
             None
         })
     }
 
+    fn cache_layout(&self) -> Result<CacheLayout> {
+        CacheLayout::from_config(self.config, &self.suffix)
+    }
+
     pub fn build_indexedlog_cache(&self) -> Result<Arc<IndexedLogHgIdDataStore>> {
-        let cache_path = get_cache_path(self.config, &self.suffix)?;
         let max_log_count = self
             .config
             .get_opt::<u8>("indexedlog", "data.max-log-count")?;
@@ -227,12 +242,42 @@ impl<'a> FileStoreBuilder<'a> {
             max_bytes_per_log,
             max_bytes,
         };
-        Ok(Arc::new(IndexedLogHgIdDataStore::new(
-            get_indexedlogdatastore_path(&cache_path)?,
+        let shards = self
+            .config
+            .get_or::<usize>("indexedlog", "data.shards", || 1)?;
+        Ok(Arc::new(
+            IndexedLogHgIdDataStore::new(
+                self.cache_layout()?.indexedlogdatastore_path()?,
+                self.get_extstored_policy()?,
+                &config,
+                StoreType::Shared,
+            )?
+            .with_shards(shards, &config)?
+            .with_compression(Compression::from_config(self.config)?)?,
+        ))
+    }
+
+    /// The cache of the previous cache root, if the cache is being relocated.
+    pub fn build_indexedlog_cache_previous(&self) -> Result<Option<Arc<IndexedLogHgIdDataStore>>> {
+        let path = match self.cache_layout()?.previous_indexedlogdatastore_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        Ok(Some(Arc::new(IndexedLogHgIdDataStore::open_read_only(
+            path,
             self.get_extstored_policy()?,
             &config,
             StoreType::Shared,
-        )?))
+        )?)))
+    }
+
+    pub fn build_pins(&self) -> Result<Arc<PinStore>> {
+        Ok(Arc::new(PinStore::new(self.cache_layout()?.store_path()?)?))
     }
 
     pub fn build_aux_local(&self) -> Result<Option<Arc<AuxStore>>> {
@@ -250,8 +295,7 @@ impl<'a> FileStoreBuilder<'a> {
     }
 
     pub fn build_aux_cache(&self) -> Result<Arc<AuxStore>> {
-        let cache_path = get_cache_path(self.config, &self.suffix)?;
-        let cache_path = get_indexedlogdatastore_aux_path(&cache_path)?;
+        let cache_path = self.cache_layout()?.indexedlogdatastore_aux_path()?;
         Ok(Arc::new(AuxStore::new(
             cache_path,
             self.config,
@@ -259,6 +303,19 @@ impl<'a> FileStoreBuilder<'a> {
         )?))
     }
 
+    /// The aux cache of the previous cache root, if the cache is being relocated.
+    pub fn build_aux_cache_previous(&self) -> Result<Option<Arc<AuxStore>>> {
+        let path = match self.cache_layout()?.previous_indexedlogdatastore_aux_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        Ok(Some(Arc::new(AuxStore::open_read_only(
+            path,
+            self.config,
+            StoreType::Shared,
+        )?)))
+    }
+
     pub fn build_lfs_local(&self) -> Result<Option<Arc<LfsStore>>> {
         if !self.use_lfs()? {
             return Ok(None);
@@ -277,13 +334,38 @@ impl<'a> FileStoreBuilder<'a> {
             return Ok(None);
         }
 
-        let cache_path = get_cache_path(self.config, &self.suffix)?;
+        let cache_path = self.cache_layout()?.lfs_store_path()?;
         Ok(Some(Arc::new(LfsStore::shared(&cache_path, self.config)?)))
     }
 
+    /// The LFS cache of the previous cache root, if the cache is being relocated.
+    pub fn build_lfs_cache_previous(&self) -> Result<Option<Arc<LfsStore>>> {
+        if !self.use_lfs()? {
+            return Ok(None);
+        }
+
+        let path = match self.cache_layout()?.previous_lfs_store_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        Ok(Some(Arc::new(LfsStore::open_read_only(
+            &path,
+            self.config,
+        )?)))
+    }
+
+    /// Quickly check that the indexedlog stores `build` would open can be used, without opening
+    /// or creating them. See [`IndexedLogHgIdDataStore::probe`].
+    pub fn probe(&self) -> Result<()> {
+        probe_result(
+            "file store",
+            probe_indexedlog_stores(self.config, &self.local_path, &self.suffix),
+        )
+    }
+
     pub fn build(mut self) -> Result<FileStore> {
         if self.contentstore.is_none() {
-            let cache_path = get_cache_path(self.config, &self.suffix)?;
+            let cache_path = self.cache_layout()?.store_path()?;
             check_cache_buster(&self.config, &cache_path);
         }
 
@@ -303,6 +385,8 @@ impl<'a> FileStoreBuilder<'a> {
         } else {
             Some(self.build_indexedlog_cache()?)
         };
+        let indexedlog_cache_previous = self.build_indexedlog_cache_previous()?;
+        let pins = Some(self.build_pins()?);
 
         let lfs_local = if let Some(lfs_local) = self.lfs_local.take() {
             Some(lfs_local)
@@ -315,13 +399,15 @@ impl<'a> FileStoreBuilder<'a> {
         } else {
             self.build_lfs_cache()?
         };
+        let lfs_cache_previous = self.build_lfs_cache_previous()?;
 
-        let (aux_local, aux_cache) = if self.store_aux_data {
+        let (aux_local, aux_cache, aux_cache_previous) = if self.store_aux_data {
             let aux_local = self.build_aux_local()?;
             let aux_cache = Some(self.build_aux_cache()?);
-            (aux_local, aux_cache)
+            let aux_cache_previous = self.build_aux_cache_previous()?;
+            (aux_local, aux_cache, aux_cache_previous)
         } else {
-            (None, None)
+            (None, None, None)
         };
 
         let lfs_remote = if self.use_lfs()? {
@@ -342,6 +428,28 @@ impl<'a> FileStoreBuilder<'a> {
         };
 
         let memcache = self.memcache.take();
+        if let Some(ref memcache) = memcache {
+            memcache.configure_circuit_breaker(self.config)?;
+        }
+
+        let memcache_writethrough = match memcache {
+            Some(ref memcache)
+                if self
+                    .config
+                    .get_or_default::<bool>("scmstore", "memcachewritethrough")? =>
+            {
+                let queue_size = self.config.get_or::<usize>(
+                    "scmstore",
+                    "memcachewritethroughqueuesize",
+                    || 10000,
+                )?;
+                Some(Arc::new(MemcacheWriteThrough::new(
+                    memcache.clone(),
+                    queue_size,
+                )))
+            }
+            _ => None,
+        };
 
         let edenapi = if self.use_edenapi()? {
             if let Some(edenapi) = self.edenapi.take() {
@@ -377,6 +485,10 @@ impl<'a> FileStoreBuilder<'a> {
             .config
             .get_or_default::<bool>("scmstore", "prefercomputingauxdata")?;
 
+        let local_fetch_shards =
+            self.config
+                .get_or::<usize>("scmstore", "localfetchshards", || 1)?;
+
         let activity_logger =
             if let Some(path) = self.config.get_opt::<String>("scmstore", "activitylog")? {
                 let f = std::fs::OpenOptions::new()
@@ -394,15 +506,20 @@ impl<'a> FileStoreBuilder<'a> {
             edenapi_retries,
             allow_write_lfs_ptrs,
             prefer_computing_aux_data,
+            local_fetch_shards,
 
             indexedlog_local,
             lfs_local,
 
             indexedlog_cache,
+            indexedlog_cache_previous,
+            pins,
             lfs_cache,
+            lfs_cache_previous,
             cache_to_local_cache: true,
 
             memcache,
+            memcache_writethrough,
             cache_to_memcache: true,
 
             edenapi,
@@ -415,6 +532,7 @@ impl<'a> FileStoreBuilder<'a> {
 
             aux_local,
             aux_cache,
+            aux_cache_previous,
 
             creation_time: Instant::now(),
             lfs_progress: AggregatingProgressBar::new("fetching", "LFS"),
@@ -525,19 +643,25 @@ impl<'a> TreeStoreBuilder<'a> {
                 max_bytes_per_log: None,
                 max_bytes: None,
             };
-            Some(Arc::new(IndexedLogHgIdDataStore::new(
-                get_indexedlogdatastore_path(&local_path)?,
-                ExtStoredPolicy::Use,
-                &config,
-                StoreType::Local,
-            )?))
+            Some(Arc::new(
+                IndexedLogHgIdDataStore::new(
+                    get_indexedlogdatastore_path(&local_path)?,
+                    ExtStoredPolicy::Use,
+                    &config,
+                    StoreType::Local,
+                )?
+                .with_compression(Compression::from_config(self.config)?)?,
+            ))
         } else {
             None
         })
     }
 
+    fn cache_layout(&self) -> Result<CacheLayout> {
+        CacheLayout::from_config(self.config, &self.suffix)
+    }
+
     pub fn build_indexedlog_cache(&self) -> Result<Arc<IndexedLogHgIdDataStore>> {
-        let cache_path = get_cache_path(self.config, &self.suffix)?;
         let max_log_count = self
             .config
             .get_opt::<u8>("indexedlog", "manifest.max-log-count")?;
@@ -552,20 +676,83 @@ impl<'a> TreeStoreBuilder<'a> {
             max_bytes_per_log,
             max_bytes,
         };
+        let shards = self
+            .config
+            .get_or::<usize>("indexedlog", "manifest.shards", || 1)?;
 
-        Ok(Arc::new(IndexedLogHgIdDataStore::new(
-            get_indexedlogdatastore_path(&cache_path)?,
+        Ok(Arc::new(
+            IndexedLogHgIdDataStore::new(
+                self.cache_layout()?.indexedlogdatastore_path()?,
+                ExtStoredPolicy::Use,
+                &config,
+                StoreType::Shared,
+            )?
+            .with_shards(shards, &config)?
+            .with_compression(Compression::from_config(self.config)?)?,
+        ))
+    }
+
+    /// The cache of the previous cache root, if the cache is being relocated.
+    pub fn build_indexedlog_cache_previous(&self) -> Result<Option<Arc<IndexedLogHgIdDataStore>>> {
+        let path = match self.cache_layout()?.previous_indexedlogdatastore_path() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        Ok(Some(Arc::new(IndexedLogHgIdDataStore::open_read_only(
+            path,
             ExtStoredPolicy::Use,
             &config,
             StoreType::Shared,
-        )?))
+        )?)))
+    }
+
+    /// The packed-page cache of trees, if `scmstore.tree-page-cache` is set.
+    pub fn build_page_cache(&self) -> Result<Option<Arc<TreePageStore>>> {
+        if !self
+            .config
+            .get_or_default::<bool>("scmstore", "tree-page-cache")?
+        {
+            return Ok(None);
+        }
+        Ok(Some(Arc::new(TreePageStore::new(
+            self.cache_layout()?.treepagestore_path()?,
+            self.config,
+        )?)))
+    }
+
+    /// The cache of directory aggregates, if `scmstore.tree-aux-cache` is set.
+    pub fn build_tree_aux_cache(&self) -> Result<Option<Arc<TreeAuxStore>>> {
+        if !self
+            .config
+            .get_or_default::<bool>("scmstore", "tree-aux-cache")?
+        {
+            return Ok(None);
+        }
+        Ok(Some(Arc::new(TreeAuxStore::new(
+            self.cache_layout()?.treeauxstore_path()?,
+            self.config,
+        )?)))
+    }
+
+    /// Quickly check that the indexedlog stores `build` would open can be used, without opening
+    /// or creating them. See [`IndexedLogHgIdDataStore::probe`].
+    pub fn probe(&self) -> Result<()> {
+        probe_result(
+            "tree store",
+            probe_indexedlog_stores(self.config, &self.local_path, &self.suffix),
+        )
     }
 
     pub fn build(mut self) -> Result<TreeStore> {
         // TODO(meyer): Clean this up, just copied and pasted from the other version & did some ugly hacks to get this
         // (the EdenApiAdapter stuff needs to be fixed in particular)
         if self.contentstore.is_none() {
-            let cache_path = get_cache_path(self.config, &self.suffix)?;
+            let cache_path = self.cache_layout()?.store_path()?;
             check_cache_buster(&self.config, &cache_path);
         }
 
@@ -580,8 +767,14 @@ impl<'a> TreeStoreBuilder<'a> {
         } else {
             Some(self.build_indexedlog_cache()?)
         };
+        let indexedlog_cache_previous = self.build_indexedlog_cache_previous()?;
+        let page_cache = self.build_page_cache()?;
+        let tree_aux_cache = self.build_tree_aux_cache()?;
 
         let memcache = self.memcache.take();
+        if let Some(ref memcache) = memcache {
+            memcache.configure_circuit_breaker(self.config)?;
+        }
 
         let edenapi = if self.use_edenapi()? {
             if let Some(edenapi) = self.edenapi.take() {
@@ -606,6 +799,9 @@ impl<'a> TreeStoreBuilder<'a> {
             indexedlog_local,
 
             indexedlog_cache,
+            indexedlog_cache_previous,
+            page_cache,
+            tree_aux_cache,
             cache_to_local_cache: true,
 
             memcache,
@@ -618,10 +814,48 @@ impl<'a> TreeStoreBuilder<'a> {
 
             creation_time: Instant::now(),
             flush_on_drop: true,
+            metrics: TreeStoreMetrics::new(),
         })
     }
 }
 
+/// Problems of the local, cache and previous cache indexedlog data stores. A cache that isn't
+/// configured is skipped, `build` reports it.
+fn probe_indexedlog_stores(
+    config: &ConfigSet,
+    local_path: &Option<PathBuf>,
+    suffix: &Option<PathBuf>,
+) -> Vec<String> {
+    let mut stores = Vec::new();
+    if let Some(local_path) = local_path {
+        let mut path = local_path.clone();
+        if let Some(suffix) = suffix {
+            path.push(suffix);
+        }
+        stores.push((path, StoreType::Local));
+    }
+    if let Ok(cache_path) = get_existing_cache_path(config, suffix) {
+        stores.push((cache_path, StoreType::Shared));
+    }
+    if let (Ok(Some(previous)), Ok(reponame)) = (
+        config.get_opt::<PathBuf>("remotefilelog", "previouscachepath"),
+        get_repo_name(config),
+    ) {
+        let mut path = previous.join(reponame);
+        if let Some(suffix) = suffix {
+            path.push(suffix);
+        }
+        stores.push((path, StoreType::Shared));
+    }
+
+    stores
+        .into_iter()
+        .flat_map(|(path, store_type)| {
+            IndexedLogHgIdDataStore::probe(&path.join("indexedlogdatastore"), store_type)
+        })
+        .collect()
+}
+
 fn use_edenapi_via_config(config: &dyn Config) -> Result<bool> {
     let mut use_edenapi: bool = config.get_or_default("remotefilelog", "http")?;
     if use_edenapi {
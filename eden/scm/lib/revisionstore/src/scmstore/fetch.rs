@@ -9,11 +9,15 @@ use std::collections::hash_map;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt;
+use std::str::FromStr;
 
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Error;
 use anyhow::Result;
 use crossbeam::channel::Sender;
+use serde::Deserialize;
+use serde::Serialize;
 use types::Key;
 
 use crate::scmstore::attrs::StoreAttrs;
@@ -150,6 +154,113 @@ impl<T: StoreValue> CommonFetchState<T> {
     }
 }
 
+/// Label describing the code path which triggered a fetch, such as "status-lookup", "checkout",
+/// or "eden-thrift". The cause is attached to fetch logs, metrics, and the activity log so that
+/// cache misses can be traced back to the caller generating them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize)]
+pub struct FetchCause(String);
+
+impl FetchCause {
+    const UNSPECIFIED: &'static str = "unspecified";
+
+    pub fn new(label: impl Into<String>) -> Self {
+        let label = label.into();
+        if label.is_empty() {
+            Self::unspecified()
+        } else {
+            FetchCause(label)
+        }
+    }
+
+    pub fn unspecified() -> Self {
+        FetchCause(Self::UNSPECIFIED.to_string())
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        self.0 == Self::UNSPECIFIED
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for FetchCause {
+    fn default() -> Self {
+        Self::unspecified()
+    }
+}
+
+impl fmt::Display for FetchCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for FetchCause {
+    fn from(label: &str) -> Self {
+        FetchCause::new(label)
+    }
+}
+
+impl From<String> for FetchCause {
+    fn from(label: String) -> Self {
+        FetchCause::new(label)
+    }
+}
+
+/// Which stores a fetch may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchMode {
+    /// Only the local and cache stores, like for `hg status`, which must not go to the network.
+    LocalOnly,
+    /// The local and cache stores, then the remote stores for the keys that weren't found.
+    AllowRemote,
+    /// Only the remote stores, bypassing the local and cache stores. Fetched data is still
+    /// written to the caches.
+    RemoteOnly,
+}
+
+impl FetchMode {
+    pub fn allows_local(self) -> bool {
+        self != FetchMode::RemoteOnly
+    }
+
+    pub fn allows_remote(self) -> bool {
+        self != FetchMode::LocalOnly
+    }
+}
+
+impl Default for FetchMode {
+    fn default() -> Self {
+        FetchMode::AllowRemote
+    }
+}
+
+impl FromStr for FetchMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "local-only" => Ok(FetchMode::LocalOnly),
+            "allow-remote" => Ok(FetchMode::AllowRemote),
+            "remote-only" => Ok(FetchMode::RemoteOnly),
+            _ => bail!("unknown fetch mode '{}'", s),
+        }
+    }
+}
+
+impl fmt::Display for FetchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchMode::LocalOnly => write!(f, "local-only"),
+            FetchMode::AllowRemote => write!(f, "allow-remote"),
+            FetchMode::RemoteOnly => write!(f, "remote-only"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum KeyFetchError {
     KeyedError { key: Key, errors: Vec<Error> },
@@ -323,4 +434,31 @@ mod tests {
             assert!(types::errors::is_network_error(&err));
         }
     }
+
+    #[test]
+    fn test_fetch_cause() {
+        assert_eq!(FetchCause::default(), FetchCause::unspecified());
+        assert!(FetchCause::new("").is_unspecified());
+        assert!(!FetchCause::from("checkout").is_unspecified());
+        assert_eq!(
+            FetchCause::from("status-lookup").to_string(),
+            "status-lookup"
+        );
+    }
+
+    #[test]
+    fn test_fetch_mode() -> Result<()> {
+        assert_eq!(FetchMode::default(), FetchMode::AllowRemote);
+        for mode in [
+            FetchMode::LocalOnly,
+            FetchMode::AllowRemote,
+            FetchMode::RemoteOnly,
+        ] {
+            assert_eq!(mode.to_string().parse::<FetchMode>()?, mode);
+        }
+        assert!("cache-only".parse::<FetchMode>().is_err());
+        assert!(!FetchMode::LocalOnly.allows_remote());
+        assert!(!FetchMode::RemoteOnly.allows_local());
+        Ok(())
+    }
 }
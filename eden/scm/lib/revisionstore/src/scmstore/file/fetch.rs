@@ -8,6 +8,7 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::anyhow;
 use anyhow::Result;
@@ -25,9 +26,12 @@ use types::errors::NetworkError;
 use types::Key;
 use types::Sha256;
 
+use crate::cancel::CancellationToken;
 use crate::datastore::HgIdDataStore;
 use crate::datastore::RemoteDataStore;
 use crate::error::ClonableError;
+use crate::error::FetchCancelled;
+use crate::error::LfsPointerMissing;
 use crate::fetch_logger::FetchLogger;
 use crate::indexedlogauxstore::AuxStore;
 use crate::indexedlogauxstore::Entry as AuxDataEntry;
@@ -38,13 +42,21 @@ use crate::lfs::LfsPointersEntry;
 use crate::lfs::LfsRemoteInner;
 use crate::lfs::LfsStore;
 use crate::lfs::LfsStoreEntry;
+use crate::memcache;
+use crate::memcache::McAux;
 use crate::memcache::McData;
+use crate::memcache::McWrite;
+use crate::memcache::MemcacheWriteThrough;
+use crate::priority::FetchPriority;
 use crate::scmstore::attrs::StoreAttrs;
 use crate::scmstore::fetch::CommonFetchState;
+use crate::scmstore::fetch::FetchCause;
 use crate::scmstore::fetch::FetchErrors;
+use crate::scmstore::fetch::FetchMode;
 use crate::scmstore::fetch::KeyFetchError;
 use crate::scmstore::file::metrics::FileStoreFetchMetrics;
 use crate::scmstore::file::LazyFile;
+use crate::scmstore::metrics::log_fetch_cause;
 use crate::scmstore::value::StoreValue;
 use crate::scmstore::FileAttributes;
 use crate::scmstore::FileAuxData;
@@ -82,9 +94,22 @@ pub struct FetchState {
     /// Track fetch metrics,
     metrics: FileStoreFetchMetrics,
 
+    /// The code path which triggered this fetch
+    cause: FetchCause,
+
+    /// Number of keys requested, reported along with the cause
+    requested: usize,
+
+    /// Stops the remote fetches when cancelled.
+    cancel: CancellationToken,
+
+    /// Whether remote fetches go before or after the other fetches in flight.
+    priority: FetchPriority,
+
     // Config
     extstored_policy: ExtStoredPolicy,
     compute_aux_data: bool,
+    local_fetch_shards: usize,
 }
 
 impl FetchState {
@@ -93,11 +118,21 @@ impl FetchState {
         attrs: FileAttributes,
         file_store: &FileStore,
         found_tx: Sender<Result<(Key, StoreFile), KeyFetchError>>,
+        cause: FetchCause,
+        cancel: CancellationToken,
+        priority: FetchPriority,
     ) -> Self {
+        let common = CommonFetchState::new(keys, attrs, found_tx);
+        let requested = common.pending_len();
+
         FetchState {
-            common: CommonFetchState::new(keys, attrs, found_tx),
+            common,
             errors: FetchErrors::new(),
             metrics: FileStoreFetchMetrics::default(),
+            cause,
+            requested,
+            cancel,
+            priority,
 
             lfs_pointers: HashMap::new(),
             key_origin: HashMap::new(),
@@ -106,10 +141,15 @@ impl FetchState {
             fetch_logger: file_store.fetch_logger.clone(),
             extstored_policy: file_store.extstored_policy,
             compute_aux_data: true,
+            local_fetch_shards: file_store.local_fetch_shards,
             lfs_progress: file_store.lfs_progress.clone(),
         }
     }
 
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
     pub(crate) fn pending_len(&self) -> usize {
         self.common.pending_len()
     }
@@ -122,6 +162,15 @@ impl FetchState {
         &self.metrics
     }
 
+    pub(crate) fn cause(&self) -> &FetchCause {
+        &self.cause
+    }
+
+    /// Attribute all keys which couldn't be served by the local and cache stores to this fetch's cause.
+    pub(crate) fn record_local_misses(&self) {
+        log_fetch_cause("file", &self.cause, self.requested, self.pending_len());
+    }
+
     /// Return all incomplete requested Keys for which additional attributes may be gathered by querying a store which provides the specified attributes.
     fn pending_all(&self, fetchable: FileAttributes) -> Vec<Key> {
         if fetchable.none() {
@@ -206,7 +255,10 @@ impl FetchState {
             anyhow!("expected LazyFile::EdenApi or LazyFile::Memcache, other LazyFile variants should not be written to cache")
         })?;
         if let Some(memcache) = memcache.as_ref() {
-            memcache.add_mcdata(cache_entry.clone().try_into()?);
+            if memcache.available() {
+                memcache.add_mcdata(cache_entry.clone().try_into()?);
+                memcache.record_write();
+            }
         }
         indexedlog_cache.put_entry(cache_entry)?;
         let mmap_entry = indexedlog_cache
@@ -220,7 +272,7 @@ impl FetchState {
             if self.extstored_policy == ExtStoredPolicy::Use {
                 match entry.try_into() {
                     Ok(ptr) => self.found_pointer(key, ptr, typ, true),
-                    Err(err) => self.errors.keyed_error(key, err),
+                    Err(err) => self.errors.keyed_error(key, LfsPointerMissing::wrap(err)),
                 }
             }
         } else {
@@ -252,12 +304,13 @@ impl FetchState {
         let mut errors = 0;
         let mut error: Option<String> = None;
 
+        let start = Instant::now();
         self.metrics.indexedlog.store(typ).fetch(pending.len());
-        for key in pending.into_iter() {
-            let res = store.get_raw_entry(&key);
+        for (key, res) in get_raw_entries(store, pending, self.local_fetch_shards) {
             match res {
                 Ok(Some(entry)) => {
                     self.metrics.indexedlog.store(typ).hit(1);
+                    self.metrics.indexedlog.store(typ).bytes(entry.stored_len());
                     found += 1;
                     self.found_indexedlog(key, entry, typ)
                 }
@@ -274,6 +327,7 @@ impl FetchState {
                 }
             }
         }
+        self.metrics.indexedlog.store(typ).time(start.elapsed());
 
         if found != 0 {
             debug!("    Found = {found}", found = found);
@@ -311,6 +365,7 @@ impl FetchState {
         let mut errors = 0;
         let mut error: Option<String> = None;
 
+        let start = Instant::now();
         self.metrics.aux.store(typ).fetch(pending.len());
 
         for key in pending.into_iter() {
@@ -334,6 +389,7 @@ impl FetchState {
                 }
             }
         }
+        self.metrics.aux.store(typ).time(start.elapsed());
 
         if found != 0 {
             debug!("    Found = {found}", found = found);
@@ -376,6 +432,7 @@ impl FetchState {
         let mut errors = 0;
         let mut error: Option<String> = None;
 
+        let start = Instant::now();
         self.metrics.lfs.store(typ).fetch(pending.len());
         for store_key in pending.into_iter() {
             let key = store_key.clone().maybe_into_key().expect(
@@ -385,10 +442,12 @@ impl FetchState {
                 Ok(Some(entry)) => {
                     // TODO(meyer): Make found behavior w/r/t LFS pointers and content consistent
                     self.metrics.lfs.store(typ).hit(1);
-                    if let LfsStoreEntry::PointerOnly(_) = &entry {
-                        found_pointers += 1;
-                    } else {
-                        found += 1;
+                    match &entry {
+                        LfsStoreEntry::PointerOnly(_) => found_pointers += 1,
+                        LfsStoreEntry::PointerAndBlob(_, blob) => {
+                            self.metrics.lfs.store(typ).bytes(blob.len());
+                            found += 1;
+                        }
                     }
                     self.found_lfs(key, entry, typ)
                 }
@@ -405,6 +464,7 @@ impl FetchState {
                 }
             }
         }
+        self.metrics.lfs.store(typ).time(start.elapsed());
 
         if found != 0 {
             debug!("    Found = {found}", found = found);
@@ -457,8 +517,8 @@ impl FetchState {
         store: &MemcacheStore,
         indexedlog_cache: Option<&IndexedLogHgIdDataStore>,
     ) -> Result<()> {
-        let pending = self.pending_nonlfs(FileAttributes::CONTENT);
-        if pending.is_empty() {
+        let pending = memcache::without_quarantined(self.pending_nonlfs(FileAttributes::CONTENT));
+        if pending.is_empty() || !store.available() {
             return Ok(());
         }
 
@@ -468,12 +528,41 @@ impl FetchState {
             .as_ref()
             .map(|fl| fl.report_keys(pending.iter()));
 
-        for res in store.get_data_iter(&pending)?.into_iter() {
+        self.metrics.memcache.fetch(pending.len());
+        let entries = match store.get_data_iter(&pending) {
+            Ok(entries) => entries,
+            Err(err) => {
+                store.record_fetch(pending.len(), 0, 1);
+                return Err(err);
+            }
+        };
+        // Memcache only returns the entries it has, so anything else is a miss.
+        let mut returned = 0;
+        let mut hits = 0;
+        let mut errors = 0;
+        for res in entries.into_iter() {
+            returned += 1;
             match res {
-                Ok(mcdata) => self.found_memcache(mcdata, indexedlog_cache),
-                Err(err) => self.errors.other_error(err),
+                Ok(mcdata) => {
+                    hits += 1;
+                    self.metrics.memcache.hit(1);
+                    self.metrics.memcache.bytes(mcdata.data.len());
+                    self.found_memcache(mcdata, indexedlog_cache)
+                }
+                Err(err) => {
+                    self.metrics.memcache.err(1);
+                    // Entries which fail to decode are fetched from the next stores.
+                    if !memcache::record_fetch_error(&err) {
+                        errors += 1;
+                        self.errors.other_error(err)
+                    }
+                }
             }
         }
+        store.record_fetch(pending.len(), hits, errors);
+        self.metrics
+            .memcache
+            .miss(pending.len().saturating_sub(returned));
         Ok(())
     }
 
@@ -482,9 +571,11 @@ impl FetchState {
         store: &MemcacheStore,
         indexedlog_cache: Option<&IndexedLogHgIdDataStore>,
     ) {
+        let start = Instant::now();
         if let Err(err) = self.fetch_memcache_inner(store, indexedlog_cache) {
             self.errors.other_error(err);
         }
+        self.metrics.memcache.time(start.elapsed());
     }
 
     fn found_edenapi(
@@ -493,6 +584,7 @@ impl FetchState {
         lfs_cache: Option<Arc<LfsStore>>,
         aux_cache: Option<Arc<AuxStore>>,
         memcache: Option<Arc<MemcacheStore>>,
+        memcache_writethrough: Option<Arc<MemcacheWriteThrough>>,
     ) -> Result<(StoreFile, Option<LfsPointersEntry>)> {
         let entry = entry.result?;
 
@@ -505,6 +597,12 @@ impl FetchState {
             if let Some(aux_cache) = aux_cache.as_ref() {
                 aux_cache.put(key.hgid, &aux_data.into())?;
             }
+            if let Some(memcache_writethrough) = memcache_writethrough.as_ref() {
+                memcache_writethrough.write(McWrite::Aux(McAux {
+                    key: key.clone(),
+                    aux: aux_data,
+                }));
+            }
             file.aux_data = Some(aux_data);
         }
 
@@ -515,15 +613,25 @@ impl FetchState {
                     lfs_cache.add_pointer(ptr.clone())?;
                 }
                 lfsptr = Some(ptr);
-            } else if let Some(indexedlog_cache) = indexedlog_cache.as_ref() {
-                file.content = Some(Self::evict_to_cache(
-                    key,
-                    LazyFile::EdenApi(entry),
-                    indexedlog_cache,
-                    memcache,
-                )?);
             } else {
-                file.content = Some(LazyFile::EdenApi(entry));
+                let content = LazyFile::EdenApi(entry);
+                // With write-through, the content is written to memcache in the background
+                // instead of synchronously when it's written to the cache.
+                let memcache = match memcache_writethrough.as_ref() {
+                    Some(memcache_writethrough) => {
+                        if let Some(entry) = content.indexedlog_cache_entry(key.clone())? {
+                            memcache_writethrough.write(McWrite::Data(entry.try_into()?));
+                        }
+                        None
+                    }
+                    None => memcache,
+                };
+                file.content = Some(match indexedlog_cache.as_ref() {
+                    Some(indexedlog_cache) => {
+                        Self::evict_to_cache(key, content, indexedlog_cache, memcache)?
+                    }
+                    None => content,
+                });
             }
         }
 
@@ -537,6 +645,7 @@ impl FetchState {
         lfs_cache: Option<Arc<LfsStore>>,
         aux_cache: Option<Arc<AuxStore>>,
         memcache: Option<Arc<MemcacheStore>>,
+        memcache_writethrough: Option<Arc<MemcacheWriteThrough>>,
     ) {
         let fetchable = FileAttributes::CONTENT | FileAttributes::AUX;
 
@@ -558,6 +667,9 @@ impl FetchState {
             .as_ref()
             .map(|fl| fl.report_keys(pending.iter()));
 
+        let start = Instant::now();
+        self.metrics.edenapi.fetch(pending.len());
+
         // TODO(meyer): Iterators or otherwise clean this up
         let pending_attrs: Vec<_> = pending
             .into_iter()
@@ -570,11 +682,24 @@ impl FetchState {
             })
             .collect();
 
-        let response = match block_on(store.files_attrs(pending_attrs)).map_err(|e| e.tag_network())
-        {
-            Ok(r) => r,
+        // Background fetches are sent in batches, letting interactive fetches go first.
+        let _interactive = store.lanes().enter(self.priority);
+        let request = async {
+            match self.priority {
+                FetchPriority::Interactive => store
+                    .files_attrs(pending_attrs)
+                    .await
+                    .map(|response| response.entries),
+                FetchPriority::Background => Ok(store.files_attrs_background(pending_attrs)),
+            }
+            .map_err(|e| e.tag_network())
+        };
+        let entries = match block_on(self.cancel.run(request)) {
+            Ok(entries) => entries,
             Err(err) => {
                 let err = ClonableError::new(err);
+                self.metrics.edenapi.err(fetching_keys.len());
+                self.metrics.edenapi.time(start.elapsed());
                 for key in fetching_keys.into_iter() {
                     self.errors.keyed_error(key, err.clone().into());
                 }
@@ -582,23 +707,28 @@ impl FetchState {
             }
         };
 
-        let entries = response
-            .entries
+        let entries = entries
             .map(move |res_entry| {
                 let lfs_cache = lfs_cache.clone();
                 let indexedlog_cache = indexedlog_cache.clone();
                 let aux_cache = aux_cache.clone();
                 let memcache = memcache.clone();
+                let memcache_writethrough = memcache_writethrough.clone();
                 spawn_blocking(move || {
                     res_entry.map(move |entry| {
+                        let bytes = entry
+                            .content()
+                            .map_or(0, |content| content.data_unchecked().len());
                         (
                             entry.key.clone(),
+                            bytes,
                             Self::found_edenapi(
                                 entry,
                                 indexedlog_cache,
                                 lfs_cache,
                                 aux_cache,
                                 memcache,
+                                memcache_writethrough,
                             ),
                         )
                     })
@@ -611,12 +741,15 @@ impl FetchState {
                 // of download speeds.
             })
             .buffer_unordered(4);
+        // Stop reading the response on cancellation. Dropping it aborts the requests in flight.
+        let cancel = self.cancel.clone();
+        let entries = entries.take_until(Box::pin(async move { cancel.cancelled().await }));
 
         // Record found entries
         let mut unknown_error: Option<ClonableError> = None;
         for res in stream_to_iter(entries) {
             // TODO(meyer): This outer EdenApi error with no key sucks
-            let (key, res) = match res {
+            let (key, bytes, res) = match res {
                 Ok(result) => match result.map_err(|e| e.tag_network()) {
                     Ok(result) => result,
                     Err(err) => {
@@ -638,6 +771,8 @@ impl FetchState {
             fetching_keys.remove(&key);
             match res {
                 Ok((file, maybe_lfsptr)) => {
+                    self.metrics.edenapi.hit(1);
+                    self.metrics.edenapi.bytes(bytes);
                     if let Some(lfsptr) = maybe_lfsptr {
                         found_pointers += 1;
                         self.found_pointer(key.clone(), lfsptr, StoreType::Shared, false);
@@ -647,6 +782,7 @@ impl FetchState {
                     self.found_attributes(key, file, Some(StoreType::Shared));
                 }
                 Err(err) => {
+                    self.metrics.edenapi.err(1);
                     errors += 1;
                     if error.is_none() {
                         error.replace(format!("{}: {}", key, err));
@@ -655,7 +791,14 @@ impl FetchState {
                 }
             }
         }
+        self.metrics.edenapi.time(start.elapsed());
+
+        if self.cancel.is_cancelled() && unknown_error.is_none() {
+            unknown_error.replace(ClonableError::new(FetchCancelled.into()));
+        }
 
+        // Keys which weren't returned are reported as errors below.
+        self.metrics.edenapi.err(fetching_keys.len());
         for missing_key in fetching_keys.into_iter() {
             match &unknown_error {
                 Some(error) => self.errors.keyed_error(missing_key, error.clone().into()),
@@ -743,14 +886,22 @@ impl FetchState {
 
         let prog = self.lfs_progress.create_or_extend(pending.len() as u64);
 
+        let start = Instant::now();
+        self.metrics.lfs_remote.fetch(pending.len());
+
         let mut keyed_errors = Vec::<(Key, anyhow::Error)>::new();
         let mut other_errors = vec![];
+        let mut fetched = 0;
+        let mut failed = 0;
 
         // Fetch & write to local LFS stores
         let top_level_error = store.batch_fetch(
             &pending,
             |sha256, data| -> Result<()> {
                 prog.increase_position(1);
+                fetched += 1;
+                self.metrics.lfs_remote.hit(1);
+                self.metrics.lfs_remote.bytes(data.len());
 
                 match self.pointer_origin.get(&sha256).ok_or_else(|| {
                     anyhow!(
@@ -781,6 +932,7 @@ impl FetchState {
                 Ok(())
             },
             |sha256, error| {
+                failed += 1;
                 if let Some(keys) = key_map.get(&sha256) {
                     let error = ClonableError::new(NetworkError::wrap(error));
                     for (key, _) in keys.iter() {
@@ -792,7 +944,14 @@ impl FetchState {
             },
         );
 
+        self.metrics.lfs_remote.time(start.elapsed());
+        self.metrics.lfs_remote.err(failed);
+
         if let Err(err) = top_level_error {
+            // Blobs which were fetched or failed individually are already counted.
+            self.metrics
+                .lfs_remote
+                .err(pending.len().saturating_sub(fetched + failed));
             let err = ClonableError::new(err);
             for (key, (_ptr, _write)) in self.lfs_pointers.iter() {
                 self.errors.keyed_error(key.clone(), err.clone().into());
@@ -824,6 +983,7 @@ impl FetchState {
                 meta,
             );
             self.metrics.contentstore.hit(1);
+            self.metrics.contentstore.bytes(bytes.len());
             self.found_attributes(key, LazyFile::ContentStore(bytes.into(), meta).into(), None)
         }
     }
@@ -832,6 +992,7 @@ impl FetchState {
         &mut self,
         store: &ContentStore,
         pending: &mut Vec<StoreKey>,
+        mode: FetchMode,
     ) -> Result<()> {
         debug!(
             "ContentStore Fallback  - Count = {count}",
@@ -841,7 +1002,13 @@ impl FetchState {
         let mut errors = 0;
         let mut error: Option<String> = None;
 
-        store.prefetch(&pending)?;
+        // Without remote access, only the local packs and indexedlogs of the ContentStore are read.
+        let datastore: &dyn HgIdDataStore = if mode.allows_remote() {
+            store.prefetch(&pending)?;
+            store
+        } else {
+            store.local_and_cache()
+        };
 
         for store_key in pending.drain(..) {
             let key = store_key.clone().maybe_into_key().expect(
@@ -850,7 +1017,7 @@ impl FetchState {
             // Using the ContentStore API, fetch the hg file blob, then, if it's found, also fetch the file metadata.
             // Returns the requested file as Result<(Option<Vec<u8>>, Option<Metadata>)>
             // Produces a Result::Err if either the blob or metadata get returned an error
-            let res = store
+            let res = datastore
                 .get(store_key.clone())
                 .map(|store_result| store_result.into())
                 .and_then({
@@ -858,7 +1025,7 @@ impl FetchState {
                     |maybe_blob| {
                         Ok((
                             maybe_blob,
-                            store
+                            datastore
                                 .get_meta(store_key)
                                 .map(|store_result| store_result.into())?,
                         ))
@@ -898,17 +1065,19 @@ impl FetchState {
         Ok(())
     }
 
-    pub(crate) fn fetch_contentstore(&mut self, store: &ContentStore) {
+    pub(crate) fn fetch_contentstore(&mut self, store: &ContentStore, mode: FetchMode) {
         let mut pending = self.pending_storekey(FileAttributes::CONTENT);
         if pending.is_empty() {
             return;
         }
+        let start = Instant::now();
         self.metrics.contentstore.fetch(pending.len());
-        if let Err(err) = self.fetch_contentstore_inner(store, &mut pending) {
+        if let Err(err) = self.fetch_contentstore_inner(store, &mut pending, mode) {
             debug!("ContentStore upper error - Error = {err:?}", err = err);
             self.errors.other_error(err);
             self.metrics.contentstore.err(pending.len());
         }
+        self.metrics.contentstore.time(start.elapsed());
     }
 
     // TODO(meyer): Improve how local caching works. At the very least do this in the background.
@@ -983,3 +1152,51 @@ impl FetchState {
         self.common.results(self.errors);
     }
 }
+
+/// Minimum number of keys given to each thread when sharding local lookups. Below this,
+/// the cost of spawning threads outweighs the parallelism.
+const MIN_KEYS_PER_SHARD: usize = 1000;
+
+/// Look up `keys` in the indexedlog `store`.
+///
+/// Large batches are sorted and split into up to `shards` contiguous key ranges, each looked
+/// up on its own thread with its own read handle on the store. The results of all shards are
+/// concatenated, so callers see the same results as a single-threaded scan, though not
+/// necessarily in the same order.
+fn get_raw_entries(
+    store: &IndexedLogHgIdDataStore,
+    mut keys: Vec<Key>,
+    shards: usize,
+) -> Vec<(Key, Result<Option<Entry>>)> {
+    let shards = shards.min(keys.len() / MIN_KEYS_PER_SHARD);
+    if shards <= 1 {
+        return keys
+            .into_iter()
+            .map(|key| {
+                let res = store.get_raw_entry(&key);
+                (key, res)
+            })
+            .collect();
+    }
+
+    keys.sort_unstable_by_key(|key| key.hgid);
+    let shard_size = (keys.len() + shards - 1) / shards;
+    crossbeam::thread::scope(|scope| {
+        let handles: Vec<_> = keys
+            .chunks(shard_size)
+            .map(|shard| {
+                scope.spawn(move |_| {
+                    shard
+                        .iter()
+                        .map(|key| (key.clone(), store.get_raw_entry(key)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("local fetch shard panicked"))
+            .collect()
+    })
+    .expect("local fetch shard panicked")
+}
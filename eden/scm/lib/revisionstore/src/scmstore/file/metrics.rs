@@ -7,6 +7,7 @@
 
 use std::ops::AddAssign;
 use std::sync::Arc;
+use std::time::Duration;
 
 use parking_lot::RwLock;
 
@@ -42,6 +43,14 @@ impl ContentStoreFetchMetrics {
         self.common.err(keys)
     }
 
+    pub(crate) fn bytes(&mut self, bytes: usize) {
+        self.common.bytes(bytes)
+    }
+
+    pub(crate) fn time(&mut self, elapsed: Duration) {
+        self.common.time(elapsed)
+    }
+
     pub(crate) fn hit_lfsptr(&mut self, keys: usize) {
         self.lfsptr_hits += keys;
     }
@@ -65,6 +74,9 @@ pub struct FileStoreFetchMetrics {
     pub(crate) indexedlog: LocalAndCacheFetchMetrics,
     pub(crate) lfs: LocalAndCacheFetchMetrics,
     pub(crate) aux: LocalAndCacheFetchMetrics,
+    pub(crate) memcache: FetchMetrics,
+    pub(crate) edenapi: FetchMetrics,
+    pub(crate) lfs_remote: FetchMetrics,
     pub(crate) contentstore: ContentStoreFetchMetrics,
 }
 
@@ -73,6 +85,9 @@ impl AddAssign for FileStoreFetchMetrics {
         self.indexedlog += rhs.indexedlog;
         self.lfs += rhs.lfs;
         self.aux += rhs.aux;
+        self.memcache += rhs.memcache;
+        self.edenapi += rhs.edenapi;
+        self.lfs_remote += rhs.lfs_remote;
         self.contentstore += rhs.contentstore;
     }
 }
@@ -82,6 +97,9 @@ impl FileStoreFetchMetrics {
         namespaced("indexedlog", self.indexedlog.metrics())
             .chain(namespaced("lfs", self.lfs.metrics()))
             .chain(namespaced("aux", self.aux.metrics()))
+            .chain(namespaced("memcache", self.memcache.metrics()))
+            .chain(namespaced("edenapi", self.edenapi.metrics()))
+            .chain(namespaced("lfs_remote", self.lfs_remote.metrics()))
             .chain(namespaced("contentstore", self.contentstore.metrics()))
     }
 }
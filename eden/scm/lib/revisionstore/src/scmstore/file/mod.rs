@@ -7,13 +7,17 @@
 
 mod fetch;
 mod metrics;
+mod similarity;
 mod types;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
 
+use ::types::HgId;
 use ::types::Key;
 use ::types::RepoPathBuf;
 use anyhow::anyhow;
@@ -35,6 +39,7 @@ pub use self::types::FileAttributes;
 pub use self::types::FileAuxData;
 pub(crate) use self::types::LazyFile;
 pub use self::types::StoreFile;
+use crate::cancel::CancellationToken;
 use crate::datastore::HgIdDataStore;
 use crate::datastore::HgIdMutableDeltaStore;
 use crate::datastore::RemoteDataStore;
@@ -47,10 +52,14 @@ use crate::lfs::lfs_from_hg_file_blob;
 use crate::lfs::LfsPointersEntry;
 use crate::lfs::LfsRemote;
 use crate::lfs::LfsStore;
+use crate::memcache::MemcacheWriteThrough;
 use crate::memcache::MEMCACHE_DELAY;
-use crate::remotestore::HgIdRemoteStore;
+use crate::priority::FetchPriority;
 use crate::scmstore::activitylogger::ActivityLogger;
+use crate::scmstore::fetch::FetchCause;
+use crate::scmstore::fetch::FetchMode;
 use crate::scmstore::fetch::FetchResults;
+use crate::scmstore::fetch::KeyFetchError;
 use crate::ContentDataStore;
 use crate::ContentMetadata;
 use crate::ContentStore;
@@ -61,7 +70,7 @@ use crate::LegacyStore;
 use crate::LocalStore;
 use crate::MemcacheStore;
 use crate::Metadata;
-use crate::MultiplexDeltaStore;
+use crate::PinStore;
 use crate::RepackLocation;
 use crate::StoreKey;
 use crate::StoreResult;
@@ -77,6 +86,8 @@ pub struct FileStore {
     /// Allow explicitly writing serialized LFS pointers outside of tests
     pub(crate) allow_write_lfs_ptrs: bool,
     pub(crate) prefer_computing_aux_data: bool,
+    /// Number of threads to split large local indexedlog lookups across
+    pub(crate) local_fetch_shards: usize,
 
     // Record remote fetches
     pub(crate) fetch_logger: Option<Arc<FetchLogger>>,
@@ -87,12 +98,21 @@ pub struct FileStore {
 
     // Local non-lfs cache aka shared store
     pub(crate) indexedlog_cache: Option<Arc<IndexedLogHgIdDataStore>>,
+    /// The non-lfs cache of the previous cache root, while the cache is being relocated. It is
+    /// only read, see `CacheLayout`.
+    pub(crate) indexedlog_cache_previous: Option<Arc<IndexedLogHgIdDataStore>>,
+    /// Keys of the non-lfs cache that are never evicted.
+    pub(crate) pins: Option<Arc<PinStore>>,
 
     // Local LFS cache aka shared store
     pub(crate) lfs_cache: Option<Arc<LfsStore>>,
+    /// The LFS cache of the previous cache root, only read, see `CacheLayout`.
+    pub(crate) lfs_cache_previous: Option<Arc<LfsStore>>,
 
     // Memcache
     pub(crate) memcache: Option<Arc<MemcacheStore>>,
+    /// If present, content fetched from EdenAPI is written to memcache in the background.
+    pub(crate) memcache_writethrough: Option<Arc<MemcacheWriteThrough>>,
 
     // Remote stores
     pub(crate) lfs_remote: Option<Arc<LfsRemote>>,
@@ -104,6 +124,8 @@ pub struct FileStore {
     // Aux Data Stores
     pub(crate) aux_local: Option<Arc<AuxStore>>,
     pub(crate) aux_cache: Option<Arc<AuxStore>>,
+    /// The aux cache of the previous cache root, only read, see `CacheLayout`.
+    pub(crate) aux_cache_previous: Option<Arc<AuxStore>>,
 
     // Metrics, statistics, debugging
     pub(crate) activity_logger: Option<Arc<Mutex<ActivityLogger>>>,
@@ -131,19 +153,71 @@ impl FileStore {
         &self,
         keys: impl Iterator<Item = Key>,
         attrs: FileAttributes,
+    ) -> FetchResults<StoreFile> {
+        self.fetch_with_cause(keys, attrs, FetchCause::unspecified())
+    }
+
+    /// Like `fetch`, but attributes the fetch to `cause` in logs, metrics, and the activity log.
+    pub fn fetch_with_cause(
+        &self,
+        keys: impl Iterator<Item = Key>,
+        attrs: FileAttributes,
+        cause: FetchCause,
+    ) -> FetchResults<StoreFile> {
+        self.fetch_with_cancel(keys, attrs, cause, CancellationToken::new())
+    }
+
+    /// Like `fetch_with_cause`, but stop fetching from the remote stores once `cancel` is
+    /// cancelled. The keys that weren't fetched yet are reported with a `FetchCancelled` error.
+    pub fn fetch_with_cancel(
+        &self,
+        keys: impl Iterator<Item = Key>,
+        attrs: FileAttributes,
+        cause: FetchCause,
+        cancel: CancellationToken,
+    ) -> FetchResults<StoreFile> {
+        self.fetch_with_priority(keys, attrs, cause, cancel, FetchPriority::Interactive)
+    }
+
+    /// Like `fetch_with_cancel`, but `Background` fetches are sent to EdenAPI in batches, and
+    /// the interactive fetches in flight go first between batches.
+    pub fn fetch_with_priority(
+        &self,
+        keys: impl Iterator<Item = Key>,
+        attrs: FileAttributes,
+        cause: FetchCause,
+        cancel: CancellationToken,
+        priority: FetchPriority,
+    ) -> FetchResults<StoreFile> {
+        self.fetch_with_mode(keys, attrs, cause, cancel, priority, FetchMode::AllowRemote)
+    }
+
+    /// Like `fetch_with_priority`, but only use the stores allowed by `mode`.
+    pub fn fetch_with_mode(
+        &self,
+        keys: impl Iterator<Item = Key>,
+        attrs: FileAttributes,
+        cause: FetchCause,
+        cancel: CancellationToken,
+        priority: FetchPriority,
+        mode: FetchMode,
     ) -> FetchResults<StoreFile> {
         let (found_tx, found_rx) = unbounded();
-        let mut state = FetchState::new(keys, attrs, &self, found_tx);
+        let mut state = FetchState::new(keys, attrs, &self, found_tx, cause, cancel, priority);
 
         let keys_len = state.pending_len();
 
         let aux_cache = self.aux_cache.clone();
+        let aux_cache_previous = self.aux_cache_previous.clone();
         let aux_local = self.aux_local.clone();
         let indexedlog_cache = self.indexedlog_cache.clone();
+        let indexedlog_cache_previous = self.indexedlog_cache_previous.clone();
         let indexedlog_local = self.indexedlog_local.clone();
         let lfs_cache = self.lfs_cache.clone();
+        let lfs_cache_previous = self.lfs_cache_previous.clone();
         let lfs_local = self.lfs_local.clone();
         let memcache = self.memcache.clone();
+        let memcache_writethrough = self.memcache_writethrough.clone();
         let edenapi = self.edenapi.clone();
         let lfs_remote = self.lfs_remote.clone();
         let contentstore = self.contentstore.clone();
@@ -160,40 +234,57 @@ impl FileStore {
             let span = tracing::span!(
                 tracing::Level::DEBUG,
                 "file fetch",
-                id = rand::thread_rng().gen::<u16>()
+                id = rand::thread_rng().gen::<u16>(),
+                cause = %state.cause()
             );
             let _enter = span.enter();
 
-            if let Some(ref aux_cache) = aux_cache {
-                state.fetch_aux_indexedlog(aux_cache, StoreType::Shared);
-            }
+            if mode.allows_local() {
+                if let Some(ref aux_cache) = aux_cache {
+                    state.fetch_aux_indexedlog(aux_cache, StoreType::Shared);
+                }
 
-            if let Some(ref aux_local) = aux_local {
-                state.fetch_aux_indexedlog(aux_local, StoreType::Local);
-            }
+                if let Some(ref aux_cache_previous) = aux_cache_previous {
+                    state.fetch_aux_indexedlog(aux_cache_previous, StoreType::Shared);
+                }
 
-            if let Some(ref indexedlog_cache) = indexedlog_cache {
-                state.fetch_indexedlog(indexedlog_cache, StoreType::Shared);
-            }
+                if let Some(ref aux_local) = aux_local {
+                    state.fetch_aux_indexedlog(aux_local, StoreType::Local);
+                }
 
-            if let Some(ref indexedlog_local) = indexedlog_local {
-                state.fetch_indexedlog(indexedlog_local, StoreType::Local);
-            }
+                if let Some(ref indexedlog_cache) = indexedlog_cache {
+                    state.fetch_indexedlog(indexedlog_cache, StoreType::Shared);
+                }
 
-            if let Some(ref lfs_cache) = lfs_cache {
-                state.fetch_lfs(lfs_cache, StoreType::Shared);
-            }
+                if let Some(ref indexedlog_cache_previous) = indexedlog_cache_previous {
+                    state.fetch_indexedlog(indexedlog_cache_previous, StoreType::Shared);
+                }
+
+                if let Some(ref indexedlog_local) = indexedlog_local {
+                    state.fetch_indexedlog(indexedlog_local, StoreType::Local);
+                }
+
+                if let Some(ref lfs_cache) = lfs_cache {
+                    state.fetch_lfs(lfs_cache, StoreType::Shared);
+                }
 
-            if let Some(ref lfs_local) = lfs_local {
-                state.fetch_lfs(lfs_local, StoreType::Local);
+                if let Some(ref lfs_cache_previous) = lfs_cache_previous {
+                    state.fetch_lfs(lfs_cache_previous, StoreType::Shared);
+                }
+
+                if let Some(ref lfs_local) = lfs_local {
+                    state.fetch_lfs(lfs_local, StoreType::Local);
+                }
             }
 
-            if use_memcache(creation_time) {
+            if mode.allows_remote() && use_memcache(creation_time) {
                 if let Some(ref memcache) = memcache {
                     state.fetch_memcache(memcache, indexedlog_cache.as_ref().map(|s| s.as_ref()));
                 }
             }
 
+            state.record_local_misses();
+
             if prefer_computing_aux_data {
                 state.derive_computable(
                     aux_cache.as_ref().map(|s| s.as_ref()),
@@ -201,26 +292,43 @@ impl FileStore {
                 );
             }
 
-            if let Some(ref edenapi) = edenapi {
-                state.fetch_edenapi(
-                    edenapi,
-                    indexedlog_cache.clone(),
-                    lfs_cache.clone(),
-                    aux_cache.clone(),
-                    if cache_to_memcache && use_memcache(creation_time) {
-                        memcache.clone()
-                    } else {
-                        None
-                    },
-                );
-            }
+            if mode.allows_remote() {
+                if let Some(ref edenapi) = edenapi {
+                    state.fetch_edenapi(
+                        edenapi,
+                        indexedlog_cache.clone(),
+                        lfs_cache.clone(),
+                        aux_cache.clone(),
+                        if cache_to_memcache && use_memcache(creation_time) {
+                            memcache.clone()
+                        } else {
+                            None
+                        },
+                        if cache_to_memcache && use_memcache(creation_time) {
+                            memcache_writethrough.clone()
+                        } else {
+                            None
+                        },
+                    );
+                }
 
-            if let Some(ref lfs_remote) = lfs_remote {
-                state.fetch_lfs_remote(&lfs_remote.remote, lfs_local.clone(), lfs_cache.clone());
+                if let Some(ref lfs_remote) = lfs_remote {
+                    if !state.is_cancelled() {
+                        state.fetch_lfs_remote(
+                            &lfs_remote.remote,
+                            lfs_local.clone(),
+                            lfs_cache.clone(),
+                        );
+                    }
+                }
             }
 
+            // The legacy ContentStore holds both local packs and remote stores, so it's used in
+            // every mode and only reads its local side under LocalOnly.
             if let Some(ref contentstore) = contentstore {
-                state.fetch_contentstore(contentstore);
+                if !state.is_cancelled() {
+                    state.fetch_contentstore(contentstore, mode);
+                }
             }
 
             state.derive_computable(
@@ -229,14 +337,16 @@ impl FileStore {
             );
 
             metrics.write().fetch += state.metrics().clone();
+            let cause = state.cause().clone();
             state.finish();
 
             if let Some(activity_logger) = activity_logger {
-                if let Err(err) =
-                    activity_logger
-                        .lock()
-                        .log_file_fetch(all_keys, attrs, start_instant.elapsed())
-                {
+                if let Err(err) = activity_logger.lock().log_file_fetch(
+                    all_keys,
+                    attrs,
+                    start_instant.elapsed(),
+                    cause,
+                ) {
                     tracing::error!("Error writing activity log: {}", err);
                 }
             }
@@ -284,7 +394,18 @@ impl FileStore {
         let indexedlog_local = self.indexedlog_local.as_ref().ok_or_else(|| {
             anyhow!("trying to write non-LFS file but no local non-LFS IndexedLog is available")
         })?;
-        indexedlog_local.put_entry(Entry::new(key, bytes, meta))?;
+        match self.contentstore.as_ref().and_then(|c| c.dualwrite()) {
+            Some(dualwrite) => {
+                indexedlog_local.put_entry(Entry::new(key.clone(), bytes.clone(), meta))?;
+                let delta = Delta {
+                    data: bytes,
+                    base: None,
+                    key,
+                };
+                dualwrite.mirror_to_legacy(&delta, &meta);
+            }
+            None => indexedlog_local.put_entry(Entry::new(key, bytes, meta))?,
+        }
 
         Ok(())
     }
@@ -327,6 +448,145 @@ impl FileStore {
         Ok(())
     }
 
+    /// Upload the LFS files of `keys` to the LFS server.
+    ///
+    /// The keys are translated to the content hashes of their LFS pointers in the local LFS
+    /// store. Keys without a local LFS pointer, like non-LFS files, are returned as not uploaded.
+    pub fn upload_lfs(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        match self.lfs_remote {
+            Some(ref lfs_remote) => lfs_remote.upload(keys),
+            None => Ok(keys.to_vec()),
+        }
+    }
+
+    /// Pin the keys in the shared cache, so they are never evicted by GC or by the rotation of
+    /// the cache. Keys that are not in the cache yet are kept once they are fetched.
+    pub fn pin(&self, keys: &[Key]) -> Result<()> {
+        let pins = self
+            .pins
+            .as_ref()
+            .ok_or_else(|| anyhow!("no shared cache to pin keys in"))?;
+        pins.pin(keys)?;
+        pins.flush()
+    }
+
+    /// Allow the keys to be evicted from the shared cache again.
+    pub fn unpin(&self, keys: &[Key]) -> Result<()> {
+        let pins = self
+            .pins
+            .as_ref()
+            .ok_or_else(|| anyhow!("no shared cache to pin keys in"))?;
+        pins.unpin(keys)?;
+        pins.flush()
+    }
+
+    /// Write the aux data of `keys` in the shared aux data store to a sidecar file at `path`, to
+    /// seed another machine's store with `import_aux_sidecar`. Keys without aux data are
+    /// skipped. Returns the number of entries written.
+    pub fn export_aux_sidecar(&self, keys: &[Key], path: &Path) -> Result<usize> {
+        let aux_cache = self
+            .aux_cache
+            .as_ref()
+            .ok_or_else(|| anyhow!("no shared aux data store to export from"))?;
+        let hgids: Vec<HgId> = keys.iter().map(|key| key.hgid).collect();
+        aux_cache.export_sidecar(&hgids, path)
+    }
+
+    /// Add the aux data of a sidecar file written by `export_aux_sidecar` to the shared aux
+    /// data store. Returns the number of entries added.
+    pub fn import_aux_sidecar(&self, path: &Path) -> Result<usize> {
+        let aux_cache = self
+            .aux_cache
+            .as_ref()
+            .ok_or_else(|| anyhow!("no shared aux data store to import into"))?;
+        aux_cache.import_sidecar(path)
+    }
+
+    /// Estimate how similar the contents of two files are, from 0.0 (nothing in common) to 1.0
+    /// (same lines), without returning the contents. See `similarity_batch`.
+    pub fn similarity(&self, a: &Key, b: &Key) -> Result<f64> {
+        let scores = self.similarity_batch(&[(a.clone(), b.clone())], 0.0)?;
+        Ok(scores[0])
+    }
+
+    /// Estimate the similarity of each pair of files, for rename detection and the like.
+    ///
+    /// The score is the share of bytes in lines the files have in common, ignoring the order of
+    /// the lines. Large files are compared on a sample of their lines.
+    ///
+    /// Sizes and hashes are read from the aux data first: files with the same content score 1.0,
+    /// and pairs whose sizes are too far apart to reach `threshold` score 0.0, without fetching
+    /// their contents. The contents of the other files are hashed as they are fetched, and are
+    /// not kept in memory.
+    pub fn similarity_batch(&self, pairs: &[(Key, Key)], threshold: f64) -> Result<Vec<f64>> {
+        let keys: HashSet<Key> = pairs
+            .iter()
+            .flat_map(|(a, b)| [a.clone(), b.clone()])
+            .collect();
+        let mut aux = HashMap::new();
+        for result in self.fetch_for_similarity(keys, FileAttributes::AUX) {
+            let (key, file) = result?;
+            aux.insert(key, file.aux_data()?);
+        }
+
+        let mut scores: Vec<Option<f64>> = pairs
+            .iter()
+            .map(|(a, b)| {
+                let (a, b) = (&aux[a], &aux[b]);
+                if a.content_sha256 == b.content_sha256 {
+                    Some(1.0)
+                } else if similarity::max_similarity(a.total_size as usize, b.total_size as usize)
+                    < threshold
+                {
+                    Some(0.0)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let keys: HashSet<Key> = pairs
+            .iter()
+            .zip(&scores)
+            .filter(|(_, score)| score.is_none())
+            .flat_map(|((a, b), _)| [a.clone(), b.clone()])
+            .collect();
+        let mut fingerprints = HashMap::new();
+        for result in self.fetch_for_similarity(keys, FileAttributes::CONTENT) {
+            let (key, mut file) = result?;
+            let fingerprint = similarity::Fingerprint::new(&file.file_content()?);
+            fingerprints.insert(key, fingerprint);
+        }
+
+        for ((a, b), score) in pairs.iter().zip(scores.iter_mut()) {
+            if score.is_none() {
+                *score = Some(similarity::similarity(&fingerprints[a], &fingerprints[b]));
+            }
+        }
+        Ok(scores.into_iter().flatten().collect())
+    }
+
+    /// Fetch `attrs` of `keys` for `similarity_batch`, failing on the keys which can't be fetched.
+    fn fetch_for_similarity(
+        &self,
+        keys: HashSet<Key>,
+        attrs: FileAttributes,
+    ) -> impl Iterator<Item = Result<(Key, StoreFile)>> {
+        self.fetch_with_cause(keys.into_iter(), attrs, FetchCause::new("similarity"))
+            .into_iter()
+            .filter_map(|result| match result {
+                Ok(found) => Some(Ok(found)),
+                Err(KeyFetchError::KeyedError { key, mut errors }) => {
+                    Some(Err(match errors.pop() {
+                        Some(err) => err.context(format!("failed to fetch {}", key)),
+                        None => anyhow!("failed to fetch {}", key),
+                    }))
+                }
+                // Errors which are not about a key don't prevent comparing the fetched files.
+                Err(KeyFetchError::Other(_)) => None,
+            })
+    }
+
     pub fn local(&self) -> Self {
         FileStore {
             extstored_policy: self.extstored_policy.clone(),
@@ -334,15 +594,20 @@ impl FileStore {
             edenapi_retries: self.edenapi_retries.clone(),
             allow_write_lfs_ptrs: self.allow_write_lfs_ptrs,
             prefer_computing_aux_data: self.prefer_computing_aux_data,
+            local_fetch_shards: self.local_fetch_shards,
 
             indexedlog_local: self.indexedlog_local.clone(),
             lfs_local: self.lfs_local.clone(),
 
             indexedlog_cache: self.indexedlog_cache.clone(),
+            indexedlog_cache_previous: self.indexedlog_cache_previous.clone(),
+            pins: self.pins.clone(),
             lfs_cache: self.lfs_cache.clone(),
+            lfs_cache_previous: self.lfs_cache_previous.clone(),
             cache_to_local_cache: self.cache_to_local_cache.clone(),
 
             memcache: None,
+            memcache_writethrough: None,
             cache_to_memcache: self.cache_to_memcache.clone(),
 
             edenapi: None,
@@ -355,6 +620,7 @@ impl FileStore {
 
             aux_local: self.aux_local.clone(),
             aux_cache: self.aux_cache.clone(),
+            aux_cache_previous: self.aux_cache_previous.clone(),
 
             creation_time: self.creation_time,
 
@@ -379,6 +645,9 @@ impl FileStore {
 
         if let Some(ref indexedlog_cache) = self.indexedlog_cache {
             indexedlog_cache.flush_log().map_err(&mut handle_error);
+            if let Some(ref pins) = self.pins {
+                retain_pinned(indexedlog_cache, pins).map_err(&mut handle_error);
+            }
         }
 
         if let Some(ref lfs_local) = self.lfs_local {
@@ -397,6 +666,10 @@ impl FileStore {
             aux_cache.flush().map_err(&mut handle_error);
         }
 
+        if let Some(dualwrite) = self.contentstore.as_ref().and_then(|c| c.dualwrite()) {
+            dualwrite.flush_legacy().map_err(&mut handle_error);
+        }
+
         result
     }
 
@@ -411,15 +684,20 @@ impl FileStore {
             edenapi_retries: 0,
             allow_write_lfs_ptrs: false,
             prefer_computing_aux_data: false,
+            local_fetch_shards: 1,
 
             indexedlog_local: None,
             lfs_local: None,
 
             indexedlog_cache: None,
+            indexedlog_cache_previous: None,
+            pins: None,
             lfs_cache: None,
+            lfs_cache_previous: None,
             cache_to_local_cache: true,
 
             memcache: None,
+            memcache_writethrough: None,
             cache_to_memcache: true,
 
             edenapi: None,
@@ -432,6 +710,7 @@ impl FileStore {
 
             aux_local: None,
             aux_cache: None,
+            aux_cache_previous: None,
 
             creation_time: Instant::now(),
             lfs_progress: AggregatingProgressBar::new("fetching", "LFS"),
@@ -440,6 +719,18 @@ impl FileStore {
     }
 }
 
+/// Copy the pinned entries to the newest generation of the cache, in case it was just rotated.
+fn retain_pinned(indexedlog_cache: &IndexedLogHgIdDataStore, pins: &PinStore) -> Result<()> {
+    let pinned = pins.pinned();
+    if pinned.is_empty() {
+        return Ok(());
+    }
+    if indexedlog_cache.retain(&pinned)? > 0 {
+        indexedlog_cache.flush_log()?;
+    }
+    Ok(())
+}
+
 fn use_memcache(creation_time: Instant) -> bool {
     // Only use memcache if the process has been around a while. It takes 2s to setup, which
     // hurts responiveness for short commands.
@@ -461,15 +752,20 @@ impl LegacyStore for FileStore {
             edenapi_retries: self.edenapi_retries.clone(),
             allow_write_lfs_ptrs: self.allow_write_lfs_ptrs,
             prefer_computing_aux_data: self.prefer_computing_aux_data,
+            local_fetch_shards: self.local_fetch_shards,
 
             indexedlog_local: self.indexedlog_cache.clone(),
             lfs_local: self.lfs_cache.clone(),
 
             indexedlog_cache: None,
+            indexedlog_cache_previous: None,
+            pins: None,
             lfs_cache: None,
+            lfs_cache_previous: None,
             cache_to_local_cache: false,
 
             memcache: None,
+            memcache_writethrough: None,
             cache_to_memcache: false,
 
             edenapi: None,
@@ -482,6 +778,7 @@ impl LegacyStore for FileStore {
 
             aux_local: None,
             aux_cache: None,
+            aux_cache_previous: None,
 
             creation_time: Instant::now(),
             lfs_progress: self.lfs_progress.clone(),
@@ -593,37 +890,57 @@ impl HgIdDataStore for FileStore {
 
 impl RemoteDataStore for FileStore {
     fn prefetch(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        self.prefetch_cancellable(keys, &CancellationToken::new())
+    }
+
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        self.prefetch_with_priority(keys, cancel, FetchPriority::Interactive)
+    }
+
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
         self.metrics.write().api.hg_prefetch.call(keys.len());
         let missing = self
-            .fetch(
+            .fetch_with_priority(
                 keys.iter().cloned().filter_map(|sk| sk.maybe_into_key()),
                 FileAttributes::CONTENT,
+                FetchCause::unspecified(),
+                cancel.clone(),
+                priority,
             )
-            .missing()?
-            .into_iter()
-            .map(StoreKey::HgId)
-            .collect();
-        Ok(missing)
+            .missing();
+        if cancel.is_cancelled() {
+            // Persist what was fetched before the cancellation.
+            self.flush()?;
+            cancel.check()?;
+        }
+        Ok(missing?.into_iter().map(StoreKey::HgId).collect())
     }
 
     fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         self.metrics.write().api.hg_upload.call(keys.len());
-        // TODO(meyer): Eliminate usage of legacy API, or at least minimize it (do we really need memcache + multiplex, etc)
-        if let Some(ref lfs_remote) = self.lfs_remote {
-            let mut multiplex = MultiplexDeltaStore::new();
-            multiplex.add_store(self.get_shared_mutable());
-            if use_memcache(self.creation_time) {
-                if let Some(ref memcache) = self.memcache {
-                    multiplex.add_store(memcache.clone());
-                }
+        self.upload_lfs(keys)
+    }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        let mut missing = keys.to_vec();
+        if let Some(ref edenapi) = self.edenapi {
+            missing = edenapi.lookup_missing(&missing)?;
+        }
+        if let Some(ref contentstore) = self.contentstore {
+            if !missing.is_empty() {
+                missing = contentstore.get_remote_missing(&missing)?;
             }
-            lfs_remote
-                .clone()
-                .datastore(Arc::new(multiplex))
-                .upload(keys)
-        } else {
-            Ok(keys.to_vec())
         }
+        Ok(missing)
     }
 }
 
@@ -641,6 +958,27 @@ impl LocalStore for FileStore {
             .map(StoreKey::HgId)
             .collect())
     }
+
+    /// Like `get_missing`, but only consults the indexes of the local stores.
+    fn contains(&self, key: &StoreKey) -> Result<bool> {
+        let indexedlogs = [
+            &self.indexedlog_local,
+            &self.indexedlog_cache,
+            &self.indexedlog_cache_previous,
+        ];
+        for store in indexedlogs.into_iter().flatten() {
+            if store.contains(key)? {
+                return Ok(true);
+            }
+        }
+        let lfs_stores = [&self.lfs_local, &self.lfs_cache, &self.lfs_cache_previous];
+        for store in lfs_stores.into_iter().flatten() {
+            if store.contains(key)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 }
 
 impl HgIdMutableDeltaStore for FileStore {
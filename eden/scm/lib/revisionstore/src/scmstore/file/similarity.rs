@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Cheap estimate of how similar two files are, for rename detection and the like.
+//!
+//! Files are compared line by line: the score is the share of bytes the two files have in
+//! common, `2 * common / (len_a + len_b)`, like `similar.score` in Python. Lines are compared by
+//! hash, ignoring their order, and only a sample of the lines of large files is hashed.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+/// Files larger than this only hash a sample of their lines, about this many bytes worth.
+const SAMPLE_TARGET_BYTES: usize = 1 << 20;
+
+/// The sampled lines of a file.
+pub(crate) struct Fingerprint {
+    len: usize,
+    /// Only the lines whose hash is a multiple of `rate` are kept. This is a power of two, so the
+    /// lines sampled at a coarser rate are a subset of the lines sampled at a finer one.
+    rate: u64,
+    /// Hash of a sampled line -> (number of occurrences, length of the line).
+    lines: HashMap<u64, (usize, usize)>,
+}
+
+impl Fingerprint {
+    pub(crate) fn new(data: &[u8]) -> Self {
+        let rate = ((data.len() / SAMPLE_TARGET_BYTES) as u64 + 1).next_power_of_two();
+        let mut lines = HashMap::new();
+        for line in data.split_inclusive(|b| *b == b'\n') {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(line);
+            let hash = hasher.finish();
+            if hash % rate == 0 {
+                lines.entry(hash).or_insert((0, line.len())).0 += 1;
+            }
+        }
+        Fingerprint {
+            len: data.len(),
+            rate,
+            lines,
+        }
+    }
+
+    /// Number of bytes in the lines sampled at `rate`.
+    fn sampled_len(&self, rate: u64) -> usize {
+        self.sampled(rate)
+            .map(|(_, (count, len))| count * len)
+            .sum()
+    }
+
+    fn sampled(&self, rate: u64) -> impl Iterator<Item = (&u64, &(usize, usize))> + '_ {
+        self.lines.iter().filter(move |(hash, _)| *hash % rate == 0)
+    }
+}
+
+/// Best score files of these sizes can reach: all of the smaller file is in the larger one.
+pub(crate) fn max_similarity(len_a: usize, len_b: usize) -> f64 {
+    if len_a == 0 && len_b == 0 {
+        return 1.0;
+    }
+    2.0 * len_a.min(len_b) as f64 / (len_a + len_b) as f64
+}
+
+/// Score how similar two files are, from 0.0 (no line in common) to 1.0 (same lines).
+pub(crate) fn similarity(a: &Fingerprint, b: &Fingerprint) -> f64 {
+    if a.len == 0 || b.len == 0 {
+        return max_similarity(a.len, b.len);
+    }
+
+    let rate = a.rate.max(b.rate);
+    let common: usize = a
+        .sampled(rate)
+        .filter_map(|(hash, (count, len))| {
+            let (other_count, _) = b.lines.get(hash)?;
+            Some(*count.min(other_count) * len)
+        })
+        .sum();
+    let total = a.sampled_len(rate) + b.sampled_len(rate);
+    if total == 0 {
+        // No line was sampled.
+        return 0.0;
+    }
+    2.0 * common as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(a: &[u8], b: &[u8]) -> f64 {
+        similarity(&Fingerprint::new(a), &Fingerprint::new(b))
+    }
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(score(b"", b""), 1.0);
+        assert_eq!(score(b"a\n", b""), 0.0);
+        assert_eq!(score(b"a\nb\n", b"a\nb\n"), 1.0);
+        assert_eq!(score(b"a\nb\n", b"b\na\n"), 1.0);
+        assert_eq!(score(b"a\nb\n", b"c\nd\n"), 0.0);
+        assert_eq!(score(b"a\nb\n", b"a\nc\n"), 0.5);
+        assert_eq!(score(b"a\na\nb\n", b"a\nb\n"), 0.8);
+    }
+
+    #[test]
+    fn test_sampled_similarity() {
+        let line = |i: usize| format!("line {}\n", i).into_bytes();
+        let a: Vec<u8> = (0..300_000).flat_map(line).collect();
+        let b: Vec<u8> = (0..300_000)
+            .flat_map(|i| line(if i % 2 == 0 { i } else { i + 1_000_000 }))
+            .collect();
+        assert!(a.len() > SAMPLE_TARGET_BYTES);
+
+        assert_eq!(score(&a, &a), 1.0);
+        let half = score(&a, &b);
+        assert!((0.4..0.6).contains(&half), "{}", half);
+    }
+
+    #[test]
+    fn test_max_similarity() {
+        assert_eq!(max_similarity(0, 0), 1.0);
+        assert_eq!(max_similarity(10, 0), 0.0);
+        assert_eq!(max_similarity(10, 30), 0.5);
+    }
+}
@@ -6,8 +6,10 @@
  */
 
 use std::ops::AddAssign;
+use std::time::Duration;
 
 use crate::indexedlogutil::StoreType;
+use crate::scmstore::fetch::FetchCause;
 
 #[derive(Clone, Debug, Default)]
 pub struct FetchMetrics {
@@ -25,6 +27,12 @@ pub struct FetchMetrics {
 
     /// Number of entities which returned a fetch error (including batch errors)
     errors: usize,
+
+    /// Number of bytes read from this backend for successfully fetched entities
+    bytes: usize,
+
+    /// Total time spent fetching from this backend, in microseconds
+    time_us: usize,
 }
 
 impl AddAssign for FetchMetrics {
@@ -34,6 +42,8 @@ impl AddAssign for FetchMetrics {
         self.hits += rhs.hits;
         self.misses += rhs.misses;
         self.errors += rhs.errors;
+        self.bytes += rhs.bytes;
+        self.time_us += rhs.time_us;
     }
 }
 
@@ -55,6 +65,14 @@ impl FetchMetrics {
         self.errors += keys;
     }
 
+    pub(crate) fn bytes(&mut self, bytes: usize) {
+        self.bytes += bytes;
+    }
+
+    pub(crate) fn time(&mut self, elapsed: Duration) {
+        self.time_us += elapsed.as_micros() as usize;
+    }
+
     pub(crate) fn metrics(&self) -> impl Iterator<Item = (&'static str, usize)> {
         [
             ("requests", self.requests),
@@ -62,6 +80,8 @@ impl FetchMetrics {
             ("hits", self.hits),
             ("misses", self.misses),
             ("errors", self.errors),
+            ("bytes", self.bytes),
+            ("time_us", self.time_us),
         ]
         .into_iter()
         .filter(|&(_, v)| v != 0)
@@ -106,6 +126,22 @@ impl AddAssign for LocalAndCacheFetchMetrics {
     }
 }
 
+/// Report a fetch of `keys` entities, `misses` of which couldn't be served by the local and
+/// cache stores, to the telemetry sink.
+///
+/// Causes are provided by callers, so the cause is attached to the sample as a tag rather than
+/// made part of a counter name, which would create an unbounded number of counters.
+pub(crate) fn log_fetch_cause(store: &'static str, cause: &FetchCause, keys: usize, misses: usize) {
+    if misses != 0 {
+        tracing::debug!(
+            "Local and cache misses - Cause = {cause}, Count = {count}",
+            cause = cause,
+            count = misses
+        );
+    }
+    tracing::debug!(target: "scmstore_fetch_cause", store, cause = %cause, keys, misses);
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct WriteMetrics {
     /// Numbers of entities we attempted to write
@@ -7,11 +7,17 @@
 
 pub use self::builder::FileStoreBuilder;
 pub use self::builder::TreeStoreBuilder;
+pub use self::fetch::FetchCause;
+pub use self::fetch::FetchMode;
 pub use self::fetch::KeyFetchError;
 pub use self::file::FileAttributes;
 pub use self::file::FileAuxData;
 pub use self::file::FileStore;
 pub use self::file::StoreFile;
+pub use self::tree::types::DirectoryAggregates;
+pub use self::tree::types::StoreTree;
+pub use self::tree::types::TreeAttributes;
+pub use self::tree::types::TreeAuxData;
 pub use self::tree::TreeStore;
 pub use self::util::file_to_async_key_stream;
 
@@ -0,0 +1,62 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::ops::AddAssign;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::scmstore::metrics::namespaced;
+use crate::scmstore::metrics::FetchMetrics;
+use crate::scmstore::metrics::LocalAndCacheFetchMetrics;
+
+#[derive(Clone, Debug, Default)]
+pub struct TreeStoreFetchMetrics {
+    pub(crate) indexedlog: LocalAndCacheFetchMetrics,
+    pub(crate) page_cache: FetchMetrics,
+    pub(crate) tree_aux: FetchMetrics,
+    pub(crate) memcache: FetchMetrics,
+    pub(crate) edenapi: FetchMetrics,
+    pub(crate) contentstore: FetchMetrics,
+}
+
+impl AddAssign for TreeStoreFetchMetrics {
+    fn add_assign(&mut self, rhs: Self) {
+        self.indexedlog += rhs.indexedlog;
+        self.page_cache += rhs.page_cache;
+        self.tree_aux += rhs.tree_aux;
+        self.memcache += rhs.memcache;
+        self.edenapi += rhs.edenapi;
+        self.contentstore += rhs.contentstore;
+    }
+}
+
+impl TreeStoreFetchMetrics {
+    fn metrics(&self) -> impl Iterator<Item = (String, usize)> {
+        namespaced("indexedlog", self.indexedlog.metrics())
+            .chain(namespaced("page_cache", self.page_cache.metrics()))
+            .chain(namespaced("tree_aux", self.tree_aux.metrics()))
+            .chain(namespaced("memcache", self.memcache.metrics()))
+            .chain(namespaced("edenapi", self.edenapi.metrics()))
+            .chain(namespaced("contentstore", self.contentstore.metrics()))
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TreeStoreMetrics {
+    pub(crate) fetch: TreeStoreFetchMetrics,
+}
+
+impl TreeStoreMetrics {
+    pub fn new() -> Arc<RwLock<Self>> {
+        Arc::new(RwLock::new(TreeStoreMetrics::default()))
+    }
+
+    pub fn metrics(&self) -> impl Iterator<Item = (String, usize)> {
+        namespaced("scmstore.tree", namespaced("fetch", self.fetch.metrics()))
+    }
+}
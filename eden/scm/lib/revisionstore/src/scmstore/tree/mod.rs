@@ -10,6 +10,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
 
+use ::types::HgId;
 use ::types::Key;
 use ::types::Node;
 use ::types::RepoPath;
@@ -20,23 +21,39 @@ use anyhow::Result;
 use crossbeam::channel::unbounded;
 use edenapi_types::TreeChildEntry;
 use minibytes::Bytes;
+use parking_lot::RwLock;
 use tracing::field;
 
+mod metrics;
 pub mod types;
 
+pub use self::metrics::TreeStoreFetchMetrics;
+pub use self::metrics::TreeStoreMetrics;
+
 use crate::datastore::HgIdDataStore;
 use crate::datastore::RemoteDataStore;
+use crate::indexedlogauxstore::AuxStore;
 use crate::indexedlogdatastore::Entry;
 use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
+use crate::indexedlogtreeauxstore::TreeAuxStore;
+use crate::indexedlogutil::StoreType;
+use crate::memcache;
 use crate::memcache::MEMCACHE_DELAY;
 use crate::scmstore::fetch::CommonFetchState;
+use crate::scmstore::fetch::FetchCause;
 use crate::scmstore::fetch::FetchErrors;
+use crate::scmstore::fetch::FetchMode;
 use crate::scmstore::fetch::FetchResults;
 use crate::scmstore::fetch::KeyFetchError;
 use crate::scmstore::file::FileStore;
+use crate::scmstore::metrics::log_fetch_cause;
+use crate::scmstore::metrics::FetchMetrics;
+use crate::scmstore::tree::types::DirectoryAggregates;
 use crate::scmstore::tree::types::LazyTree;
 use crate::scmstore::tree::types::StoreTree;
 use crate::scmstore::tree::types::TreeAttributes;
+use crate::scmstore::FileAuxData;
+use crate::treepagestore::TreePageStore;
 use crate::util;
 use crate::ContentDataStore;
 use crate::ContentMetadata;
@@ -60,6 +77,17 @@ pub struct TreeStore {
     /// a remote store.
     pub indexedlog_cache: Option<Arc<IndexedLogHgIdDataStore>>,
 
+    /// The "cache" indexedlog store of the previous cache root, while the cache is being
+    /// relocated. It is only read, see `CacheLayout`.
+    pub indexedlog_cache_previous: Option<Arc<IndexedLogHgIdDataStore>>,
+
+    /// If provided, trees downloaded from a remote store are cached in packed pages there instead
+    /// of in indexedlog_cache, which is still read. See `TreePageStore`.
+    pub page_cache: Option<Arc<TreePageStore>>,
+
+    /// If provided, the directory aggregates computed with tree aux data are cached there.
+    pub tree_aux_cache: Option<Arc<TreeAuxStore>>,
+
     /// If cache_to_local_cache is true, data found by falling back to a remote store
     /// will the written to indexedlog_cache.
     pub cache_to_local_cache: bool,
@@ -86,6 +114,8 @@ pub struct TreeStore {
     pub creation_time: Instant,
 
     pub flush_on_drop: bool,
+
+    pub(crate) metrics: Arc<RwLock<TreeStoreMetrics>>,
 }
 
 impl Drop for TreeStore {
@@ -98,197 +128,104 @@ impl Drop for TreeStore {
 
 impl TreeStore {
     pub fn fetch_batch(&self, reqs: impl Iterator<Item = Key>) -> Result<FetchResults<StoreTree>> {
+        self.fetch_batch_with_cause(reqs, FetchCause::unspecified())
+    }
+
+    /// Like `fetch_batch`, but attributes the fetch to `cause` in logs.
+    pub fn fetch_batch_with_cause(
+        &self,
+        reqs: impl Iterator<Item = Key>,
+        cause: FetchCause,
+    ) -> Result<FetchResults<StoreTree>> {
+        self.fetch_batch_with_attrs(reqs, TreeAttributes::CONTENT, cause)
+    }
+
+    /// Fetch the requested `attrs` for each of `reqs`. Aux data is computed from the tree
+    /// content, with child file aux data read from the FileStore's aux stores.
+    pub fn fetch_batch_with_attrs(
+        &self,
+        reqs: impl Iterator<Item = Key>,
+        attrs: TreeAttributes,
+        cause: FetchCause,
+    ) -> Result<FetchResults<StoreTree>> {
+        self.fetch_batch_with_mode(reqs, attrs, cause, FetchMode::AllowRemote)
+    }
+
+    /// Like `fetch_batch_with_attrs`, but only use the stores allowed by `mode`.
+    pub fn fetch_batch_with_mode(
+        &self,
+        reqs: impl Iterator<Item = Key>,
+        attrs: TreeAttributes,
+        cause: FetchCause,
+        mode: FetchMode,
+    ) -> Result<FetchResults<StoreTree>> {
         let (found_tx, found_rx) = unbounded();
         let found_tx2 = found_tx.clone();
-        let mut common: CommonFetchState<StoreTree> =
-            CommonFetchState::new(reqs, TreeAttributes::CONTENT, found_tx);
+        let mut common: CommonFetchState<StoreTree> = CommonFetchState::new(reqs, attrs, found_tx);
 
         let keys_len = common.pending_len();
 
-        let indexedlog_cache = self.indexedlog_cache.clone();
-        let indexedlog_local = self.indexedlog_local.clone();
-        let memcache = self.memcache.clone();
-        let edenapi = self.edenapi.clone();
-        let contentstore = self.contentstore.clone();
-        let creation_time = self.creation_time;
-        let cache_to_memcache = self.cache_to_memcache;
-        let cache_to_local_cache = self.cache_to_local_cache;
         let (aux_local, aux_cache) = if let Some(ref filestore) = self.filestore {
             (filestore.aux_local.clone(), filestore.aux_cache.clone())
         } else {
             (None, None)
         };
-        let process_func = move || -> Result<()> {
-            if let Some(ref indexedlog_cache) = indexedlog_cache {
-                let pending: Vec<_> = common
-                    .pending(TreeAttributes::CONTENT, false)
-                    .map(|(key, _attrs)| key.clone())
-                    .collect();
-                for key in pending.into_iter() {
-                    if let Some(entry) = indexedlog_cache.get_entry(key)? {
-                        common.found(entry.key().clone(), LazyTree::IndexedLog(entry).into());
-                    }
-                }
-            }
+        let stores = TreeFetchStores {
+            indexedlog_cache: self.indexedlog_cache.clone(),
+            indexedlog_cache_previous: self.indexedlog_cache_previous.clone(),
+            page_cache: self.page_cache.clone(),
+            tree_aux_cache: self.tree_aux_cache.clone(),
+            indexedlog_local: self.indexedlog_local.clone(),
+            memcache: self.memcache.clone(),
+            edenapi: self.edenapi.clone(),
+            contentstore: self.contentstore.clone(),
+            aux_local,
+            aux_cache,
+            creation_time: self.creation_time,
+            cache_to_memcache: self.cache_to_memcache,
+            cache_to_local_cache: self.cache_to_local_cache,
+        };
+        let store_metrics = self.metrics.clone();
+        let process_func = move |metrics: &mut TreeStoreFetchMetrics| -> Result<()> {
+            let span = tracing::debug_span!("tree fetch", cause = %cause);
+            let _enter = span.enter();
 
-            if let Some(ref indexedlog_local) = indexedlog_local {
-                let pending: Vec<_> = common
-                    .pending(TreeAttributes::CONTENT, false)
-                    .map(|(key, _attrs)| key.clone())
-                    .collect();
-                for key in pending.into_iter() {
-                    if let Some(entry) = indexedlog_local.get_entry(key)? {
-                        common.found(entry.key().clone(), LazyTree::IndexedLog(entry).into());
-                    }
-                }
+            if mode.allows_local() {
+                stores.fetch_local(&mut common, metrics)?;
             }
 
-            if use_memcache(creation_time) {
-                if let Some(ref memcache) = memcache {
-                    let pending: Vec<_> = common
-                        .pending(TreeAttributes::CONTENT, false)
-                        .map(|(key, _attrs)| key.clone())
-                        .collect();
-
-                    if !pending.is_empty() {
-                        for entry in memcache.get_data_iter(&pending)? {
-                            let entry = entry?;
-                            let key = entry.key.clone();
-                            let entry = LazyTree::Memcache(entry);
-                            if indexedlog_cache.is_some() && cache_to_local_cache {
-                                if let Some(entry) = entry.indexedlog_cache_entry(key.clone())? {
-                                    indexedlog_cache.as_ref().unwrap().put_entry(entry)?;
-                                }
-                            }
-                            common.found(key, entry.into());
-                        }
-                    }
-                }
+            if mode.allows_remote() {
+                stores.fetch_memcache(&mut common, metrics)?;
             }
 
-            if let Some(ref edenapi) = edenapi {
-                let pending: Vec<_> = common
-                    .pending(TreeAttributes::CONTENT, false)
-                    .map(|(key, _attrs)| key.clone())
-                    .collect();
-                if !pending.is_empty() {
-                    let span = tracing::info_span!(
-                        "fetch_edenapi",
-                        downloaded = field::Empty,
-                        uploaded = field::Empty,
-                        requests = field::Empty,
-                        time = field::Empty,
-                        latency = field::Empty,
-                        download_speed = field::Empty,
-                    );
-                    let _enter = span.enter();
-                    let attributes = if aux_local.is_some() {
-                        Some(edenapi_types::TreeAttributes {
-                            child_metadata: true,
-                            ..edenapi_types::TreeAttributes::default()
-                        })
-                    } else {
-                        None
-                    };
-                    let response = edenapi
-                        .trees_blocking(pending, attributes)
-                        .map_err(|e| e.tag_network())?;
-                    for entry in response.entries {
-                        let entry = entry?;
-                        let key = entry.key.clone();
-                        if let Some(ref aux_local) = aux_local {
-                            if let Some(ref children) = entry.children {
-                                for file_entry in children {
-                                    let file_entry = match file_entry {
-                                        Ok(file_entry) => file_entry,
-                                        Err(err) => {
-                                            // not failing tree fetching for aux related problems
-                                            tracing::warn!("Error fetching child entry: {:?}", err);
-                                            continue;
-                                        }
-                                    };
-                                    if let TreeChildEntry::File(file_entry) = file_entry {
-                                        if let Some(metadata) = file_entry.file_metadata {
-                                            let aux_entry = crate::indexedlogauxstore::Entry {
-                                                total_size: metadata.size.unwrap(),
-                                                content_id: metadata.content_id.unwrap(),
-                                                content_sha1: metadata.content_sha1.unwrap(),
-                                                content_sha256: metadata.content_sha256.unwrap(),
-                                            };
-                                            if let Some(ref aux_cache) = aux_cache {
-                                                aux_cache.put(file_entry.key.hgid, &aux_entry)?;
-                                            } else {
-                                                aux_local.put(file_entry.key.hgid, &aux_entry)?;
-                                            }
-                                        }
-                                    }
-                                }
-                            } else {
-                                // this is odd, need to log
-                                tracing::warn!(
-                                    "No children returned when requested tree {}",
-                                    entry.key.hgid
-                                );
-                            }
-                        }
-                        let entry = LazyTree::EdenApi(entry);
-                        if indexedlog_cache.is_some() && cache_to_local_cache {
-                            if let Some(entry) = entry.indexedlog_cache_entry(key.clone())? {
-                                indexedlog_cache.as_ref().unwrap().put_entry(entry)?;
-                            }
-                        }
-                        if memcache.is_some() && cache_to_memcache && use_memcache(creation_time) {
-                            if let Some(entry) = entry.indexedlog_cache_entry(key.clone())? {
-                                memcache.as_ref().unwrap().add_mcdata(entry.try_into()?);
-                            }
-                        }
-                        common.found(key, entry.into());
-                    }
-                    util::record_edenapi_stats(&span, &response.stats);
-                }
+            log_fetch_cause("tree", &cause, keys_len, common.pending_len());
+
+            if mode.allows_remote() {
+                stores.fetch_edenapi(&mut common, metrics)?;
             }
 
-            if let Some(ref contentstore) = contentstore {
-                let pending: Vec<_> = common
-                    .pending(TreeAttributes::CONTENT, false)
-                    .map(|(key, _attrs)| StoreKey::HgId(key.clone()))
-                    .collect();
-                if !pending.is_empty() {
-                    contentstore.prefetch(&pending)?;
-
-                    let pending = pending.into_iter().map(|key| match key {
-                        StoreKey::HgId(key) => key,
-                        // Safe because we constructed pending with only StoreKey::HgId above
-                        // we're just re-using the already allocated paths in the keys
-                        _ => unreachable!("unexpected non-HgId StoreKey"),
-                    });
-
-                    for key in pending {
-                        let store_key = StoreKey::HgId(key.clone());
-                        let blob = match contentstore.get(store_key.clone())? {
-                            StoreResult::Found(v) => Some(v),
-                            StoreResult::NotFound(_k) => None,
-                        };
-                        let meta = match contentstore.get_meta(store_key)? {
-                            StoreResult::Found(v) => Some(v),
-                            StoreResult::NotFound(_k) => None,
-                        };
-
-                        if let (Some(blob), Some(meta)) = (blob, meta) {
-                            // We don't write to local indexedlog or memcache for contentstore fallbacks because
-                            // contentstore handles that internally.
-                            common.found(key, LazyTree::ContentStore(blob.into(), meta).into());
-                        }
-                    }
-                }
+            stores.fetch_contentstore(&mut common, metrics, mode)?;
+
+            let mut errors = FetchErrors::new();
+            if attrs.aux_data {
+                derive_aux_data(
+                    &mut common,
+                    &mut errors,
+                    stores.aux_cache.as_deref(),
+                    stores.aux_local.as_deref(),
+                    stores.tree_aux_cache.as_deref(),
+                );
             }
 
             // TODO(meyer): Report incomplete / not found, handle errors better instead of just always failing the batch, etc
-            common.results(FetchErrors::new());
+            common.results(errors);
             Ok(())
         };
         let process_func_errors = move || {
-            if let Err(err) = process_func() {
+            let mut metrics = TreeStoreFetchMetrics::default();
+            let res = process_func(&mut metrics);
+            store_metrics.write().fetch += metrics;
+            if let Err(err) = res {
                 let _ = found_tx2.send(Err(KeyFetchError::Other(err)));
             }
         };
@@ -317,6 +254,9 @@ impl TreeStore {
         TreeStore {
             indexedlog_local: self.indexedlog_local.clone(),
             indexedlog_cache: self.indexedlog_cache.clone(),
+            indexedlog_cache_previous: self.indexedlog_cache_previous.clone(),
+            page_cache: self.page_cache.clone(),
+            tree_aux_cache: self.tree_aux_cache.clone(),
             cache_to_local_cache: false,
             memcache: None,
             cache_to_memcache: false,
@@ -326,6 +266,7 @@ impl TreeStore {
             // TODO(meyer): Do we actually need the outer FileStore / TreeStore to be Arc'd?
             filestore: self.filestore.as_ref().map(|store| Arc::new(store.local())),
             flush_on_drop: false,
+            metrics: self.metrics.clone(),
         }
     }
 
@@ -334,6 +275,9 @@ impl TreeStore {
             indexedlog_local: None,
 
             indexedlog_cache: None,
+            indexedlog_cache_previous: None,
+            page_cache: None,
+            tree_aux_cache: None,
             cache_to_local_cache: true,
 
             memcache: None,
@@ -346,6 +290,7 @@ impl TreeStore {
             filestore: None,
             creation_time: Instant::now(),
             flush_on_drop: true,
+            metrics: TreeStoreMetrics::new(),
         }
     }
 
@@ -365,8 +310,523 @@ impl TreeStore {
             indexedlog_cache.flush_log().map_err(&mut handle_error);
         }
 
+        if let Some(ref page_cache) = self.page_cache {
+            page_cache.flush().map_err(&mut handle_error);
+        }
+
+        if let Some(ref tree_aux_cache) = self.tree_aux_cache {
+            tree_aux_cache.flush().map_err(&mut handle_error);
+        }
+
         result
     }
+
+    pub fn metrics(&self) -> Vec<(String, usize)> {
+        self.metrics.read().metrics().collect()
+    }
+}
+
+/// The stores used by a tree fetch, cloned from the `TreeStore` so that the fetch can run in
+/// another thread.
+struct TreeFetchStores {
+    indexedlog_cache: Option<Arc<IndexedLogHgIdDataStore>>,
+    indexedlog_cache_previous: Option<Arc<IndexedLogHgIdDataStore>>,
+    page_cache: Option<Arc<TreePageStore>>,
+    tree_aux_cache: Option<Arc<TreeAuxStore>>,
+    indexedlog_local: Option<Arc<IndexedLogHgIdDataStore>>,
+    memcache: Option<Arc<MemcacheStore>>,
+    edenapi: Option<Arc<EdenApiTreeStore>>,
+    contentstore: Option<Arc<ContentStore>>,
+    aux_local: Option<Arc<AuxStore>>,
+    aux_cache: Option<Arc<AuxStore>>,
+    creation_time: Instant,
+    cache_to_memcache: bool,
+    cache_to_local_cache: bool,
+}
+
+impl TreeFetchStores {
+    /// Fetch from the local and cache stores, which don't go to the network.
+    fn fetch_local(
+        &self,
+        common: &mut CommonFetchState<StoreTree>,
+        metrics: &mut TreeStoreFetchMetrics,
+    ) -> Result<()> {
+        // The cached aux data of a tree doesn't list its children, so it can only be used
+        // when the content isn't requested.
+        if let (Some(tree_aux_cache), false) = (&self.tree_aux_cache, common.request_attrs.content)
+        {
+            fetch_tree_aux_cache(common, &mut metrics.tree_aux, tree_aux_cache)?;
+        }
+
+        if let Some(ref page_cache) = self.page_cache {
+            fetch_page_cache(common, &mut metrics.page_cache, page_cache)?;
+        }
+
+        for indexedlog_cache in self
+            .indexedlog_cache
+            .iter()
+            .chain(self.indexedlog_cache_previous.iter())
+        {
+            fetch_indexedlog(
+                common,
+                metrics.indexedlog.store(StoreType::Shared),
+                indexedlog_cache,
+            )?;
+        }
+
+        if let Some(ref indexedlog_local) = self.indexedlog_local {
+            fetch_indexedlog(
+                common,
+                metrics.indexedlog.store(StoreType::Local),
+                indexedlog_local,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn fetch_memcache(
+        &self,
+        common: &mut CommonFetchState<StoreTree>,
+        metrics: &mut TreeStoreFetchMetrics,
+    ) -> Result<()> {
+        let memcache = match self.memcache {
+            Some(ref memcache) if use_memcache(self.creation_time) => memcache,
+            _ => return Ok(()),
+        };
+        let pending: Vec<_> = common
+            .pending(TreeAttributes::CONTENT, true)
+            .map(|(key, _attrs)| key.clone())
+            .collect();
+        let pending = memcache::without_quarantined(pending);
+        if pending.is_empty() || !memcache.available() {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        metrics.memcache.fetch(pending.len());
+        let mut found = 0;
+        let entries = match memcache.get_data_iter(&pending) {
+            Ok(entries) => entries,
+            Err(err) => {
+                memcache.record_fetch(pending.len(), 0, 1);
+                return Err(err);
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    metrics.memcache.err(1);
+                    // Entries which fail to decode are fetched from the next stores.
+                    if memcache::record_fetch_error(&err) {
+                        continue;
+                    }
+                    memcache.record_fetch(pending.len(), found, 1);
+                    return Err(err);
+                }
+            };
+            found += 1;
+            metrics.memcache.hit(1);
+            metrics.memcache.bytes(entry.data.len());
+            let key = entry.key.clone();
+            let entry = LazyTree::Memcache(entry);
+            self.cache_to_local_cache(&entry, &key)?;
+            common.found(key, entry.into());
+        }
+        memcache.record_fetch(pending.len(), found, 0);
+        metrics.memcache.miss(pending.len().saturating_sub(found));
+        metrics.memcache.time(start.elapsed());
+        Ok(())
+    }
+
+    fn fetch_edenapi(
+        &self,
+        common: &mut CommonFetchState<StoreTree>,
+        metrics: &mut TreeStoreFetchMetrics,
+    ) -> Result<()> {
+        let edenapi = match self.edenapi {
+            Some(ref edenapi) => edenapi,
+            None => return Ok(()),
+        };
+        let pending: Vec<_> = common
+            .pending(TreeAttributes::CONTENT, true)
+            .map(|(key, _attrs)| key.clone())
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let span = tracing::info_span!(
+            "fetch_edenapi",
+            downloaded = field::Empty,
+            uploaded = field::Empty,
+            requests = field::Empty,
+            time = field::Empty,
+            latency = field::Empty,
+            download_speed = field::Empty,
+        );
+        let _enter = span.enter();
+        let attributes = if self.aux_local.is_some() {
+            Some(edenapi_types::TreeAttributes {
+                child_metadata: true,
+                ..edenapi_types::TreeAttributes::default()
+            })
+        } else {
+            None
+        };
+        let start = Instant::now();
+        let keys = pending.len();
+        metrics.edenapi.fetch(keys);
+        let response = edenapi
+            .trees_blocking(pending, attributes)
+            .map_err(|e| e.tag_network())?;
+        let mut found = 0;
+        for entry in response.entries {
+            let entry = entry?;
+            found += 1;
+            metrics.edenapi.hit(1);
+            metrics
+                .edenapi
+                .bytes(entry.data.as_ref().map_or(0, |data| data.len()));
+            let key = entry.key.clone();
+            if let Some(ref aux_local) = self.aux_local {
+                cache_child_aux_data(&entry, self.aux_cache.as_deref(), aux_local)?;
+            }
+            let entry = LazyTree::EdenApi(entry);
+            self.cache_to_local_cache(&entry, &key)?;
+            if let Some(ref memcache) = self.memcache {
+                if self.cache_to_memcache
+                    && use_memcache(self.creation_time)
+                    && memcache.available()
+                {
+                    if let Some(entry) = entry.indexedlog_cache_entry(key.clone())? {
+                        memcache.add_mcdata(entry.try_into()?);
+                        memcache.record_write();
+                    }
+                }
+            }
+            common.found(key, entry.into());
+        }
+        metrics.edenapi.miss(keys.saturating_sub(found));
+        metrics.edenapi.time(start.elapsed());
+        util::record_edenapi_stats(&span, &response.stats);
+        Ok(())
+    }
+
+    /// The legacy ContentStore holds both local packs and remote stores, so it's used in every
+    /// mode and only reads its local side under LocalOnly.
+    fn fetch_contentstore(
+        &self,
+        common: &mut CommonFetchState<StoreTree>,
+        metrics: &mut TreeStoreFetchMetrics,
+        mode: FetchMode,
+    ) -> Result<()> {
+        let contentstore: &ContentStore = match self.contentstore {
+            Some(ref contentstore) => contentstore,
+            None => return Ok(()),
+        };
+        let pending: Vec<_> = common
+            .pending(TreeAttributes::CONTENT, true)
+            .map(|(key, _attrs)| key.clone())
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let start = Instant::now();
+        metrics.contentstore.fetch(pending.len());
+        let datastore: &dyn HgIdDataStore = if mode.allows_remote() {
+            let store_keys: Vec<_> = pending.iter().cloned().map(StoreKey::HgId).collect();
+            contentstore.prefetch(&store_keys)?;
+            contentstore
+        } else {
+            contentstore.local_and_cache()
+        };
+
+        for key in pending {
+            let store_key = StoreKey::HgId(key.clone());
+            let blob = match datastore.get(store_key.clone())? {
+                StoreResult::Found(v) => Some(v),
+                StoreResult::NotFound(_k) => None,
+            };
+            let meta = match datastore.get_meta(store_key)? {
+                StoreResult::Found(v) => Some(v),
+                StoreResult::NotFound(_k) => None,
+            };
+
+            if let (Some(blob), Some(meta)) = (blob, meta) {
+                metrics.contentstore.hit(1);
+                metrics.contentstore.bytes(blob.len());
+                // We don't write to local indexedlog or memcache for contentstore fallbacks because
+                // contentstore handles that internally.
+                common.found(key, LazyTree::ContentStore(blob.into(), meta).into());
+            } else {
+                metrics.contentstore.miss(1);
+            }
+        }
+        metrics.contentstore.time(start.elapsed());
+        Ok(())
+    }
+
+    /// Write a tree found in a remote store to the local cache, if enabled.
+    fn cache_to_local_cache(&self, entry: &LazyTree, key: &Key) -> Result<()> {
+        if !self.cache_to_local_cache {
+            return Ok(());
+        }
+        cache_tree(
+            entry,
+            key,
+            self.page_cache.as_deref(),
+            self.indexedlog_cache.as_deref(),
+        )
+    }
+}
+
+fn fetch_tree_aux_cache(
+    common: &mut CommonFetchState<StoreTree>,
+    metrics: &mut FetchMetrics,
+    tree_aux_cache: &TreeAuxStore,
+) -> Result<()> {
+    let pending: Vec<_> = common
+        .pending(TreeAttributes::AUX, false)
+        .map(|(key, _attrs)| key.clone())
+        .collect();
+    let start = Instant::now();
+    metrics.fetch(pending.len());
+    for key in pending.into_iter() {
+        match tree_aux_cache.get(key.hgid) {
+            // Aggregates missing from the cache are computed again, in case the aux data of the
+            // children is now available.
+            Ok(Some(aux_data)) if aux_data.aggregates.is_some() => {
+                metrics.hit(1);
+                let tree = StoreTree {
+                    content: None,
+                    aux_data: Some(aux_data),
+                };
+                common.found(key, tree);
+            }
+            Ok(_) => metrics.miss(1),
+            Err(err) => {
+                metrics.err(1);
+                return Err(err);
+            }
+        }
+    }
+    metrics.time(start.elapsed());
+    Ok(())
+}
+
+fn fetch_page_cache(
+    common: &mut CommonFetchState<StoreTree>,
+    metrics: &mut FetchMetrics,
+    page_cache: &TreePageStore,
+) -> Result<()> {
+    let pending: Vec<_> = common
+        .pending(TreeAttributes::CONTENT, true)
+        .map(|(key, _attrs)| key.clone())
+        .collect();
+    let start = Instant::now();
+    metrics.fetch(pending.len());
+    for key in pending.into_iter() {
+        match page_cache.get(&key.hgid) {
+            Ok(Some(data)) => {
+                metrics.hit(1);
+                metrics.bytes(data.len());
+                let entry = Entry::new(key.clone(), data, Metadata::default());
+                common.found(key, LazyTree::IndexedLog(entry).into());
+            }
+            Ok(None) => metrics.miss(1),
+            Err(err) => {
+                metrics.err(1);
+                return Err(err);
+            }
+        }
+    }
+    metrics.time(start.elapsed());
+    Ok(())
+}
+
+fn fetch_indexedlog(
+    common: &mut CommonFetchState<StoreTree>,
+    metrics: &mut FetchMetrics,
+    store: &IndexedLogHgIdDataStore,
+) -> Result<()> {
+    let pending: Vec<_> = common
+        .pending(TreeAttributes::CONTENT, true)
+        .map(|(key, _attrs)| key.clone())
+        .collect();
+    let start = Instant::now();
+    metrics.fetch(pending.len());
+    for key in pending.into_iter() {
+        match store.get_entry(key) {
+            Ok(Some(entry)) => {
+                metrics.hit(1);
+                metrics.bytes(entry.stored_len());
+                common.found(entry.key().clone(), LazyTree::IndexedLog(entry).into());
+            }
+            Ok(None) => metrics.miss(1),
+            Err(err) => {
+                metrics.err(1);
+                return Err(err);
+            }
+        }
+    }
+    metrics.time(start.elapsed());
+    Ok(())
+}
+
+/// Write the file aux data returned by EdenApi along with a tree.
+fn cache_child_aux_data(
+    entry: &edenapi_types::TreeEntry,
+    aux_cache: Option<&AuxStore>,
+    aux_local: &AuxStore,
+) -> Result<()> {
+    let children = match entry.children {
+        Some(ref children) => children,
+        None => {
+            // this is odd, need to log
+            tracing::warn!(
+                "No children returned when requested tree {}",
+                entry.key.hgid
+            );
+            return Ok(());
+        }
+    };
+    for file_entry in children {
+        let file_entry = match file_entry {
+            Ok(file_entry) => file_entry,
+            Err(err) => {
+                // not failing tree fetching for aux related problems
+                tracing::warn!("Error fetching child entry: {:?}", err);
+                continue;
+            }
+        };
+        if let TreeChildEntry::File(file_entry) = file_entry {
+            if let Some(metadata) = file_entry.file_metadata {
+                let aux_entry = match (
+                    metadata.size,
+                    metadata.content_id,
+                    metadata.content_sha1,
+                    metadata.content_sha256,
+                ) {
+                    (
+                        Some(total_size),
+                        Some(content_id),
+                        Some(content_sha1),
+                        Some(content_sha256),
+                    ) => crate::indexedlogauxstore::Entry {
+                        total_size,
+                        content_id,
+                        content_sha1,
+                        content_sha256,
+                    },
+                    _ => {
+                        // Only complete aux data can be cached.
+                        tracing::warn!(
+                            "Partial aux data returned for file {}",
+                            file_entry.key.hgid
+                        );
+                        continue;
+                    }
+                };
+                match aux_cache {
+                    Some(aux_cache) => aux_cache.put(file_entry.key.hgid, &aux_entry)?,
+                    None => aux_local.put(file_entry.key.hgid, &aux_entry)?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a tree found in a remote store to the page cache if there is one, or else to the
+/// indexedlog cache.
+fn cache_tree(
+    entry: &LazyTree,
+    key: &Key,
+    page_cache: Option<&TreePageStore>,
+    indexedlog_cache: Option<&IndexedLogHgIdDataStore>,
+) -> Result<()> {
+    if page_cache.is_none() && indexedlog_cache.is_none() {
+        return Ok(());
+    }
+    let mut entry = match entry.indexedlog_cache_entry(key.clone())? {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+    match (page_cache, indexedlog_cache) {
+        (Some(page_cache), _) => page_cache.put(key.hgid, entry.content()?),
+        (None, Some(indexedlog_cache)) => indexedlog_cache.put_entry(entry),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Compute aux data for found trees which were fetched with content but without aux data.
+///
+/// The computed aux data is written to `tree_aux_cache`, unless it's already there. Deeper trees
+/// are processed first so that the aggregates of a tree can use those of its subtrees fetched in
+/// the same batch.
+fn derive_aux_data(
+    common: &mut CommonFetchState<StoreTree>,
+    errors: &mut FetchErrors,
+    aux_cache: Option<&AuxStore>,
+    aux_local: Option<&AuxStore>,
+    tree_aux_cache: Option<&TreeAuxStore>,
+) {
+    let file_aux = |hgid: HgId| -> Result<Option<FileAuxData>> {
+        for store in [aux_cache, aux_local].into_iter().flatten() {
+            if let Some(entry) = store.get(hgid)? {
+                return Ok(Some(entry.into()));
+            }
+        }
+        Ok(None)
+    };
+    let tree_aux = |hgid: HgId| -> Result<Option<DirectoryAggregates>> {
+        match tree_aux_cache {
+            Some(store) => Ok(store.get(hgid)?.and_then(|aux_data| aux_data.aggregates)),
+            None => Ok(None),
+        }
+    };
+    let cache_aux_data = |hgid: HgId, tree: &StoreTree| -> Result<()> {
+        if let (Some(store), Some(aux_data)) = (tree_aux_cache, &tree.aux_data) {
+            let cached = store.get(hgid)?;
+            let is_new = match cached {
+                None => true,
+                Some(cached) => cached.aggregates.is_none() && aux_data.aggregates.is_some(),
+            };
+            if is_new {
+                store.put(hgid, aux_data)?;
+            }
+        }
+        Ok(())
+    };
+
+    let mut keys = common.pending.iter().cloned().collect::<Vec<_>>();
+    keys.sort_by_key(|key| std::cmp::Reverse(key.path.components().count()));
+    for key in keys {
+        let mut tree = match common.found.remove(&key) {
+            Some(tree) if tree.content.is_some() && tree.aux_data.is_none() => tree,
+            Some(tree) => {
+                common.found.insert(key, tree);
+                continue;
+            }
+            None => continue,
+        };
+        match tree.compute_aux_data(&key.path, &file_aux, &tree_aux) {
+            Ok(()) => {
+                if let Err(err) = cache_aux_data(key.hgid, &tree) {
+                    // The aux data is still valid, only the cache is missing it.
+                    tracing::warn!("Error caching tree aux data: {:?}", err);
+                }
+                common.found(key, tree);
+            }
+            Err(err) => {
+                errors.keyed_error(key.clone(), err);
+                common.found.insert(key, tree);
+            }
+        }
+    }
 }
 
 fn use_memcache(creation_time: Instant) -> bool {
@@ -387,6 +847,9 @@ impl LegacyStore for TreeStore {
         Arc::new(TreeStore {
             indexedlog_local: self.indexedlog_cache.clone(),
             indexedlog_cache: None,
+            indexedlog_cache_previous: None,
+            page_cache: None,
+            tree_aux_cache: None,
             cache_to_local_cache: false,
 
             memcache: None,
@@ -398,6 +861,7 @@ impl LegacyStore for TreeStore {
             filestore: None,
             creation_time: Instant::now(),
             flush_on_drop: true,
+            metrics: self.metrics.clone(),
         })
     }
 
@@ -501,6 +965,19 @@ impl RemoteDataStore for TreeStore {
     fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         Ok(keys.to_vec())
     }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        let mut missing = keys.to_vec();
+        if let Some(ref edenapi) = self.edenapi {
+            missing = edenapi.lookup_missing(&missing)?;
+        }
+        if let Some(ref contentstore) = self.contentstore {
+            if !missing.is_empty() {
+                missing = contentstore.get_remote_missing(&missing)?;
+            }
+        }
+        Ok(missing)
+    }
 }
 
 impl LocalStore for TreeStore {
@@ -560,6 +1037,12 @@ impl HgIdMutableDeltaStore for TreeStore {
         if let Some(ref indexedlog_cache) = self.indexedlog_cache {
             indexedlog_cache.flush_log()?;
         }
+        if let Some(ref page_cache) = self.page_cache {
+            page_cache.flush()?;
+        }
+        if let Some(ref tree_aux_cache) = self.tree_aux_cache {
+            tree_aux_cache.flush()?;
+        }
         Ok(None)
     }
 }
@@ -599,3 +1082,164 @@ impl storemodel::TreeStore for TreeStore {
         unimplemented!("not needed yet");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ::types::testutil::*;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+    use crate::indexedlogutil::StoreType;
+    use crate::localstore::ExtStoredPolicy;
+    use crate::types::ContentHash;
+
+    #[test]
+    fn test_fetch_aux_data() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let indexedlog = Arc::new(IndexedLogHgIdDataStore::new(
+            &tmp,
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Local,
+        )?);
+
+        let content = Bytes::from(format!("a\0{}\n", hgid("1").to_hex()).into_bytes());
+        let k = key("dir", "2");
+        indexedlog.put_entry(Entry::new(k.clone(), content.clone(), Metadata::default()))?;
+
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(indexedlog);
+
+        let tree = store
+            .fetch_batch_with_attrs(
+                std::iter::once(k.clone()),
+                TreeAttributes::AUX,
+                FetchCause::unspecified(),
+            )?
+            .single()?
+            .expect("tree not found");
+        assert!(tree.content.is_none());
+
+        let aux_data = tree.aux_data()?;
+        assert_eq!(aux_data.total_size, content.len() as u64);
+        assert_eq!(
+            aux_data.content_sha256,
+            ContentHash::sha256(&content).unwrap_sha256()
+        );
+        assert_eq!(
+            aux_data.content_blake3.into_inner(),
+            *blake3::hash(&content).as_bytes()
+        );
+        // No file store, so no child aux data is available.
+        assert!(aux_data.child_metadata.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_aux_data_aggregates() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let indexedlog = Arc::new(IndexedLogHgIdDataStore::new(
+            tmp.path().join("indexedlog"),
+            ExtStoredPolicy::Use,
+            &config,
+            StoreType::Local,
+        )?);
+        let aux_cache = Arc::new(AuxStore::new(
+            tmp.path().join("aux"),
+            &Default::default(),
+            StoreType::Shared,
+        )?);
+        let tree_aux_cache = Arc::new(TreeAuxStore::new(
+            tmp.path().join("treeaux"),
+            &Default::default(),
+        )?);
+
+        let file_aux = |size| crate::indexedlogauxstore::Entry {
+            total_size: size,
+            ..Default::default()
+        };
+        aux_cache.put(hgid("1"), &file_aux(10))?;
+        aux_cache.put(hgid("3"), &file_aux(5))?;
+
+        let sub = key("dir/sub", "4");
+        let sub_content = format!("b\0{}\n", hgid("3").to_hex());
+        let dir = key("dir", "2");
+        let dir_content = format!("a\0{}\nsub\0{}t\n", hgid("1").to_hex(), sub.hgid.to_hex());
+        for (k, content) in [(&sub, sub_content), (&dir, dir_content)] {
+            indexedlog.put_entry(Entry::new(
+                k.clone(),
+                Bytes::from(content.into_bytes()),
+                Metadata::default(),
+            ))?;
+        }
+
+        let mut filestore = FileStore::empty();
+        filestore.aux_cache = Some(aux_cache);
+        let mut store = TreeStore::empty();
+        store.indexedlog_local = Some(indexedlog);
+        store.filestore = Some(Arc::new(filestore));
+        store.tree_aux_cache = Some(tree_aux_cache.clone());
+
+        // The subtree is fetched in the same batch, after its parent.
+        let (found, _missing, _errors) = store
+            .fetch_batch_with_attrs(
+                vec![dir.clone(), sub.clone()].into_iter(),
+                TreeAttributes::AUX,
+                FetchCause::unspecified(),
+            )?
+            .consume();
+        let expected = DirectoryAggregates {
+            entry_count: 2,
+            descendant_file_count: 2,
+            descendant_size: 15,
+            max_depth: 1,
+        };
+        assert_eq!(found[&dir].aux_data()?.aggregates, Some(expected));
+        let cached_aggregates = |hgid| -> Result<Option<DirectoryAggregates>> {
+            Ok(tree_aux_cache
+                .get(hgid)?
+                .and_then(|aux_data| aux_data.aggregates))
+        };
+        assert_eq!(cached_aggregates(dir.hgid)?, Some(expected));
+        assert_eq!(
+            cached_aggregates(sub.hgid)?,
+            Some(DirectoryAggregates {
+                entry_count: 1,
+                descendant_file_count: 1,
+                descendant_size: 5,
+                max_depth: 0,
+            })
+        );
+
+        // The cached aux data is served without the tree content.
+        let mut cache_only = TreeStore::empty();
+        cache_only.tree_aux_cache = Some(tree_aux_cache.clone());
+        let tree = cache_only
+            .fetch_batch_with_attrs(
+                std::iter::once(dir.clone()),
+                TreeAttributes::AUX,
+                FetchCause::unspecified(),
+            )?
+            .single()?
+            .expect("tree not found");
+        let aux_data = tree.aux_data()?;
+        assert_eq!(aux_data.aggregates, Some(expected));
+        assert_eq!(
+            aux_data.content_sha256,
+            found[&dir].aux_data()?.content_sha256
+        );
+        assert!(aux_data.child_metadata.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use serde::Deserialize;
+use serde::Serialize;
+use types::Blake3;
+use types::Key;
+use types::Sha256;
+
+use crate::scmstore::FileAuxData;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct TreeAuxData {
+    /// Size of the tree, as encoded in the Mercurial blob.
+    pub total_size: u64,
+    /// SHA-256 of the tree, as encoded in the Mercurial blob.
+    pub content_sha256: Sha256,
+    /// Blake3 of the tree, as encoded in the Mercurial blob.
+    pub content_blake3: Blake3,
+    /// Aux data of the file children which is available locally. Children whose aux data
+    /// hasn't been fetched yet are omitted. Only available when the aux data was computed from
+    /// the tree content, not when it was read from the `TreeAuxStore`.
+    pub child_metadata: Vec<(Key, FileAuxData)>,
+    /// Aggregates of the directory, if the aux data of all its descendants is available.
+    pub aggregates: Option<DirectoryAggregates>,
+}
+
+/// Directory-level aggregates of a tree.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct DirectoryAggregates {
+    /// Number of direct entries of the tree, files and directories.
+    pub entry_count: u64,
+    /// Number of files in the tree and its subtrees.
+    pub descendant_file_count: u64,
+    /// Total size of the files in the tree and its subtrees.
+    pub descendant_size: u64,
+    /// Number of directory levels below the tree, 0 if it has no subdirectories.
+    pub max_depth: u64,
+}
@@ -7,14 +7,21 @@
 
 use anyhow::Result;
 use edenapi_types::TreeEntry;
+use manifest_tree::Flag;
 use manifest_tree::TreeEntry as ManifestTreeEntry;
 use minibytes::Bytes;
 use storemodel::TreeFormat;
+use types::Blake3;
 use types::HgId;
 use types::Key;
+use types::RepoPath;
 
 use crate::indexedlogdatastore::Entry;
 use crate::memcache::McData;
+use crate::scmstore::tree::types::DirectoryAggregates;
+use crate::scmstore::tree::types::TreeAuxData;
+use crate::scmstore::FileAuxData;
+use crate::types::ContentHash;
 use crate::Metadata;
 
 /// A minimal tree enum that simply wraps the possible underlying tree types,
@@ -73,6 +80,64 @@ impl LazyTree {
         })
     }
 
+    /// Compute the aux data associated with this tree from its content. `path` is the path of
+    /// the tree, `file_aux` looks up the aux data of its file children, and `tree_aux` the
+    /// aggregates of its directory children.
+    pub(crate) fn aux_data(
+        &mut self,
+        path: &RepoPath,
+        file_aux: impl Fn(HgId) -> Result<Option<FileAuxData>>,
+        tree_aux: impl Fn(HgId) -> Result<Option<DirectoryAggregates>>,
+    ) -> Result<TreeAuxData> {
+        let content = self.hg_content()?;
+        let mut child_metadata = Vec::new();
+        // Aggregates are only known if they are known for all the children.
+        let mut aggregates = Some(DirectoryAggregates::default());
+        let entry = ManifestTreeEntry(content.clone(), TreeFormat::Hg);
+        for element in entry.elements() {
+            let element = element?;
+            if let Some(ref mut aggregates) = aggregates {
+                aggregates.entry_count += 1;
+            }
+            match element.flag {
+                Flag::File(_) => {
+                    let aux_data = file_aux(element.hgid)?;
+                    aggregates = match (aggregates, &aux_data) {
+                        (Some(mut aggregates), Some(aux_data)) => {
+                            aggregates.descendant_file_count += 1;
+                            aggregates.descendant_size += aux_data.total_size;
+                            Some(aggregates)
+                        }
+                        _ => None,
+                    };
+                    if let Some(aux_data) = aux_data {
+                        let mut child_path = path.to_owned();
+                        child_path.push(element.component.as_path_component());
+                        child_metadata.push((Key::new(child_path, element.hgid), aux_data));
+                    }
+                }
+                Flag::Directory => {
+                    if let Some(current) = aggregates {
+                        aggregates = tree_aux(element.hgid)?.map(|child| DirectoryAggregates {
+                            entry_count: current.entry_count,
+                            descendant_file_count: current.descendant_file_count
+                                + child.descendant_file_count,
+                            descendant_size: current.descendant_size + child.descendant_size,
+                            max_depth: current.max_depth.max(child.max_depth + 1),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(TreeAuxData {
+            total_size: content.len() as u64,
+            content_sha256: ContentHash::sha256(&content).unwrap_sha256(),
+            content_blake3: Blake3::from_byte_array(blake3::hash(&content).into()),
+            child_metadata,
+            aggregates,
+        })
+    }
+
     pub fn manifest_tree_entry(&mut self) -> Result<ManifestTreeEntry> {
         // TODO(meyer): Make manifest-tree crate use minibytes::Bytes
         // Currently revisionstore is only for hg format.
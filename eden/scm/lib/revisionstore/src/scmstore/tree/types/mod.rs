@@ -6,9 +6,12 @@
  */
 
 mod attrs;
+mod auxdata;
 mod lazy_tree;
 mod store_tree;
 
 pub use self::attrs::TreeAttributes;
+pub use self::auxdata::DirectoryAggregates;
+pub use self::auxdata::TreeAuxData;
 pub(crate) use self::lazy_tree::LazyTree;
 pub use self::store_tree::StoreTree;
@@ -10,17 +10,44 @@ use std::ops::BitOr;
 use anyhow::anyhow;
 use anyhow::Result;
 use manifest_tree::TreeEntry as ManifestTreeEntry;
+use types::HgId;
+use types::RepoPath;
 
+use crate::scmstore::tree::types::DirectoryAggregates;
 use crate::scmstore::tree::types::LazyTree;
 use crate::scmstore::tree::types::TreeAttributes;
+use crate::scmstore::tree::types::TreeAuxData;
 use crate::scmstore::value::StoreValue;
+use crate::scmstore::FileAuxData;
 
 #[derive(Debug)]
 pub struct StoreTree {
     pub(crate) content: Option<LazyTree>,
+    pub(crate) aux_data: Option<TreeAuxData>,
 }
 
 impl StoreTree {
+    pub fn aux_data(&self) -> Result<TreeAuxData> {
+        self.aux_data
+            .clone()
+            .ok_or_else(|| anyhow!("no aux data available"))
+    }
+
+    pub(crate) fn compute_aux_data(
+        &mut self,
+        path: &RepoPath,
+        file_aux: impl Fn(HgId) -> Result<Option<FileAuxData>>,
+        tree_aux: impl Fn(HgId) -> Result<Option<DirectoryAggregates>>,
+    ) -> Result<()> {
+        self.aux_data = Some(
+            self.content
+                .as_mut()
+                .ok_or_else(|| anyhow!("failed to compute aux data, no content available"))?
+                .aux_data(path, file_aux, tree_aux)?,
+        );
+        Ok(())
+    }
+
     pub fn manifest_tree_entry(&mut self) -> Result<ManifestTreeEntry> {
         self.content
             .as_mut()
@@ -36,6 +63,7 @@ impl StoreValue for StoreTree {
     fn attrs(&self) -> TreeAttributes {
         TreeAttributes {
             content: self.content.is_some(),
+            aux_data: self.aux_data.is_some(),
         }
     }
 
@@ -43,6 +71,7 @@ impl StoreValue for StoreTree {
     fn mask(self, attrs: TreeAttributes) -> Self {
         StoreTree {
             content: if attrs.content { self.content } else { None },
+            aux_data: if attrs.aux_data { self.aux_data } else { None },
         }
     }
 }
@@ -53,18 +82,25 @@ impl BitOr for StoreTree {
     fn bitor(self, rhs: Self) -> Self::Output {
         StoreTree {
             content: self.content.or(rhs.content),
+            aux_data: self.aux_data.or(rhs.aux_data),
         }
     }
 }
 
 impl Default for StoreTree {
     fn default() -> Self {
-        StoreTree { content: None }
+        StoreTree {
+            content: None,
+            aux_data: None,
+        }
     }
 }
 
 impl From<LazyTree> for StoreTree {
     fn from(v: LazyTree) -> Self {
-        StoreTree { content: Some(v) }
+        StoreTree {
+            content: Some(v),
+            aux_data: None,
+        }
     }
 }
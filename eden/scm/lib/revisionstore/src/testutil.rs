@@ -139,6 +139,17 @@ impl RemoteDataStore for FakeRemoteDataStore {
     fn upload(&self, _keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         unimplemented!()
     }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        Ok(keys
+            .iter()
+            .filter(|k| match k {
+                StoreKey::HgId(k) => !self.map.contains_key(k),
+                StoreKey::Content(_, _) => true,
+            })
+            .cloned()
+            .collect())
+    }
 }
 
 impl HgIdDataStore for FakeRemoteDataStore {
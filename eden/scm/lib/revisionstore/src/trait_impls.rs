@@ -18,6 +18,8 @@ use futures::stream::BoxStream;
 use futures::Stream;
 use futures::StreamExt;
 use minibytes::Bytes;
+use storemodel::FileAuxData;
+use storemodel::ReadFileAuxData;
 use storemodel::ReadFileContents;
 use tokio::runtime::Handle;
 use types::Key;
@@ -56,6 +58,15 @@ impl ReadFileContents for ArcFileStore {
     }
 }
 
+#[async_trait]
+impl ReadFileAuxData for ArcFileStore {
+    type Error = anyhow::Error;
+
+    async fn read_file_aux_data(&self, keys: Vec<Key>) -> BoxStream<Result<(FileAuxData, Key)>> {
+        stream_aux_data_from_scmstore(self.0.clone(), keys).boxed()
+    }
+}
+
 const PREFETCH_CHUNK_SIZE: usize = 1000;
 const FETCH_PARALLELISM: usize = 20;
 
@@ -141,3 +152,42 @@ fn stream_data_from_scmstore(
         })
         .flatten()
 }
+
+fn stream_aux_data_from_scmstore(
+    store: Arc<FileStore>,
+    keys: Vec<Key>,
+) -> impl Stream<Item = Result<(FileAuxData, Key)>> {
+    stream::iter(keys.into_iter())
+        .chunks(PREFETCH_CHUNK_SIZE)
+        .map(move |chunk| {
+            let store = store.clone();
+            Handle::current().spawn_blocking(move || {
+                let mut data = vec![];
+                for result in store.fetch(chunk.iter().cloned(), FileAttributes::AUX) {
+                    let result = match result {
+                        Err(err) => Err(err.into()),
+                        Ok((key, file)) => file.aux_data().map(|aux| {
+                            let aux = FileAuxData {
+                                total_size: aux.total_size,
+                                content_sha256: aux.content_sha256,
+                            };
+                            (aux, key)
+                        }),
+                    };
+                    let is_err = result.is_err();
+                    data.push(result);
+                    if is_err {
+                        break;
+                    }
+                }
+                stream::iter(data.into_iter())
+            })
+        })
+        .buffer_unordered(FETCH_PARALLELISM)
+        .map(|r| {
+            r.unwrap_or_else(|_| {
+                stream::iter(vec![Err(anyhow!("background fetch join error"))].into_iter())
+            })
+        })
+        .flatten()
+}
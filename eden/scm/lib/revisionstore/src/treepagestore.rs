@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Cache of tree blobs packed in pages.
+//!
+//! Trees are small and numerous, so storing each of them as its own indexedlog entry spends a
+//! large part of the store on per-entry headers and checksums. This store buffers trees in memory
+//! and appends them to a shared indexedlog as pages of many trees. The index of the log maps the
+//! node of every tree of a page to the page, and the page starts with an offset table to find the
+//! tree in it.
+//!
+//! The on-disk format of a page is the following:
+//! - Count: 4 unsigned bytes, big-endian
+//! - Count times, sorted by node:
+//!   - Node: <20 bytes>
+//!   - Offset of the tree in the page: 4 unsigned bytes, big-endian
+//!   - Length of the tree: 4 unsigned bytes, big-endian
+//! - The trees
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+use byteorder::BigEndian;
+use byteorder::ByteOrder;
+use byteorder::WriteBytesExt;
+use configparser::config::ConfigSet;
+use configparser::convert::ByteCount;
+use indexedlog::log::IndexOutput;
+use minibytes::Bytes;
+use parking_lot::Mutex;
+use parking_lot::RwLock;
+use types::HgId;
+
+use crate::indexedlogutil::Store;
+use crate::indexedlogutil::StoreOpenOptions;
+use crate::sliceext::SliceExt;
+
+const COUNT_LEN: usize = 4;
+const TABLE_ENTRY_LEN: usize = HgId::len() + 8;
+
+/// Trees that were not written to a page yet.
+#[derive(Default)]
+struct PendingPage {
+    trees: BTreeMap<HgId, Bytes>,
+    size: usize,
+}
+
+pub struct TreePageStore {
+    log: RwLock<Store>,
+    pending: Mutex<PendingPage>,
+    page_size: usize,
+}
+
+impl TreePageStore {
+    /// Open the page store at `path`. Pages are filled up to `scmstore.tree-page-size` bytes.
+    pub fn new(path: impl AsRef<Path>, config: &ConfigSet) -> Result<Self> {
+        let page_size = config
+            .get_or("scmstore", "tree-page-size", || ByteCount::from(64 * 1024))?
+            .value() as usize;
+        let log = TreePageStore::open_options(config)?.shared(path)?;
+        Ok(TreePageStore {
+            log: RwLock::new(log),
+            pending: Mutex::new(PendingPage::default()),
+            page_size,
+        })
+    }
+
+    fn open_options(config: &ConfigSet) -> Result<StoreOpenOptions> {
+        let mut open_options = StoreOpenOptions::new()
+            .max_log_count(4)
+            .max_bytes_per_log(1000 * 1000 * 1000)
+            .auto_sync_threshold(10 * 1024 * 1024)
+            .create(true)
+            .index("node", index_page);
+
+        if let Some(max_bytes_per_log) =
+            config.get_opt::<ByteCount>("indexedlog", "tree-pages.max-bytes-per-log")?
+        {
+            open_options = open_options.max_bytes_per_log(max_bytes_per_log.value());
+        }
+        Ok(open_options)
+    }
+
+    pub fn get(&self, hgid: &HgId) -> Result<Option<Bytes>> {
+        if let Some(tree) = self.pending.lock().trees.get(hgid) {
+            return Ok(Some(tree.clone()));
+        }
+
+        let log = self.log.read();
+        let mut pages = log.lookup(0, hgid)?;
+        let page = match pages.next() {
+            None => return Ok(None),
+            Some(page) => log.slice_to_bytes(page?),
+        };
+        drop(log);
+        find_in_page(&page, hgid)
+    }
+
+    /// Add a tree. It is written to the store with the next full page, or on `flush`.
+    pub fn put(&self, hgid: HgId, tree: Bytes) -> Result<()> {
+        let mut pending = self.pending.lock();
+        pending.size += TABLE_ENTRY_LEN + tree.len();
+        if let Some(previous) = pending.trees.insert(hgid, tree) {
+            pending.size -= TABLE_ENTRY_LEN + previous.len();
+        }
+        if pending.size >= self.page_size {
+            let page = std::mem::take(&mut *pending);
+            drop(pending);
+            self.write_page(page)?;
+        }
+        Ok(())
+    }
+
+    fn write_page(&self, page: PendingPage) -> Result<()> {
+        if page.trees.is_empty() {
+            return Ok(());
+        }
+        let table_len = COUNT_LEN + page.trees.len() * TABLE_ENTRY_LEN;
+        let mut buf = Vec::with_capacity(table_len + page.size);
+        buf.write_u32::<BigEndian>(page.trees.len() as u32)?;
+        let mut offset = table_len;
+        for (hgid, tree) in page.trees.iter() {
+            buf.write_all(hgid.as_ref())?;
+            buf.write_u32::<BigEndian>(u32::try_from(offset)?)?;
+            buf.write_u32::<BigEndian>(tree.len() as u32)?;
+            offset += tree.len();
+        }
+        for tree in page.trees.values() {
+            buf.write_all(tree)?;
+        }
+        self.log.write().append(buf)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let page = std::mem::take(&mut *self.pending.lock());
+        self.write_page(page)?;
+        self.log.write().flush()
+    }
+}
+
+fn page_count(page: &[u8]) -> usize {
+    match page.get(..COUNT_LEN) {
+        // Ignore the part of a corrupted table that would be out of the page.
+        Some(count) => {
+            (BigEndian::read_u32(count) as usize).min((page.len() - COUNT_LEN) / TABLE_ENTRY_LEN)
+        }
+        None => 0,
+    }
+}
+
+/// Index every node of the offset table of the page.
+fn index_page(page: &[u8]) -> Vec<IndexOutput> {
+    (0..page_count(page))
+        .map(|i| {
+            let start = (COUNT_LEN + i * TABLE_ENTRY_LEN) as u64;
+            IndexOutput::Reference(start..start + HgId::len() as u64)
+        })
+        .collect()
+}
+
+/// Bisect the offset table of the page to find `hgid`.
+fn find_in_page(page: &Bytes, hgid: &HgId) -> Result<Option<Bytes>> {
+    let table = page
+        .as_ref()
+        .get_err(COUNT_LEN..COUNT_LEN + page_count(page) * TABLE_ENTRY_LEN)?;
+    let entries = table.chunks_exact(TABLE_ENTRY_LEN).collect::<Vec<_>>();
+    let entry = match entries.binary_search_by(|entry| entry[..HgId::len()].cmp(hgid.as_ref())) {
+        Ok(index) => entries[index],
+        Err(_) => return Ok(None),
+    };
+    let offset = BigEndian::read_u32(&entry[HgId::len()..]) as usize;
+    let len = BigEndian::read_u32(&entry[HgId::len() + 4..]) as usize;
+    let tree = page.as_ref().get_err(offset..offset + len)?;
+    Ok(Some(page.slice_to_bytes(tree)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use types::testutil::*;
+
+    use super::*;
+
+    #[test]
+    fn test_put_get() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let mut config = ConfigSet::new();
+        config.set(
+            "scmstore",
+            "tree-page-size",
+            Some("100"),
+            &Default::default(),
+        );
+
+        let store = TreePageStore::new(&tempdir, &config)?;
+        let trees: Vec<(HgId, Bytes)> = (1..20)
+            .map(|i| (hgid(&i.to_string()), Bytes::from(vec![i as u8; i])))
+            .collect();
+        for (hgid, tree) in trees.iter() {
+            store.put(*hgid, tree.clone())?;
+        }
+        // Found before the pages are flushed.
+        assert_eq!(store.get(&trees[18].0)?, Some(trees[18].1.clone()));
+        store.flush()?;
+        drop(store);
+
+        let store = TreePageStore::new(&tempdir, &config)?;
+        for (hgid, tree) in trees.iter() {
+            assert_eq!(store.get(hgid)?, Some(tree.clone()));
+        }
+        assert_eq!(store.get(&hgid("ff"))?, None);
+        Ok(())
+    }
+}
@@ -9,12 +9,14 @@
 use anyhow::Result;
 use minibytes::Bytes;
 
+use crate::cancel::CancellationToken;
 use crate::datastore::ContentDataStore;
 use crate::datastore::ContentMetadata;
 use crate::datastore::HgIdDataStore;
 use crate::datastore::Metadata;
 use crate::datastore::RemoteDataStore;
 use crate::datastore::StoreResult;
+use crate::priority::FetchPriority;
 use crate::types::StoreKey;
 use crate::unionstore::UnionStore;
 
@@ -67,6 +69,30 @@ impl<T: RemoteDataStore> RemoteDataStore for UnionHgIdDataStore<T> {
             })
     }
 
+    fn prefetch_cancellable(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<StoreKey>> {
+        self.prefetch_with_priority(keys, cancel, FetchPriority::Interactive)
+    }
+
+    fn prefetch_with_priority(
+        &self,
+        keys: &[StoreKey],
+        cancel: &CancellationToken,
+        priority: FetchPriority,
+    ) -> Result<Vec<StoreKey>> {
+        let mut missing_keys = keys.to_vec();
+        for store in self {
+            if missing_keys.is_empty() {
+                break;
+            }
+            missing_keys = store.prefetch_with_priority(&missing_keys, cancel, priority)?;
+        }
+        Ok(missing_keys)
+    }
+
     fn upload(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
         self.into_iter()
             .fold(Ok(keys.to_vec()), |not_sent, store| match not_sent {
@@ -80,6 +106,17 @@ impl<T: RemoteDataStore> RemoteDataStore for UnionHgIdDataStore<T> {
                 Err(e) => Err(e),
             })
     }
+
+    fn get_remote_missing(&self, keys: &[StoreKey]) -> Result<Vec<StoreKey>> {
+        let mut missing_keys = keys.to_vec();
+        for store in self {
+            if missing_keys.is_empty() {
+                break;
+            }
+            missing_keys = store.get_remote_missing(&missing_keys)?;
+        }
+        Ok(missing_keys)
+    }
 }
 
 pub type UnionContentDataStore<T> = UnionStore<T>;
@@ -7,6 +7,7 @@
 
 // Union history store
 use anyhow::Result;
+use types::HgId;
 use types::Key;
 use types::NodeInfo;
 
@@ -35,6 +36,45 @@ impl<T: HgIdHistoryStore> HgIdHistoryStore for UnionHgIdHistoryStore<T> {
         }
         Ok(())
     }
+
+    fn get_node_info_batch(&self, keys: &[Key]) -> Result<Vec<Option<NodeInfo>>> {
+        union_batch(self, keys, |store, keys| store.get_node_info_batch(keys))
+    }
+
+    fn get_linknode_batch(&self, keys: &[Key]) -> Result<Vec<Option<HgId>>> {
+        union_batch(self, keys, |store, keys| store.get_linknode_batch(keys))
+    }
+}
+
+/// Look up `keys` in each store in turn with `get`, only asking a store for the keys that the
+/// previous ones didn't have.
+fn union_batch<T, R: Clone>(
+    union: &UnionHgIdHistoryStore<T>,
+    keys: &[Key],
+    get: impl Fn(&T, &[Key]) -> Result<Vec<Option<R>>>,
+) -> Result<Vec<Option<R>>> {
+    let mut results = vec![None; keys.len()];
+    // Indices of the keys that haven't been found yet.
+    let mut pending: Vec<usize> = (0..keys.len()).collect();
+    for store in union {
+        if pending.is_empty() {
+            break;
+        }
+        let pending_keys: Vec<Key> = pending.iter().map(|i| keys[*i].clone()).collect();
+        let found = get(store, &pending_keys)?;
+        pending = pending
+            .into_iter()
+            .zip(found)
+            .filter_map(|(i, value)| match value {
+                Some(value) => {
+                    results[i] = Some(value);
+                    None
+                }
+                None => Some(i),
+            })
+            .collect();
+    }
+    Ok(results)
 }
 
 impl<T: RemoteHistoryStore> RemoteHistoryStore for UnionHgIdHistoryStore<T> {
@@ -17,6 +17,7 @@ use crate::localstore::LocalStore;
 use crate::repack::ToKeys;
 use crate::types::StoreKey;
 
+#[derive(Clone)]
 pub struct UnionStore<T> {
     stores: Vec<T>,
 }
@@ -40,6 +41,15 @@ impl<T: LocalStore> LocalStore for UnionStore<T> {
                 Err(e) => Err(e),
             })
     }
+
+    fn contains(&self, key: &StoreKey) -> Result<bool> {
+        for store in self {
+            if store.contains(key)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 }
 
 impl<T> IntoIterator for UnionStore<T> {
@@ -18,6 +18,7 @@ use edenapi::Stats;
 use hgtime::HgTime;
 use thiserror::Error;
 use tracing::Span;
+use types::RepoPath;
 use util::path::create_dir;
 use util::path::create_shared_dir;
 
@@ -62,6 +63,22 @@ pub fn get_cache_path(config: &ConfigSet, suffix: &Option<impl AsRef<Path>>) ->
     Ok(path)
 }
 
+/// Like `get_cache_path`, but doesn't create any directories.
+pub fn get_existing_cache_path(
+    config: &ConfigSet,
+    suffix: &Option<impl AsRef<Path>>,
+) -> Result<PathBuf> {
+    let reponame = get_repo_name(config)?;
+    let mut path: PathBuf = config
+        .get_or_default::<Option<_>>("remotefilelog", "cachepath")?
+        .ok_or_else(|| Error::ConfigNotSet("remotefilelog.cachepath".into()))?;
+    path.push(reponame);
+    if let Some(ref suffix) = suffix {
+        path.push(suffix);
+    }
+    Ok(path)
+}
+
 pub fn get_local_path(local_path: PathBuf, suffix: &Option<impl AsRef<Path>>) -> Result<PathBuf> {
     let mut path = local_path;
     create_dir(&path)?;
@@ -95,6 +112,28 @@ pub fn get_indexedloghistorystore_path(path: impl AsRef<Path>) -> Result<PathBuf
     Ok(path)
 }
 
+/// Unlike the other stores, the directory is only created by `NegativeCache::new`
+/// when the negative cache is enabled.
+pub fn get_negativecache_path(path: impl AsRef<Path>) -> Result<PathBuf> {
+    let mut path = path.as_ref().to_owned();
+    path.push("negativecache");
+    Ok(path)
+}
+
+pub fn get_treepagestore_path(path: impl AsRef<Path>) -> Result<PathBuf> {
+    let mut path = path.as_ref().to_owned();
+    path.push("treepagestore");
+    create_shared_dir(&path)?;
+    Ok(path)
+}
+
+pub fn get_treeauxstore_path(path: impl AsRef<Path>) -> Result<PathBuf> {
+    let mut path = path.as_ref().to_owned();
+    path.push("treeauxstore");
+    create_shared_dir(&path)?;
+    Ok(path)
+}
+
 pub fn get_packs_path(path: impl AsRef<Path>, suffix: &Option<PathBuf>) -> Result<PathBuf> {
     let mut path = path.as_ref().to_owned();
     path.push("packs");
@@ -112,6 +151,19 @@ pub fn get_cache_packs_path(config: &ConfigSet, suffix: &Option<PathBuf>) -> Res
     get_packs_path(get_config_cache_path(config)?, suffix)
 }
 
+/// Like `get_cache_packs_path`, but doesn't create any directories.
+pub fn get_existing_cache_packs_path(
+    config: &ConfigSet,
+    suffix: &Option<PathBuf>,
+) -> Result<PathBuf> {
+    let mut path = get_existing_cache_path(config, &None::<PathBuf>)?;
+    path.push("packs");
+    if let Some(suffix) = suffix {
+        path.push(suffix);
+    }
+    Ok(path)
+}
+
 fn get_lfs_path(store_path: impl AsRef<Path>) -> Result<PathBuf> {
     let mut path = store_path.as_ref().to_owned();
     path.push("lfs");
@@ -144,6 +196,15 @@ pub fn get_lfs_blobs_path(store_path: impl AsRef<Path>) -> Result<PathBuf> {
     Ok(path)
 }
 
+/// Whether `path` is `prefix`, or is under the `prefix` directory. The empty prefix matches all
+/// the paths.
+pub(crate) fn path_has_prefix(path: &RepoPath, prefix: &RepoPath) -> bool {
+    let path = path.as_byte_slice();
+    let prefix = prefix.as_byte_slice();
+    prefix.is_empty()
+        || (path.starts_with(prefix) && (path.len() == prefix.len() || path[prefix.len()] == b'/'))
+}
+
 pub const RUN_ONCE_FILENAME: &str = "runoncemarker";
 pub fn check_run_once(store_path: impl AsRef<Path>, key: &str, cutoff: HgTime) -> bool {
     if HgTime::now() > Some(cutoff) {
@@ -0,0 +1,617 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Deep verification of the indexedlog stores, used by `repair`.
+//!
+//! Rebuilding indexes does not detect entries whose content was corrupted on disk. Deep
+//! verification recomputes the hgid of every entry from its content and parents, and compares it
+//! with the stored one. Data entries are full texts, so their delta chain is a single full text
+//! and no delta needs to be applied. Since the content and the parents live in different stores, a
+//! mismatch cannot tell which one is wrong: the data and history entries are both considered
+//! corrupt.
+//!
+//! Corrupt entries are copied into a `corrupt/` directory next to the store, and the store is
+//! rewritten without them so they are fetched again. The entries of the other store with the same
+//! hgid are removed too, see [`remove_hgids`].
+//!
+//! Datapacks store delta chains. Their entries are verified over the full text rebuilt from the
+//! chain, and since packs are immutable, a pack with a corrupt entry is quarantined as a whole.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::format_err;
+use anyhow::Result;
+use indexedlog::lock::DirLockOptions;
+use indexedlog::lock::ScopedDirLock;
+use minibytes::Bytes;
+use tracing::warn;
+use types::HgId;
+use types::Key;
+use types::Parents;
+
+use crate::compression::copy_dictionaries;
+use crate::datapack::DataPack;
+use crate::datastore::HgIdDataStore;
+use crate::datastore::StoreResult;
+use crate::historystore::HgIdHistoryStore;
+use crate::indexedlogutil::Store;
+use crate::localstore::ExtStoredPolicy;
+use crate::repack::ToKeys;
+use crate::types::StoreKey;
+
+const CORRUPT_DIR: &str = "corrupt";
+/// Extension of the store being replaced by a rewritten one.
+const OLD_EXTENSION: &str = "verify-old";
+
+/// Held by every open indexedlog. Taking it exclusively proves that no other process has the store
+/// open.
+static NO_READERS_LOCK_OPTS: DirLockOptions = DirLockOptions {
+    exclusive: true,
+    non_blocking: true,
+    file_name: "rlock",
+};
+
+/// Result of the verification of a single entry.
+pub(crate) enum Verdict {
+    Valid,
+    Corrupt(Key),
+    /// The entry could not be verified, for example because its parents are not known locally.
+    Unverifiable,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct VerifyStats {
+    pub(crate) checked: usize,
+    pub(crate) corrupt: usize,
+    pub(crate) unverifiable: usize,
+}
+
+impl VerifyStats {
+    pub(crate) fn summary(&self, path: &Path) -> String {
+        format!(
+            "Verified {} entries in {}: {} corrupt, {} could not be verified\n",
+            self.checked,
+            path.display(),
+            self.corrupt,
+            self.unverifiable,
+        )
+    }
+}
+
+/// Whether `key.hgid` is the hash of `content` with parents `p1` and `p2`.
+pub(crate) fn hgid_matches(key: &Key, content: &[u8], p1: HgId, p2: HgId) -> bool {
+    HgId::from_content(content, Parents::new(p1, p2)) == key.hgid
+}
+
+/// Check every entry of the store at `path` with `check`. Corrupt entries are quarantined, and the
+/// store is rewritten without them. Return the hgids of the corrupt entries.
+///
+/// `open` opens the store at the given path. It is also used to create the rewritten store.
+pub(crate) fn verify_store(
+    path: &Path,
+    open: impl Fn(&Path) -> Result<Store>,
+    mut check: impl FnMut(Bytes) -> Result<Verdict>,
+) -> Result<(VerifyStats, HashSet<HgId>)> {
+    let mut stats = VerifyStats::default();
+    let mut corrupt = HashSet::new();
+    let mut corrupt_hgids = HashSet::new();
+    {
+        let log = open(path)?;
+        for (index, buf) in log.iter().enumerate() {
+            let buf = buf?;
+            stats.checked += 1;
+            let name = match check(log.slice_to_bytes(buf)) {
+                Ok(Verdict::Valid) => continue,
+                Ok(Verdict::Unverifiable) => {
+                    stats.unverifiable += 1;
+                    continue;
+                }
+                Ok(Verdict::Corrupt(key)) => {
+                    corrupt_hgids.insert(key.hgid);
+                    format!("{}-{}", key.hgid, index)
+                }
+                // Entries that cannot be decoded are corrupt too.
+                Err(err) => {
+                    warn!(%err, index, "cannot decode entry");
+                    format!("undecodable-{}", index)
+                }
+            };
+            quarantine(path, &name, buf)?;
+            corrupt.insert(index);
+        }
+    }
+    stats.corrupt = corrupt.len();
+    if !corrupt.is_empty() {
+        rewrite_without(path, open, &corrupt)?;
+    }
+    Ok((stats, corrupt_hgids))
+}
+
+/// Quarantine the entries of the store at `path` whose hgid is in `hgids`. This removes the other
+/// half of the entries found corrupt by [`verify_store`] in the matching data or history store.
+/// Return a message with the number of removed entries.
+pub(crate) fn remove_hgids(
+    path: &Path,
+    open: impl Fn(&Path) -> Result<Store>,
+    hgids: &HashSet<HgId>,
+    hgid_of: impl Fn(Bytes) -> Result<Key>,
+) -> Result<String> {
+    if hgids.is_empty() {
+        return Ok(String::new());
+    }
+    let (stats, _) = verify_store(path, open, |bytes| {
+        let key = hgid_of(bytes)?;
+        Ok(if hgids.contains(&key.hgid) {
+            Verdict::Corrupt(key)
+        } else {
+            Verdict::Valid
+        })
+    })?;
+    Ok(format!(
+        "Removed {} entries matching corrupt entries from {}\n",
+        stats.corrupt,
+        path.display(),
+    ))
+}
+
+/// Check every datapack in `packs_path`, recomputing the hgid of each entry from the full text of
+/// its delta chain and the parents found in `history`. Packs with a corrupt entry are moved into
+/// a `corrupt/` directory.
+pub(crate) fn verify_datapacks(
+    packs_path: &Path,
+    history: &dyn HgIdHistoryStore,
+) -> Result<VerifyStats> {
+    let mut stats = VerifyStats::default();
+    let mut corrupt_packs = Vec::new();
+    let entries = match fs::read_dir(packs_path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(stats),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some("datapack".as_ref()) {
+            continue;
+        }
+        let base = path.with_extension("");
+        // LFS pointers are not hashed like regular content, `Ignore` hides them.
+        let pack = match DataPack::new(&base, ExtStoredPolicy::Ignore) {
+            Ok(pack) => pack,
+            Err(err) => {
+                warn!(%err, ?path, "cannot open datapack");
+                corrupt_packs.push(base);
+                continue;
+            }
+        };
+        let mut corrupt = 0;
+        for key in pack.to_keys() {
+            stats.checked += 1;
+            let verdict = key.and_then(|key| verify_pack_entry(&pack, history, key));
+            match verdict {
+                Ok(Verdict::Valid) => {}
+                Ok(Verdict::Unverifiable) => stats.unverifiable += 1,
+                Ok(Verdict::Corrupt(_)) => corrupt += 1,
+                Err(err) => {
+                    warn!(%err, ?path, "cannot read datapack entry");
+                    corrupt += 1;
+                }
+            }
+        }
+        if corrupt > 0 {
+            stats.corrupt += corrupt;
+            corrupt_packs.push(base);
+        }
+    }
+    for base in corrupt_packs {
+        quarantine_pack(packs_path, &base)?;
+    }
+    Ok(stats)
+}
+
+fn verify_pack_entry(pack: &DataPack, history: &dyn HgIdHistoryStore, key: Key) -> Result<Verdict> {
+    if let StoreResult::NotFound(_) = pack.get_meta(StoreKey::hgid(key.clone()))? {
+        return Ok(Verdict::Unverifiable);
+    }
+    let info = match history.get_node_info(&key) {
+        Ok(Some(info)) => info,
+        _ => return Ok(Verdict::Unverifiable),
+    };
+    let content = match pack.get(StoreKey::hgid(key.clone()))? {
+        StoreResult::Found(content) => content,
+        StoreResult::NotFound(_) => return Ok(Verdict::Unverifiable),
+    };
+    let (p1, p2) = (info.parents[0].hgid, info.parents[1].hgid);
+    if hgid_matches(&key, &content, p1, p2) {
+        Ok(Verdict::Valid)
+    } else {
+        Ok(Verdict::Corrupt(key))
+    }
+}
+
+/// Move the pack and index files of the datapack `base` into the `corrupt/` directory of
+/// `packs_path`. Pack stores only look at the files of their directory.
+fn quarantine_pack(packs_path: &Path, base: &Path) -> Result<()> {
+    let dir = packs_path.join(CORRUPT_DIR);
+    fs::create_dir_all(&dir)?;
+    for extension in ["datapack", "dataidx"] {
+        let path = base.with_extension(extension);
+        if let Some(name) = path.file_name() {
+            match fs::rename(&path, dir.join(name)) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The directory receiving the corrupt entries of the store at `store_path`.
+fn corrupt_dir(store_path: &Path) -> PathBuf {
+    let store_name = store_path.file_name().unwrap_or_default();
+    match store_path.parent() {
+        Some(parent) => parent.join(CORRUPT_DIR).join(store_name),
+        None => PathBuf::from(CORRUPT_DIR).join(store_name),
+    }
+}
+
+/// Copy the raw bytes of a corrupt entry into the `corrupt/` directory next to the store.
+fn quarantine(store_path: &Path, name: &str, buf: &[u8]) -> Result<()> {
+    let dir = corrupt_dir(store_path);
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(name), buf)?;
+    Ok(())
+}
+
+/// Whether `name`, in the directory of a store, is part of the rotated log itself.
+fn is_log_file(name: &OsStr) -> bool {
+    match name.to_str() {
+        Some(name) => {
+            name == "latest" || name == "lock" || name == "rlock" || name.parse::<u64>().is_ok()
+        }
+        None => false,
+    }
+}
+
+/// Move what `path` contains besides the log, ex. the shards of a sharded store, to `new_path`,
+/// unless `new_path` already has it.
+fn move_extras(path: &Path, new_path: &Path) -> Result<()> {
+    for entry in fs::read_dir(path)? {
+        let name = entry?.file_name();
+        if is_log_file(&name) || new_path.join(&name).exists() {
+            continue;
+        }
+        fs::rename(path.join(&name), new_path.join(&name))?;
+    }
+    Ok(())
+}
+
+/// Rewrite the store at `path` without the entries at the `corrupt` positions.
+///
+/// The rewritten store is built next to the store, then swapped with it. The other files of the
+/// store directory, like the shards of a sharded store, are moved to the rewritten store. Writers
+/// are blocked while the store is copied, and the swap is skipped if another process has the store
+/// open. A swap interrupted by a crash is completed or rolled back by [`recover_rewrite`] on the
+/// next run.
+pub(crate) fn rewrite_without(
+    path: &Path,
+    open: impl Fn(&Path) -> Result<Store>,
+    corrupt: &HashSet<usize>,
+) -> Result<()> {
+    let new_path = path.with_extension("verify");
+    let old_path = path.with_extension(OLD_EXTENSION);
+    recover_rewrite(path)?;
+    if new_path.exists() {
+        fs::remove_dir_all(&new_path)?;
+    }
+
+    {
+        // Block writers until the copy is swapped in, so no appended entry is lost.
+        let _write_lock = ScopedDirLock::new(path)?;
+        {
+            let log = open(path)?;
+            let mut new_log = open(&new_path)?;
+            for (index, buf) in log.iter().enumerate() {
+                let buf = buf?;
+                if !corrupt.contains(&index) {
+                    new_log.append(buf)?;
+                }
+            }
+            new_log.flush()?;
+            copy_dictionaries(path, &new_path)?;
+        }
+
+        // Readers do not take the write lock. Their mmaps would keep pointing at the old store.
+        if let Err(err) = ScopedDirLock::new_with_options(path, &NO_READERS_LOCK_OPTS) {
+            let _ = fs::remove_dir_all(&new_path);
+            return Err(format_err!(
+                "cannot rewrite {} while it is in use: {}",
+                path.display(),
+                err
+            ));
+        }
+        move_extras(path, &new_path)?;
+        // Locks are released before the swap: Windows cannot rename a directory with open files.
+    }
+
+    fs::rename(path, &old_path)?;
+    if let Err(err) = fs::rename(&new_path, path) {
+        // Something recreated the store in between. It is empty, the old store is kept.
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
+        fs::rename(&old_path, path)?;
+        move_extras(&new_path, path)?;
+        return Err(err.into());
+    }
+    if let Err(err) = fs::remove_dir_all(&old_path) {
+        warn!(%err, ?old_path, "cannot remove old store after verification");
+    }
+    Ok(())
+}
+
+/// Complete or roll back a swap of `rewrite_without` interrupted by a crash. If the store at `path`
+/// is missing, the old store is the only copy and is moved back. Otherwise the swap completed.
+///
+/// This must run before anything opens the store, since opening it creates an empty store.
+pub(crate) fn recover_rewrite(path: &Path) -> Result<()> {
+    let old_path = path.with_extension(OLD_EXTENSION);
+    let old_path = old_path.as_path();
+    if !old_path.exists() {
+        return Ok(());
+    }
+    if path.exists() {
+        fs::remove_dir_all(old_path)?;
+    } else {
+        fs::rename(old_path, path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use configparser::config::ConfigSet;
+    use tempfile::TempDir;
+    use types::testutil::*;
+    use types::NodeInfo;
+    use types::RepoPathBuf;
+
+    use super::*;
+    use crate::contentstore::ContentStore;
+    use crate::datapack::tests::make_datapack;
+    use crate::datastore::Delta;
+    use crate::datastore::HgIdDataStore;
+    use crate::datastore::HgIdMutableDeltaStore;
+    use crate::datastore::Metadata;
+    use crate::historypack::tests::make_historypack;
+    use crate::historystore::HgIdMutableHistoryStore;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStore;
+    use crate::indexedlogdatastore::IndexedLogHgIdDataStoreConfig;
+    use crate::indexedloghistorystore::IndexedLogHgIdHistoryStore;
+    use crate::indexedlogutil::StoreType;
+    use crate::metadatastore::MetadataStore;
+    use crate::util::get_indexedlogdatastore_path;
+    use crate::util::get_indexedloghistorystore_path;
+
+    /// The number of files below `dir`.
+    fn count_files(dir: &Path) -> Result<usize> {
+        let mut count = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            count += if path.is_dir() {
+                count_files(&path)?
+            } else {
+                1
+            };
+        }
+        Ok(count)
+    }
+
+    #[test]
+    fn test_repair_verify() -> Result<()> {
+        check_repair_verify(0)
+    }
+
+    #[test]
+    fn test_repair_verify_shards() -> Result<()> {
+        check_repair_verify(2)
+    }
+
+    fn check_repair_verify(shards: usize) -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let config = ConfigSet::new();
+        let data_config = IndexedLogHgIdDataStoreConfig {
+            max_log_count: None,
+            max_bytes_per_log: None,
+            max_bytes: None,
+        };
+        let path = RepoPathBuf::from_string("a".to_string())?;
+        let parents = [null_key("a"), null_key("a")];
+        let good = Key::new(path.clone(), HgId::from_content(b"good", Parents::None));
+        let bad = Key::new(path, HgId::from_content(b"bad", Parents::None));
+
+        {
+            let data = IndexedLogHgIdDataStore::new(
+                get_indexedlogdatastore_path(&tempdir)?,
+                ExtStoredPolicy::Use,
+                &data_config,
+                StoreType::Shared,
+            )?
+            .with_shards(shards, &data_config)?;
+            let history = IndexedLogHgIdHistoryStore::new(
+                get_indexedloghistorystore_path(&tempdir)?,
+                &config,
+                StoreType::Shared,
+            )?;
+            for (key, content) in [(&good, "good"), (&bad, "corrupted")] {
+                let delta = Delta {
+                    data: content.as_bytes().to_vec().into(),
+                    base: None,
+                    key: key.clone(),
+                };
+                data.add(&delta, &Default::default())?;
+                let info = NodeInfo {
+                    parents: parents.clone(),
+                    linknode: hgid("1"),
+                };
+                history.add(key, &info)?;
+            }
+            data.flush()?;
+            history.flush()?;
+        }
+
+        let summary =
+            MetadataStore::repair(tempdir.path(), None::<&Path>, None::<&Path>, &config, true)?;
+        assert!(summary.contains("Verified 2 entries"), "{}", summary);
+        assert!(summary.contains("1 corrupt"), "{}", summary);
+        assert!(summary.contains("Removed 1 entries"), "{}", summary);
+        // The corrupt pair was removed from both stores already.
+        let summary =
+            ContentStore::repair(tempdir.path(), None::<&Path>, None::<&Path>, &config, true)?;
+        assert!(summary.contains("Verified 1 entries"), "{}", summary);
+        assert!(summary.contains("0 corrupt"), "{}", summary);
+        // The entries of a shard are quarantined next to the shard, in the store.
+        let data_path = get_indexedlogdatastore_path(&tempdir)?;
+        let quarantined = match shards {
+            0 => count_files(&tempdir.path().join(CORRUPT_DIR).join("indexedlogdatastore"))?,
+            _ => count_files(&data_path.join(CORRUPT_DIR))?,
+        };
+        assert_eq!(quarantined, 1);
+        // The rewritten stores kept their shards.
+        for shard in 0..shards {
+            assert!(data_path.join(format!("shard-{}", shard)).is_dir());
+        }
+
+        let data = IndexedLogHgIdDataStore::new(
+            get_indexedlogdatastore_path(&tempdir)?,
+            ExtStoredPolicy::Use,
+            &data_config,
+            StoreType::Shared,
+        )?;
+        assert_eq!(
+            data.get(StoreKey::hgid(good))?,
+            StoreResult::Found(b"good".to_vec())
+        );
+        assert_eq!(
+            data.get(StoreKey::hgid(bad.clone()))?,
+            StoreResult::NotFound(StoreKey::hgid(bad.clone()))
+        );
+        let history = IndexedLogHgIdHistoryStore::new(
+            get_indexedloghistorystore_path(&tempdir)?,
+            &config,
+            StoreType::Shared,
+        )?;
+        assert!(history.get_node_info(&good)?.is_some());
+        assert!(history.get_node_info(&bad)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_datapacks() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let base = Key::new(
+            RepoPathBuf::from_string("a".to_string())?,
+            HgId::from_content(b"base", Parents::None),
+        );
+        let good = Key::new(
+            base.path.clone(),
+            HgId::from_content(b"good", Parents::None),
+        );
+        let bad = Key::new(base.path.clone(), HgId::from_content(b"bad", Parents::None));
+        let mut nodes = HashMap::new();
+        for key in [&base, &good, &bad] {
+            let info = NodeInfo {
+                parents: [null_key("a"), null_key("a")],
+                linknode: hgid("1"),
+            };
+            nodes.insert(key.clone(), info);
+        }
+        let history = make_historypack(&tempdir, &nodes);
+
+        // The corrupt entry is a delta, its full text is rebuilt from the chain.
+        let full = |key: &Key, data: &str| -> Result<(Delta, Metadata)> {
+            let delta = Delta {
+                data: data.as_bytes().to_vec().into(),
+                base: None,
+                key: key.clone(),
+            };
+            Ok((delta, Default::default()))
+        };
+        // An mpatch hunk replacing bytes 0..4 of the base with 9 new bytes.
+        let mut replace = Vec::new();
+        for n in [0u32, 4, 9] {
+            replace.extend_from_slice(&n.to_be_bytes());
+        }
+        replace.extend_from_slice(b"corrupted");
+
+        let valid_pack = make_datapack(&tempdir, &vec![full(&good, "good")?]);
+        let corrupt_pack = make_datapack(
+            &tempdir,
+            &vec![
+                full(&base, "base")?,
+                (
+                    Delta {
+                        data: replace.into(),
+                        base: Some(base.clone()),
+                        key: bad.clone(),
+                    },
+                    Default::default(),
+                ),
+            ],
+        );
+        let (valid_path, corrupt_path) = (
+            valid_pack.pack_path().to_path_buf(),
+            corrupt_pack.pack_path().to_path_buf(),
+        );
+        drop((valid_pack, corrupt_pack));
+
+        let stats = verify_datapacks(tempdir.path(), &history)?;
+        assert_eq!(
+            stats,
+            VerifyStats {
+                checked: 3,
+                corrupt: 1,
+                unverifiable: 0,
+            }
+        );
+        assert!(valid_path.exists());
+        assert!(!corrupt_path.exists());
+        let quarantined = tempdir
+            .path()
+            .join(CORRUPT_DIR)
+            .join(corrupt_path.file_name().unwrap());
+        assert!(quarantined.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_rewrite() -> Result<()> {
+        let tempdir = TempDir::new()?;
+        let path = tempdir.path().join("store");
+        let old_path = path.with_extension(OLD_EXTENSION);
+
+        // Interrupted before the new store was moved in: the old store is the only copy.
+        fs::create_dir(&old_path)?;
+        fs::write(old_path.join("log"), b"old")?;
+        recover_rewrite(&path)?;
+        assert_eq!(fs::read(path.join("log"))?, b"old");
+        assert!(!old_path.exists());
+
+        // Interrupted after the new store was moved in: the old store is dropped.
+        fs::create_dir(&old_path)?;
+        recover_rewrite(&path)?;
+        assert_eq!(fs::read(path.join("log"))?, b"old");
+        assert!(!old_path.exists());
+        Ok(())
+    }
+}
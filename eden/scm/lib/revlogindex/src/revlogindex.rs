@@ -1899,6 +1899,14 @@ impl CheckIntegrity for RevlogIndex {
         unsupported_dag_error()
     }
 
+    async fn check_idmap(&self) -> dag::Result<Vec<String>> {
+        unsupported_dag_error()
+    }
+
+    async fn check_integrity(&self) -> dag::Result<dag::IntegrityReport> {
+        unsupported_dag_error()
+    }
+
     async fn check_isomorphic_graph(
         &self,
         other: &dyn DagAlgorithm,
@@ -23,11 +23,14 @@
 use async_trait::async_trait;
 pub use futures;
 use futures::stream::BoxStream;
+use futures::StreamExt;
 pub use minibytes;
+use sha2::Digest;
 pub use types;
 use types::HgId;
 use types::Key;
 use types::RepoPath;
+use types::Sha256;
 
 #[async_trait]
 #[auto_impl::auto_impl(Arc)]
@@ -48,6 +51,63 @@ pub trait ReadFileContents {
     ) -> BoxStream<Result<(minibytes::Bytes, Key), Self::Error>>;
 }
 
+/// Size and hash of the content of a file, as returned by `ReadFileContents`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileAuxData {
+    pub total_size: u64,
+    pub content_sha256: Sha256,
+}
+
+impl FileAuxData {
+    /// Compute the aux data of `content`.
+    pub fn from_content(content: &[u8]) -> Self {
+        let hash: [u8; Sha256::len()] = sha2::Sha256::digest(content).into();
+        FileAuxData {
+            total_size: content.len() as u64,
+            content_sha256: Sha256::from(&hash),
+        }
+    }
+}
+
+#[async_trait]
+#[auto_impl::auto_impl(Arc)]
+pub trait ReadFileAuxData {
+    type Error;
+
+    /// Read the size and hash of the content of specified files.
+    ///
+    /// Stores can often answer this without fetching or reading the content,
+    /// which makes it cheaper than `ReadFileContents` for large files.
+    async fn read_file_aux_data(
+        &self,
+        keys: Vec<Key>,
+    ) -> BoxStream<Result<(FileAuxData, Key), Self::Error>>;
+}
+
+/// `ReadFileAuxData` for stores which don't record aux data, computed from
+/// the contents read with `ReadFileContents`.
+pub struct ComputedFileAuxData<T>(pub T);
+
+#[async_trait]
+impl<T> ReadFileAuxData for ComputedFileAuxData<T>
+where
+    T: ReadFileContents + Send + Sync,
+    T::Error: Send + 'static,
+{
+    type Error = T::Error;
+
+    async fn read_file_aux_data(
+        &self,
+        keys: Vec<Key>,
+    ) -> BoxStream<Result<(FileAuxData, Key), Self::Error>> {
+        self.0
+            .read_file_contents(keys)
+            .await
+            .map(|result| result.map(|(content, key)| (FileAuxData::from_content(&content), key)))
+            .boxed()
+    }
+}
+
 #[async_trait]
 pub trait ReadRootTreeIds {
     /// Read root tree nodes of given commits.
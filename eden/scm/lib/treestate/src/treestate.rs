@@ -36,6 +36,36 @@ pub struct TreeState {
     root: TreeStateRoot,
 }
 
+/// Decides when a treestate file should be compacted.
+///
+/// Flushes append the changed parts of the tree, leaving the replaced parts behind as garbage.
+/// A file is compacted once it grows past `factor` times its size right after its last
+/// compaction, unless it was smaller than `min_size` then.
+#[derive(Clone, Copy, Debug)]
+pub struct CompactionPolicy {
+    pub min_size: u64,
+    pub factor: u64,
+}
+
+impl CompactionPolicy {
+    /// Size past which a file whose live data takes `live_size` bytes should be compacted, or 0
+    /// if it should not be compacted.
+    pub fn threshold(&self, live_size: u64) -> u64 {
+        if live_size > self.min_size {
+            live_size.saturating_mul(self.factor)
+        } else {
+            0
+        }
+    }
+}
+
+/// Sizes of a treestate file before and after a compaction.
+#[derive(Clone, Copy, Debug)]
+pub struct CompactionStats {
+    pub old_size: u64,
+    pub new_size: u64,
+}
+
 /// `TreeStateRoot` contains block id to the root `Tree`, and other metadata.
 #[derive(Default)]
 pub(crate) struct TreeStateRoot {
@@ -92,6 +122,22 @@ impl TreeState {
         Ok(root_id)
     }
 
+    /// Drop the garbage of the file by saving the live entries, including the unflushed ones, as
+    /// a new file at `path`. Return the new `root_id` and the sizes of both files.
+    ///
+    /// The current file is left untouched, so readers that opened it keep a consistent view.
+    /// Deleting it once it is no longer referenced is up to the caller, which should hold the
+    /// working copy lock to avoid racing with other writers.
+    pub fn compact<P: AsRef<Path>>(&mut self, path: P) -> Result<(BlockId, CompactionStats)> {
+        let old_size = self.store.position();
+        let root_id = self.write_as(path)?;
+        let stats = CompactionStats {
+            old_size,
+            new_size: self.store.position(),
+        };
+        Ok((root_id, stats))
+    }
+
     fn write_root(&mut self, tree_block_id: BlockId) -> Result<BlockId> {
         self.root.tree_block_id = tree_block_id;
         self.root.file_count = self.len() as u32;
@@ -406,6 +452,39 @@ mod tests {
         assert_eq!(state.len(), SAMPLE_PATHS.len());
     }
 
+    #[test]
+    fn test_compact() {
+        let dir = TempDir::new("treestate").expect("tempdir");
+        let mut state = new_treestate(dir.path().join("1"));
+        state.flush().expect("flush");
+        for path in &SAMPLE_PATHS[1..] {
+            state.remove(path).expect("remove");
+            state.flush().expect("flush");
+        }
+        let (block_id, stats) = state.compact(dir.path().join("2")).expect("compact");
+        assert!(stats.new_size < stats.old_size);
+        assert_eq!(state.path(), dir.path().join("2"));
+
+        let mut state = TreeState::open(dir.path().join("2"), block_id.into()).expect("open");
+        assert_eq!(state.len(), 1);
+        assert!(state.get(SAMPLE_PATHS[0]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_compaction_policy() {
+        let policy = CompactionPolicy {
+            min_size: 100,
+            factor: 3,
+        };
+        assert_eq!(policy.threshold(100), 0);
+        assert_eq!(policy.threshold(101), 303);
+        let policy = CompactionPolicy {
+            min_size: 100,
+            factor: 0,
+        };
+        assert_eq!(policy.threshold(101), 0);
+    }
+
     #[test]
     fn test_has_dir() {
         let dir = TempDir::new("treestate").expect("tempdir");
@@ -0,0 +1,24 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use crate::hash::AbstractHashType;
+use crate::hash::HashTypeInfo;
+
+/// A Blake3 hash.
+pub type Blake3 = AbstractHashType<Blake3TypeInfo, 32>;
+
+pub struct Blake3TypeInfo;
+
+impl HashTypeInfo for Blake3TypeInfo {
+    const HASH_TYPE_NAME: &'static str = "Blake3";
+}
+
+impl Blake3 {
+    pub fn into_inner(self) -> [u8; Self::len()] {
+        self.into_byte_array()
+    }
+}
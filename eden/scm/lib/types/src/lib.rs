@@ -7,6 +7,7 @@
 
 //! Common types used by sibling crates
 
+pub mod blake3;
 pub mod errors;
 pub mod hash;
 pub mod hgid;
@@ -19,6 +20,7 @@ pub mod path;
 pub mod serde_with;
 pub mod sha;
 
+pub use crate::blake3::Blake3;
 pub use crate::hgid::HgId;
 pub use crate::key::Key;
 pub use crate::node::Node;
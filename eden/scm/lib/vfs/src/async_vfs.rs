@@ -5,6 +5,7 @@
  * GNU General Public License version 2.
  */
 
+use std::path::PathBuf;
 use std::thread;
 use std::thread::JoinHandle;
 
@@ -31,6 +32,8 @@ struct WorkItem {
 #[derive(Debug)]
 enum Action {
     Write(RepoPathBuf, Bytes, UpdateFlag),
+    /// Clone the file from the given source, writing the content if cloning fails.
+    Clone(RepoPathBuf, PathBuf, Bytes, UpdateFlag),
     Remove(RepoPathBuf),
     SetExecutable(RepoPathBuf, bool),
     Batch(Vec<Action>),
@@ -74,6 +77,19 @@ impl AsyncVfsWriter {
         self.submit_action(Action::Batch(batch)).await
     }
 
+    /// Like `write_batch`, but clone each file from a source file with the same content. Files
+    /// that can't be cloned are written instead.
+    pub async fn clone_batch<B: Into<Bytes>>(
+        &self,
+        batch: impl IntoIterator<Item = (RepoPathBuf, PathBuf, B, UpdateFlag)>,
+    ) -> Result<usize> {
+        let batch = batch
+            .into_iter()
+            .map(|(path, source, data, flag)| Action::Clone(path, source, data.into(), flag))
+            .collect();
+        self.submit_action(Action::Batch(batch)).await
+    }
+
     pub async fn remove(&self, path: RepoPathBuf) -> Result<()> {
         self.submit_action(Action::Remove(path)).await.map(|_| ())
     }
@@ -113,6 +129,9 @@ fn async_vfs_worker(vfs: VFS, receiver: Receiver<WorkItem>) {
 fn execute_action(vfs: &VFS, action: Action) -> Result<usize> {
     match action {
         Action::Write(path, data, flag) => vfs.write(&path, &data, flag),
+        Action::Clone(path, source, data, flag) => vfs
+            .clone_file(&path, &source, flag)
+            .or_else(|_| vfs.write(&path, &data, flag)),
         Action::Remove(path) => vfs.remove(&path).map(|_| 0),
         Action::SetExecutable(path, flag) => vfs.set_executable(&path, flag).map(|_| 0),
         Action::Batch(batch) => {
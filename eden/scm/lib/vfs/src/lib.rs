@@ -13,6 +13,7 @@ pub use util::lock::PathLock;
 
 pub use crate::async_vfs::AsyncVfsWriter;
 pub use crate::pathauditor::PathAuditor;
+pub use crate::vfs::clone_file;
 pub use crate::vfs::is_executable;
 pub use crate::vfs::is_symlink;
 pub use crate::vfs::UpdateFlag;
@@ -47,6 +47,7 @@ struct Inner {
     auditor: PathAuditor,
     supports_symlinks: bool,
     supports_executables: bool,
+    supports_clone: bool,
     case_sensitive: bool,
 }
 
@@ -64,6 +65,7 @@ impl VFS {
             fstype(&root).with_context(|| format!("Can't construct a VFS for {:?}", root))?;
         let supports_symlinks = supports_symlinks(&fs_type);
         let supports_executables = supports_executables(&fs_type);
+        let supports_clone = supports_clone(&fs_type);
         let case_sensitive = case_sensitive(&root, &fs_type)?;
 
         Ok(Self {
@@ -72,6 +74,7 @@ impl VFS {
                 auditor,
                 supports_symlinks,
                 supports_executables,
+                supports_clone,
                 case_sensitive,
             }),
         })
@@ -261,6 +264,43 @@ impl VFS {
         }
     }
 
+    fn clone_inner(&self, path: &RepoPath, source: &Path, flag: UpdateFlag) -> Result<usize> {
+        let filepath = self
+            .inner
+            .auditor
+            .audit(path)
+            .with_context(|| format!("Can't write into {}", path))?;
+
+        let exec = match flag {
+            UpdateFlag::Regular => false,
+            UpdateFlag::Executable => true,
+            UpdateFlag::Symlink => bail!("Can't clone symlink {}", path),
+        };
+        let size = clone_file(source, &filepath)
+            .with_context(|| format!("Can't clone {:?} into {:?}", source, filepath))?;
+        self.set_exec(&filepath, exec)?;
+        Ok(size)
+    }
+
+    /// Overwrite the file with a clone of `source`, sharing its blocks on disk instead of copying
+    /// them. `source` must be on the same filesystem as the working copy.
+    ///
+    /// Return an error if the filesystem can't clone files, in which case the caller should fall
+    /// back to `write`.
+    pub fn clone_file(&self, path: &RepoPath, source: &Path, flag: UpdateFlag) -> Result<usize> {
+        match self.clone_inner(path, source, flag) {
+            Ok(size) => Ok(size),
+            Err(e) => {
+                self.clear_conflicts(path).with_context(|| {
+                    format!("Can't clear conflicts after handling error \"{:?}\"", e)
+                })?;
+                self.clone_inner(path, source, flag).with_context(|| {
+                    format!("Can't clone '{:?}' after handling error \"{:?}\"", path, e)
+                })
+            }
+        }
+    }
+
     pub fn set_executable(&self, path: &RepoPath, flag: bool) -> Result<()> {
         let filepath = self
             .inner
@@ -336,6 +376,12 @@ impl VFS {
     pub fn supports_executables(&self) -> bool {
         self.inner.supports_executables
     }
+
+    /// Whether the filesystem may support cloning files (reflinks). Cloning can still fail, for
+    /// example on XFS filesystems created without reflink support.
+    pub fn supports_clone(&self) -> bool {
+        self.inner.supports_clone
+    }
 }
 
 #[cfg(unix)]
@@ -397,6 +443,26 @@ mod unix_tests {
         assert_eq!(0, metadata.permissions().mode() & 0o111)
     }
 
+    #[test]
+    fn test_clone_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let vfs = VFS::new(tmp.path().to_path_buf()).unwrap();
+        let source = tmp.path().join("source");
+        fs::write(&source, b"abc").unwrap();
+        let path = RepoPath::from_str("a/b").unwrap();
+
+        assert!(vfs.clone_file(path, &source, UpdateFlag::Symlink).is_err());
+        // Cloning depends on the filesystem the test runs on.
+        if let Ok(size) = vfs.clone_file(path, &source, UpdateFlag::Executable) {
+            assert_eq!(size, 3);
+            assert_eq!(vfs.read(path).unwrap(), b"abc");
+            let metadata = fs::symlink_metadata(vfs.join(path)).unwrap();
+            assert_ne!(0, metadata.permissions().mode() & 0o111);
+        }
+    }
+
     #[test]
     fn test_update_mode() {
         assert_eq!(0o644, VFS::update_mode(0o644, false));
@@ -432,6 +498,65 @@ fn supports_executables(fs_type: &FsType) -> bool {
     }
 }
 
+/// Filesystems that can share blocks between files: APFS on macOS, Btrfs and XFS on Linux.
+fn supports_clone(fs_type: &FsType) -> bool {
+    match *fs_type {
+        FsType::APFS => cfg!(target_os = "macos"),
+        FsType::BTRFS | FsType::XFS => cfg!(target_os = "linux"),
+        _ => false,
+    }
+}
+
+/// Clone `source` into `dest`, replacing `dest` if it exists. Return the size of the file.
+#[cfg(target_os = "linux")]
+pub fn clone_file(source: &Path, dest: &Path) -> Result<usize> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    // _IOW(0x94, 9, int), not exposed by all the versions of libc.
+    const FICLONE: u32 = 0x40049409;
+
+    let src = File::open(source)?;
+    let size = src.metadata()?.len() as usize;
+    let dst = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(dest)?;
+    let ret = unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE as _, src.as_raw_fd()) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(size)
+}
+
+/// Clone `source` into `dest`, replacing `dest` if it exists. Return the size of the file.
+#[cfg(target_os = "macos")]
+pub fn clone_file(source: &Path, dest: &Path) -> Result<usize> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let size = source.metadata()?.len() as usize;
+    // clonefile refuses to overwrite an existing file.
+    match std::fs::remove_file(dest) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    let src = CString::new(source.as_os_str().as_bytes())?;
+    let dst = CString::new(dest.as_os_str().as_bytes())?;
+    let ret = unsafe { libc::clonefile(src.as_ptr(), dst.as_ptr(), libc::CLONE_NOFOLLOW) };
+    if ret != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(size)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn clone_file(_source: &Path, _dest: &Path) -> Result<usize> {
+    bail!("cloning files is not supported on this platform")
+}
+
 /// determines whether FS located at root is case sensitive
 fn case_sensitive(root: &Path, fs_type: &FsType) -> Result<bool> {
     // Logic in this function is consistent with util.fscasesensitive in Python
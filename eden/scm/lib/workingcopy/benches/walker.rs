@@ -0,0 +1,61 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fs;
+use std::path::Path;
+
+use minibench::bench;
+use minibench::elapsed;
+use pathmatcher::AlwaysMatcher;
+use tempfile::tempdir;
+use workingcopy::walker::Walker;
+
+/// Create `width` directories at each level, `depth` levels deep, with
+/// `files` files in each directory.
+fn create_tree(root: &Path, width: usize, depth: usize, files: usize) {
+    for i in 0..files {
+        fs::write(root.join(format!("f{}", i)), b"").unwrap();
+    }
+    if depth == 0 {
+        return;
+    }
+    for i in 0..width {
+        let dir = root.join(format!("d{}", i));
+        fs::create_dir(&dir).unwrap();
+        create_tree(&dir, width, depth - 1, files);
+    }
+}
+
+fn main() {
+    // (name, width, depth, files per directory)
+    let trees = [
+        ("wide (1000 dirs x 20 files)", 1000, 1, 20),
+        ("deep and wide (20^2 dirs x 50 files)", 20, 2, 50),
+        ("flat (20000 files)", 0, 0, 20000),
+    ];
+    for (name, width, depth, files) in trees {
+        let dir = tempdir().unwrap();
+        create_tree(dir.path(), width, depth, files);
+        for num_threads in [0, 1, 4, 8, 16] {
+            bench(
+                format!("walk {} with {} threads", name, num_threads),
+                || {
+                    elapsed(|| {
+                        let walker = Walker::new(
+                            dir.path().to_path_buf(),
+                            AlwaysMatcher::new(),
+                            false,
+                            num_threads,
+                        )
+                        .unwrap();
+                        assert!(walker.count() > 0);
+                    })
+                },
+            );
+        }
+    }
+}
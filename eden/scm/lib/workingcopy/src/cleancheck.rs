@@ -0,0 +1,272 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Decide whether a tracked file is clean from its size, mtime and mode,
+//! without reading its content.
+
+use std::fs::Metadata;
+
+use anyhow::Result;
+use treestate::filestate::FileStateV2;
+use treestate::filestate::StateFlags;
+use types::RepoPathBuf;
+use vfs::is_executable;
+use vfs::is_symlink;
+use vfs::VFS;
+
+use crate::filechangedetector::HgModifiedTime;
+use crate::walker::WalkError;
+
+/// Metadata of a file in the working copy that is compared with the
+/// treestate.
+#[derive(Clone, Debug)]
+pub struct FileMetadata {
+    pub size: u64,
+    pub mtime: HgModifiedTime,
+    pub is_executable: bool,
+    pub is_symlink: bool,
+}
+
+/// Result of [`CleanChecker::check`].
+#[derive(Debug, PartialEq)]
+pub enum CleanCheck {
+    /// The file is unchanged.
+    Clean,
+    /// The size or the executable bit changed.
+    Modified { mode_changed: bool },
+    /// The file became a symlink, or the other way around.
+    TypeChanged,
+    /// Metadata is not enough to tell. The content needs to be compared.
+    NeedCheck,
+}
+
+/// Compares working copy metadata with treestate entries.
+#[derive(Clone)]
+pub struct CleanChecker {
+    last_write: HgModifiedTime,
+    mtime_race_window: u64,
+    check_executable: bool,
+    check_symlink: bool,
+}
+
+impl CleanChecker {
+    /// `last_write` is when the treestate was last written. Files modified
+    /// within `mtime_race_window` seconds of it might have been changed
+    /// again without changing their mtime, so their content is compared.
+    pub fn new(last_write: HgModifiedTime, mtime_race_window: u64, vfs: &VFS) -> Self {
+        Self {
+            last_write,
+            mtime_race_window,
+            check_executable: vfs.supports_executables(),
+            check_symlink: vfs.supports_symlinks(),
+        }
+    }
+
+    /// Extract the metadata used by [`CleanChecker::check`].
+    pub fn file_metadata(&self, metadata: &Metadata) -> Result<FileMetadata> {
+        Ok(FileMetadata {
+            size: metadata.len(),
+            mtime: metadata.modified()?.try_into()?,
+            is_executable: self.check_executable && is_executable(metadata),
+            is_symlink: self.check_symlink && is_symlink(metadata),
+        })
+    }
+
+    /// Check a file that exists in the working copy and in P1.
+    pub fn check(
+        &self,
+        path: &RepoPathBuf,
+        state: &FileStateV2,
+        metadata: &FileMetadata,
+    ) -> Result<CleanCheck> {
+        // If working copy file size or flags are different from what is in treestate, it has changed.
+        // Note: state.size is i32 since Mercurial uses negative numbers to indicate special files.
+        // A -1 indicates the file is either in a merge state or a lookup state.
+        // A -2 indicates the file comes from the other parent (and may or may not exist in the
+        // current parent).
+        //
+        // Regardless, if the size is negative, we'll do a lookup comparison since we can't
+        // determine if the file has changed relative to p1. This logic is a mess and we should get
+        // rid of all these negative numbers.
+        let valid_size = state.size >= 0;
+        if valid_size {
+            let size_different = metadata.size != state.size.try_into().unwrap_or(std::u64::MAX);
+            let exec_different =
+                self.check_executable && metadata.is_executable != state.is_executable();
+            let symlink_different = self.check_symlink && metadata.is_symlink != state.is_symlink();
+
+            if symlink_different {
+                return Ok(CleanCheck::TypeChanged);
+            }
+            if size_different || exec_different {
+                return Ok(CleanCheck::Modified {
+                    mode_changed: exec_different,
+                });
+            }
+        }
+
+        // If it's marked NEED_CHECK, we always need to do a lookup, regardless of the mtime.
+        let needs_check = state.state.intersects(StateFlags::NEED_CHECK) || !valid_size;
+        if needs_check {
+            return Ok(CleanCheck::NeedCheck);
+        }
+
+        // If the mtime has changed or is close to the last normal() write time, we need to
+        // compare the file contents in the later Lookups phase. mtime can be negative as well.
+        // A -1 indicates the file is in a lookup state.
+        if state.mtime < 0 {
+            return Ok(CleanCheck::NeedCheck);
+        }
+
+        let state_mtime: Result<HgModifiedTime> = state.mtime.try_into();
+        let state_mtime = state_mtime.map_err(|e| WalkError::InvalidMTime(path.to_owned(), e))?;
+        if metadata.mtime != state_mtime
+            || metadata
+                .mtime
+                .is_within(&self.last_write, self.mtime_race_window)
+        {
+            return Ok(CleanCheck::NeedCheck);
+        }
+
+        Ok(CleanCheck::Clean)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAST_WRITE: u64 = 1000;
+
+    fn checker(mtime_race_window: u64) -> CleanChecker {
+        CleanChecker {
+            last_write: LAST_WRITE.into(),
+            mtime_race_window,
+            check_executable: true,
+            check_symlink: true,
+        }
+    }
+
+    fn state(mode: u32, size: i32, mtime: i32, flags: StateFlags) -> FileStateV2 {
+        FileStateV2 {
+            mode,
+            size,
+            mtime,
+            state: StateFlags::EXIST_P1 | StateFlags::EXIST_NEXT | flags,
+            copied: None,
+        }
+    }
+
+    fn metadata(size: u64, mtime: u64, is_executable: bool, is_symlink: bool) -> FileMetadata {
+        FileMetadata {
+            size,
+            mtime: mtime.into(),
+            is_executable,
+            is_symlink,
+        }
+    }
+
+    fn check(checker: &CleanChecker, state: &FileStateV2, metadata: &FileMetadata) -> CleanCheck {
+        let path = RepoPathBuf::from_string("a".to_string()).unwrap();
+        checker.check(&path, state, metadata).unwrap()
+    }
+
+    #[test]
+    fn test_clean() {
+        let state = state(0o644, 3, 10, StateFlags::empty());
+        assert_eq!(
+            check(&checker(0), &state, &metadata(3, 10, false, false)),
+            CleanCheck::Clean
+        );
+    }
+
+    #[test]
+    fn test_modified() {
+        let checker = checker(0);
+        let state = state(0o644, 3, 10, StateFlags::empty());
+        assert_eq!(
+            check(&checker, &state, &metadata(4, 10, false, false)),
+            CleanCheck::Modified {
+                mode_changed: false
+            }
+        );
+        // Executable bit changes are detected without reading the content.
+        assert_eq!(
+            check(&checker, &state, &metadata(3, 10, true, false)),
+            CleanCheck::Modified { mode_changed: true }
+        );
+        assert_eq!(
+            check(&checker, &state, &metadata(3, 10, false, true)),
+            CleanCheck::TypeChanged
+        );
+
+        // Flags are ignored if the file system does not support them.
+        let checker = CleanChecker {
+            check_executable: false,
+            ..checker
+        };
+        assert_eq!(
+            check(&checker, &state, &metadata(3, 10, true, false)),
+            CleanCheck::Clean
+        );
+    }
+
+    #[test]
+    fn test_need_check() {
+        let checker = checker(0);
+        // Explicitly marked.
+        let state1 = state(0o644, 3, 10, StateFlags::NEED_CHECK);
+        assert_eq!(
+            check(&checker, &state1, &metadata(3, 10, false, false)),
+            CleanCheck::NeedCheck
+        );
+        // Size is unknown (merge or lookup state).
+        let state2 = state(0o644, -1, 10, StateFlags::empty());
+        assert_eq!(
+            check(&checker, &state2, &metadata(5, 10, false, false)),
+            CleanCheck::NeedCheck
+        );
+        // Mtime is unknown.
+        let state3 = state(0o644, 3, -1, StateFlags::empty());
+        assert_eq!(
+            check(&checker, &state3, &metadata(3, 10, false, false)),
+            CleanCheck::NeedCheck
+        );
+        // Mtime changed but size did not.
+        let state4 = state(0o644, 3, 10, StateFlags::empty());
+        assert_eq!(
+            check(&checker, &state4, &metadata(3, 11, false, false)),
+            CleanCheck::NeedCheck
+        );
+        // Size changes are detected even if the file needs a check.
+        assert_eq!(
+            check(&checker, &state1, &metadata(4, 10, false, false)),
+            CleanCheck::Modified {
+                mode_changed: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_mtime_race_window() {
+        let mtime = LAST_WRITE - 2;
+        let early_state = state(0o644, 3, mtime as i32, StateFlags::empty());
+        let early_metadata = metadata(3, mtime, false, false);
+        let early_check = |window| check(&checker(window), &early_state, &early_metadata);
+        assert_eq!(early_check(0), CleanCheck::Clean);
+        assert_eq!(early_check(1), CleanCheck::Clean);
+        assert_eq!(early_check(2), CleanCheck::NeedCheck);
+
+        // Files written at the same time as the treestate are always checked.
+        let racy_state = state(0o644, 3, LAST_WRITE as i32, StateFlags::empty());
+        let racy_metadata = metadata(3, LAST_WRITE, false, false);
+        assert_eq!(
+            check(&checker(0), &racy_state, &racy_metadata),
+            CleanCheck::NeedCheck
+        );
+    }
+}
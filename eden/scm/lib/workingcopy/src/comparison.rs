@@ -0,0 +1,270 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Strategies to confirm whether "lookup" files, whose metadata isn't enough
+//! to tell whether they changed, differ from their version in the parent commit.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Result;
+use futures::StreamExt;
+use sha2::Digest;
+use storemodel::FileAuxData;
+use storemodel::ReadFileAuxData;
+use types::Key;
+use types::RepoPathBuf;
+use types::Sha256;
+use vfs::VFS;
+
+use crate::filechangedetector::ArcReadFileContents;
+use crate::filechangedetector::ResolvedFileChangeResult;
+use crate::filesystem::ChangeType;
+
+pub type ArcReadFileAuxData = Arc<dyn ReadFileAuxData<Error = anyhow::Error> + Send + Sync>;
+pub type ArcContentComparison = Arc<dyn ContentComparison>;
+
+/// Compares working copy files with their version in the parent commit.
+pub trait ContentComparison: Send + Sync {
+    /// Compare the files on disk with `keys`, their versions in the parent commit.
+    fn compare(&self, vfs: &VFS, keys: Vec<Key>) -> Vec<Result<ResolvedFileChangeResult>>;
+}
+
+/// How lookup files are compared.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComparisonStrategy {
+    /// Compare the full content. Always correct, but the content of the parent
+    /// commit has to be fetched and both versions read.
+    Content,
+    /// Compare the size and SHA-256 of the file on disk with the ones recorded
+    /// by the store. The content of the parent commit isn't fetched.
+    Hash,
+    /// Only compare sizes. Modifications that keep the size of a file are
+    /// missed.
+    Size,
+}
+
+impl FromStr for ComparisonStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "content" => Ok(ComparisonStrategy::Content),
+            "hash" => Ok(ComparisonStrategy::Hash),
+            "size" => Ok(ComparisonStrategy::Size),
+            _ => bail!("unknown content comparison strategy '{}'", s),
+        }
+    }
+}
+
+impl ComparisonStrategy {
+    /// Build the comparison. `Hash` and `Size` need a store with aux data.
+    pub fn build(
+        self,
+        store: ArcReadFileContents,
+        aux_store: Option<ArcReadFileAuxData>,
+    ) -> Result<ArcContentComparison> {
+        Ok(match (self, aux_store) {
+            (ComparisonStrategy::Content, _) => Arc::new(FullContentComparison::new(store)),
+            (ComparisonStrategy::Hash, Some(aux_store)) => {
+                Arc::new(AuxDataComparison::new(aux_store, true))
+            }
+            (ComparisonStrategy::Size, Some(aux_store)) => {
+                tracing::warn!(
+                    "comparing lookup files by size only, some modifications may be missed"
+                );
+                Arc::new(AuxDataComparison::new(aux_store, false))
+            }
+            (strategy, None) => bail!(
+                "{:?} content comparison requires a store with aux data",
+                strategy
+            ),
+        })
+    }
+}
+
+pub struct FullContentComparison {
+    store: ArcReadFileContents,
+}
+
+impl FullContentComparison {
+    pub fn new(store: ArcReadFileContents) -> Self {
+        FullContentComparison { store }
+    }
+}
+
+impl ContentComparison for FullContentComparison {
+    fn compare(&self, vfs: &VFS, keys: Vec<Key>) -> Vec<Result<ResolvedFileChangeResult>> {
+        async_runtime::block_on(async {
+            self.store
+                .read_file_contents(keys)
+                .await
+                .map(|result| {
+                    let (expected, key) = result?;
+                    let actual = match vfs.read(&key.path) {
+                        Ok(x) => x,
+                        Err(e) => return deleted_or_err(key.path, e),
+                    };
+                    Ok(resolved(key.path, expected == actual))
+                })
+                .collect::<Vec<_>>()
+                .await
+        })
+    }
+}
+
+/// Compares files with the aux data recorded by the store: the size, and the
+/// SHA-256 of the content if `check_hash` is set.
+pub struct AuxDataComparison {
+    store: ArcReadFileAuxData,
+    check_hash: bool,
+}
+
+impl AuxDataComparison {
+    pub fn new(store: ArcReadFileAuxData, check_hash: bool) -> Self {
+        AuxDataComparison { store, check_hash }
+    }
+
+    fn compare_one(
+        &self,
+        vfs: &VFS,
+        aux: FileAuxData,
+        path: RepoPathBuf,
+    ) -> Result<ResolvedFileChangeResult> {
+        let metadata = match vfs.metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => return deleted_or_err(path, e),
+        };
+        if metadata.len() != aux.total_size {
+            return Ok(resolved(path, false));
+        }
+        if !self.check_hash {
+            return Ok(resolved(path, true));
+        }
+        let actual = match vfs.read(&path) {
+            Ok(x) => x,
+            Err(e) => return deleted_or_err(path, e),
+        };
+        let hash: [u8; Sha256::len()] = sha2::Sha256::digest(&actual).into();
+        Ok(resolved(path, Sha256::from(&hash) == aux.content_sha256))
+    }
+}
+
+impl ContentComparison for AuxDataComparison {
+    fn compare(&self, vfs: &VFS, keys: Vec<Key>) -> Vec<Result<ResolvedFileChangeResult>> {
+        async_runtime::block_on(async {
+            self.store
+                .read_file_aux_data(keys)
+                .await
+                .map(|result| {
+                    let (aux, key) = result?;
+                    self.compare_one(vfs, aux, key.path)
+                })
+                .collect::<Vec<_>>()
+                .await
+        })
+    }
+}
+
+fn resolved(path: RepoPathBuf, same: bool) -> ResolvedFileChangeResult {
+    if same {
+        ResolvedFileChangeResult::No(path)
+    } else {
+        ResolvedFileChangeResult::Yes(ChangeType::Modified {
+            path,
+            mode_changed: false,
+        })
+    }
+}
+
+fn deleted_or_err(path: RepoPathBuf, e: anyhow::Error) -> Result<ResolvedFileChangeResult> {
+    match e.downcast_ref::<std::io::Error>() {
+        Some(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(ResolvedFileChangeResult::Yes(ChangeType::Deleted(path)))
+        }
+        _ => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use futures::stream;
+    use futures::stream::BoxStream;
+    use storemodel::minibytes::Bytes;
+    use storemodel::ComputedFileAuxData;
+    use storemodel::ReadFileContents;
+    use tempfile::TempDir;
+    use types::HgId;
+
+    use super::*;
+
+    /// A store without aux data, which always returns the same content.
+    struct FakeContentStore(Bytes);
+
+    #[async_trait]
+    impl ReadFileContents for FakeContentStore {
+        type Error = anyhow::Error;
+
+        async fn read_file_contents(&self, keys: Vec<Key>) -> BoxStream<Result<(Bytes, Key)>> {
+            let content = self.0.clone();
+            stream::iter(keys.into_iter().map(move |key| Ok((content.clone(), key)))).boxed()
+        }
+    }
+
+    fn compare(check_hash: bool, stored: &[u8], on_disk: Option<&[u8]>) -> Result<bool> {
+        let dir = TempDir::new()?;
+        if let Some(on_disk) = on_disk {
+            std::fs::write(dir.path().join("a"), on_disk)?;
+        }
+        // The aux data is computed from the content by the store model.
+        let store = FakeContentStore(Bytes::copy_from_slice(stored));
+        let comparison = AuxDataComparison::new(Arc::new(ComputedFileAuxData(store)), check_hash);
+        let vfs = VFS::new(dir.path().to_path_buf())?;
+        let path = RepoPathBuf::from_string("a".to_string())?;
+        let key = Key::new(path.clone(), *HgId::null_id());
+        let mut results = comparison.compare(&vfs, vec![key]);
+        Ok(match results.pop().unwrap()? {
+            ResolvedFileChangeResult::No(_) => false,
+            ResolvedFileChangeResult::Yes(ChangeType::Deleted(_)) => {
+                assert!(on_disk.is_none());
+                true
+            }
+            ResolvedFileChangeResult::Yes(change) => {
+                assert!(on_disk.is_some());
+                assert_eq!(
+                    change,
+                    ChangeType::Modified {
+                        path,
+                        mode_changed: false,
+                    }
+                );
+                true
+            }
+        })
+    }
+
+    #[test]
+    fn test_hash_comparison() -> Result<()> {
+        assert!(!compare(true, b"abc", Some(b"abc"))?);
+        assert!(compare(true, b"abc", Some(b"abd"))?);
+        assert!(compare(true, b"abc", Some(b"abcd"))?);
+        assert!(compare(true, b"abc", None)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_comparison() -> Result<()> {
+        assert!(!compare(false, b"abc", Some(b"abc"))?);
+        // Same size: the modification is missed.
+        assert!(!compare(false, b"abc", Some(b"abd"))?);
+        assert!(compare(false, b"abc", Some(b"abcd"))?);
+        assert!(compare(false, b"abc", None)?);
+        Ok(())
+    }
+}
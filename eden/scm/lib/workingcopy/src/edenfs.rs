@@ -5,11 +5,15 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::BTreeSet;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::anyhow;
 use anyhow::Result;
+use error_code::ErrorCode;
+use error_code::WithErrorCode;
+use parking_lot::Mutex;
 use pathmatcher::Matcher;
 use thrift_types::edenfs::ScmFileStatus;
 use types::RepoPathBuf;
@@ -17,14 +21,39 @@ use types::RepoPathBuf;
 use crate::filesystem::ChangeType;
 use crate::filesystem::PendingChangeResult;
 use crate::filesystem::PendingChanges;
+use crate::walker::WalkProgress;
 
 pub struct EdenFileSystem {
     root: PathBuf,
+    // Directories containing the pending changes seen by the last status.
+    accessed_dirs: Mutex<BTreeSet<RepoPathBuf>>,
+    // Whether to prefetch the unloaded directories touched by status.
+    prefetch_unloaded: bool,
 }
 
 impl EdenFileSystem {
-    pub fn new(root: PathBuf) -> Result<Self> {
-        Ok(EdenFileSystem { root })
+    pub fn new(root: PathBuf, prefetch_unloaded: bool) -> Result<Self> {
+        Ok(EdenFileSystem {
+            root,
+            accessed_dirs: Default::default(),
+            prefetch_unloaded,
+        })
+    }
+
+    /// Directories containing the pending changes seen by the last call to
+    /// `pending_changes`.
+    pub fn accessed_directories(&self) -> Vec<RepoPathBuf> {
+        self.accessed_dirs.lock().iter().cloned().collect()
+    }
+
+    /// Accessed directories that EdenFS has not loaded yet.
+    pub fn unloaded_directories(&self) -> Result<Vec<RepoPathBuf>> {
+        edenfs_client::prefetch::get_unloaded_directories(&self.root, &self.accessed_directories())
+    }
+
+    /// Ask EdenFS to prefetch `dirs`, recursively.
+    pub fn prefetch_directories(&self, dirs: &[RepoPathBuf], background: bool) -> Result<()> {
+        edenfs_client::prefetch::prefetch_directories(&self.root, dirs, background)
     }
 }
 
@@ -32,8 +61,32 @@ impl PendingChanges for EdenFileSystem {
     fn pending_changes(
         &self,
         _matcher: Arc<dyn Matcher + Send + Sync + 'static>,
+        _progress: Option<WalkProgress>,
     ) -> Result<Box<dyn Iterator<Item = Result<PendingChangeResult>>>> {
-        let result = edenfs_client::status::get_status(&self.root)?;
+        let result = edenfs_client::status::get_status(&self.root)
+            .with_error_code(ErrorCode::EdenFsFailed)?;
+
+        let accessed_dirs: BTreeSet<RepoPathBuf> = result
+            .status
+            .entries
+            .keys()
+            .filter_map(|path| RepoPathBuf::from_utf8(path.clone()).ok())
+            .filter_map(|path| path.parent().map(|p| p.to_owned()))
+            .collect();
+        if self.prefetch_unloaded {
+            // Warm up the directories status touched, so following commands
+            // like diff do not stall on a cold mount. This runs in the
+            // background and is best-effort. The root is skipped since
+            // prefetching it means prefetching the whole repo.
+            let dirs = accessed_dirs
+                .iter()
+                .filter(|dir| !dir.is_empty())
+                .cloned()
+                .collect();
+            edenfs_client::prefetch::spawn_prefetch_unloaded_directories(self.root.clone(), dirs);
+        }
+        *self.accessed_dirs.lock() = accessed_dirs;
+
         Ok(Box::new(result.status.entries.into_iter().filter_map(
             |(path, status)| {
                 {
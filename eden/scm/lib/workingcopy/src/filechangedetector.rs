@@ -13,7 +13,6 @@ use std::time::SystemTime;
 
 use anyhow::Error;
 use anyhow::Result;
-use futures::StreamExt;
 use manifest::Manifest;
 use manifest_tree::TreeManifest;
 use parking_lot::RwLock;
@@ -24,19 +23,26 @@ use treestate::filestate::StateFlags;
 use treestate::treestate::TreeState;
 use types::Key;
 use types::RepoPathBuf;
-use vfs::is_executable;
-use vfs::is_symlink;
 use vfs::VFS;
 
+use crate::cleancheck::CleanCheck;
+use crate::cleancheck::CleanChecker;
+use crate::comparison::ArcContentComparison;
 use crate::filesystem::ChangeType;
-use crate::walker::WalkError;
 
 pub type ArcReadFileContents = Arc<dyn ReadFileContents<Error = anyhow::Error> + Send + Sync>;
 
 /// Represents a file modification time in Mercurial, in seconds since the unix epoch.
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct HgModifiedTime(u64);
 
+impl HgModifiedTime {
+    /// Whether `self` and `other` are at most `window` seconds apart.
+    pub fn is_within(&self, other: &Self, window: u64) -> bool {
+        self.0.abs_diff(other.0) <= window
+    }
+}
+
 impl From<u64> for HgModifiedTime {
     fn from(value: u64) -> Self {
         HgModifiedTime(value)
@@ -86,10 +92,10 @@ pub trait FileChangeDetectorTrait {
 pub struct FileChangeDetector {
     treestate: Rc<RefCell<TreeState>>,
     vfs: VFS,
-    last_write: HgModifiedTime,
+    clean_checker: CleanChecker,
     lookups: Vec<RepoPathBuf>,
     manifest: Arc<RwLock<TreeManifest>>,
-    store: ArcReadFileContents,
+    comparison: ArcContentComparison,
 }
 
 impl FileChangeDetector {
@@ -97,17 +103,19 @@ impl FileChangeDetector {
         treestate: Rc<RefCell<TreeState>>,
         vfs: VFS,
         last_write: HgModifiedTime,
+        mtime_race_window: u64,
         manifest: Arc<RwLock<TreeManifest>>,
-        store: ArcReadFileContents,
+        comparison: ArcContentComparison,
     ) -> Self {
         let lookups: Vec<RepoPathBuf> = vec![];
+        let clean_checker = CleanChecker::new(last_write, mtime_race_window, &vfs);
         FileChangeDetector {
             treestate,
             vfs,
-            last_write,
+            clean_checker,
             lookups,
             manifest,
-            store,
+            comparison,
         }
     }
 }
@@ -125,9 +133,19 @@ impl FileChangeDetector {
             // File exists and is in the tree state: it might have changed.
             (Some(state), true) => state,
 
-            // If the file is not valid (e.g. a directory or a weird file like
-            // a fifo file) but exists in P1 (as a valid file at some previous
-            // time) then we consider it now deleted.
+            // If the file is now a directory but exists in P1 (as a valid
+            // file at some previous time), its type changed.
+            (Some(state), false)
+                if file_type.is_dir() && state.state.intersects(StateFlags::EXIST_P1) =>
+            {
+                return Ok(FileChangeResult::Yes(ChangeType::TypeChanged {
+                    path: path.clone(),
+                    is_directory: true,
+                }));
+            }
+
+            // If the file is not valid (e.g. a weird file like a fifo file)
+            // but exists in P1 then we consider it now deleted.
             (Some(state), false) if state.state.intersects(StateFlags::EXIST_P1) => {
                 return Ok(Self::deleted(path));
             }
@@ -151,58 +169,26 @@ impl FileChangeDetector {
         let flags = state.state;
         let in_parent = flags.intersects(StateFlags::EXIST_P1); // TODO: Also check against P2?
         if !in_parent {
-            return Ok(Self::changed(path));
+            return Ok(FileChangeResult::Yes(ChangeType::Added(path.clone())));
         }
 
-        // If working copy file size or flags are different from what is in treestate, it has changed.
-        // Note: state.size is i32 since Mercurial uses negative numbers to indicate special files.
-        // A -1 indicates the file is either in a merge state or a lookup state.
-        // A -2 indicates the file comes from the other parent (and may or may not exist in the
-        // current parent).
-        //
-        // Regardless, if the size is negative, we'll do a lookup comparison since we can't
-        // determine if the file has changed relative to p1. This logic is a mess and we should get
-        // rid of all these negative numbers.
-        let valid_size = state.size >= 0;
-        if valid_size {
-            let size_different = metadata.len() != state.size.try_into().unwrap_or(std::u64::MAX);
-            let exec_different = self.vfs.supports_executables()
-                && is_executable(&metadata) != state.is_executable();
-            let symlink_different =
-                self.vfs.supports_symlinks() && is_symlink(&metadata) != state.is_symlink();
-
-            if size_different || exec_different || symlink_different {
-                return Ok(Self::changed(path));
+        let metadata = self.clean_checker.file_metadata(&metadata)?;
+        let result = match self.clean_checker.check(path, &state, &metadata)? {
+            CleanCheck::Clean => FileChangeResult::No,
+            CleanCheck::Modified { mode_changed } => FileChangeResult::Yes(ChangeType::Modified {
+                path: path.clone(),
+                mode_changed,
+            }),
+            CleanCheck::TypeChanged => FileChangeResult::Yes(ChangeType::TypeChanged {
+                path: path.clone(),
+                is_directory: false,
+            }),
+            CleanCheck::NeedCheck => {
+                self.lookups.push(path.to_owned());
+                FileChangeResult::Maybe
             }
-        }
-
-        // If it's marked NEED_CHECK, we always need to do a lookup, regardless of the mtime.
-        let needs_check = flags.intersects(StateFlags::NEED_CHECK) || !valid_size;
-        if needs_check {
-            self.lookups.push(path.to_owned());
-            return Ok(FileChangeResult::Maybe);
-        }
-
-        // If the mtime has changed or matches the last normal() write time, we need to compare the
-        // file contents in the later Lookups phase.  mtime can be negative as well. A -1 indicates
-        // the file is in a lookup state. Since a -1 will always cause the equality comparison
-        // below to fail and force a lookup, the -1 is handled correctly without special casing. In
-        // theory all -1 files should be marked NEED_CHECK above (I think).
-        if state.mtime < 0 {
-            self.lookups.push(path.to_owned());
-            return Ok(FileChangeResult::Maybe);
-        }
-
-        let state_mtime: Result<HgModifiedTime> = state.mtime.try_into();
-        let state_mtime = state_mtime.map_err(|e| WalkError::InvalidMTime(path.to_owned(), e))?;
-        let mtime: HgModifiedTime = metadata.modified()?.try_into()?;
-
-        if mtime != state_mtime || mtime == self.last_write {
-            self.lookups.push(path.to_owned());
-            return Ok(FileChangeResult::Maybe);
-        }
-
-        Ok(FileChangeResult::No)
+        };
+        Ok(result)
     }
 
     fn get_treestate(&self, path: &RepoPathBuf) -> Result<Option<FileStateV2>> {
@@ -273,43 +259,135 @@ impl FileChangeDetectorTrait for FileChangeDetector {
             })
             .collect::<Vec<_>>();
 
-        // Then fetch the contents of each file and check it against the filesystem.
-        // TODO: if the underlying stores gain the ability to do hash-based comparisons,
-        // switch this to use that (rather than pulling down the entire contents of each
-        // file).
-        let vfs = self.vfs.clone();
-        let comparisons = async_runtime::block_on(async {
-            self.store
-                .read_file_contents(keys)
-                .await
-                .map(|result| {
-                    let (expected, key) = match result {
-                        Ok(x) => x,
-                        Err(e) => return Err(e),
-                    };
-                    let actual = match vfs.read(&key.path) {
-                        Ok(x) => x,
-                        Err(e) => match e.downcast_ref::<std::io::Error>() {
-                            Some(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                                return Ok(ResolvedFileChangeResult::Yes(ChangeType::Deleted(
-                                    key.path,
-                                )));
-                            }
-                            _ => return Err(e),
-                        },
-                    };
-                    if expected == actual {
-                        Ok(ResolvedFileChangeResult::No(key.path))
-                    } else {
-                        Ok(ResolvedFileChangeResult::Yes(ChangeType::Changed(key.path)))
-                    }
-                })
-                .collect::<Vec<_>>()
-                .await
-        });
-        results.extend(comparisons);
+        // Then check each file against the filesystem.
+        results.extend(self.comparison.compare(&self.vfs, keys));
         Box::new(results.into_iter())
     }
     // TODO: after finishing these comparisons, update the cached mtimes of files so we
     // don't have to do a comparison again next time.
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::fs::PermissionsExt;
+
+    use async_trait::async_trait;
+    use futures::stream;
+    use futures::stream::BoxStream;
+    use futures::StreamExt;
+    use manifest_tree::TreeStore;
+    use storemodel::minibytes::Bytes;
+    use tempfile::TempDir;
+    use types::HgId;
+    use types::RepoPath;
+
+    use super::*;
+    use crate::comparison::ComparisonStrategy;
+
+    /// A store without files or trees. Detecting changes from metadata
+    /// does not read it.
+    struct EmptyStore;
+
+    #[async_trait]
+    impl ReadFileContents for EmptyStore {
+        type Error = anyhow::Error;
+
+        async fn read_file_contents(&self, _keys: Vec<Key>) -> BoxStream<Result<(Bytes, Key)>> {
+            stream::empty().boxed()
+        }
+    }
+
+    impl TreeStore for EmptyStore {
+        fn get(&self, path: &RepoPath, _hgid: HgId) -> Result<Bytes> {
+            anyhow::bail!("unexpected tree read: {}", path)
+        }
+
+        fn insert(&self, path: &RepoPath, _hgid: HgId, _data: Bytes) -> Result<()> {
+            anyhow::bail!("unexpected tree write: {}", path)
+        }
+    }
+
+    /// Track "a" in `root` as a clean, non-executable file in P1.
+    fn detector(root: &TempDir, treestate_dir: &TempDir) -> Result<FileChangeDetector> {
+        let path = root.path().join("a");
+        fs::write(&path, b"abc")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+        let mtime: HgModifiedTime = fs::symlink_metadata(&path)?.modified()?.try_into()?;
+
+        let mut treestate = TreeState::open(treestate_dir.path().join("1"), None)?;
+        let state = FileStateV2 {
+            mode: 0o644,
+            size: 3,
+            mtime: mtime.0.try_into()?,
+            state: StateFlags::EXIST_P1 | StateFlags::EXIST_NEXT,
+            copied: None,
+        };
+        treestate.insert("a", &state)?;
+
+        let store = Arc::new(EmptyStore);
+        let manifest = TreeManifest::ephemeral(store.clone());
+        let comparison = ComparisonStrategy::Content.build(store, None)?;
+        Ok(FileChangeDetector::new(
+            Rc::new(RefCell::new(treestate)),
+            VFS::new(root.path().to_path_buf())?,
+            0u64.into(),
+            0,
+            Arc::new(RwLock::new(manifest)),
+            comparison,
+        ))
+    }
+
+    fn has_changed(detector: &mut FileChangeDetector) -> Result<Option<ChangeType>> {
+        let path = RepoPathBuf::from_string("a".to_string())?;
+        Ok(match detector.has_changed(&path)? {
+            FileChangeResult::Yes(change) => Some(change),
+            FileChangeResult::No => None,
+            FileChangeResult::Maybe => panic!("unexpected content check"),
+        })
+    }
+
+    #[test]
+    fn test_executable_bit_changed() -> Result<()> {
+        let (root, treestate_dir) = (TempDir::new()?, TempDir::new()?);
+        let mut detector = detector(&root, &treestate_dir)?;
+        assert_eq!(has_changed(&mut detector)?, None);
+
+        // Detected without reading the content.
+        let path = root.path().join("a");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        assert_eq!(
+            has_changed(&mut detector)?,
+            Some(ChangeType::Modified {
+                path: RepoPathBuf::from_string("a".to_string())?,
+                mode_changed: true,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_type_changed() -> Result<()> {
+        let (root, treestate_dir) = (TempDir::new()?, TempDir::new()?);
+        let mut detector = detector(&root, &treestate_dir)?;
+        let path = root.path().join("a");
+        let type_changed = |is_directory| -> Result<Option<ChangeType>> {
+            Ok(Some(ChangeType::TypeChanged {
+                path: RepoPathBuf::from_string("a".to_string())?,
+                is_directory,
+            }))
+        };
+
+        // The file became a symlink.
+        fs::remove_file(&path)?;
+        symlink("abc", &path)?;
+        assert_eq!(has_changed(&mut detector)?, type_changed(false)?);
+
+        // The file became a directory.
+        fs::remove_file(&path)?;
+        fs::create_dir(&path)?;
+        assert_eq!(has_changed(&mut detector)?, type_changed(true)?);
+        Ok(())
+    }
+}
@@ -8,6 +8,7 @@
 mod pendingchanges;
 
 pub use pendingchanges::ChangeType;
+pub(crate) use pendingchanges::GroupByDirectory;
 pub use pendingchanges::PendingChangeResult;
 pub use pendingchanges::PendingChanges;
 
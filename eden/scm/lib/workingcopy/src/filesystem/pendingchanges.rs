@@ -5,6 +5,8 @@
  * GNU General Public License version 2.
  */
 
+use std::collections::btree_map;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -12,10 +14,31 @@ use pathmatcher::Matcher;
 use serde::Serialize;
 use types::RepoPathBuf;
 
-#[derive(Serialize)]
+use crate::walker::DirectoryFingerprint;
+use crate::walker::WalkProgress;
+
+#[derive(Debug, PartialEq, Serialize)]
 pub enum ChangeType {
+    /// Changed, without details about how. Used when the file system cannot
+    /// tell, like for untracked files or EdenFS.
     Changed(RepoPathBuf),
     Deleted(RepoPathBuf),
+    /// A file in the parent commit has different content or flags.
+    /// `mode_changed` is set if the executable bit changed. The content
+    /// might not be read if the mode changed.
+    Modified {
+        path: RepoPathBuf,
+        mode_changed: bool,
+    },
+    /// A file that is tracked but does not exist in the parent commit.
+    Added(RepoPathBuf),
+    /// A file in the parent commit became a symlink or a directory, or a
+    /// symlink became a file. `is_directory` is set if the path is now a
+    /// directory. Status reports such files as deleted.
+    TypeChanged {
+        path: RepoPathBuf,
+        is_directory: bool,
+    },
 }
 
 impl ChangeType {
@@ -23,6 +46,29 @@ impl ChangeType {
         match self {
             ChangeType::Changed(path) => path,
             ChangeType::Deleted(path) => path,
+            ChangeType::Modified { path, .. } => path,
+            ChangeType::Added(path) => path,
+            ChangeType::TypeChanged { path, .. } => path,
+        }
+    }
+
+    /// Whether the file no longer exists in the working copy.
+    pub fn is_deleted(&self) -> bool {
+        matches!(
+            self,
+            ChangeType::Deleted(_)
+                | ChangeType::TypeChanged {
+                    is_directory: true,
+                    ..
+                }
+        )
+    }
+
+    /// The directory containing the file. Empty for files at the root.
+    pub fn get_directory(&self) -> RepoPathBuf {
+        match self.get_path().parent() {
+            Some(dir) => dir.to_owned(),
+            None => RepoPathBuf::new(),
         }
     }
 }
@@ -30,12 +76,136 @@ impl ChangeType {
 #[derive(Serialize)]
 pub enum PendingChangeResult {
     File(ChangeType),
-    SeenDirectory(RepoPathBuf),
+    /// A directory in the working copy. The fingerprint is only set if
+    /// requested, and if the file system walks the working copy.
+    SeenDirectory(RepoPathBuf, Option<DirectoryFingerprint>),
+    /// An untracked file that matches the ignore rules. Only reported if
+    /// ignored files are requested.
+    Ignored(RepoPathBuf),
+    /// The root of a nested repository. Its content is not reported.
+    NestedRepo(RepoPathBuf),
 }
 
 pub trait PendingChanges {
+    /// `progress` receives the number of directories and files walked, if
+    /// the file system walks the working copy.
     fn pending_changes(
         &self,
         matcher: Arc<dyn Matcher + Send + Sync + 'static>,
+        progress: Option<WalkProgress>,
     ) -> Result<Box<dyn Iterator<Item = Result<PendingChangeResult>>>>;
+
+    /// Like `pending_changes`, but file changes are batched by the directory
+    /// containing them. Other results are dropped.
+    ///
+    /// File systems that walk the working copy report a directory as soon as
+    /// it was walked. Changes found after the walk, like deleted files, and
+    /// changes of file systems that do not walk, are reported at the end. So
+    /// a directory can be reported twice.
+    fn pending_changes_grouped(
+        &self,
+        matcher: Arc<dyn Matcher + Send + Sync + 'static>,
+        progress: Option<WalkProgress>,
+    ) -> Result<Box<dyn Iterator<Item = Result<(RepoPathBuf, Vec<ChangeType>)>>>> {
+        let changes = self.pending_changes(matcher, progress)?;
+        Ok(Box::new(GroupByDirectory::new(changes)))
+    }
+}
+
+/// Batches file changes by directory. A directory is reported at its
+/// [`PendingChangeResult::SeenDirectory`], which the walker reports right
+/// after the files of the directory. Other changes are reported at the end.
+pub(crate) struct GroupByDirectory<I> {
+    changes: I,
+    /// Changes of directories not reported yet.
+    pending: BTreeMap<RepoPathBuf, Vec<ChangeType>>,
+    /// Set once `changes` is exhausted.
+    remaining: Option<btree_map::IntoIter<RepoPathBuf, Vec<ChangeType>>>,
+}
+
+impl<I> GroupByDirectory<I> {
+    pub(crate) fn new(changes: I) -> Self {
+        Self {
+            changes,
+            pending: BTreeMap::new(),
+            remaining: None,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<PendingChangeResult>>> Iterator for GroupByDirectory<I> {
+    type Item = Result<(RepoPathBuf, Vec<ChangeType>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(remaining) = &mut self.remaining {
+            return remaining.next().map(Ok);
+        }
+        loop {
+            match self.changes.next() {
+                Some(Ok(PendingChangeResult::File(change))) => {
+                    self.pending
+                        .entry(change.get_directory())
+                        .or_default()
+                        .push(change);
+                }
+                Some(Ok(PendingChangeResult::SeenDirectory(dir, _))) => {
+                    if let Some(changes) = self.pending.remove(&dir) {
+                        return Some(Ok((dir, changes)));
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let mut remaining = std::mem::take(&mut self.pending).into_iter();
+                    let next = remaining.next();
+                    self.remaining = Some(remaining);
+                    return next.map(Ok);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> RepoPathBuf {
+        RepoPathBuf::from_string(path.to_string()).unwrap()
+    }
+
+    fn changed(path_str: &str) -> Result<PendingChangeResult> {
+        Ok(PendingChangeResult::File(ChangeType::Changed(path(
+            path_str,
+        ))))
+    }
+
+    fn seen(dir: &str) -> Result<PendingChangeResult> {
+        Ok(PendingChangeResult::SeenDirectory(path(dir), None))
+    }
+
+    #[test]
+    fn test_group_by_directory() {
+        let changes = vec![
+            changed("a/b/3"),
+            seen("a/b"),
+            changed("a/1"),
+            Ok(PendingChangeResult::Ignored(path("a/ignored"))),
+            changed("a/2"),
+            seen("a"),
+            seen("c"),
+            // Found after the walk.
+            changed("y"),
+            changed("a/4"),
+            changed("x"),
+        ];
+        let grouped: Vec<String> = GroupByDirectory::new(changes.into_iter())
+            .map(|batch| {
+                let (dir, changes) = batch.unwrap();
+                let paths: Vec<&str> = changes.iter().map(|c| c.get_path().as_str()).collect();
+                format!("{}: {}", dir, paths.join(" "))
+            })
+            .collect();
+        assert_eq!(grouped, ["a/b: a/b/3", "a: a/1 a/2", ": y x", "a: a/4"]);
+    }
 }
@@ -0,0 +1,184 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Status state that survives interrupted commands.
+//!
+//! The treestate is only written when a command finishes. If status is
+//! interrupted, the next status has to query from the old watchman clock, or
+//! walk the whole working copy again. [`FsMonitorState`] keeps the watchman
+//! clock in an indexedlog under `fsmonitor.state-v2` in the dot directory,
+//! which is written as soon as watchman answered.
+//!
+//! Each entry is:
+//!
+//! ```plain,ignore
+//! KEY + NUL + VALUE
+//! ```
+//!
+//! The newest entry of a key wins. The only key is `clock`: the watchman
+//! clock, the treestate clock it was queried from, then the NUL-separated
+//! paths that needed a check at that clock.
+//!
+//! The persisted clock is only valid on top of the treestate clock it was
+//! queried from. If the treestate clock changed since, for example because it
+//! was cleared to force a full crawl, the persisted clock is ignored.
+
+use std::path::Path;
+
+use anyhow::Result;
+use indexedlog::log::IndexOutput;
+use indexedlog::rotate::OpenOptions;
+use indexedlog::rotate::RotateLog;
+use types::RepoPath;
+use types::RepoPathBuf;
+
+/// Name of the indexedlog directory, relative to the dot directory.
+pub const FSMONITOR_STATE_DIR: &str = "fsmonitor.state-v2";
+
+const CLOCK_KEY: &[u8] = b"clock";
+const SEPARATOR: u8 = 0;
+
+/// Persisted status state. See the module documentation.
+pub struct FsMonitorState {
+    log: RotateLog,
+}
+
+impl FsMonitorState {
+    /// Open the state in `dot_dir`. Create it on demand.
+    pub fn open(dot_dir: &Path) -> Result<Self> {
+        let log = OpenOptions::new()
+            .create(true)
+            .max_bytes_per_log(4 << 20)
+            .max_log_count(3)
+            .index("key", index_key)
+            .open(dot_dir.join(FSMONITOR_STATE_DIR))?;
+        Ok(Self { log })
+    }
+
+    /// The last persisted watchman clock, and the paths that needed a check
+    /// at that clock. `None` if it was not queried from the treestate clock
+    /// `base`.
+    pub fn clock(&self, base: &str) -> Result<Option<(String, Vec<RepoPathBuf>)>> {
+        let value = match self.get(CLOCK_KEY)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let mut parts = value.split(|&b| b == SEPARATOR);
+        let clock = parts.next().unwrap_or_default();
+        if parts.next() != Some(base.as_bytes()) {
+            return Ok(None);
+        }
+        let clock = String::from_utf8(clock.to_vec())?;
+        let needs_check = parts
+            .map(|path| RepoPathBuf::from_utf8(path.to_vec()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some((clock, needs_check)))
+    }
+
+    /// Record the watchman clock queried from the treestate clock `base`, and
+    /// the paths that need a check at it.
+    pub fn set_clock<'a>(
+        &mut self,
+        clock: &str,
+        base: &str,
+        needs_check: impl IntoIterator<Item = &'a RepoPath>,
+    ) -> Result<()> {
+        let mut value = clock.as_bytes().to_vec();
+        value.push(SEPARATOR);
+        value.extend_from_slice(base.as_bytes());
+        for path in needs_check {
+            value.push(SEPARATOR);
+            value.extend_from_slice(path.as_byte_slice());
+        }
+        self.set(CLOCK_KEY, &value)
+    }
+
+    /// Write pending changes to disk.
+    pub fn flush(&mut self) -> Result<()> {
+        self.log.sync()?;
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        // Entries are visited from the newest.
+        match self.log.lookup(0, key.to_vec())?.next() {
+            Some(entry) => Ok(Some(entry?[key.len() + 1..].to_vec())),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        let mut entry = Vec::with_capacity(key.len() + 1 + value.len());
+        entry.extend_from_slice(key);
+        entry.push(SEPARATOR);
+        entry.extend_from_slice(value);
+        self.log.append(entry)?;
+        Ok(())
+    }
+}
+
+fn index_key(data: &[u8]) -> Vec<IndexOutput> {
+    match data.iter().position(|&b| b == SEPARATOR) {
+        Some(len) => vec![IndexOutput::Reference(0..len as u64)],
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> RepoPathBuf {
+        RepoPathBuf::from_string(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_clock() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = FsMonitorState::open(dir.path()).unwrap();
+        assert!(state.clock("c:0").unwrap().is_none());
+
+        state.set_clock("c:1", "c:0", Vec::new()).unwrap();
+        let (a, b) = (path("a"), path("dir/b"));
+        state
+            .set_clock("c:2", "c:0", vec![a.as_repo_path(), b.as_repo_path()])
+            .unwrap();
+        assert_eq!(
+            state.clock("c:0").unwrap(),
+            Some(("c:2".to_string(), vec![a.clone(), b.clone()]))
+        );
+
+        // Nothing is persisted without flush.
+        let reopened = FsMonitorState::open(dir.path()).unwrap();
+        assert!(reopened.clock("c:0").unwrap().is_none());
+
+        state.flush().unwrap();
+        let reopened = FsMonitorState::open(dir.path()).unwrap();
+        assert_eq!(
+            reopened.clock("c:0").unwrap(),
+            Some(("c:2".to_string(), vec![a, b]))
+        );
+    }
+
+    #[test]
+    fn test_clock_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = FsMonitorState::open(dir.path()).unwrap();
+        let a = path("a");
+        state
+            .set_clock("c:2", "c:1", vec![a.as_repo_path()])
+            .unwrap();
+        assert_eq!(
+            state.clock("c:1").unwrap(),
+            Some(("c:2".to_string(), vec![a]))
+        );
+
+        // The treestate clock changed, or was cleared, since.
+        assert!(state.clock("c:3").unwrap().is_none());
+        assert!(state.clock("").unwrap().is_none());
+    }
+}
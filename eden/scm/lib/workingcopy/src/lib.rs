@@ -5,9 +5,12 @@
  * GNU General Public License version 2.
  */
 
+mod cleancheck;
+pub mod comparison;
 pub mod edenfs;
 mod filechangedetector;
 pub mod filesystem;
+pub mod fsmonitorstate;
 pub mod physicalfs;
 pub mod sparse;
 pub mod status;
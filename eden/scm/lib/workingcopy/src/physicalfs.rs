@@ -12,56 +12,80 @@ use std::rc::Rc;
 use std::sync::Arc;
 
 use anyhow::Result;
+use error_code::ErrorCode;
+use error_code::WithErrorCode;
 use manifest_tree::TreeManifest;
 use parking_lot::RwLock;
+use pathmatcher::DifferenceMatcher;
+use pathmatcher::GitignoreMatcher;
 use pathmatcher::Matcher;
-use storemodel::ReadFileContents;
 use treestate::filestate::StateFlags;
 use treestate::tree::VisitorResult;
 use treestate::treestate::TreeState;
 use types::RepoPathBuf;
 use vfs::VFS;
 
+use crate::comparison::ArcContentComparison;
 use crate::filechangedetector::FileChangeDetector;
 use crate::filechangedetector::FileChangeDetectorTrait;
 use crate::filechangedetector::FileChangeResult;
 use crate::filechangedetector::HgModifiedTime;
 use crate::filechangedetector::ResolvedFileChangeResult;
+use crate::filesystem::ChangeType;
+use crate::filesystem::GroupByDirectory;
 use crate::filesystem::PendingChangeResult;
 use crate::filesystem::PendingChanges as PendingChangesTrait;
 use crate::walker::WalkEntry;
+use crate::walker::WalkProgress;
 use crate::walker::Walker;
 
-type ArcReadFileContents = Arc<dyn ReadFileContents<Error = anyhow::Error> + Send + Sync>;
 pub struct PhysicalFileSystem {
     // TODO: Make this an Arc<Mutex<VFS>> so we can persist the vfs pathauditor cache
     vfs: VFS,
     manifest: Arc<RwLock<TreeManifest>>,
-    store: ArcReadFileContents,
+    comparison: ArcContentComparison,
     treestate: Rc<RefCell<TreeState>>,
     include_directories: bool,
+    directory_fingerprints: bool,
+    ignore_matcher: Arc<GitignoreMatcher>,
+    include_ignored: bool,
     last_write: HgModifiedTime,
+    mtime_race_window: u64,
     num_threads: u8,
+    opaque_nested_repos: bool,
 }
 
 impl PhysicalFileSystem {
     pub fn new(
         root: PathBuf,
         manifest: Arc<RwLock<TreeManifest>>,
-        store: ArcReadFileContents,
+        comparison: ArcContentComparison,
         treestate: Rc<RefCell<TreeState>>,
         include_directories: bool,
+        // Whether directories are reported with their fingerprints.
+        directory_fingerprints: bool,
+        ignore_matcher: Arc<GitignoreMatcher>,
+        include_ignored: bool,
         last_write: HgModifiedTime,
+        mtime_race_window: u64,
         num_threads: u8,
+        // Whether nested `.git` repositories are skipped and nested
+        // repositories are reported.
+        opaque_nested_repos: bool,
     ) -> Result<Self> {
         Ok(PhysicalFileSystem {
             vfs: VFS::new(root)?,
             manifest,
-            store,
+            comparison,
             treestate,
             include_directories,
+            directory_fingerprints,
+            ignore_matcher,
+            include_ignored,
             last_write,
+            mtime_race_window,
             num_threads,
+            opaque_nested_repos,
         })
     }
 }
@@ -70,32 +94,82 @@ impl PendingChangesTrait for PhysicalFileSystem {
     fn pending_changes(
         &self,
         matcher: Arc<dyn Matcher + Send + Sync + 'static>,
+        progress: Option<WalkProgress>,
     ) -> Result<Box<dyn Iterator<Item = Result<PendingChangeResult>>>> {
-        let walker = Walker::new(
+        let pending_changes =
+            self.new_pending_changes(matcher, progress, self.include_directories)?;
+        Ok(Box::new(pending_changes))
+    }
+
+    fn pending_changes_grouped(
+        &self,
+        matcher: Arc<dyn Matcher + Send + Sync + 'static>,
+        progress: Option<WalkProgress>,
+    ) -> Result<Box<dyn Iterator<Item = Result<(RepoPathBuf, Vec<ChangeType>)>>>> {
+        // Directories mark the end of their files in the walk.
+        let pending_changes = self.new_pending_changes(matcher, progress, true)?;
+        Ok(Box::new(GroupByDirectory::new(pending_changes)))
+    }
+}
+
+impl PhysicalFileSystem {
+    fn new_pending_changes(
+        &self,
+        matcher: Arc<dyn Matcher + Send + Sync + 'static>,
+        progress: Option<WalkProgress>,
+        include_directories: bool,
+    ) -> Result<PendingChanges<Arc<dyn Matcher + Send + Sync + 'static>>> {
+        let walk_matcher: Arc<dyn Matcher + Send + Sync + 'static> = if self.include_ignored {
+            matcher.clone()
+        } else {
+            // Do not descend into ignored directories. Ignored files that are
+            // tracked are still checked when iterating the treestate.
+            Arc::new(DifferenceMatcher::new(
+                matcher.clone(),
+                self.ignore_matcher.clone(),
+            ))
+        };
+        let mut walker = Walker::new(
             self.vfs.root().to_path_buf(),
-            matcher.clone(),
-            false,
+            walk_matcher,
+            include_directories,
             self.num_threads,
-        )?;
+        )
+        .with_error_code(ErrorCode::WalkFailed)?;
+        if self.directory_fingerprints {
+            walker = walker.with_directory_fingerprints();
+        }
+        if self.opaque_nested_repos {
+            walker = walker.with_opaque_nested_repos();
+        }
+        if let Some(progress) = progress {
+            walker = walker.with_progress(progress);
+        }
         let file_change_detector = FileChangeDetector::new(
             self.treestate.clone(),
             self.vfs.clone(),
             self.last_write.clone(),
+            self.mtime_race_window,
             self.manifest.clone(),
-            self.store.clone(),
+            self.comparison.clone(),
         );
         let pending_changes = PendingChanges {
             walker,
             matcher,
             treestate: self.treestate.clone(),
             stage: PendingChangesStage::Walk,
-            include_directories: self.include_directories,
+            include_directories,
+            ignore_matcher: if self.include_ignored {
+                Some(self.ignore_matcher.clone())
+            } else {
+                None
+            },
             seen: HashSet::new(),
             tree_iter: None,
             lookup_iter: None,
             file_change_detector,
         };
-        Ok(Box::new(pending_changes))
+        Ok(pending_changes)
     }
 }
 
@@ -105,6 +179,8 @@ pub struct PendingChanges<M: Matcher + Clone + Send + Sync + 'static> {
     treestate: Rc<RefCell<TreeState>>,
     stage: PendingChangesStage,
     include_directories: bool,
+    /// Set if ignored files are reported.
+    ignore_matcher: Option<Arc<GitignoreMatcher>>,
     seen: HashSet<RepoPathBuf>,
     tree_iter: Option<Box<dyn Iterator<Item = Result<PendingChangeResult>> + Send>>,
     lookup_iter: Option<Box<dyn Iterator<Item = Result<PendingChangeResult>> + Send>>,
@@ -137,6 +213,11 @@ impl<M: Matcher + Clone + Send + Sync + 'static> PendingChanges<M> {
                 Some(Ok(WalkEntry::File(file, metadata))) => {
                     let file = normalize(file);
                     self.seen.insert(file.to_owned());
+                    match self.is_ignored(&file) {
+                        Ok(true) => return Some(Ok(PendingChangeResult::Ignored(file))),
+                        Ok(false) => {}
+                        Err(e) => return Some(Err(e)),
+                    }
                     let changed = match self
                         .file_change_detector
                         .has_changed_with_fresh_metadata(&file, metadata)
@@ -149,14 +230,17 @@ impl<M: Matcher + Clone + Send + Sync + 'static> PendingChanges<M> {
                         return Some(Ok(PendingChangeResult::File(change_type)));
                     }
                 }
-                Some(Ok(WalkEntry::Directory(dir))) => {
+                Some(Ok(WalkEntry::Directory(dir, fingerprint))) => {
                     if self.include_directories {
                         let dir = normalize(dir);
-                        return Some(Ok(PendingChangeResult::SeenDirectory(dir)));
+                        return Some(Ok(PendingChangeResult::SeenDirectory(dir, fingerprint)));
                     }
                 }
+                Some(Ok(WalkEntry::NestedRepo(dir))) => {
+                    return Some(Ok(PendingChangeResult::NestedRepo(normalize(dir))));
+                }
                 Some(Err(e)) => {
-                    return Some(Err(e));
+                    return Some(Err(e).with_error_code(ErrorCode::WalkFailed));
                 }
                 None => {
                     return None;
@@ -165,6 +249,24 @@ impl<M: Matcher + Clone + Send + Sync + 'static> PendingChanges<M> {
         }
     }
 
+    /// Whether `path` is an untracked file that matches the ignore rules.
+    /// Always false if ignored files are not reported.
+    fn is_ignored(&self, path: &RepoPathBuf) -> Result<bool> {
+        let ignore_matcher = match &self.ignore_matcher {
+            Some(ignore_matcher) => ignore_matcher,
+            None => return Ok(false),
+        };
+        if !ignore_matcher.matches_file(path)? {
+            return Ok(false);
+        }
+        let mask = StateFlags::EXIST_P1 | StateFlags::EXIST_P2 | StateFlags::EXIST_NEXT;
+        let tracked = match self.treestate.borrow_mut().get(path)? {
+            Some(state) => state.state.intersects(mask),
+            None => false,
+        };
+        Ok(!tracked)
+    }
+
     fn next_tree(&mut self) -> Option<Result<PendingChangeResult>> {
         if self.tree_iter.is_none() {
             self.tree_iter = Some(Box::new(self.get_tree_entries().into_iter()));
@@ -175,7 +277,9 @@ impl<M: Matcher + Clone + Send + Sync + 'static> PendingChanges<M> {
 
     fn get_tree_entries(&mut self) -> Vec<Result<PendingChangeResult>> {
         let mut results = vec![];
-        let tracked = self.get_tracked_from_p1();
+        let tracked = self
+            .get_tracked_from_p1()
+            .with_error_code(ErrorCode::TreeStateFailed);
         if let Err(e) = tracked {
             results.push(Err(e));
             return results;
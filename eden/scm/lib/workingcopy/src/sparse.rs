@@ -13,6 +13,8 @@ use anyhow::Error;
 use async_runtime::try_block_unless_interrupted;
 use configmodel::Config;
 use configmodel::ConfigExt;
+use error_code::ErrorCode;
+use error_code::WithErrorCode;
 use manifest::FileMetadata;
 use manifest::FsNodeMetadata;
 use manifest::Manifest;
@@ -73,7 +75,8 @@ pub fn sparse_matcher(
                 Ok(Some(bytes))
             }
             Some(Err(err)) => Err(err),
-            None => Err(anyhow!("no contents for {}", repo_path)),
+            None => Err(anyhow!("no contents for {}", repo_path))
+                .with_error_code(ErrorCode::SparseProfileFailed),
         }
     }))?;
 
@@ -20,53 +20,70 @@ use pathmatcher::ExactMatcher;
 use pathmatcher::Matcher;
 use status::Status;
 use status::StatusBuilder;
-use storemodel::ReadFileContents;
 use treestate::filestate::StateFlags;
 use treestate::treestate::TreeState;
 use types::RepoPathBuf;
 
+use crate::comparison::ArcContentComparison;
 use crate::filechangedetector::HgModifiedTime;
-use crate::filesystem::ChangeType;
 use crate::filesystem::FileSystemType;
+use crate::filesystem::PendingChangeResult;
+use crate::walker::WalkProgress;
 use crate::workingcopy::WorkingCopy;
 
-type ArcReadFileContents = Arc<dyn ReadFileContents<Error = anyhow::Error> + Send + Sync>;
-
 pub fn status(
     root: PathBuf,
+    dot_dir: PathBuf,
     file_system_type: FileSystemType,
     manifest: Arc<RwLock<TreeManifest>>,
-    store: ArcReadFileContents,
+    comparison: ArcContentComparison,
     treestate: TreeState,
     last_write: HgModifiedTime,
     matcher: Arc<dyn Matcher + Send + Sync + 'static>,
     _list_unknown: bool,
+    mtime_race_window: u64,
+    num_threads: u8,
+    ignore_files: Vec<PathBuf>,
+    list_ignored: bool,
+    opaque_nested_repos: bool,
+    edenfs_prefetch_unloaded: bool,
+    progress: Option<WalkProgress>,
 ) -> (TreeState, Result<Status>) {
     let manifest = manifest.read().clone();
     let result = WorkingCopy::new(
         root,
+        dot_dir,
         file_system_type,
         treestate,
         manifest,
-        store,
+        comparison,
         last_write,
+        mtime_race_window,
+        num_threads,
+        ignore_files,
+        list_ignored,
+        opaque_nested_repos,
+        edenfs_prefetch_unloaded,
     );
     let working_copy = match result {
         Ok(wc) => wc,
         Err((treestate, e)) => return (treestate, Err(e)),
     };
 
-    let status = working_copy.status(matcher);
+    let status = working_copy.status(matcher, progress);
     let treestate = working_copy.destroy();
     (treestate, status)
 }
 
 /// Compute the status of the working copy relative to the current commit.
+///
+/// `pending_changes` are consumed as they are produced. Ignored files in it
+/// are reported as ignored.
 #[allow(unused_variables)]
 pub fn compute_status(
     manifest: &impl Manifest,
     treestate: Rc<RefCell<TreeState>>,
-    pending_changes: impl Iterator<Item = Result<ChangeType>>,
+    pending_changes: impl Iterator<Item = Result<PendingChangeResult>>,
     matcher: Arc<dyn Matcher + Send + Sync + 'static>,
 ) -> Result<Status> {
     let mut modified = vec![];
@@ -74,6 +91,7 @@ pub fn compute_status(
     let mut removed = vec![];
     let mut deleted = vec![];
     let mut unknown = vec![];
+    let mut ignored = vec![];
 
     // Step 1: get the tree state for each pending change in the working copy.
     // We may have a TreeState that only holds files that are being added/removed
@@ -83,11 +101,16 @@ pub fn compute_status(
     // Changed files that don't exist in the TreeState. Maps to (is_deleted, in_manifest).
     let mut manifest_files = HashMap::<RepoPathBuf, (bool, bool)>::new();
     for change in pending_changes {
-        let (path, is_deleted) = match change {
-            Ok(ChangeType::Changed(path)) => (path, false),
-            Ok(ChangeType::Deleted(path)) => (path, true),
-            Err(e) => return Err(e),
+        let change = match change? {
+            PendingChangeResult::File(change) => change,
+            PendingChangeResult::Ignored(path) => {
+                ignored.push(path);
+                continue;
+            }
+            _ => continue,
         };
+        let is_deleted = change.is_deleted();
+        let path = change.get_path().clone();
 
         match treestate.borrow_mut().get(&path)? {
             Some(state) => {
@@ -223,6 +246,7 @@ pub fn compute_status(
         .removed(removed)
         .deleted(deleted)
         .unknown(unknown)
+        .ignored(ignored)
         .build())
 }
 
@@ -264,6 +288,7 @@ mod tests {
     const COPIED: StateFlags = StateFlags::COPIED;
 
     use super::*;
+    use crate::filesystem::ChangeType;
 
     struct DummyManifest {
         files: Vec<RepoPathBuf>,
@@ -336,6 +361,24 @@ mod tests {
     /// * `treestate` is a list of (path, state flags).
     /// * `changes` is a list of (path, deleted).
     fn status_helper(treestate: &[(&str, StateFlags)], changes: &[(&str, bool)]) -> Result<Status> {
+        let changes = changes.iter().map(|&(path, is_deleted)| {
+            let path = RepoPathBuf::from_string(path.to_string()).expect("path");
+            if is_deleted {
+                ChangeType::Deleted(path)
+            } else {
+                ChangeType::Changed(path)
+            }
+        });
+        status_helper_with_change_types(treestate, changes.collect(), &[])
+    }
+
+    /// Similar to `status_helper`, but takes [`ChangeType`]s as changes, and
+    /// ignored files reported by the file system.
+    fn status_helper_with_change_types(
+        treestate: &[(&str, StateFlags)],
+        changes: Vec<ChangeType>,
+        ignored: &[&str],
+    ) -> Result<Status> {
         // Build the TreeState.
         let dir = TempDir::new("treestate").expect("tempdir");
         let mut state = TreeState::open(dir.path().join("1"), None).expect("open");
@@ -361,19 +404,17 @@ mod tests {
             files: manifest_files,
         };
 
-        // Build the pending changes.
-        let changes = changes.iter().map(|&(path, is_deleted)| {
-            let path = RepoPathBuf::from_string(path.to_string()).expect("path");
-            if is_deleted {
-                Ok(ChangeType::Deleted(path))
-            } else {
-                Ok(ChangeType::Changed(path))
-            }
-        });
-
         // Compute the status.
         let matcher = Arc::new(pathmatcher::AlwaysMatcher::new());
-        compute_status(&manifest, treestate, changes, matcher)
+        let ignored = ignored.iter().map(|p| {
+            let path = RepoPathBuf::from_string(p.to_string()).expect("path");
+            PendingChangeResult::Ignored(path)
+        });
+        let changes = changes
+            .into_iter()
+            .map(PendingChangeResult::File)
+            .chain(ignored);
+        compute_status(&manifest, treestate, changes.map(Ok), matcher)
     }
 
     /// Compare the [`Status`] with the expected status for each given file.
@@ -415,6 +456,69 @@ mod tests {
         );
     }
 
+    /// Test status for detailed change types.
+    #[test]
+    fn test_status_change_types() {
+        let path = |p: &str| RepoPathBuf::from_string(p.to_string()).expect("path");
+        let treestate = &[
+            ("content-changed", EXIST_P1 | EXIST_NEXT),
+            ("mode-changed", EXIST_P1 | EXIST_NEXT),
+            ("type-changed", EXIST_P1 | EXIST_NEXT),
+            ("replaced-by-dir", EXIST_P1 | EXIST_NEXT),
+            ("added-file", EXIST_NEXT),
+        ];
+        let changes = vec![
+            ChangeType::Modified {
+                path: path("content-changed"),
+                mode_changed: false,
+            },
+            ChangeType::Modified {
+                path: path("mode-changed"),
+                mode_changed: true,
+            },
+            ChangeType::TypeChanged {
+                path: path("type-changed"),
+                is_directory: false,
+            },
+            ChangeType::TypeChanged {
+                path: path("replaced-by-dir"),
+                is_directory: true,
+            },
+            ChangeType::Added(path("added-file")),
+        ];
+        let status = status_helper_with_change_types(treestate, changes, &[]).expect("status");
+        compare_status(
+            status,
+            &[
+                ("content-changed", Some(FileStatus::Modified)),
+                ("mode-changed", Some(FileStatus::Modified)),
+                ("type-changed", Some(FileStatus::Modified)),
+                ("replaced-by-dir", Some(FileStatus::Deleted)),
+                ("added-file", Some(FileStatus::Added)),
+            ],
+        );
+    }
+
+    /// Test status for ignored files reported by the file system.
+    #[test]
+    fn test_status_ignored() {
+        let treestate = &[("tracked", EXIST_P1 | EXIST_NEXT)];
+        let changes = vec![ChangeType::Changed(
+            RepoPathBuf::from_string("unknown".to_string()).expect("path"),
+        )];
+        let status = status_helper_with_change_types(treestate, changes, &["build/out", "a.o"])
+            .expect("status");
+        compare_status(
+            status,
+            &[
+                ("tracked", None),
+                ("unknown", Some(FileStatus::Unknown)),
+                ("build/out", Some(FileStatus::Ignored)),
+                ("a.o", Some(FileStatus::Ignored)),
+            ],
+        );
+    }
+
     /// Test status for files that aren't in pending changes.
     #[test]
     fn test_status_no_changes() {
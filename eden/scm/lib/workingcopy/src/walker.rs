@@ -12,22 +12,29 @@ use std::io;
 use std::num::NonZeroU8;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::Duration;
+use std::time::UNIX_EPOCH;
 
-use anyhow::Error;
 use anyhow::Result;
-use crossbeam::channel::unbounded;
+use crossbeam::channel::bounded;
 use crossbeam::channel::Receiver;
-use crossbeam::channel::RecvError;
-use crossbeam::channel::RecvTimeoutError;
 use crossbeam::channel::Sender;
+use crossbeam::deque::Injector;
+use crossbeam::deque::Stealer;
+use crossbeam::deque::Worker;
+use crossbeam::utils::Backoff;
 use pathmatcher::DirectoryMatch;
 use pathmatcher::Matcher;
+use progress_model::ProgressBar;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
 use thiserror::Error;
 use types::path::ParseError;
 use types::RepoPath;
@@ -45,10 +52,6 @@ pub enum WalkError {
     InvalidFileType(RepoPathBuf),
     #[error("invalid mtime for '{0}': {1}")]
     InvalidMTime(RepoPathBuf, #[source] anyhow::Error),
-    #[error("channel disconnected")]
-    ChannelDisconnected(#[source] RecvTimeoutError),
-    #[error("channel recv error")]
-    ChannelRecvError(#[source] RecvError),
 }
 
 impl WalkError {
@@ -59,8 +62,6 @@ impl WalkError {
             WalkError::RepoPathError(path, _) => path.to_string(),
             WalkError::InvalidFileType(path) => path.to_string(),
             WalkError::InvalidMTime(path, _) => path.to_string(),
-            WalkError::ChannelDisconnected(_) => "".to_string(),
-            WalkError::ChannelRecvError(_) => "".to_string(),
         }
     }
 
@@ -71,22 +72,154 @@ impl WalkError {
             WalkError::RepoPathError(_, error) => error.to_string(),
             WalkError::InvalidFileType(_) => "invalid file type".to_string(),
             WalkError::InvalidMTime(_, error) => format!("invalid mtime - {}", error.to_string()),
-            WalkError::ChannelDisconnected(error) => error.to_string(),
-            WalkError::ChannelRecvError(error) => error.to_string(),
         }
     }
 }
 
 pub enum WalkEntry {
     File(RepoPathBuf, Metadata),
-    Directory(RepoPathBuf),
+    /// A directory, with its fingerprint if requested and the directory
+    /// was read successfully. The files of a directory are reported together,
+    /// right before the directory.
+    Directory(RepoPathBuf, Option<DirectoryFingerprint>),
+    /// The root of a nested repository. Only reported if nested repositories
+    /// are opaque. Its content is not walked.
+    NestedRepo(RepoPathBuf),
 }
 
 impl AsRef<RepoPath> for WalkEntry {
     fn as_ref(&self) -> &RepoPath {
         match self {
             WalkEntry::File(f, _) => f,
-            WalkEntry::Directory(d) => d,
+            WalkEntry::Directory(d, _) => d,
+            WalkEntry::NestedRepo(d) => d,
+        }
+    }
+}
+
+/// Fingerprint of the direct children of a directory: their names and types,
+/// and the size, mtime and mode of non-directories. Subdirectories have their
+/// own fingerprints. Fingerprints are stable across runs, so they can be
+/// stored to skip unchanged directories later.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct DirectoryFingerprint(pub [u8; 32]);
+
+/// Collects the children of a directory to compute its fingerprint.
+struct FingerprintBuilder {
+    /// One record per child. `None` if metadata of a child cannot be read.
+    records: Option<Vec<Vec<u8>>>,
+}
+
+impl FingerprintBuilder {
+    fn new() -> Self {
+        Self {
+            records: Some(Vec::new()),
+        }
+    }
+
+    fn add(&mut self, entry: &DirEntry) {
+        if let Some(records) = &mut self.records {
+            match Self::record(entry) {
+                Ok(record) => records.push(record),
+                Err(_) => self.records = None,
+            }
+        }
+    }
+
+    /// Encode a child. The record starts with the name followed by a NUL so
+    /// sorting records sorts children by name.
+    fn record(entry: &DirEntry) -> io::Result<Vec<u8>> {
+        let mut record = entry
+            .file_name()
+            .to_string_lossy()
+            .into_owned()
+            .into_bytes();
+        record.push(0);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            record.push(b'd');
+            return Ok(record);
+        }
+        let metadata = entry.metadata()?;
+        record.push(if file_type.is_symlink() {
+            b'l'
+        } else if file_type.is_file() {
+            b'f'
+        } else {
+            b'o'
+        });
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        record.extend_from_slice(&metadata.len().to_le_bytes());
+        record.extend_from_slice(&mtime.as_secs().to_le_bytes());
+        record.extend_from_slice(&mtime.subsec_nanos().to_le_bytes());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            record.extend_from_slice(&metadata.permissions().mode().to_le_bytes());
+        }
+        #[cfg(not(unix))]
+        record.push(metadata.permissions().readonly() as u8);
+        Ok(record)
+    }
+
+    fn finish(self) -> Option<DirectoryFingerprint> {
+        let mut records = self.records?;
+        records.sort_unstable();
+        let mut hasher = Sha256::new();
+        for record in records {
+            hasher.update((record.len() as u64).to_le_bytes());
+            hasher.update(&record);
+        }
+        Some(DirectoryFingerprint(hasher.finalize().into()))
+    }
+}
+
+/// Progress of a [`Walker`]: directories read and files seen, matched or not.
+/// Cheap to clone. Updated by walker threads.
+#[derive(Clone)]
+pub struct WalkProgress {
+    dirs: Arc<ProgressBar>,
+    files: Arc<ProgressBar>,
+}
+
+impl WalkProgress {
+    /// Create progress bars registered with the main registry, so they are
+    /// rendered by the CLI.
+    pub fn register_new() -> Self {
+        Self {
+            dirs: ProgressBar::register_new("walking", 0, "dirs"),
+            files: ProgressBar::register_new("walking", 0, "files"),
+        }
+    }
+
+    /// Number of directories read so far.
+    pub fn dirs_visited(&self) -> u64 {
+        self.dirs.position_total().0
+    }
+
+    /// Number of files seen so far.
+    pub fn files_examined(&self) -> u64 {
+        self.files.position_total().0
+    }
+
+    fn visit_dir(&self) {
+        self.dirs.increase_position(1);
+    }
+
+    fn examine_file(&self) {
+        self.files.increase_position(1);
+    }
+}
+
+impl Default for WalkProgress {
+    /// Progress bars that are not rendered.
+    fn default() -> Self {
+        Self {
+            dirs: ProgressBar::new("walking", 0, "dirs"),
+            files: ProgressBar::new("walking", 0, "files"),
         }
     }
 }
@@ -120,6 +253,38 @@ where
         };
         Ok(Walker(inner))
     }
+
+    /// Compute a [`DirectoryFingerprint`] for each directory. Only has an
+    /// effect if directories are included. Must be called before iterating.
+    pub fn with_directory_fingerprints(mut self) -> Self {
+        match &mut self.0 {
+            WalkerType::Single(w) => w.directory_fingerprints = true,
+            WalkerType::Multi(w) => w.directory_fingerprints = true,
+        }
+        self
+    }
+
+    /// Stop at nested `.git` repositories too, and report nested repositories
+    /// as [`WalkEntry::NestedRepo`]. Otherwise, only nested `.hg`
+    /// repositories are skipped, as directories. Must be called before
+    /// iterating.
+    pub fn with_opaque_nested_repos(mut self) -> Self {
+        match &mut self.0 {
+            WalkerType::Single(w) => w.opaque_nested_repos = true,
+            WalkerType::Multi(w) => w.opaque_nested_repos = true,
+        }
+        self
+    }
+
+    /// Report progress to `progress` instead of progress bars that are not
+    /// rendered. Must be called before iterating.
+    pub fn with_progress(mut self, progress: WalkProgress) -> Self {
+        match &mut self.0 {
+            WalkerType::Single(w) => w.progress = progress,
+            WalkerType::Multi(w) => w.progress = progress,
+        }
+        self
+    }
 }
 
 impl<M> Iterator for Walker<M>
@@ -135,6 +300,26 @@ where
     }
 }
 
+/// Whether `abs_dir` is the root of a nested repository that is not walked.
+fn is_nested_repo(abs_dir: &Path, opaque_nested_repos: bool) -> bool {
+    abs_dir.join(".hg").exists() || (opaque_nested_repos && abs_dir.join(".git").exists())
+}
+
+/// The entry reported for a nested repository, if any.
+fn nested_repo_entry(
+    dir: RepoPathBuf,
+    opaque_nested_repos: bool,
+    include_directories: bool,
+) -> Option<WalkEntry> {
+    if opaque_nested_repos {
+        Some(WalkEntry::NestedRepo(dir))
+    } else if include_directories {
+        Some(WalkEntry::Directory(dir, None))
+    } else {
+        None
+    }
+}
+
 enum WalkerType<M> {
     Single(SingleWalker<M>),
     Multi(MultiWalker<M>),
@@ -148,6 +333,9 @@ struct SingleWalker<M> {
     results: Vec<Result<WalkEntry>>,
     matcher: M,
     include_directories: bool,
+    directory_fingerprints: bool,
+    opaque_nested_repos: bool,
+    progress: WalkProgress,
 }
 
 impl<M> SingleWalker<M>
@@ -165,6 +353,9 @@ where
             results: Vec::new(),
             matcher,
             include_directories,
+            directory_fingerprints: false,
+            opaque_nested_repos: false,
+            progress: WalkProgress::default(),
         };
         Ok(walker)
     }
@@ -185,6 +376,7 @@ where
         let mut candidate_path = next_dir.clone();
         candidate_path.push(filename);
         if filetype.is_file() || filetype.is_symlink() {
+            self.progress.examine_file();
             if self.matcher.matches_file(candidate_path.as_repo_path())? {
                 self.results
                     .push(Ok(WalkEntry::File(candidate_path, entry.metadata()?)));
@@ -208,21 +400,34 @@ where
     fn walk(&mut self) -> Result<()> {
         while self.results.is_empty() && !self.dir_matches.is_empty() {
             let next_dir = self.dir_matches.pop().unwrap();
-            if self.include_directories {
-                self.results
-                    .push(Ok(WalkEntry::Directory(next_dir.clone())));
-            }
             let abs_next_dir = self.root.join(next_dir.as_str());
-            // Don't process the directory if it contains a .hg directory, unless it's the root.
-            if next_dir.is_empty() || !Path::exists(&abs_next_dir.join(".hg")) {
-                for entry in fs::read_dir(abs_next_dir)
-                    .map_err(|e| WalkError::IOError(next_dir.clone(), e))?
-                {
-                    let entry = entry.map_err(|e| WalkError::IOError(next_dir.clone(), e))?;
-                    if let Err(e) = self.match_entry(&next_dir, entry) {
-                        self.results.push(Err(e));
-                    }
+            // Don't process the directory if it is a nested repo, unless it's the root.
+            if !next_dir.is_empty() && is_nested_repo(&abs_next_dir, self.opaque_nested_repos) {
+                let entry =
+                    nested_repo_entry(next_dir, self.opaque_nested_repos, self.include_directories);
+                self.results.extend(entry.map(Ok));
+                continue;
+            }
+            self.progress.visit_dir();
+            let mut builder = (self.include_directories && self.directory_fingerprints)
+                .then(FingerprintBuilder::new);
+            for entry in
+                fs::read_dir(abs_next_dir).map_err(|e| WalkError::IOError(next_dir.clone(), e))?
+            {
+                let entry = entry.map_err(|e| WalkError::IOError(next_dir.clone(), e))?;
+                if let Some(builder) = &mut builder {
+                    builder.add(&entry);
                 }
+                if let Err(e) = self.match_entry(&next_dir, entry) {
+                    self.results.push(Err(e));
+                }
+            }
+            let fingerprint = builder.and_then(FingerprintBuilder::finish);
+            if self.include_directories {
+                // Results are popped from the end. Report the directory after
+                // its files.
+                self.results
+                    .insert(0, Ok(WalkEntry::Directory(next_dir, fingerprint)));
             }
         }
         Ok(())
@@ -242,35 +447,42 @@ where
     }
 }
 
+/// Data shared by the threads of a multi-threaded walker.
 pub struct WalkerData<M> {
-    result_sender: Sender<Result<WalkEntry>>,
-    queue_sender: Sender<RepoPathBuf>,
-    queue_receiver: Receiver<RepoPathBuf>,
+    /// Directories not yet picked up by any thread.
+    injector: Injector<RepoPathBuf>,
+    /// Steal directories from other threads' local queues.
+    stealers: Vec<Stealer<RepoPathBuf>>,
     matcher: M,
+    /// Number of directories queued or being read.
     busy_nodes: AtomicU64,
-    result_cnt: AtomicU64,
+    /// Set when the consumer is gone. Threads should stop.
+    cancelled: AtomicBool,
     root: PathBuf,
     include_directories: bool,
 }
 
-impl<M> WalkerData<M> {
-    fn enqueue_result(&self, msg: Result<WalkEntry, Error>) -> Result<()> {
-        self.result_cnt.fetch_add(1, Ordering::AcqRel);
-        Ok(self.result_sender.send(msg)?)
-    }
-
-    fn enqueue_work(&self, msg: RepoPathBuf) -> Result<()> {
-        self.busy_nodes.fetch_add(1, Ordering::AcqRel);
-        Ok(self.queue_sender.send(msg)?)
-    }
-}
-
+/// [`MultiWalker`] traverses the working copy using a pool of threads.
+///
+/// Each thread has a local queue of directories to read. Idle threads steal
+/// directories from other threads. The results of each directory are sent
+/// together through a bounded channel, so they are not interleaved with the
+/// results of other directories, and memory usage does not grow with the size
+/// of the working copy if the consumer is slower than the threads.
 struct MultiWalker<M> {
-    threads: Vec<JoinHandle<Result<()>>>,
-    results: Vec<Result<WalkEntry>>,
-    result_receiver: Receiver<Result<WalkEntry>>,
-    has_walked: bool,
+    /// Local queues to move into threads. Empty after threads are spawned.
+    workers: Vec<Worker<RepoPathBuf>>,
+    threads: Vec<JoinHandle<()>>,
+    /// Cloned into threads. `None` after threads are spawned so the channel
+    /// gets disconnected after all threads exit.
+    result_sender: Option<Sender<Vec<Result<WalkEntry>>>>,
+    result_receiver: Receiver<Vec<Result<WalkEntry>>>,
+    /// Results of the directory being consumed.
+    pending_results: std::vec::IntoIter<Result<WalkEntry>>,
     payload: Arc<WalkerData<M>>,
+    directory_fingerprints: bool,
+    opaque_nested_repos: bool,
+    progress: WalkProgress,
 }
 
 impl<M> MultiWalker<M>
@@ -281,7 +493,12 @@ where
     M: Sync,
     M: 'static,
 {
-    const RECV_TIMEOUT: Duration = Duration::from_millis(5);
+    /// Maximum number of directories whose results are buffered between
+    /// threads and the consumer.
+    const RESULT_CHANNEL_CAPACITY: usize = 4096;
+
+    /// How long an idle thread sleeps before looking for work again.
+    const IDLE_SLEEP: Duration = Duration::from_millis(1);
 
     pub fn new(
         root: PathBuf,
@@ -289,35 +506,228 @@ where
         include_directories: bool,
         num_threads: NonZeroU8,
     ) -> Result<Self> {
-        let (s_results, r_results) = unbounded();
-        let (s_queue, r_queue) = unbounded();
-        let num_threads = num_threads.get();
+        let (s_results, r_results) = bounded(Self::RESULT_CHANNEL_CAPACITY);
+        let workers: Vec<Worker<RepoPathBuf>> =
+            (0..num_threads.get()).map(|_| Worker::new_lifo()).collect();
+        let stealers = workers.iter().map(|w| w.stealer()).collect();
 
         Ok(MultiWalker {
-            threads: Vec::with_capacity(num_threads.into()),
-            results: Vec::new(),
+            workers,
+            threads: Vec::with_capacity(num_threads.get().into()),
+            result_sender: Some(s_results),
             result_receiver: r_results,
-            has_walked: false,
+            pending_results: Vec::new().into_iter(),
             payload: Arc::new(WalkerData {
+                injector: Injector::new(),
+                stealers,
+                matcher,
                 busy_nodes: AtomicU64::new(0),
-                result_cnt: AtomicU64::new(0),
-                result_sender: s_results,
-                queue_sender: s_queue,
-                queue_receiver: r_queue,
+                cancelled: AtomicBool::new(false),
                 root,
-                matcher,
                 include_directories,
             }),
+            directory_fingerprints: false,
+            opaque_nested_repos: false,
+            progress: WalkProgress::default(),
+        })
+    }
+
+    /// Queue the root directory and spawn threads.
+    fn start(&mut self) -> Result<()> {
+        let result_sender = match self.result_sender.take() {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+        if self
+            .payload
+            .matcher
+            .matches_directory(&RepoPathBuf::new())?
+            != DirectoryMatch::Nothing
+        {
+            self.payload.busy_nodes.fetch_add(1, Ordering::AcqRel);
+            self.payload.injector.push(RepoPathBuf::new());
+        }
+
+        for local in self.workers.drain(..) {
+            let thread = WalkerThread {
+                shared: self.payload.clone(),
+                local,
+                result_sender: result_sender.clone(),
+                directory_fingerprints: self.directory_fingerprints,
+                opaque_nested_repos: self.opaque_nested_repos,
+                progress: self.progress.clone(),
+            };
+            self.threads.push(thread::spawn(move || thread.run()));
+        }
+        Ok(())
+    }
+}
+
+impl<M> Iterator for MultiWalker<M>
+where
+    M: Matcher,
+    M: Clone,
+    M: Send,
+    M: Sync,
+    M: 'static,
+{
+    type Item = Result<WalkEntry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.start() {
+            return Some(Err(e));
+        }
+        loop {
+            if let Some(entry) = self.pending_results.next() {
+                return Some(entry);
+            }
+            match self.result_receiver.recv() {
+                Ok(results) => self.pending_results = results.into_iter(),
+                // All threads have exited.
+                Err(_) => {
+                    for handle in self.threads.drain(..) {
+                        handle.join().expect("Failed to join thread.");
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+impl<M> Drop for MultiWalker<M> {
+    fn drop(&mut self) {
+        // Threads blocked on sending results will notice the disconnected
+        // channel. Other threads will notice this flag.
+        self.payload.cancelled.store(true, Ordering::Release);
+    }
+}
+
+/// State owned by a single [`MultiWalker`] thread.
+struct WalkerThread<M> {
+    shared: Arc<WalkerData<M>>,
+    /// Directories to read. Other threads can steal from it.
+    local: Worker<RepoPathBuf>,
+    result_sender: Sender<Vec<Result<WalkEntry>>>,
+    directory_fingerprints: bool,
+    opaque_nested_repos: bool,
+    progress: WalkProgress,
+}
+
+impl<M> WalkerThread<M>
+where
+    M: Matcher,
+    M: Clone,
+    M: Send,
+    M: Sync,
+    M: 'static,
+{
+    fn run(self) {
+        let backoff = Backoff::new();
+        while !self.shared.cancelled.load(Ordering::Acquire) {
+            match self.find_work() {
+                Some(dir) => {
+                    backoff.reset();
+                    let result = self.walk_dir(&dir);
+                    // Decrement after children are queued so busy_nodes does
+                    // not drop to 0 while there is still work.
+                    self.shared.busy_nodes.fetch_sub(1, Ordering::AcqRel);
+                    if result.is_err() {
+                        // The consumer is gone.
+                        self.shared.cancelled.store(true, Ordering::Release);
+                    }
+                }
+                None => {
+                    if self.shared.busy_nodes.load(Ordering::Acquire) == 0 {
+                        break;
+                    }
+                    if backoff.is_completed() {
+                        thread::sleep(MultiWalker::<M>::IDLE_SLEEP);
+                    } else {
+                        backoff.snooze();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Pop a directory from the local queue, or steal one from the global
+    /// queue or other threads.
+    fn find_work(&self) -> Option<RepoPathBuf> {
+        self.local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.shared
+                    .injector
+                    .steal_batch_and_pop(&self.local)
+                    .or_else(|| self.shared.stealers.iter().map(|s| s.steal()).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
         })
     }
 
+    /// Read a directory. Errors are sent as results. Only fails if results
+    /// cannot be sent.
+    fn walk_dir(&self, dir: &RepoPathBuf) -> Result<()> {
+        let mut results = Vec::new();
+        self.read_dir(dir, &mut results);
+        if results.is_empty() {
+            return Ok(());
+        }
+        Ok(self.result_sender.send(results)?)
+    }
+
+    /// Collect the results of a directory into `results`.
+    fn read_dir(&self, dir: &RepoPathBuf, results: &mut Vec<Result<WalkEntry>>) {
+        let abs_dir_path = self.shared.root.join(dir.as_str());
+        // Don't process the directory if it is a nested repo, unless it's the root.
+        if !dir.is_empty() && is_nested_repo(&abs_dir_path, self.opaque_nested_repos) {
+            let entry = nested_repo_entry(
+                dir.clone(),
+                self.opaque_nested_repos,
+                self.shared.include_directories,
+            );
+            results.extend(entry.map(Ok));
+            return;
+        }
+        let entries = match fs::read_dir(abs_dir_path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                results.push(Err(WalkError::IOError(dir.clone(), e).into()));
+                return;
+            }
+        };
+        self.progress.visit_dir();
+        let mut builder = (self.shared.include_directories && self.directory_fingerprints)
+            .then(FingerprintBuilder::new);
+        for entry in entries {
+            let result = entry
+                .map_err(|e| WalkError::IOError(dir.clone(), e).into())
+                .and_then(|entry| {
+                    if let Some(builder) = &mut builder {
+                        builder.add(&entry);
+                    }
+                    self.match_entry_and_enqueue(dir, entry)
+                });
+            match result {
+                Ok(Some(entry)) => results.push(Ok(entry)),
+                Ok(None) => {}
+                Err(e) => results.push(Err(e)),
+            }
+        }
+        if self.shared.include_directories {
+            let fingerprint = builder.and_then(FingerprintBuilder::finish);
+            results.push(Ok(WalkEntry::Directory(dir.clone(), fingerprint)));
+        }
+    }
+
     // WARNING: SIDE EFFECTS - if entry matches and is child directory, will push
-    // child and increment busy_nodes atomic.
+    // child and increment busy_nodes atomic. Returns the entry to report for
+    // matching files.
     fn match_entry_and_enqueue(
+        &self,
         dir: &RepoPathBuf,
         entry: DirEntry,
-        shared_data: Arc<WalkerData<M>>,
-    ) -> Result<()> {
+    ) -> Result<Option<WalkEntry>> {
         let filename = entry.file_name();
         let filename = filename
             .to_str()
@@ -330,132 +740,28 @@ where
 
         let mut candidate_path = dir.clone();
         candidate_path.push(filename);
+        let matcher = &self.shared.matcher;
         if filetype.is_file() || filetype.is_symlink() {
-            if shared_data
-                .matcher
-                .matches_file(candidate_path.as_repo_path())?
-            {
-                shared_data
-                    .enqueue_result(Ok(WalkEntry::File(candidate_path, entry.metadata()?)))?;
+            self.progress.examine_file();
+            if matcher.matches_file(candidate_path.as_repo_path())? {
+                return Ok(Some(WalkEntry::File(candidate_path, entry.metadata()?)));
             }
         } else if filetype.is_dir() {
             if filename.as_str() != ".hg"
-                && shared_data
-                    .matcher
-                    .matches_directory(candidate_path.as_repo_path())?
+                && matcher.matches_directory(candidate_path.as_repo_path())?
                     != DirectoryMatch::Nothing
             {
-                shared_data.enqueue_work(candidate_path)?;
+                self.enqueue_work(candidate_path);
             }
-        } else if shared_data
-            .matcher
-            .matches_file(candidate_path.as_repo_path())?
-        {
+        } else if matcher.matches_file(candidate_path.as_repo_path())? {
             return Err(WalkError::InvalidFileType(filename.to_owned()).into());
         }
-        Ok(())
-    }
-
-    fn walk(&mut self) -> Result<()> {
-        if self
-            .payload
-            .matcher
-            .matches_directory(&RepoPathBuf::new())?
-            != DirectoryMatch::Nothing
-        {
-            self.payload.enqueue_work(RepoPathBuf::new())?;
-        }
-
-        for _t in 0..self.threads.capacity() {
-            let shared_data = self.payload.clone();
-
-            // TODO make sure that _t is different for each thread
-            self.threads.push(thread::spawn(move || {
-                loop {
-                    let result = shared_data
-                        .queue_receiver
-                        .recv_timeout(MultiWalker::<M>::RECV_TIMEOUT);
-                    match result {
-                        Ok(dir) => {
-                            // Anonymous function so we can capture all errors returned, and decrement
-                            // busy_nodes even in the event of an error.
-                            let result = (|| -> Result<()> {
-                                if shared_data.include_directories {
-                                    shared_data
-                                        .enqueue_result(Ok(WalkEntry::Directory(dir.clone())))?;
-                                }
-                                let abs_dir_path = shared_data.root.join(dir.as_str());
-                                for entry in fs::read_dir(abs_dir_path)
-                                    .map_err(|e| WalkError::IOError(dir.clone(), e))?
-                                {
-                                    let entry =
-                                        entry.map_err(|e| WalkError::IOError(dir.clone(), e))?;
-                                    if let Err(e) = MultiWalker::match_entry_and_enqueue(
-                                        &dir,
-                                        entry,
-                                        shared_data.clone(),
-                                    ) {
-                                        shared_data.enqueue_result(Err(e))?;
-                                    }
-                                }
-                                Ok(())
-                            })();
-                            shared_data.busy_nodes.fetch_sub(1, Ordering::AcqRel);
-                            result?;
-                        }
-                        Err(RecvTimeoutError::Timeout) => {
-                            if shared_data.busy_nodes.load(Ordering::Relaxed) == 0 {
-                                return Ok(());
-                            }
-                        }
-                        Err(e) => {
-                            return Err(WalkError::ChannelDisconnected(e).into());
-                        }
-                    };
-                }
-            }));
-        }
-
-        // TODO in future, let caller handle receiving on the channel.
-        for handle in self.threads.drain(0..) {
-            let thread_result = handle.join().expect("Failed to join thread.");
-            if let Err(e) = thread_result {
-                self.results.push(Err(e));
-            }
-        }
-        let cnt = self.payload.result_cnt.load(Ordering::Relaxed);
-        for _ in 0..cnt {
-            let result = self.result_receiver.recv();
-
-            match result {
-                Ok(val) => self.results.push(val),
-                // Should not get disconnected because Walker owns a Sender.
-                Err(e) => return Err(WalkError::ChannelRecvError(e).into()),
-            }
-        }
-        Ok(())
+        Ok(None)
     }
-}
 
-impl<M> Iterator for MultiWalker<M>
-where
-    M: Matcher,
-    M: Clone,
-    M: Send,
-    M: Sync,
-    M: 'static,
-{
-    type Item = Result<WalkEntry>;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.has_walked {
-            self.results.pop()
-        } else {
-            self.has_walked = true;
-            match self.walk() {
-                Err(e) => Some(Err(e)),
-                Ok(()) => self.results.pop(),
-            }
-        }
+    fn enqueue_work(&self, dir: RepoPathBuf) {
+        self.shared.busy_nodes.fetch_add(1, Ordering::AcqRel);
+        self.local.push(dir);
     }
 }
 
@@ -466,6 +772,8 @@ mod tests {
     use std::path::PathBuf;
 
     use pathmatcher::AlwaysMatcher;
+    use pathmatcher::DifferenceMatcher;
+    use pathmatcher::GitignoreMatcher;
     use pathmatcher::NeverMatcher;
     use pathmatcher::TreeMatcher;
     use tempfile::tempdir;
@@ -701,4 +1009,250 @@ mod tests {
         }
         Ok(())
     }
+
+    /// Create `dir_count` directories with `file_count` files each.
+    fn create_wide_directory(dir_count: usize, file_count: usize) -> Result<tempfile::TempDir> {
+        let directories: Vec<String> = (0..dir_count).map(|i| format!("dir{}", i)).collect();
+        let files: Vec<String> = directories
+            .iter()
+            .flat_map(|d| (0..file_count).map(move |i| format!("{}/f{}", d, i)))
+            .collect();
+        create_directory(
+            &directories.iter().map(|s| s.as_str()).collect(),
+            &files.iter().map(|s| s.as_str()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_multiwalker_wide() -> Result<()> {
+        // More results than the channel capacity.
+        let root_dir = create_wide_directory(50, 100)?;
+        let root_path = PathBuf::from(root_dir.path());
+        let walk = |walker: &mut dyn Iterator<Item = Result<WalkEntry>>| -> Result<Vec<String>> {
+            let mut paths = walker
+                .map(|e| e.map(|e| e.as_ref().to_string()))
+                .collect::<Result<Vec<_>>>()?;
+            paths.sort();
+            Ok(paths)
+        };
+        let expected = walk(&mut SingleWalker::new(
+            root_path.clone(),
+            AlwaysMatcher::new(),
+            true,
+        )?)?;
+        assert_eq!(expected.len(), 1 + 50 + 50 * 100);
+        for num_threads in [1, 3, 8] {
+            let mut walker = MultiWalker::new(
+                root_path.clone(),
+                AlwaysMatcher::new(),
+                true,
+                NonZeroU8::new(num_threads).unwrap(),
+            )?;
+            assert_eq!(walk(&mut walker)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiwalker_drop_early() -> Result<()> {
+        let root_dir = create_wide_directory(50, 100)?;
+        let root_path = PathBuf::from(root_dir.path());
+        let walker = MultiWalker::new(
+            root_path,
+            AlwaysMatcher::new(),
+            false,
+            NonZeroU8::new(4).unwrap(),
+        )?;
+        // Threads blocked on the full channel exit after the walker is dropped.
+        assert_eq!(walker.take(10).count(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiwalker_nested_repo() -> Result<()> {
+        let directories = vec!["dirA", "nested/.hg", "nested/dirB"];
+        let files = vec!["dirA/a.txt", "nested/dirB/b.txt"];
+        let root_dir = create_directory(&directories, &files)?;
+        let root_path = PathBuf::from(root_dir.path());
+        let walker = MultiWalker::new(
+            root_path,
+            AlwaysMatcher::new(),
+            false,
+            NonZeroU8::new(2).unwrap(),
+        )?;
+        let walked_files: Result<Vec<_>> = walker.collect();
+        let walked_files = walked_files?;
+        assert_eq!(walked_files.len(), 1);
+        assert_eq!(walked_files[0].as_ref().to_string(), "dirA/a.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn test_walker_progress() -> Result<()> {
+        let directories = vec!["dirA", "dirB/dirC"];
+        let files = vec!["a.txt", "dirA/b.txt", "dirB/c.txt", "dirB/dirC/d.txt"];
+        let root_dir = create_directory(&directories, &files)?;
+        for num_threads in [0, 2] {
+            let progress = WalkProgress::default();
+            // Files that do not match are still examined.
+            let matcher = TreeMatcher::from_rules(["dirB/**"].iter()).unwrap();
+            let walker = Walker::new(root_dir.path().to_path_buf(), matcher, false, num_threads)?
+                .with_progress(progress.clone());
+            let walked_files: Result<Vec<_>> = walker.collect();
+            assert_eq!(walked_files?.len(), 2);
+            assert_eq!(progress.dirs_visited(), 3);
+            assert_eq!(progress.files_examined(), 3);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_walker_gitignore() -> Result<()> {
+        let directories = vec!["build", "src"];
+        let files = vec!["a.txt", "a.o", "build/out", "src/b.txt"];
+        let root_dir = create_directory(&directories, &files)?;
+        std::fs::write(root_dir.path().join(".gitignore"), "build/\n*.o\n")?;
+        for num_threads in [0, 2] {
+            let progress = WalkProgress::default();
+            let ignore_matcher = GitignoreMatcher::new(root_dir.path(), Vec::new());
+            let matcher = Arc::new(DifferenceMatcher::new(AlwaysMatcher::new(), ignore_matcher));
+            let walker = Walker::new(root_dir.path().to_path_buf(), matcher, false, num_threads)?
+                .with_progress(progress.clone());
+            let walked_files: Result<Vec<_>> = walker.collect();
+            let mut walked_files: Vec<String> = walked_files?
+                .iter()
+                .map(|file| file.as_ref().to_string())
+                .collect();
+            walked_files.sort();
+            assert_eq!(walked_files, [".gitignore", "a.txt", "src/b.txt"]);
+            // Ignored directories are not read.
+            assert_eq!(progress.dirs_visited(), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_walker_directory_fingerprints() -> Result<()> {
+        let directories = vec!["dirA", "dirB"];
+        let files = vec!["a.txt", "dirA/b.txt", "dirB/c.txt"];
+        let root_dir = create_directory(&directories, &files)?;
+        let fingerprints = |num_threads| -> Result<Vec<(String, Option<DirectoryFingerprint>)>> {
+            let walker = Walker::new(
+                root_dir.path().to_path_buf(),
+                AlwaysMatcher::new(),
+                true,
+                num_threads,
+            )?
+            .with_directory_fingerprints();
+            let mut dirs = Vec::new();
+            for entry in walker {
+                if let WalkEntry::Directory(dir, fingerprint) = entry? {
+                    dirs.push((dir.to_string(), fingerprint));
+                }
+            }
+            dirs.sort_by(|a, b| a.0.cmp(&b.0));
+            Ok(dirs)
+        };
+
+        let before = fingerprints(0)?;
+        assert_eq!(before.len(), 3);
+        assert!(before.iter().all(|(_, f)| f.is_some()));
+        assert_eq!(fingerprints(2)?, before);
+
+        // Only the directory whose children changed gets a new fingerprint.
+        std::fs::write(root_dir.path().join("dirA/b.txt"), b"changed")?;
+        let after = fingerprints(2)?;
+        assert_eq!(after[0], before[0]);
+        assert_ne!(after[1], before[1]);
+        assert_eq!(after[2], before[2]);
+
+        // Fingerprints are not computed unless requested.
+        let walker = Walker::new(root_dir.path().to_path_buf(), AlwaysMatcher::new(), true, 0)?;
+        for entry in walker {
+            if let WalkEntry::Directory(_, fingerprint) = entry? {
+                assert_eq!(fingerprint, None);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_walker_directory_batches() -> Result<()> {
+        let directories = vec!["dirA", "dirB/dirC", "dirD"];
+        let files = vec![
+            "a.txt",
+            "b.txt",
+            "dirA/c.txt",
+            "dirA/d.txt",
+            "dirB/e.txt",
+            "dirB/dirC/f.txt",
+            "dirD/g.txt",
+        ];
+        let root_dir = create_directory(&directories, &files)?;
+        for num_threads in [0, 1, 4] {
+            let walker = Walker::new(
+                root_dir.path().to_path_buf(),
+                AlwaysMatcher::new(),
+                true,
+                num_threads,
+            )?;
+            // Files are reported together, right before their directory.
+            let mut files = Vec::new();
+            let mut dirs = Vec::new();
+            for entry in walker {
+                match entry? {
+                    WalkEntry::File(file, _) => files.push(file),
+                    WalkEntry::Directory(dir, _) => {
+                        for file in files.drain(..) {
+                            assert_eq!(file.parent(), Some(dir.as_repo_path()));
+                        }
+                        dirs.push(dir.to_string());
+                    }
+                    WalkEntry::NestedRepo(_) => unreachable!(),
+                }
+            }
+            assert!(files.is_empty());
+            dirs.sort();
+            assert_eq!(dirs, ["", "dirA", "dirB", "dirB/dirC", "dirD"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_walker_opaque_nested_repos() -> Result<()> {
+        let directories = vec!["dirA", "hgrepo/.hg", "gitrepo/.git", "gitrepo/dirB"];
+        let files = vec!["dirA/a.txt", "hgrepo/b.txt", "gitrepo/dirB/c.txt"];
+        let root_dir = create_directory(&directories, &files)?;
+        let walk = |num_threads, opaque| -> Result<Vec<String>> {
+            let mut walker = Walker::new(
+                root_dir.path().to_path_buf(),
+                AlwaysMatcher::new(),
+                false,
+                num_threads,
+            )?;
+            if opaque {
+                walker = walker.with_opaque_nested_repos();
+            }
+            let mut entries = Vec::new();
+            for entry in walker {
+                entries.push(match entry? {
+                    WalkEntry::NestedRepo(path) => format!("nested {}", path),
+                    entry => entry.as_ref().to_string(),
+                });
+            }
+            entries.sort();
+            Ok(entries)
+        };
+        for num_threads in [0, 2] {
+            assert_eq!(
+                walk(num_threads, false)?,
+                vec!["dirA/a.txt", "gitrepo/dirB/c.txt"]
+            );
+            assert_eq!(
+                walk(num_threads, true)?,
+                vec!["dirA/a.txt", "nested gitrepo", "nested hgrepo"]
+            );
+        }
+        Ok(())
+    }
 }
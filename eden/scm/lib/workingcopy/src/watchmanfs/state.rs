@@ -20,6 +20,7 @@ use crate::filechangedetector::FileChangeDetectorTrait;
 use crate::filechangedetector::FileChangeResult;
 use crate::filechangedetector::ResolvedFileChangeResult;
 use crate::filesystem::PendingChangeResult;
+use crate::fsmonitorstate::FsMonitorState;
 
 query_result_type! {
     pub struct StatusQuery {
@@ -30,6 +31,9 @@ query_result_type! {
 
 pub struct WatchmanState {
     treestate_needs_check: HashSet<RepoPathBuf>,
+    /// Paths that needed a check at a clock persisted outside of the
+    /// treestate. See [`WatchmanState::resume_from`].
+    persisted_needs_check: Vec<RepoPathBuf>,
     clock: Option<Clock>,
     treestate_errors: Vec<Error>,
 }
@@ -49,6 +53,7 @@ impl WatchmanState {
 
         Ok(WatchmanState {
             treestate_needs_check: needs_check,
+            persisted_needs_check: Vec::new(),
             clock: treestate.get_clock()?,
             treestate_errors: errors,
         })
@@ -58,6 +63,15 @@ impl WatchmanState {
         self.clock.clone()
     }
 
+    /// Query from a clock persisted by a previous status that might not have
+    /// written the treestate. Paths that needed a check at that clock are
+    /// checked again. The clock must have been queried from the current
+    /// treestate clock, see [`FsMonitorState::clock`].
+    pub fn resume_from(&mut self, clock: String, needs_check: Vec<RepoPathBuf>) {
+        self.clock = Some(Clock::Spec(ClockSpec::StringClock(clock)));
+        self.persisted_needs_check = needs_check;
+    }
+
     pub fn merge(
         self,
         result: QueryResult<StatusQuery>,
@@ -75,6 +89,7 @@ impl WatchmanState {
             .map(Result::unwrap)
             .collect::<HashSet<_>>();
         needs_check.extend(self.treestate_needs_check.iter().cloned());
+        needs_check.extend(self.persisted_needs_check.into_iter());
 
         let mut errors = errors
             .into_iter()
@@ -152,6 +167,27 @@ impl WatchmanPendingChanges {
         treestate.set_clock(self.clock.clone())?;
         Ok(())
     }
+
+    /// Record the clock and the paths that need a check at it, so the next
+    /// status can continue from here even if the treestate, whose clock is
+    /// `base`, is not written.
+    pub fn persist_monitor_state(&self, state: &mut FsMonitorState, base: &str) -> Result<()> {
+        let clock = match &self.clock {
+            Clock::Spec(ClockSpec::StringClock(clock)) => clock,
+            clock => {
+                return Err(anyhow!(
+                    "Watchman implementation only handles opaque string type. Got the following clock instead: {:?}",
+                    clock
+                ));
+            }
+        };
+        state.set_clock(
+            clock,
+            base,
+            self.needs_mark.iter().map(|path| path.as_repo_path()),
+        )?;
+        state.flush()
+    }
 }
 
 impl IntoIterator for WatchmanPendingChanges {
@@ -395,15 +431,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn resume_from_test() {
+        let test = WatchmanStateTest::new(vec![(InitialState::Clean, Event::Nothing)]);
+        let mut state = WatchmanState::new(test.treestate()).unwrap();
+
+        // The file changed before an interrupted status persisted its clock.
+        let path = RepoPathBuf::from_string("file0.txt".to_string()).unwrap();
+        state.resume_from("c:1".to_string(), vec![path.clone()]);
+        assert!(matches!(
+            state.get_clock(),
+            Some(Clock::Spec(ClockSpec::StringClock(clock))) if clock == "c:1"
+        ));
+
+        let file_change_detector = WatchmanStateTestFileChangeDetector {
+            changed_files: HashSet::from([path.clone()]),
+            deleted_files: HashSet::new(),
+        };
+        let pending_changes = state
+            .merge(test.query_result(), file_change_detector)
+            .unwrap();
+
+        let expected = vec![Ok(PendingChangeResult::File(ChangeType::Changed(path)))];
+        assert_eq!(
+            to_string(expected.into_iter()),
+            to_string(pending_changes.into_iter()),
+        );
+    }
+
     fn to_string(results: impl Iterator<Item = Result<PendingChangeResult>>) -> String {
         let mut results = results.map(Result::unwrap).collect::<Vec<_>>();
         results.sort_by(|a, b| match (a, b) {
-            (PendingChangeResult::File(a), PendingChangeResult::File(b)) => match (a, b) {
-                (
-                    ChangeType::Changed(a) | ChangeType::Deleted(a),
-                    ChangeType::Changed(b) | ChangeType::Deleted(b),
-                ) => a.cmp(b),
-            },
+            (PendingChangeResult::File(a), PendingChangeResult::File(b)) => {
+                a.get_path().cmp(b.get_path())
+            }
             _ => panic!("Unexpected pending change result"),
         });
         serde_json::to_string(&results).unwrap()
@@ -11,6 +11,8 @@ use std::rc::Rc;
 use std::sync::Arc;
 
 use anyhow::Result;
+use error_code::ErrorCode;
+use error_code::WithErrorCode;
 use manifest_tree::TreeManifest;
 use parking_lot::RwLock;
 use pathmatcher::Matcher;
@@ -21,34 +23,43 @@ use watchman_client::prelude::*;
 use super::state::StatusQuery;
 use super::state::WatchmanState;
 use super::treestate::WatchmanTreeState;
-use crate::filechangedetector::ArcReadFileContents;
+use crate::comparison::ArcContentComparison;
 use crate::filechangedetector::FileChangeDetector;
 use crate::filechangedetector::HgModifiedTime;
 use crate::filesystem::PendingChangeResult;
 use crate::filesystem::PendingChanges;
+use crate::fsmonitorstate::FsMonitorState;
+use crate::walker::WalkProgress;
 
 pub struct WatchmanFileSystem {
     vfs: VFS,
+    dot_dir: PathBuf,
     treestate: Rc<RefCell<TreeState>>,
     manifest: Arc<RwLock<TreeManifest>>,
-    store: ArcReadFileContents,
+    comparison: ArcContentComparison,
     last_write: HgModifiedTime,
+    mtime_race_window: u64,
 }
 
 impl WatchmanFileSystem {
     pub fn new(
         root: PathBuf,
+        // Where the fsmonitor state is persisted.
+        dot_dir: PathBuf,
         treestate: Rc<RefCell<TreeState>>,
         manifest: Arc<RwLock<TreeManifest>>,
-        store: ArcReadFileContents,
+        comparison: ArcContentComparison,
         last_write: HgModifiedTime,
+        mtime_race_window: u64,
     ) -> Result<Self> {
         Ok(WatchmanFileSystem {
             vfs: VFS::new(root)?,
+            dot_dir,
             treestate,
             manifest,
-            store,
+            comparison,
             last_write,
+            mtime_race_window,
         })
     }
 
@@ -82,25 +93,61 @@ impl PendingChanges for WatchmanFileSystem {
     fn pending_changes(
         &self,
         _matcher: Arc<dyn Matcher + Send + Sync + 'static>,
+        _progress: Option<WalkProgress>,
     ) -> Result<Box<dyn Iterator<Item = Result<PendingChangeResult>>>> {
-        let state = WatchmanState::new(WatchmanTreeState {
+        let mut state = WatchmanState::new(WatchmanTreeState {
             treestate: self.treestate.clone(),
-        })?;
+        })
+        .with_error_code(ErrorCode::TreeStateFailed)?;
+
+        // The persisted state is only an optimization. Do not fail status
+        // if it cannot be used. It is only used on top of a treestate clock:
+        // a missing or cleared clock means a fresh crawl is needed.
+        let base_clock = match state.get_clock() {
+            Some(Clock::Spec(ClockSpec::StringClock(clock))) => Some(clock),
+            _ => None,
+        };
+        let mut monitor_state = match base_clock {
+            Some(_) => match FsMonitorState::open(&self.dot_dir) {
+                Ok(monitor_state) => Some(monitor_state),
+                Err(err) => {
+                    tracing::warn!(?err, "cannot open fsmonitor state");
+                    None
+                }
+            },
+            None => None,
+        };
+        if let (Some(monitor_state), Some(base_clock)) = (&monitor_state, &base_clock) {
+            match monitor_state.clock(base_clock) {
+                Ok(Some((clock, needs_check))) => state.resume_from(clock, needs_check),
+                Ok(None) => {}
+                Err(err) => tracing::warn!(?err, "cannot read fsmonitor clock"),
+            }
+        }
 
-        let result = async_runtime::block_on(self.query_result(&state))?;
+        let result = async_runtime::block_on(self.query_result(&state))
+            .with_error_code(ErrorCode::WatchmanFailed)?;
 
         let file_change_detector = FileChangeDetector::new(
             self.treestate.clone(),
             self.vfs.clone(),
             self.last_write.clone(),
+            self.mtime_race_window,
             self.manifest.clone(),
-            self.store.clone(),
+            self.comparison.clone(),
         );
         let mut pending_changes = state.merge(result, file_change_detector)?;
 
-        pending_changes.persist(WatchmanTreeState {
-            treestate: self.treestate.clone(),
-        })?;
+        pending_changes
+            .persist(WatchmanTreeState {
+                treestate: self.treestate.clone(),
+            })
+            .with_error_code(ErrorCode::TreeStateFailed)?;
+        if let (Some(monitor_state), Some(base_clock)) = (&mut monitor_state, &base_clock) {
+            if let Err(err) = pending_changes.persist_monitor_state(monitor_state, base_clock) {
+                tracing::warn!(?err, "cannot write fsmonitor clock");
+            }
+        }
 
         Ok(Box::new(pending_changes.into_iter()))
     }
@@ -14,11 +14,12 @@ use anyhow::Error;
 use anyhow::Result;
 use manifest_tree::TreeManifest;
 use parking_lot::RwLock;
+use pathmatcher::GitignoreMatcher;
 use pathmatcher::Matcher;
 use status::Status;
-use storemodel::ReadFileContents;
 use treestate::treestate::TreeState;
 
+use crate::comparison::ArcContentComparison;
 use crate::edenfs::EdenFileSystem;
 use crate::filechangedetector::HgModifiedTime;
 use crate::filesystem::FileSystemType;
@@ -26,9 +27,9 @@ use crate::filesystem::PendingChangeResult;
 use crate::filesystem::PendingChanges;
 use crate::physicalfs::PhysicalFileSystem;
 use crate::status::compute_status;
+use crate::walker::WalkProgress;
 use crate::watchmanfs::WatchmanFileSystem;
 
-type ArcReadFileContents = Arc<dyn ReadFileContents<Error = anyhow::Error> + Send + Sync>;
 type FileSystem = Box<dyn PendingChanges>;
 
 pub struct WorkingCopy {
@@ -40,23 +41,48 @@ pub struct WorkingCopy {
 impl WorkingCopy {
     pub fn new(
         root: PathBuf,
+        // The repository dot directory, like `.hg`.
+        dot_dir: PathBuf,
         // TODO: Have constructor figure out FileSystemType
         file_system_type: FileSystemType,
         treestate: TreeState,
         manifest: TreeManifest,
-        store: ArcReadFileContents,
+        comparison: ArcContentComparison,
         last_write: HgModifiedTime,
+        // Files modified within this many seconds of `last_write` are compared by content.
+        mtime_race_window: u64,
+        // Threads to walk the working copy. 0 means single-threaded.
+        num_threads: u8,
+        // Global ignore files, in addition to `.gitignore` in the working copy.
+        ignore_files: Vec<PathBuf>,
+        // Whether untracked ignored files are reported in the status.
+        include_ignored: bool,
+        // Whether to stop at nested `.git` repositories, in addition to `.hg`.
+        opaque_nested_repos: bool,
+        // Whether EdenFS prefetches the unloaded directories touched by status.
+        edenfs_prefetch_unloaded: bool,
     ) -> std::result::Result<Self, (TreeState, Error)> {
         let treestate = Rc::new(RefCell::new(treestate));
         let manifest = Arc::new(RwLock::new(manifest));
+        let ignore_matcher = Arc::new(GitignoreMatcher::new(
+            &root,
+            ignore_files.iter().map(|p| p.as_path()).collect(),
+        ));
 
         let filesystem: Result<FileSystem> = Self::construct_file_system(
             root,
+            dot_dir,
             file_system_type,
             treestate.clone(),
             manifest.clone(),
-            store,
+            comparison,
             last_write,
+            mtime_race_window,
+            num_threads,
+            ignore_matcher,
+            include_ignored,
+            opaque_nested_repos,
+            edenfs_prefetch_unloaded,
         );
 
         let filesystem = match filesystem {
@@ -78,30 +104,44 @@ impl WorkingCopy {
 
     fn construct_file_system(
         root: PathBuf,
+        dot_dir: PathBuf,
         file_system_type: FileSystemType,
         treestate: Rc<RefCell<TreeState>>,
         manifest: Arc<RwLock<TreeManifest>>,
-        store: ArcReadFileContents,
+        comparison: ArcContentComparison,
         last_write: HgModifiedTime,
+        mtime_race_window: u64,
+        num_threads: u8,
+        ignore_matcher: Arc<GitignoreMatcher>,
+        include_ignored: bool,
+        opaque_nested_repos: bool,
+        edenfs_prefetch_unloaded: bool,
     ) -> Result<FileSystem> {
         Ok(match file_system_type {
             FileSystemType::Normal => Box::new(PhysicalFileSystem::new(
                 root,
                 manifest.clone(),
-                store,
+                comparison,
                 treestate.clone(),
                 false,
+                false,
+                ignore_matcher,
+                include_ignored,
                 last_write,
-                8,
+                mtime_race_window,
+                num_threads,
+                opaque_nested_repos,
             )?),
             FileSystemType::Watchman => Box::new(WatchmanFileSystem::new(
                 root,
+                dot_dir,
                 treestate.clone(),
                 manifest.clone(),
-                store,
+                comparison,
                 last_write,
+                mtime_race_window,
             )?),
-            FileSystemType::Eden => Box::new(EdenFileSystem::new(root)?),
+            FileSystemType::Eden => Box::new(EdenFileSystem::new(root, edenfs_prefetch_unloaded)?),
         })
     }
 
@@ -115,18 +155,25 @@ impl WorkingCopy {
             .into_inner()
     }
 
-    pub fn status(&self, matcher: Arc<dyn Matcher + Send + Sync + 'static>) -> Result<Status> {
+    pub fn status(
+        &self,
+        matcher: Arc<dyn Matcher + Send + Sync + 'static>,
+        progress: Option<WalkProgress>,
+    ) -> Result<Status> {
         let pending_changes = self
             .filesystem
-            .pending_changes(matcher.clone())?
+            .pending_changes(matcher.clone(), progress)?
             .filter_map(|result| match result {
                 Ok(PendingChangeResult::File(change_type)) => {
                     match matcher.matches_file(change_type.get_path()) {
-                        Ok(true) => Some(Ok(change_type)),
+                        Ok(true) => Some(Ok(PendingChangeResult::File(change_type))),
                         Err(e) => Some(Err(e)),
                         _ => None,
                     }
                 }
+                Ok(PendingChangeResult::Ignored(path)) => {
+                    Some(Ok(PendingChangeResult::Ignored(path)))
+                }
                 Err(e) => Some(Err(e)),
                 _ => None,
             });
@@ -105,6 +105,7 @@ Show debug commands if there are no other candidates
   debugcleanremotenames
   debugcolor
   debugcommands
+  debugcommitcomplete
   debugcompactmetalog
   debugcomplete
   debugconfig
@@ -427,12 +428,13 @@ Show all commands + options
   debugcleanremotenames: 
   debugcolor: style
   debugcommands: 
+  debugcommitcomplete: files
   debugcompactmetalog: 
   debugcomplete: options
   debugcreatestreamclonebundle: 
   debugdag: bookmarks, branches, dots, spaces
   debugdata: changelog, manifest, dir
-  debugdatapack: long, node, node-delta
+  debugdatapack: long, node, node-delta, extstored
   debugdate: extended, range
   debugdeltachain: changelog, manifest, dir, template
   debugdetectissues: 
@@ -0,0 +1,19 @@
+#debugruntest-compatible
+
+  $ newserver server
+  $ newremoterepo repo
+
+  $ echo a > a
+  $ hg commit -Aqm A
+  $ hg debugcommitcomplete .
+  complete
+  $ hg debugcommitcomplete --files .
+  complete
+
+Without the local file contents, only the commit and its trees are complete
+  $ rm -rf .hg/store/indexedlogdatastore
+  $ hg debugcommitcomplete .
+  complete
+  $ hg debugcommitcomplete --files .
+  incomplete
+  [1]
@@ -292,6 +292,80 @@ class rusthistpacktests(histpacktestsbase, unittest.TestCase):
         unittest.TestCase.__init__(self, *args, **kwargs)
 
 
+class unionhistorystoretests(unittest.TestCase):
+    def setUp(self):
+        self.tempdir = tempfile.mkdtemp()
+
+    def tearDown(self):
+        shutil.rmtree(self.tempdir)
+
+    def getFakeHash(self):
+        return os.urandom(20)
+
+    def testPacksAndIndexedLog(self):
+        """Test reading entries from both packs and the indexedlog."""
+        config = uimod.ui()._uiconfig._rcfg
+        packdir = os.path.join(self.tempdir, "packs")
+        indexedlogdir = os.path.join(self.tempdir, "indexedlog")
+        os.mkdir(packdir)
+
+        packnode = self.getFakeHash()
+        packinfo = (self.getFakeHash(), nullid, self.getFakeHash(), None)
+        packer = revisionstore.mutablehistorystore(packdir)
+        packer.add("foo", packnode, *packinfo)
+        packer.flush()
+
+        lognode = self.getFakeHash()
+        loginfo = (self.getFakeHash(), nullid, self.getFakeHash(), None)
+        log = revisionstore.indexedloghistorystore(indexedlogdir, config)
+        log.add("bar", lognode, *loginfo)
+        log.flush()
+        del log
+
+        store = revisionstore.unionhistorystore(packdir, indexedlogdir, config)
+        self.assertEqual(store.getnodeinfo("foo", packnode), packinfo)
+        self.assertEqual(store.getnodeinfo("bar", lognode), loginfo)
+        self.assertRaises(KeyError, store.getnodeinfo, "baz", self.getFakeHash())
+
+        missing = ("baz", self.getFakeHash())
+        self.assertEqual(
+            store.getmissing([("foo", packnode), ("bar", lognode), missing]),
+            [missing],
+        )
+
+        metrics = store.getmetrics()
+        self.assertEqual(metrics["numpacks"], 1)
+        self.assertGreater(metrics["totalpacksize"], 0)
+        self.assertGreater(metrics["indexedlogsize"], 0)
+        self.assertEqual(
+            metrics["totalsize"], metrics["totalpacksize"] + metrics["indexedlogsize"]
+        )
+
+    def testIterEntries(self):
+        """Test iterating over the entries of the indexedlog, with and without a
+        prefix."""
+        config = uimod.ui()._uiconfig._rcfg
+        indexedlogdir = os.path.join(self.tempdir, "indexedlog")
+
+        log = revisionstore.indexedloghistorystore(indexedlogdir, config)
+        entries = set()
+        for name in ["dir/foo", "dir/bar", "dirfoo"]:
+            node = self.getFakeHash()
+            log.add(name, node, self.getFakeHash(), nullid, self.getFakeHash(), None)
+            entries.add((name, node))
+        log.flush()
+
+        it = log.iterentries()
+        self.assertIs(iter(it), it)
+        self.assertEqual(set(entry[:2] for entry in it), entries)
+        self.assertEqual(list(it), [])
+
+        self.assertEqual(
+            set(entry[:2] for entry in log.iterentries("dir")),
+            set(entry for entry in entries if entry[0].startswith("dir/")),
+        )
+
+
 # TODO:
 # histpack store:
 # - repack two packs into one
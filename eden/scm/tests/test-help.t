@@ -929,6 +929,8 @@ Test list of internal help commands
    debugcolor    show available color, effects or style
    debugcommands
                  list all available commands and options
+   debugcommitcomplete
+                 test if a commit can be used without fetching anything
    debugcompactmetalog
                  compact the metalog by dropping history
    debugcomplete
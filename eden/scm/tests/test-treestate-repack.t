@@ -50,6 +50,7 @@ Repack makes the file smaller
 
 Auto repack happens when treestate exceeds size threshold
 
+  $ setconfig treestate.backgroundcompact=false
   $ for i in 12 1 12 1 12 1; do
   >   echo .
   >   echo $i > a
@@ -78,3 +79,19 @@ Cleanup does not remove files that are not old enough
 
   $ touch .hg/treestate/00000000-0000-0000-0000-000000000007
   $ hg debugtreestate cleanup --debug --config treestate.mingcage=1000
+
+By default, auto repack runs in a background process
+
+  $ setconfig treestate.backgroundcompact=true
+  $ for i in 12 1 12 1 12 1; do
+  >   echo $i > a
+  >   touch -t 200001010000 a
+  >   hg ci -m modify -q --debug 2>&1 | grep "in the background"
+  > done
+  compacting treestate in the background
+  $ for i in $(seq 100); do
+  >   hg debugtreestate | grep -q 0000000000000000000000000000000000000003 && break
+  >   sleep 0.1
+  > done
+  $ hg debugtreestate
+  dirstate v2 (using treestate/0000000000000000000000000000000000000003, offset 88, 5 files tracked)
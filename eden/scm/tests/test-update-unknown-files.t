@@ -70,3 +70,32 @@ Replacing symlink with content
   $ cat y/a
   yyy
 #endif
+
+Path conflicts are handled according to checkout.conflict-policy
+  $ hg up -q 'desc(A)'
+  $ mkdir d
+  $ echo f > d/f
+  $ hg add d/f
+  $ hg commit -m 'F'
+  $ hg up -q 'desc(A)'
+  $ echo untracked > d
+  $ hg up 'desc(F)'
+  d: untracked file conflicts with directory
+  abort: untracked files in working directory differ from files in requested revision
+  [255]
+  $ hg up 'desc(F)' --config checkout.conflict-policy=backup
+  1 files updated, 0 files merged, 0 files removed, 0 files unresolved
+  $ cat d.orig
+  untracked
+  $ cat d/f
+  f
+
+With modified files to merge, the merge policy aborts without backing up anything
+  $ echo changed > d/f
+  $ hg up 'desc(A)' --config checkout.conflict-policy=merge
+  abort: 1 conflicting file changes:
+   d/f
+  (commit, shelve, update --clean to discard all your changes, or update --merge to merge them)
+  [255]
+  $ ls d
+  f